@@ -1,35 +1,79 @@
 //! Default channel handle for `node` feature.
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Weak;
+
 use async_channel as ac;
 use async_channel::Receiver;
 use async_channel::Sender;
+use async_channel::TrySendError;
 use async_trait::async_trait;
 
 use crate::error::Error;
 use crate::error::Result;
 use crate::types::channel::Channel;
+use crate::types::channel::OverflowPolicy;
+
+/// Sender of [AcChannel], which applies the [OverflowPolicy] when the channel is full.
+#[derive(Debug)]
+pub struct AcSender<T> {
+    sender: Sender<T>,
+    /// Used to drop the oldest message when overflow. It's weak, so that the channel is
+    /// closed once the receivers are dropped, instead of buffering for no one.
+    receiver: Weak<Receiver<T>>,
+    policy: OverflowPolicy,
+    dropped: Arc<AtomicU64>,
+}
+
+impl<T> Clone for AcSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            receiver: self.receiver.clone(),
+            policy: self.policy,
+            dropped: self.dropped.clone(),
+        }
+    }
+}
 
 /// Channel combine with async_channel::Sender and async_channel::Receiver.
 #[derive(Debug)]
 pub struct AcChannel<T> {
     /// async channel send `Message` through sender.
-    sender: Sender<T>,
+    sender: AcSender<T>,
     /// async channel rece `Message` through receiver.
-    receiver: Receiver<T>,
+    receiver: Arc<Receiver<T>>,
+}
+
+impl<T> AcChannel<T> {
+    fn with_channel((tx, rx): (Sender<T>, Receiver<T>), policy: OverflowPolicy) -> Self {
+        let rx = Arc::new(rx);
+        Self {
+            sender: AcSender {
+                sender: tx,
+                receiver: Arc::downgrade(&rx),
+                policy,
+                dropped: Arc::new(AtomicU64::new(0)),
+            },
+            receiver: rx,
+        }
+    }
 }
 
 #[async_trait]
 impl<T: Send> Channel<T> for AcChannel<T>
 where T: std::fmt::Debug
 {
-    type Sender = Sender<T>;
-    type Receiver = Receiver<T>;
+    type Sender = AcSender<T>;
+    type Receiver = Arc<Receiver<T>>;
 
     fn new() -> Self {
-        let (tx, rx) = ac::unbounded();
-        Self {
-            sender: tx,
-            receiver: rx,
-        }
+        Self::with_channel(ac::unbounded(), OverflowPolicy::default())
+    }
+
+    fn bounded(capacity: usize, policy: OverflowPolicy) -> Self {
+        Self::with_channel(ac::bounded(capacity), policy)
     }
 
     fn sender(&self) -> Self::Sender {
@@ -40,14 +84,57 @@ where T: std::fmt::Debug
         self.receiver.clone()
     }
 
+    fn len(&self) -> usize {
+        self.receiver.len()
+    }
+
+    fn take_dropped(&self) -> u64 {
+        self.sender.dropped.swap(0, Ordering::Relaxed)
+    }
+
     async fn send(sender: &Self::Sender, msg: T) -> Result<()> {
         tracing::debug!("channel sending message: {:?}", msg);
-        match sender.send(msg).await {
-            Ok(_) => {
-                tracing::debug!("channel send message success");
-                Ok(())
+        let mut msg = msg;
+        loop {
+            match sender.policy {
+                OverflowPolicy::Backpressure => {
+                    return match sender.sender.send(msg).await {
+                        Ok(_) => {
+                            tracing::debug!("channel send message success");
+                            Ok(())
+                        }
+                        Err(_) => Err(Error::ChannelSendMessageFailed),
+                    };
+                }
+                OverflowPolicy::DropNewest | OverflowPolicy::DropOldest => {
+                    match sender.sender.try_send(msg) {
+                        Ok(_) => {
+                            tracing::debug!("channel send message success");
+                            return Ok(());
+                        }
+                        Err(TrySendError::Closed(_)) => {
+                            return Err(Error::ChannelSendMessageFailed)
+                        }
+                        Err(TrySendError::Full(m)) => {
+                            if sender.policy == OverflowPolicy::DropNewest {
+                                tracing::warn!("channel is full, drop newest message: {:?}", m);
+                                sender.dropped.fetch_add(1, Ordering::Relaxed);
+                                return Ok(());
+                            }
+                            // The receiver may have consumed a message meanwhile, retry anyway.
+                            let oldest = sender.receiver.upgrade().map(|r| r.try_recv());
+                            if let Some(Ok(oldest)) = oldest {
+                                tracing::warn!(
+                                    "channel is full, drop oldest message: {:?}",
+                                    oldest
+                                );
+                                sender.dropped.fetch_add(1, Ordering::Relaxed);
+                            }
+                            msg = m;
+                        }
+                    }
+                }
             }
-            Err(_) => Err(Error::ChannelSendMessageFailed),
         }
     }
 
@@ -61,3 +148,80 @@ where T: std::fmt::Debug
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bounded_channel_overflow() {
+        let ch = AcChannel::<u32>::bounded(2, OverflowPolicy::DropNewest);
+        for i in 0..4 {
+            AcChannel::<u32>::send(&ch.sender(), i).await.unwrap();
+        }
+        assert_eq!(ch.len(), 2);
+        assert_eq!(ch.take_dropped(), 2);
+        assert_eq!(ch.take_dropped(), 0);
+        assert_eq!(
+            AcChannel::<u32>::recv(&ch.receiver()).await.unwrap(),
+            Some(0)
+        );
+        assert_eq!(
+            AcChannel::<u32>::recv(&ch.receiver()).await.unwrap(),
+            Some(1)
+        );
+
+        let ch = AcChannel::<u32>::bounded(2, OverflowPolicy::DropOldest);
+        for i in 0..4 {
+            AcChannel::<u32>::send(&ch.sender(), i).await.unwrap();
+        }
+        assert_eq!(ch.len(), 2);
+        assert_eq!(ch.take_dropped(), 2);
+        assert_eq!(
+            AcChannel::<u32>::recv(&ch.receiver()).await.unwrap(),
+            Some(2)
+        );
+        assert_eq!(
+            AcChannel::<u32>::recv(&ch.receiver()).await.unwrap(),
+            Some(3)
+        );
+        assert!(ch.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_bounded_channel_backpressure() {
+        let ch = AcChannel::<u32>::bounded(1, OverflowPolicy::Backpressure);
+        AcChannel::<u32>::send(&ch.sender(), 0).await.unwrap();
+
+        let sender = ch.sender();
+        let pending = tokio::spawn(async move { AcChannel::<u32>::send(&sender, 1).await });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(!pending.is_finished());
+
+        assert_eq!(
+            AcChannel::<u32>::recv(&ch.receiver()).await.unwrap(),
+            Some(0)
+        );
+        pending.await.unwrap().unwrap();
+        assert_eq!(
+            AcChannel::<u32>::recv(&ch.receiver()).await.unwrap(),
+            Some(1)
+        );
+        assert_eq!(ch.take_dropped(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_channel_closed_without_receiver() {
+        for policy in [
+            OverflowPolicy::Backpressure,
+            OverflowPolicy::DropNewest,
+            OverflowPolicy::DropOldest,
+        ] {
+            let ch = AcChannel::<u32>::bounded(2, policy);
+            let sender = ch.sender();
+            AcChannel::<u32>::send(&sender, 0).await.unwrap();
+            drop(ch);
+            assert!(AcChannel::<u32>::send(&sender, 1).await.is_err());
+        }
+    }
+}
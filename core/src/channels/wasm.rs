@@ -1,5 +1,9 @@
 //! Async channel handle for `wasm` features.
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::Weak;
 
 /// ref: https://github.com/Ciantic/rust-shared-wasm-experiments/blob/master/src/lib.rs
 use async_trait::async_trait;
@@ -9,28 +13,83 @@ use futures::lock::Mutex;
 use crate::error::Error;
 use crate::error::Result;
 use crate::types::channel::Channel;
+use crate::types::channel::OverflowPolicy;
 
-type Sender<T> = Arc<mpsc::UnboundedSender<T>>;
-type Receiver<T> = Arc<Mutex<mpsc::UnboundedReceiver<T>>>;
+/// State shared by sender and receiver, used to bound the unbounded mpsc channel.
+#[derive(Debug)]
+struct QueueState {
+    capacity: Option<usize>,
+    policy: OverflowPolicy,
+    len: AtomicUsize,
+    dropped: AtomicU64,
+}
+
+/// Sender of [CbChannel].
+#[derive(Debug)]
+pub struct CbSender<T> {
+    sender: mpsc::UnboundedSender<T>,
+    /// Used to drop the oldest message when overflow. It's weak, so that the channel is
+    /// closed once the receivers are dropped, instead of buffering for no one.
+    receiver: Weak<CbReceiver<T>>,
+    state: Arc<QueueState>,
+}
+
+/// Receiver of [CbChannel].
+#[derive(Debug)]
+pub struct CbReceiver<T> {
+    receiver: Mutex<mpsc::UnboundedReceiver<T>>,
+    state: Arc<QueueState>,
+}
+
+type Sender<T> = Arc<CbSender<T>>;
+type Receiver<T> = Arc<CbReceiver<T>>;
 
 /// Channel combine with mpsc::UnboundedSender and mpsc::UnboundedReceiver.
+/// A bounded channel is emulated by counting messages in the channel.
 #[derive(Debug)]
 pub struct CbChannel<T> {
     sender: Sender<T>,
     receiver: Receiver<T>,
 }
 
+impl<T> CbChannel<T> {
+    fn with_capacity(capacity: Option<usize>, policy: OverflowPolicy) -> Self {
+        let (tx, rx) = mpsc::unbounded();
+        let state = Arc::new(QueueState {
+            capacity,
+            policy,
+            len: AtomicUsize::new(0),
+            dropped: AtomicU64::new(0),
+        });
+        let receiver = Arc::new(CbReceiver {
+            receiver: Mutex::new(rx),
+            state: state.clone(),
+        });
+        Self {
+            sender: Arc::new(CbSender {
+                sender: tx,
+                receiver: Arc::downgrade(&receiver),
+                state,
+            }),
+            receiver,
+        }
+    }
+}
+
 #[async_trait(?Send)]
 impl<T: Send> Channel<T> for CbChannel<T> {
     type Sender = Sender<T>;
     type Receiver = Receiver<T>;
 
     fn new() -> Self {
-        let (tx, rx) = mpsc::unbounded();
-        Self {
-            sender: Arc::new(tx),
-            receiver: Arc::new(Mutex::new(rx)),
-        }
+        Self::with_capacity(None, OverflowPolicy::default())
+    }
+
+    /// The receiver of wasm is polled by timer, a sender cannot wait for it in the
+    /// single threaded browser runtime. So [OverflowPolicy::Backpressure] behaves
+    /// the same as [OverflowPolicy::DropNewest] here.
+    fn bounded(capacity: usize, policy: OverflowPolicy) -> Self {
+        Self::with_capacity(Some(capacity), policy)
     }
 
     fn sender(&self) -> Self::Sender {
@@ -41,25 +100,61 @@ impl<T: Send> Channel<T> for CbChannel<T> {
         self.receiver.clone()
     }
 
+    fn len(&self) -> usize {
+        self.sender.state.len.load(Ordering::Relaxed)
+    }
+
+    fn take_dropped(&self) -> u64 {
+        self.sender.state.dropped.swap(0, Ordering::Relaxed)
+    }
+
     /// Sends a message along this channel.
     /// This is an unbounded sender, so this function differs from Sink::send
     /// by ensuring the return type reflects that the channel is always ready to receive messages.
     /// Err(TrySendError) can caused by `is_full` or `is_disconnected`
     /// ref: https://docs.rs/futures/latest/futures/channel/mpsc/struct.UnboundedSender.html
     async fn send(sender: &Self::Sender, msg: T) -> Result<()> {
-        match sender.unbounded_send(msg) {
-            Ok(()) => Ok(()),
+        let state = &sender.state;
+        if let Some(capacity) = state.capacity {
+            if state.len.load(Ordering::Relaxed) >= capacity {
+                match state.policy {
+                    OverflowPolicy::DropOldest => {
+                        if let Some(receiver) = sender.receiver.upgrade() {
+                            let mut receiver = receiver.receiver.lock().await;
+                            if let Ok(Some(_)) = receiver.try_next() {
+                                tracing::warn!("channel is full, drop oldest message");
+                                state.len.fetch_sub(1, Ordering::Relaxed);
+                                state.dropped.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                    }
+                    OverflowPolicy::DropNewest | OverflowPolicy::Backpressure => {
+                        tracing::warn!("channel is full, drop newest message");
+                        state.dropped.fetch_add(1, Ordering::Relaxed);
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        match sender.sender.unbounded_send(msg) {
+            Ok(()) => {
+                state.len.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
             Err(_) => Err(Error::ChannelSendMessageFailed),
         }
     }
 
     async fn recv(receiver: &Self::Receiver) -> Result<Option<T>> {
-        let mut receiver = receiver.lock().await;
-        match receiver.try_next() {
+        let mut inner = receiver.receiver.lock().await;
+        match inner.try_next() {
             // when there are no messages available, but channel is not yet closed
             Err(_) => Ok(None),
             // when message is fetched
-            Ok(Some(x)) => Ok(Some(x)),
+            Ok(Some(x)) => {
+                receiver.state.len.fetch_sub(1, Ordering::Relaxed);
+                Ok(Some(x))
+            }
             // when channel is closed and no messages left in the queue
             Ok(None) => Ok(None),
         }
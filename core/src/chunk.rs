@@ -7,6 +7,8 @@
 //! to be sent efficiently while not blocking other messages that share
 //! the same connection, or even the same MSRP session.
 
+use std::collections::HashMap;
+
 use bytes::Bytes;
 use itertools::Itertools;
 use serde::Deserialize;
@@ -15,10 +17,13 @@ use uuid::Uuid;
 
 use crate::consts::DEFAULT_TTL_MS;
 use crate::consts::MAX_TTL_MS;
+use crate::consts::TRANSPORT_MAX_SIZE;
 use crate::consts::TS_OFFSET_TOLERANCE_MS;
+use crate::dht::Did;
 use crate::error::Error;
 use crate::error::Result;
 use crate::utils::get_epoch_ms;
+use crate::utils::sleep_ms;
 
 /// A data structure to presenting Chunks
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -37,6 +42,12 @@ impl Chunk {
         a.meta.id == b.meta.id && a.chunk[1] == b.chunk[1]
     }
 
+    /// Size of the message claimed by chunk, assuming all chunks of it are as large as this
+    /// one. Chunks split by [ChunkList::split] are, except the last one.
+    pub fn declared_len(&self) -> usize {
+        self.chunk[1].saturating_mul(self.data.len())
+    }
+
     /// serelize chunk to bytes
     pub fn to_bincode(&self) -> Result<Bytes> {
         bincode::serialize(self)
@@ -92,8 +103,20 @@ pub trait ChunkManager {
     fn remove_expired(&mut self);
     /// handle a chunk
     fn handle(&mut self, chunk: Chunk) -> Option<Bytes>;
+    /// bytes of incomplete messages buffered for a peer,
+    /// zero if chunks are not attributed to peers, see [ChunkPool]
+    fn peer_memory(&self, _did: Did) -> usize {
+        0
+    }
 }
 
+/// Default max bytes of incomplete messages buffered for a peer by [ChunkPool].
+pub const DEFAULT_PEER_CHUNK_BUDGET: usize = TRANSPORT_MAX_SIZE * 4;
+
+/// Bytes counted by [ChunkPool] for each buffered chunk besides its data,
+/// so that empty chunks are not free to buffer.
+pub const CHUNK_OVERHEAD: usize = std::mem::size_of::<Chunk>();
+
 /// List of Chunk, simply wrapped `Vec<Chunk>`
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ChunkList<const MTU: usize>(Vec<Chunk>);
@@ -114,12 +137,12 @@ impl<const MTU: usize> ChunkList<MTU> {
         &mut self.0
     }
 
-    /// dedup and sort elements in list
+    /// sort and dedup elements in list
     pub fn formalize(&self) -> Self {
         let mut chunks = self.to_vec();
-        // dedup same chunk id
-        chunks.dedup_by_key(|c| c.chunk[0]);
         chunks.sort_by_key(|a| a.chunk[0]);
+        // dedup same chunk id, which are adjacent once sorted
+        chunks.dedup_by_key(|c| c.chunk[0]);
         Self::from(chunks)
     }
 
@@ -142,6 +165,57 @@ impl<const MTU: usize> ChunkList<MTU> {
         !chunks.is_empty() && chunks.len() == chunks.first().unwrap().chunk[1]
     }
 
+    /// Ids of messages with chunks in list, in the order they first appear.
+    pub fn ids(&self) -> Vec<Uuid> {
+        self.0.iter().map(|c| c.meta.id).unique().collect()
+    }
+
+    /// Positions of chunks of a message not received yet, in order.
+    /// None if no chunk of the message is in list.
+    pub fn missing(&self, id: Uuid) -> Option<Vec<usize>> {
+        let chunks = self.search(id).to_vec();
+        let total = chunks.first()?.chunk[1];
+        let mut received = chunks.iter().map(|c| c.chunk[0]).peekable();
+        Some(
+            (0..total)
+                .filter(|i| received.next_if_eq(i).is_none())
+                .collect(),
+        )
+    }
+
+    /// Handle a chunk received at `now`, see [ChunkManager::handle].
+    /// Chunks of other messages expired by `now` are removed.
+    pub fn handle_at(&mut self, chunk: Chunk, now: u128) -> Option<Bytes> {
+        if chunk.meta.ttl_ms > MAX_TTL_MS {
+            return None;
+        }
+
+        if chunk.meta.ts_ms.saturating_sub(TS_OFFSET_TOLERANCE_MS) > now {
+            return None;
+        }
+
+        // A chunk out of its message would complete the message with another one missing.
+        if chunk.chunk[0] >= chunk.chunk[1] {
+            tracing::debug!("Drop chunk {:?} of message {}", chunk.chunk, chunk.meta.id);
+            return None;
+        }
+
+        let id = chunk.meta.id;
+        self.as_vec_mut().push(chunk);
+        self.remove_expired_at(now);
+
+        let data = self.get(id)?;
+
+        self.remove(id);
+        Some(data)
+    }
+
+    /// Remove chunks expired by `now`.
+    pub fn remove_expired_at(&mut self, now: u128) {
+        self.as_vec_mut()
+            .retain(|e| e.meta.ts_ms + e.meta.ttl_ms as u128 > now)
+    }
+
     /// if list is completed, withdraw data, or return None
     pub fn try_withdraw(&self) -> Option<Bytes> {
         if !self.is_completed() {
@@ -180,7 +254,15 @@ impl<const MTU: usize> IntoIterator for ChunkList<MTU> {
 
 impl<const MTU: usize> From<&Bytes> for ChunkList<MTU> {
     fn from(bytes: &Bytes) -> Self {
-        let chunks: Vec<Bytes> = bytes.chunks(MTU).map(|c| c.to_vec().into()).collect();
+        Self::split(bytes, MTU)
+    }
+}
+
+impl<const MTU: usize> ChunkList<MTU> {
+    /// Split bytes into chunks of at most `mtu` bytes, such as by an MTU negotiated
+    /// with the receiver. Chunks of any size are reassembled by the receiver.
+    pub fn split(bytes: &Bytes, mtu: usize) -> Self {
+        let chunks: Vec<Bytes> = bytes.chunks(mtu).map(|c| c.to_vec().into()).collect();
         let chunks_len: usize = chunks.len();
         let meta = ChunkMeta::default();
         Self(
@@ -211,31 +293,17 @@ impl<const MTU: usize> From<Vec<Chunk>> for ChunkList<MTU> {
 
 impl<const MTU: usize> ChunkManager for ChunkList<MTU> {
     fn list_completed(&self) -> Vec<Uuid> {
-        // group by msg uuid and chunk size
-        self.into_iter()
-            .group_by(|item| item.clone())
+        // chunks of messages may interleave, so they are grouped by msg uuid
+        self.ids()
             .into_iter()
-            .filter_map(|(c, g)| {
-                if ChunkList::<MTU>::from(g.collect_vec()).is_completed() {
-                    Some(c.meta.id)
-                } else {
-                    None
-                }
-            })
+            .filter(|id| self.search(*id).is_completed())
             .collect_vec()
     }
 
     fn list_pending(&self) -> Vec<Uuid> {
-        self.into_iter()
-            .group_by(|item| item.clone())
+        self.ids()
             .into_iter()
-            .filter_map(|(c, g)| {
-                if !ChunkList::<MTU>::from(g.collect_vec()).is_completed() {
-                    Some(c.meta.id)
-                } else {
-                    None
-                }
-            })
+            .filter(|id| !self.search(*id).is_completed())
             .collect_vec()
     }
 
@@ -248,35 +316,374 @@ impl<const MTU: usize> ChunkManager for ChunkList<MTU> {
     }
 
     fn remove_expired(&mut self) {
-        let now = get_epoch_ms();
-        self.as_vec_mut()
-            .retain(|e| e.meta.ts_ms + e.meta.ttl_ms as u128 > now)
+        self.remove_expired_at(get_epoch_ms())
     }
 
     fn handle(&mut self, chunk: Chunk) -> Option<Bytes> {
-        if chunk.meta.ttl_ms > MAX_TTL_MS {
-            return None;
-        }
+        self.handle_at(chunk, get_epoch_ms())
+    }
+}
 
-        if chunk.meta.ts_ms - TS_OFFSET_TOLERANCE_MS > get_epoch_ms() {
-            return None;
+/// An incomplete message evicted from [ChunkPool], since its sender is over budget.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkEvicted<T = ()> {
+    /// The sender of message.
+    pub did: Did,
+    /// Id of message.
+    pub id: Uuid,
+    /// Bytes of chunks released, including [CHUNK_OVERHEAD] of each chunk.
+    pub bytes: usize,
+    /// Origin of message given with its first chunk, see [ChunkPool::handle_from_with].
+    pub origin: T,
+}
+
+/// Reassembly buffer of chunks from many peers, where incomplete messages of a peer take
+/// at most `peer_budget` bytes. When a chunk would exceed the budget of its sender, the
+/// oldest incomplete messages of the sender are evicted until it fits, so that no peer can
+/// make a node buffer more than the budget, while other peers are not affected.
+/// Every chunk is counted with [CHUNK_OVERHEAD] besides its data.
+///
+/// Each message may keep an origin `T` given with its first chunk, such as the payload
+/// carrying it, which is returned once the message is evicted.
+#[derive(Debug, Clone)]
+pub struct ChunkPool<const MTU: usize, T = ()> {
+    chunks: ChunkList<MTU>,
+    /// Sender, arrival order and origin of the first chunk of each message.
+    senders: HashMap<Uuid, (Did, u64, T)>,
+    arrivals: u64,
+    peer_budget: usize,
+}
+
+impl<const MTU: usize, T> Default for ChunkPool<MTU, T> {
+    fn default() -> Self {
+        Self::new(DEFAULT_PEER_CHUNK_BUDGET)
+    }
+}
+
+impl<const MTU: usize> ChunkPool<MTU> {
+    /// Handle a chunk sent by `did`, see [ChunkPool::handle_from_with].
+    pub fn handle_from(&mut self, did: Did, chunk: Chunk) -> (Option<Bytes>, Vec<ChunkEvicted>) {
+        self.handle_from_with(did, chunk, || ())
+    }
+}
+
+impl<const MTU: usize, T> ChunkPool<MTU, T> {
+    /// Create a pool buffering at most `peer_budget` bytes for each peer.
+    pub fn new(peer_budget: usize) -> Self {
+        Self {
+            chunks: ChunkList::default(),
+            senders: HashMap::new(),
+            arrivals: 0,
+            peer_budget,
         }
+    }
 
-        self.as_vec_mut().push(chunk.clone());
-        self.remove_expired();
+    /// Max bytes of incomplete messages buffered for a peer.
+    pub fn peer_budget(&self) -> usize {
+        self.peer_budget
+    }
+
+    /// Bytes buffered for message.
+    fn memory_of(&self, id: Uuid) -> usize {
+        self.chunks
+            .as_vec()
+            .iter()
+            .filter(|c| c.meta.id == id)
+            .map(|c| c.data.len() + CHUNK_OVERHEAD)
+            .sum()
+    }
+
+    /// Forget senders of messages whose chunks are all gone.
+    fn sync_senders(&mut self) {
+        let ids: std::collections::HashSet<Uuid> =
+            self.chunks.as_vec().iter().map(|c| c.meta.id).collect();
+        self.senders.retain(|id, _| ids.contains(id));
+    }
+
+    /// Positions of chunks of a message not received yet, see [ChunkList::missing].
+    pub fn missing(&self, id: Uuid) -> Option<Vec<usize>> {
+        self.chunks.missing(id)
+    }
 
+    /// Handle a chunk sent by `did`, returns the message if it's completed, with messages
+    /// of `did` evicted to keep it within budget, the oldest first. A chunk is dropped if its
+    /// own message is evicted, or if the message is started by another peer.
+    /// The origin of message is only taken from its first chunk.
+    pub fn handle_from_with(
+        &mut self,
+        did: Did,
+        chunk: Chunk,
+        origin: impl FnOnce() -> T,
+    ) -> (Option<Bytes>, Vec<ChunkEvicted<T>>) {
         let id = chunk.meta.id;
-        let data = self.get(id)?;
+        let mut evicted = vec![];
+        match self.senders.get(&id) {
+            Some((sender, _, _)) if *sender != did => return (None, evicted),
+            Some(_) => (),
+            None => {
+                self.arrivals += 1;
+                self.senders.insert(id, (did, self.arrivals, origin()));
+            }
+        }
 
-        self.remove(id);
-        Some(data)
+        let size = chunk.data.len() + CHUNK_OVERHEAD;
+        while self.peer_memory(did) + size > self.peer_budget {
+            // The message in progress is evicted only if no other one is left.
+            let oldest = self
+                .senders
+                .iter()
+                .filter(|(m, (sender, _, _))| *sender == did && **m != id)
+                .min_by_key(|(_, (_, arrival, _))| *arrival)
+                .map(|(m, _)| *m)
+                .unwrap_or(id);
+            let bytes = self.memory_of(oldest);
+            self.chunks.remove(oldest);
+            if let Some((_, _, origin)) = self.senders.remove(&oldest) {
+                evicted.push(ChunkEvicted {
+                    did,
+                    id: oldest,
+                    bytes,
+                    origin,
+                });
+            }
+            if oldest == id {
+                return (None, evicted);
+            }
+        }
+
+        let data = self.chunks.handle(chunk);
+        self.sync_senders();
+        (data, evicted)
+    }
+}
+
+impl<const MTU: usize, T> ChunkManager for ChunkPool<MTU, T> {
+    fn list_completed(&self) -> Vec<Uuid> {
+        self.chunks.list_completed()
+    }
+
+    fn list_pending(&self) -> Vec<Uuid> {
+        self.chunks.list_pending()
+    }
+
+    fn get(&self, id: Uuid) -> Option<Bytes> {
+        self.chunks.get(id)
+    }
+
+    fn remove(&mut self, id: Uuid) {
+        self.chunks.remove(id);
+        self.senders.remove(&id);
+    }
+
+    fn remove_expired(&mut self) {
+        self.chunks.remove_expired();
+        self.sync_senders();
+    }
+
+    /// Handle a chunk not attributed to any peer, which is not limited by budget.
+    fn handle(&mut self, chunk: Chunk) -> Option<Bytes> {
+        let data = self.chunks.handle(chunk);
+        self.sync_senders();
+        data
+    }
+
+    fn peer_memory(&self, did: Did) -> usize {
+        self.chunks
+            .as_vec()
+            .iter()
+            .filter(|c| matches!(self.senders.get(&c.meta.id), Some((s, _, _)) if *s == did))
+            .map(|c| c.data.len() + CHUNK_OVERHEAD)
+            .sum()
+    }
+}
+
+/// Pacing of chunk transmission. Large messages are split into many chunks,
+/// sending them back-to-back may overflow the send buffer of the data channel
+/// or the reassembly buffer of receiver.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ChunkPacing {
+    /// Send chunks as fast as possible.
+    #[default]
+    Unpaced,
+    /// Send at most `chunks` chunks in every `interval_ms`.
+    ChunksPerInterval {
+        /// chunks allowed in an interval
+        chunks: usize,
+        /// length of interval
+        interval_ms: u64,
+    },
+    /// Limit the bytes sent per second.
+    BytesPerSecond(u64),
+}
+
+impl ChunkPacing {
+    /// Milliseconds to wait before sending the next chunk,
+    /// given chunks and bytes already sent in `elapsed_ms`.
+    pub fn delay_ms(&self, sent_chunks: usize, sent_bytes: usize, elapsed_ms: u64) -> u64 {
+        let expected_ms = match *self {
+            Self::Unpaced => 0,
+            Self::ChunksPerInterval {
+                chunks,
+                interval_ms,
+            } if chunks > 0 => (sent_chunks / chunks) as u64 * interval_ms,
+            Self::BytesPerSecond(rate) if rate > 0 => sent_bytes as u64 * 1000 / rate,
+            _ => 0,
+        };
+        expected_ms.saturating_sub(elapsed_ms)
+    }
+}
+
+/// Apply [ChunkPacing] to a sequence of chunk sendings.
+#[derive(Debug, Clone)]
+pub struct ChunkPacer {
+    pacing: ChunkPacing,
+    start_ms: u128,
+    sent_chunks: usize,
+    sent_bytes: usize,
+}
+
+impl ChunkPacer {
+    /// Create a pacer, the rate is measured from now.
+    pub fn new(pacing: ChunkPacing) -> Self {
+        Self {
+            pacing,
+            start_ms: get_epoch_ms(),
+            sent_chunks: 0,
+            sent_bytes: 0,
+        }
+    }
+
+    /// Wait until a chunk of `len` bytes is allowed to send, and count it as sent.
+    /// Returns the milliseconds waited.
+    pub async fn pace(&mut self, len: usize) -> u64 {
+        let elapsed_ms = get_epoch_ms().saturating_sub(self.start_ms) as u64;
+        let delay = self
+            .pacing
+            .delay_ms(self.sent_chunks, self.sent_bytes, elapsed_ms);
+        sleep_ms(delay).await;
+        self.sent_chunks += 1;
+        self.sent_bytes += len;
+        delay
     }
 }
 
 #[cfg(test)]
 mod test {
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+
     use super::*;
 
+    /// Deliver chunks to a list in the given order of their positions in `chunks`,
+    /// returns messages reassembled in the order they complete.
+    fn deliver(cl: &mut ChunkList<32>, chunks: &[Chunk], order: &[usize]) -> Vec<Bytes> {
+        order
+            .iter()
+            .filter_map(|i| cl.handle(chunks[*i].clone()))
+            .collect()
+    }
+
+    fn message(len: usize) -> (Bytes, Vec<Chunk>) {
+        let data: Bytes = (0..len).map(|i| i as u8).collect::<Vec<u8>>().into();
+        let chunks = ChunkList::<32>::from(&data).to_vec();
+        (data, chunks)
+    }
+
+    #[test]
+    fn test_chunk_reassembly_in_any_order() {
+        let (data, chunks) = message(32 * 10 + 7);
+        let n = chunks.len();
+
+        let reverse: Vec<usize> = (0..n).rev().collect();
+        let mut cl = ChunkList::<32>::default();
+        assert_eq!(deliver(&mut cl, &chunks, &reverse), vec![data.clone()]);
+        assert!(cl.as_vec().is_empty());
+
+        // Orders are reproducible by seed.
+        for seed in 0..16 {
+            let mut order: Vec<usize> = (0..n).collect();
+            order.shuffle(&mut StdRng::seed_from_u64(seed));
+            let mut cl = ChunkList::<32>::default();
+            assert_eq!(
+                deliver(&mut cl, &chunks, &order),
+                vec![data.clone()],
+                "seed {seed}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_chunk_reassembly_interleaved() {
+        let (data1, chunks1) = message(32 * 4);
+        let (data2, chunks2) = message(32 * 3 + 1);
+        let chunks: Vec<Chunk> = chunks1
+            .iter()
+            .cloned()
+            .interleave(chunks2.iter().cloned())
+            .collect();
+        // a0 b0 a1 b1 a2 b2 b3 a3
+        let mut order: Vec<usize> = (0..chunks.len()).collect();
+        order.swap(6, 7);
+
+        let mut cl = ChunkList::<32>::default();
+        let received = deliver(&mut cl, &chunks, &order[..6]);
+        assert!(received.is_empty());
+        assert_eq!(cl.list_pending(), vec![
+            chunks1[0].meta.id,
+            chunks2[0].meta.id
+        ]);
+        assert!(cl.list_completed().is_empty());
+
+        let received = deliver(&mut cl, &chunks, &order[6..]);
+        assert_eq!(received, vec![data2, data1]);
+        assert!(cl.as_vec().is_empty());
+    }
+
+    #[test]
+    fn test_chunk_reassembly_duplicated() {
+        let (data, chunks) = message(32 * 3);
+        let id = chunks[0].meta.id;
+
+        // Duplicates not adjacent to each other don't count as other chunks.
+        let mut cl = ChunkList::<32>::default();
+        assert!(deliver(&mut cl, &chunks, &[1, 0, 1]).is_empty());
+        assert_eq!(cl.missing(id), Some(vec![2]));
+        assert_eq!(cl.search(id).to_vec().len(), 2);
+        assert_eq!(deliver(&mut cl, &chunks, &[2]), vec![data.clone()]);
+
+        // A chunk delivered again after its message completes starts it over.
+        assert!(deliver(&mut cl, &chunks, &[0]).is_empty());
+        assert_eq!(cl.list_pending(), vec![id]);
+    }
+
+    #[test]
+    fn test_chunk_reassembly_missing() {
+        let (data, chunks) = message(32 * 6);
+        let id = chunks[0].meta.id;
+
+        let mut cl = ChunkList::<32>::default();
+        assert_eq!(cl.missing(id), None);
+        assert!(deliver(&mut cl, &chunks, &[5, 0, 3]).is_empty());
+        assert_eq!(cl.missing(id), Some(vec![1, 2, 4]));
+        assert_eq!(cl.get(id), None);
+
+        // A forged chunk out of its message doesn't fill the gap.
+        let mut forged = chunks[0].clone();
+        forged.chunk = [6, 6];
+        assert!(cl.handle(forged).is_none());
+        assert_eq!(cl.missing(id), Some(vec![1, 2, 4]));
+
+        // Chunks of an expired message are dropped.
+        let expired_at = chunks[0].meta.ts_ms + chunks[0].meta.ttl_ms as u128;
+        assert!(cl.handle_at(chunks[1].clone(), expired_at).is_none());
+        assert_eq!(cl.missing(id), None);
+
+        assert!(deliver(&mut cl, &chunks, &[4, 2, 1, 0, 3]).is_empty());
+        assert_eq!(cl.missing(id), Some(vec![5]));
+        assert_eq!(deliver(&mut cl, &chunks, &[5]), vec![data]);
+    }
+
     #[test]
     fn test_data_chunks() {
         let data = "helloworld".repeat(2).into();
@@ -410,4 +817,109 @@ mod test {
         cl.handle(regular);
         assert_eq!(cl.as_vec().len(), 6);
     }
+
+    #[test]
+    fn test_chunk_pool_peer_budget() {
+        let (a, b): (Did, Did) = (1u32.into(), 2u32.into());
+        // Bytes counted for a chunk of 32 bytes.
+        let unit = 32 + CHUNK_OVERHEAD;
+        let budget = unit * 3 + unit / 2;
+        let mut pool = ChunkPool::<32>::new(budget);
+        let message = |len: usize| ChunkList::<32>::from(&Bytes::from(vec![1u8; len])).to_vec();
+
+        // Three incomplete messages of 64 bytes from a, each buffering 32 bytes.
+        let (m1, m2, m3) = (message(64), message(64), message(64));
+        for m in [&m1, &m2, &m3] {
+            assert_eq!(pool.handle_from(a, m[0].clone()), (None, vec![]));
+        }
+        assert_eq!(pool.peer_memory(a), unit * 3);
+
+        // Peers don't share budget.
+        let other = message(64);
+        assert_eq!(pool.handle_from(b, other[0].clone()), (None, vec![]));
+        assert_eq!(pool.peer_memory(b), unit);
+
+        // A chunk of another message from a evicts the oldest one.
+        let m4 = message(64);
+        let (data, evicted) = pool.handle_from(a, m4[0].clone());
+        assert!(data.is_none());
+        assert_eq!(evicted, vec![ChunkEvicted {
+            did: a,
+            id: m1[0].meta.id,
+            bytes: unit,
+            origin: (),
+        }]);
+        assert_eq!(pool.peer_memory(a), unit * 3);
+
+        // The message in progress is kept, evicting the oldest other one.
+        let (data, evicted) = pool.handle_from(a, m2[1].clone());
+        assert_eq!(data.unwrap().len(), 64);
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].id, m3[0].meta.id);
+        assert_eq!(pool.peer_memory(a), unit);
+
+        // A peer can't inject chunks into a message of another.
+        assert_eq!(pool.handle_from(a, other[1].clone()), (None, vec![]));
+        assert_eq!(pool.peer_memory(b), unit);
+
+        // A message larger than budget evicts itself at last.
+        let huge = message(32 * 5);
+        let huge_id = huge[0].meta.id;
+        let mut evicted = vec![];
+        for c in huge {
+            evicted.extend(pool.handle_from(b, c).1);
+        }
+        assert!(evicted.iter().any(|e| e.id == other[0].meta.id));
+        assert!(evicted.iter().any(|e| e.id == huge_id));
+        assert!(pool.peer_memory(b) <= budget);
+        assert_eq!(pool.peer_memory(a), unit);
+    }
+
+    #[test]
+    fn test_chunk_pool_empty_chunks() {
+        let a: Did = 1u32.into();
+        let budget = CHUNK_OVERHEAD * 4;
+        let mut pool = ChunkPool::<32, usize>::new(budget);
+        let empty = || Chunk {
+            chunk: [0, 2],
+            data: Bytes::new(),
+            meta: ChunkMeta::default(),
+        };
+
+        // Empty chunks are counted against budget, evicting the oldest with its origin.
+        let mut evicted = vec![];
+        for i in 0..10 {
+            evicted.extend(pool.handle_from_with(a, empty(), || i).1);
+        }
+        assert_eq!(pool.peer_memory(a), budget);
+        assert_eq!(pool.list_pending().len(), 4);
+        assert_eq!(
+            evicted.iter().map(|e| e.origin).collect::<Vec<_>>(),
+            (0..6).collect::<Vec<_>>()
+        );
+        assert!(evicted.iter().all(|e| e.bytes == CHUNK_OVERHEAD));
+    }
+
+    #[test]
+    fn test_chunk_pacing() {
+        let pacing = ChunkPacing::default();
+        assert_eq!(pacing.delay_ms(100, 1 << 20, 0), 0);
+
+        let pacing = ChunkPacing::ChunksPerInterval {
+            chunks: 4,
+            interval_ms: 100,
+        };
+        assert_eq!(pacing.delay_ms(3, 0, 0), 0);
+        assert_eq!(pacing.delay_ms(4, 0, 30), 70);
+        assert_eq!(pacing.delay_ms(8, 0, 150), 50);
+        assert_eq!(pacing.delay_ms(8, 0, 250), 0);
+
+        let pacing = ChunkPacing::BytesPerSecond(1000);
+        assert_eq!(pacing.delay_ms(0, 0, 0), 0);
+        assert_eq!(pacing.delay_ms(1, 500, 100), 400);
+        assert_eq!(pacing.delay_ms(2, 1000, 1200), 0);
+
+        let pacing = ChunkPacing::BytesPerSecond(0);
+        assert_eq!(pacing.delay_ms(1, 500, 0), 0);
+    }
 }
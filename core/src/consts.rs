@@ -7,4 +7,16 @@ pub const TS_OFFSET_TOLERANCE_MS: u128 = 3000;
 pub const DEFAULT_SESSION_TTL_MS: u64 = 30 * 24 * 3600 * 1000;
 pub const TRANSPORT_MTU: usize = 60000;
 pub const TRANSPORT_MAX_SIZE: usize = TRANSPORT_MTU * 16;
+/// Default max size of data decompressed or reassembled from chunks, which rejects
+/// decompression bombs, a few bytes inflating to gigabytes.
+pub const MAX_DECOMPRESSED_SIZE: usize = TRANSPORT_MAX_SIZE * 16;
 pub const VNODE_DATA_MAX_LEN: usize = 1024;
+/// Max number of vnodes moved to successor by one sync, the rest is moved by following syncs.
+pub const VNODE_SYNC_BATCH_SIZE: usize = 32;
+/// Max number of replicas collected for a vnode by a search, see
+/// [PeerRing::local_replicas_add](crate::dht::PeerRing::local_replicas_add).
+pub const MAX_VNODE_REPLICAS: usize = 64;
+/// Max number of nodes remembered as joined by DHT, the earliest joined is forgotten first.
+pub const DHT_JOINED_MAX_ENTRIES: usize = 1024;
+/// Time in ms a node is remembered as joined by DHT, a later join is handled as a new one.
+pub const DHT_JOINED_TTL_MS: u128 = 600 * 1000;
@@ -1,5 +1,6 @@
 //! Chord algorithm implement.
 #![warn(missing_docs)]
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::sync::MutexGuard;
@@ -16,9 +17,19 @@ use super::types::ChordStorage;
 use super::types::ChordStorageCache;
 use super::types::ChordStorageSync;
 use super::types::CorrectChord;
+use super::vnode::VNodeChange;
+use super::vnode::VNodeChanged;
 use super::vnode::VNodeOperation;
+use super::vnode::VNodeReplica;
+use super::vnode::VNodeRevision;
 use super::vnode::VirtualNode;
+use super::watch::VNodeSubscriptions;
+use super::watch::VNodeWatchers;
 use super::FingerTable;
+use crate::consts::DHT_JOINED_MAX_ENTRIES;
+use crate::consts::DHT_JOINED_TTL_MS;
+use crate::consts::MAX_VNODE_REPLICAS;
+use crate::consts::VNODE_SYNC_BATCH_SIZE;
 use crate::dht::Did;
 use crate::dht::LiveDid;
 use crate::dht::SuccessorReader;
@@ -29,6 +40,7 @@ use crate::storage::MemStorage;
 use crate::storage::PersistenceStorage;
 use crate::storage::PersistenceStorageReadAndWrite;
 use crate::storage::PersistenceStorageRemove;
+use crate::utils::get_epoch_ms;
 
 /// PeerRing is used to help a node interact with other nodes.
 /// All nodes in rings network form a clockwise ring in the order of Did.
@@ -51,6 +63,17 @@ pub struct PeerRing {
     pub storage: Arc<PersistenceStorage>,
     /// Local cache for [ChordStorage].
     pub cache: Arc<MemStorage<Did, VirtualNode>>,
+    /// Local cache of replicas collected by searching all replicas of a vnode.
+    pub replicas: Arc<MemStorage<Did, Vec<VNodeReplica>>>,
+    /// Revisions of vnodes in local storage, see [VNodeRevision].
+    pub revisions: Arc<MemStorage<Did, VNodeRevision>>,
+    /// Nodes joined since they were last removed, with the time they joined,
+    /// see [PeerRing::is_joined].
+    joined: Arc<Mutex<HashMap<Did, u128>>>,
+    /// Watchers of vnodes stored on current node, see [watch](super::watch).
+    pub vnode_watchers: VNodeWatchers,
+    /// Subscriptions of current node to changes of watched vnodes.
+    pub vnode_subscriptions: VNodeSubscriptions,
 }
 
 /// Type alias is just for making the code easy to read.
@@ -90,6 +113,8 @@ pub enum RemoteAction {
     Notify(Did),
     /// Let `did_a` sync data with it's successor.
     SyncVNodeWithSuccessor(Vec<VirtualNode>),
+    /// Push a change of vnode to its watcher `did_a`.
+    NotifyVNodeChanged(VNodeChanged),
 
     /// Need `did_a` to find `did_b` then send back with `for connect` flag.
     FindSuccessorForConnect(Did),
@@ -192,6 +217,11 @@ impl PeerRing {
             finger: Arc::new(Mutex::new(FingerTable::new(did, 160))),
             storage: Arc::new(storage),
             cache: Arc::new(MemStorage::<Did, VirtualNode>::new()),
+            replicas: Arc::new(MemStorage::<Did, Vec<VNodeReplica>>::new()),
+            revisions: Arc::new(MemStorage::<Did, VNodeRevision>::new()),
+            joined: Arc::new(Mutex::new(HashMap::new())),
+            vnode_watchers: VNodeWatchers::default(),
+            vnode_subscriptions: VNodeSubscriptions::default(),
             did,
         }
     }
@@ -207,11 +237,78 @@ impl PeerRing {
         self.successor_seq.clone()
     }
 
+    /// Read the replica stored under `key` from local storage.
+    pub async fn local_replica(&self, key: Did) -> VNodeReplica {
+        let vnode: Option<VirtualNode> = self.storage.get(&key).await.ok().flatten();
+        let revision = self.revisions.get(&key).unwrap_or_default();
+        VNodeReplica::new(self.did, key, vnode, revision)
+    }
+
+    /// Start collecting replicas of `vid`, forgetting the ones collected before.
+    pub fn local_replicas_start(&self, vid: Did) {
+        self.replicas.set(&vid, vec![]);
+    }
+
+    /// Record a replica of `vid`. A newer record of the same holder and key replaces the old one.
+    /// Returns false if the replica is dropped, since no search of `vid` is started by
+    /// [PeerRing::local_replicas_start], or [MAX_VNODE_REPLICAS] replicas are collected.
+    pub fn local_replicas_add(&self, vid: Did, replica: VNodeReplica) -> bool {
+        let Some(mut replicas) = self.replicas.get(&vid) else {
+            return false;
+        };
+        replicas.retain(|r| r.holder != replica.holder || r.key != replica.key);
+        if replicas.len() >= MAX_VNODE_REPLICAS {
+            return false;
+        }
+        replicas.push(replica);
+        self.replicas.set(&vid, replicas);
+        true
+    }
+
+    /// Get all replicas of `vid` collected so far.
+    pub fn local_replicas_get(&self, vid: Did) -> Vec<VNodeReplica> {
+        self.replicas.get(&vid).unwrap_or_default()
+    }
+
+    /// Forget replicas of `vid` collected before.
+    pub fn local_replicas_clear(&self, vid: Did) {
+        self.replicas.remove(&vid);
+    }
+
     /// Lock and return MutexGuard of finger table.
     pub fn lock_finger(&self) -> Result<MutexGuard<FingerTable>> {
         self.finger.lock().map_err(|_| Error::DHTSyncLockError)
     }
 
+    /// Check if a node is joined and not removed since then.
+    /// A repeated [Chord::join] of such node only refreshes local state.
+    /// A node is remembered for [DHT_JOINED_TTL_MS], and at most [DHT_JOINED_MAX_ENTRIES]
+    /// nodes are remembered, so that nodes never removed don't grow it unbounded.
+    pub fn is_joined(&self, did: Did) -> Result<bool> {
+        let joined = self.joined.lock().map_err(|_| Error::DHTSyncLockError)?;
+        let now = get_epoch_ms();
+        Ok(joined
+            .get(&did)
+            .is_some_and(|ts| now.saturating_sub(*ts) < DHT_JOINED_TTL_MS))
+    }
+
+    /// Remember a node as joined, returns false if it's joined already.
+    fn mark_joined(&self, did: Did) -> Result<bool> {
+        let mut joined = self.joined.lock().map_err(|_| Error::DHTSyncLockError)?;
+        let now = get_epoch_ms();
+        joined.retain(|_, ts| now.saturating_sub(*ts) < DHT_JOINED_TTL_MS);
+        if joined.contains_key(&did) {
+            return Ok(false);
+        }
+        if joined.len() >= DHT_JOINED_MAX_ENTRIES {
+            if let Some(earliest) = joined.iter().min_by_key(|(_, ts)| **ts).map(|(d, _)| *d) {
+                joined.remove(&earliest);
+            }
+        }
+        joined.insert(did, now);
+        Ok(true)
+    }
+
     /// Lock and return MutexGuard of predecessor.
     pub fn lock_predecessor(&self) -> Result<MutexGuard<Option<Did>>> {
         self.predecessor.lock().map_err(|_| Error::DHTSyncLockError)
@@ -231,6 +328,10 @@ impl PeerRing {
         }
         finger.remove(did);
         successor.remove(did)?;
+        self.joined
+            .lock()
+            .map_err(|_| Error::DHTSyncLockError)?
+            .remove(&did);
         if successor.is_empty()? {
             if let Some(x) = finger.first() {
                 successor.update(x)?;
@@ -243,6 +344,23 @@ impl PeerRing {
     pub fn bias(&self, did: Did) -> BiasId {
         BiasId::new(self.did, did)
     }
+
+    /// Publish a change of vnode to local subscriptions, and return actions
+    /// pushing it to remote watchers.
+    fn notify_vnode_watchers(&self, changed: VNodeChanged) -> Vec<PeerRingAction> {
+        let mut ret = vec![];
+        for watcher in self.vnode_watchers.watchers(changed.vid) {
+            if watcher == self.did {
+                self.vnode_subscriptions.publish(&changed);
+            } else {
+                ret.push(PeerRingAction::RemoteAction(
+                    watcher,
+                    RemoteAction::NotifyVNodeChanged(changed.clone()),
+                ));
+            }
+        }
+        ret
+    }
 }
 
 impl Chord<PeerRingAction> for PeerRing {
@@ -253,6 +371,12 @@ impl Chord<PeerRingAction> for PeerRing {
     /// This method will return a [RemoteAction::FindSuccessorForConnect] to the caller.
     /// The caller will send it to the node identified by `did`, and let the node find
     /// the successor of current node and make current node connect to that successor.
+    ///
+    /// Joining is idempotent. A node may join repeatedly when it retries, or reconnects
+    /// over another transport without being removed. Such a join refreshes finger table
+    /// and successor sequence, which is a no-op for a known node, and returns
+    /// [PeerRingAction::None], so that no redundant remote work is triggered.
+    /// The ring is keyed by Did, so a node keeps its position when its transport changes.
     fn join(&self, did: Did) -> Result<PeerRingAction> {
         if did == self.did {
             return Ok(PeerRingAction::None);
@@ -263,6 +387,11 @@ impl Chord<PeerRingAction> for PeerRing {
         finger.join(did);
         // Always try update
         self.successors().update(did)?;
+
+        if !self.mark_joined(did)? {
+            tracing::debug!("node {} joined again, skip remote actions", did);
+            return Ok(PeerRingAction::None);
+        }
         Ok(PeerRingAction::RemoteAction(
             did,
             RemoteAction::FindSuccessorForConnect(self.did),
@@ -437,13 +566,19 @@ impl<const REDUNDANT: u16> ChordStorage<PeerRingAction, REDUNDANT> for PeerRing
             let maybe_act = match self.find_successor(vid) {
                 // `vnode` should be on current node.
                 Ok(PeerRingAction::Some(_)) => {
-                    let this = if let Ok(Some(this)) = self.storage.get(&vid).await {
-                        Ok(this)
-                    } else {
-                        op.clone().gen_default_vnode()
-                    }?;
+                    let (this, change) = match self.storage.get(&vid).await {
+                        Ok(Some(this)) => (this, VNodeChange::Updated),
+                        _ => (op.clone().gen_default_vnode()?, VNodeChange::Stored),
+                    };
                     let vnode = this.operate(op.clone())?;
                     self.storage.put(&vid, &vnode).await?;
+                    let revision = self.revisions.get(&vid).unwrap_or_default().next();
+                    self.revisions.set(&vid, revision);
+                    ret.extend(self.notify_vnode_watchers(VNodeChanged {
+                        vid,
+                        change,
+                        vnode: Some(vnode),
+                    }));
                     Ok(PeerRingAction::None)
                 }
                 // `vnode` should be on other nodes.
@@ -470,14 +605,23 @@ impl ChordStorageSync<PeerRingAction> for PeerRing {
     /// When the successor of a node is updated, it needs to check if there are
     /// `VirtualNode`s that are no longer between current node and `new_successor`,
     /// and sync them to the new successor.
+    /// At most [VNODE_SYNC_BATCH_SIZE] vnodes are popped out at once, call it again
+    /// to sync the rest.
     async fn sync_vnode_with_successor(&self, new_successor: Did) -> Result<PeerRingAction> {
+        if new_successor == self.did {
+            return Ok(PeerRingAction::None);
+        }
         let mut data = Vec::<VirtualNode>::new();
         let all_items: Vec<(Did, VirtualNode)> = self.storage.get_all().await?;
 
-        // Pop out all items that are not between current node and `new_successor`.
+        // Pop out items that are not between current node and `new_successor`.
         for (vid, vnode) in all_items.iter() {
+            if data.len() >= VNODE_SYNC_BATCH_SIZE {
+                break;
+            }
             if self.bias(*vid) > self.bias(new_successor) && self.storage.remove(vid).await.is_ok()
             {
+                self.revisions.remove(vid);
                 data.push(vnode.clone());
             }
         }
@@ -485,7 +629,7 @@ impl ChordStorageSync<PeerRingAction> for PeerRing {
         if !data.is_empty() {
             Ok(PeerRingAction::RemoteAction(
                 new_successor,
-                RemoteAction::SyncVNodeWithSuccessor(data), // TODO: This might be too large.
+                RemoteAction::SyncVNodeWithSuccessor(data),
             ))
         } else {
             Ok(PeerRingAction::None)
@@ -700,10 +844,10 @@ mod tests {
         assert_eq!(node_a.successors().list()?, vec![b]);
 
         // Test repeated join.
-        node_a.join(b)?;
+        assert_eq!(node_a.join(b)?, PeerRingAction::None);
         assert_eq!(node_a.lock_finger()?.list(), &expected_finger_list);
         assert_eq!(node_a.successors().list()?, vec![b]);
-        node_a.join(b)?;
+        assert_eq!(node_a.join(b)?, PeerRingAction::None);
         assert_eq!(node_a.lock_finger()?.list(), &expected_finger_list);
         assert_eq!(node_a.successors().list()?, vec![b]);
 
@@ -856,6 +1000,100 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_duplicate_join() -> Result<()> {
+        let a = Did::from_str("0x00E807fcc88dD319270493fB2e822e388Fe36ab0").unwrap();
+        let b = Did::from_str("0x119999cf1046e68e36E1aA2E0E07105eDDD1f08E").unwrap();
+        let c = Did::from_str("0xccffee254729296a45a3885639AC7E10F9d54979").unwrap();
+        let db_path = PersistenceStorage::random_path("./tmp");
+        let db = PersistenceStorage::new_with_path(db_path.as_str())
+            .await
+            .unwrap();
+        let node = PeerRing::new_with_storage(a, 3, db);
+
+        assert!(node.join(b)?.is_remote());
+        assert!(node.join(c)?.is_remote());
+        let finger = node.lock_finger()?.list().clone();
+        let successors = node.successors().list()?;
+
+        // Repeated joins, in any order, neither change ring state nor trigger remote actions.
+        for did in [b, c, b, b, c] {
+            assert_eq!(node.join(did)?, PeerRingAction::None);
+            assert!(node.is_joined(did)?);
+        }
+        assert_eq!(node.lock_finger()?.list(), &finger);
+        assert_eq!(node.successors().list()?, successors);
+
+        // A node removed, such as on disconnection, joins again as a new node.
+        node.remove(b)?;
+        assert!(!node.is_joined(b)?);
+        assert_eq!(
+            node.join(b)?,
+            PeerRingAction::RemoteAction(b, RemoteAction::FindSuccessorForConnect(a))
+        );
+        assert_eq!(node.lock_finger()?.list(), &finger);
+        assert_eq!(node.successors().list()?, successors);
+
+        // Nodes remembered as joined expire, and are capped by forgetting the earliest joined.
+        node.joined.lock().unwrap().insert(c, 0);
+        node.joined.lock().unwrap().insert(b, get_epoch_ms() - 1);
+        for i in 0..DHT_JOINED_MAX_ENTRIES as u32 {
+            node.join(Did::from(i + 1))?;
+        }
+        assert_eq!(node.joined.lock().unwrap().len(), DHT_JOINED_MAX_ENTRIES);
+        assert!(!node.is_joined(b)?);
+        assert!(node.join(c)?.is_remote());
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_vnode_with_successor_in_batches() -> Result<()> {
+        let a = Did::from_str("0x00E807fcc88dD319270493fB2e822e388Fe36ab0").unwrap();
+        let b = Did::from_str("0x7f9999cf1046e68e36E1aA2E0E07105eDDD1f08E").unwrap();
+        let db_path = PersistenceStorage::random_path("./tmp");
+        let db = PersistenceStorage::new_with_path(db_path.as_str())
+            .await
+            .unwrap();
+        let node = PeerRing::new_with_storage(a, 3, db);
+
+        // Nothing is synced to the node itself.
+        assert_eq!(
+            node.sync_vnode_with_successor(a).await?,
+            PeerRingAction::None
+        );
+
+        for i in 0..VNODE_SYNC_BATCH_SIZE * 3 {
+            let vnode = VirtualNode::try_from(format!("vnode {}", i))?;
+            node.storage.put(&vnode.did, &vnode).await?;
+        }
+        let all: Vec<(Did, VirtualNode)> = node.storage.get_all().await?;
+        let expected = all
+            .into_iter()
+            .filter(|(vid, _)| node.bias(*vid) > node.bias(b))
+            .count();
+        assert!(expected > VNODE_SYNC_BATCH_SIZE);
+
+        // Vnodes in charge of b are moved batch by batch, and the rest are kept.
+        let mut moved = 0;
+        while let PeerRingAction::RemoteAction(next, RemoteAction::SyncVNodeWithSuccessor(data)) =
+            node.sync_vnode_with_successor(b).await?
+        {
+            assert_eq!(next, b);
+            assert!(data.len() <= VNODE_SYNC_BATCH_SIZE);
+            assert!(data.iter().all(|v| node.bias(v.did) > node.bias(b)));
+            moved += data.len();
+        }
+        assert_eq!(moved, expected);
+        let kept: Vec<(Did, VirtualNode)> = node.storage.get_all().await?;
+        assert_eq!(kept.len(), VNODE_SYNC_BATCH_SIZE * 3 - expected);
+        assert!(kept.iter().all(|(vid, _)| node.bias(*vid) <= node.bias(b)));
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_two_node_finger_failed_case() -> Result<()> {
         let did1 = Did::from_str("0x051cf4f8d020cb910474bef3e17f153fface2b5f").unwrap();
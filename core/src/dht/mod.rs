@@ -30,6 +30,7 @@ pub use stabilization::TStabilize;
 pub mod subring;
 /// VNode is a special node that only has virtual address
 pub mod vnode;
+pub mod watch;
 
 #[cfg(test)]
 pub mod tests {
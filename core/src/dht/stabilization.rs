@@ -1,12 +1,14 @@
 //! Stabilization wait to notify predecessors and update fingersTable.
 use std::sync::Arc;
 
+use async_lock::Mutex;
 use async_trait::async_trait;
 use rings_transport::core::transport::ConnectionInterface;
 
 use crate::dht::successor::SuccessorReader;
 use crate::dht::types::CorrectChord;
 use crate::dht::Chord;
+use crate::dht::ChordStorageSync;
 use crate::dht::PeerRing;
 use crate::dht::PeerRingAction;
 use crate::dht::PeerRingRemoteAction;
@@ -20,8 +22,16 @@ use crate::message::MessagePayload;
 use crate::message::NotifyPredecessorSend;
 use crate::message::PayloadSender;
 use crate::message::QueryForTopoInfoSend;
+use crate::message::SyncVNodeWithSuccessor;
+use crate::storage::PersistenceStorageReadAndWrite;
+use crate::swarm::CancellationToken;
+use crate::swarm::Heartbeat;
+use crate::swarm::Pulse;
 use crate::swarm::Swarm;
 
+/// Interval of stabilization in seconds before initial convergence is completed.
+pub const INITIAL_STABILIZE_INTERVAL: usize = 1;
+
 /// A combination contains chord and swarm, use to run stabilize.
 /// - swarm: transports communicate with each others.
 /// - chord: fix local fingers table.
@@ -30,13 +40,19 @@ pub struct Stabilization {
     chord: Arc<PeerRing>,
     swarm: Arc<Swarm>,
     timeout: usize,
+    /// Held while a stabilization pass is running.
+    running: Arc<Mutex<()>>,
+    /// Cancelled once stabilization is stopped.
+    stop_token: CancellationToken,
+    /// Heartbeat of the loop of [TStabilize::wait], busy while a pass is running.
+    heartbeat: Heartbeat,
 }
 
 /// A trait with `wait` method.
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
 #[cfg_attr(not(feature = "wasm"), async_trait)]
 pub trait TStabilize {
-    /// Wait and poll
+    /// Wait and poll, until [Stabilization::stop] is called.
     async fn wait(self: Arc<Self>);
 }
 
@@ -54,6 +70,21 @@ impl Stabilization {
 
         Ok(())
     }
+
+    /// Reconnect pinned peers whose connections are lost.
+    pub async fn reconnect_pinned_peers(&self) -> Result<()> {
+        for did in self.swarm.pinned_peers() {
+            if self.swarm.get_connection(did).is_some() {
+                continue;
+            }
+            tracing::info!("STABILIZATION reconnect_pinned_peers: {:?}", did);
+            if let Err(e) = self.swarm.connect(did).await {
+                tracing::warn!("[stabilize] Failed on reconnect pinned peer {did}: {e:?}");
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl Stabilization {
@@ -63,6 +94,9 @@ impl Stabilization {
             chord: swarm.dht(),
             swarm,
             timeout,
+            running: Arc::new(Mutex::new(())),
+            stop_token: CancellationToken::new(),
+            heartbeat: Heartbeat::default(),
         }
     }
 
@@ -137,6 +171,38 @@ impl Stabilization {
     }
 }
 
+impl Stabilization {
+    /// Move vnodes no longer in charge of to successor, such as after a node joined between
+    /// this node and its former successor. One batch is moved in each pass, so that a large
+    /// transfer never blocks stabilization, and a batch failed on sending is kept for next pass.
+    pub async fn sync_vnode_with_successor(&self) -> Result<()> {
+        let successor = self.chord.successors().min()?;
+        let PeerRingAction::RemoteAction(next, PeerRingRemoteAction::SyncVNodeWithSuccessor(data)) =
+            self.chord.sync_vnode_with_successor(successor).await?
+        else {
+            return Ok(());
+        };
+        tracing::debug!("STABILIZATION sync {} vnodes to {:?}", data.len(), next);
+        let msg = Message::SyncVNodeWithSuccessor(SyncVNodeWithSuccessor { data: data.clone() });
+        if let Err(e) = self.swarm.send_message(msg, next).await {
+            for vnode in data {
+                self.chord.storage.put(&vnode.did, &vnode).await?;
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Renew watches of subscribed vnodes before their leases expire. A renewal reaches
+    /// the node currently responsible for the vnode, so watches follow migrated vnodes.
+    pub async fn renew_vnode_watches(&self) -> Result<()> {
+        for vid in self.chord.vnode_subscriptions.due_for_renewal() {
+            self.swarm.storage_watch(vid).await?;
+        }
+        Ok(())
+    }
+}
+
 impl Stabilization {
     /// Call stabilization from correct chord implementation
     pub async fn correct_stabilize(&self) -> Result<()> {
@@ -158,6 +224,9 @@ impl Stabilization {
 impl Stabilization {
     /// Call stabilize periodly.
     pub async fn stabilize(&self) -> Result<()> {
+        if !self.swarm.dht_enabled() {
+            return self.maintain_connections().await;
+        }
         tracing::debug!("STABILIZATION notify_predecessor start");
         if let Err(e) = self.notify_predecessor().await {
             tracing::error!("[stabilize] Failed on notify predecessor {:?}", e);
@@ -168,6 +237,28 @@ impl Stabilization {
             tracing::error!("[stabilize] Failed on fix_finger {:?}", e);
         }
         tracing::debug!("STABILIZATION fix_fingers end");
+        tracing::debug!("STABILIZATION sync_vnode_with_successor start");
+        if let Err(e) = self.sync_vnode_with_successor().await {
+            tracing::error!("[stabilize] Failed on sync vnode with successor {:?}", e);
+        }
+        tracing::debug!("STABILIZATION sync_vnode_with_successor end");
+        if let Err(e) = self.renew_vnode_watches().await {
+            tracing::error!("[stabilize] Failed on renew vnode watches {:?}", e);
+        }
+        self.maintain_connections().await?;
+        #[cfg(feature = "experimental")]
+        {
+            tracing::debug!("STABILIZATION correct_stabilize start");
+            if let Err(e) = self.correct_stabilize() {
+                tracing::error!("[stabilize] Failed on call correct stabilize {:?}", e);
+            }
+            tracing::debug!("STABILIZATION correct_stabilize end");
+        }
+        Ok(())
+    }
+
+    /// Maintain connections regardless of DHT participation.
+    async fn maintain_connections(&self) -> Result<()> {
         tracing::debug!("STABILIZATION clean_unavailable_connections start");
         if let Err(e) = self.clean_unavailable_connections().await {
             tracing::error!(
@@ -176,44 +267,92 @@ impl Stabilization {
             );
         }
         tracing::debug!("STABILIZATION clean_unavailable_connections end");
-        #[cfg(feature = "experimental")]
-        {
-            tracing::debug!("STABILIZATION correct_stabilize start");
-            if let Err(e) = self.correct_stabilize() {
-                tracing::error!("[stabilize] Failed on call correct stabilize {:?}", e);
+        tracing::debug!("STABILIZATION reconnect_pinned_peers start");
+        if let Err(e) = self.reconnect_pinned_peers().await {
+            tracing::error!("[stabilize] Failed on reconnect pinned peers {:?}", e);
+        }
+        tracing::debug!("STABILIZATION reconnect_pinned_peers end");
+        if let Err(e) = self.swarm.announce_if_due().await {
+            tracing::error!("[stabilize] Failed on announce {:?}", e);
+        }
+        if let Err(e) = self.swarm.check_convergence().await {
+            tracing::error!("[stabilize] Failed on check convergence {:?}", e);
+        }
+        Ok(())
+    }
+
+    /// Run one stabilization pass out of band and return when it completes, which is
+    /// useful to converge the ring right after a topology change.
+    /// If a pass is already running, by timer or another trigger, wait for it to complete
+    /// instead of running again.
+    /// Nothing is run once stabilization is stopped.
+    pub async fn trigger(&self) -> Result<()> {
+        if let Some(_guard) = self.running.try_lock() {
+            if self.is_stopped() {
+                return Ok(());
             }
-            tracing::debug!("STABILIZATION correct_stabilize end");
+            let _busy = self.heartbeat.busy();
+            return self.stabilize().await;
         }
+        let _guard = self.running.lock().await;
         Ok(())
     }
+
+    /// Stop stabilization, so that [TStabilize::wait] returns. A pass in progress is finished
+    /// before returning, rather than dropped midway, and no pass runs afterward.
+    pub async fn stop(&self) {
+        self.stop_token.cancel();
+        let _guard = self.running.lock().await;
+        tracing::info!("Stabilization stopped");
+    }
+
+    /// Check if stabilization is stopped.
+    pub fn is_stopped(&self) -> bool {
+        self.stop_token.is_cancelled()
+    }
+
+    /// Report the state of the loop of [TStabilize::wait], which beats on every pass.
+    pub fn pulse(&self) -> Pulse {
+        self.heartbeat.pulse()
+    }
+
+    /// Wait for the next pass after `interval_ms`, returns false if stopped meanwhile.
+    async fn wait_next_pass(&self, interval_ms: u64) -> bool {
+        let stopped = self.stop_token.cancelled();
+        crate::utils::timeout_ms(interval_ms, stopped)
+            .await
+            .is_none()
+            && !self.is_stopped()
+    }
 }
 
 #[cfg(not(feature = "wasm"))]
 mod stabilizer {
     use std::sync::Arc;
-    use std::time::Duration;
 
     use async_trait::async_trait;
-    use futures::future::FutureExt;
-    use futures::pin_mut;
-    use futures::select;
-    use futures_timer::Delay;
 
     use super::Stabilization;
     use super::TStabilize;
+    use super::INITIAL_STABILIZE_INTERVAL;
 
     #[async_trait]
     impl TStabilize for Stabilization {
         async fn wait(self: Arc<Self>) {
+            let _running = self.heartbeat.start();
             loop {
-                let timeout = Delay::new(Duration::from_secs(self.timeout as u64)).fuse();
-                pin_mut!(timeout);
-                select! {
-                    _ = timeout => self
-                        .stabilize()
-                        .await
-                        .unwrap_or_else(|e| tracing::error!("failed to stabilize {:?}", e)),
+                // Stabilize frequently until initial convergence, so that a node joins quickly.
+                let interval = if self.swarm.is_converged() {
+                    self.timeout
+                } else {
+                    self.timeout.min(INITIAL_STABILIZE_INTERVAL)
+                };
+                if !self.wait_next_pass(interval as u64 * 1000).await {
+                    break;
                 }
+                self.trigger()
+                    .await
+                    .unwrap_or_else(|e| tracing::error!("failed to stabilize {:?}", e));
             }
         }
     }
@@ -224,26 +363,22 @@ mod stabilizer {
     use std::sync::Arc;
 
     use async_trait::async_trait;
-    use wasm_bindgen_futures::spawn_local;
 
     use super::Stabilization;
     use super::TStabilize;
-    use crate::poll;
+
+    /// Interval of stabilization in browser, in milliseconds.
+    const WASM_STABILIZE_INTERVAL_MS: u64 = 25000;
 
     #[async_trait(?Send)]
     impl TStabilize for Stabilization {
         async fn wait(self: Arc<Self>) {
-            let caller = Arc::clone(&self);
-            let func = move || {
-                let caller = caller.clone();
-                spawn_local(Box::pin(async move {
-                    caller
-                        .stabilize()
-                        .await
-                        .unwrap_or_else(|e| tracing::error!("failed to stabilize {:?}", e));
-                }))
-            };
-            poll!(func, 25000);
+            let _running = self.heartbeat.start();
+            while self.wait_next_pass(WASM_STABILIZE_INTERVAL_MS).await {
+                self.trigger()
+                    .await
+                    .unwrap_or_else(|e| tracing::error!("failed to stabilize {:?}", e));
+            }
         }
     }
 }
@@ -256,6 +391,7 @@ pub mod tests {
     use rings_transport::core::transport::WebrtcConnectionState;
 
     use super::*;
+    use crate::dht::Did;
     use crate::ecc::SecretKey;
     use crate::tests::default::prepare_node;
     use crate::tests::manually_establish_connection;
@@ -317,4 +453,76 @@ pub mod tests {
         assert!(node1.get_connection(node2.did()).is_none());
         assert!(node1.get_connection(node3.did()).is_none());
     }
+
+    #[tokio::test]
+    async fn test_trigger_concurrently() {
+        let (node1, _) = prepare_node(SecretKey::random()).await;
+        let stb = Stabilization::new(node1, 3);
+
+        let (r1, r2) = futures::join!(stb.trigger(), stb.trigger());
+        assert!(r1.is_ok() && r2.is_ok());
+        // Lock is released after passes complete.
+        assert!(stb.running.try_lock().is_some());
+        stb.trigger().await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_stop_mid_pass() {
+        let (node1, _) = prepare_node(SecretKey::random()).await;
+        let did: Did = SecretKey::random().address().into();
+        node1.dht().join(did).unwrap();
+
+        // Hold the finger table from another thread, so that the pass blocks on fixing fingers.
+        let finger = node1.dht().finger.clone();
+        let (locked_tx, locked_rx) = std::sync::mpsc::channel();
+        let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+        let holder = std::thread::spawn(move || {
+            let _finger = finger.lock().unwrap();
+            locked_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        });
+        locked_rx.recv().unwrap();
+
+        let stb = Arc::new(Stabilization::new(node1.clone(), 1));
+        let waiting = tokio::spawn(stb.clone().wait());
+        let pass = tokio::spawn({
+            let stb = stb.clone();
+            async move { stb.trigger().await }
+        });
+        // Wait for the pass to start.
+        while stb.pulse().busy_ms.is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let stopping = tokio::spawn({
+            let stb = stb.clone();
+            async move { stb.stop().await }
+        });
+        while !stb.is_stopped() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        // Stopping waits for the pass in progress.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!stopping.is_finished());
+        assert!(!pass.is_finished());
+
+        release_tx.send(()).unwrap();
+        holder.join().unwrap();
+        tokio::time::timeout(Duration::from_secs(3), stopping)
+            .await
+            .unwrap()
+            .unwrap();
+        // The pass is finished rather than dropped, and the lock is released.
+        pass.await.unwrap().unwrap();
+        assert!(stb.running.try_lock().is_some());
+        tokio::time::timeout(Duration::from_secs(3), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(node1.dht().successors().list().unwrap(), vec![did]);
+        // Nothing runs once stopped.
+        stb.trigger().await.unwrap();
+        assert_eq!(node1.dht().successors().list().unwrap(), vec![did]);
+    }
 }
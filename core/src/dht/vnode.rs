@@ -16,6 +16,7 @@ use crate::message::Encoded;
 use crate::message::Encoder;
 use crate::message::MessagePayload;
 use crate::message::MessageVerificationExt;
+use crate::utils::get_epoch_ms;
 
 /// VNode Types
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -29,6 +30,31 @@ pub enum VNodeType {
     RelayMessage,
 }
 
+/// Kind of change of a [VirtualNode], see [VNodeChanged].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VNodeChange {
+    /// The vnode is created.
+    Stored,
+    /// The vnode is operated when it exists.
+    Updated,
+    /// The vnode is deleted.
+    Deleted,
+    /// The vnode is expired.
+    Expired,
+}
+
+/// Notification of a change of [VirtualNode], pushed to its watchers,
+/// see [watch](super::watch).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VNodeChanged {
+    /// Did of the vnode
+    pub vid: Did,
+    /// Kind of change
+    pub change: VNodeChange,
+    /// The vnode after change, None if it's deleted or expired.
+    pub vnode: Option<VirtualNode>,
+}
+
 /// VNode Operations
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum VNodeOperation {
@@ -64,6 +90,62 @@ pub struct VirtualNode {
     pub kind: VNodeType,
 }
 
+/// A copy of a [VirtualNode] held by a specific node, returned when searching all replicas.
+/// A replica without `vnode` means the holder is expected to store it but has no copy.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VNodeReplica {
+    /// The did of the node holding this replica.
+    pub holder: Did,
+    /// The storage key of this replica, which is the did of vnode or one of its affine rotations.
+    pub key: Did,
+    /// The replica data, `None` if the holder has no copy.
+    pub vnode: Option<VirtualNode>,
+    /// Number of times the holder wrote the replica, see [VNodeRevision::version].
+    pub version: u64,
+    /// Epoch milliseconds when the holder last wrote the replica, 0 if unknown.
+    /// A later timestamp means a fresher copy.
+    pub timestamp: u128,
+}
+
+impl VNodeReplica {
+    /// Create a replica record of `holder`, versioned by the revision of its storage.
+    pub fn new(
+        holder: Did,
+        key: Did,
+        vnode: Option<VirtualNode>,
+        revision: VNodeRevision,
+    ) -> Self {
+        Self {
+            holder,
+            key,
+            vnode,
+            version: revision.version,
+            timestamp: revision.updated_at,
+        }
+    }
+}
+
+/// Revision of a vnode in local storage, bumped on each write of it by
+/// [ChordStorage::vnode_operate](super::ChordStorage::vnode_operate).
+/// A vnode not written since the node started has the default revision.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VNodeRevision {
+    /// Number of writes.
+    pub version: u64,
+    /// Epoch milliseconds of the last write.
+    pub updated_at: u128,
+}
+
+impl VNodeRevision {
+    /// The revision after another write.
+    pub fn next(self) -> Self {
+        Self {
+            version: self.version + 1,
+            updated_at: get_epoch_ms(),
+        }
+    }
+}
+
 impl VirtualNode {
     /// Generate did from topic.
     pub fn gen_did(topic: &str) -> Result<Did> {
@@ -72,6 +154,13 @@ impl VirtualNode {
         tracing::debug!("gen_did: topic: {}, did: {:?}", topic, did);
         did
     }
+
+    /// Generate topic scoped in namespace, such as an application id.
+    /// The namespace is length prefixed before hashing, so topics of different
+    /// namespaces never collide, even if one namespace is a prefix of another.
+    pub fn namespaced_topic(namespace: &str, topic: &str) -> String {
+        format!("{}:{}/{}", namespace.len(), namespace, topic)
+    }
 }
 
 impl VNodeOperation {
@@ -274,6 +363,26 @@ impl VirtualNode {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_namespaced_topic() {
+        let a = VirtualNode::namespaced_topic("app-a", "users/1");
+        let b = VirtualNode::namespaced_topic("app-b", "users/1");
+        assert_ne!(
+            VirtualNode::gen_did(&a).unwrap(),
+            VirtualNode::gen_did(&b).unwrap()
+        );
+        assert_ne!(
+            VirtualNode::gen_did(&a).unwrap(),
+            VirtualNode::gen_did("users/1").unwrap()
+        );
+
+        // A namespace being a prefix of another doesn't make topics collide.
+        assert_ne!(
+            VirtualNode::namespaced_topic("app", "/x/y"),
+            VirtualNode::namespaced_topic("app/x", "y")
+        );
+    }
+
     #[test]
     fn test_vnode_extend_over_max_len() {
         let topic = "test0".to_string();
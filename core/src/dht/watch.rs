@@ -0,0 +1,268 @@
+#![warn(missing_docs)]
+//! Watching changes of virtual nodes.
+//!
+//! A node watching a [VirtualNode](super::vnode::VirtualNode) registers itself on the node
+//! responsible for it by [WatchVNode](crate::message::WatchVNode), and the registration is
+//! leased for [VNODE_WATCH_TTL_MS]. The responsible node pushes [VNodeChanged] to watchers
+//! whenever the vnode changes. Watchers renew their leases in stabilization, so that a dead
+//! watcher is dropped when its lease expires, and a node taking charge of a migrated vnode
+//! learns its watchers by the renewals.
+//!
+//! Watchers are identified by signers of registrations. A watcher finds the node responsible
+//! for the vnode by a lookup of its successor before each registration, and only takes changes
+//! signed by that node, see [VNodeSubscriptions::holder].
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use futures::channel::mpsc;
+
+use super::vnode::VNodeChanged;
+use crate::dht::Did;
+use crate::error::Error;
+use crate::error::Result;
+use crate::utils::get_epoch_ms;
+
+/// Lease of a watcher in milliseconds, watchers renew it in half of the time.
+pub const VNODE_WATCH_TTL_MS: u64 = 60 * 1000;
+
+/// Max number of watchers of a virtual node.
+pub const MAX_VNODE_WATCHERS: usize = 64;
+
+/// Max number of virtual nodes watched by a watcher on a node.
+pub const MAX_VNODE_WATCHES_PER_PEER: usize = 256;
+
+/// Watchers of virtual nodes which current node is responsible for,
+/// with expiry time of their leases. Cloning it shares the watchers.
+#[derive(Debug, Clone, Default)]
+pub struct VNodeWatchers(Arc<DashMap<Did, HashMap<Did, u128>>>);
+
+impl VNodeWatchers {
+    /// Register or renew a watcher of vnode, fails with [Error::TooManyVNodeWatchers]
+    /// if a new watcher exceeds [MAX_VNODE_WATCHERS], or [Error::TooManyVNodeWatches]
+    /// if the watcher watches [MAX_VNODE_WATCHES_PER_PEER] vnodes already.
+    pub fn watch(&self, vid: Did, watcher: Did) -> Result<()> {
+        self.watch_at(vid, watcher, get_epoch_ms())
+    }
+
+    fn watch_at(&self, vid: Did, watcher: Did, now_ms: u128) -> Result<()> {
+        let renewing = self.0.get(&vid).is_some_and(|watchers| {
+            watchers
+                .get(&watcher)
+                .is_some_and(|expires_at| *expires_at > now_ms)
+        });
+        if !renewing && self.watches_of(watcher, now_ms) >= MAX_VNODE_WATCHES_PER_PEER {
+            return Err(Error::TooManyVNodeWatches(watcher));
+        }
+        let mut watchers = self.0.entry(vid).or_default();
+        watchers.retain(|_, expires_at| *expires_at > now_ms);
+        if !watchers.contains_key(&watcher) && watchers.len() >= MAX_VNODE_WATCHERS {
+            return Err(Error::TooManyVNodeWatchers(vid));
+        }
+        watchers.insert(watcher, now_ms + VNODE_WATCH_TTL_MS as u128);
+        Ok(())
+    }
+
+    /// Number of vnodes watched by a watcher, whose leases are not expired.
+    fn watches_of(&self, watcher: Did, now_ms: u128) -> usize {
+        self.0
+            .iter()
+            .filter(|watchers| {
+                watchers
+                    .get(&watcher)
+                    .is_some_and(|expires_at| *expires_at > now_ms)
+            })
+            .count()
+    }
+
+    /// Watchers of vnode whose leases are not expired.
+    pub fn watchers(&self, vid: Did) -> Vec<Did> {
+        self.watchers_at(vid, get_epoch_ms())
+    }
+
+    fn watchers_at(&self, vid: Did, now_ms: u128) -> Vec<Did> {
+        let Some(mut watchers) = self.0.get_mut(&vid) else {
+            return vec![];
+        };
+        watchers.retain(|_, expires_at| *expires_at > now_ms);
+        let dids = watchers.keys().copied().collect();
+        drop(watchers);
+        self.0.remove_if(&vid, |_, watchers| watchers.is_empty());
+        dids
+    }
+
+    /// Drop a watcher from all vnodes, such as when it's unreachable.
+    pub fn remove_watcher(&self, watcher: Did) {
+        self.0.retain(|_, watchers| {
+            watchers.remove(&watcher);
+            !watchers.is_empty()
+        });
+    }
+}
+
+#[derive(Debug)]
+struct Subscription {
+    senders: Vec<mpsc::UnboundedSender<VNodeChanged>>,
+    renewed_at_ms: u128,
+    holder: Option<Did>,
+}
+
+/// Subscriptions of current node to changes of watched vnodes,
+/// cloning it shares the subscriptions.
+#[derive(Debug, Clone, Default)]
+pub struct VNodeSubscriptions(Arc<DashMap<Did, Subscription>>);
+
+impl VNodeSubscriptions {
+    /// Subscribe changes of vnode, the subscription is dropped with the receiver.
+    /// Registering on the responsible node is up to caller, which is renewed later
+    /// by [VNodeSubscriptions::due_for_renewal].
+    pub fn subscribe(&self, vid: Did) -> mpsc::UnboundedReceiver<VNodeChanged> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.0
+            .entry(vid)
+            .or_insert_with(|| Subscription {
+                senders: vec![],
+                renewed_at_ms: get_epoch_ms(),
+                holder: None,
+            })
+            .senders
+            .push(sender);
+        receiver
+    }
+
+    /// Deliver a change to subscribers of vnode.
+    pub fn publish(&self, changed: &VNodeChanged) {
+        if let Some(mut sub) = self.0.get_mut(&changed.vid) {
+            sub.senders
+                .retain(|sender| sender.unbounded_send(changed.clone()).is_ok());
+        }
+        self.0
+            .remove_if(&changed.vid, |_, sub| sub.senders.is_empty());
+    }
+
+    /// Record the node found responsible for vnode by a lookup, where current node is
+    /// registered as a watcher.
+    pub fn set_holder(&self, vid: Did, holder: Did) {
+        if let Some(mut sub) = self.0.get_mut(&vid) {
+            sub.holder = Some(holder);
+        }
+    }
+
+    /// The node found responsible for vnode, the only one whose changes are taken.
+    pub fn holder(&self, vid: Did) -> Option<Did> {
+        self.0.get(&vid).and_then(|sub| sub.holder)
+    }
+
+    /// Check if any receiver of vnode is alive.
+    pub fn is_subscribed(&self, vid: Did) -> bool {
+        self.0
+            .get(&vid)
+            .map(|sub| sub.senders.iter().any(|s| !s.is_closed()))
+            .unwrap_or(false)
+    }
+
+    /// Subscribed vnodes whose registrations should be renewed now, which are then
+    /// considered renewed. Subscriptions without alive receivers are dropped.
+    pub fn due_for_renewal(&self) -> Vec<Did> {
+        self.due_for_renewal_at(get_epoch_ms())
+    }
+
+    fn due_for_renewal_at(&self, now_ms: u128) -> Vec<Did> {
+        self.0.retain(|_, sub| {
+            sub.senders.retain(|s| !s.is_closed());
+            !sub.senders.is_empty()
+        });
+        let mut due = vec![];
+        for mut sub in self.0.iter_mut() {
+            if sub.renewed_at_ms + VNODE_WATCH_TTL_MS as u128 / 2 <= now_ms {
+                sub.renewed_at_ms = now_ms;
+                due.push(*sub.key());
+            }
+        }
+        due
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::dht::vnode::VNodeChange;
+
+    #[test]
+    fn test_vnode_watchers() {
+        let vid = Did::from_str("0x11E807fcc88dD319270493fB2e822e388Fe36ab0").unwrap();
+        let watcher = Did::from_str("0x999999cf1046e68e36E1aA2E0E07105eDDD1f08E").unwrap();
+        let watchers = VNodeWatchers::default();
+        let now = 1_000_000;
+
+        watchers.watch_at(vid, watcher, now).unwrap();
+        assert_eq!(watchers.watchers_at(vid, now), vec![watcher]);
+
+        // Watchers are capped, but renewing is always allowed.
+        for i in 1..MAX_VNODE_WATCHERS {
+            watchers.watch_at(vid, Did::from(i as u32), now).unwrap();
+        }
+        assert!(matches!(
+            watchers.watch_at(vid, Did::from(MAX_VNODE_WATCHERS as u32), now),
+            Err(Error::TooManyVNodeWatchers(_))
+        ));
+        let later = now + VNODE_WATCH_TTL_MS as u128 / 2;
+        watchers.watch_at(vid, watcher, later).unwrap();
+
+        // Watchers not renewing are dropped when their leases expire.
+        let expired = now + VNODE_WATCH_TTL_MS as u128;
+        assert_eq!(watchers.watchers_at(vid, expired), vec![watcher]);
+
+        watchers.remove_watcher(watcher);
+        assert!(watchers.watchers_at(vid, expired).is_empty());
+
+        // Vnodes watched by a watcher are capped, but renewing is always allowed.
+        for i in 0..MAX_VNODE_WATCHES_PER_PEER {
+            watchers
+                .watch_at(Did::from(i as u32), watcher, now)
+                .unwrap();
+        }
+        let vid = Did::from(MAX_VNODE_WATCHES_PER_PEER as u32);
+        assert!(matches!(
+            watchers.watch_at(vid, watcher, now),
+            Err(Error::TooManyVNodeWatches(_))
+        ));
+        watchers.watch_at(Did::from(0u32), watcher, now).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_vnode_subscriptions() {
+        let vid = Did::from_str("0x11E807fcc88dD319270493fB2e822e388Fe36ab0").unwrap();
+        let subs = VNodeSubscriptions::default();
+        let mut changes = subs.subscribe(vid);
+        assert!(subs.is_subscribed(vid));
+        assert_eq!(subs.holder(vid), None);
+        let holder = Did::from_str("0x999999cf1046e68e36E1aA2E0E07105eDDD1f08E").unwrap();
+        subs.set_holder(vid, holder);
+        assert_eq!(subs.holder(vid), Some(holder));
+
+        let now = get_epoch_ms();
+        assert!(subs.due_for_renewal_at(now).is_empty());
+        let later = now + VNODE_WATCH_TTL_MS as u128 / 2;
+        assert_eq!(subs.due_for_renewal_at(later), vec![vid]);
+        assert!(subs.due_for_renewal_at(later).is_empty());
+
+        let changed = VNodeChanged {
+            vid,
+            change: VNodeChange::Stored,
+            vnode: None,
+        };
+        subs.publish(&changed);
+        assert_eq!(changes.next().await, Some(changed));
+
+        // Dropping the receiver ends the subscription.
+        drop(changes);
+        assert!(!subs.is_subscribed(vid));
+        assert!(subs.due_for_renewal_at(later * 2).is_empty());
+    }
+}
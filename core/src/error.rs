@@ -91,6 +91,24 @@ pub enum Error {
     #[error("Gzip decode error.")]
     GzipDecode,
 
+    #[error("Decompressed data exceeds the limit of {0} bytes")]
+    DecompressedTooLarge(usize),
+
+    #[error("Failed on training compression dictionary: {0}")]
+    DictionaryTrain(String),
+
+    #[error("Failed on compressing against dictionary")]
+    ZstdEncode,
+
+    #[error("Failed on decompressing against dictionary")]
+    ZstdDecode,
+
+    #[error("Payload is compressed against unknown dictionary {0:#x}")]
+    UnknownDictionary(u32),
+
+    #[error("Compression dictionary is not supported on this runtime")]
+    DictionaryUnsupported,
+
     #[error("Failed on promise, state is not succeeded")]
     PromiseStateFailed,
 
@@ -166,6 +184,9 @@ pub enum Error {
     #[error("Node behaviour bad")]
     NodeBehaviourBad(crate::dht::Did),
 
+    #[error("Connection with {0} is rejected: {1}")]
+    ConnectionRejected(crate::dht::Did, String),
+
     #[error("Cannot get transport from did: {0}")]
     SwarmMissTransport(crate::dht::Did),
 
@@ -285,6 +306,9 @@ pub enum Error {
     #[error("Suspected infinite looping in path")]
     InfiniteRelayPath,
 
+    #[error("Routing loop, {0} is already in path")]
+    RoutingLoop(crate::dht::Did),
+
     #[error("The destination of report message should always be the first element of path")]
     InvalidRelayDestination,
 
@@ -294,6 +318,45 @@ pub enum Error {
     #[error("Cannot get next hop when sending message")]
     NoNextHop,
 
+    #[error("DHT is disabled, message rejected: {0}")]
+    DhtDisabled(String),
+
+    #[error("Message of type {0} is rejected by filter")]
+    MessageRejected(String),
+
+    #[error("Message from {0} is rejected, since it's not a member of closed network")]
+    NotMember(crate::dht::Did),
+
+    #[error("NAT discovery failed: {0}")]
+    NatDiscovery(String),
+
+    #[error("Send to {0} timed out before handing off to transport")]
+    SendTimeout(crate::dht::Did),
+
+    #[error("Protocol version mismatch, expected {expected:#x}, got {got:#x}")]
+    ProtocolVersionMismatch { expected: u8, got: u8 },
+
+    #[error("Too many watchers of vnode {0}")]
+    TooManyVNodeWatchers(crate::dht::Did),
+
+    #[error("Too many vnodes watched by {0}")]
+    TooManyVNodeWatches(crate::dht::Did),
+
+    #[error("Public key of {0} is unknown, no message is received from it yet")]
+    PeerKeyUnknown(crate::dht::Did),
+
+    #[error("Invalid stream frame")]
+    InvalidStreamFrame,
+
+    #[error("Stream {1} from {0} is not found")]
+    StreamNotFound(crate::dht::Did, uuid::Uuid),
+
+    #[error("Too many streams opened by {0}")]
+    TooManyStreams(crate::dht::Did),
+
+    #[error("Too many streams waiting to be accepted")]
+    TooManyPendingStreams,
+
     #[error("To generate REPORT, you should provide SEND")]
     ReportNeedSend,
 
@@ -357,6 +420,36 @@ pub enum Error {
     #[error("Session is expired")]
     SessionExpired,
 
+    #[error("Routing query to {0} is timed out")]
+    RoutingQueryTimeout(crate::dht::Did),
+
+    #[error("Routing query to {0} is denied: {1}")]
+    RoutingQueryDenied(crate::dht::Did, String),
+
+    #[error("Lookup is cancelled")]
+    LookupCancelled,
+
+    #[error("Lookup via {0} is timed out")]
+    LookupTimeout(crate::dht::Did),
+
+    #[error("Invalid payload pipeline: {0}")]
+    InvalidPayloadPipeline(String),
+
+    #[error("Failed on encrypting identity")]
+    IdentityEncryptionFailed,
+
+    #[error("Failed on decrypting identity, the password is wrong or it's tampered with")]
+    IdentityDecryptionFailed,
+
+    #[error("Unsupported version {0} of identity format")]
+    UnsupportedIdentityVersion(u8),
+
+    #[error("Rounds {0} of identity key derivation are out of bounds")]
+    InvalidIdentityKdfRounds(u32),
+
+    #[error("Storing of {0} is rate limited")]
+    StoreRateLimited(crate::dht::Did),
+
     #[error("Transport error: {0}")]
     Transport(#[from] rings_transport::error::Error),
 }
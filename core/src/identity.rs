@@ -0,0 +1,157 @@
+#![warn(missing_docs)]
+//! Export and import of node identity, so that a replaced machine resumes the ring position of
+//! the node, and the virtual nodes it owns.
+//!
+//! The identity of a node is the [SecretKey] of its account, which decides its [Did]. An
+//! [EncryptedIdentity] carries it encrypted by a password, with AES-256-GCM keyed by
+//! PBKDF2-HMAC-SHA256 of the password. The format is versioned by [EncryptedIdentity::version],
+//! and an identity of an unknown version is rejected rather than misread.
+//!
+//! The key is long-term, unlike a [SessionSk](crate::session::SessionSk), which expires, so a
+//! backup stays valid. The importing node signs a new session by the key.
+use ecies::utils::aes_decrypt;
+use ecies::utils::aes_encrypt;
+use pbkdf2::pbkdf2_hmac;
+use rand::RngCore;
+use serde::Deserialize;
+use serde::Serialize;
+use sha2::Sha256;
+
+use crate::dht::Did;
+use crate::ecc::SecretKey;
+use crate::error::Error;
+use crate::error::Result;
+
+/// Version of the format of [EncryptedIdentity] written by this crate.
+/// Version 1 carried a session, which expires, and is no longer read.
+pub const IDENTITY_FORMAT_VERSION: u8 = 2;
+
+/// Rounds of PBKDF2 deriving the key of [EncryptedIdentity] from password.
+/// An identity of fewer rounds is rejected.
+pub const IDENTITY_KDF_ROUNDS: u32 = 100_000;
+
+/// Max rounds of PBKDF2 accepted from an [EncryptedIdentity], so that a crafted identity can't
+/// keep the importing node busy.
+pub const MAX_IDENTITY_KDF_ROUNDS: u32 = 10_000_000;
+
+const IDENTITY_SALT_SIZE: usize = 16;
+
+/// Identity of a node encrypted by a password, see the [module level documentation](self).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncryptedIdentity {
+    /// Version of format.
+    pub version: u8,
+    /// Did of the identity, in plain text, so that operators can tell backups apart.
+    pub did: Did,
+    /// Rounds of PBKDF2.
+    pub rounds: u32,
+    /// Salt of PBKDF2.
+    pub salt: Vec<u8>,
+    /// [SecretKey] encrypted by the key derived from password.
+    pub ciphertext: Vec<u8>,
+}
+
+/// PBKDF2-HMAC-SHA256 of RFC 8018.
+fn derive_key(password: &str, salt: &[u8], rounds: u32) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, rounds, &mut key);
+    key
+}
+
+impl EncryptedIdentity {
+    /// Encrypt an identity by password.
+    pub fn encrypt(key: &SecretKey, password: &str) -> Result<Self> {
+        let mut salt = vec![0u8; IDENTITY_SALT_SIZE];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let plaintext = serde_json::to_vec(key).map_err(|_| Error::SerializeError)?;
+        let ciphertext = aes_encrypt(
+            &derive_key(password, &salt, IDENTITY_KDF_ROUNDS),
+            &plaintext,
+        )
+        .ok_or(Error::IdentityEncryptionFailed)?;
+        Ok(Self {
+            version: IDENTITY_FORMAT_VERSION,
+            did: key.address().into(),
+            rounds: IDENTITY_KDF_ROUNDS,
+            salt,
+            ciphertext,
+        })
+    }
+
+    /// Decrypt the identity by password. Fails if the password is wrong, the identity is
+    /// tampered with, or its rounds of PBKDF2 are out of
+    /// `[IDENTITY_KDF_ROUNDS, MAX_IDENTITY_KDF_ROUNDS]`.
+    pub fn decrypt(&self, password: &str) -> Result<SecretKey> {
+        if self.version != IDENTITY_FORMAT_VERSION {
+            return Err(Error::UnsupportedIdentityVersion(self.version));
+        }
+        if !(IDENTITY_KDF_ROUNDS..=MAX_IDENTITY_KDF_ROUNDS).contains(&self.rounds) {
+            return Err(Error::InvalidIdentityKdfRounds(self.rounds));
+        }
+        let cipher_key = derive_key(password, &self.salt, self.rounds);
+        let plaintext =
+            aes_decrypt(&cipher_key, &self.ciphertext).ok_or(Error::IdentityDecryptionFailed)?;
+        let key: SecretKey = serde_json::from_slice(&plaintext).map_err(Error::Deserialize)?;
+        if Did::from(key.address()) != self.did {
+            return Err(Error::IdentityDecryptionFailed);
+        }
+        Ok(key)
+    }
+
+    /// Serialize the identity, such as to be written to a backup file.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(self).map_err(|_| Error::SerializeError)
+    }
+
+    /// Deserialize an identity serialized by [EncryptedIdentity::to_bytes].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        serde_json::from_slice(bytes).map_err(Error::Deserialize)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_derive_key() {
+        // Test vector of PBKDF2-HMAC-SHA256 in RFC 7914.
+        assert_eq!(
+            hex::encode(derive_key("passwd", b"salt", 1)),
+            "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc"
+        );
+    }
+
+    #[test]
+    fn test_identity_round_trip() {
+        let key = SecretKey::random();
+        let identity = EncryptedIdentity::encrypt(&key, "secret").unwrap();
+        assert_eq!(identity.did, key.address().into());
+
+        let bytes = identity.to_bytes().unwrap();
+        let restored = EncryptedIdentity::from_bytes(&bytes).unwrap();
+        assert_eq!(restored.decrypt("secret").unwrap(), key);
+
+        assert!(matches!(
+            restored.decrypt("wrong"),
+            Err(Error::IdentityDecryptionFailed)
+        ));
+        let mut tampered = restored.clone();
+        tampered.did = Did::from(1u32);
+        assert!(tampered.decrypt("secret").is_err());
+        for rounds in [1, MAX_IDENTITY_KDF_ROUNDS + 1] {
+            let mut crafted = restored.clone();
+            crafted.rounds = rounds;
+            assert!(matches!(
+                crafted.decrypt("secret"),
+                Err(Error::InvalidIdentityKdfRounds(r)) if r == rounds
+            ));
+        }
+        let mut future = restored;
+        future.version += 1;
+        assert!(matches!(
+            future.decrypt("secret"),
+            Err(Error::UnsupportedIdentityVersion(v)) if v == IDENTITY_FORMAT_VERSION + 1
+        ));
+    }
+}
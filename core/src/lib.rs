@@ -76,5 +76,8 @@ pub use async_trait::async_trait;
 pub use futures;
 pub mod chunk;
 pub mod consts;
+pub mod identity;
 pub mod inspect;
 pub mod measure;
+pub mod metrics;
+pub mod traffic;
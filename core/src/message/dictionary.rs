@@ -0,0 +1,402 @@
+#![warn(missing_docs)]
+//! Compression of payloads against a shared zstd dictionary.
+//!
+//! DHT maintenance and tunnel framing send many small payloads, which generic compression
+//! can't shrink, since each of them is too short to repeat anything. A dictionary trained on
+//! representative traffic, see [CompressionDictionary::train], carries the repeated parts
+//! instead, so that even a small payload compresses well against it.
+//!
+//! Dictionaries are distributed out of band, such as by config of nodes, and identified by
+//! hash of their content, so that a retrained dictionary is a new version. Once connected,
+//! peers offer the ids of dictionaries they know by a
+//! [DictionaryOffer](crate::message::DictionaryOffer), and each side compresses payloads sent
+//! to the other with the first dictionary offered which it knows as well. Payloads sent to a
+//! peer which offered no known dictionary, or which would not shrink, are sent as is.
+//!
+//! Compressing against a dictionary is only supported on native runtime, a browser node
+//! never offers dictionaries.
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use dashmap::DashMap;
+
+use crate::consts::MAX_DECOMPRESSED_SIZE;
+use crate::dht::Did;
+use crate::ecc::keccak256;
+use crate::error::Error;
+use crate::error::Result;
+use crate::message::types::DictionaryOffer;
+use crate::message::HandleMsg;
+use crate::message::MessageHandler;
+use crate::message::MessageHandlerEvent;
+use crate::message::MessagePayload;
+use crate::message::MessageVerificationExt;
+
+/// Leading byte of a payload compressed against a dictionary, followed by the id of
+/// dictionary in 4 bytes of little endian and a zstd frame.
+pub const DICT_COMPRESSED_PAYLOAD_TAG: u8 = 0xD2;
+
+/// Compression level of zstd.
+pub const DICT_COMPRESSION_LEVEL: i32 = 3;
+
+/// Default max size of a trained dictionary, which is the default of zstd.
+pub const DEFAULT_DICTIONARY_SIZE: usize = 112 * 1024;
+
+/// Id of a dictionary, taken from hash of its content.
+pub type DictionaryId = u32;
+
+/// A zstd dictionary shared by peers, cloning it shares the content.
+#[derive(Clone)]
+pub struct CompressionDictionary {
+    id: DictionaryId,
+    data: Arc<Vec<u8>>,
+    #[cfg(not(feature = "wasm"))]
+    encoder: Arc<zstd::dict::EncoderDictionary<'static>>,
+    #[cfg(not(feature = "wasm"))]
+    decoder: Arc<zstd::dict::DecoderDictionary<'static>>,
+}
+
+impl std::fmt::Debug for CompressionDictionary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CompressionDictionary")
+            .field("id", &self.id)
+            .field("len", &self.data.len())
+            .finish()
+    }
+}
+
+impl CompressionDictionary {
+    /// Load a dictionary from its content, such as a file written from
+    /// [CompressionDictionary::as_bytes].
+    pub fn new(data: Vec<u8>) -> Self {
+        let hash = keccak256(&data);
+        Self {
+            id: DictionaryId::from_le_bytes([hash[0], hash[1], hash[2], hash[3]]),
+            #[cfg(not(feature = "wasm"))]
+            encoder: Arc::new(zstd::dict::EncoderDictionary::copy(
+                &data,
+                DICT_COMPRESSION_LEVEL,
+            )),
+            #[cfg(not(feature = "wasm"))]
+            decoder: Arc::new(zstd::dict::DecoderDictionary::copy(&data)),
+            data: Arc::new(data),
+        }
+    }
+
+    /// Train a dictionary of at most `max_size` bytes on samples of traffic, such as
+    /// payloads encoded by [MessagePayload::to_compact](crate::message::MessagePayload).
+    /// Training needs a few hundred samples at least.
+    #[cfg(not(feature = "wasm"))]
+    pub fn train<S: AsRef<[u8]>>(samples: &[S], max_size: usize) -> Result<Self> {
+        let data = zstd::dict::from_samples(samples, max_size)
+            .map_err(|e| Error::DictionaryTrain(e.to_string()))?;
+        Ok(Self::new(data))
+    }
+
+    /// Id of the dictionary.
+    pub fn id(&self) -> DictionaryId {
+        self.id
+    }
+
+    /// Content of the dictionary.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Compress data against the dictionary, into a payload starting with
+    /// [DICT_COMPRESSED_PAYLOAD_TAG].
+    pub fn compress(&self, data: &[u8]) -> Result<Bytes> {
+        let mut compressed = vec![DICT_COMPRESSED_PAYLOAD_TAG];
+        compressed.extend_from_slice(&self.id.to_le_bytes());
+        compressed.extend(self.compress_frame(data)?);
+        Ok(compressed.into())
+    }
+
+    #[cfg(not(feature = "wasm"))]
+    fn compress_frame(&self, data: &[u8]) -> Result<Vec<u8>> {
+        zstd::bulk::Compressor::with_prepared_dictionary(&self.encoder)
+            .and_then(|mut c| c.compress(data))
+            .map_err(|_| Error::ZstdEncode)
+    }
+
+    #[cfg(feature = "wasm")]
+    fn compress_frame(&self, _data: &[u8]) -> Result<Vec<u8>> {
+        Err(Error::DictionaryUnsupported)
+    }
+
+    /// Decompress a zstd frame against the dictionary, failing with
+    /// [Error::DecompressedTooLarge] as soon as more than `limit` bytes are decompressed.
+    /// Output is streamed instead of allocated up front, like
+    /// [decode_gzip_data_with_limit](crate::message::decode_gzip_data_with_limit).
+    #[cfg(not(feature = "wasm"))]
+    pub fn decompress_frame(&self, frame: &[u8], limit: usize) -> Result<Vec<u8>> {
+        use std::io::Read;
+
+        if let Ok(Some(len)) = zstd::zstd_safe::get_frame_content_size(frame) {
+            if len > limit as u64 {
+                return Err(Error::DecompressedTooLarge(limit));
+            }
+        }
+        let decoder = zstd::stream::read::Decoder::with_prepared_dictionary(frame, &self.decoder)
+            .map_err(|_| Error::ZstdDecode)?;
+        let mut data = Vec::new();
+        decoder
+            .single_frame()
+            .take(limit as u64 + 1)
+            .read_to_end(&mut data)
+            .map_err(|_| Error::ZstdDecode)?;
+        if data.len() > limit {
+            return Err(Error::DecompressedTooLarge(limit));
+        }
+        Ok(data)
+    }
+
+    /// Decompress a zstd frame against the dictionary, which is unsupported on browser.
+    #[cfg(feature = "wasm")]
+    pub fn decompress_frame(&self, _frame: &[u8], _limit: usize) -> Result<Vec<u8>> {
+        Err(Error::DictionaryUnsupported)
+    }
+}
+
+/// Dictionaries known by a node, and the one negotiated with each peer.
+/// Cloned sets share the same dictionaries.
+#[derive(Debug, Clone, Default)]
+pub struct CompressionDictionaries {
+    known: Arc<DashMap<DictionaryId, CompressionDictionary>>,
+    peers: Arc<DashMap<Did, DictionaryId>>,
+}
+
+impl CompressionDictionaries {
+    /// Know a dictionary, which is offered to peers connected from now on.
+    pub fn insert(&self, dictionary: CompressionDictionary) {
+        self.known.insert(dictionary.id(), dictionary);
+    }
+
+    /// Get a known dictionary.
+    pub fn get(&self, id: DictionaryId) -> Option<CompressionDictionary> {
+        self.known.get(&id).map(|d| d.clone())
+    }
+
+    /// Ids of dictionaries offered to peers, which is empty on browser.
+    pub fn ids(&self) -> Vec<DictionaryId> {
+        if cfg!(feature = "wasm") {
+            return vec![];
+        }
+        let mut ids = self.known.iter().map(|d| *d.key()).collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    /// Choose the first dictionary offered by peer which is known, and compress payloads
+    /// sent to peer against it. Returns the id chosen.
+    pub fn negotiate(&self, peer: Did, offered: &[DictionaryId]) -> Option<DictionaryId> {
+        let known = self.ids();
+        match offered.iter().find(|id| known.contains(id)) {
+            Some(id) => {
+                self.peers.insert(peer, *id);
+                Some(*id)
+            }
+            None => {
+                self.peers.remove(&peer);
+                None
+            }
+        }
+    }
+
+    /// Id of the dictionary negotiated with peer.
+    pub fn peer_dictionary(&self, peer: Did) -> Option<DictionaryId> {
+        self.peers.get(&peer).map(|id| *id)
+    }
+
+    /// Forget the dictionary negotiated with peer, such as on disconnection.
+    pub fn forget_peer(&self, peer: Did) {
+        self.peers.remove(&peer);
+    }
+
+    /// Compress data sent to peer against the dictionary negotiated. Returns None if no
+    /// dictionary is negotiated, or data would not shrink.
+    pub fn compress_for(&self, peer: Did, data: &[u8]) -> Option<Bytes> {
+        let dictionary = self.get(self.peer_dictionary(peer)?)?;
+        match dictionary.compress(data) {
+            Ok(compressed) if compressed.len() < data.len() => Some(compressed),
+            Ok(_) => None,
+            Err(e) => {
+                tracing::warn!("Failed on compressing against dictionary: {e}");
+                None
+            }
+        }
+    }
+
+    /// Decompress data received if it starts with [DICT_COMPRESSED_PAYLOAD_TAG], otherwise
+    /// data is returned as is. Output is bounded by [MAX_DECOMPRESSED_SIZE].
+    pub fn decompress<'a>(&self, data: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        let Some((&DICT_COMPRESSED_PAYLOAD_TAG, rest)) = data.split_first() else {
+            return Ok(Cow::Borrowed(data));
+        };
+        if rest.len() < 4 {
+            return Err(Error::ZstdDecode);
+        }
+        let (id, frame) = rest.split_at(4);
+        let id = DictionaryId::from_le_bytes([id[0], id[1], id[2], id[3]]);
+        let dictionary = self.get(id).ok_or(Error::UnknownDictionary(id))?;
+        dictionary
+            .decompress_frame(frame, MAX_DECOMPRESSED_SIZE)
+            .map(Cow::Owned)
+    }
+}
+
+/// An offer is only taken from the peer sending it directly, a relayed one is ignored.
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<DictionaryOffer> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        msg: &DictionaryOffer,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        let peer = ctx.transaction.signer();
+        if ctx.relay.path != [peer] {
+            return Ok(vec![]);
+        }
+        match self.dictionaries().negotiate(peer, &msg.ids) {
+            Some(id) => tracing::debug!("Compress payloads to {peer} against dictionary {id:#x}"),
+            None => tracing::debug!("No common dictionary with {peer}"),
+        }
+        Ok(vec![])
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use super::*;
+    use crate::ecc::SecretKey;
+    use crate::message::FindSuccessorReportHandler;
+    use crate::message::FindSuccessorSend;
+    use crate::message::FindSuccessorThen;
+    use crate::message::Message;
+    use crate::message::MessagePayload;
+    use crate::message::QueryForTopoInfoSend;
+    use crate::session::SessionSk;
+
+    /// Encoded DHT control messages among random nodes.
+    fn control_messages(n: usize) -> Vec<Vec<u8>> {
+        (0..n)
+            .map(|i| {
+                let sk = SessionSk::new_with_seckey(&SecretKey::random()).unwrap();
+                let did: Did = SecretKey::random().address().into();
+                let msg = if i % 2 == 0 {
+                    Message::FindSuccessorSend(FindSuccessorSend {
+                        did,
+                        strict: false,
+                        then: FindSuccessorThen::Report(FindSuccessorReportHandler::FixFingerTable),
+                    })
+                } else {
+                    Message::QueryForTopoInfoSend(QueryForTopoInfoSend::new_for_stab(did))
+                };
+                MessagePayload::new_send(msg, &sk, did, did)
+                    .unwrap()
+                    .to_compact()
+                    .unwrap()
+                    .to_vec()
+            })
+            .collect()
+    }
+
+    fn zstd_len(data: &[u8]) -> usize {
+        zstd::bulk::compress(data, DICT_COMPRESSION_LEVEL)
+            .unwrap()
+            .len()
+    }
+
+    fn gzip_len(data: &[u8]) -> usize {
+        let mut ec = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        ec.write_all(data).unwrap();
+        ec.finish().unwrap().len()
+    }
+
+    #[test]
+    fn test_dictionary_compression_ratio() {
+        let dictionary = CompressionDictionary::train(&control_messages(1000), 16 * 1024).unwrap();
+        let samples = control_messages(100);
+
+        let raw: usize = samples.iter().map(|s| s.len()).sum();
+        let gzip: usize = samples.iter().map(|s| gzip_len(s)).sum();
+        let zstd: usize = samples.iter().map(|s| zstd_len(s)).sum();
+        let dict: usize = samples
+            .iter()
+            .map(|s| dictionary.compress(s).unwrap().len())
+            .sum();
+
+        // Small payloads barely shrink by themselves, while the dictionary does better.
+        assert!(dict < zstd && dict < gzip && dict < raw);
+
+        let compressed = dictionary.compress(&samples[0]).unwrap();
+        let dictionaries = CompressionDictionaries::default();
+        assert!(matches!(
+            dictionaries.decompress(&compressed),
+            Err(Error::UnknownDictionary(id)) if id == dictionary.id()
+        ));
+        dictionaries.insert(dictionary.clone());
+        assert_eq!(
+            dictionaries.decompress(&compressed).unwrap().as_ref(),
+            samples[0].as_slice()
+        );
+        // Data not compressed passes through.
+        assert_eq!(
+            dictionaries.decompress(&samples[0]).unwrap().as_ref(),
+            samples[0].as_slice()
+        );
+    }
+
+    #[test]
+    fn test_decompress_frame_limit() {
+        let dictionary = CompressionDictionary::train(&control_messages(1000), 16 * 1024).unwrap();
+        let data = vec![7u8; 4096];
+
+        // A streamed frame doesn't declare its size, so the limit applies while decoding.
+        let mut encoder =
+            zstd::stream::write::Encoder::with_prepared_dictionary(Vec::new(), &dictionary.encoder)
+                .unwrap();
+        encoder.write_all(&data).unwrap();
+        let frame = encoder.finish().unwrap();
+        assert!(matches!(
+            dictionary.decompress_frame(&frame, 1024),
+            Err(Error::DecompressedTooLarge(1024))
+        ));
+        assert_eq!(dictionary.decompress_frame(&frame, 4096).unwrap(), data);
+
+        // A frame declaring a size over limit is rejected before decoding.
+        let frame = dictionary.compress_frame(&data).unwrap();
+        assert!(matches!(
+            dictionary.decompress_frame(&frame, 1024),
+            Err(Error::DecompressedTooLarge(1024))
+        ));
+    }
+
+    #[test]
+    fn test_dictionary_negotiation() {
+        let dictionary = CompressionDictionary::new(b"rings dictionary".repeat(64));
+        let dictionaries = CompressionDictionaries::default();
+        dictionaries.insert(dictionary.clone());
+        let peer: Did = SecretKey::random().address().into();
+        let data = b"rings dictionary rings".repeat(4);
+
+        assert_eq!(dictionaries.compress_for(peer, &data), None);
+        assert_eq!(dictionaries.negotiate(peer, &[1, 2]), None);
+        assert_eq!(
+            dictionaries.negotiate(peer, &[1, dictionary.id()]),
+            Some(dictionary.id())
+        );
+        let compressed = dictionaries.compress_for(peer, &data).unwrap();
+        assert!(compressed.len() < data.len());
+        assert_eq!(dictionaries.decompress(&compressed).unwrap().as_ref(), data);
+
+        dictionaries.forget_peer(peer);
+        assert_eq!(dictionaries.compress_for(peer, &data), None);
+    }
+}
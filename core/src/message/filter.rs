@@ -0,0 +1,92 @@
+#![warn(missing_docs)]
+//! Filter of messages by type, which lets a node take a specialized role in a heterogeneous
+//! network, such as a relay rejecting operations of virtual nodes, or a storage node
+//! rejecting custom messages. Messages are named by [Message::kind].
+use std::collections::BTreeSet;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use super::Message;
+
+/// Filter of messages accepted by [MessageHandler](super::MessageHandler), checked before
+/// any processing. Local messages `JoinDHT` and `LeaveDHT` are always accepted, since they
+/// are how a node tracks its own connections.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageFilter {
+    /// Accept every type of message.
+    #[default]
+    All,
+    /// Accept only the listed types of message.
+    Allow(BTreeSet<String>),
+    /// Accept every type of message except the listed ones.
+    Deny(BTreeSet<String>),
+}
+
+impl MessageFilter {
+    /// Accept only the listed types of message, see [Message::kind].
+    pub fn allow<'a>(kinds: impl IntoIterator<Item = &'a str>) -> Self {
+        Self::Allow(kinds.into_iter().map(str::to_string).collect())
+    }
+
+    /// Reject the listed types of message, see [Message::kind].
+    pub fn deny<'a>(kinds: impl IntoIterator<Item = &'a str>) -> Self {
+        Self::Deny(kinds.into_iter().map(str::to_string).collect())
+    }
+
+    /// Check if message is accepted.
+    pub fn accepts(&self, message: &Message) -> bool {
+        if matches!(message, Message::JoinDHT(_) | Message::LeaveDHT(_)) {
+            return true;
+        }
+        match self {
+            Self::All => true,
+            Self::Allow(kinds) => kinds.contains(message.kind()),
+            Self::Deny(kinds) => !kinds.contains(message.kind()),
+        }
+    }
+
+    /// Types listed in filter which are not a type of message, likely misspelled.
+    pub fn unknown_kinds(&self) -> Vec<&str> {
+        match self {
+            Self::All => vec![],
+            Self::Allow(kinds) | Self::Deny(kinds) => kinds
+                .iter()
+                .map(String::as_str)
+                .filter(|k| !Message::KINDS.contains(k))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message::LeaveDHT;
+
+    #[test]
+    fn test_message_filter() {
+        let custom = Message::custom(b"hello").unwrap();
+        let leave = Message::LeaveDHT(LeaveDHT {
+            did: crate::dht::Did::from(1u32),
+        });
+        assert!(MessageFilter::default().accepts(&custom));
+
+        let storage_only = MessageFilter::deny(["CustomMessage"]);
+        assert!(!storage_only.accepts(&custom));
+        assert!(storage_only.accepts(&leave));
+
+        let custom_only = MessageFilter::allow(["CustomMessage"]);
+        assert!(custom_only.accepts(&custom));
+        // Local messages are always accepted.
+        assert!(custom_only.accepts(&leave));
+
+        let misspelled = MessageFilter::deny(["OperateVNode", "StoreVNode"]);
+        assert_eq!(misspelled.unknown_kinds(), vec!["StoreVNode"]);
+
+        let json = r#"{"deny":["OperateVNode"]}"#;
+        let filter: MessageFilter = serde_json::from_str(json).unwrap();
+        assert_eq!(filter, MessageFilter::deny(["OperateVNode"]));
+    }
+}
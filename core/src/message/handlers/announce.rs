@@ -0,0 +1,427 @@
+#![warn(missing_docs)]
+//! Gossip of node announcements, which lets nodes learn about peers beyond their
+//! connections without a central registry.
+//!
+//! A node announces itself by sending a [NodeAnnouncement] to its connected peers, see
+//! [Swarm::announce](crate::swarm::Swarm::announce). The [AnnouncedNode] inside is signed by
+//! the node announced, while the number of hops it traveled is signed by the neighbour
+//! sending it, which is the signer of the message. A receiver records the node in
+//! [KnownNodes], and sends the announcement to its own connected peers, with the hops
+//! counted by itself, until it has traveled [MAX_ANNOUNCE_HOPS] hops. An announcement of a
+//! node is forwarded only if it's newer than the one known, so each node forwards an
+//! announcement at most once. A neighbour could still claim fewer hops than traveled, but
+//! only for announcements it sends.
+//!
+//! A node could still sign announcements with a new timestamp as fast as it likes, each
+//! of which is newer and would flood the network. So each node announced has a token bucket
+//! of [ANNOUNCE_BURST] tokens, refilled one per [ANNOUNCE_REFILL_MS], and its announcements
+//! are neither recorded nor forwarded while the bucket is empty.
+//!
+//! Since a node can make as many dids as it likes, announcements are also admitted by a
+//! bucket of the neighbour sending them, see [NEIGHBOUR_ANNOUNCE_BURST], and by a bucket
+//! shared by all neighbours, see [GLOBAL_ANNOUNCE_BURST], before the signature of the node
+//! announced is verified.
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::consts::TS_OFFSET_TOLERANCE_MS;
+use crate::dht::Did;
+use crate::error::Error;
+use crate::error::Result;
+use crate::message::types::AnnouncedNode;
+use crate::message::types::Message;
+use crate::message::types::NodeAnnouncement;
+use crate::message::HandleMsg;
+use crate::message::MessageHandler;
+use crate::message::MessageHandlerEvent;
+use crate::message::MessagePayload;
+use crate::message::MessageVerificationExt;
+use crate::message::Transaction;
+use crate::session::SessionSk;
+use crate::utils::get_epoch_ms;
+
+/// Max number of hops an announcement travels from the node announced.
+pub const MAX_ANNOUNCE_HOPS: usize = 6;
+
+/// Interval of announcing a node periodically, in milliseconds.
+pub const ANNOUNCE_INTERVAL_MS: u128 = 5 * 60 * 1000;
+
+/// A known node is forgotten if it's not announced again in this time, in milliseconds.
+pub const KNOWN_NODE_TTL_MS: u128 = 3 * ANNOUNCE_INTERVAL_MS;
+
+/// Max number of announcements of a node accepted in a burst.
+pub const ANNOUNCE_BURST: u32 = 4;
+
+/// A token of the bucket of a node is refilled in this time, in milliseconds.
+pub const ANNOUNCE_REFILL_MS: u128 = ANNOUNCE_INTERVAL_MS / 4;
+
+/// Max number of announcements sent by a neighbour accepted in a burst.
+pub const NEIGHBOUR_ANNOUNCE_BURST: u32 = 64;
+
+/// A token of the bucket of a neighbour is refilled in this time, in milliseconds.
+pub const NEIGHBOUR_ANNOUNCE_REFILL_MS: u128 = 1000;
+
+/// Max number of announcements sent by all neighbours accepted in a burst.
+pub const GLOBAL_ANNOUNCE_BURST: u32 = 256;
+
+/// A token of the bucket shared by all neighbours is refilled in this time, in milliseconds.
+pub const GLOBAL_ANNOUNCE_REFILL_MS: u128 = 100;
+
+/// Max number of nodes known, the node announced least recently is forgotten first.
+pub const KNOWN_NODES_CAPACITY: usize = 1024;
+
+/// Max number of capabilities in an announcement.
+pub const MAX_ANNOUNCED_CAPABILITIES: usize = 16;
+
+/// Max length of a capability in an announcement.
+pub const MAX_CAPABILITY_LEN: usize = 64;
+
+/// A node learned from announcements.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KnownNode {
+    /// Did of the node.
+    pub did: Did,
+    /// Capabilities announced by the node.
+    pub capabilities: Vec<String>,
+    /// Time the node announced itself, by the clock of the node.
+    pub announced_at_ms: u128,
+    /// Local time the announcement is received.
+    pub received_at_ms: u128,
+    /// Number of hops the announcement traveled.
+    pub hops: usize,
+}
+
+/// Token bucket of announcements, holding at most `burst` tokens, refilled one per
+/// `refill_ms`.
+#[derive(Debug, Clone, Copy)]
+struct AnnounceBucket {
+    tokens: u32,
+    refilled_at_ms: u128,
+    burst: u32,
+    refill_ms: u128,
+}
+
+impl AnnounceBucket {
+    fn full(now: u128, burst: u32, refill_ms: u128) -> Self {
+        Self {
+            tokens: burst,
+            refilled_at_ms: now,
+            burst,
+            refill_ms,
+        }
+    }
+
+    /// Refill tokens by `now`.
+    fn refill(&mut self, now: u128) {
+        let refills = now.saturating_sub(self.refilled_at_ms) / self.refill_ms;
+        if self.tokens as u128 + refills >= self.burst as u128 {
+            *self = Self::full(now, self.burst, self.refill_ms);
+        } else {
+            self.tokens += refills as u32;
+            self.refilled_at_ms += refills * self.refill_ms;
+        }
+    }
+
+    /// Check if the bucket is full at `now`, and then it has no state worth keeping.
+    fn is_full(&mut self, now: u128) -> bool {
+        self.refill(now);
+        self.tokens == self.burst
+    }
+
+    /// Take a token at `now`, returns false if the bucket is empty.
+    fn take(&mut self, now: u128) -> bool {
+        self.refill(now);
+        if self.tokens == 0 {
+            return false;
+        }
+        self.tokens -= 1;
+        true
+    }
+}
+
+/// Buckets admitting announcements by the neighbours sending them, see
+/// [NEIGHBOUR_ANNOUNCE_BURST] and [GLOBAL_ANNOUNCE_BURST].
+#[derive(Debug)]
+struct Admission {
+    global: AnnounceBucket,
+    neighbours: HashMap<Did, AnnounceBucket>,
+}
+
+impl Admission {
+    fn new(now: u128) -> Self {
+        Self {
+            global: AnnounceBucket::full(now, GLOBAL_ANNOUNCE_BURST, GLOBAL_ANNOUNCE_REFILL_MS),
+            neighbours: HashMap::new(),
+        }
+    }
+
+    /// Admit an announcement sent by `neighbour` at `now`. Buckets of neighbours are only
+    /// kept until full again, so they are bounded by the admission rate of the global one.
+    fn admit(&mut self, neighbour: Did, now: u128) -> bool {
+        self.neighbours.retain(|_, b| !b.is_full(now));
+        let bucket = self.neighbours.entry(neighbour).or_insert_with(|| {
+            AnnounceBucket::full(now, NEIGHBOUR_ANNOUNCE_BURST, NEIGHBOUR_ANNOUNCE_REFILL_MS)
+        });
+        bucket.take(now) && self.global.take(now)
+    }
+}
+
+#[derive(Debug)]
+struct KnownNodesInner {
+    nodes: HashMap<Did, (KnownNode, AnnounceBucket)>,
+    admission: Admission,
+}
+
+impl Default for KnownNodesInner {
+    fn default() -> Self {
+        Self {
+            nodes: HashMap::new(),
+            admission: Admission::new(get_epoch_ms()),
+        }
+    }
+}
+
+/// Nodes learned from announcements, bounded by [KNOWN_NODES_CAPACITY] and expired after
+/// [KNOWN_NODE_TTL_MS]. Cloned sets share the same nodes.
+#[derive(Debug, Clone, Default)]
+pub struct KnownNodes(Arc<Mutex<KnownNodesInner>>);
+
+impl KnownNodes {
+    fn lock(&self) -> MutexGuard<'_, KnownNodesInner> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Admit an announcement sent by `neighbour` at `now`. Returns false if the bucket of
+    /// the neighbour, or the one shared by all neighbours, is empty, and then it should be
+    /// dropped without verifying.
+    pub fn admit(&self, neighbour: Did, now: u128) -> bool {
+        self.lock().admission.admit(neighbour, now)
+    }
+
+    /// Record an announcement received at `now`. Returns false if it's not newer than the
+    /// announcement known of the node, or the bucket of the node is empty, and then it
+    /// should not be forwarded.
+    pub fn observe(&self, announced: &AnnouncedNode, hops: usize, now: u128) -> bool {
+        let mut inner = self.lock();
+        let nodes = &mut inner.nodes;
+        nodes.retain(|_, (n, _)| now.saturating_sub(n.received_at_ms) < KNOWN_NODE_TTL_MS);
+
+        let mut bucket = AnnounceBucket::full(now, ANNOUNCE_BURST, ANNOUNCE_REFILL_MS);
+        if let Some((known, known_bucket)) = nodes.get_mut(&announced.did) {
+            if known.announced_at_ms >= announced.ts_ms || !known_bucket.take(now) {
+                return false;
+            }
+            bucket = *known_bucket;
+        } else {
+            if nodes.len() >= KNOWN_NODES_CAPACITY {
+                let stalest = nodes
+                    .values()
+                    .min_by_key(|(n, _)| n.received_at_ms)
+                    .map(|(n, _)| n.did);
+                if let Some(did) = stalest {
+                    nodes.remove(&did);
+                }
+            }
+            bucket.take(now);
+        }
+
+        let node = KnownNode {
+            did: announced.did,
+            capabilities: announced.capabilities.clone(),
+            announced_at_ms: announced.ts_ms,
+            received_at_ms: now,
+            hops,
+        };
+        nodes.insert(announced.did, (node, bucket));
+        true
+    }
+
+    /// Nodes known and not expired at `now`, the freshest first.
+    pub fn list(&self, now: u128) -> Vec<KnownNode> {
+        let inner = self.lock();
+        let mut list: Vec<KnownNode> = inner
+            .nodes
+            .values()
+            .map(|(n, _)| n)
+            .filter(|n| now.saturating_sub(n.received_at_ms) < KNOWN_NODE_TTL_MS)
+            .cloned()
+            .collect();
+        list.sort_by(|a, b| b.received_at_ms.cmp(&a.received_at_ms));
+        list
+    }
+}
+
+impl NodeAnnouncement {
+    /// Announce a node signed by itself, which is sent by the node itself.
+    pub fn new(announced: AnnouncedNode, session_sk: &SessionSk) -> Result<Self> {
+        let did = announced.did;
+        let announced = Transaction::new(did, uuid::Uuid::new_v4(), announced, session_sk)?;
+        Ok(Self { announced, hops: 0 })
+    }
+}
+
+/// Check if an announcement is sent directly by its signer, and the node inside is
+/// well-formed and signed by itself.
+fn validate(ctx: &MessagePayload, msg: &NodeAnnouncement, now: u128) -> Result<AnnouncedNode> {
+    if ctx.relay.path.as_slice() != [ctx.transaction.signer()] {
+        return Err(Error::InvalidMessage(
+            "Announcement is not sent by a neighbour".to_string(),
+        ));
+    }
+    let announced: AnnouncedNode = msg.announced.data()?;
+    if !msg.announced.verify_signature() || msg.announced.signer() != announced.did {
+        return Err(Error::InvalidMessage(
+            "Announcement is not signed by the node announced".to_string(),
+        ));
+    }
+    if announced.ts_ms > now + TS_OFFSET_TOLERANCE_MS {
+        return Err(Error::InvalidMessage(
+            "Announcement is from the future".to_string(),
+        ));
+    }
+    if announced.capabilities.len() > MAX_ANNOUNCED_CAPABILITIES
+        || announced
+            .capabilities
+            .iter()
+            .any(|c| c.len() > MAX_CAPABILITY_LEN)
+    {
+        return Err(Error::InvalidMessage(
+            "Too many or too long capabilities".to_string(),
+        ));
+    }
+    Ok(announced)
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<NodeAnnouncement> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        msg: &NodeAnnouncement,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        let now = get_epoch_ms();
+        let neighbour = ctx.transaction.signer();
+        if !self.known_nodes.admit(neighbour, now) {
+            tracing::debug!("Drop announcement sent by {neighbour} over the admission rate");
+            return Ok(vec![]);
+        }
+        let announced = validate(ctx, msg, now)?;
+        if announced.did == self.dht.did {
+            return Ok(vec![]);
+        }
+
+        let hops = msg.hops as usize + 1;
+        if !self.known_nodes.observe(&announced, hops, now) || hops >= MAX_ANNOUNCE_HOPS {
+            return Ok(vec![]);
+        }
+        let forwarded = NodeAnnouncement {
+            announced: msg.announced.clone(),
+            hops: hops as u8,
+        };
+        Ok(vec![MessageHandlerEvent::Gossip(
+            Message::NodeAnnouncement(forwarded),
+            vec![neighbour, announced.did],
+        )])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ecc::SecretKey;
+
+    fn announcement(did: Did, ts_ms: u128) -> AnnouncedNode {
+        AnnouncedNode {
+            did,
+            capabilities: vec!["relay".to_string()],
+            ts_ms,
+        }
+    }
+
+    #[test]
+    fn test_known_nodes() {
+        let known = KnownNodes::default();
+        let a: Did = SecretKey::random().address().into();
+        let b: Did = SecretKey::random().address().into();
+
+        assert!(known.observe(&announcement(a, 10), 1, 100));
+        // Duplicated or older announcements are not forwarded again.
+        assert!(!known.observe(&announcement(a, 10), 2, 101));
+        assert!(!known.observe(&announcement(a, 9), 1, 101));
+        assert!(known.observe(&announcement(b, 10), 3, 102));
+        assert!(known.observe(&announcement(a, 11), 1, 103));
+
+        let list = known.list(103);
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].did, a);
+        assert_eq!(list[0].announced_at_ms, 11);
+        assert_eq!(list[1].hops, 3);
+
+        // Stale nodes expire.
+        assert_eq!(known.list(102 + KNOWN_NODE_TTL_MS).len(), 1);
+        assert!(known.list(103 + KNOWN_NODE_TTL_MS).is_empty());
+    }
+
+    #[test]
+    fn test_known_nodes_rate_limited() {
+        let known = KnownNodes::default();
+        let a: Did = SecretKey::random().address().into();
+        let b: Did = SecretKey::random().address().into();
+
+        // A node announcing itself faster than its bucket refills is dropped.
+        for ts in 0..ANNOUNCE_BURST as u128 {
+            assert!(known.observe(&announcement(a, ts + 1), 1, 100));
+        }
+        assert!(!known.observe(&announcement(a, 100), 1, 100));
+        assert_eq!(known.list(100)[0].announced_at_ms, ANNOUNCE_BURST as u128);
+        // Nodes are limited by their own buckets.
+        assert!(known.observe(&announcement(b, 1), 1, 100));
+
+        // A token is refilled in time.
+        let later = 100 + ANNOUNCE_REFILL_MS;
+        assert!(known.observe(&announcement(a, 101), 1, later));
+        assert!(!known.observe(&announcement(a, 102), 1, later));
+    }
+
+    #[test]
+    fn test_announcements_admitted_by_neighbours() {
+        let known = KnownNodes::default();
+        let now = get_epoch_ms();
+        let a: Did = SecretKey::random().address().into();
+        let b: Did = SecretKey::random().address().into();
+
+        // A neighbour sending faster than its bucket refills is dropped, whatever it announces.
+        for _ in 0..NEIGHBOUR_ANNOUNCE_BURST {
+            assert!(known.admit(a, now));
+        }
+        assert!(!known.admit(a, now));
+        assert!(known.admit(b, now));
+        assert!(known.admit(a, now + NEIGHBOUR_ANNOUNCE_REFILL_MS));
+
+        // All neighbours share a bucket as well.
+        let neighbours: Vec<Did> = (0..GLOBAL_ANNOUNCE_BURST).map(Did::from).collect();
+        let admitted = neighbours.iter().filter(|n| known.admit(**n, now)).count();
+        assert!(admitted < neighbours.len());
+        assert!(!known.admit(SecretKey::random().address().into(), now));
+    }
+
+    #[test]
+    fn test_known_nodes_bounded() {
+        let known = KnownNodes::default();
+        let dids: Vec<Did> = (0..=KNOWN_NODES_CAPACITY as u32).map(Did::from).collect();
+        for (i, did) in dids.iter().enumerate() {
+            assert!(known.observe(&announcement(*did, 1), 1, i as u128));
+        }
+        let list = known.list(KNOWN_NODES_CAPACITY as u128);
+        assert_eq!(list.len(), KNOWN_NODES_CAPACITY);
+        // The node announced least recently is forgotten.
+        assert!(list.iter().all(|n| n.did != dids[0]));
+    }
+}
@@ -13,6 +13,7 @@ use crate::message::types::ConnectNodeReport;
 use crate::message::types::ConnectNodeSend;
 use crate::message::types::FindSuccessorReport;
 use crate::message::types::FindSuccessorSend;
+use crate::message::types::IceCandidate;
 use crate::message::types::JoinDHT;
 use crate::message::types::Message;
 use crate::message::types::QueryForTopoInfoReport;
@@ -25,6 +26,8 @@ use crate::message::LeaveDHT;
 use crate::message::MessageHandler;
 use crate::message::MessageHandlerEvent;
 use crate::message::MessagePayload;
+use crate::message::MessageVerificationExt;
+use crate::swarm::lookup::LookupReport;
 
 /// QueryForTopoInfoSend is direct message
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
@@ -96,6 +99,11 @@ impl HandleMsg<JoinDHT> for MessageHandler {
         // otherwise, it will be a `send` op
         // let act = self.dht.join(msg.did)?;
         // handle_join_dht(&self, act, ctx).await
+        // A node connected directly only joins a closed network if it's a member already.
+        if !self.is_member(msg.did) {
+            tracing::warn!("Refuse non-member {} connected directly", msg.did);
+            return Ok(vec![MessageHandlerEvent::Disconnect(msg.did)]);
+        }
         Ok(vec![MessageHandlerEvent::JoinDHT(ctx.clone(), msg.did)])
     }
 }
@@ -138,6 +146,27 @@ impl HandleMsg<ConnectNodeReport> for MessageHandler {
     }
 }
 
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<IceCandidate> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        msg: &IceCandidate,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        if self.dht.did != ctx.relay.destination {
+            Ok(vec![MessageHandlerEvent::ForwardPayload(ctx.clone(), None)])
+        } else {
+            // The relay path is not signed, so a candidate is only taken for the connection
+            // with its signer.
+            Ok(vec![MessageHandlerEvent::AddIceCandidate(
+                ctx.transaction.signer(),
+                msg.clone(),
+            )])
+        }
+    }
+}
+
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
 #[cfg_attr(not(feature = "wasm"), async_trait)]
 impl HandleMsg<FindSuccessorSend> for MessageHandler {
@@ -146,6 +175,10 @@ impl HandleMsg<FindSuccessorSend> for MessageHandler {
         ctx: &MessagePayload,
         msg: &FindSuccessorSend,
     ) -> Result<Vec<MessageHandlerEvent>> {
+        if self.pending_lookups.is_abandoned(ctx.transaction.tx_id) {
+            tracing::debug!("Stop forwarding abandoned lookup of {}", msg.did);
+            return Ok(vec![]);
+        }
         match self.dht.find_successor(msg.did)? {
             PeerRingAction::Some(did) => {
                 if !msg.strict || self.dht.did == msg.did {
@@ -189,6 +222,12 @@ impl HandleMsg<FindSuccessorReport> for MessageHandler {
         if self.dht.did != ctx.relay.destination {
             return Ok(vec![MessageHandlerEvent::ForwardPayload(ctx.clone(), None)]);
         }
+        let tx_id = ctx.transaction.tx_id;
+        if self.pending_lookups.is_abandoned(tx_id) {
+            return Ok(vec![]);
+        }
+        self.pending_lookups
+            .resolve(tx_id, LookupReport::Successor(msg.did));
 
         match &msg.handler {
             FindSuccessorReportHandler::FixFingerTable => {
@@ -216,7 +255,6 @@ pub mod tests {
     use crate::ecc::tests::gen_ordered_keys;
     use crate::ecc::SecretKey;
     use crate::message::handlers::tests::assert_no_more_msg;
-    use crate::message::MessageVerificationExt;
     use crate::swarm::Swarm;
     use crate::tests::default::prepare_node;
     use crate::tests::manually_establish_connection;
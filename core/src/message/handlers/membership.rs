@@ -0,0 +1,96 @@
+#![warn(missing_docs)]
+//! Membership boundary of a closed network, see [MessageHandler::with_closed_network].
+//!
+//! In a closed network, a message is only handled if its signer is a member, which is either
+//! a seed authorized in advance, or a node in the view of DHT, such as a successor, the
+//! predecessor or a finger. Nodes enter the view by being reported by members during
+//! stabilization, so membership spreads from the seeds. A node connected directly only joins
+//! DHT locally if it's a member already, otherwise it's disconnected. Direct connections are
+//! also guarded by [ConnectionGate](crate::swarm::ConnectionGate), while connecting through
+//! the network is refused for a node outside the view, since its `ConnectNodeSend` is rejected.
+//!
+//! The very first join of a node comes from outside the view, so the nodes bootstrapping the
+//! network, and the ones expected to join through the network, should be seeds.
+//!
+//! The signer of transaction is checked rather than the origin of relay path, which isn't
+//! covered by the signature of origin. Even `LeaveDHT`, which cleans up a closed connection,
+//! is only handled from a member, since it's signed by the node itself.
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::dht::Did;
+use crate::dht::PeerRing;
+use crate::dht::SuccessorReader;
+use crate::message::MessageHandler;
+
+/// Seeds of a closed network. Cloning it shares the seeds.
+#[derive(Debug, Clone, Default)]
+pub struct ClosedNetwork {
+    seeds: Arc<HashSet<Did>>,
+}
+
+impl ClosedNetwork {
+    /// Create a closed network with seeds authorized in advance.
+    pub fn new(seeds: impl IntoIterator<Item = Did>) -> Self {
+        Self {
+            seeds: Arc::new(seeds.into_iter().collect()),
+        }
+    }
+
+    /// Check if a node is a member, by seeds and the view of DHT.
+    pub fn is_member(&self, dht: &PeerRing, did: Did) -> bool {
+        did == dht.did
+            || self.seeds.contains(&did)
+            || dht.successors().contains(&did).unwrap_or(false)
+            || dht
+                .lock_predecessor()
+                .map(|p| *p == Some(did))
+                .unwrap_or(false)
+            || dht
+                .lock_finger()
+                .map(|ft| ft.contains(Some(did)))
+                .unwrap_or(false)
+    }
+}
+
+impl MessageHandler {
+    /// Only handle messages originated by members, see [membership](self).
+    /// Messages of any node are handled by default.
+    pub fn with_closed_network(mut self, network: ClosedNetwork) -> Self {
+        self.closed_network = Some(network);
+        self
+    }
+
+    /// Check if the signer of a message is a member. Always true in an open network.
+    pub fn is_member(&self, did: Did) -> bool {
+        match self.closed_network {
+            Some(ref network) => network.is_member(&self.dht, did),
+            None => true,
+        }
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dht::Chord;
+    use crate::ecc::SecretKey;
+    use crate::tests::default::gen_pure_dht;
+
+    #[tokio::test]
+    async fn test_closed_network_membership() {
+        let did: Did = SecretKey::random().address().into();
+        let dht = gen_pure_dht(did).await.unwrap();
+        let (seed, joined, stranger): (Did, Did, Did) = (1u32.into(), 2u32.into(), 3u32.into());
+        let network = ClosedNetwork::new([seed]);
+
+        assert!(network.is_member(&dht, did));
+        assert!(network.is_member(&dht, seed));
+        assert!(!network.is_member(&dht, joined));
+
+        dht.join(joined).unwrap();
+        assert!(network.is_member(&dht, joined));
+        assert!(!network.is_member(&dht, stranger));
+    }
+}
@@ -15,25 +15,47 @@ use async_trait::async_trait;
 
 use super::CustomMessage;
 use super::Message;
+use super::MessageFilter;
 use super::MessagePayload;
 use crate::dht::vnode::VirtualNode;
 use crate::dht::Did;
 use crate::dht::PeerRing;
 use crate::error::Error;
 use crate::error::Result;
+use crate::message::handlers::announce::KnownNodes;
+use crate::message::handlers::membership::ClosedNetwork;
+use crate::message::handlers::pause::ListenGate;
+use crate::message::handlers::routing::RoutingQueries;
+use crate::message::CompressionDictionaries;
 use crate::message::ConnectNodeReport;
 use crate::message::ConnectNodeSend;
+use crate::message::IceCandidate;
+use crate::message::MessageVerificationExt;
+use crate::swarm::lookup::PendingLookups;
+use crate::swarm::Deadletter;
+use crate::swarm::DeadletterFn;
+use crate::swarm::SessionKeys;
 
+/// Gossip of node announcements
+pub mod announce;
 /// Operator and Handler for Connection
 pub mod connection;
 /// Operator and Handler for CustomMessage
 pub mod custom;
 /// For handle dht related actions
 pub mod dht;
+/// Membership boundary of a closed network
+pub mod membership;
+/// Pause and resume of the message listener
+pub mod pause;
+/// Query of the DHT view of a remote node
+pub mod routing;
 /// Operator and handler for DHT stablization
 pub mod stabilization;
 /// Operator and Handler for Storage
 pub mod storage;
+/// Rate limit of storing virtual nodes
+pub mod store_limit;
 /// Operator and Handler for Subring
 pub mod subring;
 
@@ -95,6 +117,10 @@ pub enum MessageHandlerEvent {
     /// sender's Did and Message.
     AcceptAnswer(Did, ConnectNodeReport),
 
+    /// Instructs the swarm to add an ICE candidate trickled by the remote peer of a
+    /// connection, by given signer's Did and Message.
+    AddIceCandidate(Did, IceCandidate),
+
     /// Tell swarm to forward the payload to destination by given
     /// Payload and optional next hop.
     ForwardPayload(MessagePayload, Option<Did>),
@@ -118,6 +144,14 @@ pub enum MessageHandlerEvent {
     StorageStore(VirtualNode),
     /// Notify a node
     Notify(Did),
+
+    /// Instructs the swarm to send the message directly to every connected peer, except
+    /// the ones given.
+    Gossip(Message, Vec<Did>),
+
+    /// Instructs the swarm to report its view of DHT to the origin of a routing query,
+    /// if the origin is authorized.
+    ReportRouting(MessagePayload),
 }
 
 /// MessageHandler will manage resources.
@@ -128,6 +162,28 @@ pub struct MessageHandler {
     callback: Arc<Option<CallbackFn>>,
     /// A specific validator implement ValidatorFn.
     validator: Arc<Option<ValidatorFn>>,
+    /// Reject DHT messages, see [MessageHandler::no_dht].
+    no_dht: bool,
+    /// Types of message accepted, see [MessageHandler::with_filter].
+    filter: MessageFilter,
+    /// Receiver of undeliverable messages, see [MessageHandler::set_deadletter_handler].
+    deadletter: Deadletter,
+    /// Nodes learned from announcements, see [announce].
+    known_nodes: KnownNodes,
+    /// Routing queries waiting for reports, see [routing].
+    routing_queries: RoutingQueries,
+    /// Lookups waiting for reports, see [lookup](crate::swarm::lookup).
+    pending_lookups: PendingLookups,
+    /// Compression dictionaries, see [dictionary](crate::message::dictionary).
+    dictionaries: CompressionDictionaries,
+    /// Gate of the message listener, see [pause].
+    listen_gate: ListenGate,
+    /// Seeds of a closed network, open if None, see [membership].
+    closed_network: Option<ClosedNetwork>,
+    /// Limiter of stores by origin, unlimited if None, see [store_limit].
+    store_limiter: Option<store_limit::StoreLimiter>,
+    /// Encryption keys, which record ephemeral keys offered by peers, see [SessionKeys].
+    session_keys: SessionKeys,
 }
 
 /// Generic trait for handle message ,inspired by Actor-Model.
@@ -149,7 +205,101 @@ impl MessageHandler {
             dht,
             callback: Arc::new(callback),
             validator: Arc::new(validator),
+            no_dht: false,
+            filter: MessageFilter::default(),
+            deadletter: Deadletter::default(),
+            known_nodes: KnownNodes::default(),
+            routing_queries: RoutingQueries::default(),
+            pending_lookups: PendingLookups::default(),
+            dictionaries: CompressionDictionaries::default(),
+            listen_gate: ListenGate::default(),
+            closed_network: None,
+            store_limiter: None,
+            session_keys: SessionKeys::default(),
+        }
+    }
+
+    /// Get nodes learned from announcements, see [announce].
+    pub fn known_nodes(&self) -> &KnownNodes {
+        &self.known_nodes
+    }
+
+    /// Get routing queries waiting for reports, see [routing].
+    pub fn routing_queries(&self) -> &RoutingQueries {
+        &self.routing_queries
+    }
+
+    /// Get lookups waiting for reports, see [lookup](crate::swarm::lookup).
+    pub fn pending_lookups(&self) -> &PendingLookups {
+        &self.pending_lookups
+    }
+
+    /// Get compression dictionaries, see [dictionary](crate::message::dictionary).
+    pub fn dictionaries(&self) -> &CompressionDictionaries {
+        &self.dictionaries
+    }
+
+    /// Share compression dictionaries, which record the ones offered by peers.
+    pub fn with_dictionaries(mut self, dictionaries: CompressionDictionaries) -> Self {
+        self.dictionaries = dictionaries;
+        self
+    }
+
+    /// Get encryption keys, see [SessionKeys].
+    pub fn session_keys(&self) -> &SessionKeys {
+        &self.session_keys
+    }
+
+    /// Share encryption keys, which record ephemeral keys offered by peers.
+    pub fn with_session_keys(mut self, session_keys: SessionKeys) -> Self {
+        self.session_keys = session_keys;
+        self
+    }
+
+    /// Set the handler of undeliverable messages, such as expired ones or ones failed
+    /// on relaying, which are otherwise only counted and logged, see [DropReason].
+    ///
+    /// [DropReason]: crate::swarm::DropReason
+    pub fn set_deadletter_handler(&self, handler: DeadletterFn) {
+        self.deadletter.set_handler(handler)
+    }
+
+    /// Get the deadletter shared by drop paths of swarm.
+    pub(crate) fn deadletter(&self) -> Deadletter {
+        self.deadletter.clone()
+    }
+
+    /// Run without DHT participation, only messages of connecting and custom messages
+    /// are handled, while DHT control messages are rejected.
+    pub fn no_dht(mut self) -> Self {
+        self.no_dht = true;
+        self
+    }
+
+    /// Accept only the types of message passing filter, others are rejected with
+    /// [Error::MessageRejected] before any processing. Every type is accepted by default.
+    pub fn with_filter(mut self, filter: MessageFilter) -> Self {
+        for kind in filter.unknown_kinds() {
+            tracing::warn!("Message filter lists unknown type {}", kind);
         }
+        self.filter = filter;
+        self
+    }
+
+    /// Check if message is allowed when DHT is disabled.
+    /// `LeaveDHT` is kept, since it's the local message cleaning up a closed connection.
+    fn is_p2p_message(message: &Message) -> bool {
+        matches!(
+            message,
+            Message::LeaveDHT(_)
+                | Message::ConnectNodeSend(_)
+                | Message::ConnectNodeReport(_)
+                | Message::CustomMessage(_)
+                | Message::NodeAnnouncement(_)
+                | Message::DictionaryOffer(_)
+                | Message::IceCandidate(_)
+                | Message::EphemeralKeyOffer(_)
+        )
     }
 
     /// Invoke callback, which will be call after builtin handler.
@@ -210,6 +360,30 @@ impl MessageHandler {
             &message
         );
 
+        if self.no_dht && !Self::is_p2p_message(&message) {
+            return Err(Error::DhtDisabled(message.to_string()));
+        }
+
+        if !self.filter.accepts(&message) {
+            tracing::warn!(
+                "Reject {} from {} by message filter",
+                message.kind(),
+                payload.transaction.signer()
+            );
+            return Err(Error::MessageRejected(message.kind().to_string()));
+        }
+
+        // The origin of relay path is not signed by origin, so membership is of the signer.
+        let signer = payload.transaction.signer();
+        if !self.is_member(signer) {
+            tracing::warn!(
+                "Reject {} from {} not in membership",
+                message.kind(),
+                signer
+            );
+            return Err(Error::NotMember(signer));
+        }
+
         let mut events = match &message {
             Message::JoinDHT(ref msg) => self.handle(payload, msg).await,
             Message::LeaveDHT(ref msg) => self.handle(payload, msg).await,
@@ -226,6 +400,21 @@ impl MessageHandler {
             Message::CustomMessage(ref msg) => self.handle(payload, msg).await,
             Message::QueryForTopoInfoSend(ref msg) => self.handle(payload, msg).await,
             Message::QueryForTopoInfoReport(ref msg) => self.handle(payload, msg).await,
+            Message::SearchVNodeReplicas(ref msg) => self.handle(payload, msg).await,
+            Message::FetchVNodeReplica(ref msg) => self.handle(payload, msg).await,
+            Message::FoundVNodeReplicas(ref msg) => self.handle(payload, msg).await,
+            Message::WatchVNode(ref msg) => self.handle(payload, msg).await,
+            Message::VNodeChanged(ref msg) => self.handle(payload, msg).await,
+            // Streams are bound to the transport of peer, so they are opened by
+            // the transport callback, and a relayed one is ignored.
+            Message::StreamOpen(_) => Ok(vec![]),
+            Message::NodeAnnouncement(ref msg) => self.handle(payload, msg).await,
+            Message::RoutingQuery(ref msg) => self.handle(payload, msg).await,
+            Message::RoutingReport(ref msg) => self.handle(payload, msg).await,
+            Message::DictionaryOffer(ref msg) => self.handle(payload, msg).await,
+            Message::IceCandidate(ref msg) => self.handle(payload, msg).await,
+            Message::EphemeralKeyOffer(ref msg) => self.handle(payload, msg).await,
+            Message::OperateVNodeRejected(ref msg) => self.handle(payload, msg).await,
         }?;
 
         tracing::debug!("INVOKE CALLBACK {}", &payload.transaction.tx_id);
@@ -246,10 +435,19 @@ pub mod tests {
 
     use super::*;
     use crate::dht::Did;
+    use crate::dht::SuccessorReader;
+    use crate::ecc::tests::gen_ordered_keys;
     use crate::ecc::SecretKey;
-    use crate::message::MessageVerificationExt;
+    use crate::message::handlers::routing::RoutingSnapshot;
+    use crate::message::MessageRelay;
     use crate::message::PayloadSender;
+    use crate::message::Transaction;
+    use crate::session::SessionSk;
+    use crate::storage::PersistenceStorage;
+    use crate::swarm::DidListGate;
     use crate::swarm::Swarm;
+    use crate::swarm::SwarmBuilder;
+    use crate::tests::default::prepare_node;
     use crate::tests::default::prepare_node_with_callback;
     use crate::tests::manually_establish_connection;
 
@@ -360,6 +558,455 @@ pub mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_send_message_without_direct_link() -> Result<()> {
+        let keys = gen_ordered_keys(3);
+        let (key1, key2, key3) = (keys[0], keys[1], keys[2]);
+        let (did1, did3): (Did, Did) = (key1.address().into(), key3.address().into());
+
+        let msg_callback3 = MessageCallbackInstance {
+            handler_messages: Arc::new(Mutex::new(vec![])),
+        };
+        let cb3: CallbackFn = Box::new(msg_callback3.clone());
+
+        let build = |key: SecretKey, callback: Option<CallbackFn>, deny: Did| async move {
+            let path = PersistenceStorage::random_path("./tmp");
+            let storage = PersistenceStorage::new_with_path(path.as_str())
+                .await
+                .unwrap();
+            let session_sk = SessionSk::new_with_seckey(&key).unwrap();
+            let mut builder =
+                SwarmBuilder::new("stun://stun.l.google.com:19302", storage, session_sk)
+                    .connection_gate(Box::new(DidListGate::default().deny([deny])));
+            if let Some(callback) = callback {
+                builder = builder.message_callback(callback);
+            }
+            Arc::new(builder.build())
+        };
+
+        // node1 and node3 refuse to connect each other, so messages must go through node2.
+        let node1 = build(key1, None, did3).await;
+        let (node2, _path2) = prepare_node(key2).await;
+        let node3 = build(key3, Some(cb3), did1).await;
+
+        manually_establish_connection(&node1, &node2).await;
+        manually_establish_connection(&node2, &node3).await;
+
+        for node in [node1.clone(), node2.clone(), node3.clone()] {
+            tokio::spawn(async move { node.listen().await });
+        }
+        sleep(Duration::from_secs(5)).await;
+        assert!(node1.get_connection(did3).is_none());
+        assert_eq!(node1.closest_connected_peer(did3), Some(node2.did()));
+
+        node1
+            .send_message(Message::custom("Hello world 1 to 3".as_bytes())?, did3)
+            .await?;
+        sleep(Duration::from_secs(5)).await;
+
+        // The payload is resigned by node2 when forwarding, so only the content is checked.
+        let received: Vec<Vec<u8>> = msg_callback3
+            .handler_messages
+            .lock()
+            .await
+            .iter()
+            .map(|(_, msg)| msg.clone())
+            .collect();
+        assert_eq!(received, vec!["Hello world 1 to 3".as_bytes().to_vec()]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_no_dht_rejects_dht_messages() -> Result<()> {
+        let (node, _path) = prepare_node(SecretKey::random()).await;
+        let handler = MessageHandler::new(node.dht(), None, None).no_dht();
+
+        let find = MessagePayload::new_send(
+            Message::FindSuccessorSend(crate::message::FindSuccessorSend {
+                did: node.did(),
+                strict: false,
+                then: crate::message::FindSuccessorThen::Report(
+                    crate::message::FindSuccessorReportHandler::None,
+                ),
+            }),
+            node.session_sk(),
+            node.did(),
+            node.did(),
+        )?;
+        assert!(matches!(
+            handler.handle_message(&find).await,
+            Err(Error::DhtDisabled(_))
+        ));
+
+        let custom = MessagePayload::new_send(
+            Message::custom("Hello without dht".as_bytes())?,
+            node.session_sk(),
+            node.did(),
+            node.did(),
+        )?;
+        assert!(handler.handle_message(&custom).await.is_ok());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_message_filter_rejects_before_handling() -> Result<()> {
+        let (node, _path) = prepare_node(SecretKey::random()).await;
+        let handler = MessageHandler::new(node.dht(), None, None)
+            .with_filter(MessageFilter::deny(["CustomMessage"]));
+
+        let custom = MessagePayload::new_send(
+            Message::custom("Hello storage node".as_bytes())?,
+            node.session_sk(),
+            node.did(),
+            node.did(),
+        )?;
+        assert!(matches!(
+            handler.handle_message(&custom).await,
+            Err(Error::MessageRejected(kind)) if kind == "CustomMessage"
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_closed_network_rejects_strangers() -> Result<()> {
+        let (node, _path) = prepare_node(SecretKey::random()).await;
+        let seed_sk = SessionSk::new_with_seckey(&SecretKey::random())?;
+        let stranger_sk = SessionSk::new_with_seckey(&SecretKey::random())?;
+        let handler = MessageHandler::new(node.dht(), None, None)
+            .with_closed_network(membership::ClosedNetwork::new([seed_sk.account_did()]));
+
+        let custom = |session_sk: &SessionSk| {
+            MessagePayload::new_send(
+                Message::custom("Hello closed network".as_bytes()).unwrap(),
+                session_sk,
+                node.did(),
+                node.did(),
+            )
+        };
+        assert!(handler.handle_message(&custom(&seed_sk)?).await.is_ok());
+        assert!(handler
+            .handle_message(&custom(node.session_sk())?)
+            .await
+            .is_ok());
+        let stranger = stranger_sk.account_did();
+        assert!(matches!(
+            handler.handle_message(&custom(&stranger_sk)?).await,
+            Err(Error::NotMember(did)) if did == stranger
+        ));
+
+        // A stranger can't pass by claiming a seed as the origin of relay path.
+        let transaction = Transaction::new(
+            node.did(),
+            uuid::Uuid::new_v4(),
+            Message::custom("Hello closed network".as_bytes())?,
+            &stranger_sk,
+        )?;
+        let relay = MessageRelay::new(vec![seed_sk.account_did()], node.did(), node.did());
+        let forged = MessagePayload::new(transaction, &stranger_sk, relay)?;
+        assert_eq!(forged.relay.origin_sender(), seed_sk.account_did());
+        assert!(matches!(
+            handler.handle_message(&forged).await,
+            Err(Error::NotMember(did)) if did == stranger
+        ));
+
+        // LeaveDHT is only handled from members, such as the node itself.
+        let leave = |session_sk: &SessionSk| {
+            MessagePayload::new_send(
+                Message::LeaveDHT(crate::message::LeaveDHT {
+                    did: seed_sk.account_did(),
+                }),
+                session_sk,
+                node.did(),
+                node.did(),
+            )
+        };
+        assert!(matches!(
+            handler.handle_message(&leave(&stranger_sk)?).await,
+            Err(Error::NotMember(did)) if did == stranger
+        ));
+        assert!(handler
+            .handle_message(&leave(node.session_sk())?)
+            .await
+            .is_ok());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_closed_network_refuses_direct_strangers() -> Result<()> {
+        let (node, _path) = prepare_node(SecretKey::random()).await;
+        let seed: Did = SecretKey::random().address().into();
+        let stranger: Did = SecretKey::random().address().into();
+        let handler = MessageHandler::new(node.dht(), None, None)
+            .with_closed_network(membership::ClosedNetwork::new([seed]));
+
+        // The same payload as the one loaded on a direct connection.
+        let join = |did: Did| {
+            MessagePayload::new_send(
+                Message::JoinDHT(crate::message::JoinDHT { did }),
+                node.session_sk(),
+                node.did(),
+                node.did(),
+            )
+        };
+        let evs = handler.handle_message(&join(seed)?).await?;
+        assert!(matches!(
+            evs.as_slice(),
+            [MessageHandlerEvent::JoinDHT(_, did)] if *did == seed
+        ));
+        let evs = handler.handle_message(&join(stranger)?).await?;
+        assert!(matches!(
+            evs.as_slice(),
+            [MessageHandlerEvent::Disconnect(did)] if *did == stranger
+        ));
+        assert!(!handler.is_member(stranger));
+        assert!(!node.dht().successors().contains(&stranger)?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ice_candidate_of_signer() -> Result<()> {
+        let (node, _path) = prepare_node(SecretKey::random()).await;
+        let handler = MessageHandler::new(node.dht(), None, None);
+        let victim: Did = SecretKey::random().address().into();
+        let stranger_sk = SessionSk::new_with_seckey(&SecretKey::random())?;
+
+        // A candidate claiming another peer as the origin of relay path is taken for its signer.
+        let transaction = Transaction::new(
+            node.did(),
+            uuid::Uuid::new_v4(),
+            Message::IceCandidate(crate::message::IceCandidate {
+                candidate: "{}".to_string(),
+            }),
+            &stranger_sk,
+        )?;
+        let relay = MessageRelay::new(vec![victim], node.did(), node.did());
+        let forged = MessagePayload::new(transaction, &stranger_sk, relay)?;
+        let evs = handler.handle_message(&forged).await?;
+        assert!(matches!(
+            evs.as_slice(),
+            [MessageHandlerEvent::AddIceCandidate(did, _)] if *did == stranger_sk.account_did()
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_node_announcement_gossip() -> Result<()> {
+        let (node, _path) = prepare_node(SecretKey::random()).await;
+        let handler = MessageHandler::new(node.dht(), None, None);
+        let announcer_sk = SessionSk::new_with_seckey(&SecretKey::random())?;
+        let announcer = announcer_sk.account_did();
+        let neighbour_sk = SessionSk::new_with_seckey(&SecretKey::random())?;
+
+        let announced = |did: Did| crate::message::AnnouncedNode {
+            did,
+            capabilities: vec!["relay".to_string()],
+            ts_ms: crate::utils::get_epoch_ms(),
+        };
+        let send = |announcement: crate::message::NodeAnnouncement, sender: &SessionSk| {
+            MessagePayload::new_send(
+                Message::NodeAnnouncement(announcement),
+                sender,
+                node.did(),
+                node.did(),
+            )
+        };
+
+        let mut announcement =
+            crate::message::NodeAnnouncement::new(announced(announcer), &announcer_sk)?;
+        let payload = send(announcement.clone(), &announcer_sk)?;
+        let evs = handler.handle_message(&payload).await?;
+        assert!(matches!(
+            evs.as_slice(),
+            [MessageHandlerEvent::Gossip(Message::NodeAnnouncement(forwarded), except)]
+                if forwarded.hops == 1 && except.contains(&announcer)
+        ));
+        // A duplicated announcement is not gossiped again.
+        assert!(handler.handle_message(&payload).await?.is_empty());
+        let known = handler.known_nodes().list(crate::utils::get_epoch_ms());
+        assert_eq!(known.len(), 1);
+        assert_eq!(known[0].did, announcer);
+        assert_eq!(known[0].hops, 1);
+
+        // Hops are counted by the neighbour sending it, and it's not forwarded beyond the limit.
+        let other_sk = SessionSk::new_with_seckey(&SecretKey::random())?;
+        announcement =
+            crate::message::NodeAnnouncement::new(announced(other_sk.account_did()), &other_sk)?;
+        announcement.hops = announce::MAX_ANNOUNCE_HOPS as u8 - 1;
+        let evs = handler
+            .handle_message(&send(announcement, &neighbour_sk)?)
+            .await?;
+        assert!(evs.is_empty());
+        let known = handler.known_nodes().list(crate::utils::get_epoch_ms());
+        assert_eq!(known[0].hops, announce::MAX_ANNOUNCE_HOPS);
+
+        // Announcements of others can't be forged, nor altered on the way.
+        let forged = crate::message::NodeAnnouncement::new(announced(announcer), &other_sk)?;
+        assert!(handler
+            .handle_message(&send(forged, &other_sk)?)
+            .await
+            .is_err());
+        let mut altered =
+            crate::message::NodeAnnouncement::new(announced(announcer), &announcer_sk)?;
+        let mut tampered = announced(announcer);
+        tampered.capabilities = vec!["storage".to_string()];
+        altered.announced.data = bincode::serialize(&tampered).unwrap();
+        assert!(handler
+            .handle_message(&send(altered, &neighbour_sk)?)
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remote_routing_query() -> Result<()> {
+        let (key1, key2, key3) = (
+            SecretKey::random(),
+            SecretKey::random(),
+            SecretKey::random(),
+        );
+        let did3: Did = key3.address().into();
+
+        // node2 discloses its routing to everyone except node3.
+        let path = PersistenceStorage::random_path("./tmp");
+        let storage = PersistenceStorage::new_with_path(path.as_str()).await?;
+        let node2 = Arc::new(
+            SwarmBuilder::new(
+                "stun://stun.l.google.com:19302",
+                storage,
+                SessionSk::new_with_seckey(&key2)?,
+            )
+            .authorizer(Box::new(DidListGate::default().deny([did3])))
+            .build(),
+        );
+        let (node1, _path1) = prepare_node(key1).await;
+        let (node3, _path3) = prepare_node(key3).await;
+
+        manually_establish_connection(&node1, &node2).await;
+        manually_establish_connection(&node3, &node2).await;
+        for node in [node1.clone(), node2.clone(), node3.clone()] {
+            tokio::spawn(async move { node.listen().await });
+        }
+        sleep(Duration::from_secs(5)).await;
+
+        let snapshot = node1.query_routing(node2.did(), 5000).await?;
+        assert_eq!(snapshot, RoutingSnapshot {
+            created_at_ms: snapshot.created_at_ms,
+            ..node2.routing_snapshot().count_vnodes(&node2.dht()).await
+        });
+        assert!(snapshot.successors.contains(&node1.did()));
+
+        assert!(matches!(
+            node3.query_routing(node2.did(), 5000).await,
+            Err(Error::RoutingQueryDenied(did, _)) if did == node2.did()
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cancel_lookup() -> Result<()> {
+        let (node1, _path1) = prepare_node(SecretKey::random()).await;
+        let (node2, _path2) = prepare_node(SecretKey::random()).await;
+        manually_establish_connection(&node1, &node2).await;
+        let listen1 = node1.clone();
+        tokio::spawn(async move { listen1.listen().await });
+        sleep(Duration::from_secs(3)).await;
+
+        // node2 is not listening, so the lookup is never answered.
+        let token = crate::swarm::CancellationToken::new();
+        let canceller = token.clone();
+        tokio::spawn(async move {
+            sleep(Duration::from_millis(200)).await;
+            canceller.cancel();
+        });
+        let lookup = node1.lookup_vnode(node2.did(), &token);
+        assert!(matches!(
+            tokio::time::timeout(Duration::from_secs(1), lookup).await,
+            Ok(Err(Error::LookupCancelled))
+        ));
+        assert_eq!(node1.pending_lookups(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_send_message_order() -> Result<()> {
+        let key1 = SecretKey::random();
+        let key2 = SecretKey::random();
+
+        let msg_callback2 = MessageCallbackInstance {
+            handler_messages: Arc::new(Mutex::new(vec![])),
+        };
+        let cb2: CallbackFn = Box::new(msg_callback2.clone());
+
+        let (node1, _path1) = prepare_node(key1).await;
+        let (node2, _path2) = prepare_node_with_callback(key2, Some(cb2)).await;
+        manually_establish_connection(&node1, &node2).await;
+
+        let listen2 = node2.clone();
+        tokio::spawn(async move { listen2.listen().await });
+
+        let expected: Vec<Vec<u8>> = (0..32u8).map(|i| vec![i; 4096]).collect();
+        let sendings = expected.iter().map(|data| {
+            let node1 = node1.clone();
+            let did2 = node2.did();
+            let msg = Message::custom(data).unwrap();
+            async move { node1.send_message(msg, did2).await }
+        });
+        for result in futures::future::join_all(sendings).await {
+            result?;
+        }
+        sleep(Duration::from_secs(5)).await;
+
+        let received: Vec<Vec<u8>> = msg_callback2
+            .handler_messages
+            .lock()
+            .await
+            .iter()
+            .map(|(_, msg)| msg.clone())
+            .collect();
+        assert_eq!(received, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pause_listening() -> Result<()> {
+        let key1 = SecretKey::random();
+        let key2 = SecretKey::random();
+
+        let msg_callback2 = MessageCallbackInstance {
+            handler_messages: Arc::new(Mutex::new(vec![])),
+        };
+        let cb2: CallbackFn = Box::new(msg_callback2.clone());
+
+        let (node1, _path1) = prepare_node(key1).await;
+        let (node2, _path2) = prepare_node_with_callback(key2, Some(cb2)).await;
+        manually_establish_connection(&node1, &node2).await;
+
+        node2.pause_listening();
+        let listen2 = node2.clone();
+        tokio::spawn(async move { listen2.listen().await });
+
+        let expected: Vec<Vec<u8>> = (0..4u8).map(|i| vec![i; 16]).collect();
+        for data in expected.iter() {
+            node1
+                .send_message(Message::custom(data).unwrap(), node2.did())
+                .await?;
+        }
+        sleep(Duration::from_secs(1)).await;
+        assert!(msg_callback2.handler_messages.lock().await.is_empty());
+
+        node2.resume_listening();
+        sleep(Duration::from_secs(1)).await;
+        let received: Vec<Vec<u8>> = msg_callback2
+            .handler_messages
+            .lock()
+            .await
+            .iter()
+            .map(|(_, msg)| msg.clone())
+            .collect();
+        assert_eq!(received, expected);
+        Ok(())
+    }
+
     pub async fn assert_no_more_msg(node1: &Swarm, node2: &Swarm, node3: &Swarm) {
         tokio::select! {
             _ = node1.listen_once() => unreachable!("node1 should not receive any message"),
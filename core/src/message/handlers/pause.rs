@@ -0,0 +1,135 @@
+#![warn(missing_docs)]
+//! Pause and resume of the message listener, see [MessageHandler::pause_listening].
+//!
+//! While paused, [Swarm::listen_once](crate::swarm::Swarm::listen_once) waits before taking
+//! the next message, and transports stay open. Messages received meanwhile are queued in the
+//! transport event channel of swarm, so nothing is dropped by pausing itself. Once that
+//! channel is full, its [OverflowPolicy](crate::types::channel::OverflowPolicy) applies:
+//! transports are held by backpressure, or messages are dropped and counted by the
+//! `rings_event_queue_dropped` metric, see [SwarmBuilder::event_queue].
+//!
+//! [SwarmBuilder::event_queue]: crate::swarm::SwarmBuilder::event_queue
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::task::Context;
+use std::task::Poll;
+use std::task::Waker;
+
+use crate::message::MessageHandler;
+
+/// Gate of the message listener, open unless paused.
+/// Cloned gates share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct ListenGate(Arc<ListenGateState>);
+
+#[derive(Debug, Default)]
+struct ListenGateState {
+    paused: AtomicBool,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl ListenGateState {
+    fn wakers(&self) -> MutexGuard<'_, Vec<Waker>> {
+        self.wakers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl ListenGate {
+    /// Close the gate, the listener waits until it's opened again.
+    pub fn pause(&self) {
+        self.0.paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Open the gate, and wake up the listener waiting on [ListenGate::resumed].
+    pub fn resume(&self) {
+        let mut wakers = self.0.wakers();
+        self.0.paused.store(false, Ordering::SeqCst);
+        for waker in wakers.drain(..) {
+            waker.wake();
+        }
+    }
+
+    /// Check if the gate is closed.
+    pub fn is_paused(&self) -> bool {
+        self.0.paused.load(Ordering::SeqCst)
+    }
+
+    /// A future which is ready once the gate is open.
+    pub fn resumed(&self) -> Resumed {
+        Resumed(self.clone())
+    }
+}
+
+/// Future returned by [ListenGate::resumed].
+#[derive(Debug)]
+pub struct Resumed(ListenGate);
+
+impl Future for Resumed {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if !self.0.is_paused() {
+            return Poll::Ready(());
+        }
+        let mut wakers = self.0 .0.wakers();
+        // Checked again under lock, since the gate may be opened meanwhile.
+        if !self.0.is_paused() {
+            return Poll::Ready(());
+        }
+        if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+impl MessageHandler {
+    /// Stop processing incoming messages without closing transports, such as during
+    /// reconfiguration, until [MessageHandler::resume_listening]. See [pause](self).
+    pub fn pause_listening(&self) {
+        tracing::info!("Message listener paused");
+        self.listen_gate.pause()
+    }
+
+    /// Resume processing incoming messages, starting with the ones queued while paused.
+    pub fn resume_listening(&self) {
+        tracing::info!("Message listener resumed");
+        self.listen_gate.resume()
+    }
+
+    /// Check if the message listener is paused.
+    pub fn is_listening_paused(&self) -> bool {
+        self.listen_gate.is_paused()
+    }
+
+    /// Get the gate of the message listener.
+    pub fn listen_gate(&self) -> &ListenGate {
+        &self.listen_gate
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::FutureExt;
+
+    use super::*;
+
+    #[test]
+    fn test_listen_gate() {
+        let gate = ListenGate::default();
+        assert!(gate.resumed().now_or_never().is_some());
+
+        gate.pause();
+        let mut resumed = Box::pin(gate.clone().resumed());
+        assert!((&mut resumed).now_or_never().is_none());
+
+        gate.resume();
+        assert!(!gate.is_paused());
+        futures::executor::block_on(resumed);
+    }
+}
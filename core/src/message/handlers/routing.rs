@@ -0,0 +1,231 @@
+#![warn(missing_docs)]
+//! Query of the DHT view of a remote node, for debugging inconsistencies of the ring.
+//!
+//! A node asks a peer for its view by a [RoutingQuery](crate::message::RoutingQuery), see
+//! [Swarm::query_routing](crate::swarm::Swarm::query_routing). The peer answers with a
+//! [RoutingSnapshot] of its successors, predecessor and finger table, if the asking node is
+//! authorized with [AuthAction::InspectRouting](crate::swarm::AuthAction::InspectRouting).
+//! Snapshots of several nodes are compared by [RoutingSnapshot::disagreements].
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use dashmap::DashMap;
+use futures::channel::oneshot;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::dht::Did;
+use crate::dht::PeerRing;
+use crate::error::Result;
+use crate::message::types::RoutingQuery;
+use crate::message::types::RoutingReport;
+use crate::message::HandleMsg;
+use crate::message::MessageHandler;
+use crate::message::MessageHandlerEvent;
+use crate::message::MessagePayload;
+use crate::message::MessageVerificationExt;
+use crate::storage::PersistenceStorageOperation;
+use crate::utils::get_epoch_ms;
+
+/// Default time waiting for the report of a routing query, in milliseconds.
+pub const ROUTING_QUERY_TIMEOUT_MS: u64 = 10 * 1000;
+
+/// View of DHT of a node at a time.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoutingSnapshot {
+    /// Did of the node.
+    pub did: Did,
+    /// Successors of the node, the closest first.
+    pub successors: Vec<Did>,
+    /// Predecessor of the node.
+    pub predecessor: Option<Did>,
+    /// Finger table of the node.
+    pub finger: Vec<Option<Did>>,
+    /// Time of taking the snapshot, by the clock of the node.
+    pub created_at_ms: u128,
+    /// Number of virtual nodes held by the node, None if not counted.
+    #[serde(default)]
+    pub vnodes: Option<u64>,
+}
+
+/// A node whose successor doesn't take it as predecessor, see
+/// [RoutingSnapshot::disagreements].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoutingDisagreement {
+    /// The node.
+    pub did: Did,
+    /// Successor of the node in its view.
+    pub successor: Did,
+    /// Predecessor of the successor in its view.
+    pub successor_predecessor: Option<Did>,
+}
+
+impl RoutingSnapshot {
+    /// Take a snapshot of the view of DHT.
+    pub fn take(dht: &PeerRing) -> Self {
+        Self {
+            did: dht.did,
+            successors: dht.successors().list().unwrap_or_default(),
+            predecessor: dht.lock_predecessor().map(|p| *p).ok().flatten(),
+            finger: dht
+                .lock_finger()
+                .map(|ft| ft.list().clone())
+                .unwrap_or_default(),
+            created_at_ms: get_epoch_ms(),
+            vnodes: None,
+        }
+    }
+
+    /// Count virtual nodes held in the storage of DHT.
+    pub async fn count_vnodes(mut self, dht: &PeerRing) -> Self {
+        self.vnodes = dht.storage.count().await.ok();
+        self
+    }
+
+    /// The closest successor of the node.
+    pub fn successor(&self) -> Option<Did> {
+        self.successors.first().copied()
+    }
+
+    /// Find nodes whose successor takes another node as predecessor, among the given
+    /// snapshots. A node whose successor is not among the snapshots is not checked.
+    pub fn disagreements(snapshots: &[RoutingSnapshot]) -> Vec<RoutingDisagreement> {
+        snapshots
+            .iter()
+            .filter_map(|s| {
+                let successor = s.successor()?;
+                let view = snapshots.iter().find(|v| v.did == successor)?;
+                (view.predecessor != Some(s.did)).then_some(RoutingDisagreement {
+                    did: s.did,
+                    successor,
+                    successor_predecessor: view.predecessor,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Routing queries waiting for reports, keyed by transaction id.
+/// Cloned sets share the same queries.
+#[derive(Debug, Clone, Default)]
+pub struct RoutingQueries(Arc<DashMap<uuid::Uuid, (Did, oneshot::Sender<RoutingReport>)>>);
+
+impl RoutingQueries {
+    /// Wait for the report of a query sent to `did` in transaction `tx_id`.
+    pub fn register(&self, tx_id: uuid::Uuid, did: Did) -> oneshot::Receiver<RoutingReport> {
+        let (sender, receiver) = oneshot::channel();
+        self.0.insert(tx_id, (did, sender));
+        receiver
+    }
+
+    /// Forget a query, such as one timed out.
+    pub fn cancel(&self, tx_id: uuid::Uuid) {
+        self.0.remove(&tx_id);
+    }
+
+    /// Deliver a report signed by `signer`. A report not answering a query waiting,
+    /// or not signed by the node queried, is ignored. Returns true if it's delivered.
+    pub fn resolve(&self, tx_id: uuid::Uuid, signer: Did, report: RoutingReport) -> bool {
+        match self.0.remove_if(&tx_id, |_, (did, _)| *did == signer) {
+            Some((_, (_, sender))) => sender.send(report).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Number of queries waiting.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Check if no query is waiting.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// The report is made by swarm, which checks if the asking node is authorized.
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<RoutingQuery> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        _msg: &RoutingQuery,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        if self.dht.did != ctx.relay.destination {
+            return Ok(vec![MessageHandlerEvent::ForwardPayload(ctx.clone(), None)]);
+        }
+        Ok(vec![MessageHandlerEvent::ReportRouting(ctx.clone())])
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<RoutingReport> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        msg: &RoutingReport,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        if self.dht.did != ctx.relay.destination {
+            return Ok(vec![MessageHandlerEvent::ForwardPayload(ctx.clone(), None)]);
+        }
+        let signer = ctx.transaction.signer();
+        if !self
+            .routing_queries
+            .resolve(ctx.transaction.tx_id, signer, msg.clone())
+        {
+            tracing::debug!("Ignore routing report from {signer} not queried");
+        }
+        Ok(vec![])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn snapshot(did: u32, successor: u32, predecessor: u32) -> RoutingSnapshot {
+        RoutingSnapshot {
+            did: did.into(),
+            successors: vec![successor.into()],
+            predecessor: Some(predecessor.into()),
+            finger: vec![],
+            created_at_ms: 0,
+            vnodes: None,
+        }
+    }
+
+    #[test]
+    fn test_routing_disagreements() {
+        let consistent = [snapshot(1, 2, 3), snapshot(2, 3, 1), snapshot(3, 1, 2)];
+        assert!(RoutingSnapshot::disagreements(&consistent).is_empty());
+
+        // 1 takes 2 as successor, while 2 takes 4 as predecessor.
+        let views = [snapshot(1, 2, 3), snapshot(2, 3, 4), snapshot(3, 5, 2)];
+        assert_eq!(RoutingSnapshot::disagreements(&views), vec![
+            RoutingDisagreement {
+                did: 1u32.into(),
+                successor: 2u32.into(),
+                successor_predecessor: Some(4u32.into()),
+            }
+        ]);
+    }
+
+    #[test]
+    fn test_routing_queries() {
+        let queries = RoutingQueries::default();
+        let tx_id = uuid::Uuid::new_v4();
+        let mut receiver = queries.register(tx_id, 1u32.into());
+        let report = RoutingReport::Denied("no".to_string());
+
+        // Reports of other nodes or transactions are ignored.
+        assert!(!queries.resolve(tx_id, 2u32.into(), report.clone()));
+        assert!(!queries.resolve(uuid::Uuid::new_v4(), 1u32.into(), report.clone()));
+        assert_eq!(queries.len(), 1);
+
+        assert!(queries.resolve(tx_id, 1u32.into(), report.clone()));
+        assert!(queries.is_empty());
+        assert_eq!(receiver.try_recv().unwrap(), Some(report));
+    }
+}
@@ -2,6 +2,8 @@ use async_trait::async_trait;
 
 use crate::dht::Chord;
 use crate::dht::ChordStorageSync;
+use crate::dht::Did;
+use crate::dht::PeerRing;
 use crate::dht::PeerRingAction;
 use crate::dht::PeerRingRemoteAction;
 use crate::error::Result;
@@ -47,19 +49,27 @@ impl HandleMsg<NotifyPredecessorReport> for MessageHandler {
         msg: &NotifyPredecessorReport,
     ) -> Result<Vec<MessageHandlerEvent>> {
         let mut events = vec![MessageHandlerEvent::Connect(msg.did)];
+        if let Ok(sync) = sync_vnode_with_successor(&self.dht, msg.did).await {
+            events.extend(sync);
+        }
+        Ok(events)
+    }
+}
 
-        if let Ok(PeerRingAction::RemoteAction(
-            next,
-            PeerRingRemoteAction::SyncVNodeWithSuccessor(data),
-        )) = self.dht.sync_vnode_with_successor(msg.did).await
-        {
-            events.push(MessageHandlerEvent::SendMessage(
+/// Events of moving a batch of vnodes no longer in charge of to `successor`,
+/// see [ChordStorageSync::sync_vnode_with_successor]. The rest is moved by stabilization.
+pub(crate) async fn sync_vnode_with_successor(
+    dht: &PeerRing,
+    successor: Did,
+) -> Result<Vec<MessageHandlerEvent>> {
+    match dht.sync_vnode_with_successor(successor).await? {
+        PeerRingAction::RemoteAction(next, PeerRingRemoteAction::SyncVNodeWithSuccessor(data)) => {
+            Ok(vec![MessageHandlerEvent::SendMessage(
                 Message::SyncVNodeWithSuccessor(SyncVNodeWithSuccessor { data }),
                 next,
-            ))
+            )])
         }
-
-        Ok(events)
+        _ => Ok(vec![]),
     }
 }
 
@@ -2,20 +2,30 @@
 use async_recursion::async_recursion;
 use async_trait::async_trait;
 
+use crate::consts::MAX_VNODE_REPLICAS;
+use crate::dht::vnode::VNodeChanged;
+use crate::dht::vnode::VNodeReplica;
 use crate::dht::vnode::VirtualNode;
+use crate::dht::Chord;
 use crate::dht::ChordStorage;
 use crate::dht::ChordStorageCache;
 use crate::dht::Did;
 use crate::dht::PeerRing;
 use crate::dht::PeerRingAction;
 use crate::dht::PeerRingRemoteAction;
+use crate::dht::SuccessorReader;
 use crate::error::Error;
 use crate::error::Result;
 use crate::handle_multi_actions;
+use crate::message::types::FetchVNodeReplica;
 use crate::message::types::FoundVNode;
+use crate::message::types::FoundVNodeReplicas;
 use crate::message::types::Message;
 use crate::message::types::SearchVNode;
+use crate::message::types::SearchVNodeReplicas;
 use crate::message::types::SyncVNodeWithSuccessor;
+use crate::message::types::VNodeOperationRejected;
+use crate::message::types::WatchVNode;
 use crate::message::Encoded;
 use crate::message::HandleMsg;
 use crate::message::MessageHandler;
@@ -23,6 +33,8 @@ use crate::message::MessageHandlerEvent;
 use crate::message::MessagePayload;
 use crate::message::PayloadSender;
 use crate::prelude::vnode::VNodeOperation;
+use crate::swarm::lookup::LookupReport;
+use crate::swarm::CancellationToken;
 use crate::swarm::Swarm;
 
 /// ChordStorageInterface should imply necessary method for DHT storage
@@ -37,6 +49,8 @@ pub trait ChordStorageInterface<const REDUNDANT: u16> {
     async fn storage_append_data(&self, topic: &str, data: Encoded) -> Result<()>;
     /// append data to Data type virtual node uniquely
     async fn storage_touch_data(&self, topic: &str, data: Encoded) -> Result<()>;
+    /// search all replicas of virtual node, from the responsible nodes and their successors
+    async fn storage_search_replicas(&self, vid: Did) -> Result<()>;
 }
 
 /// ChordStorageInterfaceCacheChecker defines the interface for checking the local cache of the DHT.
@@ -47,6 +61,9 @@ pub trait ChordStorageInterfaceCacheChecker {
     ///
     /// Returns an optional `VirtualNode` representing the cached data, or `None` if it is not found.
     async fn storage_check_cache(&self, vid: Did) -> Option<VirtualNode>;
+
+    /// Check the replicas of a virtual node collected by `storage_search_replicas`.
+    async fn storage_check_replicas(&self, vid: Did) -> Vec<VNodeReplica>;
 }
 
 /// Handle the storage fetch action of the peer ring.
@@ -91,6 +108,12 @@ pub(super) async fn handle_storage_store_act(swarm: &Swarm, act: PeerRingAction)
                 .send_message(Message::OperateVNode(op), target)
                 .await?;
         }
+        PeerRingAction::RemoteAction(watcher, PeerRingRemoteAction::NotifyVNodeChanged(c)) => {
+            if let Err(e) = swarm.send_message(Message::VNodeChanged(c), watcher).await {
+                tracing::warn!("Failed to notify watcher {}, dropped: {:?}", watcher, e);
+                swarm.dht.vnode_watchers.remove_watcher(watcher);
+            }
+        }
         PeerRingAction::MultiActions(acts) => {
             for act in acts {
                 handle_storage_store_act(swarm, act).await?;
@@ -110,6 +133,12 @@ pub(super) async fn handle_storage_operate_act(
 ) -> Result<Vec<MessageHandlerEvent>> {
     match act {
         PeerRingAction::None => Ok(vec![]),
+        PeerRingAction::RemoteAction(watcher, PeerRingRemoteAction::NotifyVNodeChanged(c)) => {
+            Ok(vec![MessageHandlerEvent::SendMessage(
+                Message::VNodeChanged(c.clone()),
+                *watcher,
+            )])
+        }
         PeerRingAction::RemoteAction(next, _) => Ok(vec![MessageHandlerEvent::ResetDestination(
             ctx.clone(),
             *next,
@@ -132,6 +161,11 @@ impl ChordStorageInterfaceCacheChecker for Swarm {
     async fn storage_check_cache(&self, vid: Did) -> Option<VirtualNode> {
         self.dht.local_cache_get(vid)
     }
+
+    /// Check collected replicas
+    async fn storage_check_replicas(&self, vid: Did) -> Vec<VNodeReplica> {
+        self.dht.local_replicas_get(vid)
+    }
 }
 
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
@@ -169,6 +203,55 @@ impl<const REDUNDANT: u16> ChordStorageInterface<REDUNDANT> for Swarm {
         handle_storage_store_act(self, act).await?;
         Ok(())
     }
+
+    /// Search replicas of virtual node on every affined key. The results will be
+    /// collected asynchronously, use `storage_check_replicas` to read them.
+    async fn storage_search_replicas(&self, vid: Did) -> Result<()> {
+        self.dht.local_replicas_start(vid);
+        for key in vid.rotate_affine(REDUNDANT) {
+            match self.dht.find_successor(key)? {
+                // The replica of this key should be stored in current node.
+                PeerRingAction::Some(_) => {
+                    let replica = self.dht.local_replica(key).await;
+                    self.dht.local_replicas_add(vid, replica);
+                    for succ in self.dht.successors().list()? {
+                        self.send_message(
+                            Message::FetchVNodeReplica(FetchVNodeReplica { vid, key }),
+                            succ,
+                        )
+                        .await?;
+                    }
+                }
+                PeerRingAction::RemoteAction(next, _) => {
+                    self.send_message(
+                        Message::SearchVNodeReplicas(SearchVNodeReplicas { vid, key }),
+                        next,
+                    )
+                    .await?;
+                }
+                act => return Err(Error::PeerRingUnexpectedAction(act)),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Swarm {
+    /// Register or renew current node as a watcher of vnode on the node responsible for it,
+    /// which is found by a lookup of the successor of vnode. Changes signed by that node are
+    /// published to [VNodeSubscriptions](crate::dht::watch::VNodeSubscriptions) of current node.
+    pub async fn storage_watch(&self, vid: Did) -> Result<()> {
+        let holder = self
+            .lookup_successor(vid, &CancellationToken::new())
+            .await?;
+        self.dht.vnode_subscriptions.set_holder(vid, holder);
+        if holder == self.did() {
+            return self.dht.vnode_watchers.watch(vid, self.did());
+        }
+        self.send_message(Message::WatchVNode(WatchVNode { vid }), holder)
+            .await?;
+        Ok(())
+    }
 }
 
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
@@ -181,6 +264,10 @@ impl HandleMsg<SearchVNode> for MessageHandler {
         ctx: &MessagePayload,
         msg: &SearchVNode,
     ) -> Result<Vec<MessageHandlerEvent>> {
+        if self.pending_lookups.is_abandoned(ctx.transaction.tx_id) {
+            tracing::debug!("Stop forwarding abandoned lookup of {}", msg.vid);
+            return Ok(vec![]);
+        }
         // For relay message, set redundant to 1
         match <PeerRing as ChordStorage<_, 1>>::vnode_lookup(&self.dht, msg.vid).await {
             Ok(action) => match action {
@@ -213,13 +300,113 @@ impl HandleMsg<FoundVNode> for MessageHandler {
         if self.dht.did != ctx.relay.destination {
             return Ok(vec![MessageHandlerEvent::ForwardPayload(ctx.clone(), None)]);
         }
+        let tx_id = ctx.transaction.tx_id;
+        if self.pending_lookups.is_abandoned(tx_id) {
+            return Ok(vec![]);
+        }
         for data in msg.data.iter().cloned() {
             self.dht.local_cache_set(data);
         }
+        self.pending_lookups
+            .resolve(tx_id, LookupReport::VNodes(msg.data.clone()));
         Ok(vec![])
     }
 }
 
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<SearchVNodeReplicas> for MessageHandler {
+    /// Search replicas via successor.
+    /// The responsible node reports its own replica and its successors, which the
+    /// requester asks for their replicas.
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        msg: &SearchVNodeReplicas,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        match self.dht.find_successor(msg.key)? {
+            PeerRingAction::Some(_) => {
+                let replica = self.dht.local_replica(msg.key).await;
+                Ok(vec![MessageHandlerEvent::SendReportMessage(
+                    ctx.clone(),
+                    Message::FoundVNodeReplicas(FoundVNodeReplicas {
+                        vid: msg.vid,
+                        replicas: vec![replica],
+                        successors: self.dht.successors().list()?,
+                    }),
+                )])
+            }
+            PeerRingAction::RemoteAction(next, _) => {
+                Ok(vec![MessageHandlerEvent::ResetDestination(
+                    ctx.clone(),
+                    next,
+                )])
+            }
+            act => Err(Error::PeerRingUnexpectedAction(act)),
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<FetchVNodeReplica> for MessageHandler {
+    /// Read replica from local storage and report it to the sender.
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        msg: &FetchVNodeReplica,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        let replica = self.dht.local_replica(msg.key).await;
+        Ok(vec![MessageHandlerEvent::SendReportMessage(
+            ctx.clone(),
+            Message::FoundVNodeReplicas(FoundVNodeReplicas {
+                vid: msg.vid,
+                replicas: vec![replica],
+                successors: vec![],
+            }),
+        )])
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<FoundVNodeReplicas> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        msg: &FoundVNodeReplicas,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        if self.dht.did != ctx.relay.destination {
+            return Ok(vec![MessageHandlerEvent::ForwardPayload(ctx.clone(), None)]);
+        }
+        // Only replicas of searches started by current node are collected, and a node
+        // only reports replicas held by itself.
+        let signer = ctx.transaction.signer();
+        let mut events = vec![];
+        for replica in msg.replicas.iter().cloned() {
+            let key = replica.key;
+            if replica.holder != signer || !self.dht.local_replicas_add(msg.vid, replica) {
+                tracing::debug!("Drop replica of {} reported by {}", msg.vid, signer);
+                continue;
+            }
+            // Successors of the responsible node are asked by current node, so that they
+            // report to it directly.
+            for succ in msg.successors.iter().take(MAX_VNODE_REPLICAS) {
+                if *succ == self.dht.did {
+                    let replica = self.dht.local_replica(key).await;
+                    self.dht.local_replicas_add(msg.vid, replica);
+                } else if *succ != signer {
+                    events.push(MessageHandlerEvent::SendMessage(
+                        Message::FetchVNodeReplica(FetchVNodeReplica { vid: msg.vid, key }),
+                        *succ,
+                    ));
+                }
+            }
+        }
+        Ok(events)
+    }
+}
+
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
 #[cfg_attr(not(feature = "wasm"), async_trait)]
 impl HandleMsg<VNodeOperation> for MessageHandler {
@@ -228,6 +415,19 @@ impl HandleMsg<VNodeOperation> for MessageHandler {
         ctx: &MessagePayload,
         msg: &VNodeOperation,
     ) -> Result<Vec<MessageHandlerEvent>> {
+        let vid = msg.did()?;
+        if let PeerRingAction::Some(_) = self.dht.find_successor(vid)? {
+            if let Err(e) = self.admit_store(ctx.transaction.signer()) {
+                tracing::debug!("Reject operating {vid}: {e}");
+                return Ok(vec![MessageHandlerEvent::SendReportMessage(
+                    ctx.clone(),
+                    Message::OperateVNodeRejected(VNodeOperationRejected {
+                        vid,
+                        reason: e.to_string(),
+                    }),
+                )]);
+            }
+        }
         // For relay message, set redundant to 1
         let action =
             <PeerRing as ChordStorage<_, 1>>::vnode_operate(&self.dht, msg.clone()).await?;
@@ -235,6 +435,28 @@ impl HandleMsg<VNodeOperation> for MessageHandler {
     }
 }
 
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<VNodeOperationRejected> for MessageHandler {
+    /// Relay the rejection to the requester, who learns of it by its callback.
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        msg: &VNodeOperationRejected,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        if self.dht.did != ctx.relay.destination {
+            return Ok(vec![MessageHandlerEvent::ForwardPayload(ctx.clone(), None)]);
+        }
+        tracing::warn!(
+            "Operating {} is rejected by {}: {}",
+            msg.vid,
+            ctx.transaction.signer(),
+            msg.reason
+        );
+        Ok(vec![])
+    }
+}
+
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
 #[cfg_attr(not(feature = "wasm"), async_trait)]
 impl HandleMsg<SyncVNodeWithSuccessor> for MessageHandler {
@@ -254,17 +476,103 @@ impl HandleMsg<SyncVNodeWithSuccessor> for MessageHandler {
     }
 }
 
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<WatchVNode> for MessageHandler {
+    /// Register the signer as a watcher if the vnode is on current node,
+    /// otherwise relay to the responsible node.
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        msg: &WatchVNode,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        match self.dht.find_successor(msg.vid)? {
+            PeerRingAction::Some(_) => {
+                self.dht
+                    .vnode_watchers
+                    .watch(msg.vid, ctx.transaction.signer())?;
+                Ok(vec![])
+            }
+            PeerRingAction::RemoteAction(next, _) => {
+                Ok(vec![MessageHandlerEvent::ResetDestination(
+                    ctx.clone(),
+                    next,
+                )])
+            }
+            act => Err(Error::PeerRingUnexpectedAction(act)),
+        }
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<VNodeChanged> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        msg: &VNodeChanged,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        if self.dht.did != ctx.relay.destination {
+            return Ok(vec![MessageHandlerEvent::ForwardPayload(ctx.clone(), None)]);
+        }
+        let signer = ctx.transaction.signer();
+        if self.dht.vnode_subscriptions.holder(msg.vid) != Some(signer) {
+            tracing::debug!("Ignore change of vnode {} from {}", msg.vid, signer);
+            return Ok(vec![]);
+        }
+        self.dht.vnode_subscriptions.publish(msg);
+        Ok(vec![])
+    }
+}
+
 #[cfg(not(feature = "wasm"))]
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::dht::vnode::VNodeChange;
     use crate::ecc::tests::gen_ordered_keys;
+    use crate::ecc::SecretKey;
     use crate::message::handlers::connection::tests::test_only_two_nodes_establish_connection;
     use crate::message::Encoder;
     use crate::prelude::vnode::VNodeType;
+    use crate::session::SessionSk;
     use crate::storage::PersistenceStorageOperation;
     use crate::tests::default::prepare_node;
 
+    #[tokio::test]
+    async fn test_vnode_changed_only_from_holder() -> Result<()> {
+        let (node, _path) = prepare_node(SecretKey::random()).await;
+        let handler = MessageHandler::new(node.dht(), None, None);
+        let holder_sk = SessionSk::new_with_seckey(&SecretKey::random())?;
+        let forger_sk = SessionSk::new_with_seckey(&SecretKey::random())?;
+
+        let vid = Did::from(1u32);
+        let mut changes = node.dht().vnode_subscriptions.subscribe(vid);
+        node.dht()
+            .vnode_subscriptions
+            .set_holder(vid, holder_sk.account_did());
+        let changed = VNodeChanged {
+            vid,
+            change: VNodeChange::Stored,
+            vnode: None,
+        };
+        let notify = |session_sk: &SessionSk| {
+            MessagePayload::new_send(
+                Message::VNodeChanged(changed.clone()),
+                session_sk,
+                node.did(),
+                node.did(),
+            )
+        };
+
+        // A change is taken only from the node found responsible by lookup.
+        handler.handle_message(&notify(&forger_sk)?).await?;
+        assert!(changes.try_next().is_err());
+        handler.handle_message(&notify(&holder_sk)?).await?;
+        assert_eq!(changes.try_next().unwrap(), Some(changed));
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_store_vnode() -> Result<()> {
         let keys = gen_ordered_keys(2);
@@ -478,4 +786,58 @@ mod test {
         tokio::fs::remove_dir_all("./tmp").await.ok();
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_search_all_replicas() -> Result<()> {
+        let keys = gen_ordered_keys(2);
+        let (key1, key2) = (keys[0], keys[1]);
+        let (node1, _path1) = prepare_node(key1).await;
+        let (node2, _path2) = prepare_node(key2).await;
+        test_only_two_nodes_establish_connection(&node1, &node2).await?;
+
+        let data = "Across the Great Wall we can reach every corner in the world.".to_string();
+        let vnode: VirtualNode = data.clone().try_into().unwrap();
+        let vid = vnode.did;
+
+        // Make sure the data is stored on node2.
+        let (node1, node2) = if vid.in_range(node2.did(), node2.did(), node1.did()) {
+            (node1, node2)
+        } else {
+            (node2, node1)
+        };
+
+        <Swarm as ChordStorageInterface<1>>::storage_store(&node1, vnode.clone())
+            .await
+            .unwrap();
+        node2.listen_once().await.unwrap();
+
+        <Swarm as ChordStorageInterface<1>>::storage_search_replicas(&node1, vid)
+            .await
+            .unwrap();
+
+        let ev = node2.listen_once().await.unwrap().0;
+        assert!(matches!(
+            ev.transaction.data()?,
+            Message::SearchVNodeReplicas(x) if x.vid == vid && x.key == vid
+        ));
+
+        // node2 reports its replica and its successor, which is node1.
+        let ev = node1.listen_once().await.unwrap().0;
+        assert!(matches!(
+            ev.transaction.data()?,
+            Message::FoundVNodeReplicas(x) if x.vid == vid && x.successors == vec![node1.did()]
+        ));
+
+        let replicas = node1.storage_check_replicas(vid).await;
+        assert_eq!(replicas.len(), 2);
+        let found = replicas.iter().find(|r| r.holder == node2.did()).unwrap();
+        assert_eq!(found.vnode, Some(vnode));
+        assert_eq!(found.version, 1);
+        let missing = replicas.iter().find(|r| r.holder == node1.did()).unwrap();
+        assert_eq!(missing.vnode, None);
+        assert_eq!(missing.version, 0);
+
+        tokio::fs::remove_dir_all("./tmp").await.ok();
+        Ok(())
+    }
 }
@@ -0,0 +1,285 @@
+#![warn(missing_docs)]
+//! Rate limit of storing virtual nodes by origin, see [MessageHandler::with_store_limit].
+//!
+//! Quotas bound how many bytes a peer stores, but not how often it stores, so a peer can still
+//! churn storage by rewriting the same key rapidly. Each origin storing to current node has a
+//! token bucket of [StoreLimitConfig], and an `OperateVNode` of an origin whose bucket is empty
+//! is rejected, with an `OperateVNodeRejected` sent back. The origin is the signer of the
+//! operation, since the relay path isn't signed. Operations relayed to other nodes, and the
+//! ones of current node, are not limited.
+//!
+//! Limits are scaled by the [PeerScores] of origin, so that well-behaved peers store more,
+//! while peers rejected keep losing score, and so their limits.
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::dht::Did;
+use crate::error::Error;
+use crate::error::Result;
+use crate::message::MessageHandler;
+use crate::swarm::PeerScores;
+use crate::swarm::PeerSignal;
+use crate::swarm::MAX_SCORE;
+use crate::utils::get_epoch_ms;
+
+/// Number of origins tracked before full buckets are forgotten.
+const MAX_TRACKED_ORIGINS: usize = 4096;
+
+/// Config of [StoreLimiter].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StoreLimitConfig {
+    /// Stores an origin may burst, which is the capacity of its bucket.
+    pub burst: f64,
+    /// Stores refilled to the bucket of an origin per second.
+    pub per_sec: f64,
+    /// Scale of limits of an origin scoring [MAX_SCORE], while one scoring `-MAX_SCORE` is
+    /// limited by its inverse. Limits scale exponentially in between, a score of zero is 1.
+    pub score_scale: f64,
+}
+
+impl Default for StoreLimitConfig {
+    fn default() -> Self {
+        Self {
+            burst: 32.0,
+            per_sec: 2.0,
+            score_scale: 4.0,
+        }
+    }
+}
+
+impl StoreLimitConfig {
+    /// Scale of limits of an origin by its score.
+    pub fn scale(&self, score: f64) -> f64 {
+        self.score_scale
+            .max(1.0)
+            .powf(score.clamp(-MAX_SCORE, MAX_SCORE) / MAX_SCORE)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated_at_ms: u128,
+    /// When the bucket is refilled to its capacity, by the score of origin at update.
+    full_at_ms: u128,
+}
+
+impl Bucket {
+    fn new(tokens: f64, updated_at_ms: u128, capacity: f64, rate: f64) -> Self {
+        let full_at_ms = if tokens >= capacity {
+            updated_at_ms
+        } else if rate > 0.0 {
+            updated_at_ms + ((capacity - tokens) / rate * 1000.0).ceil() as u128
+        } else {
+            u128::MAX
+        };
+        Self {
+            tokens,
+            updated_at_ms,
+            full_at_ms,
+        }
+    }
+}
+
+/// Buckets by origin, with origins ordered by when their buckets are refilled,
+/// so that full buckets are found without scanning all of them.
+#[derive(Debug, Default)]
+struct Buckets {
+    buckets: HashMap<Did, Bucket>,
+    by_full_at: BTreeSet<(u128, Did)>,
+}
+
+impl Buckets {
+    fn insert(&mut self, origin: Did, bucket: Bucket) {
+        if let Some(old) = self.buckets.insert(origin, bucket) {
+            self.by_full_at.remove(&(old.full_at_ms, origin));
+        }
+        self.by_full_at.insert((bucket.full_at_ms, origin));
+    }
+}
+
+/// Token buckets of stores by origin, see [store_limit](self). Cloned limiters share the
+/// same buckets.
+#[derive(Debug, Clone)]
+pub struct StoreLimiter {
+    config: StoreLimitConfig,
+    scores: PeerScores,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl StoreLimiter {
+    /// Create a limiter scaling limits by `scores`.
+    pub fn new(config: StoreLimitConfig, scores: PeerScores) -> Self {
+        Self {
+            config,
+            scores,
+            buckets: Default::default(),
+        }
+    }
+
+    /// Take a token of `origin` for a store, fails with [Error::StoreRateLimited] if its
+    /// bucket is empty.
+    pub fn admit(&self, origin: Did) -> Result<()> {
+        self.admit_at(origin, get_epoch_ms())
+    }
+
+    fn admit_at(&self, origin: Did, now: u128) -> Result<()> {
+        let (capacity, rate) = self.limits(origin);
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.buckets.len() >= MAX_TRACKED_ORIGINS {
+            self.forget_full(&mut buckets, now);
+        }
+
+        let tokens = match buckets.buckets.get(&origin) {
+            Some(b) => {
+                let elapsed = now.saturating_sub(b.updated_at_ms) as f64 / 1000.0;
+                (b.tokens + elapsed * rate).min(capacity)
+            }
+            None => capacity,
+        };
+        let admitted = tokens >= 1.0;
+        let tokens = if admitted { tokens - 1.0 } else { tokens };
+        buckets.insert(origin, Bucket::new(tokens, now, capacity, rate));
+        drop(buckets);
+
+        if !admitted {
+            self.scores.record(origin, PeerSignal::RateLimited);
+            return Err(Error::StoreRateLimited(origin));
+        }
+        Ok(())
+    }
+
+    /// Capacity and refilling rate of the bucket of origin, scaled by its current score.
+    fn limits(&self, origin: Did) -> (f64, f64) {
+        let scale = self.config.scale(self.scores.score(origin));
+        (self.config.burst * scale, self.config.per_sec * scale)
+    }
+
+    /// Forget origins whose buckets would be refilled by now, they start full anyway.
+    /// Only origins expected to be full by the scores at their last update are checked,
+    /// by their current scores as when admitting. The ones whose scores dropped since
+    /// are kept, and checked again when they are expected to be full.
+    fn forget_full(&self, buckets: &mut Buckets, now: u128) {
+        while let Some(&(full_at_ms, origin)) = buckets.by_full_at.first() {
+            if full_at_ms > now {
+                break;
+            }
+            buckets.by_full_at.pop_first();
+            let Some(b) = buckets.buckets.get(&origin).copied() else {
+                continue;
+            };
+            let (capacity, rate) = self.limits(origin);
+            let elapsed = now.saturating_sub(b.updated_at_ms) as f64 / 1000.0;
+            if b.tokens + elapsed * rate >= capacity {
+                buckets.buckets.remove(&origin);
+                continue;
+            }
+            let mut b = Bucket::new(b.tokens, b.updated_at_ms, capacity, rate);
+            b.full_at_ms = b.full_at_ms.max(now + 1);
+            buckets.insert(origin, b);
+        }
+    }
+}
+
+impl MessageHandler {
+    /// Rate-limit storing virtual nodes by origin, see [store_limit](self).
+    /// Stores are not limited by default.
+    pub fn with_store_limit(mut self, limiter: StoreLimiter) -> Self {
+        self.store_limiter = Some(limiter);
+        self
+    }
+
+    /// Take a token of `origin` for storing to current node. Always succeeds if not limited,
+    /// or the origin is current node.
+    pub fn admit_store(&self, origin: Did) -> Result<()> {
+        match self.store_limiter {
+            Some(ref limiter) if origin != self.dht.did => limiter.admit(origin),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_store_limiter() {
+        let config = StoreLimitConfig {
+            burst: 4.0,
+            per_sec: 1.0,
+            score_scale: 4.0,
+        };
+        let scores = PeerScores::default();
+        let limiter = StoreLimiter::new(config, scores.clone());
+        let (spammer, good) = (Did::from(1u32), Did::from(2u32));
+
+        // A burst is admitted, then the origin has to wait for refilling.
+        let now = get_epoch_ms();
+        for _ in 0..4 {
+            limiter.admit_at(spammer, now).unwrap();
+        }
+        assert!(matches!(
+            limiter.admit_at(spammer, now),
+            Err(Error::StoreRateLimited(did)) if did == spammer
+        ));
+        // Being rejected costs score, and so the rate of refilling.
+        assert!(scores.score(spammer) < 0.0);
+        assert!(limiter.admit_at(spammer, now + 500).is_err());
+        assert!(limiter.admit_at(spammer, now + 2000).is_ok());
+
+        // A peer of good score bursts more.
+        for _ in 0..50 {
+            scores.record(good, PeerSignal::Delivered);
+        }
+        let scale = config.scale(scores.score(good));
+        assert!(scale > 1.5);
+        for _ in 0..(4.0 * scale) as usize {
+            limiter.admit_at(good, now).unwrap();
+        }
+        assert!(limiter.admit_at(good, now).is_err());
+
+        // Buckets are forgotten once refilled to the capacity scaled by score.
+        let limiter = StoreLimiter::new(config, scores.clone());
+        limiter.admit_at(good, now).unwrap();
+        let mut buckets = limiter.buckets.lock().unwrap();
+        limiter.forget_full(&mut buckets, now);
+        assert!(buckets.buckets.contains_key(&good));
+        limiter.forget_full(&mut buckets, now + 1000);
+        assert!(buckets.buckets.is_empty());
+        assert!(buckets.by_full_at.is_empty());
+        drop(buckets);
+
+        assert_eq!(config.scale(0.0), 1.0);
+        assert_eq!(config.scale(MAX_SCORE), 4.0);
+        assert_eq!(config.scale(-MAX_SCORE * 2.0), 0.25);
+    }
+
+    #[test]
+    fn test_store_limiter_forgets_full_buckets() {
+        let limiter = StoreLimiter::new(StoreLimitConfig::default(), PeerScores::default());
+        let now = get_epoch_ms();
+        for i in 0..MAX_TRACKED_ORIGINS as u32 {
+            limiter.admit_at(Did::from(i), now).unwrap();
+        }
+
+        // Buckets still refilling are kept.
+        limiter.admit_at(Did::from(u32::MAX), now).unwrap();
+        let tracked = limiter.buckets.lock().unwrap().buckets.len();
+        assert_eq!(tracked, MAX_TRACKED_ORIGINS + 1);
+
+        // Once refilled, they're forgotten by the next store.
+        limiter
+            .admit_at(Did::from(u32::MAX - 1), now + 1000)
+            .unwrap();
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.buckets.len(), 1);
+        assert_eq!(buckets.by_full_at.len(), 1);
+    }
+}
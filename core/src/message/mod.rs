@@ -6,16 +6,29 @@ pub use encoder::Encoder;
 
 mod payload;
 pub use payload::decode_gzip_data;
+pub use payload::decode_gzip_data_with_limit;
 pub use payload::encode_data_gzip;
 pub use payload::from_gzipped_data;
 pub use payload::gzip_data;
 pub use payload::MessagePayload;
 pub use payload::PayloadSender;
 pub use payload::Transaction;
+pub use payload::COMPACT_PAYLOAD_VERSION;
+pub use payload::LEGACY_PAYLOAD_TAG;
+pub use payload::PATH_COMPRESSED_PAYLOAD_TAG;
+pub use payload::PAYLOAD_VERSION_TAGS;
 
 pub mod types;
 pub use types::*;
 
+pub mod dictionary;
+pub use dictionary::CompressionDictionaries;
+pub use dictionary::CompressionDictionary;
+pub use dictionary::DICT_COMPRESSED_PAYLOAD_TAG;
+
+mod filter;
+pub use filter::MessageFilter;
+
 pub mod handlers;
 pub use handlers::storage::ChordStorageInterface;
 pub use handlers::storage::ChordStorageInterfaceCacheChecker;
@@ -28,5 +41,8 @@ pub use handlers::MessageHandlerEvent;
 pub use handlers::ValidatorFn;
 
 mod protocols;
+pub use protocols::HopStamp;
 pub use protocols::MessageRelay;
+pub use protocols::MessageVerification;
 pub use protocols::MessageVerificationExt;
+pub use protocols::MAX_TRACE_HOPS;
@@ -1,12 +1,15 @@
 #![warn(missing_docs)]
 
+use std::io::Read;
 use std::io::Write;
 use std::sync::Arc;
 
 use async_trait::async_trait;
+use bincode::Options;
 use bytes::Bytes;
 use derivative::Derivative;
-use flate2::write::GzDecoder;
+use ethereum_types::H160;
+use flate2::read::GzDecoder;
 use flate2::write::GzEncoder;
 use flate2::Compression;
 use serde::de::DeserializeOwned;
@@ -16,9 +19,12 @@ use serde::Serialize;
 use super::encoder::Decoder;
 use super::encoder::Encoded;
 use super::encoder::Encoder;
+use super::protocols::HopStamp;
 use super::protocols::MessageRelay;
 use super::protocols::MessageVerification;
 use super::protocols::MessageVerificationExt;
+use super::protocols::MAX_TRACE_HOPS;
+use crate::consts::MAX_DECOMPRESSED_SIZE;
 use crate::dht::Chord;
 use crate::dht::Did;
 use crate::dht::PeerRing;
@@ -26,7 +32,49 @@ use crate::dht::PeerRingAction;
 use crate::ecc::keccak256;
 use crate::error::Error;
 use crate::error::Result;
+use crate::session::Session;
 use crate::session::SessionSk;
+use crate::swarm::SendPriority;
+
+/// Leading byte of the legacy encoding of [MessagePayload], see [MessagePayload::to_bincode].
+/// The legacy encoding is not tagged explicitly, but always starts with the length of
+/// destination did, which is encoded as a hex string of 42 bytes, so that it's told apart
+/// from other envelopes by the first byte as well.
+///
+/// Every envelope of payload is tagged by its first byte:
+///
+/// | tag | envelope |
+/// |-----|----------|
+/// | [LEGACY_PAYLOAD_TAG] | legacy encoding, see [MessagePayload::to_bincode] |
+/// | [PAYLOAD_VERSION_TAGS] | compact encoding, see [MessagePayload::to_compact] |
+/// | [STREAM_FRAME_TAG] | frame of a stream |
+/// | [PATH_COMPRESSED_PAYLOAD_TAG] | compact encoding with compressed relay path |
+/// | [DICT_COMPRESSED_PAYLOAD_TAG] | any of the above, compressed against a dictionary |
+///
+/// Data of any other tag fails to decode with [Error::ProtocolVersionMismatch].
+///
+/// [STREAM_FRAME_TAG]: crate::swarm::stream::STREAM_FRAME_TAG
+/// [DICT_COMPRESSED_PAYLOAD_TAG]: crate::message::DICT_COMPRESSED_PAYLOAD_TAG
+pub const LEGACY_PAYLOAD_TAG: u8 = 42;
+
+/// Leading byte of the compact encoding of [MessagePayload], see [MessagePayload::to_compact].
+pub const COMPACT_PAYLOAD_VERSION: u8 = 0xC1;
+
+/// Leading bytes reserved for version tags of encodings of [MessagePayload].
+/// A later version only appends fields to the compact envelope, so that an older node
+/// can still read the known fields by [MessagePayload::from_bincode_best_effort].
+pub const PAYLOAD_VERSION_TAGS: std::ops::RangeInclusive<u8> = 0xC0..=0xCF;
+
+/// Leading byte of the compact encoding of [MessagePayload] with compressed relay path,
+/// see [MessagePayload::to_compact_path_compressed]. It's out of [PAYLOAD_VERSION_TAGS],
+/// so that a node not supporting it never takes it as a later version of compact encoding.
+pub const PATH_COMPRESSED_PAYLOAD_TAG: u8 = 0xD1;
+
+/// Leading byte of the envelope of relay trace, which follows the legacy encoding of
+/// [MessagePayload] when tracing is enabled, see [MessageRelay::trace]. Older nodes ignore
+/// trailing bytes of the legacy encoding, so that a traced message is still readable by them,
+/// while the trace is dropped at their hops.
+pub const TRACE_ENVELOPE_TAG: u8 = 0xE1;
 
 /// Compresses the given data byte slice using the gzip algorithm with the specified compression level.
 pub fn encode_data_gzip(data: &Bytes, level: u8) -> Result<Bytes> {
@@ -44,12 +92,22 @@ where T: Serialize {
 }
 
 /// Decompresses the given gzip-compressed byte slice and returns the decompressed byte slice.
+/// Fails if more than [MAX_DECOMPRESSED_SIZE] bytes are decompressed.
 pub fn decode_gzip_data(data: &Bytes) -> Result<Bytes> {
+    decode_gzip_data_with_limit(data, MAX_DECOMPRESSED_SIZE)
+}
+
+/// Decompresses the given gzip-compressed byte slice, failing with
+/// [Error::DecompressedTooLarge] as soon as more than `limit` bytes are decompressed.
+pub fn decode_gzip_data_with_limit(data: &Bytes, limit: usize) -> Result<Bytes> {
     let mut writer = Vec::new();
-    let mut decoder = GzDecoder::new(writer);
-    decoder.write_all(data).map_err(|_| Error::GzipDecode)?;
-    decoder.try_finish().map_err(|_| Error::GzipDecode)?;
-    writer = decoder.finish().map_err(|_| Error::GzipDecode)?;
+    GzDecoder::new(data.as_ref())
+        .take(limit as u64 + 1)
+        .read_to_end(&mut writer)
+        .map_err(|_| Error::GzipDecode)?;
+    if writer.len() > limit {
+        return Err(Error::DecompressedTooLarge(limit));
+    }
     Ok(writer.into())
 }
 
@@ -92,6 +150,130 @@ pub struct Transaction {
     pub verification: MessageVerification,
 }
 
+/// Envelope of [MessagePayload] in compact encoding. Integers and lengths are varint encoded,
+/// and fields repeating the ones of transaction are omitted.
+#[derive(Deserialize, Serialize)]
+struct CompactPayload<P = Vec<Did>> {
+    destination: Did,
+    tx_id: uuid::Uuid,
+    data: Vec<u8>,
+    tx_verification: MessageVerification,
+    path: P,
+    next_hop: Did,
+    /// None if it's the destination of transaction.
+    relay_destination: Option<Did>,
+    trace: Option<Vec<HopStamp>>,
+    ttl_ms: u64,
+    ts_ms: u128,
+    sig: Vec<u8>,
+    /// None if it's the session of transaction, which is usual unless relayed.
+    session: Option<Session>,
+}
+
+impl<P> CompactPayload<P> {
+    fn new(payload: &MessagePayload, path: P) -> Self {
+        let tx = &payload.transaction;
+        let relay = &payload.relay;
+        let verification = &payload.verification;
+        Self {
+            destination: tx.destination,
+            tx_id: tx.tx_id,
+            data: tx.data.clone(),
+            tx_verification: tx.verification.clone(),
+            path,
+            next_hop: relay.next_hop,
+            relay_destination: Some(relay.destination).filter(|d| *d != tx.destination),
+            trace: relay.trace.clone(),
+            ttl_ms: verification.ttl_ms,
+            ts_ms: verification.ts_ms,
+            sig: verification.sig.clone(),
+            session: Some(verification.session.clone()).filter(|s| *s != tx.verification.session),
+        }
+    }
+
+    /// Split path from envelope, the payload returned has an empty path.
+    fn split_path(self) -> (P, MessagePayload) {
+        let compact = self;
+        let session = compact
+            .session
+            .unwrap_or_else(|| compact.tx_verification.session.clone());
+        let payload = MessagePayload {
+            transaction: Transaction {
+                destination: compact.destination,
+                tx_id: compact.tx_id,
+                data: compact.data,
+                verification: compact.tx_verification,
+            },
+            relay: MessageRelay {
+                path: vec![],
+                next_hop: compact.next_hop,
+                destination: compact.relay_destination.unwrap_or(compact.destination),
+                trace: compact.trace,
+            },
+            verification: MessageVerification {
+                session,
+                ttl_ms: compact.ttl_ms,
+                ts_ms: compact.ts_ms,
+                sig: compact.sig,
+            },
+        };
+        (compact.path, payload)
+    }
+}
+
+impl From<&MessagePayload> for CompactPayload {
+    fn from(payload: &MessagePayload) -> Self {
+        Self::new(payload, payload.relay.path.clone())
+    }
+}
+
+impl From<CompactPayload> for MessagePayload {
+    fn from(compact: CompactPayload) -> Self {
+        let (path, mut payload) = compact.split_path();
+        payload.relay.path = path;
+        payload
+    }
+}
+
+/// Relay path in compressed encoding. Each hop is stored as its clockwise distance on ring
+/// from the previous hop, in big-endian bytes without leading zeros. The first hop is
+/// measured from the signer of transaction, so that the origin sender takes no bytes.
+#[derive(Deserialize, Serialize)]
+struct CompressedPath(Vec<Vec<u8>>);
+
+impl CompressedPath {
+    fn compress(path: &[Did], signer: Did) -> Self {
+        let mut prev = signer;
+        let hops = path
+            .iter()
+            .map(|hop| {
+                let delta = *hop - prev;
+                prev = *hop;
+                let bytes = delta.as_bytes();
+                let zeros = bytes.iter().take_while(|b| **b == 0).count();
+                bytes[zeros..].to_vec()
+            })
+            .collect();
+        Self(hops)
+    }
+
+    fn decompress(self, signer: Did) -> Result<Vec<Did>> {
+        let mut prev = signer;
+        self.0
+            .into_iter()
+            .map(|delta| {
+                if delta.len() > 20 {
+                    return Err(Error::InvalidRelayPath);
+                }
+                let mut bytes = [0u8; 20];
+                bytes[20 - delta.len()..].copy_from_slice(&delta);
+                prev = prev + Did::from(H160::from(bytes));
+                Ok(prev)
+            })
+            .collect()
+    }
+}
+
 /// `MessagePayload` is used to transmit data between nodes.
 /// The data should be packed by [Transaction].
 #[derive(Derivative, Deserialize, Serialize, Clone, PartialEq, Eq)]
@@ -179,16 +361,110 @@ impl MessagePayload {
         Self::new(transaction, session_sk, relay)
     }
 
-    /// Deserializes a `MessagePayload` instance from the given binary data.
+    /// Deserializes a `MessagePayload` instance from the given binary data,
+    /// which is either in legacy or compact encoding, see [MessagePayload::to_compact].
+    /// Fails with [Error::ProtocolVersionMismatch] if data is tagged with an unknown version,
+    /// or an envelope unknown to this version, see [LEGACY_PAYLOAD_TAG].
     pub fn from_bincode(data: &[u8]) -> Result<Self> {
-        bincode::deserialize(data).map_err(Error::BincodeDeserialize)
+        match data.split_first() {
+            Some((&LEGACY_PAYLOAD_TAG, _)) => Self::from_legacy(data),
+            Some((&COMPACT_PAYLOAD_VERSION, compact)) => Self::compact_options()
+                .with_limit(compact.len() as u64)
+                .deserialize::<CompactPayload>(compact)
+                .map(Self::from)
+                .map_err(Error::BincodeDeserialize),
+            Some((&PATH_COMPRESSED_PAYLOAD_TAG, compressed)) => {
+                let (path, mut payload) = Self::compact_options()
+                    .with_limit(compressed.len() as u64)
+                    .deserialize::<CompactPayload<CompressedPath>>(compressed)
+                    .map_err(Error::BincodeDeserialize)?
+                    .split_path();
+                payload.relay.path = path.decompress(payload.transaction.signer())?;
+                Ok(payload)
+            }
+            Some((&got, _)) => Err(Error::ProtocolVersionMismatch {
+                expected: COMPACT_PAYLOAD_VERSION,
+                got,
+            }),
+            None => Self::from_legacy(data),
+        }
+    }
+
+    /// Decode the legacy encoding, and the envelope of relay trace following it if any.
+    fn from_legacy(data: &[u8]) -> Result<Self> {
+        let mut payload: Self = bincode::deserialize(data).map_err(Error::BincodeDeserialize)?;
+        let len = bincode::serialized_size(&payload).map_err(Error::BincodeDeserialize)? as usize;
+        if let Some((&TRACE_ENVELOPE_TAG, trace)) = data.get(len..).and_then(|d| d.split_first()) {
+            payload.relay.trace = bincode::DefaultOptions::new()
+                .with_fixint_encoding()
+                .allow_trailing_bytes()
+                .with_limit(trace.len() as u64)
+                .deserialize::<Vec<HopStamp>>(trace)
+                .ok()
+                .map(|trace| trace.into_iter().take(MAX_TRACE_HOPS).collect());
+        }
+        Ok(payload)
     }
 
-    /// Serializes the `MessagePayload` instance into binary data.
+    /// Like [MessagePayload::from_bincode], but data of a later version is decoded as far as
+    /// the fields known to this version, and the appended fields are ignored.
+    /// Data of an earlier version still fails with [Error::ProtocolVersionMismatch].
+    pub fn from_bincode_best_effort(data: &[u8]) -> Result<Self> {
+        match data.split_first() {
+            Some((&got, compact))
+                if PAYLOAD_VERSION_TAGS.contains(&got) && got > COMPACT_PAYLOAD_VERSION =>
+            {
+                Self::compact_options()
+                    .allow_trailing_bytes()
+                    .with_limit(compact.len() as u64)
+                    .deserialize::<CompactPayload>(compact)
+                    .map(Self::from)
+                    .map_err(|_| Error::ProtocolVersionMismatch {
+                        expected: COMPACT_PAYLOAD_VERSION,
+                        got,
+                    })
+            }
+            _ => Self::from_bincode(data),
+        }
+    }
+
+    fn compact_options() -> impl Options {
+        bincode::DefaultOptions::new().with_varint_encoding()
+    }
+
+    /// Serializes the `MessagePayload` instance into compact binary data, which starts with
+    /// [COMPACT_PAYLOAD_VERSION]. It's much smaller than [MessagePayload::to_bincode] for
+    /// small messages, but can only be read by nodes supporting it.
+    pub fn to_compact(&self) -> Result<Bytes> {
+        let mut data = vec![COMPACT_PAYLOAD_VERSION];
+        Self::compact_options()
+            .serialize_into(&mut data, &CompactPayload::from(self))
+            .map_err(Error::BincodeSerialize)?;
+        Ok(data.into())
+    }
+
+    /// Like [MessagePayload::to_compact], but relay path is compressed, which starts with
+    /// [PATH_COMPRESSED_PAYLOAD_TAG]. Dids of path are otherwise encoded as hex strings,
+    /// so that a hop takes at most 21 bytes instead of 43, and the origin sender takes 1.
+    pub fn to_compact_path_compressed(&self) -> Result<Bytes> {
+        let signer = self.transaction.signer();
+        let path = CompressedPath::compress(&self.relay.path, signer);
+        let mut data = vec![PATH_COMPRESSED_PAYLOAD_TAG];
+        Self::compact_options()
+            .serialize_into(&mut data, &CompactPayload::new(self, path))
+            .map_err(Error::BincodeSerialize)?;
+        Ok(data.into())
+    }
+
+    /// Serializes the `MessagePayload` instance into binary data, followed by the envelope
+    /// of relay trace if tracing is enabled, see [TRACE_ENVELOPE_TAG].
     pub fn to_bincode(&self) -> Result<Bytes> {
-        bincode::serialize(self)
-            .map(Bytes::from)
-            .map_err(Error::BincodeSerialize)
+        let mut data = bincode::serialize(self).map_err(Error::BincodeSerialize)?;
+        if let Some(trace) = self.relay.trace() {
+            data.push(TRACE_ENVELOPE_TAG);
+            bincode::serialize_into(&mut data, trace).map_err(Error::BincodeSerialize)?;
+        }
+        Ok(data.into())
     }
 }
 
@@ -252,6 +528,16 @@ pub trait PayloadSender {
             _ => Err(Error::NoNextHop),
         }
     }
+    /// Infer the next hop for a message of priority, see [SendPriority].
+    /// Defaults to `infer_next_hop`, which ignores priority.
+    fn infer_next_hop_of(
+        &self,
+        next_hop: Option<Did>,
+        destination: Did,
+        _priority: SendPriority,
+    ) -> Result<Did> {
+        self.infer_next_hop(next_hop, destination)
+    }
     /// Alias for `do_send_payload` that sets the next hop to `payload.relay.next_hop`.
     async fn send_payload(&self, payload: MessagePayload) -> Result<()> {
         self.do_send_payload(payload.relay.next_hop, payload).await
@@ -273,12 +559,37 @@ pub trait PayloadSender {
         Ok(tx_id)
     }
 
+    /// Create a payload of message to a specified destination, by the next hop inferred
+    /// for the priority of message.
+    fn new_inferred_payload<T>(&self, msg: T, destination: Did) -> Result<MessagePayload>
+    where T: Serialize {
+        let session_sk = self.session_sk();
+        let transaction = Transaction::new(destination, uuid::Uuid::new_v4(), msg, session_sk)?;
+        let priority = SendPriority::of_transaction(&transaction);
+        let next_hop = self.infer_next_hop_of(None, destination, priority)?;
+        let relay = MessageRelay::new(vec![session_sk.account_did()], next_hop, destination);
+        MessagePayload::new(transaction, session_sk, relay)
+    }
+
     /// Send a message to a specified destination.
     async fn send_message<T>(&self, msg: T, destination: Did) -> Result<uuid::Uuid>
     where T: Serialize + Send {
-        let next_hop = self.infer_next_hop(None, destination)?;
-        self.send_message_by_hop(msg, destination, next_hop).await
+        let payload = self.new_inferred_payload(msg, destination)?;
+        let tx_id = payload.transaction.tx_id;
+        self.send_payload(payload).await?;
+        Ok(tx_id)
     }
+    /// Send a message to a specified destination with tracing enabled, so that each hop
+    /// records its did and time in [MessageRelay::trace].
+    async fn send_traced_message<T>(&self, msg: T, destination: Did) -> Result<uuid::Uuid>
+    where T: Serialize + Send {
+        let mut payload = self.new_inferred_payload(msg, destination)?;
+        payload.relay = payload.relay.with_trace();
+        let tx_id = payload.transaction.tx_id;
+        self.send_payload(payload).await?;
+        Ok(tx_id)
+    }
+
     /// Send a direct message to a specified destination.
     async fn send_direct_message<T>(&self, msg: T, destination: Did) -> Result<uuid::Uuid>
     where T: Serialize + Send {
@@ -309,9 +620,16 @@ pub trait PayloadSender {
         self.send_payload(new_pl).await
     }
 
-    /// Forward a payload message, with the next hop inferred by the DHT.
+    /// Forward a payload message, with the next hop inferred for the priority of its message.
     async fn forward_payload(&self, payload: &MessagePayload, next_hop: Option<Did>) -> Result<()> {
-        let next_hop = self.infer_next_hop(next_hop, payload.relay.destination)?;
+        let next_hop = match next_hop {
+            Some(next_hop) => next_hop,
+            None => self.infer_next_hop_of(
+                None,
+                payload.relay.destination,
+                SendPriority::of_transaction(&payload.transaction),
+            )?,
+        };
         let relay = payload.relay.forward(self.dht().did, next_hop)?;
         self.forward_by_relay(payload, relay).await
     }
@@ -328,6 +646,8 @@ pub trait PayloadSender {
 
 #[cfg(test)]
 pub mod test {
+    use std::time::Instant;
+
     use rand::Rng;
 
     use super::*;
@@ -369,6 +689,29 @@ pub mod test {
         assert!(payload.verify());
     }
 
+    #[test]
+    fn test_decode_gzip_data_limit() {
+        let data = Bytes::from(vec![7u8; 1024]);
+        let gzipped = encode_data_gzip(&data, 9).unwrap();
+        assert_eq!(decode_gzip_data_with_limit(&gzipped, 1024).unwrap(), data);
+        assert!(matches!(
+            decode_gzip_data_with_limit(&gzipped, 1023),
+            Err(Error::DecompressedTooLarge(1023))
+        ));
+
+        // A bomb of a few kilobytes inflating beyond the default limit.
+        let bomb = encode_data_gzip(&Bytes::from(vec![0u8; MAX_DECOMPRESSED_SIZE + 1]), 9).unwrap();
+        assert!(bomb.len() < MAX_DECOMPRESSED_SIZE / 100);
+        assert!(matches!(
+            decode_gzip_data(&bomb),
+            Err(Error::DecompressedTooLarge(MAX_DECOMPRESSED_SIZE))
+        ));
+        assert!(matches!(
+            decode_gzip_data(&Bytes::from_static(b"not gzip")),
+            Err(Error::GzipDecode)
+        ));
+    }
+
     #[test]
     fn test_message_payload_from_auto() {
         let next_hop = SecretKey::random().address().into();
@@ -408,4 +751,216 @@ pub mod test {
             encoded_bytes2.len() - data2.len()
         );
     }
+
+    #[test]
+    fn test_message_payload_compact() {
+        let next_hop = SecretKey::random().address().into();
+        // A typical small tunnel packet.
+        let data = rand::thread_rng().gen::<[u8; 32]>().repeat(2);
+        let payload = new_payload(Message::custom(&data).unwrap(), next_hop);
+
+        let legacy = payload.to_bincode().unwrap();
+        let compact = payload.to_compact().unwrap();
+        assert!(compact.len() * 10 < legacy.len() * 7);
+
+        // Both encodings are readable, and verification holds after decoding.
+        for bytes in [legacy, compact] {
+            let decoded = MessagePayload::from_bincode(&bytes).unwrap();
+            assert_eq!(decoded, payload);
+            assert!(decoded.verify());
+        }
+
+        // Fields differing from transaction are kept.
+        let mut relayed = payload.clone();
+        relayed.relay = relayed.relay.reset_destination(next_hop).with_trace();
+        relayed.verification.session = SessionSk::new_with_seckey(&SecretKey::random())
+            .unwrap()
+            .session();
+        let decoded = MessagePayload::from_bincode(&relayed.to_compact().unwrap()).unwrap();
+        assert_eq!(decoded, relayed);
+    }
+
+    /// Measure bytes on wire and encoding time of small tunnel packets, in legacy and compact
+    /// encoding. Run with `cargo test -p rings-core bench_message_payload_compact -- --ignored
+    /// --nocapture`.
+    #[ignore]
+    #[test]
+    fn bench_message_payload_compact() {
+        const ROUNDS: u32 = 1000;
+        let next_hop = SecretKey::random().address().into();
+        println!("data\tlegacy\tcompact\tsaved\tlegacy us\tcompact us");
+        for len in [32, 64, 256, 1024] {
+            let data = vec![7u8; len];
+            let payload = new_payload(Message::custom(&data).unwrap(), next_hop);
+
+            let start = Instant::now();
+            for _ in 0..ROUNDS {
+                payload.to_bincode().unwrap();
+            }
+            let legacy_us = start.elapsed().as_micros() as f64 / ROUNDS as f64;
+            let start = Instant::now();
+            for _ in 0..ROUNDS {
+                payload.to_compact().unwrap();
+            }
+            let compact_us = start.elapsed().as_micros() as f64 / ROUNDS as f64;
+
+            let legacy = payload.to_bincode().unwrap().len();
+            let compact = payload.to_compact().unwrap().len();
+            assert!(compact < legacy);
+            println!(
+                "{}\t{}\t{}\t{:.1}%\t{:.2}\t{:.2}",
+                len,
+                legacy,
+                compact,
+                (legacy - compact) as f64 * 100.0 / legacy as f64,
+                legacy_us,
+                compact_us
+            );
+        }
+    }
+
+    #[test]
+    fn test_message_payload_trace_envelope() {
+        let next_hop = SecretKey::random().address().into();
+        let payload = new_payload(Message::custom("hello".as_bytes()).unwrap(), next_hop);
+        let mut traced = payload.clone();
+        traced.relay = traced.relay.with_trace();
+
+        // Layout of relay is kept, the trace follows it.
+        let legacy = payload.to_bincode().unwrap();
+        let bytes = traced.to_bincode().unwrap();
+        assert_eq!(bytes[..legacy.len()], legacy[..]);
+        assert_eq!(bytes[legacy.len()], TRACE_ENVELOPE_TAG);
+        assert_eq!(MessagePayload::from_bincode(&bytes).unwrap(), traced);
+
+        // Older nodes read it without the trace.
+        let decoded: MessagePayload = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded, payload);
+    }
+
+    #[test]
+    fn test_message_payload_path_compressed() {
+        let next_hop = SecretKey::random().address().into();
+        let payload = new_payload(Message::custom("hello".as_bytes()).unwrap(), next_hop);
+
+        // A route of 6 hops, the origin sender and 5 relays.
+        let mut relayed = payload.clone();
+        for _ in 0..5 {
+            relayed
+                .relay
+                .path
+                .push(SecretKey::random().address().into());
+        }
+        let compact = relayed.to_compact().unwrap();
+        let compressed = relayed.to_compact_path_compressed().unwrap();
+        assert!(compressed.len() < compact.len());
+
+        // Path compression is transparent to handlers.
+        for p in [payload, relayed] {
+            let bytes = p.to_compact_path_compressed().unwrap();
+            let decoded = MessagePayload::from_bincode(&bytes).unwrap();
+            assert_eq!(decoded, p);
+            assert!(decoded.verify());
+        }
+
+        // A hop longer than a did is rejected.
+        let forged = CompressedPath(vec![vec![1; 21]]);
+        assert!(forged.decompress(next_hop).is_err());
+    }
+
+    /// Measure bytes on wire and encoding time of small payloads relayed along routes up to 6
+    /// hops, with and without path compression. Run with `cargo test -p rings-core
+    /// bench_message_payload_path_compressed -- --ignored --nocapture`.
+    #[ignore]
+    #[test]
+    fn bench_message_payload_path_compressed() {
+        const ROUNDS: u32 = 1000;
+        let next_hop = SecretKey::random().address().into();
+        println!("data\thops\tcompact\tcompressed\tsaved\tcompact us\tcompressed us");
+        for len in [5, 64] {
+            let data = vec![7u8; len];
+            let mut payload = new_payload(Message::custom(&data).unwrap(), next_hop);
+            // The path starts with the origin sender, each relay appends itself.
+            for hops in 1..=6 {
+                if hops > 1 {
+                    payload
+                        .relay
+                        .path
+                        .push(SecretKey::random().address().into());
+                }
+
+                let start = Instant::now();
+                for _ in 0..ROUNDS {
+                    payload.to_compact().unwrap();
+                }
+                let compact_us = start.elapsed().as_micros() as f64 / ROUNDS as f64;
+                let start = Instant::now();
+                for _ in 0..ROUNDS {
+                    payload.to_compact_path_compressed().unwrap();
+                }
+                let compressed_us = start.elapsed().as_micros() as f64 / ROUNDS as f64;
+
+                let compact = payload.to_compact().unwrap().len();
+                let compressed = payload.to_compact_path_compressed().unwrap().len();
+                println!(
+                    "{}\t{}\t{}\t{}\t{:.1}%\t{:.2}\t{:.2}",
+                    len,
+                    hops,
+                    compact,
+                    compressed,
+                    (compact as f64 - compressed as f64) * 100.0 / compact as f64,
+                    compact_us,
+                    compressed_us
+                );
+            }
+            assert_eq!(payload.relay.path.len(), 6);
+        }
+    }
+
+    #[test]
+    fn test_message_payload_version_mismatch() {
+        let next_hop = SecretKey::random().address().into();
+        let payload = new_payload(Message::custom("hello".as_bytes()).unwrap(), next_hop);
+
+        // A later version appending a field to the compact envelope.
+        let mut newer = payload.to_compact().unwrap().to_vec();
+        newer[0] = COMPACT_PAYLOAD_VERSION + 1;
+        newer.extend_from_slice(&[1, 2, 3]);
+        assert!(matches!(
+            MessagePayload::from_bincode(&newer),
+            Err(Error::ProtocolVersionMismatch { expected, got })
+                if expected == COMPACT_PAYLOAD_VERSION && got == COMPACT_PAYLOAD_VERSION + 1
+        ));
+        let decoded = MessagePayload::from_bincode_best_effort(&newer).unwrap();
+        assert_eq!(decoded, payload);
+        assert!(decoded.verify());
+
+        // Fields of an earlier version cannot be guessed.
+        let mut older = payload.to_compact().unwrap().to_vec();
+        older[0] = COMPACT_PAYLOAD_VERSION - 1;
+        assert!(matches!(
+            MessagePayload::from_bincode_best_effort(&older),
+            Err(Error::ProtocolVersionMismatch { .. })
+        ));
+
+        // Legacy encoding is never taken as tagged.
+        let legacy = payload.to_bincode().unwrap();
+        assert_eq!(legacy[0], LEGACY_PAYLOAD_TAG);
+        assert!(!PAYLOAD_VERSION_TAGS.contains(&legacy[0]));
+        assert_eq!(
+            MessagePayload::from_bincode_best_effort(&legacy).unwrap(),
+            payload
+        );
+
+        // Envelopes unknown to this version, such as one compressed against a dictionary
+        // which was not decompressed, are reported as well.
+        let mut compressed = vec![crate::message::DICT_COMPRESSED_PAYLOAD_TAG];
+        compressed.extend_from_slice(&legacy);
+        for data in [&compressed[..], &[0xFF, 1, 2][..]] {
+            assert!(matches!(
+                MessagePayload::from_bincode_best_effort(data),
+                Err(Error::ProtocolVersionMismatch { got, .. }) if got == data[0]
+            ));
+        }
+    }
 }
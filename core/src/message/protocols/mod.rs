@@ -1,6 +1,8 @@
 mod relay;
 mod verify;
 
+pub use self::relay::HopStamp;
 pub use self::relay::MessageRelay;
+pub use self::relay::MAX_TRACE_HOPS;
 pub use self::verify::MessageVerification;
 pub use self::verify::MessageVerificationExt;
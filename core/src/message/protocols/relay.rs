@@ -7,6 +7,19 @@ use serde::Serialize;
 use crate::dht::Did;
 use crate::error::Error;
 use crate::error::Result;
+use crate::utils::get_epoch_ms;
+
+/// Max number of hops recorded in the trace of a relay, later hops are not recorded.
+pub const MAX_TRACE_HOPS: usize = 32;
+
+/// A hop recorded in the trace of [MessageRelay].
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HopStamp {
+    /// Did of the node handling the message.
+    pub did: Did,
+    /// Local time in milliseconds when the node sent the message.
+    pub ts_ms: u128,
+}
 
 /// MessageRelay guide message passing on rings network by relay.
 ///
@@ -26,6 +39,13 @@ pub struct MessageRelay {
     /// The destination of the message.
     /// It may help the handler to find out `next_hop` in some situations.
     pub destination: Did,
+
+    /// Hops with the time they sent the message, like the record-route option of IP.
+    /// Tracing is enabled per message by [MessageRelay::with_trace], and it's `None` otherwise.
+    /// It's not a part of the layout of relay, but carried in a separate envelope by
+    /// [MessagePayload](crate::message::MessagePayload), so that older nodes can still read it.
+    #[serde(skip)]
+    pub trace: Option<Vec<HopStamp>>,
 }
 
 impl MessageRelay {
@@ -35,14 +55,54 @@ impl MessageRelay {
             path,
             next_hop,
             destination,
+            trace: None,
         }
     }
 
+    /// Enable tracing, the origin sender is recorded as the first hop.
+    pub fn with_trace(mut self) -> Self {
+        self.trace = Some(
+            self.path
+                .first()
+                .map(|&did| stamp(did))
+                .into_iter()
+                .collect(),
+        );
+        self
+    }
+
+    /// Get hops recorded if tracing is enabled.
+    pub fn trace(&self) -> Option<&[HopStamp]> {
+        self.trace.as_deref()
+    }
+
+    /// Get latencies in milliseconds of each hop of the trace, by the time message is
+    /// received at `arrived_ms`. Each item is the did sending the message and the time until
+    /// the next hop sent it. Clocks of nodes are not synchronized, so latencies are estimations.
+    pub fn trace_latencies(&self, arrived_ms: u128) -> Option<Vec<(Did, u128)>> {
+        let trace = self.trace()?;
+        let next = trace.iter().skip(1).map(|h| h.ts_ms).chain([arrived_ms]);
+        Some(
+            trace
+                .iter()
+                .zip(next)
+                .map(|(h, t)| (h.did, t.saturating_sub(h.ts_ms)))
+                .collect(),
+        )
+    }
+
     /// Validate relay, then create a new `MessageRelay` that have `current` did in the end of path.
     /// The new relay will use `next_hop` as `next_hop` and `self.destination` as `destination`.
+    /// Fails with [Error::RoutingLoop] if `current` is already in path, which means routing
+    /// tables of nodes disagree and the message came back, so that it's dropped at once rather
+    /// than bouncing until it expires.
     pub fn forward(&self, current: Did, next_hop: Did) -> Result<Self> {
         self.validate(current)?;
 
+        if self.path.contains(&current) {
+            return Err(Error::RoutingLoop(current));
+        }
+
         if self.next_hop != current {
             return Err(Error::InvalidNextHop);
         }
@@ -50,10 +110,16 @@ impl MessageRelay {
         let mut path = self.path.clone();
         path.push(current);
 
+        let mut trace = self.trace.clone();
+        if let Some(trace) = trace.as_mut().filter(|t| t.len() < MAX_TRACE_HOPS) {
+            trace.push(stamp(current));
+        }
+
         Ok(Self {
             path,
             next_hop,
             destination: self.destination,
+            trace,
         })
     }
 
@@ -71,6 +137,7 @@ impl MessageRelay {
             path: vec![current],
             next_hop: self.path[self.path.len() - 1],
             destination: self.origin_sender(),
+            trace: None,
         })
     }
 
@@ -115,6 +182,13 @@ impl MessageRelay {
     }
 }
 
+fn stamp(did: Did) -> HopStamp {
+    HopStamp {
+        did,
+        ts_ms: get_epoch_ms(),
+    }
+}
+
 // Since rust cannot zip N iterators, when you change this number,
 // you should also change the code of `has_infinite_loop` below.
 const INFINITE_LOOP_TOLERANCE: usize = 3;
@@ -166,6 +240,59 @@ where T: PartialEq + std::fmt::Debug {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::ecc::SecretKey;
+
+    #[test]
+    fn test_relay_trace() {
+        let [a, b, c, d] = [0; 4].map(|_| -> Did { SecretKey::random().address().into() });
+
+        let relay = MessageRelay::new(vec![a], b, d);
+        assert!(relay.forward(b, c).unwrap().trace().is_none());
+
+        let relay = relay.with_trace();
+        let relay = relay.forward(b, c).unwrap().forward(c, d).unwrap();
+        let trace = relay.trace().unwrap();
+        assert_eq!(trace.iter().map(|h| h.did).collect::<Vec<_>>(), vec![
+            a, b, c
+        ]);
+
+        let arrived = trace[2].ts_ms + 5;
+        let latencies = relay.trace_latencies(arrived).unwrap();
+        assert_eq!(latencies.len(), 3);
+        assert_eq!(latencies[2], (c, 5));
+        // Reports are not traced.
+        assert!(relay.report(d).unwrap().trace().is_none());
+
+        // Trace is capped.
+        let mut relay = MessageRelay::new(vec![a], b, d).with_trace();
+        for i in 0..MAX_TRACE_HOPS * 2 {
+            let (current, next) = if i % 2 == 0 { (b, c) } else { (c, b) };
+            relay.path.clear();
+            relay.path.push(a);
+            relay = relay.forward(current, next).unwrap();
+        }
+        assert_eq!(relay.trace().unwrap().len(), MAX_TRACE_HOPS);
+    }
+
+    #[test]
+    fn test_routing_loop() {
+        let [a, b, c, d] = [0; 4].map(|_| -> Did { SecretKey::random().address().into() });
+
+        // a sends to d through b. b takes c as the next hop towards d, while c takes b,
+        // since their routing tables disagree.
+        let relay = MessageRelay::new(vec![a], b, d);
+        let relay = relay.forward(b, c).unwrap();
+        let relay = relay.forward(c, b).unwrap();
+        assert_eq!(relay.path, vec![a, b, c]);
+
+        // The loop is caught once the message comes back to b, rather than after bouncing
+        // INFINITE_LOOP_TOLERANCE times.
+        assert!(!has_infinite_loop(&[a, b, c, b]));
+        assert!(matches!(relay.forward(b, c), Err(Error::RoutingLoop(did)) if did == b));
+        // Neither can it be forwarded back to the origin sender.
+        let relay = MessageRelay::new(vec![a], b, d).forward(b, a).unwrap();
+        assert!(matches!(relay.forward(a, c), Err(Error::RoutingLoop(did)) if did == a));
+    }
 
     #[test]
     #[rustfmt::skip]
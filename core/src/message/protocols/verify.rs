@@ -9,6 +9,7 @@ use crate::consts::DEFAULT_TTL_MS;
 use crate::consts::MAX_TTL_MS;
 use crate::consts::TS_OFFSET_TOLERANCE_MS;
 use crate::dht::Did;
+use crate::ecc::PublicKey;
 use crate::error::Result;
 use crate::session::Session;
 use crate::session::SessionSk;
@@ -48,6 +49,12 @@ impl MessageVerification {
         Ok(verification)
     }
 
+    /// Recover the session public key which signed `data`.
+    pub fn session_pubkey(&self, data: &[u8]) -> Result<PublicKey> {
+        let msg = pack_msg(data, self.ts_ms, self.ttl_ms);
+        self.session.session_pubkey(&msg, &self.sig)
+    }
+
     /// Verify a MessageVerification
     pub fn verify(&self, data: &[u8]) -> bool {
         let msg = pack_msg(data, self.ts_ms, self.ttl_ms);
@@ -72,6 +79,11 @@ pub trait MessageVerificationExt {
 
     /// Checks whether the message is expired.
     fn is_expired(&self) -> bool {
+        self.is_expired_with_grace(0)
+    }
+
+    /// Checks whether the message is expired for more than `grace_ms`.
+    fn is_expired_with_grace(&self, grace_ms: u64) -> bool {
         if self.verification().ttl_ms > MAX_TTL_MS {
             return false;
         }
@@ -82,18 +94,22 @@ pub trait MessageVerificationExt {
             return false;
         }
 
-        now > self.verification().ts_ms + self.verification().ttl_ms as u128
+        now > self.verification().ts_ms + self.verification().ttl_ms as u128 + grace_ms as u128
     }
 
     /// Verifies that the message is not expired and that the signature is valid.
     fn verify(&self) -> bool {
         if self.is_expired() {
-            tracing::warn!("message expired");
+            tracing::debug!("message expired");
             return false;
         }
+        self.verify_signature()
+    }
 
+    /// Verifies that the signature is valid, regardless of expiry.
+    fn verify_signature(&self) -> bool {
         let Ok(data) = self.verification_data() else {
-            tracing::warn!("MessageVerificationExt verify get verification_data failed");
+            tracing::debug!("MessageVerificationExt verify get verification_data failed");
             return false;
         };
 
@@ -104,4 +120,10 @@ pub trait MessageVerificationExt {
     fn signer(&self) -> Did {
         self.verification().session.account_did()
     }
+
+    /// Get session public key of signer, which is used to encrypt data to signer.
+    fn signer_pubkey(&self) -> Result<PublicKey> {
+        self.verification()
+            .session_pubkey(&self.verification_data()?)
+    }
 }
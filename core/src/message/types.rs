@@ -6,11 +6,16 @@
 use serde::Deserialize;
 use serde::Serialize;
 
+use crate::dht::vnode::VNodeChanged;
 use crate::dht::vnode::VNodeOperation;
+use crate::dht::vnode::VNodeReplica;
 use crate::dht::vnode::VirtualNode;
 use crate::dht::Did;
 use crate::dht::TopoInfo;
+use crate::ecc::PublicKey;
 use crate::error::Result;
+use crate::message::handlers::routing::RoutingSnapshot;
+use crate::message::Transaction;
 
 /// The `Then` trait is used to associate a type with a "then" scenario.
 pub trait Then {
@@ -32,6 +37,13 @@ pub struct ConnectNodeReport {
     pub sdp: String,
 }
 
+/// MessageType use to trickle a local ICE candidate to the remote peer of a connection.
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, Clone)]
+pub struct IceCandidate {
+    /// candidate serialized as json
+    pub candidate: String,
+}
+
 /// MessageType use to find successor in a chord ring.
 #[derive(Debug, PartialEq, Eq, Deserialize, Serialize, Clone)]
 pub struct FindSuccessorSend {
@@ -158,6 +170,39 @@ pub struct FoundVNode {
     pub data: Vec<VirtualNode>,
 }
 
+/// MessageType use to search all replicas of a virtual node.
+/// It is routed to the node responsible for `key`, which then asks its successors.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SearchVNodeReplicas {
+    /// The virtual id of searching target
+    pub vid: Did,
+    /// The storage key of the replica, which is `vid` or one of its affine rotations.
+    pub key: Did,
+}
+
+/// MessageType use to fetch a replica from the local storage of receiver.
+/// Sent by the node searching replicas to successors of the node responsible for `key`,
+/// and the replica is reported to the sender.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct FetchVNodeReplica {
+    /// The virtual id of searching target
+    pub vid: Did,
+    /// The storage key of the replica.
+    pub key: Did,
+}
+
+/// MessageType report to origin found replicas of virtual node.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct FoundVNodeReplicas {
+    /// The virtual id of searching target
+    pub vid: Did,
+    /// Response of [SearchVNodeReplicas] and [FetchVNodeReplica]
+    pub replicas: Vec<VNodeReplica>,
+    /// Successors of the reporter, which hold further replicas of the keys reported.
+    /// Only reported by the node responsible for the keys, in response of [SearchVNodeReplicas].
+    pub successors: Vec<Did>,
+}
+
 /// MessageType after `FindSuccessorSend` and syncing data.
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct SyncVNodeWithSuccessor {
@@ -165,6 +210,88 @@ pub struct SyncVNodeWithSuccessor {
     pub data: Vec<VirtualNode>,
 }
 
+/// MessageType of registering or renewing the sender as a watcher of a virtual node,
+/// see [watch](crate::dht::watch).
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct WatchVNode {
+    /// Did of the watched virtual node.
+    pub vid: Did,
+}
+
+/// MessageType of opening a stream, which is sent directly to the reader,
+/// see [stream](crate::swarm::stream).
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct StreamOpen {
+    /// Id of the stream, carried by its frames.
+    pub id: uuid::Uuid,
+}
+
+/// A node announced to the network, which is signed by the node itself inside a
+/// [NodeAnnouncement].
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct AnnouncedNode {
+    /// Did of the node announced, which should be the signer of the announcement.
+    pub did: Did,
+    /// Capabilities of the node, such as names of services it provides.
+    pub capabilities: Vec<String>,
+    /// Time of announcing in milliseconds, later announcements of a node replace earlier ones.
+    pub ts_ms: u128,
+}
+
+/// MessageType of announcing a node to the network, which is gossiped through connected
+/// peers, see [announce](crate::message::handlers::announce).
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct NodeAnnouncement {
+    /// Transaction of [AnnouncedNode] signed by the node announced.
+    pub announced: Transaction,
+    /// Number of hops the announcement traveled before reaching the sender of the message,
+    /// which is signed by the sender.
+    pub hops: u8,
+}
+
+/// MessageType of offering compression dictionaries to a connected peer, the preferred first,
+/// see [dictionary](crate::message::dictionary).
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct DictionaryOffer {
+    /// Ids of dictionaries known by the sender.
+    pub ids: Vec<u32>,
+}
+
+/// MessageType of offering an ephemeral public key, which peers encrypt data to instead of
+/// the session key of sender. It's rotated for forward secrecy,
+/// see [SessionKeys](crate::swarm::SessionKeys).
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct EphemeralKeyOffer {
+    /// The ephemeral public key.
+    pub pubkey: PublicKey,
+    /// Time of creating the key in milliseconds, later keys of a peer replace earlier ones.
+    pub created_at_ms: u128,
+}
+
+/// MessageType of rejecting an operation of virtual node to the requester, such as by
+/// [store_limit](crate::message::handlers::store_limit).
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct VNodeOperationRejected {
+    /// Did of the virtual node operated.
+    pub vid: Did,
+    /// Reason of rejecting.
+    pub reason: String,
+}
+
+/// MessageType of asking a node for its view of DHT,
+/// see [routing](crate::message::handlers::routing).
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct RoutingQuery;
+
+/// MessageType report to origin the view of DHT, as response of [RoutingQuery].
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub enum RoutingReport {
+    /// View of DHT of the node queried.
+    Snapshot(RoutingSnapshot),
+    /// The origin is not authorized to inspect routing of the node, with reason.
+    Denied(String),
+}
+
 /// MessageType use to customize message, will be handle by `custom_message` method.
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct CustomMessage(pub Vec<u8>);
@@ -225,6 +352,32 @@ pub enum Message {
     QueryForTopoInfoSend(QueryForTopoInfoSend),
     /// Response of QueryForTopoInfoSend
     QueryForTopoInfoReport(QueryForTopoInfoReport),
+    /// Remote message of search all replicas of a virtual node.
+    SearchVNodeReplicas(SearchVNodeReplicas),
+    /// Remote message of fetch a replica from successor.
+    FetchVNodeReplica(FetchVNodeReplica),
+    /// Response when found replicas of a virtual node.
+    FoundVNodeReplicas(FoundVNodeReplicas),
+    /// Remote message of watching changes of a virtual node.
+    WatchVNode(WatchVNode),
+    /// Push a change of virtual node to its watcher.
+    VNodeChanged(VNodeChanged),
+    /// Open a stream to a connected peer.
+    StreamOpen(StreamOpen),
+    /// Announce a node to the network.
+    NodeAnnouncement(NodeAnnouncement),
+    /// Ask a node for its view of DHT.
+    RoutingQuery(RoutingQuery),
+    /// Response of RoutingQuery
+    RoutingReport(RoutingReport),
+    /// Offer compression dictionaries to a connected peer.
+    DictionaryOffer(DictionaryOffer),
+    /// Trickle an ICE candidate to the remote peer of a connection.
+    IceCandidate(IceCandidate),
+    /// Offer an ephemeral key to encrypt data to.
+    EphemeralKeyOffer(EphemeralKeyOffer),
+    /// Response of OperateVNode when it's rejected.
+    OperateVNodeRejected(VNodeOperationRejected),
 }
 
 impl std::fmt::Display for Message {
@@ -234,8 +387,103 @@ impl std::fmt::Display for Message {
 }
 
 impl Message {
+    /// Names of every type of message, see [Message::kind].
+    pub const KINDS: &'static [&'static str] = &[
+        "JoinDHT",
+        "LeaveDHT",
+        "ConnectNodeSend",
+        "ConnectNodeReport",
+        "FindSuccessorSend",
+        "FindSuccessorReport",
+        "NotifyPredecessorSend",
+        "NotifyPredecessorReport",
+        "SearchVNode",
+        "FoundVNode",
+        "OperateVNode",
+        "SyncVNodeWithSuccessor",
+        "CustomMessage",
+        "QueryForTopoInfoSend",
+        "QueryForTopoInfoReport",
+        "SearchVNodeReplicas",
+        "FetchVNodeReplica",
+        "FoundVNodeReplicas",
+        "WatchVNode",
+        "VNodeChanged",
+        "StreamOpen",
+        "NodeAnnouncement",
+        "RoutingQuery",
+        "RoutingReport",
+        "DictionaryOffer",
+        "IceCandidate",
+        "EphemeralKeyOffer",
+        "OperateVNodeRejected",
+    ];
+
+    /// Name of the type of message, which is the name of its variant.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Message::JoinDHT(_) => "JoinDHT",
+            Message::LeaveDHT(_) => "LeaveDHT",
+            Message::ConnectNodeSend(_) => "ConnectNodeSend",
+            Message::ConnectNodeReport(_) => "ConnectNodeReport",
+            Message::FindSuccessorSend(_) => "FindSuccessorSend",
+            Message::FindSuccessorReport(_) => "FindSuccessorReport",
+            Message::NotifyPredecessorSend(_) => "NotifyPredecessorSend",
+            Message::NotifyPredecessorReport(_) => "NotifyPredecessorReport",
+            Message::SearchVNode(_) => "SearchVNode",
+            Message::FoundVNode(_) => "FoundVNode",
+            Message::OperateVNode(_) => "OperateVNode",
+            Message::SyncVNodeWithSuccessor(_) => "SyncVNodeWithSuccessor",
+            Message::CustomMessage(_) => "CustomMessage",
+            Message::QueryForTopoInfoSend(_) => "QueryForTopoInfoSend",
+            Message::QueryForTopoInfoReport(_) => "QueryForTopoInfoReport",
+            Message::SearchVNodeReplicas(_) => "SearchVNodeReplicas",
+            Message::FetchVNodeReplica(_) => "FetchVNodeReplica",
+            Message::FoundVNodeReplicas(_) => "FoundVNodeReplicas",
+            Message::WatchVNode(_) => "WatchVNode",
+            Message::VNodeChanged(_) => "VNodeChanged",
+            Message::StreamOpen(_) => "StreamOpen",
+            Message::NodeAnnouncement(_) => "NodeAnnouncement",
+            Message::RoutingQuery(_) => "RoutingQuery",
+            Message::RoutingReport(_) => "RoutingReport",
+            Message::DictionaryOffer(_) => "DictionaryOffer",
+            Message::IceCandidate(_) => "IceCandidate",
+            Message::EphemeralKeyOffer(_) => "EphemeralKeyOffer",
+            Message::OperateVNodeRejected(_) => "OperateVNodeRejected",
+        }
+    }
+
+    /// Name of the type of message encoded in data of [Transaction], see [Message::kind].
+    /// Only the variant tag is read, so it's cheap to classify a message on every send, but the
+    /// rest of data is not checked.
+    pub fn kind_of(data: &[u8]) -> Option<&'static str> {
+        let tag = u32::from_le_bytes(data.get(..4)?.try_into().ok()?);
+        Self::KINDS.get(tag as usize).copied()
+    }
+
     /// Wrap a data of message into CustomMessage.
     pub fn custom(msg: &[u8]) -> Result<Message> {
         Ok(Message::CustomMessage(CustomMessage(msg.to_vec())))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_kind_of() {
+        let did = Did::from(1u32);
+        for msg in [
+            Message::JoinDHT(JoinDHT { did }),
+            Message::SearchVNode(SearchVNode { vid: did }),
+            Message::custom("Hello".as_bytes()).unwrap(),
+            Message::WatchVNode(WatchVNode { vid: did }),
+        ] {
+            let data = bincode::serialize(&msg).unwrap();
+            assert_eq!(Message::kind_of(&data), Some(msg.kind()));
+        }
+        assert_eq!(Message::kind_of(&[0, 0]), None);
+        assert_eq!(Message::kind_of(&u32::MAX.to_le_bytes()), None);
+    }
+}
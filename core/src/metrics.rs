@@ -0,0 +1,109 @@
+//! This module provide the `MetricsRecorder` trait, an injection point of telemetry.
+//! The crate emits metrics through the recorder, and the host decides where they go,
+//! such as Prometheus, OpenTelemetry or StatsD.
+#![warn(missing_docs)]
+use std::sync::Arc;
+
+/// The number of messages received by swarm.
+pub const MESSAGE_RECEIVED: &str = "rings_message_received";
+/// The number of messages failed on verification.
+pub const MESSAGE_VERIFY_FAILED: &str = "rings_message_verify_failed";
+/// The number of messages dropped for failing on decoding.
+pub const MESSAGE_DROPPED_MALFORMED: &str = "rings_message_dropped_malformed";
+/// The number of messages dropped for being expired.
+pub const MESSAGE_DROPPED_EXPIRED: &str = "rings_message_dropped_expired";
+/// The number of messages dropped for invalid signature.
+pub const MESSAGE_DROPPED_INVALID_SIGNATURE: &str = "rings_message_dropped_invalid_signature";
+/// The number of relayed messages dropped for failing on sending to the next hop.
+pub const MESSAGE_DROPPED_UNREACHABLE: &str = "rings_message_dropped_unreachable";
+/// The number of chunked messages dropped since their sender is over reassembly budget.
+pub const MESSAGE_DROPPED_OVER_BUDGET: &str = "rings_message_dropped_over_budget";
+/// The number of relayed messages dropped since they came back to a node they passed.
+pub const MESSAGE_DROPPED_ROUTING_LOOP: &str = "rings_message_dropped_routing_loop";
+/// The number of backend messages queued for the slowest local receiver of broadcast.
+pub const BACKEND_BROADCAST_LAG: &str = "rings_backend_broadcast_lag";
+/// The number of backend messages overwritten before the slowest local receiver saw them.
+pub const BACKEND_BROADCAST_OVERWRITTEN: &str = "rings_backend_broadcast_overwritten";
+/// The number of backend messages broadcast without any local receiver.
+pub const BACKEND_BROADCAST_UNRECEIVED: &str = "rings_backend_broadcast_unreceived";
+/// Time of executing an extension in milliseconds.
+pub const EXTENSION_EXECUTION_MS: &str = "rings_extension_execution_ms";
+/// The number of executions of extensions timed out.
+pub const EXTENSION_TIMEOUT: &str = "rings_extension_timeout";
+/// The number of executions of extensions rejected for reaching max concurrency.
+pub const EXTENSION_REJECTED: &str = "rings_extension_rejected";
+/// The number of http requests of services answered from response cache.
+pub const HTTP_CACHE_HIT: &str = "rings_http_cache_hit";
+/// The number of cacheable http requests of services sent to upstream.
+pub const HTTP_CACHE_MISS: &str = "rings_http_cache_miss";
+/// Bytes of http responses before gzipped for requesters, see `http_compression` of node.
+pub const HTTP_COMPRESSION_INPUT_BYTES: &str = "rings_http_compression_input_bytes";
+/// Bytes of http responses after gzipped for requesters.
+pub const HTTP_COMPRESSION_OUTPUT_BYTES: &str = "rings_http_compression_output_bytes";
+/// Bytes of payloads before compressing against a dictionary, see
+/// [dictionary](crate::message::dictionary).
+pub const DICT_COMPRESSION_INPUT_BYTES: &str = "rings_dict_compression_input_bytes";
+/// Bytes of payloads after compressing against a dictionary.
+pub const DICT_COMPRESSION_OUTPUT_BYTES: &str = "rings_dict_compression_output_bytes";
+/// The number of messages failed on handling.
+pub const MESSAGE_HANDLE_FAILED: &str = "rings_message_handle_failed";
+/// The number of events waiting in the transport event queue of swarm.
+pub const EVENT_QUEUE_DEPTH: &str = "rings_event_queue_depth";
+/// The number of events dropped due to overflow of the transport event queue.
+pub const EVENT_QUEUE_DROPPED: &str = "rings_event_queue_dropped";
+/// The number of chunks sent by a paced chunked sending.
+pub const CHUNK_SENT: &str = "rings_chunk_sent";
+/// Time waited by chunk pacing before sending a chunk in milliseconds.
+pub const CHUNK_PACING_DELAY_MS: &str = "rings_chunk_pacing_delay_ms";
+/// Time of handling a message in milliseconds.
+pub const MESSAGE_HANDLE_MS: &str = "rings_message_handle_ms";
+/// Time of creating the offer or answer of a handshake in milliseconds, see
+/// [ConnectionTiming](rings_transport::timing::ConnectionTiming).
+pub const HANDSHAKE_OFFER_MS: &str = "rings_handshake_offer_ms";
+/// Time of gathering local candidates of a handshake in milliseconds.
+pub const HANDSHAKE_GATHERING_MS: &str = "rings_handshake_gathering_ms";
+/// Time of waiting for the answer of a handshake in milliseconds.
+pub const HANDSHAKE_ANSWER_EXCHANGE_MS: &str = "rings_handshake_answer_exchange_ms";
+/// Time of ICE connectivity checks of a handshake in milliseconds.
+pub const HANDSHAKE_ICE_CHECK_MS: &str = "rings_handshake_ice_check_ms";
+/// Time of opening the data channel of a handshake in milliseconds.
+pub const HANDSHAKE_DATA_CHANNEL_MS: &str = "rings_handshake_data_channel_ms";
+/// Time of a whole handshake in milliseconds.
+pub const HANDSHAKE_TOTAL_MS: &str = "rings_handshake_total_ms";
+
+/// `MetricsRecorder` receives the metrics emitted by the crate.
+/// All methods are synchronous and should be cheap, since they are called on hot paths.
+pub trait MetricsRecorder {
+    /// Increments the counter of `name` by `value`.
+    fn increment_counter(&self, name: &'static str, value: u64);
+    /// Sets the gauge of `name` to `value`.
+    fn set_gauge(&self, name: &'static str, value: f64);
+    /// Records `value` into the histogram of `name`.
+    fn record_histogram(&self, name: &'static str, value: f64);
+}
+
+/// A recorder that drops everything, used when no recorder is injected.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopRecorder;
+
+impl MetricsRecorder for NoopRecorder {
+    #[inline]
+    fn increment_counter(&self, _name: &'static str, _value: u64) {}
+    #[inline]
+    fn set_gauge(&self, _name: &'static str, _value: f64) {}
+    #[inline]
+    fn record_histogram(&self, _name: &'static str, _value: f64) {}
+}
+
+/// Type of MetricsRecorder, see [MetricsRecorder].
+#[cfg(not(feature = "wasm"))]
+pub type MetricsImpl = Arc<dyn MetricsRecorder + Send + Sync>;
+
+/// Type of MetricsRecorder, see [MetricsRecorder].
+#[cfg(feature = "wasm")]
+pub type MetricsImpl = Arc<dyn MetricsRecorder>;
+
+/// Create a [MetricsImpl] of [NoopRecorder].
+pub fn noop_recorder() -> MetricsImpl {
+    Arc::new(NoopRecorder)
+}
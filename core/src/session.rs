@@ -269,6 +269,16 @@ impl Session {
         Ok(())
     }
 
+    /// Recover session public key from a message signed by session,
+    /// fails if the message is not signed by the key of session.
+    pub fn session_pubkey(&self, msg: &[u8], sig: impl AsRef<[u8]>) -> Result<PublicKey> {
+        let pubkey = signers::secp256k1::recover(msg, sig)?;
+        if Did::from(pubkey.address()) != self.session_id {
+            return Err(Error::VerifySignatureFailed);
+        }
+        Ok(pubkey)
+    }
+
     /// Get public key from session for encryption.
     pub fn account_pubkey(&self) -> Result<PublicKey> {
         let auth_bytes = self.pack();
@@ -289,6 +299,16 @@ impl Session {
             Account::Ed25519(pk) => pk.address().into(),
         }
     }
+
+    /// Get did of session, which is the address of session public key.
+    pub fn session_id(&self) -> Did {
+        self.session_id
+    }
+
+    /// Get timestamp in milliseconds when session was created.
+    pub fn created_at_ms(&self) -> u128 {
+        self.ts_ms
+    }
 }
 
 impl SessionSk {
@@ -323,6 +343,27 @@ impl SessionSk {
         self.session.account_did()
     }
 
+    /// Get public key of session, which peers use to encrypt data to this session.
+    pub fn pubkey(&self) -> PublicKey {
+        self.sk.pubkey()
+    }
+
+    /// Decrypt data encrypted to the public key of session.
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        ecies::decrypt(&self.sk.ser(), data).map_err(Error::MessageDecryptionFailed)
+    }
+
+    /// Derive the key of AES shared with the ephemeral public key of ECIES data encrypted to
+    /// the public key of session, see [SessionKeys](crate::swarm::SessionKeys).
+    pub(crate) fn shared_key(&self, ephemeral_pubkey: &[u8]) -> Result<[u8; 32]> {
+        let ephemeral_pubkey = ecies::PublicKey::parse_slice(ephemeral_pubkey, None)
+            .map_err(Error::MessageDecryptionFailed)?;
+        let sk: libsecp256k1::SecretKey = self.sk.into();
+        ecies::utils::decapsulate(&ephemeral_pubkey, &sk)
+            .map(Into::into)
+            .map_err(Error::MessageDecryptionFailed)
+    }
+
     /// Dump session_sk to string, allowing user to save it in a config file.
     /// It can be restored using `SessionSk::from_str`.
     pub fn dump(&self) -> Result<String> {
@@ -0,0 +1,251 @@
+#![warn(missing_docs)]
+//! This module provides [Authorizer], an integration point for external policy backends,
+//! such as OIDC, LDAP or a REST endpoint, deciding whether a peer may connect or use a service.
+//! Decisions can be cached by [CachedAuthorizer], so that the backend is not asked on every message.
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::dht::Did;
+use crate::swarm::gate::ConnectionGate;
+use crate::swarm::gate::DidListGate;
+use crate::utils::get_epoch_ms;
+
+/// An action of peer to be authorized.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AuthAction {
+    /// Connect to current node.
+    Connect,
+    /// Use a service hosted by current node, by name of service.
+    Service(String),
+    /// Inspect the view of DHT of current node, see
+    /// [routing](crate::message::handlers::routing).
+    InspectRouting,
+}
+
+/// Decision of [Authorizer].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Decision {
+    /// The action is allowed.
+    Allow,
+    /// The action is denied, with reason.
+    Deny(String),
+}
+
+/// An authorizer deciding whether a peer may take an action.
+/// It's consulted after the [ConnectionGate] on connecting, and on using services.
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+pub trait Authorizer {
+    /// Decide whether `did` may take `action`.
+    async fn authorize(&self, did: Did, action: &AuthAction) -> Decision;
+}
+
+/// Type of Authorizer, see [Authorizer].
+#[cfg(not(feature = "wasm"))]
+pub type AuthorizerImpl = Box<dyn Authorizer + Send + Sync>;
+
+/// Type of Authorizer, see [Authorizer].
+#[cfg(feature = "wasm")]
+pub type AuthorizerImpl = Box<dyn Authorizer>;
+
+/// The static allow and deny lists, applied to all actions.
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl Authorizer for DidListGate {
+    async fn authorize(&self, did: Did, _action: &AuthAction) -> Decision {
+        match self.check(did) {
+            Ok(()) => Decision::Allow,
+            Err(reason) => Decision::Deny(reason),
+        }
+    }
+}
+
+/// Default max number of decisions cached by [CachedAuthorizer].
+pub const AUTH_CACHE_MAX_ENTRIES: usize = 4096;
+
+/// A decision cached by [CachedAuthorizer].
+struct CachedDecision {
+    decision: Decision,
+    expires_at: u128,
+    used_at: u128,
+}
+
+/// An authorizer caching decisions of another one for `ttl_ms` milliseconds.
+/// At most `max_entries` decisions are cached, expired ones are dropped first,
+/// then the least recently used, so that peers can't grow the cache unbounded.
+pub struct CachedAuthorizer {
+    inner: AuthorizerImpl,
+    ttl_ms: u128,
+    max_entries: usize,
+    cache: Mutex<HashMap<(Did, AuthAction), CachedDecision>>,
+}
+
+impl CachedAuthorizer {
+    /// Cache decisions of `inner` for `ttl_ms` milliseconds, at most
+    /// [AUTH_CACHE_MAX_ENTRIES] of them.
+    pub fn new(inner: AuthorizerImpl, ttl_ms: u64) -> Self {
+        Self {
+            inner,
+            ttl_ms: ttl_ms.into(),
+            max_entries: AUTH_CACHE_MAX_ENTRIES,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Set the max number of cached decisions.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries.max(1);
+        self
+    }
+
+    /// Forget cached decisions, such as after the policy is changed.
+    pub fn clear(&self) {
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.clear()
+        }
+    }
+
+    /// Number of cached decisions, including expired ones not dropped yet.
+    pub fn len(&self) -> usize {
+        self.cache.lock().map(|c| c.len()).unwrap_or_default()
+    }
+
+    /// Check if no decision is cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get(&self, key: &(Did, AuthAction), now: u128) -> Option<Decision> {
+        let mut cache = self.cache.lock().ok()?;
+        let entry = cache.get_mut(key)?;
+        if now >= entry.expires_at {
+            cache.remove(key);
+            return None;
+        }
+        entry.used_at = now;
+        Some(entry.decision.clone())
+    }
+
+    fn insert(&self, key: (Did, AuthAction), decision: Decision, now: u128) {
+        let Ok(mut cache) = self.cache.lock() else {
+            return;
+        };
+        if cache.len() >= self.max_entries && !cache.contains_key(&key) {
+            cache.retain(|_, entry| now < entry.expires_at);
+        }
+        if cache.len() >= self.max_entries && !cache.contains_key(&key) {
+            let lru = cache
+                .iter()
+                .min_by_key(|(_, entry)| entry.used_at)
+                .map(|(key, _)| key.clone());
+            if let Some(lru) = lru {
+                cache.remove(&lru);
+            }
+        }
+        cache.insert(key, CachedDecision {
+            decision,
+            expires_at: now + self.ttl_ms,
+            used_at: now,
+        });
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl Authorizer for CachedAuthorizer {
+    async fn authorize(&self, did: Did, action: &AuthAction) -> Decision {
+        let key = (did, action.clone());
+        if let Some(decision) = self.get(&key, get_epoch_ms()) {
+            return decision;
+        }
+        let decision = self.inner.authorize(did, action).await;
+        self.insert(key, decision.clone(), get_epoch_ms());
+        decision
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    use super::*;
+
+    struct Counting(Arc<AtomicUsize>);
+
+    #[async_trait]
+    impl Authorizer for Counting {
+        async fn authorize(&self, _did: Did, action: &AuthAction) -> Decision {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            match action {
+                AuthAction::Connect => Decision::Allow,
+                AuthAction::Service(name) => Decision::Deny(format!("{name} is not allowed")),
+                AuthAction::InspectRouting => Decision::Allow,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cached_authorizer() {
+        let a = Did::from_str("0x11E807fcc88dD319270493fB2e822e388Fe36ab0").unwrap();
+        let b = Did::from_str("0x999999cf1046e68e36E1aA2E0E07105eDDD1f08E").unwrap();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let auth = CachedAuthorizer::new(Box::new(Counting(calls.clone())), 100);
+        let service = AuthAction::Service("ipfs".to_string());
+
+        assert_eq!(
+            auth.authorize(a, &AuthAction::Connect).await,
+            Decision::Allow
+        );
+        assert_eq!(
+            auth.authorize(a, &AuthAction::Connect).await,
+            Decision::Allow
+        );
+        assert!(matches!(
+            auth.authorize(a, &service).await,
+            Decision::Deny(_)
+        ));
+        assert_eq!(
+            auth.authorize(b, &AuthAction::Connect).await,
+            Decision::Allow
+        );
+        // Decisions are cached by peer and action.
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        tokio::time::sleep(std::time::Duration::from_millis(150)).await;
+        auth.authorize(a, &AuthAction::Connect).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+        // The least recently used decision is dropped when the cache is full.
+        let c = Did::from_str("0xccffee254729296a45a3885639AC7E10F9d54979").unwrap();
+        let auth =
+            CachedAuthorizer::new(Box::new(Counting(calls.clone())), 60_000).with_max_entries(2);
+        calls.store(0, Ordering::SeqCst);
+        auth.authorize(a, &AuthAction::Connect).await;
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        auth.authorize(b, &AuthAction::Connect).await;
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        auth.authorize(a, &AuthAction::Connect).await;
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        auth.authorize(c, &AuthAction::Connect).await;
+        assert_eq!(auth.len(), 2);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        auth.authorize(a, &AuthAction::Connect).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        auth.authorize(b, &AuthAction::Connect).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+        let list = DidListGate::default().deny([b]);
+        assert_eq!(list.authorize(a, &service).await, Decision::Allow);
+        assert!(matches!(
+            list.authorize(b, &service).await,
+            Decision::Deny(_)
+        ));
+    }
+}
@@ -5,18 +5,60 @@
 use std::sync::Arc;
 use std::sync::RwLock;
 
+use rings_transport::backpressure::BufferWatermark;
+use rings_transport::ice_gathering::IceGathering;
+use rings_transport::ice_gathering::IceGatheringMode;
+
 use crate::channels::Channel;
+use crate::dht::Did;
 use crate::dht::PeerRing;
+use crate::message::handlers::membership::ClosedNetwork;
+use crate::message::handlers::store_limit::StoreLimitConfig;
+use crate::message::handlers::store_limit::StoreLimiter;
 use crate::message::CallbackFn;
+use crate::message::CompressionDictionaries;
+use crate::message::CompressionDictionary;
+use crate::message::MessageFilter;
 use crate::message::MessageHandler;
 use crate::message::ValidatorFn;
+use crate::metrics::noop_recorder;
+use crate::metrics::MetricsImpl;
 use crate::session::SessionSk;
 use crate::storage::PersistenceStorage;
 use crate::swarm::callback::SharedSwarmCallback;
 use crate::swarm::callback::SwarmCallback;
+use crate::swarm::drop_log::DropLog;
+use crate::swarm::AuthorizerImpl;
+use crate::swarm::ClosestRelay;
+use crate::swarm::ConnectionGateImpl;
+use crate::swarm::Convergence;
+use crate::swarm::DropLogConfig;
+use crate::swarm::ExpiryPolicy;
+use crate::swarm::IpLimitConfig;
+use crate::swarm::IpLimiter;
+use crate::swarm::KeyRotation;
 use crate::swarm::MeasureImpl;
+use crate::swarm::MtuBounds;
+use crate::swarm::NatDiscoveryImpl;
+use crate::swarm::NetworkProfile;
+use crate::swarm::ObservedRelays;
+use crate::swarm::PayloadPipeline;
+use crate::swarm::PeerKeys;
+use crate::swarm::PeerMtus;
+use crate::swarm::PeerScores;
+use crate::swarm::PinnedPeers;
+use crate::swarm::PreferDirect;
+use crate::swarm::RelaySelectorImpl;
+use crate::swarm::RetryBudget;
+use crate::swarm::SendPathSelectorImpl;
+use crate::swarm::SessionKeys;
+use crate::swarm::Streams;
+use crate::swarm::StunDiscovery;
 use crate::swarm::Swarm;
+use crate::swarm::TrustedTransports;
+use crate::traffic::TrafficMeter;
 use crate::types::channel::Channel as ChannelTrait;
+use crate::types::channel::OverflowPolicy;
 use crate::types::Transport;
 
 struct DefaultCallback;
@@ -26,14 +68,43 @@ impl SwarmCallback for DefaultCallback {}
 pub struct SwarmBuilder {
     ice_servers: String,
     external_address: Option<String>,
+    #[cfg(feature = "websocket")]
+    relay_url: Option<String>,
     dht_succ_max: u8,
     dht_storage: PersistenceStorage,
     session_sk: SessionSk,
     session_ttl: Option<usize>,
     measure: Option<MeasureImpl>,
+    metrics: Option<MetricsImpl>,
     message_callback: Option<CallbackFn>,
     message_validator: Option<ValidatorFn>,
     callback: Option<SharedSwarmCallback>,
+    event_queue: Option<(usize, OverflowPolicy)>,
+    connection_gate: Option<ConnectionGateImpl>,
+    ip_limit: Option<IpLimitConfig>,
+    authorizer: Option<AuthorizerImpl>,
+    lookup_retry: Option<RetryBudget>,
+    drop_log: DropLogConfig,
+    buffer_watermark: Option<BufferWatermark>,
+    ice_gathering_mode: IceGatheringMode,
+    ice_gathering_timeout_ms: Option<u64>,
+    network_profile: NetworkProfile,
+    nat_discovery: Option<NatDiscoveryImpl>,
+    send_timeout_ms: Option<u64>,
+    mtu_bounds: MtuBounds,
+    relay_selector: Option<RelaySelectorImpl>,
+    path_selector: Option<SendPathSelectorImpl>,
+    compact_payload: bool,
+    compress_relay_path: bool,
+    no_dht: bool,
+    closed_network: Option<ClosedNetwork>,
+    store_limit: Option<StoreLimitConfig>,
+    message_filter: MessageFilter,
+    expiry_policy: ExpiryPolicy,
+    key_rotation: KeyRotation,
+    announcement: Option<Vec<String>>,
+    dictionaries: CompressionDictionaries,
+    payload_pipeline: PayloadPipeline,
 }
 
 impl SwarmBuilder {
@@ -42,14 +113,43 @@ impl SwarmBuilder {
         SwarmBuilder {
             ice_servers: ice_servers.to_string(),
             external_address: None,
+            #[cfg(feature = "websocket")]
+            relay_url: None,
             dht_succ_max: 3,
             dht_storage,
             session_sk,
             session_ttl: None,
             measure: None,
+            metrics: None,
             message_callback: None,
             message_validator: None,
             callback: None,
+            event_queue: None,
+            connection_gate: None,
+            ip_limit: None,
+            authorizer: None,
+            lookup_retry: None,
+            drop_log: DropLogConfig::default(),
+            buffer_watermark: None,
+            ice_gathering_mode: IceGatheringMode::default(),
+            ice_gathering_timeout_ms: None,
+            network_profile: NetworkProfile::default(),
+            nat_discovery: None,
+            send_timeout_ms: None,
+            mtu_bounds: MtuBounds::default(),
+            relay_selector: None,
+            path_selector: None,
+            compact_payload: false,
+            compress_relay_path: false,
+            no_dht: false,
+            closed_network: None,
+            store_limit: None,
+            message_filter: MessageFilter::default(),
+            expiry_policy: ExpiryPolicy::default(),
+            key_rotation: KeyRotation::default(),
+            announcement: None,
+            dictionaries: CompressionDictionaries::default(),
+            payload_pipeline: PayloadPipeline::default(),
         }
     }
 
@@ -66,6 +166,16 @@ impl SwarmBuilder {
         self
     }
 
+    /// Sets up the url of WebSocket relay, such as `wss://relay.example.com`. A connection
+    /// falls back to the relay once WebRTC with the same peer failed, or the peer is set by
+    /// [Swarm::set_relayed](crate::swarm::Swarm::set_relayed), and offers over the relay are
+    /// answered. Connections are never relayed by default.
+    #[cfg(feature = "websocket")]
+    pub fn relay_url(mut self, relay_url: String) -> Self {
+        self.relay_url = Some(relay_url);
+        self
+    }
+
     /// Setup timeout for session.
     pub fn session_ttl(mut self, ttl: usize) -> Self {
         self.session_ttl = Some(ttl);
@@ -78,6 +188,13 @@ impl SwarmBuilder {
         self
     }
 
+    /// Bind metrics recorder for Swarm.
+    /// Metrics are dropped by a no-op recorder if not set.
+    pub fn metrics(mut self, recorder: MetricsImpl) -> Self {
+        self.metrics = Some(recorder);
+        self
+    }
+
     /// Bind message callback function for Swarm.
     pub fn message_callback(mut self, callback: CallbackFn) -> Self {
         self.message_callback = Some(callback);
@@ -96,8 +213,239 @@ impl SwarmBuilder {
         self
     }
 
+    /// Bound the queue of transport events, which is unbounded by default.
+    /// When the queue holds `capacity` events, new events are handled by `policy`.
+    /// The capacity should be greater than zero.
+    pub fn event_queue(mut self, capacity: usize, policy: OverflowPolicy) -> Self {
+        self.event_queue = Some((capacity.max(1), policy));
+        self
+    }
+
+    /// Bind connection gate for Swarm, which is consulted before creating any connection.
+    pub fn connection_gate(mut self, gate: ConnectionGateImpl) -> Self {
+        self.connection_gate = Some(gate);
+        self
+    }
+
+    /// Rate-limit and cap pending transports by source IP, see [IpLimiter].
+    /// Transports are not limited by IP by default.
+    pub fn ip_limit(mut self, config: IpLimitConfig) -> Self {
+        self.ip_limit = Some(config);
+        self
+    }
+
+    /// Bind authorizer for Swarm, such as a client of an external policy backend. It's consulted
+    /// after the connection gate before creating any connection, and by services through
+    /// [Swarm::authorize]. Wrap it by [CachedAuthorizer](crate::swarm::CachedAuthorizer)
+    /// to avoid asking the backend on every message. Only the connection gate applies if not set.
+    pub fn authorizer(mut self, authorizer: AuthorizerImpl) -> Self {
+        self.authorizer = Some(authorizer);
+        self
+    }
+
+    /// Sets up the retry budget of DHT lookups, see [RetryBudget].
+    pub fn lookup_retry(mut self, budget: RetryBudget) -> Self {
+        self.lookup_retry = Some(budget);
+        self
+    }
+
+    /// Sets up the sampled logging of dropped messages, see [DropLogConfig].
+    pub fn drop_log(mut self, config: DropLogConfig) -> Self {
+        self.drop_log = config;
+        self
+    }
+
+    /// Sets up the watermarks of buffered amount of transport data channels, see [BufferWatermark].
+    pub fn buffer_watermark(mut self, watermark: BufferWatermark) -> Self {
+        self.buffer_watermark = Some(watermark);
+        self
+    }
+
+    /// Sets up the mode of ICE gathering, candidates are trickled by default. Trickling
+    /// connects faster, and waiting for all candidates sends fewer signaling messages, see
+    /// [ice_gathering](rings_transport::ice_gathering).
+    pub fn ice_gathering_mode(mut self, mode: IceGatheringMode) -> Self {
+        self.ice_gathering_mode = mode;
+        self
+    }
+
+    /// Sets up the max time waiting for all local ICE candidates to be gathered, in
+    /// milliseconds. A description waiting longer is given out with the candidates gathered
+    /// so far. It's given by [NetworkProfile] if not set, which is 3 seconds by default.
+    pub fn ice_gathering_timeout(mut self, timeout_ms: u64) -> Self {
+        self.ice_gathering_timeout_ms = Some(timeout_ms);
+        self
+    }
+
+    /// Sets up defaults of lookup retry, buffer watermarks, send timeout and ICE gathering
+    /// timeout by a [NetworkProfile]. Settings given by [SwarmBuilder::lookup_retry],
+    /// [SwarmBuilder::buffer_watermark], [SwarmBuilder::send_timeout] and
+    /// [SwarmBuilder::ice_gathering_timeout] take precedence, no matter in which order they
+    /// are called.
+    pub fn network_profile(mut self, profile: NetworkProfile) -> Self {
+        self.network_profile = profile;
+        self
+    }
+
+    /// Bind discovery of external address for Swarm, which runs when swarm starts listening.
+    /// Defaults to [StunDiscovery], which relies on STUN servers of transport only.
+    pub fn nat_discovery(mut self, discovery: NatDiscoveryImpl) -> Self {
+        self.nat_discovery = Some(discovery);
+        self
+    }
+
+    /// Sets up the max time in milliseconds for a sending to hand off a message to transport,
+    /// which is given by [NetworkProfile] if not set,
+    /// [DEFAULT_SEND_TIMEOUT_MS](crate::swarm::DEFAULT_SEND_TIMEOUT_MS) by default.
+    /// Unlike the ttl of message, which bounds its validity in network, this bounds how long
+    /// a local send call may block, such as on backpressure of data channel. The timeout
+    /// should be greater than zero.
+    pub fn send_timeout(mut self, ms: u64) -> Self {
+        self.send_timeout_ms = Some(ms.max(1));
+        self
+    }
+
+    /// Sets up the range which chunk size of each peer adapts in, see [PeerMtus]. Chunks
+    /// shrink towards the floor when sending fails, and grow towards the ceiling after
+    /// sustained success.
+    pub fn mtu_bounds(mut self, bounds: MtuBounds) -> Self {
+        self.mtu_bounds = bounds;
+        self
+    }
+
+    /// Bind relay selector for Swarm, which chooses the connected peer relaying a message
+    /// when the next hop inferred by DHT is not connected. Defaults to [ClosestRelay],
+    /// while [WeightedRandomRelay](crate::swarm::WeightedRandomRelay) spreads load over relays.
+    pub fn relay_selector(mut self, selector: RelaySelectorImpl) -> Self {
+        self.relay_selector = Some(selector);
+        self
+    }
+
+    /// Bind send path selector for Swarm, which chooses the next hop of a message when more
+    /// than one path exists, see [SendPathSelector](crate::swarm::SendPathSelector).
+    /// Defaults to [PreferDirect], while [LowestLatency](crate::swarm::LowestLatency)
+    /// favors the path of the fastest peer.
+    pub fn path_selector(mut self, selector: SendPathSelectorImpl) -> Self {
+        self.path_selector = Some(selector);
+        self
+    }
+
+    /// Send payloads in compact encoding, see
+    /// [MessagePayload::to_compact](crate::message::MessagePayload::to_compact).
+    /// Payloads in both encodings are always accepted, so it should be enabled once all peers
+    /// of the network support the compact encoding. It's disabled by default.
+    pub fn compact_payload(mut self) -> Self {
+        self.compact_payload = true;
+        self
+    }
+
+    /// Send payloads in compact encoding with compressed relay path, see
+    /// [MessagePayload::to_compact_path_compressed](crate::message::MessagePayload::to_compact_path_compressed).
+    /// It saves about half of the bytes of path on long routes, and takes precedence over
+    /// [SwarmBuilder::compact_payload]. Like it, enable it once all peers support it.
+    pub fn compress_relay_path(mut self) -> Self {
+        self.compress_relay_path = true;
+        self
+    }
+
+    /// Run pure point-to-point without DHT participation. Stabilization and finger maintenance
+    /// are skipped, DHT control messages are rejected, and messages are only delivered to
+    /// directly connected peers. DHT participation is enabled by default.
+    pub fn no_dht(mut self) -> Self {
+        self.no_dht = true;
+        self
+    }
+
+    /// Only handle messages originated by members, which are seeds or nodes in the view of
+    /// DHT, see [membership](crate::message::handlers::membership). Open by default.
+    pub fn closed_network(mut self, seeds: impl IntoIterator<Item = Did>) -> Self {
+        self.closed_network = Some(ClosedNetwork::new(seeds));
+        self
+    }
+
+    /// Rate-limit storing virtual nodes by origin, scaled by scores of peers, see
+    /// [store_limit](crate::message::handlers::store_limit). Unlimited by default.
+    pub fn store_limit(mut self, config: StoreLimitConfig) -> Self {
+        self.store_limit = Some(config);
+        self
+    }
+
+    /// Accept only the types of message passing filter, see
+    /// [MessageHandler::with_filter]. Every type is accepted by default.
+    pub fn message_filter(mut self, filter: MessageFilter) -> Self {
+        self.message_filter = filter;
+        self
+    }
+
+    /// Process messages of tolerant types even if they are expired in transit, within a grace
+    /// window, see [ExpiryPolicy]. Every type is strict by default.
+    pub fn expiry_policy(mut self, policy: ExpiryPolicy) -> Self {
+        self.expiry_policy = policy;
+        self
+    }
+
+    /// Rotate the key of encryption to each peer by policy, see [SessionKeys].
+    /// It's rotated after 1000 messages or 10 minutes by default, and the ephemeral key
+    /// offered to peers is rotated by the same period.
+    pub fn key_rotation(mut self, rotation: KeyRotation) -> Self {
+        self.key_rotation = rotation;
+        self
+    }
+
+    /// Announce self with capabilities to the network periodically, see
+    /// [announce](crate::message::handlers::announce). Not announced by default.
+    pub fn announce(mut self, capabilities: Vec<String>) -> Self {
+        self.announcement = Some(capabilities);
+        self
+    }
+
+    /// Compress payloads against a shared dictionary, for peers offering the same one,
+    /// see [dictionary](crate::message::dictionary). It can be called repeatedly to know
+    /// several versions of dictionary. Not compressed by default.
+    pub fn compression_dictionary(self, dictionary: CompressionDictionary) -> Self {
+        self.dictionaries.insert(dictionary);
+        self
+    }
+
+    /// Transform data of services sent to peers by a pipeline, such as compression and
+    /// encryption, see [transform](crate::swarm::transform). Sent as is by default.
+    pub fn payload_pipeline(mut self, pipeline: PayloadPipeline) -> Self {
+        self.payload_pipeline = pipeline;
+        self
+    }
+
+    /// Retry budget of lookups, given explicitly or by the profile.
+    fn lookup_retry_or_profile(&self) -> RetryBudget {
+        self.lookup_retry
+            .unwrap_or_else(|| self.network_profile.lookup_retry())
+    }
+
+    /// Watermarks of data channels, given explicitly or by the profile.
+    fn buffer_watermark_or_profile(&self) -> BufferWatermark {
+        self.buffer_watermark
+            .unwrap_or_else(|| self.network_profile.buffer_watermark())
+    }
+
+    /// ICE gathering, whose timeout is given explicitly or by the profile.
+    fn ice_gathering_or_profile(&self) -> IceGathering {
+        let timeout_ms = self
+            .ice_gathering_timeout_ms
+            .unwrap_or_else(|| self.network_profile.ice_gathering_timeout_ms());
+        IceGathering::new(self.ice_gathering_mode, timeout_ms)
+    }
+
+    /// Send timeout, given explicitly or by the profile.
+    fn send_timeout_or_profile(&self) -> u64 {
+        self.send_timeout_ms
+            .unwrap_or_else(|| self.network_profile.send_timeout_ms())
+    }
+
     /// Try build for `Swarm`.
     pub fn build(self) -> Swarm {
+        let lookup_retry = self.lookup_retry_or_profile();
+        let buffer_watermark = self.buffer_watermark_or_profile();
+        let ice_gathering = self.ice_gathering_or_profile();
+        let send_timeout_ms = self.send_timeout_or_profile();
         let dht_did = self.session_sk.account_did();
 
         let dht = Arc::new(PeerRing::new_with_storage(
@@ -106,25 +454,195 @@ impl SwarmBuilder {
             self.dht_storage,
         ));
 
-        let message_handler =
-            MessageHandler::new(dht.clone(), self.message_callback, self.message_validator);
+        let peer_scores = PeerScores::default();
+        let session_keys = SessionKeys::new(self.key_rotation);
+        let mut message_handler =
+            MessageHandler::new(dht.clone(), self.message_callback, self.message_validator)
+                .with_filter(self.message_filter)
+                .with_dictionaries(self.dictionaries.clone())
+                .with_session_keys(session_keys.clone());
+        if self.no_dht {
+            message_handler = message_handler.no_dht();
+        }
+        if let Some(network) = self.closed_network {
+            message_handler = message_handler.with_closed_network(network);
+        }
+        if let Some(config) = self.store_limit {
+            message_handler =
+                message_handler.with_store_limit(StoreLimiter::new(config, peer_scores.clone()));
+        }
 
-        let transport_event_channel = Channel::new();
-        let transport = Box::new(Transport::new(&self.ice_servers, self.external_address));
+        let transport_event_channel = match self.event_queue {
+            Some((capacity, policy)) => Channel::bounded(capacity, policy),
+            None => Channel::new(),
+        };
+        #[cfg(all(feature = "websocket", not(feature = "dummy")))]
+        let transport = Transport::new(
+            &self.ice_servers,
+            self.external_address,
+            self.relay_url.as_deref(),
+        );
+        #[cfg(not(all(feature = "websocket", not(feature = "dummy"))))]
+        let transport = Transport::new(&self.ice_servers, self.external_address);
+        let transport = Box::new(
+            transport
+                .with_buffer_watermark(buffer_watermark)
+                .with_ice_gathering(ice_gathering),
+        );
 
         let callback = RwLock::new(
             self.callback
                 .unwrap_or_else(|| Arc::new(DefaultCallback {})),
         );
 
+        let metrics = self.metrics.unwrap_or_else(noop_recorder);
+        let drop_log = DropLog::new(self.drop_log, metrics.clone())
+            .with_deadletter(message_handler.deadletter());
+
         Swarm {
             transport_event_channel,
             dht,
             measure: self.measure,
+            metrics,
             session_sk: self.session_sk,
             message_handler,
             transport,
             callback,
+            trusted_transports: TrustedTransports::default(),
+            pinned_peers: PinnedPeers::default(),
+            connection_gate: self.connection_gate,
+            ip_limiter: self.ip_limit.map(IpLimiter::new),
+            authorizer: self.authorizer,
+            lookup_retry,
+            nat_discovery: self
+                .nat_discovery
+                .unwrap_or_else(|| Box::new(StunDiscovery)),
+            send_timeout_ms,
+            relay_selector: self
+                .relay_selector
+                .unwrap_or_else(|| Box::new(ClosestRelay)),
+            path_selector: self.path_selector.unwrap_or_else(|| Box::new(PreferDirect)),
+            compact_payload: self.compact_payload,
+            compress_relay_path: self.compress_relay_path,
+            peer_scores,
+            peer_keys: PeerKeys::default(),
+            observed_relays: ObservedRelays::default(),
+            session_keys,
+            peer_mtus: PeerMtus::new(self.mtu_bounds),
+            streams: Streams::default(),
+            traffic: TrafficMeter::default(),
+            send_queues: Default::default(),
+            scheduled_retries: Default::default(),
+            drop_log,
+            announcement: self.announcement,
+            dictionaries: self.dictionaries,
+            payload_pipeline: self.payload_pipeline,
+            listen_heartbeat: Default::default(),
+            pending_candidates: Default::default(),
+            trickle_peers: Default::default(),
+            last_announced_ms: Default::default(),
+            expiry_policy: self.expiry_policy,
+            no_dht: self.no_dht,
+            // Without DHT participation, there is nothing to converge.
+            convergence: Convergence::new(self.no_dht),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ecc::SecretKey;
+
+    async fn new_builder() -> (SwarmBuilder, String) {
+        let path = PersistenceStorage::random_path("./tmp");
+        let storage = PersistenceStorage::new_with_path(path.as_str())
+            .await
+            .unwrap();
+        let session_sk = SessionSk::new_with_seckey(&SecretKey::random()).unwrap();
+        let builder = SwarmBuilder::new("stun://stun.l.google.com:19302", storage, session_sk);
+        (builder, path)
+    }
+
+    #[tokio::test]
+    async fn test_network_profile_fills_settings() {
+        for profile in [
+            NetworkProfile::Balanced,
+            NetworkProfile::LowLatency,
+            NetworkProfile::Mobile,
+            NetworkProfile::HighThroughput,
+            NetworkProfile::Lossy,
+        ] {
+            let (builder, path) = new_builder().await;
+            let builder = builder.network_profile(profile);
+            assert_eq!(builder.lookup_retry_or_profile(), profile.lookup_retry());
+            assert_eq!(
+                builder.buffer_watermark_or_profile(),
+                profile.buffer_watermark()
+            );
+            assert_eq!(builder.send_timeout_or_profile(), profile.send_timeout_ms());
+            assert_eq!(
+                builder.ice_gathering_or_profile().timeout_ms,
+                profile.ice_gathering_timeout_ms()
+            );
+
+            let swarm = builder.build();
+            assert_eq!(swarm.lookup_retry, profile.lookup_retry());
+            assert_eq!(swarm.send_timeout_ms, profile.send_timeout_ms());
+            tokio::fs::remove_dir_all(path).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_explicit_settings_beat_network_profile() {
+        let budget = RetryBudget::new(4, 10);
+        let watermark = BufferWatermark::new(8 * 1024, 1024);
+
+        // Explicit settings win whether they are given before or after the profile.
+        let (builder, path) = new_builder().await;
+        let before = builder
+            .lookup_retry(budget)
+            .buffer_watermark(watermark)
+            .send_timeout(1234)
+            .ice_gathering_timeout(567)
+            .ice_gathering_mode(IceGatheringMode::Full)
+            .network_profile(NetworkProfile::Mobile);
+        let (builder, path2) = new_builder().await;
+        let after = builder
+            .network_profile(NetworkProfile::Mobile)
+            .lookup_retry(budget)
+            .buffer_watermark(watermark)
+            .send_timeout(1234)
+            .ice_gathering_timeout(567)
+            .ice_gathering_mode(IceGatheringMode::Full);
+
+        for builder in [before, after] {
+            assert_eq!(builder.lookup_retry_or_profile(), budget);
+            assert_eq!(builder.buffer_watermark_or_profile(), watermark);
+            assert_eq!(builder.send_timeout_or_profile(), 1234);
+            assert_eq!(
+                builder.ice_gathering_or_profile(),
+                IceGathering::new(IceGatheringMode::Full, 567)
+            );
+        }
+
+        // Settings not given explicitly are still filled by the profile.
+        let (builder, path3) = new_builder().await;
+        let builder = builder
+            .network_profile(NetworkProfile::Lossy)
+            .send_timeout(1234);
+        assert_eq!(builder.send_timeout_or_profile(), 1234);
+        assert_eq!(
+            builder.lookup_retry_or_profile(),
+            NetworkProfile::Lossy.lookup_retry()
+        );
+        assert_eq!(
+            builder.ice_gathering_or_profile().timeout_ms,
+            NetworkProfile::Lossy.ice_gathering_timeout_ms()
+        );
+
+        for path in [path, path2, path3] {
+            tokio::fs::remove_dir_all(path).await.unwrap();
         }
     }
 }
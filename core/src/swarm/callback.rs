@@ -7,8 +7,22 @@ use rings_transport::core::transport::WebrtcConnectionState;
 
 use crate::channels::Channel;
 use crate::dht::Did;
+use crate::error::Error;
+use crate::message::CompressionDictionaries;
+use crate::message::Message;
 use crate::message::MessagePayload;
 use crate::message::MessageVerificationExt;
+use crate::swarm::drop_log::DropLog;
+use crate::swarm::expiry::ExpiryPolicy;
+use crate::swarm::stream::STREAM_FRAME_TAG;
+use crate::swarm::DropReason;
+use crate::swarm::ObservedRelays;
+use crate::swarm::PeerKeys;
+use crate::swarm::PeerScores;
+use crate::swarm::PeerSignal;
+use crate::swarm::Streams;
+use crate::swarm::TrustedTransports;
+use crate::traffic::TrafficMeter;
 use crate::types::channel::Channel as ChannelTrait;
 use crate::types::channel::TransportEvent;
 
@@ -27,6 +41,22 @@ pub enum SwarmEvent {
         /// The final state of the connection.
         state: WebrtcConnectionState,
     },
+    /// Indicates that a connection is rejected by [ConnectionGate](crate::swarm::gate::ConnectionGate)
+    /// before being created.
+    ConnectionRejected {
+        /// The did of remote peer.
+        did: Did,
+        /// The reason given by the gate.
+        reason: String,
+    },
+    /// Indicates that initial convergence of DHT is completed, and DHT maintenance
+    /// is no longer prioritized, see [Convergence](crate::swarm::Convergence).
+    InitialConvergence {
+        /// Time taken by convergence in milliseconds.
+        elapsed_ms: u128,
+        /// True if the node has not found its successor and predecessor in time.
+        timed_out: bool,
+    },
 }
 
 /// Any object that implements this trait can be used as a callback for the swarm.
@@ -52,6 +82,16 @@ pub trait SwarmCallback {
 pub(crate) struct InnerSwarmCallback {
     transport_event_sender: TransportEventSender,
     callback: SharedSwarmCallback,
+    trusted_transports: TrustedTransports,
+    relayed: bool,
+    traffic: TrafficMeter<Did>,
+    drop_log: Option<DropLog>,
+    expiry_policy: ExpiryPolicy,
+    peer_scores: PeerScores,
+    peer_keys: PeerKeys,
+    observed_relays: ObservedRelays,
+    streams: Streams,
+    dictionaries: CompressionDictionaries,
 }
 
 impl InnerSwarmCallback {
@@ -62,18 +102,177 @@ impl InnerSwarmCallback {
         Self {
             transport_event_sender,
             callback,
+            trusted_transports: TrustedTransports::default(),
+            relayed: false,
+            traffic: TrafficMeter::default(),
+            drop_log: None,
+            expiry_policy: ExpiryPolicy::default(),
+            peer_scores: PeerScores::default(),
+            peer_keys: PeerKeys::default(),
+            observed_relays: ObservedRelays::default(),
+            streams: Streams::default(),
+            dictionaries: CompressionDictionaries::default(),
+        }
+    }
+
+    /// Share the traffic meter of swarm, bytes received are recorded on it.
+    pub fn with_traffic_meter(mut self, traffic: TrafficMeter<Did>) -> Self {
+        self.traffic = traffic;
+        self
+    }
+
+    /// Share the drop log of swarm, dropped messages are counted and sampled for logging.
+    pub fn with_drop_log(mut self, drop_log: DropLog) -> Self {
+        self.drop_log = Some(drop_log);
+        self
+    }
+
+    /// Tolerate messages expired in transit by the policy of swarm, see [ExpiryPolicy].
+    pub fn with_expiry_policy(mut self, expiry_policy: ExpiryPolicy) -> Self {
+        self.expiry_policy = expiry_policy;
+        self
+    }
+
+    /// Share the peer scores of swarm, peers sending bad messages are penalized.
+    pub fn with_peer_scores(mut self, peer_scores: PeerScores) -> Self {
+        self.peer_scores = peer_scores;
+        self
+    }
+
+    /// Share the peer keys of swarm, keys of signers are learned from received messages.
+    pub fn with_peer_keys(mut self, peer_keys: PeerKeys) -> Self {
+        self.peer_keys = peer_keys;
+        self
+    }
+
+    /// Share the observed relays of swarm, peers relaying messages of other signers are recorded.
+    pub fn with_observed_relays(mut self, observed_relays: ObservedRelays) -> Self {
+        self.observed_relays = observed_relays;
+        self
+    }
+
+    /// Share the streams of swarm, frames of streams are delivered to them.
+    pub fn with_streams(mut self, streams: Streams) -> Self {
+        self.streams = streams;
+        self
+    }
+
+    /// Share the compression dictionaries of swarm, payloads compressed against them are
+    /// decompressed before decoding.
+    pub fn with_dictionaries(mut self, dictionaries: CompressionDictionaries) -> Self {
+        self.dictionaries = dictionaries;
+        self
+    }
+
+    /// Penalize the peer whose transport delivered a message dropped for `reason`.
+    fn penalize(&self, peer: Option<Did>, reason: DropReason) {
+        let signal = match reason {
+            DropReason::Malformed => PeerSignal::Malformed,
+            DropReason::InvalidSignature => PeerSignal::InvalidSignature,
+            // Expiry may be caused by clock skew or a slow path, rather than the peer.
+            DropReason::Expired => return,
+            DropReason::Unreachable => return,
+            DropReason::OverBudget => return,
+            // Routing tables may disagree while the ring is converging.
+            DropReason::RoutingLoop => return,
+        };
+        if let Some(peer) = peer {
+            self.peer_scores.record(peer, signal);
+        }
+    }
+
+    fn record_drop(&self, reason: DropReason, payload: Option<&MessagePayload>) {
+        match &self.drop_log {
+            Some(drop_log) => drop_log.record(reason, payload),
+            None => tracing::error!("Drop message of {:?}: {:?}", reason, payload),
         }
     }
+
+    /// Share the trusted transports of swarm, see [TrustedTransports].
+    pub fn with_trusted_transports(mut self, trusted_transports: TrustedTransports) -> Self {
+        self.trusted_transports = trusted_transports;
+        self
+    }
+
+    /// Mark the connection as relayed, whose messages are always verified.
+    pub fn with_relayed(mut self, relayed: bool) -> Self {
+        self.relayed = relayed;
+        self
+    }
 }
 
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
 #[cfg_attr(not(feature = "wasm"), async_trait)]
 impl TransportCallback for InnerSwarmCallback {
-    async fn on_message(&self, _cid: &str, msg: &[u8]) -> Result<(), CallbackError> {
-        let payload = MessagePayload::from_bincode(msg)?;
-        if !(payload.verify() && payload.transaction.verify()) {
-            tracing::error!("Cannot verify msg or it's expired: {:?}", payload);
-            return Err("Cannot verify msg or it's expired".into());
+    async fn on_message(&self, cid: &str, msg: &[u8]) -> Result<(), CallbackError> {
+        let peer = Did::from_str(cid).ok();
+        if let Some(peer) = peer {
+            self.traffic.record_received(&peer, msg.len());
+        }
+
+        if msg.first() == Some(&STREAM_FRAME_TAG) {
+            let peer = peer.ok_or(Error::InvalidStreamFrame)?;
+            return self.streams.dispatch(peer, msg).await.map_err(|e| e.into());
+        }
+
+        let msg = match self.dictionaries.decompress(msg) {
+            Ok(msg) => msg,
+            Err(e) => {
+                self.penalize(peer, DropReason::Malformed);
+                self.record_drop(DropReason::Malformed, None);
+                return Err(e.into());
+            }
+        };
+        let msg = msg.as_ref();
+        let payload = match MessagePayload::from_bincode(msg) {
+            Ok(payload) => Ok(payload),
+            Err(Error::ProtocolVersionMismatch { expected, got }) => {
+                tracing::warn!(
+                    "Message from {:?} has version {:#x}, expected {:#x}, try best-effort decoding",
+                    peer,
+                    got,
+                    expected
+                );
+                MessagePayload::from_bincode_best_effort(msg)
+            }
+            Err(e) => Err(e),
+        };
+        let payload = match payload {
+            Ok(payload) => payload,
+            Err(e) => {
+                self.penalize(peer, DropReason::Malformed);
+                self.record_drop(DropReason::Malformed, None);
+                return Err(e.into());
+            }
+        };
+        // A relay sees every byte, so it never binds the did of peer to the transport.
+        let trusted = !self.relayed
+            && peer
+                .map(|peer| {
+                    self.trusted_transports
+                        .can_skip_verification(peer, &payload)
+                })
+                .unwrap_or(false);
+        if !trusted {
+            if let Some(reason) = DropReason::check(&payload, &self.expiry_policy) {
+                self.penalize(peer, reason);
+                self.record_drop(reason, Some(&payload));
+                return Err("Cannot verify msg or it's expired".into());
+            }
+        }
+
+        self.peer_keys.learn(&payload);
+        self.peer_keys.learn(&payload.transaction);
+        if let Some(peer) = peer {
+            self.observed_relays
+                .observe(payload.transaction.signer(), peer);
+        }
+
+        // A stream is opened only by a message signed by the peer owning the transport.
+        if let Ok(Message::StreamOpen(open)) = payload.transaction.data() {
+            let peer = peer.filter(|p| *p == payload.transaction.signer());
+            let peer = peer.ok_or(Error::InvalidMessage("Stream opened by relay".into()))?;
+            return self.streams.register(peer, open.id).map_err(|e| e.into());
         }
 
         self.callback.on_validate(&payload).await?;
@@ -118,4 +317,91 @@ impl TransportCallback for InnerSwarmCallback {
             })
             .await
     }
+
+    async fn on_handshake_complete(&self, cid: &str) -> Result<(), CallbackError> {
+        let Ok(did) = Did::from_str(cid) else {
+            tracing::warn!("on_handshake_complete parse did failed: {}", cid);
+            return Ok(());
+        };
+
+        Channel::send(
+            &self.transport_event_sender,
+            TransportEvent::HandshakeCompleted(did),
+        )
+        .await
+        .map_err(Box::new)?;
+        Ok(())
+    }
+
+    async fn on_ice_candidate(&self, cid: &str, candidate: &str) -> Result<(), CallbackError> {
+        let Ok(did) = Did::from_str(cid) else {
+            tracing::warn!("on_ice_candidate parse did failed: {}", cid);
+            return Ok(());
+        };
+
+        Channel::send(
+            &self.transport_event_sender,
+            TransportEvent::IceCandidate(did, candidate.to_string()),
+        )
+        .await
+        .map_err(Box::new)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::ecc::SecretKey;
+    use crate::message::COMPACT_PAYLOAD_VERSION;
+    use crate::session::SessionSk;
+    use crate::tests::default::prepare_node;
+
+    /// Keeps payloads passed to [SwarmCallback::on_payload].
+    #[derive(Default)]
+    struct ReceivedPayloads(Mutex<Vec<MessagePayload>>);
+
+    #[async_trait]
+    impl SwarmCallback for ReceivedPayloads {
+        async fn on_payload(&self, payload: &MessagePayload) -> Result<(), CallbackError> {
+            self.0.lock().unwrap().push(payload.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_newer_version_reaches_handlers() {
+        let (swarm, path) = prepare_node(SecretKey::random()).await;
+        let key = SecretKey::random();
+        let sender = SessionSk::new_with_seckey(&key).unwrap();
+        let payload = MessagePayload::new_send(
+            Message::custom(b"hello").unwrap(),
+            &sender,
+            swarm.did(),
+            swarm.did(),
+        )
+        .unwrap();
+        // A later version appending a field to the compact envelope.
+        let mut newer = payload.to_compact().unwrap().to_vec();
+        newer[0] = COMPACT_PAYLOAD_VERSION + 1;
+        newer.extend_from_slice(&[1, 2, 3]);
+
+        let received = Arc::new(ReceivedPayloads::default());
+        let callback =
+            InnerSwarmCallback::new(swarm.transport_event_channel.sender(), received.clone());
+        let peer: Did = key.address().into();
+        callback
+            .on_message(&peer.to_string(), &newer)
+            .await
+            .unwrap();
+        assert_eq!(*received.0.lock().unwrap(), vec![payload.clone()]);
+
+        // The swarm takes the same payload to its handlers.
+        let (handled, _) = swarm.listen_once().await.unwrap();
+        assert_eq!(handled, payload);
+
+        tokio::fs::remove_dir_all(path).await.unwrap();
+    }
 }
@@ -0,0 +1,328 @@
+#![warn(missing_docs)]
+//! This module provides sampled logging of dropped messages.
+//! A node exposed to hostile traffic may receive lots of garbage, logging every one of them
+//! floods the logs. Dropped messages are counted by [DropReason] into metrics, while at most
+//! [DropLogConfig::max_logs_per_sec] of them are logged, with a periodic summary of counts.
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::error::Error;
+use crate::message::MessagePayload;
+use crate::message::MessageVerificationExt;
+use crate::metrics;
+use crate::metrics::MetricsImpl;
+use crate::swarm::expiry::ExpiryPolicy;
+use crate::utils::get_epoch_ms;
+
+/// Reason of dropping a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DropReason {
+    /// Failed on decoding.
+    Malformed,
+    /// Expired or timestamp is in the future.
+    Expired,
+    /// Failed on verifying signature.
+    InvalidSignature,
+    /// Failed on relaying to the next hop towards destination.
+    Unreachable,
+    /// Evicted from reassembly of chunks, since the sender is over budget,
+    /// see [ChunkPool](crate::chunk::ChunkPool).
+    OverBudget,
+    /// Came back to a node it passed while relaying, since routing tables disagree.
+    RoutingLoop,
+}
+
+impl DropReason {
+    const ALL: [DropReason; 6] = [
+        DropReason::Malformed,
+        DropReason::Expired,
+        DropReason::InvalidSignature,
+        DropReason::Unreachable,
+        DropReason::OverBudget,
+        DropReason::RoutingLoop,
+    ];
+
+    /// Reason of dropping a message which failed on relaying.
+    pub fn of_relay_error(e: &Error) -> Self {
+        match e {
+            Error::RoutingLoop(_) => Self::RoutingLoop,
+            _ => Self::Unreachable,
+        }
+    }
+
+    /// Check the verification of payload, returns the reason if it should be dropped.
+    /// An expired payload is dropped only beyond the grace window given by `expiry`.
+    pub fn check(payload: &MessagePayload, expiry: &ExpiryPolicy) -> Option<Self> {
+        let grace_ms = expiry.grace_ms_of(payload);
+        if payload.is_expired_with_grace(grace_ms)
+            || payload.transaction.is_expired_with_grace(grace_ms)
+        {
+            return Some(Self::Expired);
+        }
+        if !(payload.verify_signature() && payload.transaction.verify_signature()) {
+            return Some(Self::InvalidSignature);
+        }
+        None
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+
+    fn metric(&self) -> &'static str {
+        match self {
+            Self::Malformed => metrics::MESSAGE_DROPPED_MALFORMED,
+            Self::Expired => metrics::MESSAGE_DROPPED_EXPIRED,
+            Self::InvalidSignature => metrics::MESSAGE_DROPPED_INVALID_SIGNATURE,
+            Self::Unreachable => metrics::MESSAGE_DROPPED_UNREACHABLE,
+            Self::OverBudget => metrics::MESSAGE_DROPPED_OVER_BUDGET,
+            Self::RoutingLoop => metrics::MESSAGE_DROPPED_ROUTING_LOOP,
+        }
+    }
+}
+
+/// Boxed handler of undeliverable messages, see [Deadletter].
+#[cfg(not(feature = "wasm"))]
+pub type DeadletterFn = Box<dyn Fn(MessagePayload, DropReason) + Send + Sync>;
+
+/// Boxed handler of undeliverable messages, see [Deadletter].
+#[cfg(feature = "wasm")]
+pub type DeadletterFn = Box<dyn Fn(MessagePayload, DropReason)>;
+
+/// Deadletter receives every message dropped by swarm, with the reason of dropping,
+/// so that application can log, alert, or retry it in another way.
+/// Cloned deadletters share the same handler.
+#[derive(Clone, Default)]
+pub struct Deadletter(Arc<RwLock<Option<DeadletterFn>>>);
+
+impl Deadletter {
+    /// Set the handler, which replaces the former one.
+    pub fn set_handler(&self, handler: DeadletterFn) {
+        if let Ok(mut h) = self.0.write() {
+            *h = Some(handler);
+        }
+    }
+
+    /// Deliver a dropped message to the handler, if any.
+    pub fn deliver(&self, payload: &MessagePayload, reason: DropReason) {
+        if let Ok(h) = self.0.read() {
+            if let Some(handler) = h.as_ref() {
+                handler(payload.clone(), reason);
+            }
+        }
+    }
+}
+
+/// Config of logging dropped messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DropLogConfig {
+    /// Max number of dropped messages logged in a second, the rest are only counted.
+    pub max_logs_per_sec: u64,
+    /// Interval of logging the summary of drop counts, in milliseconds.
+    pub summary_interval_ms: u64,
+}
+
+impl Default for DropLogConfig {
+    fn default() -> Self {
+        Self {
+            max_logs_per_sec: 10,
+            summary_interval_ms: 60 * 1000,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct DropLogState {
+    window_sec: AtomicU64,
+    logged_in_window: AtomicU64,
+    suppressed: AtomicU64,
+    last_summary_ms: AtomicU64,
+    counts: [AtomicU64; DropReason::ALL.len()],
+}
+
+/// Counter and sampler of dropped messages. Cloned logs share the same state.
+#[derive(Clone)]
+pub(crate) struct DropLog {
+    config: DropLogConfig,
+    metrics: MetricsImpl,
+    state: Arc<DropLogState>,
+    deadletter: Deadletter,
+}
+
+impl DropLog {
+    pub fn new(config: DropLogConfig, metrics: MetricsImpl) -> Self {
+        let state = DropLogState::default();
+        state
+            .last_summary_ms
+            .store(get_epoch_ms() as u64, Ordering::Relaxed);
+        Self {
+            config,
+            metrics,
+            state: Arc::new(state),
+            deadletter: Deadletter::default(),
+        }
+    }
+
+    /// Deliver dropped messages to the deadletter, see [Deadletter].
+    pub fn with_deadletter(mut self, deadletter: Deadletter) -> Self {
+        self.deadletter = deadletter;
+        self
+    }
+
+    /// Count a dropped message, and log it if the rate limit allows.
+    /// The message is delivered to the deadletter regardless of sampling.
+    pub fn record(&self, reason: DropReason, payload: Option<&MessagePayload>) {
+        if !matches!(
+            reason,
+            DropReason::Unreachable | DropReason::OverBudget | DropReason::RoutingLoop
+        ) {
+            self.metrics
+                .increment_counter(metrics::MESSAGE_VERIFY_FAILED, 1);
+        }
+        self.metrics.increment_counter(reason.metric(), 1);
+        if let Some(payload) = payload {
+            self.deadletter.deliver(payload, reason);
+        }
+        self.state.counts[reason.index()].fetch_add(1, Ordering::Relaxed);
+
+        let now = get_epoch_ms() as u64;
+        if self.sample(now) {
+            tracing::error!("Drop message of {:?}: {:?}", reason, payload);
+        } else {
+            self.state.suppressed.fetch_add(1, Ordering::Relaxed);
+        }
+        self.summarize(now);
+    }
+
+    fn sample(&self, now: u64) -> bool {
+        let state = &self.state;
+        let sec = now / 1000;
+        let window = state.window_sec.load(Ordering::Relaxed);
+        if window != sec
+            && state
+                .window_sec
+                .compare_exchange(window, sec, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            state.logged_in_window.store(0, Ordering::Relaxed);
+        }
+        state.logged_in_window.fetch_add(1, Ordering::Relaxed) < self.config.max_logs_per_sec
+    }
+
+    /// Log the counts since last summary, if the summary interval is elapsed.
+    fn summarize(&self, now: u64) {
+        let state = &self.state;
+        let last = state.last_summary_ms.load(Ordering::Relaxed);
+        if now.saturating_sub(last) < self.config.summary_interval_ms
+            || state
+                .last_summary_ms
+                .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_err()
+        {
+            return;
+        }
+        let counts = self.take_counts();
+        let suppressed = state.suppressed.swap(0, Ordering::Relaxed);
+        tracing::warn!(
+            "Dropped messages in last {}ms: {:?}, {} of them are not logged",
+            now.saturating_sub(last),
+            counts,
+            suppressed
+        );
+    }
+
+    /// Counts of dropped messages since last summary, which are kept for the next summary.
+    pub fn counts(&self) -> Vec<(DropReason, u64)> {
+        DropReason::ALL
+            .iter()
+            .map(|r| (*r, self.state.counts[r.index()].load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    /// Take the counts of dropped messages since last summary.
+    fn take_counts(&self) -> Vec<(DropReason, u64)> {
+        DropReason::ALL
+            .iter()
+            .map(|r| (*r, self.state.counts[r.index()].swap(0, Ordering::Relaxed)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::ecc::SecretKey;
+    use crate::message::Message;
+    use crate::metrics::noop_recorder;
+    use crate::session::SessionSk;
+
+    #[test]
+    fn test_drop_log_sampling() {
+        let log = DropLog::new(
+            DropLogConfig {
+                max_logs_per_sec: 2,
+                summary_interval_ms: 60 * 1000,
+            },
+            noop_recorder(),
+        );
+        let now = get_epoch_ms() as u64;
+        assert!(log.sample(now));
+        assert!(log.sample(now));
+        assert!(!log.sample(now));
+        assert!(log.sample(now + 1000));
+
+        log.record(DropReason::Expired, None);
+        log.record(DropReason::Expired, None);
+        log.record(DropReason::Malformed, None);
+        assert_eq!(log.counts()[1], (DropReason::Expired, 2));
+        assert_eq!(log.take_counts(), vec![
+            (DropReason::Malformed, 1),
+            (DropReason::Expired, 2),
+            (DropReason::InvalidSignature, 0),
+            (DropReason::Unreachable, 0),
+            (DropReason::OverBudget, 0),
+            (DropReason::RoutingLoop, 0),
+        ]);
+        assert_eq!(log.take_counts()[1], (DropReason::Expired, 0));
+    }
+
+    #[test]
+    fn test_deadletter() {
+        let deadletter = Deadletter::default();
+        let log = DropLog::new(DropLogConfig::default(), noop_recorder())
+            .with_deadletter(deadletter.clone());
+
+        let received = Arc::new(Mutex::new(vec![]));
+        let r = received.clone();
+        deadletter.set_handler(Box::new(move |payload, reason| {
+            r.lock().unwrap().push((payload.transaction.tx_id, reason));
+        }));
+
+        let key = SecretKey::random();
+        let session_sk = SessionSk::new_with_seckey(&key).unwrap();
+        let did = key.address().into();
+        let payload = MessagePayload::new_send(
+            Message::custom("hello".as_bytes()).unwrap(),
+            &session_sk,
+            did,
+            did,
+        )
+        .unwrap();
+
+        // A message failing on decoding has nothing to deliver.
+        log.record(DropReason::Malformed, None);
+        log.record(DropReason::Unreachable, Some(&payload));
+        log.record(DropReason::Expired, Some(&payload));
+        assert_eq!(*received.lock().unwrap(), vec![
+            (payload.transaction.tx_id, DropReason::Unreachable),
+            (payload.transaction.tx_id, DropReason::Expired),
+        ]);
+    }
+}
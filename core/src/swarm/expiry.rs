@@ -0,0 +1,130 @@
+#![warn(missing_docs)]
+//! Tolerance of messages expired in transit.
+//!
+//! A message carries the time it was signed and its ttl, and is dropped by the receiver once
+//! `ts_ms + ttl_ms` has passed. Since the time is read from the clock of the signer, a signer
+//! whose clock runs behind the receiver's produces messages which look older than they are.
+//! [ExpiryPolicy] grants types of message a grace window, within which an expired message of
+//! that type is still processed.
+//!
+//! A grace window of `g` milliseconds tolerates a clock skew of up to `g` milliseconds, but it
+//! also widens the window in which a captured message can be replayed by `g` milliseconds.
+//! Grant it only to types of message which are harmless to process twice, such as reads, and
+//! keep writes, such as `OperateVNode`, strict. Every type is strict by default.
+//!
+//! Only the tolerance of receivers is configurable. Messages are always signed with
+//! [DEFAULT_TTL_MS](crate::consts::DEFAULT_TTL_MS), and a grace window can only extend it, so
+//! no policy makes a message expire earlier than that. There is no ttl short enough to make
+//! multi-hop messages undeliverable, and hence no floor of ttl to enforce.
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::consts::MAX_TTL_MS;
+use crate::message::Message;
+use crate::message::MessagePayload;
+
+/// Max grace window of a type of message, longer windows are truncated to it.
+pub const MAX_EXPIRY_GRACE_MS: u64 = MAX_TTL_MS;
+
+/// Grace windows of expired messages by type, see the [module level documentation](self).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExpiryPolicy {
+    /// Grace window in milliseconds, keyed by [Message::kind].
+    #[serde(default)]
+    pub grace_ms: BTreeMap<String, u64>,
+}
+
+impl ExpiryPolicy {
+    /// Grant a type of message a grace window in milliseconds, see [Message::kind].
+    pub fn grace(mut self, kind: &str, ms: u64) -> Self {
+        self.grace_ms.insert(kind.to_string(), ms);
+        self
+    }
+
+    /// Grace window of the message carried by payload, zero if its type is strict.
+    pub fn grace_ms_of(&self, payload: &MessagePayload) -> u64 {
+        // Skip decoding when every type is strict, which is the common case.
+        if self.grace_ms.is_empty() {
+            return 0;
+        }
+        payload
+            .transaction
+            .data::<Message>()
+            .ok()
+            .and_then(|msg| self.grace_ms.get(msg.kind()).copied())
+            .unwrap_or(0)
+            .min(MAX_EXPIRY_GRACE_MS)
+    }
+
+    /// Types listed in policy which are not a type of message, likely misspelled.
+    pub fn unknown_kinds(&self) -> Vec<&str> {
+        self.grace_ms
+            .keys()
+            .map(String::as_str)
+            .filter(|k| !Message::KINDS.contains(k))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dht::Did;
+    use crate::ecc::SecretKey;
+    use crate::message::MessageVerification;
+    use crate::message::MessageVerificationExt;
+    use crate::message::QueryForTopoInfoSend;
+    use crate::session::SessionSk;
+    use crate::utils::get_epoch_ms;
+
+    struct Signed(MessageVerification);
+
+    impl MessageVerificationExt for Signed {
+        fn verification_data(&self) -> crate::error::Result<Vec<u8>> {
+            Ok(vec![])
+        }
+
+        fn verification(&self) -> &MessageVerification {
+            &self.0
+        }
+    }
+
+    #[test]
+    fn test_expiry_grace() {
+        let session_sk = SessionSk::new_with_seckey(&SecretKey::random()).unwrap();
+        let mut verification = MessageVerification::new(b"", &session_sk).unwrap();
+        verification.ttl_ms = 1000;
+        verification.ts_ms = get_epoch_ms() - 1500;
+        let signed = Signed(verification);
+        assert!(signed.is_expired());
+        assert!(signed.is_expired_with_grace(100));
+        assert!(!signed.is_expired_with_grace(1000));
+
+        let did: Did = session_sk.account_did();
+        let read = MessagePayload::new_send(
+            Message::QueryForTopoInfoSend(QueryForTopoInfoSend::new_for_stab(did)),
+            &session_sk,
+            did,
+            did,
+        )
+        .unwrap();
+        let write =
+            MessagePayload::new_send(Message::custom(b"hi").unwrap(), &session_sk, did, did)
+                .unwrap();
+
+        let strict = ExpiryPolicy::default();
+        assert_eq!(strict.grace_ms_of(&read), 0);
+
+        let policy = ExpiryPolicy::default()
+            .grace("QueryForTopoInfoSend", 1000)
+            .grace("Typo", 1);
+        assert_eq!(policy.grace_ms_of(&read), 1000);
+        assert_eq!(policy.grace_ms_of(&write), 0);
+        assert_eq!(policy.unknown_kinds(), vec!["Typo"]);
+
+        let huge = ExpiryPolicy::default().grace("QueryForTopoInfoSend", u64::MAX);
+        assert_eq!(huge.grace_ms_of(&read), MAX_EXPIRY_GRACE_MS);
+    }
+}
@@ -0,0 +1,293 @@
+#![warn(missing_docs)]
+//! This module provides [ConnectionGate], which is consulted before a connection is created,
+//! so that unwanted peers are rejected before consuming any transport resources.
+//!
+//! Dids are cheap to generate, so [IpLimiter] adds a defense at the network layer, which
+//! limits the transports created for offers from a single IP address.
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::dht::Did;
+
+/// A gate deciding whether a connection with peer is allowed.
+pub trait ConnectionGate {
+    /// Returns the reason if the connection with `did` should be rejected.
+    fn check(&self, did: Did) -> Result<(), String>;
+}
+
+impl<F> ConnectionGate for F
+where F: Fn(Did) -> Result<(), String>
+{
+    fn check(&self, did: Did) -> Result<(), String> {
+        self(did)
+    }
+}
+
+/// Type of ConnectionGate, see [ConnectionGate].
+#[cfg(not(feature = "wasm"))]
+pub type ConnectionGateImpl = Box<dyn ConnectionGate + Send + Sync>;
+
+/// Type of ConnectionGate, see [ConnectionGate].
+#[cfg(feature = "wasm")]
+pub type ConnectionGateImpl = Box<dyn ConnectionGate>;
+
+/// A [ConnectionGate] based on lists of did.
+/// A did is rejected if it's in the denylist, has a denied prefix,
+/// or is not in the allowlist when the allowlist is set.
+#[derive(Debug, Clone, Default)]
+pub struct DidListGate {
+    allowlist: Option<HashSet<Did>>,
+    denylist: HashSet<Did>,
+    deny_prefixes: Vec<String>,
+}
+
+impl DidListGate {
+    /// Only allow connections with dids in the list.
+    pub fn allow(mut self, dids: impl IntoIterator<Item = Did>) -> Self {
+        self.allowlist.get_or_insert_with(HashSet::new).extend(dids);
+        self
+    }
+
+    /// Reject connections with dids in the list.
+    pub fn deny(mut self, dids: impl IntoIterator<Item = Did>) -> Self {
+        self.denylist.extend(dids);
+        self
+    }
+
+    /// Reject connections with dids starting with prefix, such as `0x00`.
+    pub fn deny_prefix(mut self, prefix: &str) -> Self {
+        self.deny_prefixes.push(prefix.to_lowercase());
+        self
+    }
+}
+
+impl ConnectionGate for DidListGate {
+    fn check(&self, did: Did) -> Result<(), String> {
+        if self.denylist.contains(&did) {
+            return Err("did is denied".to_string());
+        }
+        let did_str = did.to_string();
+        if let Some(prefix) = self.deny_prefixes.iter().find(|p| did_str.starts_with(*p)) {
+            return Err(format!("did prefix {prefix} is denied"));
+        }
+        if let Some(allowlist) = &self.allowlist {
+            if !allowlist.contains(&did) {
+                return Err("did is not allowed".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Config of [IpLimiter].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IpLimitConfig {
+    /// Max number of transports from an IP which are still handshaking.
+    pub max_pending: usize,
+    /// Max number of transports created for an IP in a window.
+    pub max_per_window: usize,
+    /// Length of the window, in milliseconds.
+    #[serde(default = "default_window_ms")]
+    pub window_ms: u64,
+}
+
+fn default_window_ms() -> u64 {
+    60 * 1000
+}
+
+impl Default for IpLimitConfig {
+    fn default() -> Self {
+        Self {
+            max_pending: 8,
+            max_per_window: 32,
+            window_ms: default_window_ms(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct IpEntry {
+    pending: HashSet<Did>,
+    attempts: VecDeque<u128>,
+}
+
+/// IpLimiter rate-limits and caps pending transports by source IP, before the handshake
+/// of webrtc. The IP of an offer comes from the signaling connection it's received from, or
+/// from the ICE candidates of it and the ones trickled, see [candidate_ips]. Once the
+/// transport is connected, the remote address of the candidate pair selected by ICE is
+/// counted as well, and the transport is closed if it's over the limits.
+///
+/// ICE candidates are claimed by the peer making the offer, so a peer can name the IP of
+/// another one, and consume its quota. The limits should be loose enough for honest peers
+/// behind a shared NAT. Cloned limiters share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct IpLimiter {
+    config: IpLimitConfig,
+    state: Arc<Mutex<HashMap<IpAddr, IpEntry>>>,
+}
+
+impl IpLimiter {
+    /// Create a limiter from config.
+    pub fn new(config: IpLimitConfig) -> Self {
+        Self {
+            config,
+            state: Default::default(),
+        }
+    }
+
+    /// Admit a transport with `did` from `ip` at `now`, returns the reason if it should be
+    /// rejected. `is_pending` tells whether a transport admitted before is still handshaking,
+    /// transports done or gone are released. Admitting a did from the same IP again is always
+    /// allowed until it's released, and is not counted again.
+    pub fn admit(
+        &self,
+        ip: IpAddr,
+        did: Did,
+        now: u128,
+        is_pending: impl Fn(Did) -> bool,
+    ) -> Result<(), String> {
+        let window_start = now.saturating_sub(self.config.window_ms as u128);
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.retain(|_, entry| {
+            // The did being admitted may have no transport yet.
+            entry.pending.retain(|d| *d == did || is_pending(*d));
+            while entry.attempts.front().is_some_and(|t| *t < window_start) {
+                entry.attempts.pop_front();
+            }
+            !(entry.pending.is_empty() && entry.attempts.is_empty())
+        });
+
+        let entry = state.entry(ip).or_default();
+        if entry.pending.contains(&did) {
+            return Ok(());
+        }
+        if entry.pending.len() >= self.config.max_pending {
+            return Err(format!("too many pending transports from {ip}"));
+        }
+        if entry.attempts.len() >= self.config.max_per_window {
+            return Err(format!("too many new transports from {ip}"));
+        }
+        entry.pending.insert(did);
+        entry.attempts.push_back(now);
+        Ok(())
+    }
+}
+
+/// IP addresses of ICE candidates in an SDP, which may be wrapped in a json of session
+/// description, or of a single candidate trickled. Candidates of mDNS hostnames are skipped.
+pub fn candidate_ips(sdp: &str) -> Vec<IpAddr> {
+    let sdp = serde_json::from_str::<serde_json::Value>(sdp)
+        .ok()
+        .and_then(|v| {
+            let field = v.get("sdp").or_else(|| v.get("candidate"))?;
+            field.as_str().map(str::to_string)
+        })
+        .unwrap_or_else(|| sdp.to_string());
+    let mut ips: Vec<IpAddr> = sdp
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            let candidate = line.strip_prefix("a=").unwrap_or(line);
+            let candidate = candidate.strip_prefix("candidate:")?;
+            // foundation component transport priority address port typ type ...
+            candidate.split_whitespace().nth(4)?.parse().ok()
+        })
+        .collect();
+    ips.sort();
+    ips.dedup();
+    ips
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_did_list_gate() {
+        let a = Did::from_str("0x11E807fcc88dD319270493fB2e822e388Fe36ab0").unwrap();
+        let b = Did::from_str("0x999999cf1046e68e36E1aA2E0E07105eDDD1f08E").unwrap();
+        let c = Did::from_str("0xc0ffee254729296a45a3885639AC7E10F9d54979").unwrap();
+
+        let gate = DidListGate::default();
+        assert!(gate.check(a).is_ok());
+
+        let gate = DidListGate::default().deny([a]).deny_prefix("0x99");
+        assert!(gate.check(a).is_err());
+        assert!(gate.check(b).is_err());
+        assert!(gate.check(c).is_ok());
+
+        let gate = DidListGate::default().allow([a, b]).deny([b]);
+        assert!(gate.check(a).is_ok());
+        assert!(gate.check(b).is_err());
+        assert!(gate.check(c).is_err());
+
+        let gate = |did: Did| {
+            if did == c {
+                Err("predicate".to_string())
+            } else {
+                Ok(())
+            }
+        };
+        assert!(gate.check(a).is_ok());
+        assert_eq!(gate.check(c), Err("predicate".to_string()));
+    }
+
+    #[test]
+    fn test_ip_limiter() {
+        let limiter = IpLimiter::new(IpLimitConfig {
+            max_pending: 2,
+            max_per_window: 3,
+            window_ms: 1000,
+        });
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let other: IpAddr = "198.51.100.1".parse().unwrap();
+        let dids: Vec<Did> = (1u32..=5).map(Did::from).collect();
+        let pending = Mutex::new(HashSet::new());
+        let is_pending = |did: Did| pending.lock().unwrap().contains(&did);
+
+        for did in &dids[..2] {
+            assert!(limiter.admit(ip, *did, 0, is_pending).is_ok());
+            pending.lock().unwrap().insert(*did);
+        }
+        // A pending did is admitted again without counting.
+        assert!(limiter.admit(ip, dids[0], 0, is_pending).is_ok());
+        assert!(limiter.admit(ip, dids[2], 0, is_pending).is_err());
+        assert!(limiter.admit(other, dids[2], 0, is_pending).is_ok());
+
+        // Transports done are released, but attempts are still limited in window.
+        pending.lock().unwrap().clear();
+        assert!(limiter.admit(ip, dids[3], 10, is_pending).is_ok());
+        assert!(limiter.admit(ip, dids[4], 10, is_pending).is_err());
+        assert!(limiter.admit(ip, dids[4], 1011, is_pending).is_ok());
+    }
+
+    #[test]
+    fn test_candidate_ips() {
+        let sdp = "v=0\r\na=candidate:1 1 udp 2130706431 192.168.1.2 50000 typ host\r\n\
+                   a=candidate:2 1 udp 1694498815 203.0.113.7 50001 typ srflx raddr 0.0.0.0\r\n\
+                   a=candidate:3 1 udp 2130706431 abc.local 50002 typ host\r\n\
+                   a=candidate:4 2 udp 2130706430 192.168.1.2 50003 typ host\r\n";
+        let desc = serde_json::json!({"type": "offer", "sdp": sdp});
+        let expected: Vec<IpAddr> = vec![
+            "192.168.1.2".parse().unwrap(),
+            "203.0.113.7".parse().unwrap(),
+        ];
+        assert_eq!(candidate_ips(&desc.to_string()), expected);
+
+        let trickled = serde_json::json!({
+            "candidate": "candidate:2 1 udp 1694498815 203.0.113.7 50001 typ srflx",
+            "sdpMid": "0",
+        });
+        let expected: Vec<IpAddr> = vec!["203.0.113.7".parse().unwrap()];
+        assert_eq!(candidate_ips(&trickled.to_string()), expected);
+    }
+}
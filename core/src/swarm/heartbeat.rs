@@ -0,0 +1,143 @@
+#![warn(missing_docs)]
+//! Heartbeats of long-running loops, such as the listen loop of [Swarm] and the loop of
+//! [Stabilization](crate::dht::Stabilization), for supervisors to tell if they are alive.
+//!
+//! A loop holds a [HeartbeatGuard] while it's running, which is dropped when the loop exits,
+//! panics, or its future is dropped, and a [BusyGuard] while it's working on an item. A loop
+//! waiting for work is alive however long it waits, while one working on an item for long is
+//! likely stuck.
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use crate::swarm::Swarm;
+use crate::types::channel::Channel as ChannelTrait;
+use crate::utils::get_epoch_ms;
+
+#[derive(Debug, Default)]
+struct HeartbeatState {
+    running: AtomicBool,
+    /// Last time the loop started or finished an item, 0 if never.
+    last_beat_ms: AtomicU64,
+    /// Time the loop started the item in progress, 0 if idle.
+    busy_since_ms: AtomicU64,
+}
+
+/// Heartbeat of a loop. Cloned ones share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct Heartbeat(Arc<HeartbeatState>);
+
+/// Marks the loop of [Heartbeat] running until dropped.
+#[derive(Debug)]
+pub struct HeartbeatGuard(Heartbeat);
+
+/// Marks the loop of [Heartbeat] busy until dropped.
+#[derive(Debug)]
+pub struct BusyGuard(Heartbeat);
+
+/// State of a loop reported by [Heartbeat::pulse].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pulse {
+    /// Whether the loop is running.
+    pub running: bool,
+    /// Milliseconds since the loop started or finished an item, None if it never ran.
+    pub since_last_beat_ms: Option<u64>,
+    /// Milliseconds the loop has been working on the item in progress, None if idle.
+    pub busy_ms: Option<u64>,
+}
+
+impl Heartbeat {
+    fn beat(&self) -> u64 {
+        let now = get_epoch_ms() as u64;
+        self.0.last_beat_ms.store(now, Ordering::Relaxed);
+        now
+    }
+
+    /// Mark the loop running until the guard is dropped.
+    pub fn start(&self) -> HeartbeatGuard {
+        self.beat();
+        self.0.running.store(true, Ordering::Relaxed);
+        HeartbeatGuard(self.clone())
+    }
+
+    fn stopped(&self) {
+        self.0.running.store(false, Ordering::Relaxed);
+    }
+
+    fn idle(&self) {
+        self.beat();
+        self.0.busy_since_ms.store(0, Ordering::Relaxed);
+    }
+
+    /// Mark the loop busy on an item until the guard is dropped.
+    pub fn busy(&self) -> BusyGuard {
+        let now = self.beat();
+        self.0.busy_since_ms.store(now, Ordering::Relaxed);
+        BusyGuard(self.clone())
+    }
+
+    /// Report the state of loop.
+    pub fn pulse(&self) -> Pulse {
+        let now = get_epoch_ms() as u64;
+        let since = |ms: u64| (ms > 0).then(|| now.saturating_sub(ms));
+        Pulse {
+            running: self.0.running.load(Ordering::Relaxed),
+            since_last_beat_ms: since(self.0.last_beat_ms.load(Ordering::Relaxed)),
+            busy_ms: since(self.0.busy_since_ms.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl Drop for HeartbeatGuard {
+    fn drop(&mut self) {
+        self.0.stopped();
+    }
+}
+
+impl Drop for BusyGuard {
+    fn drop(&mut self) {
+        self.0.idle();
+    }
+}
+
+impl Swarm {
+    /// Report the state of listen loop, see [Swarm::listen].
+    pub fn listen_pulse(&self) -> Pulse {
+        self.listen_heartbeat.pulse()
+    }
+
+    /// Number of transport events waiting for the listen loop.
+    pub fn event_queue_depth(&self) -> usize {
+        self.transport_event_channel.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_heartbeat() {
+        let heartbeat = Heartbeat::default();
+        let pulse = heartbeat.pulse();
+        assert!(!pulse.running);
+        assert_eq!(pulse.since_last_beat_ms, None);
+
+        let running = heartbeat.start();
+        let pulse = heartbeat.pulse();
+        assert!(pulse.running);
+        assert!(pulse.since_last_beat_ms.is_some());
+        assert_eq!(pulse.busy_ms, None);
+
+        {
+            let _busy = heartbeat.busy();
+            assert!(heartbeat.pulse().busy_ms.is_some());
+        }
+        assert_eq!(heartbeat.pulse().busy_ms, None);
+
+        // Cloned ones share the state, such as one dropped with the future of loop.
+        drop(running);
+        assert!(!heartbeat.clone().pulse().running);
+    }
+}
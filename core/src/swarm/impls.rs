@@ -1,9 +1,12 @@
+use std::net::IpAddr;
 use std::str::FromStr;
 
 use async_trait::async_trait;
 use rings_transport::core::transport::ConnectionInterface;
+use rings_transport::core::transport::WebrtcConnectionState;
 
 use super::callback::InnerSwarmCallback;
+use crate::dht::successor::SuccessorReader;
 use crate::dht::Did;
 use crate::error::Error;
 use crate::error::Result;
@@ -15,9 +18,15 @@ use crate::message::MessagePayload;
 use crate::message::MessageVerificationExt;
 use crate::message::PayloadSender;
 use crate::swarm::callback::SharedSwarmCallback;
+use crate::swarm::callback::SwarmEvent;
+use crate::swarm::gate::candidate_ips;
+use crate::swarm::AuthAction;
+use crate::swarm::Decision;
 use crate::swarm::Swarm;
 use crate::types::channel::Channel;
 use crate::types::Connection;
+use crate::types::ConnectionOwner;
+use crate::utils::get_epoch_ms;
 
 /// ConnectionHandshake defined how to connect two connections between two swarms.
 #[cfg_attr(feature = "wasm", async_trait(?Send))]
@@ -161,17 +170,124 @@ impl Swarm {
         Ok(())
     }
 
+    /// Consult the connection gate and authorizer, and notify callback if the connection
+    /// is rejected.
+    async fn check_connection_gate(&self, did: Did) -> Result<()> {
+        let reason = match self.connection_gate.as_ref().map(|gate| gate.check(did)) {
+            Some(Err(reason)) => reason,
+            _ => match self.authorize(did, &AuthAction::Connect).await {
+                Decision::Allow => return Ok(()),
+                Decision::Deny(reason) => reason,
+            },
+        };
+        self.reject_connection(did, reason).await
+    }
+
+    /// Check the source IPs of a transport with did by [IpLimiter](crate::swarm::gate::IpLimiter),
+    /// if it's set, which is consulted before the handshake of an offer is answered.
+    pub async fn check_ip_limit(&self, did: Did, ips: &[IpAddr]) -> Result<()> {
+        let Some(limiter) = &self.ip_limiter else {
+            return Ok(());
+        };
+        let now = get_epoch_ms();
+        let is_pending = |d: Did| {
+            self.get_connection(d).is_some_and(|c| {
+                matches!(
+                    c.webrtc_connection_state(),
+                    WebrtcConnectionState::New | WebrtcConnectionState::Connecting
+                )
+            })
+        };
+        for ip in ips {
+            if let Err(reason) = limiter.admit(*ip, did, now, is_pending) {
+                return self.reject_connection(did, reason).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the remote address of the candidate pair selected by ICE for the transport with
+    /// did by [IpLimiter](crate::swarm::gate::IpLimiter), once it's connected, which may differ
+    /// from the candidates checked before the handshake. The transport is closed if it's
+    /// rejected.
+    pub(crate) async fn check_remote_address(&self, did: Did) -> Result<()> {
+        if self.ip_limiter.is_none() {
+            return Ok(());
+        }
+        let Some(conn) = self.get_connection(did) else {
+            return Ok(());
+        };
+        let Some(addr) = conn.webrtc_remote_address().await else {
+            return Ok(());
+        };
+        if let Err(e) = self.check_ip_limit(did, &[addr.ip()]).await {
+            self.disconnect(did).await?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    async fn reject_connection(&self, did: Did, reason: String) -> Result<()> {
+        tracing::warn!("Connection with {did} is rejected: {reason}");
+        let event = SwarmEvent::ConnectionRejected {
+            did,
+            reason: reason.clone(),
+        };
+        if let Err(e) = self.callback()?.on_event(&event).await {
+            tracing::error!("Failed on notifying rejected connection: {e:?}");
+        }
+        Err(Error::ConnectionRejected(did, reason))
+    }
+
+    /// Complete initial convergence once the node has found its successor and predecessor,
+    /// or the convergence window is elapsed, and notify callback with
+    /// [SwarmEvent::InitialConvergence].
+    pub async fn check_convergence(&self) -> Result<()> {
+        if self.convergence.is_converged() {
+            return Ok(());
+        }
+        let joined = !self.dht.successors().is_empty()? && self.dht.lock_predecessor()?.is_some();
+        let timed_out = !joined && self.convergence.is_timed_out();
+        if !(joined || timed_out) || !self.convergence.complete() {
+            return Ok(());
+        }
+
+        let event = SwarmEvent::InitialConvergence {
+            elapsed_ms: self.convergence.elapsed_ms(),
+            timed_out,
+        };
+        if let Err(e) = self.callback()?.on_event(&event).await {
+            tracing::error!("Failed on notifying initial convergence: {e:?}");
+        }
+        Ok(())
+    }
+
     /// Create new connection that will be handled by swarm.
+    /// The connection gate is consulted first, so a rejected peer never consumes transport resources.
     pub async fn new_connection(&self, did: Did) -> Result<Connection> {
-        let inner_callback =
-            InnerSwarmCallback::new(self.transport_event_channel.sender(), self.callback()?);
+        self.check_connection_gate(did).await?;
 
         let cid = did.to_string();
+        let inner_callback =
+            InnerSwarmCallback::new(self.transport_event_channel.sender(), self.callback()?)
+                .with_trusted_transports(self.trusted_transports.clone())
+                .with_relayed(self.transport.is_relayed(&cid))
+                .with_traffic_meter(self.traffic.clone())
+                .with_drop_log(self.drop_log.clone())
+                .with_expiry_policy(self.expiry_policy.clone())
+                .with_peer_scores(self.peer_scores.clone())
+                .with_peer_keys(self.peer_keys.clone())
+                .with_observed_relays(self.observed_relays.clone())
+                .with_streams(self.streams.clone())
+                .with_dictionaries(self.dictionaries.clone());
+
         self.transport
             .new_connection(&cid, Box::new(inner_callback))
             .await
             .map_err(Error::Transport)?;
-        self.transport.connection(&cid).map_err(|e| e.into())
+        let conn = self.transport.connection(&cid)?;
+        self.add_pending_candidates(did, &conn).await?;
+        Ok(conn)
     }
 
     /// Get connection by did and check if it is connected.
@@ -230,7 +346,7 @@ impl ConnectionHandshake for Swarm {
         let conn = self.new_connection(peer).await?;
 
         let offer = conn.webrtc_create_offer().await.map_err(Error::Transport)?;
-        let offer_str = serde_json::to_string(&offer).map_err(|_| Error::SerializeToString)?;
+        let offer_str = self.signaled_sdp(peer, &conn, offer).await?;
         let offer_msg = ConnectNodeSend { sdp: offer_str };
 
         Ok((conn, offer_msg))
@@ -245,14 +361,22 @@ impl ConnectionHandshake for Swarm {
             return Err(Error::AlreadyConnected);
         };
 
-        let offer = serde_json::from_str(&offer_msg.sdp).map_err(Error::Deserialize)?;
+        // Counted as pending before the handshake, see [IpLimiter](crate::swarm::gate::IpLimiter).
+        self.check_ip_limit(peer, &candidate_ips(&offer_msg.sdp))
+            .await?;
+        let offer: <ConnectionOwner as ConnectionInterface>::Sdp =
+            serde_json::from_str(&offer_msg.sdp).map_err(Error::Deserialize)?;
+        // Learned before the connection is created, which starts reporting local candidates.
+        self.learn_trickle(peer, &offer_msg.sdp);
+        // The connection is created over the same kind of transport as the offer.
+        self.transport.prepare_answer(&peer.to_string(), &offer);
 
         let conn = self.new_connection(peer).await?;
         let answer = conn
             .webrtc_answer_offer(offer)
             .await
             .map_err(Error::Transport)?;
-        let answer_str = serde_json::to_string(&answer).map_err(|_| Error::SerializeToString)?;
+        let answer_str = self.signaled_sdp(peer, &conn, answer).await?;
         let answer_msg = ConnectNodeReport { sdp: answer_str };
 
         Ok((conn, answer_msg))
@@ -264,6 +388,7 @@ impl ConnectionHandshake for Swarm {
         answer_msg: &ConnectNodeReport,
     ) -> Result<Connection> {
         let answer = serde_json::from_str(&answer_msg.sdp).map_err(Error::Deserialize)?;
+        self.learn_trickle(peer, &answer_msg.sdp);
 
         let conn = self.get_connection(peer).ok_or(Error::ConnectionNotFound)?;
         conn.webrtc_accept_answer(answer)
@@ -274,7 +399,9 @@ impl ConnectionHandshake for Swarm {
     }
 
     async fn create_offer(&self, peer: Did) -> Result<(Connection, MessagePayload)> {
-        let (conn, offer_msg) = self.prepare_connection_offer(peer).await?;
+        let (conn, mut offer_msg) = self.prepare_connection_offer(peer).await?;
+        // Signaled out-of-band, so the offer carries every candidate instead of trickling.
+        offer_msg.sdp = self.gathered_sdp(&conn, offer_msg.sdp).await?;
 
         // This payload has fake next_hop.
         // The invoker should fix it before sending.
@@ -303,7 +430,9 @@ impl ConnectionHandshake for Swarm {
         };
 
         let peer = offer_payload.relay.origin_sender();
-        let (conn, answer_msg) = self.answer_remote_connection(peer, &msg).await?;
+        let (conn, mut answer_msg) = self.answer_remote_connection(peer, &msg).await?;
+        // Signaled out-of-band, so the answer carries every candidate instead of trickling.
+        answer_msg.sdp = self.gathered_sdp(&conn, answer_msg.sdp).await?;
 
         // This payload has fake next_hop.
         // The invoker should fix it before sending.
@@ -365,7 +494,7 @@ impl ConnectionManager for Swarm {
         let conn = self.new_connection(did).await?;
 
         let offer = conn.webrtc_create_offer().await.map_err(Error::Transport)?;
-        let offer_str = serde_json::to_string(&offer).map_err(|_| Error::SerializeToString)?;
+        let offer_str = self.signaled_sdp(did, &conn, offer).await?;
         let offer_msg = ConnectNodeSend { sdp: offer_str };
 
         self.send_message(Message::ConnectNodeSend(offer_msg), did)
@@ -385,7 +514,7 @@ impl ConnectionManager for Swarm {
         let conn = self.new_connection(did).await?;
 
         let offer = conn.webrtc_create_offer().await.map_err(Error::Transport)?;
-        let offer_str = serde_json::to_string(&offer).map_err(|_| Error::SerializeToString)?;
+        let offer_str = self.signaled_sdp(did, &conn, offer).await?;
         let offer_msg = ConnectNodeSend { sdp: offer_str };
 
         self.send_message_by_hop(Message::ConnectNodeSend(offer_msg), did, next_hop)
@@ -415,7 +544,8 @@ impl Judegement for Swarm {
     }
 
     /// Asynchronously checks if a connection should be established with the provided DID.
+    /// Pinned peers are always allowed.
     async fn should_connect(&self, did: Did) -> bool {
-        self.behaviour_good(did).await
+        self.is_pinned_peer(did) || self.behaviour_good(did).await
     }
 }
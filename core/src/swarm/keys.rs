@@ -0,0 +1,590 @@
+#![warn(missing_docs)]
+//! This module provides [PeerKeys], the encryption public keys of peers.
+//!
+//! A node signs its messages with its session key, which is delegated by its account,
+//! see [Session](crate::session::Session). The session public key can be recovered from
+//! the signature of any message, and is bound to the account by the session signature,
+//! so every signed message, including the ones of handshake, exchanges the key of signer.
+//! The key is used to encrypt data to the peer, which is decrypted by its session secret key.
+//!
+//! Data is encrypted by ECIES, whose output starts with an ephemeral public key, and the key
+//! of AES is derived from ECDH of the ephemeral key and the key of receiver. Instead of a new
+//! ephemeral key per message, [SessionKeys] keeps one per peer with the derived key, and
+//! rotates it after a number of messages or a period, see [KeyRotation]. The output is still
+//! plain ECIES, so it can be decrypted by [SessionSk::decrypt] as well. The receiver caches
+//! the keys derived from recent ephemeral keys, so neither side runs ECDH per message.
+//!
+//! Once a key is rotated, its ephemeral secret is forgotten, so that messages encrypted with
+//! it cannot be decrypted by the sender anymore.
+//!
+//! For forward secrecy on the side of receiver as well, each node offers an ephemeral key
+//! of its own to connected peers by a signed [EphemeralKeyOffer], which peers encrypt to
+//! instead of its session key. It's rotated by the same policy and offered again, and only
+//! the latest [MAX_EPHEMERAL_KEYS] are kept with the keys derived by them, so that a leak of
+//! keys of either side never decrypts messages sent before the rotations. Data to a peer
+//! which offered no key, such as a peer of an older version, is encrypted to its session key.
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+use async_trait::async_trait;
+use dashmap::DashMap;
+use ecies::utils::aes_decrypt;
+use ecies::utils::aes_encrypt;
+use ecies::utils::decapsulate;
+use ecies::utils::encapsulate;
+use ecies::utils::generate_keypair;
+use ecies::SecpError;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::dht::Did;
+use crate::ecc::PublicKey;
+use crate::error::Error;
+use crate::error::Result;
+use crate::message::EphemeralKeyOffer;
+use crate::message::HandleMsg;
+use crate::message::MessageHandler;
+use crate::message::MessageHandlerEvent;
+use crate::message::MessagePayload;
+use crate::message::MessageVerificationExt;
+use crate::session::SessionSk;
+use crate::utils::get_epoch_ms;
+
+/// Size of an uncompressed public key, which ECIES output starts with.
+const EPHEMERAL_KEY_SIZE: usize = ecies::FULL_PUBLIC_KEY_SIZE;
+
+/// Max number of keys derived from ephemeral keys of peers, which are kept by receiver.
+pub const MAX_INBOUND_KEYS: usize = 256;
+
+/// Max number of public keys of peers kept by [PeerKeys].
+pub const MAX_PEER_KEYS: usize = 4096;
+
+/// Public key of a peer, with the session it belongs to.
+#[derive(Debug, Clone, Copy)]
+struct PeerKey {
+    pubkey: PublicKey,
+    session_id: Did,
+    created_at_ms: u128,
+}
+
+/// Encryption public keys of peers learned from their messages,
+/// cloning it shares the keys.
+#[derive(Debug, Clone, Default)]
+pub struct PeerKeys(Arc<DashMap<Did, PeerKey>>);
+
+impl PeerKeys {
+    /// Learn the session public key of signer of a signed message, such as a
+    /// [MessagePayload](crate::message::MessagePayload) or its transaction.
+    /// A new key is only accepted if its session is signed by the account of signer,
+    /// so that a relay cannot substitute the key of another peer.
+    ///
+    /// The key is only recovered from signature once per session. A session created before
+    /// the one of the known key is ignored, so that replayed messages never roll it back.
+    /// At most [MAX_PEER_KEYS] keys are kept, the key of the earliest session is dropped.
+    pub fn learn(&self, signed: &impl MessageVerificationExt) {
+        let did = signed.signer();
+        let session = &signed.verification().session;
+        if let Some(known) = self.0.get(&did) {
+            if known.session_id == session.session_id() {
+                return;
+            }
+            if session.created_at_ms() <= known.created_at_ms {
+                tracing::debug!("Ignore key of {} from an earlier session", did);
+                return;
+            }
+        }
+        if let Err(e) = session.verify_self() {
+            tracing::debug!("Ignore key of {} with invalid session: {:?}", did, e);
+            return;
+        }
+        let Ok(pubkey) = signed.signer_pubkey() else {
+            return;
+        };
+        if self.0.len() >= MAX_PEER_KEYS && !self.0.contains_key(&did) {
+            let earliest = self
+                .0
+                .iter()
+                .min_by_key(|entry| entry.created_at_ms)
+                .map(|entry| *entry.key());
+            if let Some(earliest) = earliest {
+                self.0.remove(&earliest);
+            }
+        }
+        self.0.insert(did, PeerKey {
+            pubkey,
+            session_id: session.session_id(),
+            created_at_ms: session.created_at_ms(),
+        });
+    }
+
+    /// Get public key of peer, None if it's not exchanged yet.
+    pub fn get(&self, did: Did) -> Option<PublicKey> {
+        self.0.get(&did).map(|key| key.pubkey)
+    }
+
+    /// Number of keys of peers kept.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Check if no key of peer is kept.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Forget public key of peer.
+    pub fn remove(&self, did: Did) {
+        self.0.remove(&did);
+    }
+}
+
+/// Policy of rotating the ephemeral key of encryption to a peer, see [SessionKeys].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyRotation {
+    /// Max number of messages encrypted by a key.
+    pub max_messages: u64,
+    /// Max age of a key, in milliseconds.
+    pub max_age_ms: u64,
+}
+
+impl Default for KeyRotation {
+    fn default() -> Self {
+        Self {
+            max_messages: 1000,
+            max_age_ms: 10 * 60 * 1000,
+        }
+    }
+}
+
+/// Max number of ephemeral keys of this node kept to decrypt data, the current one and the
+/// one before, which peers may still encrypt to until they learn the rotation.
+pub const MAX_EPHEMERAL_KEYS: usize = 2;
+
+/// An ephemeral key of this node, see [EphemeralKeyOffer].
+struct EphemeralKey {
+    sk: ecies::SecretKey,
+    pubkey: PublicKey,
+    created_at_ms: u128,
+}
+
+/// An ephemeral key offered by peer, with the session key of peer which signed the offer.
+#[derive(Debug, Clone, Copy)]
+struct PeerEphemeralKey {
+    pubkey: PublicKey,
+    session_pubkey: PublicKey,
+    created_at_ms: u128,
+}
+
+/// Key derived from the ephemeral key of sender, with the ephemeral key of this node which
+/// derived it, None if it's derived by the session key.
+struct InboundKey {
+    sender_ephemeral: [u8; EPHEMERAL_KEY_SIZE],
+    key: [u8; 32],
+    derived_by: Option<PublicKey>,
+}
+
+struct OutboundKey {
+    target: PublicKey,
+    ephemeral_pubkey: [u8; EPHEMERAL_KEY_SIZE],
+    key: [u8; 32],
+    created_at_ms: u128,
+    used: u64,
+}
+
+impl OutboundKey {
+    fn new(target: PublicKey) -> Result<Self> {
+        let pubkey = ecies::PublicKey::parse_slice(&target.0, None)
+            .map_err(Error::MessageEncryptionFailed)?;
+        let (ephemeral_sk, ephemeral_pk) = generate_keypair();
+        let key = encapsulate(&ephemeral_sk, &pubkey).map_err(Error::MessageEncryptionFailed)?;
+        Ok(Self {
+            target,
+            ephemeral_pubkey: ephemeral_pk.serialize(),
+            key: key.into(),
+            created_at_ms: get_epoch_ms(),
+            used: 0,
+        })
+    }
+
+    fn is_due(&self, target: PublicKey, rotation: &KeyRotation, now: u128) -> bool {
+        self.target != target
+            || self.used >= rotation.max_messages
+            || now.saturating_sub(self.created_at_ms) >= rotation.max_age_ms as u128
+    }
+}
+
+/// Derive the key of AES shared by `sk` with the ephemeral public key of sender.
+fn shared_key(sk: &ecies::SecretKey, sender_ephemeral: &[u8]) -> Result<[u8; 32]> {
+    let sender_ephemeral = ecies::PublicKey::parse_slice(sender_ephemeral, None)
+        .map_err(Error::MessageDecryptionFailed)?;
+    decapsulate(&sender_ephemeral, sk)
+        .map(Into::into)
+        .map_err(Error::MessageDecryptionFailed)
+}
+
+/// Keys of encryption to peers and from peers, cloning it shares the keys.
+/// See the [module level documentation](self).
+#[derive(Clone, Default)]
+pub struct SessionKeys {
+    rotation: KeyRotation,
+    outbound: Arc<DashMap<Did, OutboundKey>>,
+    inbound: Arc<Mutex<VecDeque<InboundKey>>>,
+    ephemeral: Arc<Mutex<VecDeque<EphemeralKey>>>,
+    peer_ephemeral: Arc<DashMap<Did, PeerEphemeralKey>>,
+}
+
+impl SessionKeys {
+    /// Create keys rotated by policy.
+    pub fn new(rotation: KeyRotation) -> Self {
+        Self {
+            rotation,
+            ..Default::default()
+        }
+    }
+
+    /// Offer of the current ephemeral key of this node, which is created on first use and
+    /// rotated after [KeyRotation::max_age_ms]. Returns whether the key is just created,
+    /// which should be offered to peers again. Only the latest [MAX_EPHEMERAL_KEYS] keys are
+    /// kept, with the keys derived by them, so that data encrypted to a forgotten key can't
+    /// be decrypted anymore.
+    pub fn ephemeral_offer(&self) -> (EphemeralKeyOffer, bool) {
+        let now = get_epoch_ms();
+        let mut keys = self.lock_ephemeral();
+        if let Some(key) = keys.back() {
+            if now.saturating_sub(key.created_at_ms) < self.rotation.max_age_ms as u128 {
+                let offer = EphemeralKeyOffer {
+                    pubkey: key.pubkey,
+                    created_at_ms: key.created_at_ms,
+                };
+                return (offer, false);
+            }
+        }
+
+        let (sk, pubkey) = generate_keypair();
+        let key = EphemeralKey {
+            sk,
+            pubkey: pubkey.into(),
+            created_at_ms: now,
+        };
+        let offer = EphemeralKeyOffer {
+            pubkey: key.pubkey,
+            created_at_ms: key.created_at_ms,
+        };
+        keys.push_back(key);
+        while keys.len() > MAX_EPHEMERAL_KEYS {
+            let Some(forgotten) = keys.pop_front() else {
+                break;
+            };
+            let mut inbound = self.lock_inbound();
+            inbound.retain(|k| k.derived_by != Some(forgotten.pubkey));
+        }
+        (offer, true)
+    }
+
+    /// Learn the ephemeral key offered by peer, which data to the peer is encrypted to while
+    /// the session key of peer is `session_pubkey`, the one signing the offer. An offer
+    /// created no later than the known one is ignored, so that replayed offers never roll
+    /// the key back. At most [MAX_PEER_KEYS] keys are kept, the earliest is dropped.
+    pub fn learn_peer_ephemeral(
+        &self,
+        did: Did,
+        session_pubkey: PublicKey,
+        offer: &EphemeralKeyOffer,
+    ) {
+        if ecies::PublicKey::parse_slice(&offer.pubkey.0, None).is_err() {
+            tracing::debug!("Ignore invalid ephemeral key of {}", did);
+            return;
+        }
+        let known = self.peer_ephemeral.get(&did).map(|k| *k);
+        if let Some(known) = known {
+            if known.session_pubkey == session_pubkey && offer.created_at_ms <= known.created_at_ms
+            {
+                return;
+            }
+        }
+        if known.is_none() && self.peer_ephemeral.len() >= MAX_PEER_KEYS {
+            let earliest = self
+                .peer_ephemeral
+                .iter()
+                .min_by_key(|entry| entry.created_at_ms)
+                .map(|entry| *entry.key());
+            if let Some(earliest) = earliest {
+                self.peer_ephemeral.remove(&earliest);
+            }
+        }
+        self.peer_ephemeral.insert(did, PeerEphemeralKey {
+            pubkey: offer.pubkey,
+            session_pubkey,
+            created_at_ms: offer.created_at_ms,
+        });
+    }
+
+    /// Get the ephemeral key offered by peer, None if no key is offered yet.
+    pub fn peer_ephemeral_key(&self, did: Did) -> Option<PublicKey> {
+        self.peer_ephemeral.get(&did).map(|k| k.pubkey)
+    }
+
+    /// Encrypt data to peer of session key `peer_pubkey`. Data is encrypted to the ephemeral
+    /// key offered by peer if any, otherwise to its session key. The key of peer is re-derived
+    /// if the peer rotated either key, or the key is due by [KeyRotation].
+    pub fn encrypt(&self, did: Did, peer_pubkey: PublicKey, data: &[u8]) -> Result<Vec<u8>> {
+        let now = get_epoch_ms();
+        let target = self
+            .peer_ephemeral
+            .get(&did)
+            .filter(|k| k.session_pubkey == peer_pubkey)
+            .map_or(peer_pubkey, |k| k.pubkey);
+        let due = self
+            .outbound
+            .get(&did)
+            .map_or(true, |key| key.is_due(target, &self.rotation, now));
+        if due {
+            tracing::debug!("Rotate encryption key of {}", did);
+            self.outbound.insert(did, OutboundKey::new(target)?);
+        }
+        let mut entry = self
+            .outbound
+            .get_mut(&did)
+            .ok_or(Error::PeerKeyUnknown(did))?;
+        entry.used += 1;
+        let encrypted = aes_encrypt(&entry.key, data)
+            .ok_or(Error::MessageEncryptionFailed(SecpError::InvalidMessage))?;
+        let mut output = Vec::with_capacity(EPHEMERAL_KEY_SIZE + encrypted.len());
+        output.extend_from_slice(&entry.ephemeral_pubkey);
+        output.extend(encrypted);
+        Ok(output)
+    }
+
+    /// Decrypt data encrypted to this node, by the key derived from its ephemeral key before
+    /// if any. Otherwise the key is derived by the ephemeral keys of this node kept, the latest
+    /// first, then by `session_sk`.
+    pub fn decrypt(&self, session_sk: &SessionSk, data: &[u8]) -> Result<Vec<u8>> {
+        if data.len() < EPHEMERAL_KEY_SIZE {
+            return Err(Error::MessageDecryptionFailed(SecpError::InvalidMessage));
+        }
+        let (sender_ephemeral, encrypted) = data.split_at(EPHEMERAL_KEY_SIZE);
+        if let Some(key) = self.inbound_key(sender_ephemeral) {
+            if let Some(decrypted) = aes_decrypt(&key, encrypted) {
+                return Ok(decrypted);
+            }
+            self.forget_inbound(sender_ephemeral);
+        }
+
+        let ephemeral = self
+            .ephemeral
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .map(|k| (k.sk, k.pubkey))
+            .collect::<Vec<_>>();
+        for (sk, pubkey) in ephemeral {
+            let key = shared_key(&sk, sender_ephemeral)?;
+            if let Some(decrypted) = aes_decrypt(&key, encrypted) {
+                self.remember_inbound(sender_ephemeral, key, Some(pubkey));
+                return Ok(decrypted);
+            }
+        }
+
+        let key = session_sk.shared_key(sender_ephemeral)?;
+        let decrypted = aes_decrypt(&key, encrypted)
+            .ok_or(Error::MessageDecryptionFailed(SecpError::InvalidMessage))?;
+        self.remember_inbound(sender_ephemeral, key, None);
+        Ok(decrypted)
+    }
+
+    /// Forget the key of encryption to peer, so that a new one is derived on next encryption.
+    pub fn rotate(&self, did: Did) {
+        self.outbound.remove(&did);
+    }
+
+    /// Number of messages encrypted to peer by its current key, None if there is no key.
+    pub fn used(&self, did: Did) -> Option<u64> {
+        self.outbound.get(&did).map(|key| key.used)
+    }
+
+    fn lock_inbound(&self) -> MutexGuard<'_, VecDeque<InboundKey>> {
+        self.inbound.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_ephemeral(&self) -> MutexGuard<'_, VecDeque<EphemeralKey>> {
+        self.ephemeral.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn inbound_key(&self, sender_ephemeral: &[u8]) -> Option<[u8; 32]> {
+        let inbound = self.lock_inbound();
+        inbound
+            .iter()
+            .find(|k| k.sender_ephemeral[..] == *sender_ephemeral)
+            .map(|k| k.key)
+    }
+
+    fn remember_inbound(&self, sender_ephemeral: &[u8], key: [u8; 32], by: Option<PublicKey>) {
+        let mut inbound = self.lock_inbound();
+        if inbound.len() >= MAX_INBOUND_KEYS {
+            inbound.pop_front();
+        }
+        let mut ephemeral = [0u8; EPHEMERAL_KEY_SIZE];
+        ephemeral.copy_from_slice(sender_ephemeral);
+        inbound.push_back(InboundKey {
+            sender_ephemeral: ephemeral,
+            key,
+            derived_by: by,
+        });
+    }
+
+    fn forget_inbound(&self, sender_ephemeral: &[u8]) {
+        let mut inbound = self.lock_inbound();
+        inbound.retain(|k| k.sender_ephemeral[..] != *sender_ephemeral);
+    }
+}
+
+/// An offer replaces the ephemeral key known of its signer, bound to the session key which
+/// signed it, see [SessionKeys::learn_peer_ephemeral].
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl HandleMsg<EphemeralKeyOffer> for MessageHandler {
+    async fn handle(
+        &self,
+        ctx: &MessagePayload,
+        msg: &EphemeralKeyOffer,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        let session_pubkey = ctx.transaction.signer_pubkey()?;
+        self.session_keys()
+            .learn_peer_ephemeral(ctx.transaction.signer(), session_pubkey, msg);
+        Ok(vec![])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ecc::SecretKey;
+    use crate::message::Message;
+    use crate::message::MessagePayload;
+    use crate::session::SessionSk;
+
+    #[test]
+    fn test_peer_keys() {
+        let key = SecretKey::random();
+        let session_sk = SessionSk::new_with_seckey(&key).unwrap();
+        let did = key.address().into();
+        let keys = PeerKeys::default();
+        assert_eq!(keys.get(did), None);
+
+        let payload = MessagePayload::new_send(
+            Message::custom("hello".as_bytes()).unwrap(),
+            &session_sk,
+            did,
+            did,
+        )
+        .unwrap();
+        keys.learn(&payload);
+        assert_eq!(keys.get(did), Some(session_sk.pubkey()));
+
+        // Data encrypted to the key can be decrypted by the session of peer.
+        let encrypted = ecies::encrypt(&keys.get(did).unwrap().0, b"secret").unwrap();
+        assert_eq!(session_sk.decrypt(&encrypted).unwrap(), b"secret");
+
+        // A later session replaces the key, while an earlier one never rolls it back.
+        let earlier = SessionSk::new_with_seckey(&key).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let later = SessionSk::new_with_seckey(&key).unwrap();
+        let signed_by = |sk: &SessionSk| {
+            MessagePayload::new_send(Message::custom(b"hello").unwrap(), sk, did, did).unwrap()
+        };
+        keys.learn(&signed_by(&later));
+        assert_eq!(keys.get(did), Some(later.pubkey()));
+        keys.learn(&signed_by(&earlier));
+        assert_eq!(keys.get(did), Some(later.pubkey()));
+        assert_eq!(keys.len(), 1);
+
+        keys.remove(did);
+        assert_eq!(keys.get(did), None);
+    }
+
+    #[test]
+    fn test_ephemeral_keys() {
+        let receiver_sk = SessionSk::new_with_seckey(&SecretKey::random()).unwrap();
+        let did: Did = 1u32.into();
+        let sender = SessionKeys::default();
+        // Ephemeral keys of receiver are rotated whenever they are offered.
+        let receiver = SessionKeys::new(KeyRotation {
+            max_messages: 1000,
+            max_age_ms: 0,
+        });
+        let offer = || {
+            std::thread::sleep(std::time::Duration::from_millis(2));
+            let (offer, rotated) = receiver.ephemeral_offer();
+            assert!(rotated);
+            offer
+        };
+
+        let first_offer = offer();
+        sender.learn_peer_ephemeral(did, receiver_sk.pubkey(), &first_offer);
+        assert_eq!(sender.peer_ephemeral_key(did), Some(first_offer.pubkey));
+        let first = sender.encrypt(did, receiver_sk.pubkey(), b"first").unwrap();
+        // Data is encrypted to the ephemeral key, rather than the session key.
+        assert!(receiver_sk.decrypt(&first).is_err());
+        assert_eq!(receiver.decrypt(&receiver_sk, &first).unwrap(), b"first");
+
+        // A replayed offer never rolls the key back.
+        let second_offer = offer();
+        sender.learn_peer_ephemeral(did, receiver_sk.pubkey(), &second_offer);
+        sender.learn_peer_ephemeral(did, receiver_sk.pubkey(), &first_offer);
+        assert_eq!(sender.peer_ephemeral_key(did), Some(second_offer.pubkey));
+        let second = sender
+            .encrypt(did, receiver_sk.pubkey(), b"second")
+            .unwrap();
+        assert_ne!(first[..EPHEMERAL_KEY_SIZE], second[..EPHEMERAL_KEY_SIZE]);
+        assert_eq!(receiver.decrypt(&receiver_sk, &second).unwrap(), b"second");
+
+        // Once the first key is forgotten, data encrypted to it can't be decrypted anymore,
+        // even with the session key of receiver.
+        offer();
+        assert!(receiver.decrypt(&receiver_sk, &first).is_err());
+        assert_eq!(receiver.decrypt(&receiver_sk, &second).unwrap(), b"second");
+
+        // The ephemeral key is bound to the session offering it.
+        let rotated = SessionSk::new_with_seckey(&SecretKey::random()).unwrap();
+        let encrypted = sender.encrypt(did, rotated.pubkey(), b"rotated").unwrap();
+        assert_eq!(rotated.decrypt(&encrypted).unwrap(), b"rotated");
+    }
+
+    #[test]
+    fn test_session_keys_rotation() {
+        let receiver = SessionSk::new_with_seckey(&SecretKey::random()).unwrap();
+        let did: Did = 1u32.into();
+        let sender = SessionKeys::new(KeyRotation {
+            max_messages: 2,
+            max_age_ms: 60 * 1000,
+        });
+        let inbound = SessionKeys::default();
+
+        let first = sender.encrypt(did, receiver.pubkey(), b"first").unwrap();
+        let second = sender.encrypt(did, receiver.pubkey(), b"second").unwrap();
+        let third = sender.encrypt(did, receiver.pubkey(), b"third").unwrap();
+        // The ephemeral key is reused until rotated after 2 messages.
+        assert_eq!(first[..EPHEMERAL_KEY_SIZE], second[..EPHEMERAL_KEY_SIZE]);
+        assert_ne!(second[..EPHEMERAL_KEY_SIZE], third[..EPHEMERAL_KEY_SIZE]);
+        assert_eq!(sender.used(did), Some(1));
+
+        // Output is plain ECIES, which is decrypted with or without cached keys.
+        assert_eq!(receiver.decrypt(&first).unwrap(), b"first");
+        for (encrypted, data) in [(first, &b"first"[..]), (second, &b"second"[..])] {
+            assert_eq!(inbound.decrypt(&receiver, &encrypted).unwrap(), data);
+        }
+        assert_eq!(inbound.inbound.lock().unwrap().len(), 1);
+        assert_eq!(inbound.decrypt(&receiver, &third).unwrap(), b"third");
+
+        // The peer rotates its session key, so a new key is derived for it.
+        let rotated = SessionSk::new_with_seckey(&SecretKey::random()).unwrap();
+        let encrypted = sender.encrypt(did, rotated.pubkey(), b"rotated").unwrap();
+        assert_eq!(sender.used(did), Some(1));
+        assert!(inbound.decrypt(&receiver, &encrypted).is_err());
+        assert_eq!(inbound.decrypt(&rotated, &encrypted).unwrap(), b"rotated");
+    }
+}
@@ -0,0 +1,319 @@
+#![warn(missing_docs)]
+//! Cancellation of DHT lookups.
+//!
+//! A lookup, such as `FindSuccessorSend` or `SearchVNode`, travels many hops before its
+//! report comes back, see [Swarm::lookup_successor] and [Swarm::lookup_vnode]. Both accept a
+//! [CancellationToken], and return [Error::LookupCancelled](crate::error::Error) as soon as
+//! it's cancelled. Retries to alternate hops are stopped, the lookup is removed from
+//! [PendingLookups], and its report is discarded if it arrives later. A cancelled lookup
+//! passing through this node again is not forwarded further.
+//!
+//! Hops which already forwarded the lookup can't be recalled, so the lookup may still
+//! travel on remote nodes until it's answered or expired.
+//!
+//! Lookups forwarded for other nodes are retried without blocking: a failed hop is replaced
+//! by an alternate one, and the resigned payload waits in [RetryQueue] until its backoff is
+//! due, while the listening loop of swarm keeps handling other messages.
+//!
+//! [Swarm::lookup_successor]: crate::swarm::Swarm::lookup_successor
+//! [Swarm::lookup_vnode]: crate::swarm::Swarm::lookup_vnode
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::task::Context;
+use std::task::Poll;
+use std::task::Waker;
+
+use dashmap::DashMap;
+use futures::channel::oneshot;
+
+use crate::dht::vnode::VirtualNode;
+use crate::dht::Did;
+use crate::message::MessagePayload;
+use crate::swarm::types::RetryBudget;
+
+/// Default time waiting for the report of a lookup, in milliseconds.
+pub const LOOKUP_TIMEOUT_MS: u64 = 30 * 1000;
+
+/// Max number of abandoned lookups remembered, whose late reports are discarded.
+pub const MAX_ABANDONED_LOOKUPS: usize = 256;
+
+/// Max number of forwarded lookups waiting for retry, see [RetryQueue].
+pub const MAX_SCHEDULED_RETRIES: usize = 256;
+
+/// A token to cancel lookups, which runs on both native and browser runtime.
+/// Cloned tokens share the same state, so cancelling any of them cancels all.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<CancellationState>);
+
+#[derive(Debug, Default)]
+struct CancellationState {
+    cancelled: AtomicBool,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl CancellationState {
+    fn wakers(&self) -> MutexGuard<'_, Vec<Waker>> {
+        self.wakers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl CancellationToken {
+    /// Create a token not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the token, and wake up all waiting on [CancellationToken::cancelled].
+    pub fn cancel(&self) {
+        if self.0.cancelled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let wakers = std::mem::take(&mut *self.0.wakers());
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Check if the token is cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// A future which is ready once the token is cancelled.
+    pub fn cancelled(&self) -> Cancelled {
+        Cancelled(self.clone())
+    }
+}
+
+/// Future returned by [CancellationToken::cancelled].
+#[derive(Debug)]
+pub struct Cancelled(CancellationToken);
+
+impl Future for Cancelled {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0.is_cancelled() {
+            return Poll::Ready(());
+        }
+        let mut wakers = self.0 .0.wakers();
+        // Checked again under lock, since the token may be cancelled meanwhile.
+        if self.0.is_cancelled() {
+            return Poll::Ready(());
+        }
+        if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+/// Report of a lookup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LookupReport {
+    /// Successor of a did, answering `FindSuccessorSend`.
+    Successor(Did),
+    /// Virtual nodes found, answering `SearchVNode`.
+    VNodes(Vec<VirtualNode>),
+}
+
+/// Lookups waiting for reports, keyed by transaction id.
+/// Cloned sets share the same lookups.
+#[derive(Debug, Clone, Default)]
+pub struct PendingLookups {
+    pending: Arc<DashMap<uuid::Uuid, oneshot::Sender<LookupReport>>>,
+    abandoned: Arc<Mutex<VecDeque<uuid::Uuid>>>,
+}
+
+impl PendingLookups {
+    fn abandoned(&self) -> MutexGuard<'_, VecDeque<uuid::Uuid>> {
+        self.abandoned.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Wait for the report of a lookup sent in transaction `tx_id`.
+    pub fn register(&self, tx_id: uuid::Uuid) -> oneshot::Receiver<LookupReport> {
+        let (sender, receiver) = oneshot::channel();
+        self.pending.insert(tx_id, sender);
+        receiver
+    }
+
+    /// Forget a lookup which is cancelled, timed out or failed on sending. Its report is
+    /// discarded if it arrives later, see [PendingLookups::is_abandoned].
+    pub fn abandon(&self, tx_id: uuid::Uuid) {
+        self.pending.remove(&tx_id);
+        let mut abandoned = self.abandoned();
+        if abandoned.len() >= MAX_ABANDONED_LOOKUPS {
+            abandoned.pop_front();
+        }
+        abandoned.push_back(tx_id);
+    }
+
+    /// Check if a lookup is abandoned recently, so that it's neither forwarded further nor
+    /// reported.
+    pub fn is_abandoned(&self, tx_id: uuid::Uuid) -> bool {
+        self.abandoned().contains(&tx_id)
+    }
+
+    /// Deliver the report of a lookup. Returns false if no lookup is waiting for it.
+    pub fn resolve(&self, tx_id: uuid::Uuid, report: LookupReport) -> bool {
+        match self.pending.remove(&tx_id) {
+            Some((_, sender)) => sender.send(report).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Number of lookups waiting.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Check if no lookup is waiting.
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// A forwarded lookup waiting for its backoff before being sent to an alternate hop.
+#[derive(Debug, Clone)]
+pub struct ScheduledRetry {
+    /// Payload resigned towards the alternate hop.
+    pub payload: MessagePayload,
+    /// Retry budget of the lookup.
+    pub budget: RetryBudget,
+    /// Hops already tried.
+    pub tried: Vec<Did>,
+    /// Attempt number of this retry, starting from 2.
+    pub attempt: u8,
+    /// Time when the retry is due, in milliseconds since epoch.
+    pub due_ms: u128,
+}
+
+/// Retries of forwarded lookups waiting for their backoff. They're run by the listening
+/// loop of swarm once due, so that a backoff never stalls other messages.
+#[derive(Debug, Clone, Default)]
+pub struct RetryQueue(Arc<Mutex<Vec<ScheduledRetry>>>);
+
+impl RetryQueue {
+    fn lock(&self) -> MutexGuard<'_, Vec<ScheduledRetry>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Schedule a retry. Returns false if [MAX_SCHEDULED_RETRIES] retries are waiting.
+    pub fn schedule(&self, retry: ScheduledRetry) -> bool {
+        let mut retries = self.lock();
+        if retries.len() >= MAX_SCHEDULED_RETRIES {
+            return false;
+        }
+        retries.push(retry);
+        true
+    }
+
+    /// Take retries which are due at `now_ms`, earliest first.
+    pub fn take_due(&self, now_ms: u128) -> Vec<ScheduledRetry> {
+        let mut retries = self.lock();
+        let (mut due, pending) = std::mem::take(&mut *retries)
+            .into_iter()
+            .partition::<Vec<_>, _>(|r| r.due_ms <= now_ms);
+        *retries = pending;
+        due.sort_by_key(|r| r.due_ms);
+        due
+    }
+
+    /// Time when the earliest retry is due, if any.
+    pub fn next_due_ms(&self) -> Option<u128> {
+        self.lock().iter().map(|r| r.due_ms).min()
+    }
+
+    /// Number of retries waiting.
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Check if no retry is waiting.
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::FutureExt;
+
+    use super::*;
+
+    #[test]
+    fn test_cancellation_token() {
+        let token = CancellationToken::new();
+        let cloned = token.clone();
+        let mut cancelled = Box::pin(token.cancelled());
+        assert!((&mut cancelled).now_or_never().is_none());
+
+        cloned.cancel();
+        assert!(token.is_cancelled());
+        futures::executor::block_on(cancelled);
+        futures::executor::block_on(cloned.cancelled());
+    }
+
+    #[test]
+    fn test_pending_lookups() {
+        let lookups = PendingLookups::default();
+        let (tx1, tx2) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+        let mut receiver = lookups.register(tx1);
+        let _receiver2 = lookups.register(tx2);
+        assert_eq!(lookups.len(), 2);
+
+        let report = LookupReport::Successor(1u32.into());
+        assert!(lookups.resolve(tx1, report.clone()));
+        assert_eq!(receiver.try_recv().unwrap(), Some(report.clone()));
+
+        lookups.abandon(tx2);
+        assert!(lookups.is_empty());
+        assert!(lookups.is_abandoned(tx2));
+        assert!(!lookups.resolve(tx2, report));
+
+        for _ in 0..MAX_ABANDONED_LOOKUPS {
+            lookups.abandon(uuid::Uuid::new_v4());
+        }
+        assert!(!lookups.is_abandoned(tx2));
+    }
+
+    #[test]
+    fn test_retry_queue() {
+        let key = crate::ecc::SecretKey::random();
+        let session_sk = crate::session::SessionSk::new_with_seckey(&key).unwrap();
+        let did = session_sk.account_did();
+        let payload = MessagePayload::new_send("lookup", &session_sk, did, did).unwrap();
+        let retry = |due_ms| ScheduledRetry {
+            payload: payload.clone(),
+            budget: RetryBudget::default(),
+            tried: vec![did],
+            attempt: 2,
+            due_ms,
+        };
+
+        let queue = RetryQueue::default();
+        assert!(queue.next_due_ms().is_none());
+        assert!(queue.schedule(retry(300)));
+        assert!(queue.schedule(retry(100)));
+        assert!(queue.schedule(retry(200)));
+        assert_eq!(queue.next_due_ms(), Some(100));
+
+        let due = queue.take_due(200);
+        assert_eq!(due.iter().map(|r| r.due_ms).collect::<Vec<_>>(), vec![
+            100, 200
+        ]);
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.next_due_ms(), Some(300));
+
+        for _ in 1..MAX_SCHEDULED_RETRIES {
+            assert!(queue.schedule(retry(400)));
+        }
+        assert!(!queue.schedule(retry(400)));
+    }
+}
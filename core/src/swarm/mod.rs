@@ -1,51 +1,178 @@
 #![warn(missing_docs)]
 //! Tranposrt management
 
+mod auth;
 mod builder;
 /// Callback interface for swarm
 pub mod callback;
+mod drop_log;
+mod expiry;
+/// Gate of connections
+pub mod gate;
+mod heartbeat;
 /// Implementations of connection management traits for swarm
 pub mod impls;
+mod keys;
+/// Cancellation of DHT lookups
+pub mod lookup;
+mod mtu;
+mod nat;
+mod path;
+mod profile;
+mod queue;
+mod relay;
+mod score;
+/// Direct streams between connected peers
+pub mod stream;
+pub mod transform;
+mod trickle;
 mod types;
 
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::sync::RwLock;
 
 use async_recursion::async_recursion;
 use async_trait::async_trait;
+pub use auth::AuthAction;
+pub use auth::Authorizer;
+pub use auth::AuthorizerImpl;
+pub use auth::CachedAuthorizer;
+pub use auth::Decision;
 pub use builder::SwarmBuilder;
+use dashmap::DashMap;
+pub use drop_log::Deadletter;
+pub use drop_log::DeadletterFn;
+pub use drop_log::DropLogConfig;
+pub use drop_log::DropReason;
+pub use expiry::ExpiryPolicy;
+pub use expiry::MAX_EXPIRY_GRACE_MS;
+pub use gate::ConnectionGate;
+pub use gate::ConnectionGateImpl;
+pub use gate::DidListGate;
+pub use gate::IpLimitConfig;
+pub use gate::IpLimiter;
+pub use heartbeat::BusyGuard;
+pub use heartbeat::Heartbeat;
+pub use heartbeat::HeartbeatGuard;
+pub use heartbeat::Pulse;
+pub use keys::KeyRotation;
+pub use keys::PeerKeys;
+pub use keys::SessionKeys;
+pub use lookup::CancellationToken;
+pub use mtu::MtuBounds;
+pub use mtu::PeerMtus;
+pub use mtu::MAX_MTU;
+pub use mtu::MIN_MTU;
+pub use nat::NatDiscovery;
+pub use nat::NatDiscoveryChain;
+pub use nat::NatDiscoveryImpl;
+pub use nat::StaticAddress;
+pub use nat::StunDiscovery;
+pub use path::ByPriority;
+pub use path::LowestLatency;
+pub use path::PathCandidate;
+pub use path::PathKind;
+pub use path::PreferDirect;
+pub use path::SendPathSelector;
+pub use path::SendPathSelectorImpl;
+pub use path::SendPriority;
+pub use profile::NetworkProfile;
+pub use profile::DEFAULT_STABILIZE_INTERVAL;
+pub use relay::inverse_latency;
+pub use relay::inverse_load;
+pub use relay::ClosestRelay;
+pub use relay::ObservedRelays;
+pub use relay::RelayCandidate;
+pub use relay::RelaySelector;
+pub use relay::RelaySelectorImpl;
+pub use relay::WeightedRandomRelay;
 use rings_derive::JudgeConnection;
 use rings_transport::core::transport::BoxedTransport;
 use rings_transport::core::transport::ConnectionInterface;
 use rings_transport::core::transport::TransportMessage;
 use rings_transport::error::Error as TransportError;
+use rings_transport::timing::ConnectionTiming;
+pub use score::PeerScores;
+pub use score::PeerSignal;
+pub use score::FLAKY_SCORE;
+pub use score::MAX_SCORE;
+pub use score::SCORE_HALF_LIFE_MS;
+pub use score::SLOW_RTT_MS;
+pub use stream::StreamReader;
+pub use stream::StreamWriter;
+pub use stream::Streams;
+pub use transform::PayloadPipeline;
+pub use transform::PayloadTransform;
+pub use transform::PayloadTransformConfig;
+pub use transform::PayloadTransformImpl;
+pub use transform::TransformStage;
+pub use trickle::accepts_trickle;
+pub use trickle::mark_trickle;
+pub use trickle::PendingCandidates;
+pub use trickle::TricklePeers;
+pub use trickle::MAX_PENDING_CANDIDATES;
+pub use trickle::MAX_TRICKLE_PEERS;
+pub use trickle::TRICKLE_ATTRIBUTE;
+pub use types::Convergence;
 pub use types::MeasureImpl;
+pub use types::PinnedPeers;
+pub use types::RetryBudget;
+pub use types::TrustedTransports;
 pub use types::WrappedDid;
+pub use types::DEFAULT_SEND_TIMEOUT_MS;
+pub use types::INITIAL_CONVERGENCE_TIMEOUT_MS;
+pub use types::MAX_LOOKUP_ATTEMPTS;
 
 use crate::channels::Channel;
 use crate::dht::types::Chord;
+use crate::dht::vnode::VirtualNode;
+use crate::dht::ChordStorage;
 use crate::dht::CorrectChord;
 use crate::dht::Did;
 use crate::dht::PeerRing;
+use crate::dht::PeerRingAction;
+use crate::dht::SuccessorReader;
+use crate::ecc::PublicKey;
 use crate::error::Error;
 use crate::error::Result;
 use crate::inspect::SwarmInspect;
 use crate::message;
+use crate::message::handlers::announce::KnownNode;
+use crate::message::handlers::announce::ANNOUNCE_INTERVAL_MS;
+use crate::message::handlers::routing::RoutingSnapshot;
+use crate::message::handlers::stabilization::sync_vnode_with_successor;
 use crate::message::types::NotifyPredecessorSend;
 use crate::message::ChordStorageInterface;
+use crate::message::CompressionDictionaries;
 use crate::message::Message;
 use crate::message::MessageHandler;
 use crate::message::MessageHandlerEvent;
 use crate::message::MessagePayload;
+use crate::message::MessageRelay;
 use crate::message::MessageVerificationExt;
 use crate::message::PayloadSender;
+use crate::metrics;
+use crate::metrics::MetricsImpl;
 use crate::session::SessionSk;
 use crate::swarm::callback::SharedSwarmCallback;
+use crate::swarm::drop_log::DropLog;
 use crate::swarm::impls::ConnectionHandshake;
+use crate::swarm::lookup::LookupReport;
+use crate::swarm::lookup::RetryQueue;
+use crate::swarm::lookup::ScheduledRetry;
+use crate::swarm::lookup::LOOKUP_TIMEOUT_MS;
+use crate::swarm::queue::SendQueue;
+use crate::traffic::TrafficCounters;
+use crate::traffic::TrafficMeter;
 use crate::types::channel::Channel as ChannelTrait;
 use crate::types::channel::TransportEvent;
 use crate::types::Connection;
 use crate::types::ConnectionOwner;
+use crate::utils::get_epoch_ms;
+use crate::utils::sleep_ms;
+use crate::utils::timeout_ms;
 
 /// The transport and dht management.
 #[derive(JudgeConnection)]
@@ -56,10 +183,44 @@ pub struct Swarm {
     pub(crate) dht: Arc<PeerRing>,
     /// Implementationof measurement.
     pub(crate) measure: Option<MeasureImpl>,
+    /// Recorder of metrics.
+    pub(crate) metrics: MetricsImpl,
     session_sk: SessionSk,
     message_handler: MessageHandler,
     transport: BoxedTransport<ConnectionOwner, TransportError>,
     callback: RwLock<SharedSwarmCallback>,
+    trusted_transports: TrustedTransports,
+    pinned_peers: PinnedPeers,
+    connection_gate: Option<ConnectionGateImpl>,
+    ip_limiter: Option<IpLimiter>,
+    authorizer: Option<AuthorizerImpl>,
+    lookup_retry: RetryBudget,
+    nat_discovery: NatDiscoveryImpl,
+    send_timeout_ms: u64,
+    relay_selector: RelaySelectorImpl,
+    path_selector: SendPathSelectorImpl,
+    compact_payload: bool,
+    compress_relay_path: bool,
+    peer_scores: PeerScores,
+    peer_keys: PeerKeys,
+    observed_relays: ObservedRelays,
+    session_keys: SessionKeys,
+    peer_mtus: PeerMtus,
+    streams: Streams,
+    traffic: TrafficMeter<Did>,
+    send_queues: DashMap<Did, SendQueue>,
+    scheduled_retries: RetryQueue,
+    drop_log: DropLog,
+    announcement: Option<Vec<String>>,
+    dictionaries: CompressionDictionaries,
+    pending_candidates: PendingCandidates,
+    trickle_peers: TricklePeers,
+    payload_pipeline: PayloadPipeline,
+    listen_heartbeat: Heartbeat,
+    last_announced_ms: AtomicU64,
+    expiry_policy: ExpiryPolicy,
+    no_dht: bool,
+    convergence: Convergence,
 }
 
 impl Swarm {
@@ -73,6 +234,42 @@ impl Swarm {
         self.dht.clone()
     }
 
+    /// Check if swarm participates in DHT, see [SwarmBuilder::no_dht].
+    pub fn dht_enabled(&self) -> bool {
+        !self.no_dht
+    }
+
+    /// Decide whether peer may take an action by the authorizer of swarm,
+    /// see [SwarmBuilder::authorizer]. Everything is allowed without authorizer.
+    pub async fn authorize(&self, did: Did, action: &AuthAction) -> Decision {
+        match &self.authorizer {
+            Some(authorizer) => authorizer.authorize(did, action).await,
+            None => Decision::Allow,
+        }
+    }
+
+    /// Discover external address of node by [NatDiscovery] of swarm, the address found
+    /// is announced in ICE candidates of connections created afterwards.
+    pub async fn discover_external_address(&self) -> Result<Option<String>> {
+        let addr = self.nat_discovery.discover().await?;
+        if let Some(addr) = &addr {
+            tracing::info!("external address discovered: {}", addr);
+            self.transport.set_external_address(Some(addr.clone()));
+        }
+        Ok(addr)
+    }
+
+    /// Check if initial convergence of DHT is completed, see [Convergence].
+    pub fn is_converged(&self) -> bool {
+        self.convergence.is_converged()
+    }
+
+    /// Check if payload is a message of DHT maintenance, which is prioritized during
+    /// initial convergence.
+    fn is_maintenance(payload: &MessagePayload) -> bool {
+        SendPriority::of_transaction(&payload.transaction) == SendPriority::Maintenance
+    }
+
     /// Retrieves the session sk associated with the current instance.
     /// The session sk provides a segregated approach to manage private keys.
     /// It generates session secret keys for the bound entries of PKIs (Public Key Infrastructure).
@@ -80,27 +277,669 @@ impl Swarm {
         &self.session_sk
     }
 
+    /// Find the connected peer closest to destination, measured clockwise on the ring.
+    /// Returns `None` if no connected peer is closer to destination than self.
+    pub fn closest_connected_peer(&self, destination: Did) -> Option<Did> {
+        ClosestRelay.select(destination, &self.relay_candidates(destination, &[]))
+    }
+
+    /// Connected peers closer to destination than self, except the given ones,
+    /// with their load of sendings, see [RelayCandidate].
+    fn relay_candidates(&self, destination: Did, except: &[Did]) -> Vec<RelayCandidate> {
+        self.get_connection_ids()
+            .into_iter()
+            .filter(|did| !except.contains(did))
+            .filter(|did| destination - *did < destination - self.did())
+            .map(|did| {
+                let queue = self.send_queues.get(&did);
+                RelayCandidate {
+                    did,
+                    in_flight: queue.as_ref().map(|q| q.in_flight()).unwrap_or_default(),
+                    avg_send_ms: queue.and_then(|q| q.avg_send_ms()),
+                    score: self.peer_scores.score(did),
+                }
+            })
+            .collect()
+    }
+
+    /// Choose a relay towards destination by the relay selector of swarm,
+    /// see [SwarmBuilder::relay_selector]. Flaky peers, which score lower than
+    /// [FLAKY_SCORE], are avoided unless no other peer can relay.
+    fn select_relay(&self, destination: Did, except: &[Did]) -> Option<Did> {
+        let mut candidates = self.relay_candidates(destination, except);
+        if candidates.iter().any(|c| c.score >= FLAKY_SCORE) {
+            candidates.retain(|c| c.score >= FLAKY_SCORE);
+        }
+        if candidates.is_empty() {
+            return None;
+        }
+        self.relay_selector.select(destination, &candidates)
+    }
+
+    /// Viable paths towards destination, see [PathKind]. Paths through flaky peers, which
+    /// score lower than [FLAKY_SCORE], are dropped unless no other path exists.
+    pub fn path_candidates(&self, destination: Did) -> Vec<PathCandidate> {
+        let mut paths = vec![];
+        if destination != self.did() && self.get_connection(destination).is_some() {
+            paths.push((destination, PathKind::Direct));
+        }
+        let dht_hop = match self.dht.find_successor(destination) {
+            Ok(PeerRingAction::Some(did)) | Ok(PeerRingAction::RemoteAction(did, _)) => Some(did),
+            _ => None,
+        };
+        if let Some(hop) = dht_hop {
+            if hop != destination && hop != self.did() && self.get_connection(hop).is_some() {
+                paths.push((hop, PathKind::Dht));
+            }
+        }
+        if let Some(relay) = self.select_relay(destination, &[]) {
+            if paths.iter().all(|(hop, _)| *hop != relay) {
+                paths.push((relay, PathKind::Relay));
+            }
+        }
+
+        let mut candidates = paths
+            .into_iter()
+            .map(|(hop, kind)| {
+                let queue = self.send_queues.get(&hop);
+                PathCandidate {
+                    hop,
+                    kind,
+                    in_flight: queue.as_ref().map(|q| q.in_flight()).unwrap_or_default(),
+                    avg_send_ms: queue.and_then(|q| q.avg_send_ms()),
+                    score: self.peer_scores.score(hop),
+                }
+            })
+            .collect::<Vec<_>>();
+        if candidates.iter().any(|c| c.score >= FLAKY_SCORE) {
+            candidates.retain(|c| c.score >= FLAKY_SCORE);
+        }
+        candidates
+    }
+
+    /// Infer the next hop towards destination for a message of priority, by the send path
+    /// selector of swarm, see [SwarmBuilder::path_selector].
+    pub fn infer_next_hop_with_priority(
+        &self,
+        destination: Did,
+        priority: SendPriority,
+    ) -> Result<Did> {
+        // Without DHT, messages are only delivered to connected peers.
+        if self.no_dht {
+            return self
+                .get_connection(destination)
+                .map(|_| destination)
+                .ok_or(Error::NoNextHop);
+        }
+
+        if let PeerRingAction::Some(did) = self.dht.find_successor(destination)? {
+            if did == self.did() {
+                return Ok(did);
+            }
+        }
+        let candidates = self.path_candidates(destination);
+        if candidates.is_empty() {
+            return Err(Error::NoNextHop);
+        }
+        self.path_selector
+            .select(destination, priority, &candidates)
+            .ok_or(Error::NoNextHop)
+    }
+
+    /// Get score of peer based on its observed behaviour, see [PeerSignal].
+    /// Scores decay towards zero, and a peer never observed scores zero.
+    pub fn peer_score(&self, did: Did) -> f64 {
+        self.peer_scores.score(did)
+    }
+
+    /// Record a behaviour of peer observed out of swarm, such as a rate limit violation
+    /// of a service, or a round trip measured by application.
+    pub fn record_peer_signal(&self, did: Did, signal: PeerSignal) {
+        self.peer_scores.record(did, signal)
+    }
+
+    /// Encryption public key of peer, which is exchanged by any signed message from it,
+    /// see [PeerKeys]. None if no message is received from the peer yet.
+    pub fn peer_public_key(&self, did: Did) -> Option<PublicKey> {
+        self.peer_keys.get(did)
+    }
+
+    /// The connected peer which recently relayed a message signed by did, see [ObservedRelays].
+    /// None if no message of did is relayed to this node recently.
+    pub fn observed_relay(&self, did: Did) -> Option<Did> {
+        self.observed_relays.get(did)
+    }
+
+    /// Encrypt data to peer, so that only the peer can decrypt it by [Swarm::decrypt].
+    /// Fails with [Error::PeerKeyUnknown] if its key is not exchanged yet, sending any message
+    /// to the peer prompts a reply which carries the key. Data is encrypted to the ephemeral
+    /// key offered by peer for forward secrecy, or to its session key if none is offered.
+    /// The key shared with peer is cached and rotated, see [SessionKeys].
+    pub fn encrypt_for(&self, did: Did, data: &[u8]) -> Result<Vec<u8>> {
+        let pubkey = self
+            .peer_public_key(did)
+            .ok_or(Error::PeerKeyUnknown(did))?;
+        self.session_keys.encrypt(did, pubkey, data)
+    }
+
+    /// Decrypt data encrypted to this node by [Swarm::encrypt_for].
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        self.session_keys.decrypt(&self.session_sk, data)
+    }
+
+    /// Negotiated MTU of the link to a connected peer, see [PeerMtus].
+    pub fn peer_mtu(&self, did: Did) -> usize {
+        self.peer_mtus.mtu(did)
+    }
+
+    /// Infer the next hop towards destination, with the negotiated MTU of the link to it,
+    /// which messages sent to destination should be chunked by. Chunks should be sent by
+    /// the same next hop, see [PayloadSender::send_message_by_hop], and their results be
+    /// recorded against it, so that the MTU is learned for the link they are sent over.
+    pub fn next_hop_mtu(&self, destination: Did) -> Result<(Did, usize)> {
+        let next_hop = self.infer_next_hop(None, destination)?;
+        Ok((next_hop, self.peer_mtus.mtu(next_hop)))
+    }
+
+    /// Record the result of sending a chunk of `len` bytes to peer,
+    /// which grows or shrinks its MTU.
+    pub fn record_chunk_sent(&self, did: Did, len: usize, ok: bool) {
+        self.peer_mtus.record(did, len, ok)
+    }
+
+    /// Negotiated MTUs of peers which have been probed, for observability.
+    pub fn peer_mtus(&self) -> Vec<(Did, usize)> {
+        self.peer_mtus.list()
+    }
+
+    /// Open a stream to a connected peer for bulk transfer, see [stream].
+    pub async fn open_stream(&self, did: Did) -> Result<StreamWriter> {
+        let conn = self
+            .get_and_check_connection(did)
+            .await
+            .ok_or(Error::SwarmMissDidInTable(did))?;
+        let id = uuid::Uuid::new_v4();
+        self.send_direct_message(Message::StreamOpen(message::StreamOpen { id }), did)
+            .await?;
+        Ok(StreamWriter::new(did, id, conn))
+    }
+
+    /// Wait for the next stream opened by a connected peer, see [stream].
+    pub async fn accept_stream(&self) -> Option<StreamReader> {
+        self.streams.accept().await
+    }
+
+    /// Set the handler of messages dropped by swarm, see [MessageHandler::set_deadletter_handler].
+    pub fn set_deadletter_handler(&self, handler: DeadletterFn) {
+        self.message_handler.set_deadletter_handler(handler)
+    }
+
+    /// Connected peers with their scores, lowest first, so that an eviction policy
+    /// disconnects the worst peers first. Pinned peers are never evicted, so they are
+    /// excluded, see [Swarm::pin_peer].
+    pub fn peers_by_score(&self) -> Vec<(Did, f64)> {
+        let mut peers = self
+            .get_connection_ids()
+            .into_iter()
+            .filter(|did| !self.is_pinned_peer(*did))
+            .map(|did| (did, self.peer_scores.score(did)))
+            .collect::<Vec<_>>();
+        peers.sort_by(|a, b| a.1.total_cmp(&b.1));
+        peers
+    }
+
+    /// Check if payload is a DHT lookup, which is retried on failure, see [RetryBudget].
+    /// It's classified by the variant tag only, see [Message::kind_of].
+    fn is_lookup(payload: &MessagePayload) -> bool {
+        matches!(
+            Message::kind_of(&payload.transaction.data),
+            Some("FindSuccessorSend" | "SearchVNode" | "SearchVNodeReplicas")
+        )
+    }
+
+    /// Send a payload with retry budget. When sending to the next hop fails, the payload is
+    /// resigned and sent to a connected peer closer to destination among those not tried,
+    /// which is chosen by the relay selector of swarm. The backoff is awaited by the caller,
+    /// while lookups forwarded for other nodes are retried without blocking, see [RetryQueue].
+    pub async fn send_payload_with_retry(
+        &self,
+        payload: MessagePayload,
+        budget: RetryBudget,
+    ) -> Result<()> {
+        self.send_payload_with_cancel(payload, budget, &CancellationToken::new())
+            .await
+    }
+
+    /// Send a payload with retry budget like [Swarm::send_payload_with_retry], and stop
+    /// retrying once token is cancelled.
+    pub async fn send_payload_with_cancel(
+        &self,
+        payload: MessagePayload,
+        budget: RetryBudget,
+        token: &CancellationToken,
+    ) -> Result<()> {
+        let budget = RetryBudget::new(budget.attempts, budget.backoff_ms);
+        let mut tried = vec![];
+        let mut payload = payload;
+        let mut attempt = 1;
+        loop {
+            if token.is_cancelled() {
+                return Err(Error::LookupCancelled);
+            }
+            let hop = payload.relay.next_hop;
+            let Err(e) = self.do_send_payload(hop, payload.clone()).await else {
+                return Ok(());
+            };
+            tracing::warn!("Lookup via {hop} failed on attempt {attempt}: {e}");
+            tried.push(hop);
+            if attempt >= budget.attempts {
+                return Err(e);
+            }
+
+            let destination = payload.relay.destination;
+            let Some(alternate) = self.select_relay(destination, &tried) else {
+                return Err(e);
+            };
+
+            let backoff = std::pin::pin!(sleep_ms(budget.backoff_ms * attempt as u64));
+            futures::future::select(backoff, token.cancelled()).await;
+            if token.is_cancelled() {
+                return Err(Error::LookupCancelled);
+            }
+            let relay = MessageRelay {
+                next_hop: alternate,
+                ..payload.relay.clone()
+            };
+            payload = MessagePayload::new(payload.transaction, &self.session_sk, relay)?;
+            attempt += 1;
+        }
+    }
+
+    /// Forward a lookup of another node with retry budget. When sending to the next hop
+    /// fails, the payload is resigned towards an alternate hop and scheduled after its
+    /// backoff, see [RetryQueue], instead of waiting here.
+    async fn forward_lookup(
+        &self,
+        payload: MessagePayload,
+        budget: RetryBudget,
+        mut tried: Vec<Did>,
+        attempt: u8,
+    ) -> Result<()> {
+        let hop = payload.relay.next_hop;
+        let Err(e) = self.do_send_payload(hop, payload.clone()).await else {
+            return Ok(());
+        };
+        tracing::warn!("Lookup via {hop} failed on attempt {attempt}: {e}");
+        tried.push(hop);
+        if attempt >= budget.attempts {
+            return Err(e);
+        }
+        let Some(alternate) = self.select_relay(payload.relay.destination, &tried) else {
+            return Err(e);
+        };
+        let relay = MessageRelay {
+            next_hop: alternate,
+            ..payload.relay.clone()
+        };
+        let retry = ScheduledRetry {
+            payload: MessagePayload::new(payload.transaction, &self.session_sk, relay)?,
+            budget,
+            tried,
+            attempt: attempt + 1,
+            due_ms: get_epoch_ms() + (budget.backoff_ms * attempt as u64) as u128,
+        };
+        if !self.scheduled_retries.schedule(retry) {
+            tracing::warn!("Too many lookups waiting for retry, give up retrying");
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Run forwarded lookups whose retry is due. Lookups abandoned meanwhile are dropped.
+    async fn run_due_retries(&self) {
+        for retry in self.scheduled_retries.take_due(get_epoch_ms()) {
+            let tx_id = retry.payload.transaction.tx_id;
+            if self.message_handler.pending_lookups().is_abandoned(tx_id) {
+                continue;
+            }
+            let ScheduledRetry {
+                payload,
+                budget,
+                tried,
+                attempt,
+                ..
+            } = retry;
+            if let Err(e) = self.forward_lookup(payload, budget, tried, attempt).await {
+                tracing::warn!("Lookup {tx_id} failed after retry: {e}");
+            }
+        }
+    }
+
+    /// Send a message with retry budget of this call, instead of the global one.
+    pub async fn send_message_with_retry(
+        &self,
+        msg: Message,
+        destination: Did,
+        budget: RetryBudget,
+    ) -> Result<uuid::Uuid> {
+        let next_hop = self.infer_next_hop_with_priority(destination, SendPriority::of(&msg))?;
+        let payload = MessagePayload::new_send(msg, &self.session_sk, next_hop, destination)?;
+        let tx_id = payload.transaction.tx_id;
+        self.send_payload_with_retry(payload, budget).await?;
+        Ok(tx_id)
+    }
+
+    /// Get time taken by each phase of handshake with peer, see
+    /// [timing](rings_transport::timing). None if not connected.
+    pub fn connection_timing(&self, did: Did) -> Option<ConnectionTiming> {
+        self.get_connection(did).map(|c| c.connection_timing())
+    }
+
+    /// Get cumulative bytes sent to and received from peer.
+    pub fn peer_traffic(&self, did: Did) -> TrafficCounters {
+        self.traffic.get(&did)
+    }
+
+    /// Get cumulative bytes sent to and received from all peers.
+    pub fn peers_traffic(&self) -> Vec<(Did, TrafficCounters)> {
+        self.traffic.snapshot()
+    }
+
+    /// Reset traffic counters of peer, and return counters before reset.
+    pub fn reset_peer_traffic(&self, did: Did) -> TrafficCounters {
+        self.traffic.reset(&did)
+    }
+
+    /// Announce self with capabilities to connected peers, which gossip the announcement
+    /// further, see [announce](crate::message::handlers::announce).
+    pub async fn announce(&self, capabilities: Vec<String>) -> Result<()> {
+        let announced = message::AnnouncedNode {
+            did: self.did(),
+            capabilities,
+            ts_ms: get_epoch_ms(),
+        };
+        let msg =
+            Message::NodeAnnouncement(message::NodeAnnouncement::new(announced, &self.session_sk)?);
+        for (did, _) in self.get_connections() {
+            if let Err(e) = self.send_direct_message(msg.clone(), did).await {
+                tracing::warn!("Failed on announcing to {did}: {e:?}");
+            }
+        }
+        self.last_announced_ms
+            .store(get_epoch_ms() as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Announce self with capabilities given by [SwarmBuilder::announce], if it's not
+    /// announced in last [ANNOUNCE_INTERVAL_MS].
+    pub async fn announce_if_due(&self) -> Result<()> {
+        let Some(capabilities) = &self.announcement else {
+            return Ok(());
+        };
+        let last = self.last_announced_ms.load(Ordering::Relaxed) as u128;
+        if get_epoch_ms().saturating_sub(last) < ANNOUNCE_INTERVAL_MS {
+            return Ok(());
+        }
+        self.announce(capabilities.clone()).await
+    }
+
+    /// Snapshot of the view of DHT of this node.
+    pub fn routing_snapshot(&self) -> RoutingSnapshot {
+        RoutingSnapshot::take(&self.dht)
+    }
+
+    /// Ask a node for its view of DHT, waiting for the report for at most `timeout` milliseconds,
+    /// see [routing](crate::message::handlers::routing). Unlike [Swarm::routing_snapshot], the
+    /// virtual nodes held are counted.
+    pub async fn query_routing(&self, did: Did, timeout: u64) -> Result<RoutingSnapshot> {
+        if did == self.did() {
+            return Ok(self.routing_snapshot().count_vnodes(&self.dht).await);
+        }
+        let next_hop = self.infer_next_hop(None, did)?;
+        let msg = Message::RoutingQuery(message::RoutingQuery);
+        let payload = MessagePayload::new_send(msg, &self.session_sk, next_hop, did)?;
+        let tx_id = payload.transaction.tx_id;
+        let queries = self.message_handler.routing_queries();
+        let report = queries.register(tx_id, did);
+        if let Err(e) = self.send_payload(payload).await {
+            queries.cancel(tx_id);
+            return Err(e);
+        }
+        match timeout_ms(timeout, report).await {
+            Some(Ok(message::RoutingReport::Snapshot(snapshot))) => Ok(snapshot),
+            Some(Ok(message::RoutingReport::Denied(reason))) => {
+                Err(Error::RoutingQueryDenied(did, reason))
+            }
+            _ => {
+                queries.cancel(tx_id);
+                Err(Error::RoutingQueryTimeout(did))
+            }
+        }
+    }
+
+    /// Find the successor of a did by DHT, aborted once token is cancelled,
+    /// see [lookup].
+    pub async fn lookup_successor(&self, did: Did, token: &CancellationToken) -> Result<Did> {
+        let next = match self.dht.find_successor(did)? {
+            PeerRingAction::Some(succ) => return Ok(succ),
+            PeerRingAction::RemoteAction(next, _) => next,
+            act => return Err(Error::PeerRingUnexpectedAction(act)),
+        };
+        let msg = Message::FindSuccessorSend(message::FindSuccessorSend {
+            did,
+            strict: false,
+            then: message::FindSuccessorThen::Report(message::FindSuccessorReportHandler::None),
+        });
+        match self.lookup(msg, next, token).await? {
+            LookupReport::Successor(succ) => Ok(succ),
+            report => Err(Error::InvalidMessage(format!(
+                "Unexpected report of lookup: {report:?}"
+            ))),
+        }
+    }
+
+    /// Find a virtual node by DHT, aborted once token is cancelled, see [lookup].
+    /// Unlike [ChordStorageInterface::storage_fetch], the virtual node found is returned
+    /// rather than only cached.
+    pub async fn lookup_vnode(
+        &self,
+        vid: Did,
+        token: &CancellationToken,
+    ) -> Result<Option<VirtualNode>> {
+        let next = match <PeerRing as ChordStorage<_, 1>>::vnode_lookup(&self.dht, vid).await? {
+            PeerRingAction::None => return Ok(None),
+            PeerRingAction::SomeVNode(v) => return Ok(Some(v)),
+            PeerRingAction::RemoteAction(next, _) => next,
+            act => return Err(Error::PeerRingUnexpectedAction(act)),
+        };
+        let msg = Message::SearchVNode(message::SearchVNode { vid });
+        match self.lookup(msg, next, token).await? {
+            LookupReport::VNodes(data) => Ok(data.into_iter().next()),
+            report => Err(Error::InvalidMessage(format!(
+                "Unexpected report of lookup: {report:?}"
+            ))),
+        }
+    }
+
+    /// Send a lookup to destination and wait for its report, for at most
+    /// [LOOKUP_TIMEOUT_MS]. Once token is cancelled, the lookup is abandoned immediately.
+    async fn lookup(
+        &self,
+        msg: Message,
+        destination: Did,
+        token: &CancellationToken,
+    ) -> Result<LookupReport> {
+        if token.is_cancelled() {
+            return Err(Error::LookupCancelled);
+        }
+        let next_hop = self.infer_next_hop_with_priority(destination, SendPriority::of(&msg))?;
+        let payload = MessagePayload::new_send(msg, &self.session_sk, next_hop, destination)?;
+        let tx_id = payload.transaction.tx_id;
+        let lookups = self.message_handler.pending_lookups();
+        let report = lookups.register(tx_id);
+
+        let waiting = std::pin::pin!(async {
+            self.send_payload_with_cancel(payload, self.lookup_retry, token)
+                .await?;
+            match timeout_ms(LOOKUP_TIMEOUT_MS, report).await {
+                Some(Ok(report)) => Ok(report),
+                _ => Err(Error::LookupTimeout(destination)),
+            }
+        });
+        let result = match futures::future::select(waiting, token.cancelled()).await {
+            futures::future::Either::Left((result, _)) => result,
+            futures::future::Either::Right(_) => Err(Error::LookupCancelled),
+        };
+        if result.is_err() {
+            lookups.abandon(tx_id);
+        }
+        result
+    }
+
+    /// Stop processing incoming messages without closing transports, see
+    /// [MessageHandler::pause_listening].
+    pub fn pause_listening(&self) {
+        self.message_handler.pause_listening()
+    }
+
+    /// Resume processing incoming messages, see [MessageHandler::resume_listening].
+    pub fn resume_listening(&self) {
+        self.message_handler.resume_listening()
+    }
+
+    /// Check if processing incoming messages is paused.
+    pub fn is_listening_paused(&self) -> bool {
+        self.message_handler.is_listening_paused()
+    }
+
+    /// Number of lookups waiting for reports, see [lookup].
+    pub fn pending_lookups(&self) -> usize {
+        self.message_handler.pending_lookups().len()
+    }
+
+    /// Nodes learned from announcements and not expired, the freshest first.
+    pub fn known_nodes(&self) -> Vec<KnownNode> {
+        self.message_handler.known_nodes().list(get_epoch_ms())
+    }
+
+    /// Count a message dropped out of swarm, such as by the host of swarm, and deliver it
+    /// to the deadletter, see [DropReason].
+    pub fn record_drop(&self, reason: DropReason, payload: Option<&MessagePayload>) {
+        self.drop_log.record(reason, payload)
+    }
+
+    /// Counts of dropped messages by reason since the last summary of drop log,
+    /// see [DropLogConfig::summary_interval_ms].
+    pub fn drop_counts(&self) -> Vec<(DropReason, u64)> {
+        self.drop_log.counts()
+    }
+
+    /// Get the metrics recorder of swarm.
+    pub fn metrics(&self) -> MetricsImpl {
+        self.metrics.clone()
+    }
+
+    /// Skip signature verification of messages sent directly by peer over its transport.
+    ///
+    /// # Security
+    /// This trades security for CPU on high-throughput direct links. Only trust a peer
+    /// whose did is bound to the transport by the handshake. Messages relayed by the
+    /// peer on behalf of others are still verified. See [TrustedTransports].
+    pub fn trust_transport(&self, peer: Did) -> bool {
+        self.trusted_transports.insert(peer)
+    }
+
+    /// Set whether connections to peer go through the WebSocket relay instead of WebRTC. It
+    /// takes effect on the next connection, and needs a relay set by
+    /// [SwarmBuilder::relay_url](crate::swarm::SwarmBuilder::relay_url). A relayed transport is
+    /// never trusted to skip signature verification.
+    #[cfg(feature = "websocket")]
+    pub fn set_relayed(&self, peer: Did, relayed: bool) {
+        self.transport.set_relayed(&peer.to_string(), relayed)
+    }
+
+    /// Restore signature verification of messages sent by peer.
+    pub fn untrust_transport(&self, peer: Did) -> bool {
+        self.trusted_transports.remove(peer)
+    }
+
+    /// Check if the transport of peer is trusted to skip signature verification.
+    pub fn is_trusted_transport(&self, peer: Did) -> bool {
+        self.trusted_transports.contains(peer)
+    }
+
+    /// Pin peer as a permanent connection. A pinned peer is never refused by the
+    /// behaviour judgement nor evicted, see [Swarm::peers_by_score], and stabilization
+    /// reconnects it when the connection is lost. The pin is kept across reconnections until
+    /// [Swarm::unpin_peer] is called.
+    pub fn pin_peer(&self, did: Did) -> bool {
+        self.pinned_peers.insert(did)
+    }
+
+    /// Unpin peer, its connection is then managed as others.
+    pub fn unpin_peer(&self, did: Did) -> bool {
+        self.pinned_peers.remove(did)
+    }
+
+    /// Check if peer is pinned.
+    pub fn is_pinned_peer(&self, did: Did) -> bool {
+        self.pinned_peers.contains(did)
+    }
+
+    /// List pinned peers.
+    pub fn pinned_peers(&self) -> Vec<Did> {
+        self.pinned_peers.list()
+    }
+
+    /// Load message of a transport connected, which joins its peer to DHT.
+    async fn load_connected(&self, did: Did) -> Result<Option<MessagePayload>> {
+        // Peers never join DHT without DHT participation.
+        if self.no_dht {
+            self.offer_dictionaries(did).await;
+            self.offer_ephemeral_key(Some(did)).await;
+            return Ok(None);
+        }
+        match self.get_connection(did) {
+            Some(_) => {
+                self.offer_dictionaries(did).await;
+                self.offer_ephemeral_key(Some(did)).await;
+                let payload = MessagePayload::new_send(
+                    Message::JoinDHT(message::JoinDHT { did }),
+                    &self.session_sk,
+                    self.dht.did,
+                    self.dht.did,
+                )?;
+                Ok(Some(payload))
+            }
+            None => Err(Error::SwarmMissTransport(did)),
+        }
+    }
+
     /// Load message from a TransportEvent.
     async fn load_message(&self, ev: TransportEvent) -> Result<Option<MessagePayload>> {
         match ev {
             TransportEvent::DataChannelMessage(msg) => {
-                let payload = MessagePayload::from_bincode(&msg)?;
+                self.offer_ephemeral_key(None).await;
+                // A payload of a later version is accepted by the transport callback already.
+                let payload = MessagePayload::from_bincode_best_effort(&msg)?;
                 tracing::debug!("load message from channel: {:?}", payload);
                 Ok(Some(payload))
             }
-            TransportEvent::Connected(did) => match self.get_connection(did) {
-                Some(_) => {
-                    let payload = MessagePayload::new_send(
-                        Message::JoinDHT(message::JoinDHT { did }),
-                        &self.session_sk,
-                        self.dht.did,
-                        self.dht.did,
-                    )?;
-                    Ok(Some(payload))
+            TransportEvent::Connected(did) => {
+                // A transport rejected by the address it's connected from is already closed.
+                if self.check_remote_address(did).await.is_err() {
+                    return Ok(None);
                 }
-                None => Err(Error::SwarmMissTransport(did)),
-            },
+                self.load_connected(did).await
+            }
             TransportEvent::Closed(did) => {
+                self.peer_mtus.remove(did);
+                self.observed_relays.remove_via(did);
+                // Sendings still waiting keep their clone of the queue and fail without the
+                // connection, sendings after reconnecting start a new queue.
+                self.send_queues.remove(&did);
+                self.streams.close_peer(did);
+                self.dictionaries.forget_peer(did);
                 let payload = MessagePayload::new_send(
                     Message::LeaveDHT(message::LeaveDHT { did }),
                     &self.session_sk,
@@ -109,19 +948,113 @@ impl Swarm {
                 )?;
                 Ok(Some(payload))
             }
+            TransportEvent::HandshakeCompleted(did) => {
+                self.record_handshake_timing(did);
+                Ok(None)
+            }
+            TransportEvent::IceCandidate(did, candidate) => {
+                self.trickle_ice_candidate(did, candidate).await;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Offer known compression dictionaries to a peer just connected,
+    /// see [dictionary](crate::message::dictionary).
+    async fn offer_dictionaries(&self, did: Did) {
+        let ids = self.dictionaries.ids();
+        if ids.is_empty() {
+            return;
+        }
+        let msg = Message::DictionaryOffer(message::DictionaryOffer { ids });
+        if let Err(e) = self.send_direct_message(msg, did).await {
+            tracing::warn!("Failed on offering dictionaries to {did}: {e:?}");
+        }
+    }
+
+    /// Offer the ephemeral key of this node to a peer just connected, and to every connected
+    /// peer once the key is rotated, see [SessionKeys].
+    async fn offer_ephemeral_key(&self, connected: Option<Did>) {
+        let (offer, rotated) = self.session_keys.ephemeral_offer();
+        let peers: Vec<Did> = if rotated {
+            self.get_connections()
+                .into_iter()
+                .map(|(did, _)| did)
+                .collect()
+        } else {
+            connected.into_iter().collect()
+        };
+        for did in peers {
+            let msg = Message::EphemeralKeyOffer(offer.clone());
+            if let Err(e) = self.send_direct_message(msg, did).await {
+                tracing::warn!("Failed on offering ephemeral key to {did}: {e:?}");
+            }
+        }
+    }
+
+    /// Record time taken by each phase of handshake with peer.
+    fn record_handshake_timing(&self, did: Did) {
+        let Some(timing) = self.connection_timing(did) else {
+            return;
+        };
+        for (name, ms) in [
+            (metrics::HANDSHAKE_OFFER_MS, timing.offer_ms),
+            (metrics::HANDSHAKE_GATHERING_MS, timing.gathering_ms),
+            (
+                metrics::HANDSHAKE_ANSWER_EXCHANGE_MS,
+                timing.answer_exchange_ms,
+            ),
+            (metrics::HANDSHAKE_ICE_CHECK_MS, timing.ice_check_ms),
+            (metrics::HANDSHAKE_DATA_CHANNEL_MS, timing.data_channel_ms),
+            (metrics::HANDSHAKE_TOTAL_MS, timing.total_ms),
+        ] {
+            if let Some(ms) = ms {
+                self.metrics.record_histogram(name, ms as f64);
+            }
+        }
+    }
+
+    /// Record depth of the transport event queue, and events dropped by overflow.
+    fn record_event_queue(&self) {
+        self.metrics.set_gauge(
+            metrics::EVENT_QUEUE_DEPTH,
+            self.transport_event_channel.len() as f64,
+        );
+        let dropped = self.transport_event_channel.take_dropped();
+        if dropped > 0 {
+            self.metrics
+                .increment_counter(metrics::EVENT_QUEUE_DROPPED, dropped);
         }
     }
 
     /// This method is required because web-sys components is not `Send`
     /// which means an async loop cannot running concurrency.
+    /// Returns None once a scheduled lookup retry is due, so that the listening loop can
+    /// run it, see [RetryQueue].
     pub async fn poll_message(&self) -> Option<MessagePayload> {
         let receiver = &self.transport_event_channel.receiver();
-        match Channel::recv(receiver).await {
-            Ok(Some(ev)) => match self.load_message(ev).await {
-                Ok(Some(msg)) => Some(msg),
-                Ok(None) => None,
-                Err(_) => None,
-            },
+        self.record_event_queue();
+        let received = match self.scheduled_retries.next_due_ms() {
+            Some(due_ms) => {
+                let wait = due_ms.saturating_sub(get_epoch_ms()) as u64;
+                let recv = std::pin::pin!(Channel::recv(receiver));
+                let due = std::pin::pin!(sleep_ms(wait));
+                match futures::future::select(recv, due).await {
+                    futures::future::Either::Left((received, _)) => received,
+                    futures::future::Either::Right(_) => return None,
+                }
+            }
+            None => Channel::recv(receiver).await,
+        };
+        match received {
+            Ok(Some(ev)) => {
+                let _busy = self.listen_heartbeat.busy();
+                match self.load_message(ev).await {
+                    Ok(Some(msg)) => Some(msg),
+                    Ok(None) => None,
+                    Err(_) => None,
+                }
+            }
             Ok(None) => None,
             Err(e) => {
                 tracing::error!("Failed on polling message, Error {}", e);
@@ -134,13 +1067,30 @@ impl Swarm {
     /// This method will return events already consumed (landed), which is ok to be ignore.
     /// which means a listening loop cannot running concurrency.
     pub async fn listen_once(&self) -> Option<(MessagePayload, Vec<MessageHandlerEvent>)> {
+        self.message_handler.listen_gate().resumed().await;
+        self.run_due_retries().await;
         let payload = self.poll_message().await?;
+        let _busy = self.listen_heartbeat.busy();
+        self.metrics.increment_counter(metrics::MESSAGE_RECEIVED, 1);
 
-        if !(payload.verify() && payload.transaction.verify()) {
-            tracing::error!("Cannot verify msg or it's expired: {:?}", payload);
-            return None;
+        // Messages from untrusted transports are verified by the transport callback, so a
+        // payload claiming to be sent directly by a trusted peer is either genuine or was
+        // received from the transport of that peer.
+        let trusted = self
+            .trusted_transports
+            .can_skip_verification(payload.relay.origin_sender(), &payload);
+        if !trusted {
+            if let Some(reason) = DropReason::check(&payload, &self.expiry_policy) {
+                self.drop_log.record(reason, Some(&payload));
+                return None;
+            }
         }
+        let start = get_epoch_ms();
         let events = self.message_handler.handle_message(&payload).await;
+        self.metrics.record_histogram(
+            metrics::MESSAGE_HANDLE_MS,
+            get_epoch_ms().saturating_sub(start) as f64,
+        );
 
         match events {
             Ok(evs) => {
@@ -156,6 +1106,8 @@ impl Swarm {
             }
             Err(e) => {
                 tracing::error!("Message handler failed on handling event: {:#?}", e);
+                self.metrics
+                    .increment_counter(metrics::MESSAGE_HANDLE_FAILED, 1);
                 None
             }
         }
@@ -191,7 +1143,13 @@ impl Swarm {
                 Ok(vec![])
             }
 
+            // A pinned peer is only disconnected once its connection is lost.
             MessageHandlerEvent::Disconnect(did) => {
+                if self.is_pinned_peer(*did) && self.get_and_check_connection(*did).await.is_some()
+                {
+                    tracing::debug!("Keep connection of pinned peer {did}");
+                    return Ok(vec![]);
+                }
                 self.disconnect(*did).await?;
                 Ok(vec![])
             }
@@ -213,29 +1171,44 @@ impl Swarm {
                 Ok(vec![])
             }
 
+            MessageHandlerEvent::AddIceCandidate(origin_sender, msg) => {
+                self.add_ice_candidate(*origin_sender, &msg.candidate)
+                    .await?;
+                Ok(vec![])
+            }
+
             MessageHandlerEvent::ForwardPayload(payload, next_hop) => {
-                if self
+                let next_hop = if self
                     .get_and_check_connection(payload.relay.destination)
                     .await
                     .is_some()
                 {
-                    self.forward_payload(payload, Some(payload.relay.destination))
-                        .await?;
+                    Some(payload.relay.destination)
                 } else {
-                    self.forward_payload(payload, *next_hop).await?;
+                    *next_hop
+                };
+                if let Err(e) = self.forward_payload(payload, next_hop).await {
+                    self.drop_log
+                        .record(DropReason::of_relay_error(&e), Some(payload));
+                    return Err(e);
                 }
                 Ok(vec![])
             }
 
             MessageHandlerEvent::JoinDHT(ctx, did) => {
-                if cfg!(feature = "experimental") {
+                let dht_ev = if cfg!(feature = "experimental") {
                     let wdid: WrappedDid = WrappedDid::new(self, *did);
-                    let dht_ev = self.dht.join_then_sync(wdid).await?;
-                    crate::message::handlers::dht::handle_dht_events(&dht_ev, ctx).await
+                    self.dht.join_then_sync(wdid).await?
                 } else {
-                    let dht_ev = self.dht.join(*did)?;
-                    crate::message::handlers::dht::handle_dht_events(&dht_ev, ctx).await
+                    self.dht.join(*did)?
+                };
+                let mut events =
+                    crate::message::handlers::dht::handle_dht_events(&dht_ev, ctx).await?;
+                // The joined node takes charge of vnodes between it and former successor.
+                if self.dht.successors().min()? == *did {
+                    events.extend(sync_vnode_with_successor(&self.dht, *did).await?);
                 }
+                Ok(events)
             }
 
             MessageHandlerEvent::SendDirectMessage(msg, dest) => {
@@ -254,7 +1227,11 @@ impl Swarm {
             }
 
             MessageHandlerEvent::ResetDestination(payload, next_hop) => {
-                self.reset_destination(payload, *next_hop).await?;
+                if let Err(e) = self.reset_destination(payload, *next_hop).await {
+                    self.drop_log
+                        .record(DropReason::of_relay_error(&e), Some(payload));
+                    return Err(e);
+                }
                 Ok(vec![])
             }
 
@@ -262,6 +1239,35 @@ impl Swarm {
                 <Self as ChordStorageInterface<1>>::storage_store(self, vnode.clone()).await?;
                 Ok(vec![])
             }
+
+            MessageHandlerEvent::ReportRouting(payload) => {
+                let origin = payload.transaction.signer();
+                let report = match self.authorize(origin, &AuthAction::InspectRouting).await {
+                    Decision::Allow => message::RoutingReport::Snapshot(
+                        self.routing_snapshot().count_vnodes(&self.dht).await,
+                    ),
+                    Decision::Deny(reason) => {
+                        tracing::info!("Deny routing query from {origin}: {reason}");
+                        message::RoutingReport::Denied(reason)
+                    }
+                };
+                Ok(vec![MessageHandlerEvent::SendReportMessage(
+                    payload.clone(),
+                    Message::RoutingReport(report),
+                )])
+            }
+
+            MessageHandlerEvent::Gossip(msg, except) => {
+                for (did, _) in self.get_connections() {
+                    if except.contains(&did) {
+                        continue;
+                    }
+                    if let Err(e) = self.send_direct_message(msg.clone(), did).await {
+                        tracing::warn!("Failed on gossiping to {did}: {e:?}");
+                    }
+                }
+                Ok(vec![])
+            }
         }
     }
 
@@ -319,7 +1325,59 @@ impl PayloadSender for Swarm {
         Swarm::dht(self)
     }
 
+    /// Lookups are sent with the retry budget of swarm, whose retries are scheduled without
+    /// blocking, see [RetryQueue]. Others are sent once.
+    async fn send_payload(&self, payload: MessagePayload) -> Result<()> {
+        if Self::is_lookup(&payload) {
+            let budget = RetryBudget::new(self.lookup_retry.attempts, self.lookup_retry.backoff_ms);
+            return self.forward_lookup(payload, budget, vec![], 1).await;
+        }
+        self.do_send_payload(payload.relay.next_hop, payload).await
+    }
+
+    /// Infer the next hop among the destination itself, the hop inferred by DHT and a relay
+    /// closer to destination, by the send path selector of swarm, so a did can be reached
+    /// without connecting to it first. Fails if no path exists.
+    fn infer_next_hop(&self, next_hop: Option<Did>, destination: Did) -> Result<Did> {
+        if let Some(next_hop) = next_hop {
+            return Ok(next_hop);
+        }
+        self.infer_next_hop_with_priority(destination, SendPriority::Normal)
+    }
+
+    /// Infer the next hop by the send path selector of swarm, which may route messages
+    /// of priority differently, see [SwarmBuilder::path_selector].
+    fn infer_next_hop_of(
+        &self,
+        next_hop: Option<Did>,
+        destination: Did,
+        priority: SendPriority,
+    ) -> Result<Did> {
+        if let Some(next_hop) = next_hop {
+            return Ok(next_hop);
+        }
+        self.infer_next_hop_with_priority(destination, priority)
+    }
+
+    /// Fails with [Error::SendTimeout] if the payload is not handed off to transport
+    /// within the send timeout of swarm, see [SwarmBuilder::send_timeout].
     async fn do_send_payload(&self, did: Did, payload: MessagePayload) -> Result<()> {
+        let next_hop = payload.relay.next_hop;
+        match timeout_ms(self.send_timeout_ms, self.handoff_payload(did, payload)).await {
+            Some(result) => result,
+            None => {
+                tracing::warn!("Send to {did} timed out in {}ms", self.send_timeout_ms);
+                self.peer_scores.record(did, PeerSignal::Timeout);
+                self.record_sent_failed(next_hop).await;
+                Err(Error::SendTimeout(did))
+            }
+        }
+    }
+}
+
+impl Swarm {
+    /// Hand off a payload to the transport of peer, in submission order of the peer.
+    async fn handoff_payload(&self, did: Did, payload: MessagePayload) -> Result<()> {
         #[cfg(test)]
         {
             println!("+++++++++++++++++++++++++++++++++");
@@ -329,6 +1387,15 @@ impl PayloadSender for Swarm {
             println!("+++++++++++++++++++++++++++++++++");
         }
 
+        // Sendings to a peer are serialized, so that they are sent in submission order.
+        // DHT maintenance jumps ahead of application messages until initial convergence.
+        let queue = self.send_queues.entry(did).or_default().clone();
+        let _permit = if !self.is_converged() && Self::is_maintenance(&payload) {
+            queue.acquire_urgent().await
+        } else {
+            queue.acquire().await
+        };
+
         let conn = self
             .get_and_check_connection(did)
             .await
@@ -340,10 +1407,30 @@ impl PayloadSender for Swarm {
             payload.relay.next_hop,
         );
 
-        let data = payload.to_bincode()?;
+        let data = if self.compress_relay_path {
+            payload.to_compact_path_compressed()?
+        } else if self.compact_payload {
+            payload.to_compact()?
+        } else {
+            payload.to_bincode()?
+        };
+        let data = match self.dictionaries.compress_for(did, &data) {
+            Some(compressed) => {
+                self.metrics
+                    .increment_counter(metrics::DICT_COMPRESSION_INPUT_BYTES, data.len() as u64);
+                self.metrics.increment_counter(
+                    metrics::DICT_COMPRESSION_OUTPUT_BYTES,
+                    compressed.len() as u64,
+                );
+                compressed
+            }
+            None => data,
+        };
+        let started_at = get_epoch_ms();
         let result = conn
             .send_message(TransportMessage::Custom(data.to_vec()))
             .await;
+        queue.record_send_ms(get_epoch_ms().saturating_sub(started_at) as f64);
 
         tracing::debug!(
             "Sent {:?}, to node {:?}",
@@ -352,8 +1439,11 @@ impl PayloadSender for Swarm {
         );
 
         if result.is_ok() {
+            self.traffic.record_sent(&did, data.len());
+            self.peer_scores.record(did, PeerSignal::Delivered);
             self.record_sent(payload.relay.next_hop).await
         } else {
+            self.peer_scores.record(did, PeerSignal::Failed);
             self.record_sent_failed(payload.relay.next_hop).await
         }
 
@@ -365,6 +1455,10 @@ impl PayloadSender for Swarm {
 impl Swarm {
     /// Listener for native envirement, It will just launch a loop.
     pub async fn listen(self: Arc<Self>) {
+        if let Err(e) = self.discover_external_address().await {
+            tracing::warn!("Failed to discover external address: {:?}", e);
+        }
+        let _running = self.listen_heartbeat.start();
         loop {
             self.listen_once().await;
         }
@@ -375,7 +1469,16 @@ impl Swarm {
 impl Swarm {
     /// Listener for browser envirement, the implementation is based on  js_sys::window.set_timeout.
     pub async fn listen(self: Arc<Self>) {
+        if let Err(e) = self.discover_external_address().await {
+            tracing::warn!("Failed to discover external address: {:?}", e);
+        }
+        // The timer polling it is never stopped.
+        std::mem::forget(self.listen_heartbeat.start());
         let func = move || {
+            // Not polled while paused, so that waiting listeners don't pile up.
+            if self.is_listening_paused() {
+                return;
+            }
             let this = self.clone();
             wasm_bindgen_futures::spawn_local(Box::pin(async move {
                 this.listen_once().await;
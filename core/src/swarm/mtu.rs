@@ -0,0 +1,217 @@
+#![warn(missing_docs)]
+//! This module provides [PeerMtus], the effective size of chunks negotiated with each peer.
+//! Chunking starts at a conservative size, grows while chunks of full size are sent
+//! successfully, and shrinks when sending fails, so that good links are filled
+//! and bad links stay reliable. The range it adapts in is set by [MtuBounds].
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::consts::TRANSPORT_MAX_SIZE;
+use crate::consts::TRANSPORT_MTU;
+use crate::dht::Did;
+
+/// Smallest MTU which chunking shrinks to.
+pub const MIN_MTU: usize = 16 * 1024;
+
+/// MTU of a peer before any probe.
+pub const INITIAL_MTU: usize = 64 * 1024;
+
+/// Largest MTU which chunking grows to.
+pub const MAX_MTU: usize = TRANSPORT_MAX_SIZE - TRANSPORT_MTU;
+
+/// Number of consecutive chunks of full size sent successfully before MTU grows.
+pub const MTU_GROW_AFTER: usize = 4;
+
+/// Range which MTU of peers adapts in, [MIN_MTU] to [MAX_MTU] by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MtuBounds {
+    /// Floor which MTU shrinks to on failures.
+    pub min: usize,
+    /// Ceiling which MTU grows to on successes.
+    pub max: usize,
+}
+
+impl Default for MtuBounds {
+    fn default() -> Self {
+        Self {
+            min: MIN_MTU,
+            max: MAX_MTU,
+        }
+    }
+}
+
+impl MtuBounds {
+    /// Clamp bounds into the range of [MIN_MTU] to [MAX_MTU], and keep the floor under the
+    /// ceiling. Chunks smaller than [MIN_MTU] cost more in headers than they save in retries,
+    /// and ones larger than [MAX_MTU] never fit a transport.
+    pub fn normalized(self) -> Self {
+        let max = self.max.clamp(MIN_MTU, MAX_MTU);
+        Self {
+            min: self.min.clamp(MIN_MTU, max),
+            max,
+        }
+    }
+
+    /// MTU of a peer before any probe.
+    pub fn initial(&self) -> usize {
+        INITIAL_MTU.clamp(self.min, self.max)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct MtuEntry {
+    mtu: usize,
+    successes: usize,
+}
+
+/// Negotiated MTU of peers, cloning it shares the MTUs. A peer never probed
+/// has [INITIAL_MTU], and MTU stays in range of [MtuBounds].
+#[derive(Debug, Clone, Default)]
+pub struct PeerMtus {
+    entries: Arc<DashMap<Did, MtuEntry>>,
+    bounds: MtuBounds,
+}
+
+impl PeerMtus {
+    /// Create MTUs adapting in range of bounds, see [MtuBounds::normalized].
+    pub fn new(bounds: MtuBounds) -> Self {
+        Self {
+            entries: Default::default(),
+            bounds: bounds.normalized(),
+        }
+    }
+
+    /// Get MTU of peer, which chunking of messages sent to it should follow.
+    pub fn mtu(&self, did: Did) -> usize {
+        self.entries
+            .get(&did)
+            .map(|e| e.mtu)
+            .unwrap_or_else(|| self.bounds.initial())
+    }
+
+    /// MTUs of peers which have been probed.
+    pub fn list(&self) -> Vec<(Did, usize)> {
+        self.entries.iter().map(|e| (*e.key(), e.mtu)).collect()
+    }
+
+    /// Record the result of sending a message of `len` bytes to peer. MTU doubles after
+    /// [MTU_GROW_AFTER] messages of full size are sent, and halves from the failed size
+    /// when sending a message larger than the floor of [MtuBounds] fails.
+    pub fn record(&self, did: Did, len: usize, ok: bool) {
+        let MtuBounds { min, max } = self.bounds;
+        let mut entry = self.entries.entry(did).or_insert_with(|| MtuEntry {
+            mtu: self.bounds.initial(),
+            successes: 0,
+        });
+        if ok {
+            if len < entry.mtu {
+                return;
+            }
+            entry.successes += 1;
+            if entry.successes >= MTU_GROW_AFTER {
+                entry.mtu = (entry.mtu * 2).min(max);
+                entry.successes = 0;
+            }
+        } else if len > min {
+            entry.mtu = (entry.mtu.min(len) / 2).max(min);
+            entry.successes = 0;
+        }
+    }
+
+    /// Forget MTU of peer, such as when its transport is closed.
+    pub fn remove(&self, did: Did) {
+        self.entries.remove(&did);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_peer_mtus() {
+        let mtus = PeerMtus::default();
+        let did = Did::from(1u32);
+        assert_eq!(mtus.mtu(did), INITIAL_MTU);
+
+        // Small messages never probe MTU.
+        for _ in 0..MTU_GROW_AFTER {
+            mtus.record(did, 100, true);
+        }
+        assert_eq!(mtus.mtu(did), INITIAL_MTU);
+
+        for _ in 0..MTU_GROW_AFTER {
+            mtus.record(did, INITIAL_MTU, true);
+        }
+        assert_eq!(mtus.mtu(did), INITIAL_MTU * 2);
+
+        mtus.record(did, INITIAL_MTU * 2, false);
+        assert_eq!(mtus.mtu(did), INITIAL_MTU);
+
+        // Failures of small messages are not caused by MTU.
+        mtus.record(did, 100, false);
+        assert_eq!(mtus.mtu(did), INITIAL_MTU);
+
+        for _ in 0..8 {
+            mtus.record(did, MAX_MTU, false);
+        }
+        assert_eq!(mtus.mtu(did), MIN_MTU);
+
+        for _ in 0..MTU_GROW_AFTER * 16 {
+            let mtu = mtus.mtu(did);
+            mtus.record(did, mtu, true);
+        }
+        assert_eq!(mtus.mtu(did), MAX_MTU);
+
+        mtus.remove(did);
+        assert_eq!(mtus.mtu(did), INITIAL_MTU);
+    }
+
+    #[test]
+    fn test_mtu_bounds() {
+        let bounds = MtuBounds {
+            min: 0,
+            max: MAX_MTU * 2,
+        };
+        assert_eq!(bounds.normalized(), MtuBounds::default());
+        let bounds = MtuBounds {
+            min: MAX_MTU,
+            max: 32 * 1024,
+        };
+        assert_eq!(bounds.normalized(), MtuBounds {
+            min: 32 * 1024,
+            max: 32 * 1024
+        });
+
+        // Chunk size backs off on every failure of sending, down to the floor.
+        let floor = 24 * 1024;
+        let mtus = PeerMtus::new(MtuBounds {
+            min: floor,
+            max: 128 * 1024,
+        });
+        let did = Did::from(1u32);
+        let mut sizes = vec![];
+        for _ in 0..4 {
+            let mtu = mtus.mtu(did);
+            mtus.record(did, mtu, false);
+            sizes.push(mtus.mtu(did));
+        }
+        assert_eq!(sizes, vec![32 * 1024, floor, floor, floor]);
+
+        // It grows cautiously after sustained success, up to the ceiling.
+        for _ in 0..MTU_GROW_AFTER - 1 {
+            mtus.record(did, floor, true);
+        }
+        assert_eq!(mtus.mtu(did), floor);
+        for _ in 0..MTU_GROW_AFTER * 8 {
+            let mtu = mtus.mtu(did);
+            mtus.record(did, mtu, true);
+        }
+        assert_eq!(mtus.mtu(did), 128 * 1024);
+        assert_eq!(mtus.list(), vec![(did, 128 * 1024)]);
+    }
+}
@@ -0,0 +1,108 @@
+#![warn(missing_docs)]
+//! This module provides [NatDiscovery], which lets a node learn its external address
+//! by means other than STUN, such as a configured address or a port mapping of the gateway.
+//! The discovered address is announced in ICE candidates, which improves connectability
+//! behind NATs where server reflexive candidates are not usable.
+use async_trait::async_trait;
+
+use crate::error::Result;
+
+/// A method of discovering the external address of node.
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+pub trait NatDiscovery {
+    /// Returns the external ip address of node, or None if it's unknown to this method.
+    async fn discover(&self) -> Result<Option<String>>;
+}
+
+/// Type of NatDiscovery, see [NatDiscovery].
+#[cfg(not(feature = "wasm"))]
+pub type NatDiscoveryImpl = Box<dyn NatDiscovery + Send + Sync>;
+
+/// Type of NatDiscovery, see [NatDiscovery].
+#[cfg(feature = "wasm")]
+pub type NatDiscoveryImpl = Box<dyn NatDiscovery>;
+
+/// The default discovery, which leaves the work to STUN servers of transport.
+/// Server reflexive candidates are gathered by ICE itself, so no address is returned.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StunDiscovery;
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl NatDiscovery for StunDiscovery {
+    async fn discover(&self) -> Result<Option<String>> {
+        Ok(None)
+    }
+}
+
+/// A discovery returning a configured address, such as the public ip of a cloud instance.
+#[derive(Debug, Clone)]
+pub struct StaticAddress(pub String);
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl NatDiscovery for StaticAddress {
+    async fn discover(&self) -> Result<Option<String>> {
+        Ok(Some(self.0.clone()))
+    }
+}
+
+/// A discovery trying methods in order, the first address found is returned.
+/// Failures of a method are logged and skipped.
+#[derive(Default)]
+pub struct NatDiscoveryChain(Vec<NatDiscoveryImpl>);
+
+impl NatDiscoveryChain {
+    /// Append a method to the chain.
+    pub fn then(mut self, discovery: NatDiscoveryImpl) -> Self {
+        self.0.push(discovery);
+        self
+    }
+}
+
+#[cfg_attr(feature = "wasm", async_trait(?Send))]
+#[cfg_attr(not(feature = "wasm"), async_trait)]
+impl NatDiscovery for NatDiscoveryChain {
+    async fn discover(&self) -> Result<Option<String>> {
+        for discovery in &self.0 {
+            match discovery.discover().await {
+                Ok(Some(addr)) => return Ok(Some(addr)),
+                Ok(None) => {}
+                Err(e) => tracing::warn!("NAT discovery failed: {:?}", e),
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::Error;
+
+    struct Failing;
+
+    #[async_trait]
+    impl NatDiscovery for Failing {
+        async fn discover(&self) -> Result<Option<String>> {
+            Err(Error::NatDiscovery("gateway not found".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_nat_discovery_chain() {
+        let chain = NatDiscoveryChain::default()
+            .then(Box::new(StunDiscovery))
+            .then(Box::new(Failing));
+        assert_eq!(chain.discover().await.unwrap(), None);
+
+        let chain = chain
+            .then(Box::new(StaticAddress("203.0.113.7".to_string())))
+            .then(Box::new(StaticAddress("203.0.113.8".to_string())));
+        assert_eq!(
+            chain.discover().await.unwrap(),
+            Some("203.0.113.7".to_string())
+        );
+    }
+}
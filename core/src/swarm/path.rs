@@ -0,0 +1,242 @@
+#![warn(missing_docs)]
+//! This module provides [SendPathSelector], which picks the path of a message when swarm has
+//! more than one viable next hop towards its destination. A message goes directly to the
+//! destination if it's connected, to the next hop inferred by DHT if that one is connected,
+//! or through a relay chosen by [RelaySelector](crate::swarm::RelaySelector) otherwise.
+//! Each path carries the stats of the peer at its next hop, so that selection can take
+//! quality into account.
+use crate::dht::Did;
+use crate::message::Message;
+use crate::message::Transaction;
+
+/// Kind of a path, ordered by preference of [PreferDirect].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PathKind {
+    /// The next hop is the destination itself.
+    Direct,
+    /// The next hop is the one inferred by DHT.
+    Dht,
+    /// The next hop is a connected peer closer to destination, which relays the message.
+    Relay,
+}
+
+/// A viable path of a message, with stats of the peer at its next hop.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathCandidate {
+    /// Did of the next hop.
+    pub hop: Did,
+    /// Kind of the path.
+    pub kind: PathKind,
+    /// Number of sendings to the next hop in flight, including the queued ones.
+    pub in_flight: usize,
+    /// Moving average of milliseconds taken by transport to accept a sending to the next hop,
+    /// which tracks the round trip of data channel. None if nothing is sent yet.
+    pub avg_send_ms: Option<f64>,
+    /// Score of the next hop, see [PeerScores](crate::swarm::PeerScores).
+    pub score: f64,
+}
+
+/// Priority of a message, which selectors may route differently.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum SendPriority {
+    /// Messages maintaining DHT, such as `FindSuccessorSend` and `NotifyPredecessorSend`.
+    Maintenance,
+    /// Any other message.
+    #[default]
+    Normal,
+}
+
+impl SendPriority {
+    /// Priority of a message.
+    pub fn of(msg: &Message) -> Self {
+        Self::of_kind(msg.kind())
+    }
+
+    /// Priority of a type of message, see [Message::kind].
+    pub fn of_kind(kind: &str) -> Self {
+        match kind {
+            "ConnectNodeSend"
+            | "ConnectNodeReport"
+            | "IceCandidate"
+            | "FindSuccessorSend"
+            | "FindSuccessorReport"
+            | "NotifyPredecessorSend"
+            | "NotifyPredecessorReport"
+            | "QueryForTopoInfoSend"
+            | "QueryForTopoInfoReport" => Self::Maintenance,
+            _ => Self::Normal,
+        }
+    }
+
+    /// Priority of the message carried by transaction, [SendPriority::Normal] if it's not
+    /// a [Message]. It's classified by the variant tag only, see [Message::kind_of].
+    pub fn of_transaction(transaction: &Transaction) -> Self {
+        Message::kind_of(&transaction.data)
+            .map(Self::of_kind)
+            .unwrap_or_default()
+    }
+}
+
+/// A strategy choosing the path of a message among candidates, which are never empty.
+pub trait SendPathSelector {
+    /// Returns the did of the next hop chosen.
+    fn select(
+        &self,
+        destination: Did,
+        priority: SendPriority,
+        candidates: &[PathCandidate],
+    ) -> Option<Did>;
+}
+
+/// Type of SendPathSelector, see [SendPathSelector].
+#[cfg(not(feature = "wasm"))]
+pub type SendPathSelectorImpl = Box<dyn SendPathSelector + Send + Sync>;
+
+/// Type of SendPathSelector, see [SendPathSelector].
+#[cfg(feature = "wasm")]
+pub type SendPathSelectorImpl = Box<dyn SendPathSelector>;
+
+/// The default selector, which prefers a direct path, then the one inferred by DHT,
+/// then a relay, see [PathKind].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PreferDirect;
+
+impl SendPathSelector for PreferDirect {
+    fn select(&self, _: Did, _: SendPriority, candidates: &[PathCandidate]) -> Option<Did> {
+        candidates.iter().min_by_key(|c| c.kind).map(|c| c.hop)
+    }
+}
+
+/// A selector choosing the path of the lowest send latency, see
+/// [PathCandidate::avg_send_ms]. Paths not measured yet are only chosen by [PreferDirect]
+/// when no path is measured.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LowestLatency;
+
+impl SendPathSelector for LowestLatency {
+    fn select(
+        &self,
+        destination: Did,
+        priority: SendPriority,
+        candidates: &[PathCandidate],
+    ) -> Option<Did> {
+        candidates
+            .iter()
+            .filter_map(|c| Some((c, c.avg_send_ms?)))
+            .min_by(|a, b| a.1.total_cmp(&b.1).then(a.0.kind.cmp(&b.0.kind)))
+            .map(|(c, _)| c.hop)
+            .or_else(|| PreferDirect.select(destination, priority, candidates))
+    }
+}
+
+/// A selector routing maintenance messages and others by different selectors, such as
+/// maintenance by [LowestLatency] to converge fast, while others by [PreferDirect].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ByPriority<M, N> {
+    maintenance: M,
+    normal: N,
+}
+
+impl<M, N> ByPriority<M, N> {
+    /// Create a selector of maintenance messages and others.
+    pub fn new(maintenance: M, normal: N) -> Self {
+        Self {
+            maintenance,
+            normal,
+        }
+    }
+}
+
+impl<M: SendPathSelector, N: SendPathSelector> SendPathSelector for ByPriority<M, N> {
+    fn select(
+        &self,
+        destination: Did,
+        priority: SendPriority,
+        candidates: &[PathCandidate],
+    ) -> Option<Did> {
+        match priority {
+            SendPriority::Maintenance => self.maintenance.select(destination, priority, candidates),
+            SendPriority::Normal => self.normal.select(destination, priority, candidates),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ecc::SecretKey;
+    use crate::message::QueryFor;
+    use crate::message::QueryForTopoInfoSend;
+    use crate::message::RoutingQuery;
+    use crate::session::SessionSk;
+
+    fn candidate(hop: u32, kind: PathKind, avg_send_ms: Option<f64>) -> PathCandidate {
+        PathCandidate {
+            hop: hop.into(),
+            kind,
+            in_flight: 0,
+            avg_send_ms,
+            score: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_send_path_selectors() {
+        let destination: Did = 1u32.into();
+        let candidates = [
+            candidate(2, PathKind::Relay, Some(5.0)),
+            candidate(3, PathKind::Dht, Some(50.0)),
+            candidate(1, PathKind::Direct, None),
+        ];
+        let normal = SendPriority::Normal;
+
+        assert_eq!(
+            PreferDirect.select(destination, normal, &candidates),
+            Some(1u32.into())
+        );
+        assert_eq!(
+            LowestLatency.select(destination, normal, &candidates),
+            Some(2u32.into())
+        );
+        // Without any measured path, it falls back to the preferred kind.
+        assert_eq!(
+            LowestLatency.select(destination, normal, &candidates[2..]),
+            Some(1u32.into())
+        );
+
+        let selector = ByPriority::new(LowestLatency, PreferDirect);
+        assert_eq!(
+            selector.select(destination, SendPriority::Maintenance, &candidates),
+            Some(2u32.into())
+        );
+        assert_eq!(
+            selector.select(destination, normal, &candidates),
+            Some(1u32.into())
+        );
+    }
+
+    #[test]
+    fn test_send_priority_of_transaction() {
+        let session_sk = SessionSk::new_with_seckey(&SecretKey::random()).unwrap();
+        let destination: Did = SecretKey::random().address().into();
+        let transaction = |msg: Message| {
+            Transaction::new(destination, uuid::Uuid::new_v4(), msg, &session_sk).unwrap()
+        };
+
+        let query = Message::QueryForTopoInfoSend(QueryForTopoInfoSend {
+            did: destination,
+            then: QueryFor::Stabilization,
+        });
+        assert_eq!(
+            SendPriority::of_transaction(&transaction(query)),
+            SendPriority::Maintenance
+        );
+        assert_eq!(
+            SendPriority::of_transaction(&transaction(Message::RoutingQuery(RoutingQuery))),
+            SendPriority::Normal
+        );
+        // Data not being a message is sent at normal priority.
+        let raw = Transaction::new(destination, uuid::Uuid::new_v4(), 42u8, &session_sk).unwrap();
+        assert_eq!(SendPriority::of_transaction(&raw), SendPriority::Normal);
+    }
+}
@@ -0,0 +1,183 @@
+#![warn(missing_docs)]
+//! Presets of timing and buffering settings, see [NetworkProfile].
+//!
+//! A profile presets the following settings, each of which can still be given explicitly:
+//!
+//! | setting | given explicitly by |
+//! |---|---|
+//! | [NetworkProfile::lookup_retry] | [SwarmBuilder::lookup_retry](crate::swarm::SwarmBuilder::lookup_retry) |
+//! | [NetworkProfile::buffer_watermark] | [SwarmBuilder::buffer_watermark](crate::swarm::SwarmBuilder::buffer_watermark) |
+//! | [NetworkProfile::send_timeout_ms] | [SwarmBuilder::send_timeout](crate::swarm::SwarmBuilder::send_timeout) |
+//! | [NetworkProfile::ice_gathering_timeout_ms] | [SwarmBuilder::ice_gathering_timeout](crate::swarm::SwarmBuilder::ice_gathering_timeout) |
+//! | [NetworkProfile::stabilize_interval] | `stabilize_interval` of the processor builder of rings-node |
+//!
+//! Other timings are not part of a profile:
+//!
+//! - TTL of messages is [DEFAULT_TTL_MS](crate::consts::DEFAULT_TTL_MS) for every sender.
+//!   How late a message may arrive is decided by receivers through
+//!   [ExpiryPolicy](crate::swarm::ExpiryPolicy), per type of message, which depends on the
+//!   application rather than the network.
+//! - Keepalive of connections is done by ICE consent checks of the transport, which are not
+//!   configurable.
+//! - Dialing has no timeout of its own. Creating an offer is bounded by the ICE gathering
+//!   timeout, and the rest of handshake waits on the peer.
+//! - Idle connections are never reaped, connections are only closed once their transport
+//!   fails.
+//! - Swarm is driven by transport events instead of polling. Only the listener of browsers
+//!   yields to the event loop at a fixed interval, which doesn't affect the network.
+//!
+//! [NetworkProfile::Balanced] keeps the default of every setting.
+
+use rings_transport::backpressure::BufferWatermark;
+use rings_transport::ice_gathering::DEFAULT_ICE_GATHERING_TIMEOUT_MS;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::swarm::RetryBudget;
+use crate::swarm::DEFAULT_SEND_TIMEOUT_MS;
+
+/// Default interval of stabilization in seconds, which is the one of
+/// [NetworkProfile::Balanced].
+pub const DEFAULT_STABILIZE_INTERVAL: usize = 3;
+
+/// NetworkProfile bundles coherent defaults of the settings which should be tuned together,
+/// such as retries of DHT lookups, buffering of data channels and timeouts.
+/// A setting given explicitly on a builder always takes precedence over the profile.
+/// See the [module level documentation](self) for the settings covered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NetworkProfile {
+    /// Defaults suitable for most desktop and server nodes.
+    #[default]
+    Balanced,
+    /// Minimizes delay of small messages: quick retries, small send buffers so that
+    /// messages never wait behind a long queue, and short timeouts which fail fast instead
+    /// of waiting. Routes are stabilized more often to stay fresh.
+    LowLatency,
+    /// Saves battery and data on mobile networks: moderate buffers, patient retries and
+    /// timeouts for links that come and go, and rare stabilization.
+    Mobile,
+    /// Maximizes bulk transfer: large send buffers keep data channels busy, and sendings
+    /// wait longer for them to drain.
+    HighThroughput,
+    /// Tolerates packet loss and flaky links: more lookup attempts with longer backoff,
+    /// smaller buffers so that a stalled channel holds less data, long timeouts, and frequent
+    /// stabilization to repair the ring.
+    Lossy,
+}
+
+impl NetworkProfile {
+    /// Retry budget of DHT lookups.
+    pub fn lookup_retry(&self) -> RetryBudget {
+        match self {
+            Self::Balanced => RetryBudget::default(),
+            Self::LowLatency => RetryBudget::new(2, 50),
+            Self::Mobile => RetryBudget::new(3, 500),
+            Self::HighThroughput => RetryBudget::new(3, 200),
+            Self::Lossy => RetryBudget::new(5, 300),
+        }
+    }
+
+    /// Watermarks of buffered amount of data channels.
+    pub fn buffer_watermark(&self) -> BufferWatermark {
+        const KIB: usize = 1024;
+        const MIB: usize = 1024 * KIB;
+        match self {
+            Self::Balanced => BufferWatermark::default(),
+            Self::LowLatency => BufferWatermark::new(256 * KIB, 64 * KIB),
+            Self::Mobile => BufferWatermark::new(MIB, 256 * KIB),
+            Self::HighThroughput => BufferWatermark::new(16 * MIB, 4 * MIB),
+            Self::Lossy => BufferWatermark::new(2 * MIB, 512 * KIB),
+        }
+    }
+
+    /// Max time in milliseconds for a sending to hand off a message to transport.
+    pub fn send_timeout_ms(&self) -> u64 {
+        match self {
+            Self::Balanced => DEFAULT_SEND_TIMEOUT_MS,
+            Self::LowLatency => 2 * 1000,
+            Self::Mobile => 20 * 1000,
+            Self::HighThroughput => 30 * 1000,
+            Self::Lossy => 20 * 1000,
+        }
+    }
+
+    /// Max time in milliseconds waiting for all local ICE candidates to be gathered.
+    pub fn ice_gathering_timeout_ms(&self) -> u64 {
+        match self {
+            Self::Balanced => DEFAULT_ICE_GATHERING_TIMEOUT_MS,
+            Self::LowLatency => 1000,
+            Self::Mobile => 5000,
+            Self::HighThroughput => DEFAULT_ICE_GATHERING_TIMEOUT_MS,
+            Self::Lossy => 5000,
+        }
+    }
+
+    /// Interval of stabilization in seconds.
+    pub fn stabilize_interval(&self) -> usize {
+        match self {
+            Self::Balanced => DEFAULT_STABILIZE_INTERVAL,
+            Self::LowLatency => 2,
+            Self::Mobile => 20,
+            Self::HighThroughput => 5,
+            Self::Lossy => 2,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PROFILES: [NetworkProfile; 5] = [
+        NetworkProfile::Balanced,
+        NetworkProfile::LowLatency,
+        NetworkProfile::Mobile,
+        NetworkProfile::HighThroughput,
+        NetworkProfile::Lossy,
+    ];
+
+    #[test]
+    fn test_balanced_profile_keeps_defaults() {
+        let profile = NetworkProfile::default();
+        assert_eq!(profile, NetworkProfile::Balanced);
+        assert_eq!(profile.lookup_retry(), RetryBudget::default());
+        assert_eq!(profile.buffer_watermark(), BufferWatermark::default());
+        assert_eq!(profile.send_timeout_ms(), DEFAULT_SEND_TIMEOUT_MS);
+        assert_eq!(
+            profile.ice_gathering_timeout_ms(),
+            DEFAULT_ICE_GATHERING_TIMEOUT_MS
+        );
+        assert_eq!(profile.stabilize_interval(), DEFAULT_STABILIZE_INTERVAL);
+    }
+
+    #[test]
+    fn test_profiles_are_coherent() {
+        for profile in PROFILES {
+            let watermark = profile.buffer_watermark();
+            assert!(watermark.low < watermark.high, "{profile:?}");
+            assert!(profile.lookup_retry().attempts >= 1, "{profile:?}");
+            assert!(profile.send_timeout_ms() > 0, "{profile:?}");
+            assert!(profile.ice_gathering_timeout_ms() > 0, "{profile:?}");
+            assert!(profile.stabilize_interval() > 0, "{profile:?}");
+        }
+
+        let balanced = NetworkProfile::Balanced;
+        let low_latency = NetworkProfile::LowLatency;
+        assert!(low_latency.send_timeout_ms() < balanced.send_timeout_ms());
+        assert!(low_latency.ice_gathering_timeout_ms() < balanced.ice_gathering_timeout_ms());
+        assert!(low_latency.buffer_watermark().high < balanced.buffer_watermark().high);
+
+        let mobile = NetworkProfile::Mobile;
+        assert!(mobile.stabilize_interval() > balanced.stabilize_interval());
+        assert!(mobile.send_timeout_ms() > balanced.send_timeout_ms());
+
+        let high_throughput = NetworkProfile::HighThroughput;
+        assert!(high_throughput.buffer_watermark().high > balanced.buffer_watermark().high);
+        assert!(high_throughput.send_timeout_ms() > balanced.send_timeout_ms());
+
+        let lossy = NetworkProfile::Lossy;
+        assert!(lossy.lookup_retry().attempts > balanced.lookup_retry().attempts);
+        assert!(lossy.ice_gathering_timeout_ms() > balanced.ice_gathering_timeout_ms());
+        assert!(lossy.stabilize_interval() < balanced.stabilize_interval());
+    }
+}
@@ -0,0 +1,224 @@
+//! This module provides [SendQueue], which serializes sendings to a peer.
+//! Messages to a peer are sent in submission order, while sendings to different peers
+//! are not blocked by each other. Urgent sendings, such as DHT maintenance during initial
+//! convergence, jump ahead of normal sendings waiting in queue.
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+use futures::channel::oneshot;
+
+#[derive(Debug, Default)]
+struct QueueState {
+    busy: bool,
+    urgent: VecDeque<oneshot::Sender<()>>,
+    waiters: VecDeque<oneshot::Sender<()>>,
+    avg_send_ms: Option<f64>,
+}
+
+/// Weight of the latest sample in the moving average of send time.
+const SEND_MS_ALPHA: f64 = 0.2;
+
+/// A FIFO queue of sendings to a peer. Cloned queues share the same state.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SendQueue {
+    state: Arc<Mutex<QueueState>>,
+}
+
+/// Permit to send, the next sending in queue is woken when it's dropped.
+pub(crate) struct SendPermit {
+    queue: SendQueue,
+}
+
+/// A sending waiting in queue, releases the permit if it is granted but cancelled.
+struct Waiter {
+    rx: oneshot::Receiver<()>,
+    queue: SendQueue,
+    granted: bool,
+}
+
+impl SendQueue {
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Number of sendings in flight, including the queued ones.
+    pub fn in_flight(&self) -> usize {
+        let state = self.lock();
+        state.busy as usize + state.urgent.len() + state.waiters.len()
+    }
+
+    /// Record milliseconds taken by transport to accept a sending.
+    pub fn record_send_ms(&self, ms: f64) {
+        let mut state = self.lock();
+        state.avg_send_ms = Some(match state.avg_send_ms {
+            Some(avg) => avg + SEND_MS_ALPHA * (ms - avg),
+            None => ms,
+        });
+    }
+
+    /// Moving average of milliseconds taken by transport to accept a sending.
+    pub fn avg_send_ms(&self) -> Option<f64> {
+        self.lock().avg_send_ms
+    }
+
+    /// Wait until all sendings submitted before are done.
+    /// The sending is enqueued on the first poll, before any awaiting.
+    pub async fn acquire(&self) -> SendPermit {
+        self.enqueue(false).await
+    }
+
+    /// Wait until the running sending and urgent sendings submitted before are done.
+    pub async fn acquire_urgent(&self) -> SendPermit {
+        self.enqueue(true).await
+    }
+
+    async fn enqueue(&self, urgent: bool) -> SendPermit {
+        let rx = {
+            let mut state = self.lock();
+            if !state.busy {
+                state.busy = true;
+                return SendPermit {
+                    queue: self.clone(),
+                };
+            }
+            let (tx, rx) = oneshot::channel();
+            if urgent {
+                state.urgent.push_back(tx);
+            } else {
+                state.waiters.push_back(tx);
+            }
+            rx
+        };
+
+        let mut waiter = Waiter {
+            rx,
+            queue: self.clone(),
+            granted: false,
+        };
+        // The sender is only dropped after sending, so the result is always `Ok`.
+        let _ = (&mut waiter.rx).await;
+        waiter.granted = true;
+
+        SendPermit {
+            queue: self.clone(),
+        }
+    }
+
+    fn release(&self) {
+        let mut state = self.lock();
+        while let Some(tx) = state
+            .urgent
+            .pop_front()
+            .or_else(|| state.waiters.pop_front())
+        {
+            // Skip the waiters which are cancelled.
+            if tx.send(()).is_ok() {
+                return;
+            }
+        }
+        state.busy = false;
+    }
+}
+
+impl Drop for SendPermit {
+    fn drop(&mut self) {
+        self.queue.release()
+    }
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        if !self.granted {
+            if let Ok(Some(())) = self.rx.try_recv() {
+                self.queue.release()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(feature = "wasm"))]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::utils::timeout_ms;
+
+    #[tokio::test]
+    async fn test_send_queue_order() {
+        let queue = SendQueue::default();
+        let order = Arc::new(Mutex::new(vec![]));
+
+        let first = queue.acquire().await;
+        let sendings = (0..5).map(|i| {
+            let queue = queue.clone();
+            let order = order.clone();
+            async move {
+                let _permit = queue.acquire().await;
+                futures_timer::Delay::new(Duration::from_millis(10 * (5 - i))).await;
+                order.lock().unwrap().push(i);
+            }
+        });
+
+        // A cancelled waiter should not block the queue.
+        let mut cancelled = Box::pin(queue.acquire());
+        assert!(futures::poll!(&mut cancelled).is_pending());
+
+        let mut all = Box::pin(futures::future::join_all(sendings));
+        assert!(futures::poll!(&mut all).is_pending());
+        drop(cancelled);
+        drop(first);
+        all.await;
+
+        assert_eq!(*order.lock().unwrap(), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_send_queue_urgent() {
+        let queue = SendQueue::default();
+        let order = Arc::new(Mutex::new(vec![]));
+
+        let first = queue.acquire().await;
+        let sending = |name: &'static str, urgent: bool| {
+            let queue = queue.clone();
+            let order = order.clone();
+            async move {
+                let _permit = if urgent {
+                    queue.acquire_urgent().await
+                } else {
+                    queue.acquire().await
+                };
+                order.lock().unwrap().push(name);
+            }
+        };
+
+        let mut all = Box::pin(futures::future::join_all([
+            sending("normal_1", false),
+            sending("normal_2", false),
+            sending("urgent_1", true),
+            sending("urgent_2", true),
+        ]));
+        assert!(futures::poll!(&mut all).is_pending());
+        drop(first);
+        all.await;
+
+        assert_eq!(*order.lock().unwrap(), vec![
+            "urgent_1", "urgent_2", "normal_1", "normal_2"
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_send_queue_timeout() {
+        let queue = SendQueue::default();
+
+        let first = queue.acquire().await;
+        assert!(timeout_ms(50, queue.acquire()).await.is_none());
+        assert!(timeout_ms(50, queue.acquire_urgent()).await.is_none());
+
+        // Sendings timed out leave the queue, so they never block the sendings after them.
+        drop(first);
+        assert!(timeout_ms(50, queue.acquire()).await.is_some());
+    }
+}
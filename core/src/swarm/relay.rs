@@ -0,0 +1,208 @@
+#![warn(missing_docs)]
+//! This module provides [RelaySelector], which picks the connected peer relaying a message
+//! when the next hop inferred by DHT is not connected. Any candidate is closer to destination
+//! than current node, so the choice trades progress on the ring for load spreading.
+//!
+//! It also provides [ObservedRelays], the connected peers which actually relayed messages
+//! signed by other nodes, so that a node known to be reached by relay is told from one
+//! which merely has a route inferred by DHT.
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use rand::distributions::Distribution;
+use rand::distributions::WeightedIndex;
+
+use crate::dht::Did;
+use crate::utils::get_epoch_ms;
+
+/// How long in milliseconds a relay observed is taken as the way to reach the signer.
+pub const OBSERVED_RELAY_TTL_MS: u128 = 60 * 1000;
+
+/// Max number of signers whose relay is kept by [ObservedRelays].
+pub const MAX_OBSERVED_RELAYS: usize = 4096;
+
+/// A connected peer which can relay a message towards destination.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelayCandidate {
+    /// Did of the peer.
+    pub did: Did,
+    /// Number of sendings to the peer in flight, including the queued ones.
+    pub in_flight: usize,
+    /// Moving average of milliseconds taken by transport to accept a sending to the peer,
+    /// which grows with backpressure of the data channel. None if nothing is sent yet.
+    pub avg_send_ms: Option<f64>,
+    /// Score of the peer, see [PeerScores](crate::swarm::PeerScores).
+    pub score: f64,
+}
+
+/// A strategy choosing relay among candidates, which are never empty.
+pub trait RelaySelector {
+    /// Returns the did of the chosen relay.
+    fn select(&self, destination: Did, candidates: &[RelayCandidate]) -> Option<Did>;
+}
+
+/// Type of RelaySelector, see [RelaySelector].
+#[cfg(not(feature = "wasm"))]
+pub type RelaySelectorImpl = Box<dyn RelaySelector + Send + Sync>;
+
+/// Type of RelaySelector, see [RelaySelector].
+#[cfg(feature = "wasm")]
+pub type RelaySelectorImpl = Box<dyn RelaySelector>;
+
+/// The default selector, which always chooses the candidate closest to destination.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ClosestRelay;
+
+impl RelaySelector for ClosestRelay {
+    fn select(&self, destination: Did, candidates: &[RelayCandidate]) -> Option<Did> {
+        candidates
+            .iter()
+            .map(|c| c.did)
+            .min_by_key(|did| destination - *did)
+    }
+}
+
+/// Weight of a candidate favoring low send latency, see [RelayCandidate::avg_send_ms].
+/// The load is taken into account as well, so that a fast relay still yields when it's busy.
+pub fn inverse_latency(candidate: &RelayCandidate) -> f64 {
+    inverse_load(candidate) / (1.0 + candidate.avg_send_ms.unwrap_or_default())
+}
+
+/// Weight of a candidate favoring few sendings in flight, see [RelayCandidate::in_flight].
+pub fn inverse_load(candidate: &RelayCandidate) -> f64 {
+    1.0 / (1.0 + candidate.in_flight as f64)
+}
+
+/// A selector choosing candidates randomly in proportion to their weights, so that traffic
+/// is spread over relays while good relays are still favored. Candidates of non-positive
+/// weight are never chosen, and it falls back to [ClosestRelay] if no candidate has weight.
+#[derive(Debug, Clone, Copy)]
+pub struct WeightedRandomRelay {
+    weight: fn(&RelayCandidate) -> f64,
+}
+
+impl WeightedRandomRelay {
+    /// Create a selector with a weighting function, such as [inverse_latency].
+    pub fn new(weight: fn(&RelayCandidate) -> f64) -> Self {
+        Self { weight }
+    }
+}
+
+impl Default for WeightedRandomRelay {
+    fn default() -> Self {
+        Self::new(inverse_latency)
+    }
+}
+
+impl RelaySelector for WeightedRandomRelay {
+    fn select(&self, destination: Did, candidates: &[RelayCandidate]) -> Option<Did> {
+        let weights = candidates.iter().map(|c| {
+            let w = (self.weight)(c);
+            if w.is_finite() && w > 0.0 {
+                w
+            } else {
+                0.0
+            }
+        });
+        match WeightedIndex::new(weights) {
+            Ok(dist) => Some(candidates[dist.sample(&mut rand::thread_rng())].did),
+            Err(_) => ClosestRelay.select(destination, candidates),
+        }
+    }
+}
+
+/// The connected peers which last relayed a message of each signer, with the time observed.
+/// Cloning it shares the observations.
+#[derive(Debug, Clone, Default)]
+pub struct ObservedRelays(Arc<DashMap<Did, (Did, u128)>>);
+
+impl ObservedRelays {
+    /// Record a message signed by `signer` received from connected peer `via`.
+    /// A message received from its signer is not relayed, and it's ignored.
+    /// At most [MAX_OBSERVED_RELAYS] signers are kept, the earliest observed is dropped.
+    pub fn observe(&self, signer: Did, via: Did) {
+        if signer == via {
+            return;
+        }
+        if self.0.len() >= MAX_OBSERVED_RELAYS && !self.0.contains_key(&signer) {
+            let earliest = self
+                .0
+                .iter()
+                .min_by_key(|entry| entry.1)
+                .map(|entry| *entry.key());
+            if let Some(earliest) = earliest {
+                self.0.remove(&earliest);
+            }
+        }
+        self.0.insert(signer, (via, get_epoch_ms()));
+    }
+
+    /// Get the peer which relayed a message of did within [OBSERVED_RELAY_TTL_MS].
+    pub fn get(&self, did: Did) -> Option<Did> {
+        let (via, at) = *self.0.get(&did)?;
+        (get_epoch_ms().saturating_sub(at) < OBSERVED_RELAY_TTL_MS).then_some(via)
+    }
+
+    /// Forget the relays observed through peer, such as when its connection is closed.
+    pub fn remove_via(&self, via: Did) {
+        self.0.retain(|_, (v, _)| *v != via);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ecc::SecretKey;
+
+    fn candidate(in_flight: usize, avg_send_ms: Option<f64>) -> RelayCandidate {
+        RelayCandidate {
+            did: SecretKey::random().address().into(),
+            in_flight,
+            avg_send_ms,
+            score: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_weighted_random_relay() {
+        let destination: Did = SecretKey::random().address().into();
+        let idle = candidate(0, Some(1.0));
+        let busy = candidate(10, Some(1.0));
+        let candidates = [idle.clone(), busy.clone()];
+
+        let selector = WeightedRandomRelay::new(inverse_load);
+        let picks = (0..1000)
+            .filter_map(|_| selector.select(destination, &candidates))
+            .collect::<Vec<_>>();
+        let idle_picks = picks.iter().filter(|did| **did == idle.did).count();
+        // Both relays carry traffic, while the idle one is favored.
+        assert_eq!(picks.len(), 1000);
+        assert!(idle_picks > 800 && idle_picks < 1000, "{}", idle_picks);
+
+        // Without any weighted candidate, the closest one is chosen.
+        let selector = WeightedRandomRelay::new(|_| 0.0);
+        assert_eq!(
+            selector.select(destination, &candidates),
+            ClosestRelay.select(destination, &candidates)
+        );
+    }
+
+    #[test]
+    fn test_observed_relays() {
+        let signer: Did = SecretKey::random().address().into();
+        let via: Did = SecretKey::random().address().into();
+        let relays = ObservedRelays::default();
+        assert_eq!(relays.get(signer), None);
+
+        // A message received from its own signer is not relayed.
+        relays.observe(signer, signer);
+        assert_eq!(relays.get(signer), None);
+
+        relays.observe(signer, via);
+        assert_eq!(relays.get(signer), Some(via));
+        assert_eq!(relays.clone().get(signer), Some(via));
+
+        relays.remove_via(via);
+        assert_eq!(relays.get(signer), None);
+    }
+}
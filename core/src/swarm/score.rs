@@ -0,0 +1,152 @@
+#![warn(missing_docs)]
+//! This module provides [PeerScores], a reputation of peers based on observed behaviour,
+//! such as deliveries, timeouts, invalid signatures, rate limit violations and round trips.
+//! Scores decay towards zero over time, so that a misbehaving peer can recover.
+use std::sync::Arc;
+
+use dashmap::DashMap;
+
+use crate::dht::Did;
+use crate::utils::get_epoch_ms;
+
+/// Time in milliseconds for a score to decay by half.
+pub const SCORE_HALF_LIFE_MS: u64 = 10 * 60 * 1000;
+
+/// Bound of absolute value of a score.
+pub const MAX_SCORE: f64 = 100.0;
+
+/// Peers scoring lower are considered flaky, and are avoided as relays.
+pub const FLAKY_SCORE: f64 = -10.0;
+
+/// A round trip longer than this in milliseconds is penalized.
+pub const SLOW_RTT_MS: u64 = 1000;
+
+/// Behaviour of peer observed by node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerSignal {
+    /// A message is handed off to the transport of peer.
+    Delivered,
+    /// Failed on sending a message to peer.
+    Failed,
+    /// Sending a message to peer timed out.
+    Timeout,
+    /// Peer sent a message failing on signature verification.
+    InvalidSignature,
+    /// Peer sent a message which cannot be decoded.
+    Malformed,
+    /// Peer exceeded a rate limit, such as max number of requests in flight.
+    RateLimited,
+    /// A round trip with peer in milliseconds.
+    Rtt(u64),
+}
+
+impl PeerSignal {
+    fn delta(&self) -> f64 {
+        match self {
+            Self::Delivered => 1.0,
+            Self::Failed => -2.0,
+            Self::Timeout => -3.0,
+            Self::InvalidSignature => -10.0,
+            Self::Malformed => -5.0,
+            Self::RateLimited => -5.0,
+            Self::Rtt(ms) if *ms > SLOW_RTT_MS => -1.0,
+            Self::Rtt(_) => 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ScoreEntry {
+    score: f64,
+    updated_at_ms: u128,
+}
+
+impl ScoreEntry {
+    fn decayed(&self, now_ms: u128) -> f64 {
+        let elapsed = now_ms.saturating_sub(self.updated_at_ms) as f64;
+        self.score * 0.5f64.powf(elapsed / SCORE_HALF_LIFE_MS as f64)
+    }
+}
+
+/// Scores of peers, cloning it shares the scores. A peer never observed scores zero.
+#[derive(Debug, Clone, Default)]
+pub struct PeerScores(Arc<DashMap<Did, ScoreEntry>>);
+
+impl PeerScores {
+    /// Record a behaviour of peer.
+    pub fn record(&self, did: Did, signal: PeerSignal) {
+        self.record_at(did, signal, get_epoch_ms())
+    }
+
+    fn record_at(&self, did: Did, signal: PeerSignal, now_ms: u128) {
+        let mut entry = self.0.entry(did).or_insert(ScoreEntry {
+            score: 0.0,
+            updated_at_ms: now_ms,
+        });
+        let score = entry.decayed(now_ms) + signal.delta();
+        *entry = ScoreEntry {
+            score: score.clamp(-MAX_SCORE, MAX_SCORE),
+            updated_at_ms: now_ms,
+        };
+    }
+
+    /// Get score of peer, in range of [-MAX_SCORE, MAX_SCORE].
+    pub fn score(&self, did: Did) -> f64 {
+        self.score_at(did, get_epoch_ms())
+    }
+
+    fn score_at(&self, did: Did, now_ms: u128) -> f64 {
+        self.0
+            .get(&did)
+            .map(|entry| entry.decayed(now_ms))
+            .unwrap_or_default()
+    }
+
+    /// Check if peer scores lower than [FLAKY_SCORE].
+    pub fn is_flaky(&self, did: Did) -> bool {
+        self.score(did) < FLAKY_SCORE
+    }
+
+    /// Forget score of peer.
+    pub fn remove(&self, did: Did) {
+        self.0.remove(&did);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_peer_scores() {
+        let a = Did::from_str("0x11E807fcc88dD319270493fB2e822e388Fe36ab0").unwrap();
+        let b = Did::from_str("0x999999cf1046e68e36E1aA2E0E07105eDDD1f08E").unwrap();
+        let scores = PeerScores::default();
+        let now = 1_000_000;
+
+        for _ in 0..3 {
+            scores.record_at(a, PeerSignal::Delivered, now);
+        }
+        scores.record_at(a, PeerSignal::Rtt(SLOW_RTT_MS), now);
+        assert_eq!(scores.score_at(a, now), 3.0);
+        assert_eq!(scores.score_at(b, now), 0.0);
+
+        scores.record_at(b, PeerSignal::InvalidSignature, now);
+        scores.record_at(b, PeerSignal::Timeout, now);
+        assert_eq!(scores.score_at(b, now), -13.0);
+
+        // Scores decay by half in a half life, so that a flaky peer recovers.
+        let later = now + SCORE_HALF_LIFE_MS as u128;
+        assert_eq!(scores.score_at(a, later), 1.5);
+        assert_eq!(scores.score_at(b, later), -6.5);
+        scores.record_at(b, PeerSignal::Delivered, later);
+        assert_eq!(scores.score_at(b, later), -5.5);
+
+        for _ in 0..100 {
+            scores.record_at(b, PeerSignal::InvalidSignature, later);
+        }
+        assert_eq!(scores.score_at(b, later), -MAX_SCORE);
+    }
+}
@@ -0,0 +1,485 @@
+#![warn(missing_docs)]
+//! Direct streams for bulk transfer between connected peers.
+//!
+//! Chunking a large transfer into signed [CustomMessage](crate::message::CustomMessage)s
+//! pays the relay envelope and signatures on every chunk. A stream is opened instead by a
+//! signed [StreamOpen](crate::message::StreamOpen) sent directly to the peer, and its frames
+//! are then sent as bare bytes on the same transport, tagged by [STREAM_FRAME_TAG].
+//! A frame is accepted only from the transport of the peer which opened the stream,
+//! so it's authenticated by the stream setup and the transport of peer.
+//!
+//! Frames of a stream are queued up to [STREAM_QUEUE_FRAMES]. Once the queue is full,
+//! delivering the next frame waits for the reader, which holds back the transport of peer
+//! until the reader catches up, so a slow reader slows down the writer instead of buffering
+//! without bound.
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex as SyncMutex;
+use std::task::ready;
+use std::task::Context;
+use std::task::Poll;
+
+use async_lock::Mutex;
+use bytes::Bytes;
+use dashmap::DashMap;
+use futures::channel::mpsc;
+use futures::io::AsyncRead;
+use futures::io::AsyncWrite;
+use futures::Future;
+use futures::SinkExt;
+use futures::StreamExt;
+use rings_transport::core::transport::ConnectionInterface;
+use rings_transport::core::transport::TransportMessage;
+use uuid::Uuid;
+
+use crate::consts::TRANSPORT_MTU;
+use crate::dht::Did;
+use crate::error::Error;
+use crate::error::Result;
+use crate::types::Connection;
+
+/// Leading byte of a stream frame. Encodings of [MessagePayload](crate::message::MessagePayload)
+/// start with [LEGACY_PAYLOAD_TAG](crate::message::LEGACY_PAYLOAD_TAG) or a tag of
+/// [PAYLOAD_VERSION_TAGS](crate::message::PAYLOAD_VERSION_TAGS),
+/// so that frames are told apart by the first byte.
+pub const STREAM_FRAME_TAG: u8 = 0xD0;
+
+/// Max length of data in a frame.
+pub const STREAM_FRAME_MAX_LEN: usize = TRANSPORT_MTU;
+
+/// Max number of streams opened by a peer and not finished yet.
+pub const MAX_STREAMS_PER_PEER: usize = 16;
+
+/// Max number of frames queued for a stream and not read yet.
+pub const STREAM_QUEUE_FRAMES: usize = 64;
+
+/// Max number of streams opened and not accepted yet.
+pub const MAX_PENDING_STREAMS: usize = 64;
+
+const STREAM_FRAME_HEADER_LEN: usize = 18;
+
+/// Kind of a stream frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum FrameKind {
+    /// Data of stream.
+    Data = 0,
+    /// The stream is finished by writer.
+    End = 1,
+    /// The stream is torn down by writer on error.
+    Abort = 2,
+}
+
+/// A frame of stream, which is encoded as tag, stream id, kind and data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StreamFrame {
+    pub id: Uuid,
+    pub kind: FrameKind,
+    pub data: Bytes,
+}
+
+impl StreamFrame {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(STREAM_FRAME_HEADER_LEN + self.data.len());
+        frame.push(STREAM_FRAME_TAG);
+        frame.extend_from_slice(self.id.as_bytes());
+        frame.push(self.kind as u8);
+        frame.extend_from_slice(&self.data);
+        frame
+    }
+
+    pub fn decode(frame: &[u8]) -> Result<Self> {
+        if frame.len() < STREAM_FRAME_HEADER_LEN || frame[0] != STREAM_FRAME_TAG {
+            return Err(Error::InvalidStreamFrame);
+        }
+        let id = Uuid::from_slice(&frame[1..17]).map_err(|_| Error::InvalidStreamFrame)?;
+        let kind = match frame[17] {
+            0 => FrameKind::Data,
+            1 => FrameKind::End,
+            2 => FrameKind::Abort,
+            _ => return Err(Error::InvalidStreamFrame),
+        };
+        Ok(Self {
+            id,
+            kind,
+            data: Bytes::copy_from_slice(&frame[STREAM_FRAME_HEADER_LEN..]),
+        })
+    }
+}
+
+// The sender is shared behind a lock rather than cloned, since every clone of a bounded
+// sender is given a slot of its own, which would make the queue unbounded.
+type FrameSender = Arc<Mutex<mpsc::Sender<StreamFrame>>>;
+
+struct StreamsInner {
+    inbound: DashMap<(Did, Uuid), FrameSender>,
+    incoming_tx: SyncMutex<mpsc::Sender<StreamReader>>,
+    incoming_rx: Mutex<mpsc::Receiver<StreamReader>>,
+}
+
+/// Inbound streams of swarm, cloning it shares the streams.
+#[derive(Clone)]
+pub struct Streams(Arc<StreamsInner>);
+
+impl Default for Streams {
+    fn default() -> Self {
+        // The channel has a slot for its sender in addition to its buffer.
+        let (incoming_tx, incoming_rx) = mpsc::channel(MAX_PENDING_STREAMS - 1);
+        Self(Arc::new(StreamsInner {
+            inbound: DashMap::new(),
+            incoming_tx: SyncMutex::new(incoming_tx),
+            incoming_rx: Mutex::new(incoming_rx),
+        }))
+    }
+}
+
+impl Streams {
+    /// Register a stream opened by peer, which is then returned by [Streams::accept].
+    /// Fails with [Error::TooManyStreams] if peer has [MAX_STREAMS_PER_PEER] streams open,
+    /// and with [Error::TooManyPendingStreams] if [MAX_PENDING_STREAMS] streams are not
+    /// accepted yet.
+    pub fn register(&self, peer: Did, id: Uuid) -> Result<()> {
+        let open = self.0.inbound.iter().filter(|e| e.key().0 == peer).count();
+        if open >= MAX_STREAMS_PER_PEER {
+            return Err(Error::TooManyStreams(peer));
+        }
+        // The channel has a slot for its sender in addition to its buffer.
+        let (tx, rx) = mpsc::channel(STREAM_QUEUE_FRAMES - 1);
+        let reader = StreamReader {
+            peer,
+            id,
+            frames: rx,
+            buf: Bytes::new(),
+            ended: false,
+        };
+        self.0
+            .incoming_tx
+            .lock()
+            .map_err(|_| Error::TooManyPendingStreams)?
+            .try_send(reader)
+            .map_err(|_| Error::TooManyPendingStreams)?;
+        self.0.inbound.insert((peer, id), Arc::new(Mutex::new(tx)));
+        Ok(())
+    }
+
+    /// Deliver a frame received from the transport of peer to its stream.
+    /// Waits for the reader if [STREAM_QUEUE_FRAMES] frames of the stream are not read yet.
+    pub async fn dispatch(&self, peer: Did, frame: &[u8]) -> Result<()> {
+        let frame = StreamFrame::decode(frame)?;
+        let key = (peer, frame.id);
+        let finished = frame.kind != FrameKind::Data;
+        // The entry is not held across the wait, so that other streams are not blocked.
+        let tx = match self.0.inbound.get(&key) {
+            Some(tx) => tx.clone(),
+            None => return Err(Error::StreamNotFound(peer, key.1)),
+        };
+        let delivered = tx.lock().await.send(frame).await.is_ok();
+        // A stream dropped by reader, or finished by writer, is forgotten.
+        if finished || !delivered {
+            self.0.inbound.remove(&key);
+        }
+        Ok(())
+    }
+
+    /// Wait for the next stream opened by a peer.
+    pub async fn accept(&self) -> Option<StreamReader> {
+        self.0.incoming_rx.lock().await.next().await
+    }
+
+    /// Tear down streams of peer, such as when its transport is closed.
+    /// Readers of them fail with [io::ErrorKind::UnexpectedEof].
+    pub fn close_peer(&self, peer: Did) {
+        self.0.inbound.retain(|k, _| k.0 != peer);
+    }
+}
+
+/// Reading half of a stream opened by peer, see [Streams::accept].
+/// Reading fails with [io::ErrorKind::UnexpectedEof] if the stream is torn down
+/// before finished by writer, and with [io::ErrorKind::ConnectionAborted] if aborted.
+pub struct StreamReader {
+    peer: Did,
+    id: Uuid,
+    frames: mpsc::Receiver<StreamFrame>,
+    buf: Bytes,
+    ended: bool,
+}
+
+impl StreamReader {
+    /// Did of the peer writing the stream.
+    pub fn peer(&self) -> Did {
+        self.peer
+    }
+
+    /// Id of the stream.
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+}
+
+impl AsyncRead for StreamReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        out: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            if !self.buf.is_empty() {
+                let n = out.len().min(self.buf.len());
+                out[..n].copy_from_slice(&self.buf.split_to(n));
+                return Poll::Ready(Ok(n));
+            }
+            if self.ended {
+                return Poll::Ready(Ok(0));
+            }
+            match ready!(self.frames.poll_next_unpin(cx)) {
+                Some(frame) => match frame.kind {
+                    FrameKind::Data => self.buf = frame.data,
+                    FrameKind::End => self.ended = true,
+                    FrameKind::Abort => {
+                        return Poll::Ready(Err(io::ErrorKind::ConnectionAborted.into()))
+                    }
+                },
+                None => return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into())),
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+type SendFuture = futures::future::BoxFuture<'static, io::Result<()>>;
+
+#[cfg(feature = "wasm")]
+type SendFuture = futures::future::LocalBoxFuture<'static, io::Result<()>>;
+
+/// Writing half of a stream opened to peer, see [Swarm::open_stream](super::Swarm::open_stream).
+/// A write is accepted once the former frame is handed off to transport, so `flush` should be
+/// called to see the result of the last write. The stream should be finished by `close`,
+/// or torn down by [StreamWriter::abort] on error.
+pub struct StreamWriter {
+    peer: Did,
+    id: Uuid,
+    conn: Connection,
+    sending: Option<SendFuture>,
+    closed: bool,
+}
+
+impl StreamWriter {
+    pub(crate) fn new(peer: Did, id: Uuid, conn: Connection) -> Self {
+        Self {
+            peer,
+            id,
+            conn,
+            sending: None,
+            closed: false,
+        }
+    }
+
+    /// Did of the peer reading the stream.
+    pub fn peer(&self) -> Did {
+        self.peer
+    }
+
+    /// Id of the stream.
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// Tear down the stream, reader of it fails with [io::ErrorKind::ConnectionAborted].
+    pub async fn abort(mut self) -> io::Result<()> {
+        // The frame in flight is dropped, it's superseded by the abort.
+        self.sending = None;
+        self.closed = true;
+        self.send(FrameKind::Abort, Bytes::new());
+        futures::future::poll_fn(|cx| self.poll_sending(cx)).await
+    }
+
+    fn send(&mut self, kind: FrameKind, data: Bytes) {
+        let frame = StreamFrame {
+            id: self.id,
+            kind,
+            data,
+        }
+        .encode();
+        let conn = self.conn.clone();
+        self.sending = Some(Box::pin(async move {
+            conn.send_message(TransportMessage::Custom(frame))
+                .await
+                .map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e.to_string()))
+        }));
+    }
+
+    fn poll_sending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let Some(sending) = self.sending.as_mut() else {
+            return Poll::Ready(Ok(()));
+        };
+        let result = ready!(sending.as_mut().poll(cx));
+        self.sending = None;
+        Poll::Ready(result)
+    }
+}
+
+impl AsyncWrite for StreamWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.closed {
+            return Poll::Ready(Err(io::ErrorKind::NotConnected.into()));
+        }
+        ready!(self.poll_sending(cx))?;
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let n = buf.len().min(STREAM_FRAME_MAX_LEN);
+        self.send(FrameKind::Data, Bytes::copy_from_slice(&buf[..n]));
+        if let Poll::Ready(Err(e)) = self.poll_sending(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_sending(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if !self.closed {
+            ready!(self.poll_sending(cx))?;
+            self.closed = true;
+            self.send(FrameKind::End, Bytes::new());
+        }
+        self.poll_sending(cx)
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod test {
+    use futures::io::AsyncReadExt;
+    use futures::FutureExt;
+
+    use super::*;
+
+    fn frame(id: Uuid, kind: FrameKind, data: &[u8]) -> Vec<u8> {
+        StreamFrame {
+            id,
+            kind,
+            data: Bytes::copy_from_slice(data),
+        }
+        .encode()
+    }
+
+    #[test]
+    fn test_stream_frame_codec() {
+        let id = Uuid::new_v4();
+        let encoded = frame(id, FrameKind::Data, b"hello");
+        assert_eq!(encoded[0], STREAM_FRAME_TAG);
+        let decoded = StreamFrame::decode(&encoded).unwrap();
+        assert_eq!(decoded.id, id);
+        assert_eq!(decoded.kind, FrameKind::Data);
+        assert_eq!(&decoded.data[..], b"hello");
+
+        assert!(StreamFrame::decode(&encoded[..10]).is_err());
+        let mut unknown = encoded;
+        unknown[17] = 9;
+        assert!(StreamFrame::decode(&unknown).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_streams() {
+        let streams = Streams::default();
+        let peer = Did::from(1u32);
+        let id = Uuid::new_v4();
+
+        // Frames of a stream never opened are rejected.
+        assert!(matches!(
+            streams
+                .dispatch(peer, &frame(id, FrameKind::Data, b"x"))
+                .await,
+            Err(Error::StreamNotFound(..))
+        ));
+
+        streams.register(peer, id).unwrap();
+        streams
+            .dispatch(peer, &frame(id, FrameKind::Data, b"hello "))
+            .await
+            .unwrap();
+        streams
+            .dispatch(peer, &frame(id, FrameKind::Data, b"world"))
+            .await
+            .unwrap();
+        streams
+            .dispatch(peer, &frame(id, FrameKind::End, b""))
+            .await
+            .unwrap();
+        // The stream is forgotten once finished.
+        assert!(streams
+            .dispatch(peer, &frame(id, FrameKind::Data, b"!"))
+            .await
+            .is_err());
+
+        let mut reader = streams.accept().await.unwrap();
+        assert_eq!((reader.peer(), reader.id()), (peer, id));
+        let mut data = vec![];
+        reader.read_to_end(&mut data).await.unwrap();
+        assert_eq!(data, b"hello world");
+
+        // A stream torn down before finished fails its reader.
+        let id = Uuid::new_v4();
+        streams.register(peer, id).unwrap();
+        streams
+            .dispatch(peer, &frame(id, FrameKind::Data, b"partial"))
+            .await
+            .unwrap();
+        streams.close_peer(peer);
+        let mut reader = streams.accept().await.unwrap();
+        let err = reader.read_to_end(&mut vec![]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        for _ in 0..MAX_STREAMS_PER_PEER {
+            streams.register(peer, Uuid::new_v4()).unwrap();
+        }
+        assert!(matches!(
+            streams.register(peer, Uuid::new_v4()),
+            Err(Error::TooManyStreams(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_stream_backpressure() {
+        let streams = Streams::default();
+        let peer = Did::from(1u32);
+        let id = Uuid::new_v4();
+        streams.register(peer, id).unwrap();
+
+        for _ in 0..STREAM_QUEUE_FRAMES {
+            streams
+                .dispatch(peer, &frame(id, FrameKind::Data, b"x"))
+                .await
+                .unwrap();
+        }
+        // The queue is full, so the next frame waits for the reader.
+        let pending = frame(id, FrameKind::Data, b"y");
+        let mut dispatch = Box::pin(streams.dispatch(peer, &pending));
+        assert!((&mut dispatch).now_or_never().is_none());
+
+        let mut reader = streams.accept().await.unwrap();
+        let mut byte = [0u8; 1];
+        reader.read_exact(&mut byte).await.unwrap();
+        dispatch.await.unwrap();
+
+        // Streams not accepted yet are bounded as well.
+        for i in 1..MAX_PENDING_STREAMS {
+            streams
+                .register(Did::from(i as u32 + 1), Uuid::new_v4())
+                .unwrap();
+        }
+        assert!(streams.register(peer, Uuid::new_v4()).is_ok());
+        assert!(matches!(
+            streams.register(Did::from(1000u32), Uuid::new_v4()),
+            Err(Error::TooManyPendingStreams)
+        ));
+    }
+}
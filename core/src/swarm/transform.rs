@@ -0,0 +1,507 @@
+#![warn(missing_docs)]
+//! This module provides [PayloadPipeline], an ordered list of [PayloadTransform]s applied to
+//! data sent to a peer, and reversed in the opposite order on receipt.
+//!
+//! Each transform declares a [TransformStage], and a pipeline only accepts transforms in the
+//! order of their stages, so that combinations stay correct:
+//! - Data is compressed before encrypted, since ciphertext doesn't compress.
+//! - It's signed after encrypted, so the signature is checked before anything is decrypted.
+//! - It's chunked last, so every chunk fits the transport, and nothing before chunking
+//!   sees a partial message.
+//!
+//! For example, a pipeline of gzip, encryption and chunking compresses, encrypts and splits
+//! data on send, and reassembles, decrypts and decompresses it on receipt.
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use bytes::Bytes;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::chunk::Chunk;
+use crate::chunk::ChunkList;
+use crate::chunk::ChunkPool;
+use crate::consts::MAX_DECOMPRESSED_SIZE;
+use crate::consts::TRANSPORT_MTU;
+use crate::dht::Did;
+use crate::error::Error;
+use crate::error::Result;
+use crate::message::decode_gzip_data_with_limit;
+use crate::message::encode_data_gzip;
+use crate::session::Session;
+use crate::swarm::Swarm;
+
+/// Default level of [GzipCompression].
+pub const DEFAULT_GZIP_LEVEL: u8 = 6;
+
+/// Stage of a [PayloadTransform], which decides where it can be placed in a pipeline.
+/// Transforms of a pipeline are ordered by their stages, the same stage may repeat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TransformStage {
+    /// Encoding of plain data, such as a custom serialization.
+    Encoding,
+    /// Compression of plain data.
+    Compression,
+    /// Encryption to peer.
+    Encryption,
+    /// Signing by local session.
+    Signing,
+    /// Splitting into frames, which must be the last.
+    Chunking,
+}
+
+/// A transform of data sent to a peer, and its reverse on receipt.
+pub trait PayloadTransform {
+    /// Name of the transform, for logging.
+    fn name(&self) -> &'static str;
+
+    /// Stage of the transform, see [TransformStage].
+    fn stage(&self) -> TransformStage;
+
+    /// Transform data sent to `peer`, into one or more frames.
+    fn apply(&self, swarm: &Swarm, peer: Did, data: Vec<u8>) -> Result<Vec<Vec<u8>>>;
+
+    /// Reverse a frame received from `peer`.
+    /// Returns None if more frames are needed, such as chunks not all received yet.
+    fn reverse(&self, swarm: &Swarm, peer: Did, frame: Vec<u8>) -> Result<Option<Vec<u8>>>;
+}
+
+/// Boxed PayloadTransform, for non-wasm, it should be Sized, Send and Sync.
+#[cfg(not(feature = "wasm"))]
+pub type PayloadTransformImpl = Box<dyn PayloadTransform + Send + Sync>;
+
+/// Boxed PayloadTransform
+#[cfg(feature = "wasm")]
+pub type PayloadTransformImpl = Box<dyn PayloadTransform>;
+
+/// Ordered transforms of data sent to peers, see [transform](self).
+/// Cloning it shares the transforms. It's empty by default, which keeps data as is.
+#[derive(Clone, Default)]
+pub struct PayloadPipeline(Arc<Vec<PayloadTransformImpl>>);
+
+impl std::fmt::Debug for PayloadPipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("PayloadPipeline")
+            .field(&self.names())
+            .finish()
+    }
+}
+
+impl PayloadPipeline {
+    /// Create a pipeline applying transforms in the given order.
+    /// Fails if they are not ordered by [TransformStage].
+    pub fn new(transforms: Vec<PayloadTransformImpl>) -> Result<Self> {
+        for pair in transforms.windows(2) {
+            if pair[0].stage() > pair[1].stage() {
+                return Err(Error::InvalidPayloadPipeline(format!(
+                    "{} ({:?}) can't be applied after {} ({:?})",
+                    pair[1].name(),
+                    pair[1].stage(),
+                    pair[0].name(),
+                    pair[0].stage(),
+                )));
+            }
+        }
+        Ok(Self(Arc::new(transforms)))
+    }
+
+    /// Create a pipeline of built-in transforms by config.
+    pub fn from_config(config: &[PayloadTransformConfig]) -> Result<Self> {
+        Self::from_config_with_max_size(config, MAX_DECOMPRESSED_SIZE)
+    }
+
+    /// Create a pipeline of built-in transforms by config, which never decompresses data
+    /// larger than `max_size` bytes.
+    pub fn from_config_with_max_size(
+        config: &[PayloadTransformConfig],
+        max_size: usize,
+    ) -> Result<Self> {
+        Self::new(
+            config
+                .iter()
+                .map(|c| c.transform_with_max_size(max_size))
+                .collect(),
+        )
+    }
+
+    /// Check if the pipeline has no transform.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Names of transforms in the order they are applied.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.0.iter().map(|t| t.name()).collect()
+    }
+
+    /// Apply transforms to data sent to `peer` in order, returns frames to send.
+    pub fn apply(&self, swarm: &Swarm, peer: Did, data: Vec<u8>) -> Result<Vec<Vec<u8>>> {
+        let mut frames = vec![data];
+        for transform in self.0.iter() {
+            let mut next = Vec::with_capacity(frames.len());
+            for frame in frames {
+                next.extend(transform.apply(swarm, peer, frame)?);
+            }
+            frames = next;
+        }
+        Ok(frames)
+    }
+
+    /// Reverse transforms of a frame received from `peer` in the opposite order.
+    /// Returns None if more frames are needed to restore the data.
+    pub fn reverse(&self, swarm: &Swarm, peer: Did, frame: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let mut data = frame;
+        for transform in self.0.iter().rev() {
+            match transform.reverse(swarm, peer, data)? {
+                Some(reversed) => data = reversed,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(data))
+    }
+}
+
+/// Config of a built-in transform, such as `kind: gzip` and `level: 9`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum PayloadTransformConfig {
+    /// See [GzipCompression].
+    Gzip {
+        /// Level of compression, from 0 to 9.
+        #[serde(default = "default_gzip_level")]
+        level: u8,
+    },
+    /// See [PeerEncryption].
+    Encrypt,
+    /// See [SessionSigning].
+    Sign,
+    /// See [Chunking].
+    Chunk {
+        /// Max size of a chunk in bytes.
+        #[serde(default = "default_chunk_mtu")]
+        mtu: usize,
+    },
+}
+
+fn default_gzip_level() -> u8 {
+    DEFAULT_GZIP_LEVEL
+}
+
+fn default_chunk_mtu() -> usize {
+    TRANSPORT_MTU
+}
+
+impl PayloadTransformConfig {
+    /// Create the transform of config.
+    pub fn transform(&self) -> PayloadTransformImpl {
+        self.transform_with_max_size(MAX_DECOMPRESSED_SIZE)
+    }
+
+    /// Create the transform of config, which never decompresses data larger than
+    /// `max_size` bytes.
+    pub fn transform_with_max_size(&self, max_size: usize) -> PayloadTransformImpl {
+        match self {
+            Self::Gzip { level } => Box::new(GzipCompression::new(*level).with_max_size(max_size)),
+            Self::Encrypt => Box::new(PeerEncryption),
+            Self::Sign => Box::new(SessionSigning),
+            Self::Chunk { mtu } => Box::new(Chunking::new(*mtu)),
+        }
+    }
+}
+
+/// Compress data by gzip. Decompression is limited, see
+/// [decode_gzip_data_with_limit](crate::message::decode_gzip_data_with_limit).
+#[derive(Debug, Clone, Copy)]
+pub struct GzipCompression {
+    level: u8,
+    max_size: usize,
+}
+
+impl GzipCompression {
+    /// Create with level of compression, from 0 to 9. Data is decompressed up to
+    /// [MAX_DECOMPRESSED_SIZE] bytes.
+    pub fn new(level: u8) -> Self {
+        Self {
+            level,
+            max_size: MAX_DECOMPRESSED_SIZE,
+        }
+    }
+
+    /// Set max size of data decompressed, in bytes.
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+}
+
+impl PayloadTransform for GzipCompression {
+    fn name(&self) -> &'static str {
+        "gzip"
+    }
+
+    fn stage(&self) -> TransformStage {
+        TransformStage::Compression
+    }
+
+    fn apply(&self, _swarm: &Swarm, _peer: Did, data: Vec<u8>) -> Result<Vec<Vec<u8>>> {
+        Ok(vec![encode_data_gzip(&data.into(), self.level)?.to_vec()])
+    }
+
+    fn reverse(&self, _swarm: &Swarm, _peer: Did, frame: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        Ok(Some(
+            decode_gzip_data_with_limit(&frame.into(), self.max_size)?.to_vec(),
+        ))
+    }
+}
+
+/// Encrypt data to peer by [Swarm::encrypt_for], which fails if the key of peer is not
+/// exchanged yet.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PeerEncryption;
+
+impl PayloadTransform for PeerEncryption {
+    fn name(&self) -> &'static str {
+        "encrypt"
+    }
+
+    fn stage(&self) -> TransformStage {
+        TransformStage::Encryption
+    }
+
+    fn apply(&self, swarm: &Swarm, peer: Did, data: Vec<u8>) -> Result<Vec<Vec<u8>>> {
+        Ok(vec![swarm.encrypt_for(peer, &data)?])
+    }
+
+    fn reverse(&self, swarm: &Swarm, _peer: Did, frame: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        Ok(Some(swarm.decrypt(&frame)?))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignedFrame {
+    session: Session,
+    sig: Vec<u8>,
+    data: Vec<u8>,
+}
+
+/// Sign data by the session of swarm. A frame is rejected unless it's signed by the
+/// account of its sender.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SessionSigning;
+
+impl PayloadTransform for SessionSigning {
+    fn name(&self) -> &'static str {
+        "sign"
+    }
+
+    fn stage(&self) -> TransformStage {
+        TransformStage::Signing
+    }
+
+    fn apply(&self, swarm: &Swarm, _peer: Did, data: Vec<u8>) -> Result<Vec<Vec<u8>>> {
+        let session_sk = swarm.session_sk();
+        let frame = SignedFrame {
+            session: session_sk.session(),
+            sig: session_sk.sign(&data)?,
+            data,
+        };
+        Ok(vec![
+            bincode::serialize(&frame).map_err(Error::BincodeSerialize)?
+        ])
+    }
+
+    fn reverse(&self, _swarm: &Swarm, peer: Did, frame: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let frame: SignedFrame = bincode::deserialize(&frame).map_err(Error::BincodeDeserialize)?;
+        if frame.session.account_did() != peer {
+            return Err(Error::VerifySignatureFailed);
+        }
+        frame.session.verify(&frame.data, &frame.sig)?;
+        Ok(Some(frame.data))
+    }
+}
+
+/// Split data into chunks of at most `mtu` bytes, which are reassembled by receiver.
+/// Incomplete messages are buffered within a budget for each peer, see [ChunkPool].
+#[derive(Debug)]
+pub struct Chunking {
+    mtu: usize,
+    pool: Mutex<ChunkPool<TRANSPORT_MTU>>,
+}
+
+impl Chunking {
+    /// Create with max size of a chunk in bytes.
+    pub fn new(mtu: usize) -> Self {
+        Self {
+            mtu: mtu.max(1),
+            pool: Mutex::new(ChunkPool::default()),
+        }
+    }
+}
+
+impl PayloadTransform for Chunking {
+    fn name(&self) -> &'static str {
+        "chunk"
+    }
+
+    fn stage(&self) -> TransformStage {
+        TransformStage::Chunking
+    }
+
+    fn apply(&self, _swarm: &Swarm, _peer: Did, data: Vec<u8>) -> Result<Vec<Vec<u8>>> {
+        ChunkList::<TRANSPORT_MTU>::split(&Bytes::from(data), self.mtu)
+            .to_vec()
+            .iter()
+            .map(|c| c.to_bincode().map(|b| b.to_vec()))
+            .collect()
+    }
+
+    fn reverse(&self, _swarm: &Swarm, peer: Did, frame: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let chunk = Chunk::from_bincode(&frame)?;
+        let mut pool = self.pool.lock().unwrap_or_else(|e| e.into_inner());
+        let (data, evicted) = pool.handle_from(peer, chunk);
+        drop(pool);
+        for e in evicted {
+            tracing::warn!("Chunks of {} from {} are evicted", e.id, e.did);
+        }
+        Ok(data.map(|d| d.to_vec()))
+    }
+}
+
+impl Swarm {
+    /// Get the payload pipeline of swarm, see [SwarmBuilder::payload_pipeline].
+    ///
+    /// [SwarmBuilder::payload_pipeline]: crate::swarm::SwarmBuilder::payload_pipeline
+    pub fn payload_pipeline(&self) -> &PayloadPipeline {
+        &self.payload_pipeline
+    }
+
+    /// Apply the payload pipeline to data sent to peer, see [PayloadPipeline::apply].
+    pub fn transform_payload(&self, peer: Did, data: Vec<u8>) -> Result<Vec<Vec<u8>>> {
+        self.payload_pipeline.apply(self, peer, data)
+    }
+
+    /// Reverse the payload pipeline of a frame received from peer,
+    /// see [PayloadPipeline::reverse].
+    pub fn restore_payload(&self, peer: Did, frame: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        self.payload_pipeline.reverse(self, peer, frame)
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod test {
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::Rng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::ecc::SecretKey;
+    use crate::message::Message;
+    use crate::message::MessagePayload;
+    use crate::tests::default::prepare_node;
+
+    /// Two swarms knowing the encryption keys of each other.
+    async fn prepare_peers() -> (Arc<Swarm>, Arc<Swarm>) {
+        let (a, _) = prepare_node(SecretKey::random()).await;
+        let (b, _) = prepare_node(SecretKey::random()).await;
+        for (from, to) in [(&a, &b), (&b, &a)] {
+            let payload = MessagePayload::new_send(
+                Message::custom(b"hello").unwrap(),
+                from.session_sk(),
+                to.did(),
+                to.did(),
+            )
+            .unwrap();
+            to.peer_keys.learn(&payload);
+        }
+        (a, b)
+    }
+
+    /// Every combination of built-in transforms in the order of stages.
+    fn combinations() -> Vec<Vec<PayloadTransformConfig>> {
+        let all = [
+            PayloadTransformConfig::Gzip { level: 6 },
+            PayloadTransformConfig::Encrypt,
+            PayloadTransformConfig::Sign,
+            PayloadTransformConfig::Chunk { mtu: 64 },
+        ];
+        (0..1 << all.len())
+            .map(|mask| {
+                all.iter()
+                    .enumerate()
+                    .filter(|(i, _)| mask & (1 << i) != 0)
+                    .map(|(_, c)| c.clone())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_round_trip() {
+        let (a, b) = prepare_peers().await;
+
+        for config in combinations() {
+            let pipeline = PayloadPipeline::from_config(&config).unwrap();
+            // Data and orders of delivery are reproducible by seed.
+            for seed in 0..8 {
+                let mut rng = StdRng::seed_from_u64(seed);
+                let len = rng.gen_range(0..1024);
+                let data: Vec<u8> = (0..len).map(|_| rng.gen_range(0..4)).collect();
+
+                let mut frames = pipeline.apply(&a, b.did(), data.clone()).unwrap();
+                frames.shuffle(&mut rng);
+                let restored: Vec<Vec<u8>> = frames
+                    .into_iter()
+                    .filter_map(|f| pipeline.reverse(&b, a.did(), f).unwrap())
+                    .collect();
+                assert_eq!(restored, vec![data], "{:?}, seed {seed}", pipeline);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_max_size() {
+        let (a, b) = prepare_peers().await;
+        let config = [PayloadTransformConfig::Gzip { level: 6 }];
+        let pipeline = PayloadPipeline::from_config_with_max_size(&config, 1024).unwrap();
+
+        let frames = pipeline.apply(&a, b.did(), vec![0; 1024]).unwrap();
+        let restored = pipeline.reverse(&b, a.did(), frames[0].clone()).unwrap();
+        assert_eq!(restored, Some(vec![0; 1024]));
+
+        // Data inflating beyond the max size is rejected.
+        let frames = pipeline.apply(&a, b.did(), vec![0; 1025]).unwrap();
+        assert!(pipeline.reverse(&b, a.did(), frames[0].clone()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_rejects_forged_frames() {
+        let (a, b) = prepare_peers().await;
+        let (c, _) = prepare_node(SecretKey::random()).await;
+
+        // A frame signed by c is not accepted as one from a.
+        let signing = PayloadPipeline::from_config(&[PayloadTransformConfig::Sign]).unwrap();
+        let frames = signing.apply(&c, b.did(), b"data".to_vec()).unwrap();
+        assert!(signing.reverse(&b, a.did(), frames[0].clone()).is_err());
+
+        // Data encrypted to b can't be decrypted by c.
+        let encryption = PayloadPipeline::from_config(&[PayloadTransformConfig::Encrypt]).unwrap();
+        let frames = encryption.apply(&a, b.did(), b"data".to_vec()).unwrap();
+        assert!(encryption.reverse(&c, a.did(), frames[0].clone()).is_err());
+    }
+
+    #[test]
+    fn test_pipeline_order() {
+        let config = |kinds: &[&str]| -> Vec<PayloadTransformConfig> {
+            kinds
+                .iter()
+                .map(|k| serde_json::from_value(serde_json::json!({ "kind": k })).unwrap())
+                .collect()
+        };
+        assert!(PayloadPipeline::from_config(&config(&["gzip", "encrypt", "chunk"])).is_ok());
+        assert!(PayloadPipeline::from_config(&config(&["encrypt", "gzip"])).is_err());
+        assert!(PayloadPipeline::from_config(&config(&["chunk", "sign"])).is_err());
+        assert!(PayloadPipeline::default().is_empty());
+    }
+}
@@ -0,0 +1,422 @@
+#![warn(missing_docs)]
+//! Trickle ICE between swarms, see
+//! [ice_gathering](rings_transport::ice_gathering) for the trade-off of trickling.
+//!
+//! Local candidates reported by a connection are sent to its remote peer by
+//! [Message::IceCandidate] through DHT, the same way as the offer and answer of the handshake.
+//! A candidate may arrive before the offer creating the connection, so it's kept by
+//! [PendingCandidates] until then. Descriptions exchanged out-of-band, such as by
+//! [ConnectionHandshake::create_offer](crate::swarm::impls::ConnectionHandshake::create_offer),
+//! carry every candidate gathered instead, since nothing else is signaled.
+//!
+//! Peers without trickling drop [Message::IceCandidate] as undecodable, so candidates are only
+//! trickled to a peer known to accept them. Descriptions signaled by swarm are marked by
+//! [TRICKLE_ATTRIBUTE], and a peer is known once a description of it with the mark is received,
+//! see [TricklePeers]. A description to any other peer waits for every candidate, like
+//! [IceGatheringMode::Full](rings_transport::ice_gathering::IceGatheringMode::Full). So an
+//! offer to an unknown peer carries every candidate, and the answer to a marked offer is given
+//! out right away, with candidates trickled afterwards.
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+use rings_transport::core::transport::ConnectionInterface;
+
+use crate::dht::Did;
+use crate::error::Error;
+use crate::error::Result;
+use crate::message::IceCandidate;
+use crate::message::Message;
+use crate::message::PayloadSender;
+use crate::swarm::gate::candidate_ips;
+use crate::swarm::Swarm;
+use crate::types::Connection;
+
+/// Max number of candidates kept of a peer whose connection is not created yet.
+pub const MAX_PENDING_CANDIDATES: usize = 32;
+
+/// Max number of peers whose candidates are kept before their connections are created.
+pub const MAX_PENDING_PEERS: usize = 256;
+
+/// Max number of peers remembered to accept trickled candidates.
+pub const MAX_TRICKLE_PEERS: usize = 1024;
+
+/// SDP attribute marking a description of a peer accepting trickled candidates.
+/// Unknown attributes are ignored by SDP parsers, so peers without trickling still accept it.
+pub const TRICKLE_ATTRIBUTE: &str = "a=x-rings-trickle";
+
+#[derive(Debug, Default)]
+struct PendingCandidatesInner {
+    candidates: HashMap<Did, VecDeque<String>>,
+    /// Peers in the order their first candidates are received.
+    peers: VecDeque<Did>,
+}
+
+/// Candidates received before their connections are created, kept by peer.
+/// The oldest candidates of a peer are dropped once over [MAX_PENDING_CANDIDATES], and the
+/// candidates of the oldest peer are dropped once over [MAX_PENDING_PEERS], so a peer
+/// sending many candidates only pushes out its own ones.
+/// Cloned ones share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct PendingCandidates(Arc<Mutex<PendingCandidatesInner>>);
+
+impl PendingCandidates {
+    fn lock(&self) -> MutexGuard<'_, PendingCandidatesInner> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Keep a candidate of did.
+    pub fn push(&self, did: Did, candidate: String) {
+        let mut guard = self.lock();
+        let pending = &mut *guard;
+        if !pending.candidates.contains_key(&did) {
+            if pending.peers.len() >= MAX_PENDING_PEERS {
+                if let Some(oldest) = pending.peers.pop_front() {
+                    pending.candidates.remove(&oldest);
+                }
+            }
+            pending.peers.push_back(did);
+        }
+        let candidates = pending.candidates.entry(did).or_default();
+        if candidates.len() >= MAX_PENDING_CANDIDATES {
+            candidates.pop_front();
+        }
+        candidates.push_back(candidate);
+    }
+
+    /// Take the candidates of did, in the order they are received.
+    pub fn take(&self, did: Did) -> Vec<String> {
+        let mut pending = self.lock();
+        let Some(taken) = pending.candidates.remove(&did) else {
+            return vec![];
+        };
+        pending.peers.retain(|d| *d != did);
+        taken.into()
+    }
+}
+
+/// Peers known to accept trickled candidates, see [trickle](self).
+/// The oldest ones are forgotten once over [MAX_TRICKLE_PEERS].
+/// Cloned ones share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct TricklePeers(Arc<Mutex<VecDeque<Did>>>);
+
+impl TricklePeers {
+    fn lock(&self) -> MutexGuard<'_, VecDeque<Did>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Remember did accepts trickled candidates.
+    pub fn insert(&self, did: Did) {
+        let mut peers = self.lock();
+        if peers.contains(&did) {
+            return;
+        }
+        if peers.len() >= MAX_TRICKLE_PEERS {
+            peers.pop_front();
+        }
+        peers.push_back(did);
+    }
+
+    /// Check if did is known to accept trickled candidates.
+    pub fn contains(&self, did: Did) -> bool {
+        self.lock().contains(&did)
+    }
+}
+
+/// Text of SDP in a serialized description, which is either a json string of SDP, or a json
+/// object with SDP in its `sdp` field.
+fn sdp_text(desc: &mut serde_json::Value) -> Option<&mut String> {
+    match desc {
+        serde_json::Value::String(sdp) => Some(sdp),
+        serde_json::Value::Object(desc) => match desc.get_mut("sdp")? {
+            serde_json::Value::String(sdp) => Some(sdp),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Mark a serialized description by [TRICKLE_ATTRIBUTE]. A description without SDP, such as
+/// one of a connection without ICE, is returned as is.
+pub fn mark_trickle(desc: &str) -> String {
+    let Ok(mut value) = serde_json::from_str::<serde_json::Value>(desc) else {
+        return desc.to_string();
+    };
+    let Some(sdp) = sdp_text(&mut value) else {
+        return desc.to_string();
+    };
+    if !sdp.is_empty() && !sdp.ends_with('\n') {
+        sdp.push_str("\r\n");
+    }
+    sdp.push_str(TRICKLE_ATTRIBUTE);
+    sdp.push_str("\r\n");
+    value.to_string()
+}
+
+/// Check if a serialized description is marked by [TRICKLE_ATTRIBUTE].
+pub fn accepts_trickle(desc: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(desc)
+        .ok()
+        .as_mut()
+        .and_then(sdp_text)
+        .map(|sdp| sdp.lines().any(|line| line.trim_end() == TRICKLE_ATTRIBUTE))
+        .unwrap_or(false)
+}
+
+impl Swarm {
+    /// Send a local candidate of the connection with did to its remote peer, if the peer is
+    /// known to accept it. Otherwise the candidate is carried by the description.
+    pub(crate) async fn trickle_ice_candidate(&self, did: Did, candidate: String) {
+        if !self.trickle_peers.contains(did) {
+            return;
+        }
+        let msg = Message::IceCandidate(IceCandidate { candidate });
+        // Peers handshaking out-of-band may be unreachable, and get candidates in descriptions.
+        if let Err(e) = self.send_message(msg, did).await {
+            tracing::debug!("Failed on trickling ICE candidate to {did}: {e:?}");
+        }
+    }
+
+    /// Add a candidate trickled by did to its connection, or keep it until the connection is
+    /// created. The IP of candidate is checked by [IpLimiter](crate::swarm::IpLimiter) like
+    /// the ones in an offer.
+    pub(crate) async fn add_ice_candidate(&self, did: Did, candidate: &str) -> Result<()> {
+        match self.get_connection(did) {
+            Some(conn) => self.add_candidate_to(did, &conn, candidate).await,
+            None => {
+                self.pending_candidates.push(did, candidate.to_string());
+                Ok(())
+            }
+        }
+    }
+
+    /// Add candidates of did received before its connection is created.
+    pub(crate) async fn add_pending_candidates(&self, did: Did, conn: &Connection) -> Result<()> {
+        for candidate in self.pending_candidates.take(did) {
+            self.add_candidate_to(did, conn, &candidate).await?;
+        }
+        Ok(())
+    }
+
+    async fn add_candidate_to(&self, did: Did, conn: &Connection, candidate: &str) -> Result<()> {
+        if let Err(e) = self.check_ip_limit(did, &candidate_ips(candidate)).await {
+            self.disconnect(did).await?;
+            return Err(e);
+        }
+        conn.webrtc_add_ice_candidate(candidate)
+            .await
+            .map_err(Error::Transport)
+    }
+
+    /// Remember peer accepts trickled candidates if its description is marked.
+    pub(crate) fn learn_trickle(&self, peer: Did, desc: &str) {
+        if accepts_trickle(desc) {
+            self.trickle_peers.insert(peer);
+        }
+    }
+
+    /// Serialized description of connection to signal to peer, marked by [TRICKLE_ATTRIBUTE].
+    /// It carries every candidate unless peer is known to accept trickled ones.
+    pub(crate) async fn signaled_sdp(
+        &self,
+        peer: Did,
+        conn: &Connection,
+        desc: <Connection as ConnectionInterface>::Sdp,
+    ) -> Result<String> {
+        let desc = if self.trickle_peers.contains(peer) {
+            desc
+        } else {
+            conn.webrtc_gathered_description()
+                .await
+                .map_err(Error::Transport)?
+                .unwrap_or(desc)
+        };
+        let desc = serde_json::to_string(&desc).map_err(|_| Error::SerializeToString)?;
+        Ok(mark_trickle(&desc))
+    }
+
+    /// Serialized description of connection carrying every candidate gathered, for
+    /// descriptions signaled out-of-band. `sdp` is returned if it carries every candidate.
+    pub(crate) async fn gathered_sdp(&self, conn: &Connection, sdp: String) -> Result<String> {
+        match conn
+            .webrtc_gathered_description()
+            .await
+            .map_err(Error::Transport)?
+        {
+            Some(desc) => serde_json::to_string(&desc).map_err(|_| Error::SerializeToString),
+            None => Ok(sdp),
+        }
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[cfg(test)]
+mod test {
+    use rings_transport::ice_gathering::IceGatheringMode;
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+
+    use super::*;
+    use crate::channels::Channel;
+    use crate::ecc::SecretKey;
+    use crate::message::ConnectNodeSend;
+    use crate::swarm::impls::ConnectionHandshake;
+    use crate::tests::default::prepare_node_with_gathering;
+    use crate::types::channel::Channel as ChannelTrait;
+    use crate::types::channel::TransportEvent;
+
+    #[test]
+    fn test_pending_candidates() {
+        let (a, b): (Did, Did) = (1u32.into(), 2u32.into());
+        let pending = PendingCandidates::default();
+        pending.push(a, "a1".to_string());
+        pending.push(b, "b1".to_string());
+        pending.push(a, "a2".to_string());
+
+        assert_eq!(pending.take(a), vec!["a1", "a2"]);
+        assert!(pending.take(a).is_empty());
+        assert_eq!(pending.take(b), vec!["b1"]);
+
+        // A peer sending many candidates only pushes out its own ones.
+        pending.push(b, "b1".to_string());
+        for i in 0..=MAX_PENDING_CANDIDATES {
+            pending.push(a, i.to_string());
+        }
+        let taken = pending.take(a);
+        assert_eq!(taken.len(), MAX_PENDING_CANDIDATES);
+        assert_eq!(taken[0], "1");
+        assert_eq!(pending.take(b), vec!["b1"]);
+
+        // The oldest peer is forgotten once too many peers are pending.
+        for i in 0..=MAX_PENDING_PEERS {
+            pending.push(Did::from(i as u32 + 10), i.to_string());
+        }
+        assert!(pending.take(Did::from(10u32)).is_empty());
+        assert_eq!(pending.take(Did::from(11u32)), vec!["1"]);
+    }
+
+    #[test]
+    fn test_mark_trickle() {
+        let sdp = "v=0\r\no=- 1 2 IN IP4 0.0.0.0\r\n";
+        let desc = serde_json::json!({"type": "offer", "sdp": sdp}).to_string();
+        assert!(!accepts_trickle(&desc));
+        let marked = mark_trickle(&desc);
+        assert!(accepts_trickle(&marked));
+        let value: serde_json::Value = serde_json::from_str(&marked).unwrap();
+        assert_eq!(value["type"], "offer");
+        assert_eq!(value["sdp"], format!("{sdp}{TRICKLE_ATTRIBUTE}\r\n"));
+
+        // SDP serialized as a json string.
+        let desc = serde_json::to_string(sdp).unwrap();
+        assert!(accepts_trickle(&mark_trickle(&desc)));
+
+        // Descriptions without SDP are kept as is.
+        let desc = serde_json::json!({"rand_id": "x"}).to_string();
+        assert_eq!(mark_trickle(&desc), desc);
+        assert!(!accepts_trickle(&desc));
+        assert!(!accepts_trickle("not json"));
+    }
+
+    #[test]
+    fn test_trickle_peers() {
+        let peers = TricklePeers::default();
+        for i in 0..=MAX_TRICKLE_PEERS as u32 {
+            peers.insert(i.into());
+        }
+        peers.insert(1u32.into());
+        assert!(!peers.contains(0u32.into()));
+        assert!(peers.contains(1u32.into()));
+        assert!(peers.contains((MAX_TRICKLE_PEERS as u32).into()));
+    }
+
+    async fn prepare_trickle_node() -> Arc<Swarm> {
+        let key = SecretKey::random();
+        prepare_node_with_gathering(key, None, IceGatheringMode::Trickle)
+            .await
+            .0
+    }
+
+    /// Description without candidates, so that a connection relies on candidates trickled.
+    fn without_candidates<T: Serialize + DeserializeOwned>(desc: T) -> T {
+        let mut value = serde_json::to_value(desc).unwrap();
+        let sdp = sdp_text(&mut value).unwrap();
+        *sdp = sdp
+            .split_inclusive('\n')
+            .filter(|line| !line.starts_with("a=candidate"))
+            .collect();
+        serde_json::from_value(value).unwrap()
+    }
+
+    /// Forward candidates reported by connections of `from` to `to`, as DHT does.
+    async fn forward_candidates(from: Arc<Swarm>, to: Arc<Swarm>) {
+        let receiver = from.transport_event_channel.receiver();
+        while let Ok(Some(ev)) = Channel::recv(&receiver).await {
+            if let TransportEvent::IceCandidate(_, candidate) = ev {
+                to.add_ice_candidate(from.did(), &candidate).await.unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_trickle_connection() {
+        let swarm1 = prepare_trickle_node().await;
+        let swarm2 = prepare_trickle_node().await;
+        tokio::spawn(forward_candidates(swarm1.clone(), swarm2.clone()));
+        tokio::spawn(forward_candidates(swarm2.clone(), swarm1.clone()));
+
+        let conn1 = swarm1.new_connection(swarm2.did()).await.unwrap();
+        let offer = conn1.webrtc_create_offer().await.unwrap();
+        let conn2 = swarm2.new_connection(swarm1.did()).await.unwrap();
+        let answer = conn2
+            .webrtc_answer_offer(without_candidates(offer))
+            .await
+            .unwrap();
+        conn1
+            .webrtc_accept_answer(without_candidates(answer))
+            .await
+            .unwrap();
+
+        let open = conn1.webrtc_wait_for_data_channel_open();
+        tokio::time::timeout(std::time::Duration::from_secs(30), open)
+            .await
+            .expect("connected by candidates trickled")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_trickle_negotiation() {
+        let swarm1 = prepare_trickle_node().await;
+        let swarm2 = prepare_trickle_node().await;
+        let swarm3 = prepare_trickle_node().await;
+
+        // An offer of a peer without trickling is unmarked, so the answer isn't trickled to it.
+        let conn1 = swarm1.new_connection(swarm2.did()).await.unwrap();
+        let offer = conn1.webrtc_create_offer().await.unwrap();
+        let offer = ConnectNodeSend {
+            sdp: serde_json::to_string(&offer).unwrap(),
+        };
+        let (_, answer) = swarm2
+            .answer_remote_connection(swarm1.did(), &offer)
+            .await
+            .unwrap();
+        assert!(!swarm2.trickle_peers.contains(swarm1.did()));
+        assert!(accepts_trickle(&answer.sdp));
+        swarm1
+            .accept_remote_connection(swarm2.did(), &answer)
+            .await
+            .unwrap();
+        assert!(swarm1.trickle_peers.contains(swarm2.did()));
+
+        // A marked offer is answered right away, and candidates are trickled afterwards.
+        let (_, offer) = swarm3.prepare_connection_offer(swarm2.did()).await.unwrap();
+        assert!(accepts_trickle(&offer.sdp));
+        swarm2
+            .answer_remote_connection(swarm3.did(), &offer)
+            .await
+            .unwrap();
+        assert!(swarm2.trickle_peers.contains(swarm3.did()));
+    }
+}
@@ -1,13 +1,21 @@
 #![warn(missing_docs)]
 //! This module defines type and type alias related to Swarm.
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
 use async_trait::async_trait;
+use dashmap::DashSet;
 use rings_transport::core::transport::ConnectionInterface;
 
 use crate::dht::Did;
 use crate::dht::LiveDid;
 use crate::measure::BehaviourJudgement;
+use crate::message::MessagePayload;
+use crate::message::MessageVerificationExt;
 use crate::swarm::Swarm;
 use crate::types::Connection;
+use crate::utils::get_epoch_ms;
 
 /// Type of Measure, see [Measure].
 #[cfg(not(feature = "wasm"))]
@@ -62,3 +70,240 @@ impl LiveDid for WrappedDid {
         }
     }
 }
+
+/// Upper bound of [RetryBudget::attempts], so that retries never amplify load too much
+/// during a partition.
+pub const MAX_LOOKUP_ATTEMPTS: u8 = 5;
+
+/// Retry budget of DHT lookups, such as `FindSuccessorSend` and `SearchVNode`.
+/// When sending a lookup to the next hop fails, it's retried through an alternate
+/// connected peer, after waiting `backoff_ms * attempt`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryBudget {
+    /// Total attempts including the first one, capped by [MAX_LOOKUP_ATTEMPTS].
+    pub attempts: u8,
+    /// Base of linear backoff between attempts in milliseconds.
+    pub backoff_ms: u64,
+}
+
+impl RetryBudget {
+    /// Create a retry budget, attempts are capped by [MAX_LOOKUP_ATTEMPTS].
+    pub fn new(attempts: u8, backoff_ms: u64) -> Self {
+        Self {
+            attempts: attempts.clamp(1, MAX_LOOKUP_ATTEMPTS),
+            backoff_ms,
+        }
+    }
+
+    /// A budget without retry.
+    pub fn none() -> Self {
+        Self::new(1, 0)
+    }
+}
+
+impl Default for RetryBudget {
+    fn default() -> Self {
+        Self::new(3, 200)
+    }
+}
+
+/// Peers whose transports are trusted to skip per-message signature verification.
+///
+/// # Security
+/// Trusting a transport is a trade-off of security for CPU. Once a peer is trusted,
+/// a message received on its transport is accepted without checking signatures,
+/// as long as it is signed by that peer and has not been relayed by anyone else.
+/// Only trust a peer when its did is bound to the transport by the handshake,
+/// otherwise a forged message may be accepted. Relayed messages, messages from any
+/// other transport, and messages over a WebSocket relay are always verified.
+#[derive(Clone, Default)]
+pub struct TrustedTransports(Arc<DashSet<Did>>);
+
+impl TrustedTransports {
+    /// Trust the transport of peer.
+    pub fn insert(&self, peer: Did) -> bool {
+        self.0.insert(peer)
+    }
+
+    /// Stop trusting the transport of peer.
+    pub fn remove(&self, peer: Did) -> bool {
+        self.0.remove(&peer).is_some()
+    }
+
+    /// Check if the transport of peer is trusted.
+    pub fn contains(&self, peer: Did) -> bool {
+        self.0.contains(&peer)
+    }
+
+    /// Check if the payload received from the transport of peer can skip signature verification.
+    /// The payload should be sent directly by the trusted peer and not expired.
+    pub(crate) fn can_skip_verification(&self, peer: Did, payload: &MessagePayload) -> bool {
+        self.contains(peer)
+            && payload.relay.path == [peer]
+            && payload.signer() == peer
+            && payload.transaction.signer() == peer
+            && !payload.is_expired()
+            && !payload.transaction.is_expired()
+    }
+}
+
+/// Peers pinned as permanent connections.
+/// Pinning is keyed by did, so it survives reconnection. A pinned peer always passes
+/// the behaviour judgement, and is reconnected by stabilization once its connection is lost.
+#[derive(Clone, Default)]
+pub struct PinnedPeers(Arc<DashSet<Did>>);
+
+impl PinnedPeers {
+    /// Pin peer.
+    pub fn insert(&self, peer: Did) -> bool {
+        self.0.insert(peer)
+    }
+
+    /// Unpin peer.
+    pub fn remove(&self, peer: Did) -> bool {
+        self.0.remove(&peer).is_some()
+    }
+
+    /// Check if peer is pinned.
+    pub fn contains(&self, peer: Did) -> bool {
+        self.0.contains(&peer)
+    }
+
+    /// List pinned peers.
+    pub fn list(&self) -> Vec<Did> {
+        self.0.iter().map(|did| *did).collect()
+    }
+}
+
+/// Default time in milliseconds for a sending to hand off a message to transport,
+/// including waiting in the send queue of peer and backpressure of data channel.
+pub const DEFAULT_SEND_TIMEOUT_MS: u64 = 10 * 1000;
+
+/// Max time of initial convergence in milliseconds. The maintenance priority boost is lifted
+/// after it even if the node has not found its successor and predecessor.
+pub const INITIAL_CONVERGENCE_TIMEOUT_MS: u128 = 60 * 1000;
+
+/// Initial convergence of DHT after swarm starts. Until convergence completes,
+/// DHT maintenance messages are sent ahead of application messages, so that a node joins
+/// the ring quickly under application load.
+#[derive(Debug)]
+pub struct Convergence {
+    started_at_ms: u128,
+    converged: AtomicBool,
+}
+
+impl Convergence {
+    /// Start convergence from now, it's completed already if `converged` is true.
+    pub fn new(converged: bool) -> Self {
+        Self {
+            started_at_ms: get_epoch_ms(),
+            converged: AtomicBool::new(converged),
+        }
+    }
+
+    /// Check if initial convergence is completed.
+    pub fn is_converged(&self) -> bool {
+        self.converged.load(Ordering::SeqCst)
+    }
+
+    /// Milliseconds since convergence started.
+    pub fn elapsed_ms(&self) -> u128 {
+        get_epoch_ms().saturating_sub(self.started_at_ms)
+    }
+
+    /// Check if convergence window is elapsed.
+    pub fn is_timed_out(&self) -> bool {
+        self.elapsed_ms() >= INITIAL_CONVERGENCE_TIMEOUT_MS
+    }
+
+    /// Mark convergence completed, returns true only for the first call.
+    pub fn complete(&self) -> bool {
+        !self.converged.swap(true, Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ecc::SecretKey;
+    use crate::message::JoinDHT;
+    use crate::message::Message;
+    use crate::message::MessageRelay;
+    use crate::session::SessionSk;
+
+    #[test]
+    fn test_trusted_transports_skip_verification() {
+        let peer_sk = SessionSk::new_with_seckey(&SecretKey::random()).unwrap();
+        let relay_sk = SessionSk::new_with_seckey(&SecretKey::random()).unwrap();
+        let peer = peer_sk.account_did();
+        let relayer = relay_sk.account_did();
+        let local: Did = SecretKey::random().address().into();
+
+        let msg = Message::JoinDHT(JoinDHT { did: peer });
+        let direct = MessagePayload::new_send(msg.clone(), &peer_sk, local, local).unwrap();
+
+        let trusted = TrustedTransports::default();
+        assert!(!trusted.can_skip_verification(peer, &direct));
+
+        assert!(trusted.insert(peer));
+        assert!(trusted.can_skip_verification(peer, &direct));
+        // Only the transport of the trusted peer can skip verification.
+        assert!(!trusted.can_skip_verification(relayer, &direct));
+
+        // Messages relayed by the trusted peer are still verified.
+        let relayed = MessagePayload::new(
+            direct.transaction.clone(),
+            &peer_sk,
+            MessageRelay::new(vec![relayer, peer], local, local),
+        )
+        .unwrap();
+        assert!(!trusted.can_skip_verification(peer, &relayed));
+
+        // Messages signed by others are still verified.
+        let forged = MessagePayload::new_send(msg, &relay_sk, local, local).unwrap();
+        let forged = MessagePayload {
+            relay: MessageRelay::new(vec![peer], local, local),
+            ..forged
+        };
+        assert!(!trusted.can_skip_verification(peer, &forged));
+
+        assert!(trusted.remove(peer));
+        assert!(!trusted.can_skip_verification(peer, &direct));
+    }
+
+    #[test]
+    fn test_pinned_peers() {
+        let peer: Did = SecretKey::random().address().into();
+        let pinned = PinnedPeers::default();
+        assert!(!pinned.contains(peer));
+
+        assert!(pinned.insert(peer));
+        assert!(!pinned.insert(peer));
+        // Clones share the same pinned set.
+        assert!(pinned.clone().contains(peer));
+        assert_eq!(pinned.list(), vec![peer]);
+
+        assert!(pinned.remove(peer));
+        assert!(!pinned.remove(peer));
+        assert!(pinned.list().is_empty());
+    }
+
+    #[test]
+    fn test_convergence_completes_once() {
+        let convergence = Convergence::new(false);
+        assert!(!convergence.is_converged());
+        assert!(!convergence.is_timed_out());
+        assert!(convergence.complete());
+        assert!(!convergence.complete());
+        assert!(convergence.is_converged());
+        assert!(Convergence::new(true).is_converged());
+    }
+
+    #[test]
+    fn test_retry_budget_is_bounded() {
+        assert_eq!(RetryBudget::new(0, 100).attempts, 1);
+        assert_eq!(RetryBudget::new(3, 100).attempts, 3);
+        assert_eq!(RetryBudget::new(u8::MAX, 100).attempts, MAX_LOOKUP_ATTEMPTS);
+        assert_eq!(RetryBudget::none().attempts, 1);
+    }
+}
@@ -1,5 +1,7 @@
 use std::sync::Arc;
 
+use rings_transport::ice_gathering::IceGatheringMode;
+
 use crate::dht::Did;
 use crate::dht::PeerRing;
 use crate::ecc::SecretKey;
@@ -16,6 +18,15 @@ mod test_stabilization;
 pub async fn prepare_node_with_callback(
     key: SecretKey,
     message_callback: Option<CallbackFn>,
+) -> (Arc<Swarm>, String) {
+    // Messages are listened one by one in tests, so candidates are not trickled between them.
+    prepare_node_with_gathering(key, message_callback, IceGatheringMode::Full).await
+}
+
+pub async fn prepare_node_with_gathering(
+    key: SecretKey,
+    message_callback: Option<CallbackFn>,
+    mode: IceGatheringMode,
 ) -> (Arc<Swarm>, String) {
     let stun = "stun://stun.l.google.com:19302";
     let path = PersistenceStorage::random_path("./tmp");
@@ -25,7 +36,7 @@ pub async fn prepare_node_with_callback(
 
     let session_sk = SessionSk::new_with_seckey(&key).unwrap();
 
-    let mut swarm_builder = SwarmBuilder::new(stun, storage, session_sk);
+    let mut swarm_builder = SwarmBuilder::new(stun, storage, session_sk).ice_gathering_mode(mode);
 
     if let Some(callback) = message_callback {
         swarm_builder = swarm_builder.message_callback(callback);
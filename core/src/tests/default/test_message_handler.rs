@@ -18,8 +18,13 @@ use crate::message::FindSuccessorThen;
 use crate::message::Message;
 use crate::message::PayloadSender;
 use crate::prelude::vnode::VNodeOperation;
+use crate::session::SessionSk;
+use crate::storage::PersistenceStorage;
 use crate::storage::PersistenceStorageOperation;
 use crate::storage::PersistenceStorageReadAndWrite;
+use crate::swarm::impls::ConnectionHandshake;
+use crate::swarm::IpLimitConfig;
+use crate::swarm::SwarmBuilder;
 use crate::tests::default::prepare_node;
 use crate::tests::manually_establish_connection;
 
@@ -40,6 +45,34 @@ async fn test_handle_join() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_ip_limit_before_handshake() -> Result<()> {
+    let path = PersistenceStorage::random_path("./tmp");
+    let storage = PersistenceStorage::new_with_path(path.as_str()).await?;
+    let session_sk = SessionSk::new_with_seckey(&SecretKey::random())?;
+    let answerer = SwarmBuilder::new("stun://stun.l.google.com:19302", storage, session_sk)
+        .ip_limit(IpLimitConfig {
+            max_pending: 1,
+            ..Default::default()
+        })
+        .build();
+    let node1 = prepare_node(SecretKey::random()).await.0;
+    let node2 = prepare_node(SecretKey::random()).await.0;
+
+    // Offers signaled out-of-band carry every candidate, and both nodes share the host IP.
+    let (_, offer) = node1.create_offer(answerer.did()).await?;
+    answerer.answer_offer(offer).await?;
+    let (_, offer) = node2.create_offer(answerer.did()).await?;
+    assert!(matches!(
+        answerer.answer_offer(offer).await,
+        Err(Error::ConnectionRejected(did, _)) if did == node2.did()
+    ));
+    // The offer is rejected before a transport is created for the handshake.
+    assert!(answerer.get_connection(node2.did()).is_none());
+    tokio::fs::remove_dir_all("./tmp").await.ok();
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_handle_connect_node() -> Result<()> {
     let keys = gen_ordered_keys(3);
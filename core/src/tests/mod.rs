@@ -24,8 +24,19 @@ pub async fn manually_establish_connection(swarm1: &Swarm, swarm2: &Swarm) {
     let conn1 = swarm1.new_connection(swarm2.did()).await.unwrap();
     let conn2 = swarm2.new_connection(swarm1.did()).await.unwrap();
 
+    // Nothing else is signaled, so descriptions carry every candidate.
     let offer = conn1.webrtc_create_offer().await.unwrap();
+    let offer = conn1
+        .webrtc_gathered_description()
+        .await
+        .unwrap()
+        .unwrap_or(offer);
     let answer = conn2.webrtc_answer_offer(offer).await.unwrap();
+    let answer = conn2
+        .webrtc_gathered_description()
+        .await
+        .unwrap()
+        .unwrap_or(answer);
     conn1.webrtc_accept_answer(answer).await.unwrap();
 
     assert!(swarm1.get_connection(swarm2.did()).is_some());
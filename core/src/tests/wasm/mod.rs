@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use rings_transport::ice_gathering::IceGatheringMode;
 use wasm_bindgen_test::wasm_bindgen_test_configure;
 
 use crate::ecc::SecretKey;
@@ -29,7 +30,11 @@ pub async fn prepare_node(key: SecretKey) -> Arc<Swarm> {
             .await
             .unwrap();
 
-    let swarm = Arc::new(SwarmBuilder::new(stun, storage, session_sk).build());
+    let swarm = Arc::new(
+        SwarmBuilder::new(stun, storage, session_sk)
+            .ice_gathering_mode(IceGatheringMode::Full)
+            .build(),
+    );
 
     println!("key: {:?}", key.to_string());
     println!("did: {:?}", swarm.did());
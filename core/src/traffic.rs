@@ -0,0 +1,134 @@
+//! This module provide the `TrafficMeter` for accounting of bytes sent and received.
+//! Unlike [Measure](crate::measure::Measure), it doesn't judge peers. It keeps cumulative
+//! counters for billing, quota or fairness policies.
+#![warn(missing_docs)]
+use std::hash::Hash;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// A snapshot of traffic counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrafficCounters {
+    /// Bytes sent.
+    pub bytes_sent: u64,
+    /// Bytes received.
+    pub bytes_received: u64,
+}
+
+#[derive(Debug, Default)]
+struct AtomicTraffic {
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+}
+
+impl AtomicTraffic {
+    fn snapshot(&self) -> TrafficCounters {
+        TrafficCounters {
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Cumulative traffic counters keyed by peer or service.
+/// Recording is an atomic increment, the map is only written when a key is seen first time.
+/// Cloned meters share the same counters.
+#[derive(Debug, Clone)]
+pub struct TrafficMeter<K: Hash + Eq> {
+    table: Arc<DashMap<K, AtomicTraffic>>,
+}
+
+impl<K: Hash + Eq> Default for TrafficMeter<K> {
+    fn default() -> Self {
+        Self {
+            table: Arc::new(DashMap::new()),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone> TrafficMeter<K> {
+    fn with_counter(&self, key: &K, f: impl FnOnce(&AtomicTraffic)) {
+        if let Some(counter) = self.table.get(key) {
+            return f(&counter);
+        }
+        f(&self.table.entry(key.clone()).or_default());
+    }
+
+    /// Record bytes sent to key.
+    pub fn record_sent(&self, key: &K, bytes: usize) {
+        self.with_counter(key, |c| {
+            c.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+        })
+    }
+
+    /// Record bytes received from key.
+    pub fn record_received(&self, key: &K, bytes: usize) {
+        self.with_counter(key, |c| {
+            c.bytes_received.fetch_add(bytes as u64, Ordering::Relaxed);
+        })
+    }
+
+    /// Get counters of key.
+    pub fn get(&self, key: &K) -> TrafficCounters {
+        self.table
+            .get(key)
+            .map(|c| c.snapshot())
+            .unwrap_or_default()
+    }
+
+    /// Get counters of all keys.
+    pub fn snapshot(&self) -> Vec<(K, TrafficCounters)> {
+        self.table
+            .iter()
+            .map(|c| (c.key().clone(), c.value().snapshot()))
+            .collect()
+    }
+
+    /// Reset counters of key, and return counters before reset.
+    pub fn reset(&self, key: &K) -> TrafficCounters {
+        self.table
+            .remove(key)
+            .map(|(_, c)| c.snapshot())
+            .unwrap_or_default()
+    }
+
+    /// Reset counters of all keys.
+    pub fn reset_all(&self) {
+        self.table.clear()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_traffic_meter() {
+        let meter = TrafficMeter::<String>::default();
+        let a = "a".to_string();
+        let b = "b".to_string();
+
+        meter.record_sent(&a, 10);
+        meter.record_sent(&a, 5);
+        meter.clone().record_received(&a, 7);
+        meter.record_received(&b, 1);
+
+        assert_eq!(meter.get(&a), TrafficCounters {
+            bytes_sent: 15,
+            bytes_received: 7
+        });
+        assert_eq!(meter.snapshot().len(), 2);
+
+        assert_eq!(meter.reset(&a).bytes_sent, 15);
+        assert_eq!(meter.get(&a), TrafficCounters::default());
+        assert_eq!(meter.get(&b).bytes_received, 1);
+
+        meter.reset_all();
+        assert!(meter.snapshot().is_empty());
+    }
+}
@@ -10,6 +10,20 @@ pub enum TransportEvent {
     Connected(Did),
     DataChannelMessage(Vec<u8>),
     Closed(Did),
+    IceCandidate(Did, String),
+    HandshakeCompleted(Did),
+}
+
+/// Policy applied when sending to a bounded channel which is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Drop the oldest message in the channel to make room for the new one.
+    DropOldest,
+    /// Drop the new message.
+    DropNewest,
+    /// Wait until the receiver makes room for the new message.
+    #[default]
+    Backpressure,
 }
 
 /// Channel trant implement methods.
@@ -19,9 +33,21 @@ pub trait Channel<T: Send> {
     type Sender;
     type Receiver;
 
+    /// Create an unbounded channel.
     fn new() -> Self;
+    /// Create a channel holding at most `capacity` messages.
+    /// When it's full, sending is handled by `policy`.
+    fn bounded(capacity: usize, policy: OverflowPolicy) -> Self;
     fn sender(&self) -> Self::Sender;
     fn receiver(&self) -> Self::Receiver;
+    /// Number of messages waiting in the channel.
+    fn len(&self) -> usize;
+    /// Check if there is no message waiting in the channel.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Take the number of messages dropped by overflow since last call.
+    fn take_dropped(&self) -> u64;
     async fn send(sender: &Self::Sender, msg: T) -> Result<()>;
     async fn recv(receiver: &Self::Receiver) -> Result<Option<T>>;
 }
@@ -6,6 +6,30 @@ pub fn get_epoch_ms() -> u128 {
     Utc::now().timestamp_millis() as u128
 }
 
+/// Sleep for milliseconds, on both native and browser runtime.
+pub async fn sleep_ms(ms: u64) {
+    if ms == 0 {
+        return;
+    }
+    #[cfg(not(feature = "wasm"))]
+    futures_timer::Delay::new(std::time::Duration::from_millis(ms)).await;
+    #[cfg(feature = "wasm")]
+    if let Err(e) = js_utils::window_sleep(ms as i32).await {
+        tracing::warn!("Failed on sleep: {:?}", e);
+    }
+}
+
+/// Run a future with a timeout in milliseconds, on both native and browser runtime.
+/// Returns None if the future is not ready in time, and the future is dropped then.
+pub async fn timeout_ms<F: std::future::Future>(ms: u64, fut: F) -> Option<F::Output> {
+    let fut = std::pin::pin!(fut);
+    let timer = std::pin::pin!(sleep_ms(ms));
+    match futures::future::select(fut, timer).await {
+        futures::future::Either::Left((output, _)) => Some(output),
+        futures::future::Either::Right(_) => None,
+    }
+}
+
 #[cfg(feature = "wasm")]
 /// Toolset for wasm
 pub mod js_value {
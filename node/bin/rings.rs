@@ -13,6 +13,7 @@ use futures::pin_mut;
 use futures::select;
 use futures::StreamExt;
 use futures_timer::Delay;
+use rings_node::backend::service::capture::render_capture;
 use rings_node::backend::service::proxy::wrap_custom_message;
 use rings_node::backend::service::proxy::Tunnel;
 use rings_node::backend::service::proxy::TunnelMessage;
@@ -27,6 +28,7 @@ use rings_node::native::endpoint::run_http_api;
 use rings_node::prelude::http;
 use rings_node::prelude::rings_core::dht::Did;
 use rings_node::prelude::rings_core::ecc::SecretKey;
+use rings_node::prelude::rings_core::message::CompressionDictionary;
 use rings_node::prelude::rings_core::message::PayloadSender;
 use rings_node::prelude::rings_core::prelude::uuid::Uuid;
 use rings_node::prelude::PersistenceStorage;
@@ -69,6 +71,8 @@ enum Command {
         about = "Show information of swarm. Include transport table, successors, predecessor, and finger table."
     )]
     Inspect(InspectCommand),
+    #[command(about = "Renders a tunnel capture file in a human-readable form.")]
+    RenderCapture(RenderCaptureCommand),
 }
 
 #[derive(Args, Debug)]
@@ -160,6 +164,11 @@ struct RunCommand {
     proxy_target_did: Option<String>,
     #[arg(long)]
     proxy_target_name: Option<String>,
+    #[arg(
+        long,
+        help = "Capture tunnels of proxy, requires tunnel_capture in config file"
+    )]
+    proxy_capture: bool,
 }
 
 #[derive(Args, Debug)]
@@ -353,6 +362,12 @@ struct InspectCommand {
     client_args: ClientArgs,
 }
 
+#[derive(Args, Debug)]
+struct RenderCaptureCommand {
+    #[arg(help = "The capture file of a tunnel")]
+    file: String,
+}
+
 #[allow(clippy::too_many_arguments)]
 async fn daemon_run(args: RunCommand) -> anyhow::Result<()> {
     let mut c = config::Config::read_fs(args.config_args.config)?;
@@ -395,50 +410,128 @@ async fn daemon_run(args: RunCommand) -> anyhow::Result<()> {
             .await?;
 
     let measure = PeriodicMeasure::new(per_measure_storage);
+    let peer_book = match c.peer_book.as_ref() {
+        Some(config) => Some(
+            PersistenceStorage::new_with_cap_and_path(config.capacity, config.path.clone()).await?,
+        ),
+        None => None,
+    };
 
-    let processor = Arc::new(
-        ProcessorBuilder::from_config(&processor_config)?
-            .storage(per_data_storage)
-            .measure(measure)
-            .build()?,
-    );
+    let mut processor_builder = ProcessorBuilder::from_config(&processor_config)?
+        .storage(per_data_storage)
+        .measure(measure)
+        .message_filter(c.message_filter.clone())
+        .expiry_policy(c.expiry_policy.clone())
+        .key_rotation(c.key_rotation)
+        .ice_gathering(c.ice_gathering)
+        .mtu_bounds(c.mtu_bounds)
+        .payload_transforms(c.payload_transforms.clone());
+    if let Some(storage) = peer_book {
+        processor_builder = processor_builder.peer_book(storage);
+    }
+    if let Some(key) = c.ecdsa_key {
+        processor_builder = processor_builder.secret_key(key)?;
+    }
+    if let Some(ip_limit) = c.ip_limit {
+        processor_builder = processor_builder.ip_limit(ip_limit);
+    }
+    if let Some(max_message_size) = c.max_message_size {
+        processor_builder = processor_builder.max_message_size(max_message_size);
+    }
+    if let Some(capabilities) = c.announce.clone() {
+        processor_builder = processor_builder.announce(capabilities);
+    }
+    if let Some(seeds) = c.closed_network.clone() {
+        processor_builder = processor_builder.closed_network(seeds);
+    }
+    if let Some(config) = c.store_limit {
+        processor_builder = processor_builder.store_limit(config);
+    }
+    if let Some(path) = c.compression_dictionary.as_ref() {
+        let dictionary = CompressionDictionary::new(std::fs::read(path)?);
+        processor_builder = processor_builder.compression_dictionary(dictionary);
+    }
+    let processor = Arc::new(processor_builder.build()?);
     println!("Did: {}", processor.swarm.did());
 
-    let (sender, receiver) = tokio::sync::broadcast::channel(1024);
-    let backend = Arc::new(Backend::new(backend_config, sender, processor.swarm.clone()).await?);
+    let (sender, receiver) = tokio::sync::broadcast::channel(backend_config.broadcast.capacity);
+    let backend = Arc::new(
+        Backend::new(
+            backend_config,
+            sender,
+            processor.swarm.clone(),
+            processor.drain_state(),
+        )
+        .await?,
+    );
     let backend_service_names = backend.service_names();
 
-    processor.swarm.set_callback(backend.clone()).unwrap();
-
-    if args.proxy_listen_address.is_some() {
-        let proxy_listen_address = args.proxy_listen_address.unwrap().parse()?;
-        let proxy_target_did = args.proxy_target_did.unwrap().parse()?;
-        let proxy_target_name = args.proxy_target_name.unwrap();
-
-        println!("Proxy listen: {proxy_listen_address}");
-
-        let processor_clone = processor.clone();
-        let backend_clone = backend.clone();
-        let _ = futures::join!(
-            processor.listen(),
-            service_loop_register(&processor, backend_service_names),
-            run_http_api(c.http_addr, processor_clone, receiver),
-            proxy_listen(
-                backend_clone,
-                proxy_listen_address,
-                proxy_target_did,
-                &proxy_target_name
-            )
-        );
-    } else {
-        let processor_clone = processor.clone();
-        let _ = futures::join!(
-            processor.listen(),
-            service_loop_register(&processor, backend_service_names),
-            run_http_api(c.http_addr, processor_clone, receiver),
+    processor.set_backend(backend.clone())?;
+
+    if let Some(admin) = c.admin.clone() {
+        #[cfg(feature = "admin")]
+        {
+            let serving = rings_node::native::endpoint::admin::bind_admin_api(
+                admin,
+                processor.clone(),
+                backend.clone(),
+            )?;
+            tokio::spawn(async move {
+                if let Err(e) = serving.await {
+                    tracing::error!("Admin API stopped: {e:?}");
+                }
+            });
+        }
+        #[cfg(not(feature = "admin"))]
+        anyhow::bail!(
+            "Admin API on {} is configured, but not built, enable feature `admin`.",
+            admin.bind
         );
     }
 
+    let serving = async {
+        if args.proxy_listen_address.is_some() {
+            let proxy_listen_address = args.proxy_listen_address.unwrap().parse()?;
+            let proxy_target_did = args.proxy_target_did.unwrap().parse()?;
+            let proxy_target_name = args.proxy_target_name.unwrap();
+
+            println!("Proxy listen: {proxy_listen_address}");
+
+            let processor_clone = processor.clone();
+            let backend_clone = backend.clone();
+            let _ = futures::join!(
+                processor.listen(),
+                reconnect_peers(&processor),
+                service_loop_register(&processor, backend_service_names),
+                run_http_api(c.http_addr, processor_clone, receiver),
+                proxy_listen(
+                    backend_clone,
+                    proxy_listen_address,
+                    proxy_target_did,
+                    &proxy_target_name,
+                    args.proxy_capture,
+                )
+            );
+        } else {
+            let processor_clone = processor.clone();
+            let _ = futures::join!(
+                processor.listen(),
+                reconnect_peers(&processor),
+                service_loop_register(&processor, backend_service_names),
+                run_http_api(c.http_addr, processor_clone, receiver),
+            );
+        }
+        anyhow::Ok(())
+    };
+
+    tokio::select! {
+        served = serving => served?,
+        _ = tokio::signal::ctrl_c() => tracing::info!("Shutting down"),
+    }
+
+    // Stabilization is stopped first, so that no pass runs on connections being closed.
+    processor.stop_stabilization().await;
+    backend.shutdown().await;
     Ok(())
 }
 
@@ -468,11 +561,19 @@ async fn proxy_listen(
     proxy_listen_address: SocketAddr,
     proxy_target_did: Did,
     proxy_target_name: &str,
+    capture: bool,
 ) -> anyhow::Result<()> {
     let listener = TcpListener::bind(proxy_listen_address).await?;
     loop {
         let (socket, _) = listener.accept().await?;
-        proxy_dial(backend.clone(), socket, proxy_target_did, proxy_target_name).await?;
+        proxy_dial(
+            backend.clone(),
+            socket,
+            proxy_target_did,
+            proxy_target_name,
+            capture,
+        )
+        .await?;
     }
 }
 
@@ -481,10 +582,16 @@ pub async fn proxy_dial(
     local_stream: TcpStream,
     proxy_target_did: Did,
     proxy_target_name: &str,
+    capture: bool,
 ) -> anyhow::Result<()> {
     let tid = Uuid::new_v4();
 
-    let mut tunnel = Tunnel::new(tid);
+    let mut tunnel = Tunnel::new(tid).with_queue(backend.tcp_server.queue());
+    if capture {
+        if let Some(capture) = backend.tcp_server.new_capture(tid) {
+            tunnel = tunnel.with_capture(capture);
+        }
+    }
     tunnel
         .listen(local_stream, backend.swarm.clone(), proxy_target_did)
         .await;
@@ -650,10 +757,18 @@ async fn main() -> anyhow::Result<()> {
                 .display();
             Ok(())
         }
+        Command::RenderCapture(args) => {
+            print!("{}", render_capture(args.file)?);
+            Ok(())
+        }
     }
 }
 
 async fn register_services(processor: &Processor, names: Vec<String>) -> anyhow::Result<()> {
+    // Stop refreshing registrations while draining, so that the node isn't found by new clients.
+    if processor.drain_state().is_draining() {
+        return Ok(());
+    }
     let jobs = names.iter().map(|n| processor.register_service(n));
     let results = futures::future::join_all(jobs).await;
 
@@ -666,6 +781,22 @@ async fn register_services(processor: &Processor, names: Vec<String>) -> anyhow:
     Ok(())
 }
 
+/// Reconnect peers remembered by the peer book, bootstrap and relay nodes first.
+async fn reconnect_peers(processor: &Processor) {
+    match processor.reconnect_peers(true).await {
+        Ok(progress) => {
+            for p in progress {
+                match p.result {
+                    Ok(_) => tracing::info!("Reconnected peer {}", p.did),
+                    Err(e) => tracing::warn!("Failed on reconnecting peer {}: {}", p.did, e),
+                }
+            }
+        }
+        Err(rings_node::error::Error::PeerBookNotConfigured) => {}
+        Err(e) => tracing::error!("Failed on reconnecting peers: {}", e),
+    }
+}
+
 async fn service_loop_register(processor: &Processor, names: Vec<String>) {
     loop {
         let timeout = Delay::new(Duration::from_secs(30)).fuse();
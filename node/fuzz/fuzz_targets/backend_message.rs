@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rings_node::backend::types::split_custom_message;
+use rings_node::backend::types::BackendMessage;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((_, body)) = split_custom_message(data) {
+        if let Ok(msg) = BackendMessage::try_from(body) {
+            let bytes: Vec<u8> = msg.into();
+            assert_eq!(bytes, body);
+        }
+    }
+});
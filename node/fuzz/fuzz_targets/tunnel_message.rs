@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rings_node::backend::service::proxy::TunnelMessage;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = TunnelMessage::try_from(data);
+});
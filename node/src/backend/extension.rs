@@ -34,6 +34,25 @@
 //!
 //! You can see that this wat/wasm extension defines a handler function and
 //! imports the message_type ABI.
+//!
+//! Executions of each extension can be bounded by [ExtensionConfig], and are unbounded by
+//! default. An extension running [ExtensionConfig::max_concurrency] executions rejects more with
+//! [Error::ExtensionBusy], and an execution exceeding [ExtensionConfig::timeout_ms] fails with
+//! [Error::ExtensionTimeout].
+//!
+//! Each execution runs on an instance of its own, with its own store, so that executions of an
+//! extension, or of different extensions, run concurrently without sharing memory. Idle instances
+//! are reused, and one stopped by a trap is discarded.
+//!
+//! Native nodes meter wasm, and an execution running more operators than its
+//! [ExtensionConfig::fuel] is stopped with [Error::ExtensionOutOfFuel]. A timed out execution
+//! keeps its slot of concurrency until it's stopped by fuel, which is sized to the timeout by
+//! default, see [EXTENSION_FUEL_PER_MS], so that a runaway extension releases its thread soon
+//! after timing out. Browsers don't meter wasm, and don't time executions either.
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::sync::RwLock;
 
@@ -46,8 +65,17 @@ use crate::backend::types::BackendMessage;
 use crate::error::Error;
 use crate::error::Result;
 use crate::prelude::reqwest;
+use crate::prelude::rings_core::dht::Did;
+use crate::prelude::rings_core::metrics;
+use crate::prelude::rings_core::metrics::MetricsImpl;
+use crate::prelude::rings_core::utils::get_epoch_ms;
 use crate::prelude::*;
 
+/// Fuel given to an execution for each millisecond of [ExtensionConfig::timeout_ms], if
+/// [ExtensionConfig::fuel] isn't set. It's below the operators run by a millisecond of
+/// compiled wasm, so that an execution runs out of fuel about when it times out.
+pub const EXTENSION_FUEL_PER_MS: u64 = 100_000;
+
 /// Path of a wasm extension
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
 pub enum Path {
@@ -57,17 +85,238 @@ pub enum Path {
     Remote(String),
 }
 
+impl std::fmt::Display for Path {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Path::Local(path) | Path::Remote(path) => write!(f, "{}", path),
+        }
+    }
+}
+
 /// Configure for Extension
-#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(default)]
 pub struct ExtensionConfig {
     /// Path of extension, can be remote or local
     pub paths: Vec<Path>,
+    /// Max number of concurrent executions of each extension, unbounded if not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_concurrency: Option<usize>,
+    /// Timeout of an execution of extension in milliseconds, not timed if not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    /// Number of wasm operators an execution may run before it's stopped. Defaults to
+    /// [EXTENSION_FUEL_PER_MS] for each millisecond of `timeout_ms` if it's set, and
+    /// unbounded otherwise.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fuel: Option<u64>,
+}
+
+impl ExtensionConfig {
+    /// Fuel of an execution, see [ExtensionConfig::fuel].
+    pub fn fuel(&self) -> Option<u64> {
+        let by_timeout = self
+            .timeout_ms
+            .map(|ms| ms.saturating_mul(EXTENSION_FUEL_PER_MS));
+        self.fuel.or(by_timeout)
+    }
+}
+
+/// Counters of executions of an extension, see [Extension::stats].
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ExtensionStats {
+    /// Path of extension.
+    pub path: Path,
+    /// Number of executions finished in time, including failed ones.
+    pub executions: u64,
+    /// Number of executions timed out.
+    pub timeouts: u64,
+    /// Number of executions rejected for reaching max concurrency.
+    pub rejected: u64,
+    /// Number of executions running, including timed out ones which have not returned.
+    pub running: usize,
+}
+
+/// Number of executions running, see [InFlight::enter].
+#[derive(Debug, Clone, Default)]
+struct InFlight(Arc<AtomicUsize>);
+
+/// An execution counted by [InFlight], which leaves on drop.
+struct InFlightGuard(Arc<AtomicUsize>);
+
+impl InFlight {
+    /// Enter an execution, None if `max` executions are running.
+    fn enter(&self, max: usize) -> Option<InFlightGuard> {
+        self.0
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| InFlightGuard(self.0.clone()))
+    }
+
+    fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A loaded extension with its bounds of execution.
+struct BoundedHandler {
+    path: Path,
+    handler: Arc<Handler>,
+    in_flight: InFlight,
+    executions: AtomicU64,
+    timeouts: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl BoundedHandler {
+    fn new(path: Path, handler: Handler) -> Self {
+        Self {
+            path,
+            handler: Arc::new(handler),
+            in_flight: InFlight::default(),
+            executions: AtomicU64::new(0),
+            timeouts: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    fn stats(&self) -> ExtensionStats {
+        ExtensionStats {
+            path: self.path.clone(),
+            executions: self.executions.load(Ordering::SeqCst),
+            timeouts: self.timeouts.load(Ordering::SeqCst),
+            rejected: self.rejected.load(Ordering::SeqCst),
+            running: self.in_flight.count(),
+        }
+    }
+
+    /// Call the handler within bounds of config.
+    async fn call(
+        &self,
+        config: &ExtensionConfig,
+        metrics: &MetricsImpl,
+        msg: BackendMessage,
+    ) -> Result<BackendMessage> {
+        let max_concurrency = config.max_concurrency.unwrap_or(usize::MAX).max(1);
+        let Some(guard) = self.in_flight.enter(max_concurrency) else {
+            self.rejected.fetch_add(1, Ordering::SeqCst);
+            metrics.increment_counter(metrics::EXTENSION_REJECTED, 1);
+            return Err(Error::ExtensionBusy(self.path.to_string()));
+        };
+
+        let started_at = get_epoch_ms();
+        let ret = self
+            .call_with_timeout(config.timeout_ms, config.fuel(), guard, msg)
+            .await;
+        if matches!(ret, Err(Error::ExtensionTimeout(..))) {
+            self.timeouts.fetch_add(1, Ordering::SeqCst);
+            metrics.increment_counter(metrics::EXTENSION_TIMEOUT, 1);
+        } else {
+            self.executions.fetch_add(1, Ordering::SeqCst);
+            metrics.record_histogram(
+                metrics::EXTENSION_EXECUTION_MS,
+                get_epoch_ms().saturating_sub(started_at) as f64,
+            );
+        }
+        ret
+    }
+
+    /// The call runs on a blocking thread, which holds `guard` until it returns, or is stopped
+    /// by `fuel`. So timed out calls keep counting against `max_concurrency`, which bounds the
+    /// blocking threads taken by an extension.
+    #[cfg(feature = "node")]
+    async fn call_with_timeout(
+        &self,
+        timeout_ms: Option<u64>,
+        fuel: Option<u64>,
+        guard: InFlightGuard,
+        msg: BackendMessage,
+    ) -> Result<BackendMessage> {
+        let handler = self.handler.clone();
+        let call = tokio::task::spawn_blocking(move || {
+            let _guard = guard;
+            handler.call_with_fuel(msg, fuel)
+        });
+        let ret = match timeout_ms {
+            Some(timeout_ms) => {
+                tokio::time::timeout(std::time::Duration::from_millis(timeout_ms), call)
+                    .await
+                    .map_err(|_| Error::ExtensionTimeout(self.path.to_string(), timeout_ms))?
+            }
+            None => call.await,
+        };
+        ret.map_err(|e| Error::WasmRuntimeError(e.to_string()))?
+    }
+
+    /// Browsers run a single thread, which can't be preempted, and don't meter wasm, so the
+    /// call is neither timed nor fueled.
+    #[cfg(not(feature = "node"))]
+    async fn call_with_timeout(
+        &self,
+        _timeout_ms: Option<u64>,
+        _fuel: Option<u64>,
+        _guard: InFlightGuard,
+        msg: BackendMessage,
+    ) -> Result<BackendMessage> {
+        self.handler.call(msg)
+    }
 }
 
 /// Manager of Extension
 pub struct Extension {
     /// Extension list
-    handlers: Vec<Handler>,
+    handlers: Vec<BoundedHandler>,
+    /// Bounds of executions
+    config: ExtensionConfig,
+    /// Recorder of executions
+    metrics: MetricsImpl,
+    /// Handle for sending messages proactively
+    sender: ExtensionSender,
+}
+
+/// A handle of swarm for extensions, which allows pushing messages outside the
+/// request/response cycle of [MessageEndpoint::handle_message], such as streaming
+/// updates of a subscription.
+///
+/// The handle is only active between `on_start` and `on_stop` of the [Extension].
+/// Once the extension is stopped, sending is rejected with [Error::ExtensionStopped],
+/// so background tasks holding the handle should exit on that error.
+#[derive(Clone)]
+pub struct ExtensionSender {
+    swarm: Arc<Swarm>,
+    active: Arc<AtomicBool>,
+}
+
+impl ExtensionSender {
+    /// Check if the extension is running.
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    /// Emit events to swarm, as if they were returned by a handler.
+    pub async fn send(&self, events: &[MessageHandlerEvent]) -> Result<()> {
+        if !self.is_active() {
+            return Err(Error::ExtensionStopped);
+        }
+        self.swarm.handle_message_handler_events(events).await?;
+        Ok(())
+    }
+
+    /// Send a message to destination proactively.
+    pub async fn send_message(&self, msg: BackendMessage, destination: Did) -> Result<()> {
+        let msg_bytes: bytes::Bytes = msg.into();
+        let msg = Message::custom(&msg_bytes).map_err(|_| Error::InvalidMessage)?;
+        self.send(&[MessageHandlerEvent::SendMessage(msg, destination)])
+            .await
+    }
 }
 
 /// Calls the extension handler with the given message and returns the response.
@@ -116,16 +365,36 @@ impl Extension {
     }
 
     /// Creates a new Extension instance with the specified configuration.
-    pub async fn new(config: &ExtensionConfig) -> Result<Self> {
+    pub async fn new(config: &ExtensionConfig, swarm: Arc<Swarm>) -> Result<Self> {
         let mut handlers = vec![];
         for p in &config.paths {
             if let Ok(h) = Self::load(p).await {
-                handlers.push(h)
+                handlers.push(BoundedHandler::new(p.clone(), h))
             } else {
                 log::error!("Failed on loading extension {:?}", p)
             }
         }
-        Ok(Self { handlers })
+        let metrics = swarm.metrics();
+        let sender = ExtensionSender {
+            swarm,
+            active: Arc::new(AtomicBool::new(false)),
+        };
+        Ok(Self {
+            handlers,
+            config: config.clone(),
+            metrics,
+            sender,
+        })
+    }
+
+    /// Get counters of executions of each extension.
+    pub fn stats(&self) -> Vec<ExtensionStats> {
+        self.handlers.iter().map(|h| h.stats()).collect()
+    }
+
+    /// Get a handle for sending messages proactively, see [ExtensionSender].
+    pub fn sender(&self) -> ExtensionSender {
+        self.sender.clone()
     }
 }
 
@@ -141,7 +410,7 @@ impl MessageEndpoint for Extension {
         let mut ret = vec![];
 
         for h in &self.handlers {
-            let resp = h.call(data.clone())?;
+            let resp = h.call(&self.config, &self.metrics, data.clone()).await?;
             if resp != data.clone() {
                 let resp_bytes: bytes::Bytes = resp.into();
                 let ev = MessageHandlerEvent::SendReportMessage(
@@ -153,45 +422,68 @@ impl MessageEndpoint for Extension {
         }
         Ok(ret)
     }
+
+    async fn on_start(&self) -> Result<()> {
+        self.sender.active.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn on_stop(&self) -> Result<()> {
+        self.sender.active.store(false, Ordering::SeqCst);
+        Ok(())
+    }
 }
 
 /// Loader of wasm, including ABI generator
 pub mod loader {
     use core::any::Any;
     use std::fs;
+    #[cfg(feature = "node")]
     use std::sync::Arc;
-    use std::sync::RwLock;
+    use std::sync::Mutex;
 
-    use lazy_static::lazy_static;
     use wasmer::imports;
     use wasmer::AsStoreMut;
     use wasmer::ExternRef;
-    use wasmer::FromToNativeWasmType;
     use wasmer::FunctionEnv;
     use wasmer::FunctionEnvMut;
     use wasmer::FunctionType;
     use wasmer::Type;
     use wasmer::TypedFunction;
     use wasmer::Value;
+    #[cfg(feature = "node")]
+    use wasmer_middlewares::metering::get_remaining_points;
+    #[cfg(feature = "node")]
+    use wasmer_middlewares::metering::set_remaining_points;
+    #[cfg(feature = "node")]
+    use wasmer_middlewares::metering::MeteringPoints;
 
     use super::MaybeBackendMessage;
     use crate::backend::types::BackendMessage;
     use crate::error::Error;
     use crate::error::Result;
 
-    lazy_static! {
-        static ref WASM_MEM: Arc<RwLock<wasmer::Store>> =
-            Arc::new(RwLock::new(wasmer::Store::default()));
+    /// Max number of idle instances kept by a [Handler] for reuse.
+    pub const MAX_IDLE_INSTANCES: usize = 16;
+
+    /// Store metering every operator by a point, see [Handler::call_with_fuel].
+    /// The metering middleware can't be shared by modules, so each module is compiled
+    /// by an engine of its own.
+    #[cfg(feature = "node")]
+    fn new_store() -> wasmer::Store {
+        use wasmer::wasmparser::Operator;
+        use wasmer::CompilerConfig;
+        use wasmer_middlewares::Metering;
+
+        let metering = Arc::new(Metering::new(u64::MAX, |_: &Operator| 1));
+        let mut compiler = wasmer::Cranelift::default();
+        compiler.push_middleware(metering);
+        wasmer::Store::new(wasmer::EngineBuilder::new(compiler))
     }
 
-    impl TryFrom<MaybeBackendMessage> for FunctionEnv<MaybeBackendMessage> {
-        type Error = Error;
-        fn try_from(data: MaybeBackendMessage) -> Result<Self> {
-            let mut mem = WASM_MEM
-                .write()
-                .map_err(|_| Error::WasmGlobalMemoryLockError)?;
-            Ok(FunctionEnv::new(&mut mem, data))
-        }
+    #[cfg(not(feature = "node"))]
+    fn new_store() -> wasmer::Store {
+        wasmer::Store::default()
     }
 
     /// The "WasmABILander" defines how a Rust native struct generates the corresponding Wasm ABI for its getter functions.
@@ -427,79 +719,45 @@ pub mod loader {
         }
     }
 
-    unsafe impl FromToNativeWasmType for MaybeBackendMessage {
-        type Native = Option<ExternRef>;
-
-        fn from_native(native: Self::Native) -> Self {
-            if native.is_none() {
-                return Self::default();
-            }
-            match WASM_MEM
-                .read()
-                .map_err(|_| Error::WasmGlobalMemoryLockError)
-            {
-                Ok(mem) => {
-                    if let Some(m) = native.unwrap().downcast::<Self>(&mem) {
-                        m.clone()
-                    } else {
-                        Self::default()
-                    }
-                }
-                Err(e) => {
-                    log::error!("{:?}", e);
-                    Self::default()
-                }
-            }
-        }
-
-        fn to_native(self) -> Self::Native {
-            // Convert BackendMessage to the native representation
-            match WASM_MEM
-                .write()
-                .map_err(|_| Error::WasmGlobalMemoryLockError)
-            {
-                Ok(mut mem) => {
-                    let ext_ref = ExternRef::new::<Self>(&mut mem, self);
-                    // Checks whether this ExternRef can be used with the given context.
-                    if ext_ref.is_from_store(&mem) {
-                        Some(ext_ref)
-                    } else {
-                        None
-                    }
-                }
-                Err(e) => {
-                    log::error!("{:?}", e);
-                    None
-                }
-            }
-        }
-    }
-
     /// Type of message handler that the Wasm/Wat should implement
     type TyHandler = TypedFunction<Option<ExternRef>, Option<ExternRef>>;
 
-    /// Externref type handler, this is a wrapper of handler function
-    pub struct Handler {
+    /// An instance of extension with a store of its own, which is used by one execution
+    /// at a time.
+    struct HandlerInstance {
+        store: wasmer::Store,
         /// The native function get from wasm.
-        pub func: TyHandler,
+        func: TyHandler,
         /// By default, when resolving an ExternRef, it points to the function environment.
-        pub msg_ref: MaybeBackendMessage,
+        msg_ref: MaybeBackendMessage,
+        /// The instance exporting `func`, whose points of metering are set on each call.
+        instance: wasmer::Instance,
     }
 
-    impl super::ExtensionHandlerCaller for Handler {
-        fn call(&self, msg: BackendMessage) -> Result<BackendMessage> {
+    impl HandlerInstance {
+        /// Wrap the message and call the handler, see [Handler::call_with_fuel].
+        fn call(&mut self, msg: BackendMessage, fuel: Option<u64>) -> Result<BackendMessage> {
             self.msg_ref.wrap(msg)?;
-            let native_msg = self.msg_ref.clone().to_native();
-            let r = {
-                let mut mem = WASM_MEM
-                    .write()
-                    .map_err(|_| Error::WasmGlobalMemoryLockError)?;
-                self.func
-                    .call(&mut mem, native_msg)
-                    .map_err(|e| Error::WasmRuntimeError(e.to_string()))?
-            };
-            let ret = MaybeBackendMessage::from_native(r);
-            let data = ret.0.read().map_err(|_| Error::WasmGlobalMemoryLockError)?;
+            let native_msg = ExternRef::new(&mut self.store, self.msg_ref.clone());
+            #[cfg(feature = "node")]
+            set_remaining_points(&mut self.store, &self.instance, fuel.unwrap_or(u64::MAX));
+            #[cfg(not(feature = "node"))]
+            let _ = fuel;
+            let ret = self.func.call(&mut self.store, Some(native_msg));
+            #[cfg(feature = "node")]
+            if let (Err(_), Some(fuel)) = (&ret, fuel) {
+                if matches!(
+                    get_remaining_points(&mut self.store, &self.instance),
+                    MeteringPoints::Exhausted
+                ) {
+                    return Err(Error::ExtensionOutOfFuel(fuel));
+                }
+            }
+            let r = ret.map_err(|e| Error::WasmRuntimeError(e.to_string()))?;
+            let ret = r
+                .and_then(|r| r.downcast::<MaybeBackendMessage>(&self.store).cloned())
+                .unwrap_or_default();
+            let data = ret.0.read().map_err(|_| Error::WasmBackendMessageRwLockError)?;
             if let Some(m) = &*data {
                 Ok(*m.clone())
             } else {
@@ -508,30 +766,82 @@ pub mod loader {
         }
     }
 
+    /// Externref type handler, this is a wrapper of handler function.
+    /// Each execution takes an idle instance, or creates a new one.
+    pub struct Handler {
+        engine: wasmer::Engine,
+        module: wasmer::Module,
+        idle: Mutex<Vec<HandlerInstance>>,
+    }
+
+    impl Handler {
+        /// Create an instance of module in a new store.
+        fn instantiate(&self) -> Result<HandlerInstance> {
+            let mut store = wasmer::Store::new(self.engine.clone());
+            let msg_ref = MaybeBackendMessage::default();
+            let env = FunctionEnv::new(&mut store, msg_ref.clone());
+            let import_object = MaybeBackendMessage::land_abi(&env, &mut store);
+            let instance = wasmer::Instance::new(&mut store, &self.module, &import_object)
+                .map_err(|_| Error::WasmInstantiationError)?;
+            let func: TyHandler = instance
+                .exports
+                .get_function("handler")
+                .map_err(|_| Error::WasmExportError)?
+                .typed(&store)
+                .map_err(|_| Error::WasmExportError)?;
+            Ok(HandlerInstance {
+                store,
+                func,
+                msg_ref,
+                instance,
+            })
+        }
+
+        /// Call the handler, which is stopped with [Error::ExtensionOutOfFuel] once it runs
+        /// more operators than `fuel`, or unbounded if `fuel` is None.
+        /// Fuel is ignored in browsers, which don't meter wasm.
+        /// An instance failing the call may be left in any state, so it's discarded.
+        pub fn call_with_fuel(
+            &self,
+            msg: BackendMessage,
+            fuel: Option<u64>,
+        ) -> Result<BackendMessage> {
+            let idle = self.idle.lock().map_err(|_| Error::Lock)?.pop();
+            let mut instance = match idle {
+                Some(instance) => instance,
+                None => self.instantiate()?,
+            };
+            let ret = instance.call(msg, fuel);
+            if ret.is_ok() {
+                let mut idle = self.idle.lock().map_err(|_| Error::Lock)?;
+                if idle.len() < MAX_IDLE_INSTANCES {
+                    idle.push(instance);
+                }
+            }
+            ret
+        }
+    }
+
+    impl super::ExtensionHandlerCaller for Handler {
+        fn call(&self, msg: BackendMessage) -> Result<BackendMessage> {
+            self.call_with_fuel(msg, None)
+        }
+    }
+
     /// wasm loarder, bytes can be WAT of *.wasm binary
     pub async fn load(bytes: impl AsRef<[u8]>) -> Result<Handler> {
-        let message = MaybeBackendMessage::default();
-        let env: FunctionEnv<MaybeBackendMessage> = message.clone().try_into()?;
-        let mut store = WASM_MEM
-            .write()
-            .map_err(|_| Error::WasmGlobalMemoryLockError)?;
+        let store = new_store();
         let module = wasmer::Module::new(&store, &bytes)
             .map_err(|e| Error::WasmCompileError(e.to_string()))?;
-        // The module doesn't import anything, so we create an empty import object.
-        let import_object = MaybeBackendMessage::land_abi(&env, &mut store);
-        let ins = wasmer::Instance::new(&mut store, &module, &import_object)
-            .map_err(|_| Error::WasmInstantiationError)?;
-        let exports: wasmer::Exports = ins.exports;
-        let handler: TyHandler = exports
-            .get_function("handler")
-            .map_err(|_| Error::WasmExportError)?
-            .typed(&store)
-            .map_err(|_| Error::WasmExportError)?;
-
-        Ok(Handler {
-            func: handler,
-            msg_ref: message,
-        })
+        let handler = Handler {
+            engine: store.engine().clone(),
+            module,
+            idle: Mutex::new(vec![]),
+        };
+        // Instantiate once, so that a module missing imports or handler is rejected here.
+        let instance = handler.instantiate()?;
+        handler.idle.lock().map_err(|_| Error::Lock)?.push(instance);
+        Ok(handler)
     }
 
     /// Load wasm from filesystem
@@ -548,8 +858,87 @@ pub mod loader {
 #[cfg(test)]
 mod test {
     use crate::backend::extension::loader::load;
+    use crate::backend::extension::ExtensionConfig;
     use crate::backend::extension::ExtensionHandlerCaller;
+    use crate::backend::extension::InFlight;
+    use crate::backend::extension::EXTENSION_FUEL_PER_MS;
     use crate::backend::types::BackendMessage;
+    use crate::error::Error;
+
+    #[test]
+    fn test_in_flight() {
+        let in_flight = InFlight::default();
+        let first = in_flight.enter(2).unwrap();
+        let second = in_flight.enter(2).unwrap();
+        assert!(in_flight.enter(2).is_none());
+        assert_eq!(in_flight.count(), 2);
+
+        // A slot is freed once an execution returns.
+        drop(first);
+        let _third = in_flight.enter(2).unwrap();
+        drop(second);
+        assert_eq!(in_flight.count(), 1);
+
+        let config: ExtensionConfig = serde_json::from_str(r#"{"paths": []}"#).unwrap();
+        assert_eq!(config, ExtensionConfig::default());
+        assert_eq!(config.fuel(), None);
+        let config: ExtensionConfig = serde_json::from_str(r#"{"timeout_ms": 1000}"#).unwrap();
+        assert_eq!(config.fuel(), Some(1000 * EXTENSION_FUEL_PER_MS));
+    }
+
+    #[cfg(feature = "node")]
+    #[tokio::test]
+    async fn test_out_of_fuel() {
+        let wasm = r#"
+(module
+  ;; fn handler(param: ExternRef) -> ExternRef, which never returns
+  (func $handler  (param externref) (result externref)
+      (loop $forever (br $forever))
+      (return (local.get 0))
+  )
+
+  (export "handler" (func $handler))
+)
+"#;
+        let handler = load(wasm.to_string()).await.unwrap();
+        let msg = BackendMessage::from((2u16, "hello extension".as_bytes()));
+        assert!(matches!(
+            handler.call_with_fuel(msg, Some(10_000)),
+            Err(Error::ExtensionOutOfFuel(10_000))
+        ));
+    }
+
+    #[cfg(feature = "node")]
+    #[tokio::test]
+    async fn test_concurrent_calls() {
+        let wasm = r#"
+(module
+  ;; fn handler(param: ExternRef) -> ExternRef
+  (func $handler  (param externref) (result externref)
+      (return (local.get 0))
+  )
+
+  (export "handler" (func $handler))
+)
+"#;
+        // Extensions are loaded by engines of their own, and each execution takes an
+        // instance of its own, so concurrent calls never swap their messages.
+        let handlers = [
+            std::sync::Arc::new(load(wasm.to_string()).await.unwrap()),
+            std::sync::Arc::new(load(wasm.to_string()).await.unwrap()),
+        ];
+        let calls = (0..32u16).map(|i| {
+            let handler = handlers[i as usize % 2].clone();
+            tokio::task::spawn_blocking(move || {
+                let msg = BackendMessage::from((i, format!("message {i}").as_bytes()));
+                (msg.clone(), handler.call_with_fuel(msg, Some(10_000)).unwrap())
+            })
+        });
+        for call in futures::future::join_all(calls).await {
+            let (msg, ret) = call.unwrap();
+            assert_eq!(ret, msg);
+        }
+    }
 
     #[tokio::test]
     async fn test_load_wasm() {
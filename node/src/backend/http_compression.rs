@@ -0,0 +1,211 @@
+#![warn(missing_docs)]
+//! Compression of http responses sent back to requesters, chosen by content.
+//!
+//! A [MessageType::HttpResponse](crate::backend::MessageType::HttpResponse) is always gzipped,
+//! whatever the body is, which costs cpu of both sides for images and video that are
+//! compressed already. Requesters able to decode an [EncodedHttpResponse] announce it by
+//! header [HTTP_COMPRESSION_HEADER], then a response is gzipped only if its body is
+//! compressible, which is text such as json, html and javascript, and long enough to pay off.
+//! Bodies encoded by upstream, media and archives are sent as they are.
+//!
+//! Only the browser client decodes an [EncodedHttpResponse]. Native requesters pass responses on
+//! as they are received, so they strip the header from requests they send, see
+//! [strip_compression_header].
+//!
+//! It's independent of the compression negotiated by swarm, which compresses every message
+//! to a peer, so the choice is made here even if swarm compresses nothing.
+//!
+//! A json api response of 100 records, about 13KiB, is gzipped to about 1.2KiB, saving over 90%
+//! of bytes sent over data channel, see `test_json_saving`. Sent as they are, a png of 4KiB
+//! saves the cpu of gzip at both sides, and the few bytes gzip would add.
+
+use crate::backend::types::EncodedHttpResponse;
+use crate::backend::types::HttpResponse;
+use crate::backend::types::ResponseEncoding;
+use crate::error::Result;
+use crate::prelude::rings_rpc::types::HttpRequest;
+
+/// Header of request announcing that requester decodes an [EncodedHttpResponse].
+/// It's not forwarded to upstream.
+pub const HTTP_COMPRESSION_HEADER: &str = "x-rings-compression";
+
+/// Bodies shorter than it are not compressed, since gzip saves little of them.
+pub const MIN_COMPRESS_LEN: usize = 256;
+
+/// Level of gzip of [EncodedHttpResponse], which is the default of zlib. Higher levels cost
+/// the serving node much more cpu for few bytes saved.
+pub const HTTP_COMPRESSION_LEVEL: u8 = 6;
+
+/// Check if request announces decoding an [EncodedHttpResponse], by [HTTP_COMPRESSION_HEADER].
+pub fn wants_compression(request: &HttpRequest) -> bool {
+    request
+        .headers
+        .keys()
+        .any(|k| k.eq_ignore_ascii_case(HTTP_COMPRESSION_HEADER))
+}
+
+/// Remove [HTTP_COMPRESSION_HEADER] from request, for requesters which can't decode an
+/// [EncodedHttpResponse], so that they get a
+/// [MessageType::HttpResponse](crate::backend::MessageType::HttpResponse).
+pub fn strip_compression_header(mut request: HttpRequest) -> HttpRequest {
+    request
+        .headers
+        .retain(|k, _| !k.eq_ignore_ascii_case(HTTP_COMPRESSION_HEADER));
+    request
+}
+
+fn header<'a>(response: &'a HttpResponse, name: &str) -> Option<&'a str> {
+    response
+        .headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+/// Check if content of type `content_type` is worth compressing, which is text-like content.
+/// Images except svg, video, audio, fonts and archives are compressed already.
+pub fn is_compressible(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let (kind, subtype) = mime.split_once('/').unwrap_or((mime.as_str(), ""));
+    match kind {
+        "text" => true,
+        "image" => matches!(subtype, "svg+xml" | "bmp" | "x-icon"),
+        "application" => {
+            matches!(
+                subtype,
+                "json"
+                    | "javascript"
+                    | "ecmascript"
+                    | "xml"
+                    | "wasm"
+                    | "graphql"
+                    | "x-ndjson"
+                    | "yaml"
+                    | "toml"
+                    | "x-www-form-urlencoded"
+            ) || subtype.ends_with("+json")
+                || subtype.ends_with("+xml")
+        }
+        _ => false,
+    }
+}
+
+/// Choose encoding of response by its body. Bodies without content type are compressed,
+/// as every response was before.
+pub fn choose_encoding(response: &HttpResponse) -> ResponseEncoding {
+    let len = response.body.as_ref().map(|b| b.len()).unwrap_or(0);
+    if len < MIN_COMPRESS_LEN {
+        return ResponseEncoding::Identity;
+    }
+    if header(response, "content-encoding").is_some_and(|e| !e.eq_ignore_ascii_case("identity")) {
+        return ResponseEncoding::Identity;
+    }
+    match header(response, "content-type") {
+        Some(content_type) if !is_compressible(content_type) => ResponseEncoding::Identity,
+        _ => ResponseEncoding::Gzip,
+    }
+}
+
+/// Encode response by the encoding chosen for its body.
+pub fn encode_response(response: &HttpResponse) -> Result<EncodedHttpResponse> {
+    EncodedHttpResponse::encode(response, choose_encoding(response))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::backend::types::BackendMessage;
+    use crate::backend::MessageType;
+    use crate::prelude::rings_core::consts::MAX_DECOMPRESSED_SIZE;
+
+    fn response(content_type: &str, body: Vec<u8>) -> HttpResponse {
+        HttpResponse {
+            status: 200,
+            headers: HashMap::from([("Content-Type".to_string(), content_type.to_string())]),
+            body: Some(Bytes::from(body)),
+        }
+    }
+
+    #[test]
+    fn test_json_saving() {
+        let records: Vec<String> = (0..100)
+            .map(|i| {
+                format!(
+                    concat!(
+                        r#"{{"id":{i},"name":"user-{i}","email":"user-{i}@example.com","#,
+                        r#""active":{active},"roles":["reader","writer"],"#,
+                        r#""created_at":"2023-06-{day:02}T08:00:00Z"}}"#,
+                    ),
+                    i = i,
+                    active = i % 3 != 0,
+                    day = i % 28 + 1
+                )
+            })
+            .collect();
+        let body = format!(r#"{{"data":[{}],"page":1,"total":100}}"#, records.join(","));
+        let resp = response("application/json; charset=utf-8", body.into_bytes());
+
+        let encoded = encode_response(&resp).unwrap();
+        assert_eq!(encoded.encoding, ResponseEncoding::Gzip);
+        let raw = bincode::serialize(&resp).unwrap().len();
+        assert!(encoded.data.len() * 10 < raw);
+
+        let msg = BackendMessage::try_from((MessageType::EncodedHttpResponse, &encoded)).unwrap();
+        let received = EncodedHttpResponse::try_from(&msg).unwrap();
+        assert_eq!(received.decode(MAX_DECOMPRESSED_SIZE).unwrap(), resp);
+    }
+
+    #[test]
+    fn test_choose_encoding() {
+        let image = response("image/png", vec![7u8; 4096]);
+        let encoded = encode_response(&image).unwrap();
+        assert_eq!(encoded.encoding, ResponseEncoding::Identity);
+        assert_eq!(encoded.decode(MAX_DECOMPRESSED_SIZE).unwrap(), image);
+
+        assert_eq!(
+            choose_encoding(&response("text/html", vec![b'a'; 64])),
+            ResponseEncoding::Identity
+        );
+        assert_eq!(
+            choose_encoding(&response("image/svg+xml", vec![b'a'; 4096])),
+            ResponseEncoding::Gzip
+        );
+        let mut gzipped = response("application/json", vec![b'a'; 4096]);
+        gzipped
+            .headers
+            .insert("content-encoding".to_string(), "gzip".to_string());
+        assert_eq!(choose_encoding(&gzipped), ResponseEncoding::Identity);
+
+        assert!(is_compressible("application/problem+json"));
+        assert!(!is_compressible("video/mp4"));
+        assert!(!is_compressible("application/zip"));
+    }
+
+    #[test]
+    fn test_strip_compression_header() {
+        let request = HttpRequest {
+            name: "api".to_string(),
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            timeout: Default::default(),
+            headers: HashMap::from([
+                ("X-Rings-Compression".to_string(), "gzip".to_string()),
+                ("Accept".to_string(), "application/json".to_string()),
+            ]),
+            body: None,
+        };
+        assert!(wants_compression(&request));
+        let request = strip_compression_header(request);
+        assert!(!wants_compression(&request));
+        assert_eq!(request.headers.len(), 1);
+    }
+}
@@ -1,6 +1,7 @@
 pub mod types;
 
 pub mod extension;
+pub mod http_compression;
 #[cfg(feature = "node")]
 pub mod service;
 pub use types::MessageEndpoint;
@@ -0,0 +1,421 @@
+#![warn(missing_docs)]
+//! Access log of hidden services, like the access log of a web server.
+//!
+//! An [AccessLogEntry] is recorded for each HTTP request handled by
+//! [HttpServer](crate::backend::service::http_server::HttpServer), and for each tunnel of
+//! [TcpServer](crate::backend::service::tcp_server::TcpServer) when it's refused or closed.
+//! Unlike metrics, which aggregate traffic, and tunnel capture, which records raw bytes, it
+//! tells which peer accessed which service, when, and how it went.
+//!
+//! Requests may carry secrets, so they are summarized by [RedactionRules] of each HTTP
+//! service. Bodies are redacted by default. Tunnels are summarized by their id only, nothing
+//! flowing through them is logged.
+//!
+//! Entries are written to file by a background thread, so that services never wait on disk.
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::Instant;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::backend::service::proxy::TunnelId;
+use crate::error::Error;
+use crate::error::Result;
+use crate::prelude::rings_core::dht::Did;
+use crate::prelude::rings_rpc::types::HttpRequest;
+
+/// Replacement of redacted values.
+pub const REDACTED: &str = "[redacted]";
+
+/// Max length of a body logged, longer ones are truncated.
+pub const MAX_LOGGED_BODY_LEN: usize = 1024;
+
+/// Target of tracing events emitted when no file is configured.
+pub const ACCESS_LOG_TARGET: &str = "access_log";
+
+/// Max number of entries waiting to be written to file, new ones are dropped once over.
+pub const ACCESS_LOG_QUEUE_SIZE: usize = 1024;
+
+/// Where entries are written.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct AccessLogConfig {
+    /// file which entries are appended to as json lines, entries are emitted as tracing
+    /// events with target [ACCESS_LOG_TARGET] if not provided
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+}
+
+fn default_redacted_headers() -> Vec<String> {
+    [
+        "authorization",
+        "proxy-authorization",
+        "cookie",
+        "set-cookie",
+    ]
+    .iter()
+    .map(|h| h.to_string())
+    .collect()
+}
+
+/// How requests of a service are summarized in the access log.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct RedactionRules {
+    /// headers whose values are redacted, case-insensitive, credentials and cookies
+    /// by default
+    pub headers: Vec<String>,
+    /// redact the query of path
+    pub query: bool,
+    /// redact the whole path
+    pub path: bool,
+    /// redact bodies, only their length is logged, true by default
+    pub body: bool,
+}
+
+impl Default for RedactionRules {
+    fn default() -> Self {
+        Self {
+            headers: default_redacted_headers(),
+            query: false,
+            path: false,
+            body: true,
+        }
+    }
+}
+
+impl RedactionRules {
+    /// Redact path of request.
+    pub fn redact_path(&self, path: &str) -> String {
+        if self.path {
+            return REDACTED.to_string();
+        }
+        match path.split_once('?') {
+            Some((path, _)) if self.query => format!("{}?{}", path, REDACTED),
+            _ => path.to_string(),
+        }
+    }
+
+    /// Redact values of headers, the names are kept.
+    pub fn redact_headers(&self, headers: &HashMap<String, String>) -> BTreeMap<String, String> {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                if self.headers.iter().any(|h| h.eq_ignore_ascii_case(name)) {
+                    (name.clone(), REDACTED.to_string())
+                } else {
+                    (name.clone(), value.clone())
+                }
+            })
+            .collect()
+    }
+
+    /// Redact body, or keep at most [MAX_LOGGED_BODY_LEN] bytes of it as text.
+    pub fn redact_body(&self, body: &[u8]) -> String {
+        if self.body {
+            return format!("{} {} bytes", REDACTED, body.len());
+        }
+        let logged = &body[..body.len().min(MAX_LOGGED_BODY_LEN)];
+        let mut text = String::from_utf8_lossy(logged).into_owned();
+        if body.len() > logged.len() {
+            text.push_str("...");
+        }
+        text
+    }
+}
+
+/// An entry of access log.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct AccessLogEntry {
+    /// when the request was received, in milliseconds since epoch
+    pub timestamp_ms: u64,
+    /// did of requester
+    pub peer: Did,
+    /// name of service
+    pub service: String,
+    /// summary of request, such as `GET /index.html`, or `tunnel <tid>`
+    pub request: String,
+    /// headers of request, redacted
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// body of request, redacted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// status of response, such as `200`, or the error handling request
+    pub status: String,
+    /// size of response body in bytes, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<usize>,
+    /// time taken to handle request, or lifetime of tunnel, in milliseconds
+    pub duration_ms: u64,
+}
+
+impl AccessLogEntry {
+    /// Summarize an HTTP request by rules of its service. Response is filled by
+    /// [AccessLogEntry::respond].
+    pub fn http(
+        timestamp_ms: u64,
+        peer: Did,
+        service: &str,
+        request: &HttpRequest,
+        rules: &RedactionRules,
+    ) -> Self {
+        Self {
+            timestamp_ms,
+            peer,
+            service: service.to_string(),
+            request: format!("{} {}", request.method, rules.redact_path(&request.path)),
+            headers: rules.redact_headers(&request.headers),
+            body: request.body.as_ref().map(|b| rules.redact_body(b)),
+            status: String::new(),
+            size: None,
+            duration_ms: 0,
+        }
+    }
+
+    /// Summarize a tunnel to TCP service. Response is filled by [AccessLogEntry::respond].
+    pub fn tunnel(timestamp_ms: u64, peer: Did, service: &str, tid: TunnelId) -> Self {
+        Self {
+            timestamp_ms,
+            peer,
+            service: service.to_string(),
+            request: format!("tunnel {}", tid),
+            headers: BTreeMap::new(),
+            body: None,
+            status: String::new(),
+            size: None,
+            duration_ms: 0,
+        }
+    }
+
+    /// Fill the response of entry.
+    pub fn respond(mut self, status: impl ToString, size: Option<usize>, duration_ms: u64) -> Self {
+        self.status = status.to_string();
+        self.size = size;
+        self.duration_ms = duration_ms;
+        self
+    }
+}
+
+enum AccessLogCommand {
+    Write(String),
+    Flush(mpsc::Sender<()>),
+}
+
+enum AccessLogSink {
+    Tracing,
+    File(mpsc::SyncSender<AccessLogCommand>),
+}
+
+/// AccessLog writes entries to the sink of [AccessLogConfig]. Writing to file is stopped
+/// on the first failure, so that a full disk never breaks services.
+#[derive(Clone)]
+pub struct AccessLog(Arc<AccessLogSink>);
+
+impl AccessLog {
+    /// Open the sink of config. A file is written by a background thread, which exits once
+    /// every clone of AccessLog is dropped.
+    pub fn open(config: &AccessLogConfig) -> Result<Self> {
+        let sink = match config.path.as_ref() {
+            Some(path) => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| Error::OpenFileError(e.to_string()))?;
+                let (sender, receiver) = mpsc::sync_channel(ACCESS_LOG_QUEUE_SIZE);
+                let path = path.clone();
+                std::thread::Builder::new()
+                    .name("access-log".to_string())
+                    .spawn(move || write_file(path, file, receiver))
+                    .map_err(|e| Error::OpenFileError(e.to_string()))?;
+                AccessLogSink::File(sender)
+            }
+            None => AccessLogSink::Tracing,
+        };
+        Ok(Self(Arc::new(sink)))
+    }
+
+    /// Record an entry, which never blocks. It's dropped if too many entries are waiting to
+    /// be written, see [ACCESS_LOG_QUEUE_SIZE].
+    pub fn record(&self, entry: &AccessLogEntry) {
+        let line = match serde_json::to_string(entry) {
+            Ok(line) => line,
+            Err(e) => {
+                tracing::error!("Serialize access log entry failed: {}", e);
+                return;
+            }
+        };
+        match self.0.as_ref() {
+            AccessLogSink::Tracing => tracing::info!(target: ACCESS_LOG_TARGET, "{}", line),
+            AccessLogSink::File(sender) => match sender.try_send(AccessLogCommand::Write(line)) {
+                Err(mpsc::TrySendError::Full(_)) => {
+                    tracing::warn!("Access log entry dropped, too many waiting to be written")
+                }
+                // Writing is stopped.
+                Err(mpsc::TrySendError::Disconnected(_)) | Ok(()) => {}
+            },
+        }
+    }
+
+    /// Block until entries recorded are written to file. It returns right away if writing
+    /// is stopped or no file is configured.
+    pub fn flush(&self) {
+        let AccessLogSink::File(sender) = self.0.as_ref() else {
+            return;
+        };
+        let (done, flushed) = mpsc::channel();
+        if sender.send(AccessLogCommand::Flush(done)).is_ok() {
+            let _ = flushed.recv();
+        }
+    }
+
+    /// Open entry of a tunnel `started` at, which is recorded with its lifetime once the guard
+    /// is dropped.
+    pub fn open_tunnel(&self, entry: AccessLogEntry, started: Instant) -> TunnelLogGuard {
+        TunnelLogGuard {
+            access_log: self.clone(),
+            entry: Some(entry),
+            started,
+        }
+    }
+}
+
+/// Write lines to file, buffered and flushed once no more line is waiting.
+fn write_file(path: PathBuf, file: File, receiver: mpsc::Receiver<AccessLogCommand>) {
+    let mut writer = BufWriter::new(file);
+    while let Ok(command) = receiver.recv() {
+        let mut written = Ok(());
+        let mut flushing = vec![];
+        for command in std::iter::once(command).chain(receiver.try_iter()) {
+            match command {
+                AccessLogCommand::Write(line) => written = writeln!(writer, "{}", line),
+                AccessLogCommand::Flush(done) => flushing.push(done),
+            }
+            if written.is_err() {
+                break;
+            }
+        }
+        let written = written.and_then(|_| writer.flush());
+        for done in flushing {
+            let _ = done.send(());
+        }
+        if let Err(e) = written {
+            tracing::error!("Access log {:?} stopped: {}", path, e);
+            return;
+        }
+    }
+}
+
+/// Entry of a tunnel open, see [AccessLog::open_tunnel]. It's held by the tunnel, so that the
+/// tunnel is recorded as closed however it's torn down.
+pub struct TunnelLogGuard {
+    access_log: AccessLog,
+    entry: Option<AccessLogEntry>,
+    started: Instant,
+}
+
+impl Drop for TunnelLogGuard {
+    fn drop(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            let duration_ms = self.started.elapsed().as_millis() as u64;
+            self.access_log
+                .record(&entry.respond("closed", None, duration_ms));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::rings_core::prelude::uuid::Uuid;
+
+    fn request(path: &str, body: Option<&[u8]>) -> HttpRequest {
+        HttpRequest {
+            name: "api".to_string(),
+            method: "POST".to_string(),
+            path: path.to_string(),
+            timeout: Default::default(),
+            headers: [
+                ("Authorization", "Bearer secret"),
+                ("Content-Type", "application/json"),
+            ]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+            body: body.map(|b| b.to_vec()),
+        }
+    }
+
+    #[test]
+    fn test_redaction_rules() {
+        let peer: Did = 1u32.into();
+        let req = request("/login?token=secret", Some(b"password=secret"));
+
+        let entry = AccessLogEntry::http(0, peer, "api", &req, &RedactionRules::default());
+        assert_eq!(entry.request, "POST /login?token=secret");
+        assert_eq!(entry.headers["Authorization"], REDACTED);
+        assert_eq!(entry.headers["Content-Type"], "application/json");
+        assert_eq!(entry.body.unwrap(), "[redacted] 15 bytes");
+
+        let rules: RedactionRules =
+            serde_json::from_str(r#"{"query": true, "body": false, "headers": []}"#).unwrap();
+        let entry = AccessLogEntry::http(0, peer, "api", &req, &rules);
+        assert_eq!(entry.request, "POST /login?[redacted]");
+        assert_eq!(entry.headers["Authorization"], "Bearer secret");
+        assert_eq!(entry.body.unwrap(), "password=secret");
+
+        let rules = RedactionRules {
+            path: true,
+            body: false,
+            ..Default::default()
+        };
+        let long = vec![b'a'; MAX_LOGGED_BODY_LEN + 1];
+        let entry = AccessLogEntry::http(0, peer, "api", &request("/a", Some(&long)), &rules);
+        assert_eq!(entry.request, "POST [redacted]");
+        assert_eq!(entry.body.unwrap().len(), MAX_LOGGED_BODY_LEN + 3);
+    }
+
+    #[test]
+    fn test_access_log_file() {
+        let path = std::env::temp_dir().join(format!("rings-access-{}.log", Uuid::new_v4()));
+        let log = AccessLog::open(&AccessLogConfig {
+            path: Some(path.clone()),
+        })
+        .unwrap();
+
+        let peer: Did = 1u32.into();
+        let rules = RedactionRules::default();
+        let entry = AccessLogEntry::http(42, peer, "api", &request("/", None), &rules).respond(
+            200,
+            Some(5),
+            7,
+        );
+        log.record(&entry);
+        log.record(&entry.clone().respond("InvalidService", None, 1));
+        let tunnel = AccessLogEntry::tunnel(42, peer, "ssh", Uuid::new_v4());
+        drop(log.open_tunnel(tunnel.clone(), Instant::now()));
+        log.flush();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let entries: Vec<AccessLogEntry> = content
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0], entry);
+        assert_eq!(entries[1].status, "InvalidService");
+        assert_eq!(entries[2].request, tunnel.request);
+        assert_eq!(entries[2].status, "closed");
+        assert!(!content.contains("Bearer"));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
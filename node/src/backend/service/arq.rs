@@ -0,0 +1,581 @@
+#![warn(missing_docs)]
+//! Acknowledged chunks, so that a lost chunk is resent alone instead of the whole message.
+//!
+//! [ChunkArq::send] sends chunks of a message in bursts, at most
+//! [ArqConfig::window] chunks unacknowledged at a time. The last chunk of each burst asks
+//! receiver to report chunks received by a [ChunkAck], which is also sent once the message
+//! is completed. A chunk missing from an ack while a chunk sent after it is received is
+//! considered lost, and is resent in the next burst. A chunk neither acknowledged nor
+//! reported missing within [ArqConfig::retransmit_timeout_ms] is resent too, such as when the
+//! last chunk of a burst is lost.
+//!
+//! Segments of a [transfer](crate::backend::service::transfer) are sent this way. Chunks sent
+//! by [Processor::send_backend_message](crate::processor::Processor) are never acknowledged,
+//! lost ones are only recovered by resending the whole message.
+//!
+//! A receiver only accepts a message of at most [ArqConfig::max_chunks] chunks, so that an ack
+//! never takes more than a bitmap of that many bits.
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::time::Duration;
+
+use bincode::Options;
+use bytes::Bytes;
+use serde::Deserialize;
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::backend::service::Backend;
+use crate::backend::types::encode_custom_messages;
+use crate::backend::types::encode_custom_messages_with_mtu;
+use crate::backend::types::BackendMessage;
+use crate::backend::types::MessageType;
+use crate::backend::types::CUSTOM_MESSAGE_FLAG_ACKED_CHUNK;
+use crate::backend::types::CUSTOM_MESSAGE_FLAG_ACK_REQUEST;
+use crate::backend::types::CUSTOM_MESSAGE_HEADER_LEN;
+use crate::consts::BACKEND_MTU;
+use crate::error::Error;
+use crate::error::Result;
+use crate::prelude::rings_core::chunk::Chunk;
+use crate::prelude::rings_core::prelude::dashmap::DashMap;
+use crate::prelude::rings_core::prelude::uuid::Uuid;
+use crate::prelude::rings_core::utils::get_epoch_ms;
+use crate::prelude::*;
+
+/// Max number of completed messages remembered by receiver, whose chunks resent are
+/// acknowledged again instead of starting the message over.
+pub const MAX_COMPLETED_MESSAGES: usize = 256;
+
+/// Chunks of a message received, carried by [BackendMessage] of [MessageType::ChunkAck].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ChunkAck {
+    /// id of message, see [ChunkMeta](crate::prelude::rings_core::chunk::ChunkMeta)
+    pub message_id: Uuid,
+    /// number of chunks of message
+    pub total: usize,
+    /// bit `i % 8` of byte `i / 8` is set if chunk `i` is received
+    pub received_bitmap: Vec<u8>,
+}
+
+impl ChunkAck {
+    /// Ack of a message with chunks at `missing` not received yet.
+    /// `total` should be checked by [ChunkArq::check] if it's taken from a chunk received.
+    pub fn new(message_id: Uuid, total: usize, missing: &[usize]) -> Self {
+        let mut received_bitmap = vec![0u8; (total + 7) / 8];
+        for i in 0..total {
+            received_bitmap[i / 8] |= 1 << (i % 8);
+        }
+        for &i in missing.iter().filter(|&&i| i < total) {
+            received_bitmap[i / 8] &= !(1 << (i % 8));
+        }
+        Self {
+            message_id,
+            total,
+            received_bitmap,
+        }
+    }
+
+    /// Ack of a message with all chunks received.
+    pub fn completed(message_id: Uuid, total: usize) -> Self {
+        Self::new(message_id, total, &[])
+    }
+
+    /// Check if chunk `i` is received.
+    pub fn is_received(&self, i: usize) -> bool {
+        i < self.total
+            && self
+                .received_bitmap
+                .get(i / 8)
+                .map(|b| b & (1 << (i % 8)) != 0)
+                .unwrap_or(false)
+    }
+
+    /// Check if all chunks are received.
+    pub fn is_completed(&self) -> bool {
+        (0..self.total).all(|i| self.is_received(i))
+    }
+}
+
+impl TryFrom<&BackendMessage> for ChunkAck {
+    type Error = Error;
+
+    /// Parse a received [BackendMessage], which should be of [MessageType::ChunkAck].
+    /// Reading is limited to the length of input.
+    fn try_from(msg: &BackendMessage) -> Result<Self> {
+        if !matches!(MessageType::from(msg.message_type), MessageType::ChunkAck) {
+            return Err(Error::InvalidMessage);
+        }
+        bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .allow_trailing_bytes()
+            .with_limit(msg.data.len() as u64)
+            .deserialize(&msg.data)
+            .map_err(|_| Error::DecodeError)
+    }
+}
+
+/// Config of acknowledged chunks, see [arq](self).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct ArqConfig {
+    /// max number of chunks sent and not acknowledged yet
+    pub window: usize,
+    /// milliseconds to wait for an ack before resending chunks
+    pub retransmit_timeout_ms: u64,
+    /// max number of times a chunk is resent, before sending fails
+    pub max_retransmits: u32,
+    /// max number of chunks of a message, a chunk of a longer message is rejected by receiver
+    pub max_chunks: usize,
+}
+
+impl Default for ArqConfig {
+    fn default() -> Self {
+        Self {
+            window: 64,
+            retransmit_timeout_ms: 3000,
+            max_retransmits: 5,
+            max_chunks: 1024,
+        }
+    }
+}
+
+/// State of a chunk sent.
+#[derive(Debug, Clone, Copy, Default)]
+struct Sent {
+    /// time of the last send
+    at: u128,
+    /// sequence of the last send among all sends of message
+    seq: u64,
+    /// number of sends
+    sends: u32,
+    acked: bool,
+    lost: bool,
+}
+
+/// Chunks of a message on sender, which decides what to send next by acks received.
+#[derive(Debug)]
+pub struct OutgoingChunks {
+    id: Uuid,
+    config: ArqConfig,
+    sent: Vec<Option<Sent>>,
+    seq: u64,
+    retransmits: usize,
+}
+
+impl OutgoingChunks {
+    /// Chunks of message `id` with `total` chunks, none sent yet.
+    pub fn new(id: Uuid, total: usize, config: ArqConfig) -> Self {
+        Self {
+            id,
+            config: ArqConfig {
+                window: config.window.max(1),
+                ..config
+            },
+            sent: vec![None; total],
+            seq: 0,
+            retransmits: 0,
+        }
+    }
+
+    /// Positions of chunks to send at `now`, which are recorded as sent. Lost and timed out
+    /// chunks are resent first, then new chunks are sent as long as the window allows.
+    /// Fails if a chunk is resent too many times, see [ArqConfig::max_retransmits].
+    pub fn poll(&mut self, now: u128) -> Result<Vec<usize>> {
+        let timeout = self.config.retransmit_timeout_ms as u128;
+        let mut batch = vec![];
+        let mut in_flight = 0;
+        for (i, sent) in self.sent.iter().enumerate() {
+            let Some(sent) = sent else {
+                continue;
+            };
+            if sent.acked {
+                continue;
+            }
+            in_flight += 1;
+            if sent.lost || now.saturating_sub(sent.at) >= timeout {
+                if sent.sends > self.config.max_retransmits {
+                    return Err(Error::ChunksUnacknowledged(self.id.to_string()));
+                }
+                batch.push(i);
+            }
+        }
+        self.retransmits += batch.len();
+        let fresh = self
+            .sent
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.is_none())
+            .map(|(i, _)| i)
+            .take(self.config.window.saturating_sub(in_flight));
+        batch.extend(fresh);
+
+        for &i in &batch {
+            self.seq += 1;
+            let sent = self.sent[i].get_or_insert_with(Sent::default);
+            sent.at = now;
+            sent.seq = self.seq;
+            sent.sends += 1;
+            sent.lost = false;
+        }
+        Ok(batch)
+    }
+
+    /// Apply an ack. A chunk missing from it is lost if a chunk sent later is received.
+    pub fn on_ack(&mut self, ack: &ChunkAck) {
+        if ack.message_id != self.id || ack.total != self.sent.len() {
+            return;
+        }
+        let mut latest = 0;
+        for (i, sent) in self.sent.iter_mut().enumerate() {
+            if let Some(sent) = sent {
+                if ack.is_received(i) {
+                    sent.acked = true;
+                    latest = latest.max(sent.seq);
+                }
+            }
+        }
+        for sent in self.sent.iter_mut().flatten() {
+            if !sent.acked && sent.seq < latest {
+                sent.lost = true;
+            }
+        }
+    }
+
+    /// Check if all chunks are acknowledged.
+    pub fn is_completed(&self) -> bool {
+        self.sent.iter().all(|s| matches!(s, Some(s) if s.acked))
+    }
+
+    /// Number of chunks resent.
+    pub fn retransmits(&self) -> usize {
+        self.retransmits
+    }
+}
+
+/// Acks waited by senders, and messages completed on receiver.
+#[derive(Debug, Default)]
+pub struct ChunkArq {
+    config: ArqConfig,
+    waiting: DashMap<Uuid, (Did, mpsc::UnboundedSender<ChunkAck>)>,
+    completed: Mutex<VecDeque<Uuid>>,
+}
+
+impl ChunkArq {
+    fn lock_completed(&self) -> MutexGuard<'_, VecDeque<Uuid>> {
+        self.completed.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Create with config.
+    pub fn new(config: ArqConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Check position of a chunk received, before its message is acknowledged. A message of no
+    /// chunk or of more than [ArqConfig::max_chunks] chunks is rejected, and so is a chunk out
+    /// of its message.
+    pub fn check(&self, chunk: &Chunk) -> Result<()> {
+        let [index, total] = chunk.chunk;
+        if total == 0 || total > self.config.max_chunks || index >= total {
+            return Err(Error::InvalidMessage);
+        }
+        Ok(())
+    }
+
+    /// Deliver an ack sent by `did`, returns false if no sender waits for it.
+    pub fn deliver(&self, did: Did, ack: ChunkAck) -> bool {
+        match self.waiting.get(&ack.message_id) {
+            Some(entry) if entry.0 == did => entry.1.send(ack).is_ok(),
+            _ => false,
+        }
+    }
+
+    /// Remember a message completed on receiver.
+    fn complete(&self, id: Uuid) {
+        let mut completed = self.lock_completed();
+        if completed.len() >= MAX_COMPLETED_MESSAGES {
+            completed.pop_front();
+        }
+        completed.push_back(id);
+    }
+
+    /// Check if a message is completed on receiver recently.
+    fn is_completed(&self, id: Uuid) -> bool {
+        self.lock_completed().contains(&id)
+    }
+
+    /// Send a message to peer, chunks of which are acknowledged by peer and resent
+    /// selectively once lost, see [arq](self). Returns once every chunk is acknowledged.
+    /// A message fitting in one chunk is sent as is, without waiting for any ack, and a message
+    /// of more than [ArqConfig::max_chunks] chunks is rejected.
+    ///
+    /// Acks are delivered by the callback of swarm, so this should never be awaited by a handler
+    /// of messages from `destination`.
+    pub async fn send(&self, swarm: &Swarm, destination: Did, msg: BackendMessage) -> Result<()> {
+        let (next_hop, mtu) = swarm
+            .next_hop_mtu(destination)
+            .map_err(Error::SendMessage)?;
+        let mut frames = encode_custom_messages_with_mtu(msg, mtu)?;
+        if frames.len() == 1 {
+            return Self::send_custom(swarm, destination, next_hop, &frames[0]).await;
+        }
+        if frames.len() > self.config.max_chunks {
+            return Err(Error::MessageTooLarge(self.config.max_chunks * mtu));
+        }
+
+        let id = Chunk::from_bincode(&frames[0][CUSTOM_MESSAGE_HEADER_LEN..])
+            .map_err(|_| Error::EncodeError)?
+            .meta
+            .id;
+        let (sender, mut acks) = mpsc::unbounded_channel();
+        self.waiting.insert(id, (destination, sender));
+        let mut outgoing = OutgoingChunks::new(id, frames.len(), self.config);
+        let result = self
+            .send_chunks(
+                swarm,
+                destination,
+                next_hop,
+                &mut outgoing,
+                &mut frames,
+                &mut acks,
+            )
+            .await;
+        self.waiting.remove(&id);
+        result
+    }
+
+    async fn send_chunks(
+        &self,
+        swarm: &Swarm,
+        destination: Did,
+        next_hop: Did,
+        outgoing: &mut OutgoingChunks,
+        frames: &mut [Vec<u8>],
+        acks: &mut mpsc::UnboundedReceiver<ChunkAck>,
+    ) -> Result<()> {
+        let timeout = Duration::from_millis(self.config.retransmit_timeout_ms);
+        loop {
+            let batch = outgoing.poll(get_epoch_ms())?;
+            for (n, &i) in batch.iter().enumerate() {
+                frames[i][0] = if n + 1 == batch.len() {
+                    CUSTOM_MESSAGE_FLAG_ACK_REQUEST
+                } else {
+                    CUSTOM_MESSAGE_FLAG_ACKED_CHUNK
+                };
+                Self::send_custom(swarm, destination, next_hop, &frames[i]).await?;
+            }
+            if let Ok(Some(ack)) = tokio::time::timeout(timeout, acks.recv()).await {
+                outgoing.on_ack(&ack);
+                // Acks queued meanwhile are applied together.
+                while let Ok(ack) = acks.try_recv() {
+                    outgoing.on_ack(&ack);
+                }
+            }
+            if outgoing.is_completed() {
+                if outgoing.retransmits() > 0 {
+                    tracing::debug!(
+                        "{} of {} chunks resent to {}",
+                        outgoing.retransmits(),
+                        frames.len(),
+                        destination
+                    );
+                }
+                return Ok(());
+            }
+        }
+    }
+
+    async fn send_custom(
+        swarm: &Swarm,
+        destination: Did,
+        next_hop: Did,
+        data: &[u8],
+    ) -> Result<()> {
+        let msg = Message::custom(data).map_err(Error::SendMessage)?;
+        let result = swarm.send_message_by_hop(msg, destination, next_hop).await;
+        swarm.record_chunk_sent(next_hop, data.len(), result.is_ok());
+        result.map(|_| ()).map_err(Error::SendMessage)
+    }
+}
+
+impl Backend {
+    /// Reassemble an acknowledged chunk, and report chunks received to sender once asked
+    /// or the message is completed. Chunks of a message completed are only acknowledged.
+    /// A chunk failing [ChunkArq::check] is rejected before anything is buffered or sent back.
+    pub(crate) async fn handle_acked_chunk(
+        &self,
+        payload: &MessagePayload,
+        data: &[u8],
+        ack_requested: bool,
+    ) -> Result<Option<Bytes>> {
+        let chunk = Chunk::from_bincode(data).map_err(|_| Error::DecodeError)?;
+        self.arq.check(&chunk)?;
+        let (id, total) = (chunk.meta.id, chunk.chunk[1]);
+        if self.arq.is_completed(id) {
+            self.send_chunk_ack(payload, ChunkAck::completed(id, total))
+                .await;
+            return Ok(None);
+        }
+
+        let data = self.handle_chunk(payload, chunk).await?;
+        let ack = match data {
+            Some(_) => {
+                self.arq.complete(id);
+                Some(ChunkAck::completed(id, total))
+            }
+            None if ack_requested => {
+                let missing = self.chunk_pool.lock().await.missing(id);
+                let missing = missing.unwrap_or_else(|| (0..total).collect());
+                Some(ChunkAck::new(id, total, &missing))
+            }
+            None => None,
+        };
+        if let Some(ack) = ack {
+            self.send_chunk_ack(payload, ack).await;
+        }
+        Ok(data)
+    }
+
+    /// Send an ack back to sender. A failed ack is only logged, since sender resends chunks
+    /// not acknowledged in time.
+    async fn send_chunk_ack(&self, payload: &MessagePayload, ack: ChunkAck) {
+        let evs = BackendMessage::try_from((MessageType::ChunkAck, &ack))
+            .and_then(encode_custom_messages::<BACKEND_MTU>)
+            .and_then(|frames| {
+                frames
+                    .into_iter()
+                    .map(|data| {
+                        Ok(MessageHandlerEvent::SendReportMessage(
+                            payload.clone(),
+                            Message::custom(&data).map_err(|_| Error::InvalidMessage)?,
+                        ))
+                    })
+                    .collect::<Result<Vec<_>>>()
+            });
+        let result = match evs {
+            Ok(evs) => self
+                .swarm
+                .handle_message_handler_events(&evs)
+                .await
+                .map_err(Error::SendMessage),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            tracing::warn!("send ack of message {} failed: {}", ack.message_id, e);
+        }
+    }
+
+    /// Deliver an ack to the sender waiting for it.
+    pub(crate) fn handle_chunk_ack(&self, payload: &MessagePayload, msg: &BackendMessage) {
+        match ChunkAck::try_from(msg) {
+            Ok(ack) => {
+                if !self.arq.deliver(payload.transaction.signer(), ack) {
+                    tracing::debug!("Drop chunk ack of message not waited");
+                }
+            }
+            Err(e) => tracing::error!("decode chunk ack failed: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::prelude::rings_core::chunk::ChunkList;
+
+    #[test]
+    fn test_chunk_ack() {
+        let id = Uuid::new_v4();
+        let ack = ChunkAck::new(id, 10, &[2, 5, 12]);
+        assert_eq!(ack.received_bitmap.len(), 2);
+        assert!(!ack.is_received(2) && !ack.is_received(5) && !ack.is_received(10));
+        assert!((0..10)
+            .filter(|i| ![2, 5].contains(i))
+            .all(|i| ack.is_received(i)));
+        assert!(!ack.is_completed());
+        assert!(ChunkAck::completed(id, 10).is_completed());
+
+        let msg = BackendMessage::try_from((MessageType::ChunkAck, &ack)).unwrap();
+        assert_eq!(ChunkAck::try_from(&msg).unwrap(), ack);
+    }
+
+    #[test]
+    fn test_check_chunk() {
+        let arq = ChunkArq::new(ArqConfig {
+            max_chunks: 4,
+            ..Default::default()
+        });
+        let mut chunk =
+            ChunkList::<BACKEND_MTU>::split(&Bytes::from_static(b"abcd"), 1).to_vec()[0].clone();
+        assert!(arq.check(&chunk).is_ok());
+        for position in [[0, 0], [0, 5], [0, usize::MAX], [4, 4], [usize::MAX, 4]] {
+            chunk.chunk = position;
+            assert!(arq.check(&chunk).is_err(), "{:?}", position);
+        }
+    }
+
+    #[test]
+    fn test_resend_lost_chunks_only() {
+        let config = ArqConfig {
+            window: 4,
+            retransmit_timeout_ms: 1000,
+            max_retransmits: 3,
+            ..Default::default()
+        };
+        let total = 10;
+        let mut outgoing = OutgoingChunks::new(Uuid::nil(), total, config);
+        // The first sending of chunks 2 and 5 is lost.
+        let mut lost: HashSet<usize> = HashSet::from([2, 5]);
+        let mut received: HashSet<usize> = HashSet::new();
+        let mut sends = vec![0; total];
+
+        for _ in 0..10 {
+            if outgoing.is_completed() {
+                break;
+            }
+            let batch = outgoing.poll(0).unwrap();
+            assert!(!batch.is_empty());
+            assert!(batch.len() <= config.window);
+            for &i in &batch {
+                sends[i] += 1;
+                if !lost.remove(&i) {
+                    received.insert(i);
+                }
+            }
+            // Receiver is asked to ack by the last chunk of each batch.
+            let missing: Vec<usize> = (0..total).filter(|i| !received.contains(i)).collect();
+            let ack = ChunkAck::new(Uuid::nil(), total, &missing);
+            outgoing.on_ack(&ack);
+        }
+
+        assert!(outgoing.is_completed());
+        assert_eq!(outgoing.retransmits(), 2);
+        for (i, n) in sends.iter().enumerate() {
+            assert_eq!(*n, if i == 2 || i == 5 { 2 } else { 1 }, "chunk {}", i);
+        }
+    }
+
+    #[test]
+    fn test_resend_on_timeout() {
+        let config = ArqConfig {
+            window: 2,
+            retransmit_timeout_ms: 1000,
+            max_retransmits: 1,
+            ..Default::default()
+        };
+        let mut outgoing = OutgoingChunks::new(Uuid::nil(), 3, config);
+        assert_eq!(outgoing.poll(0).unwrap(), vec![0, 1]);
+        // Nothing new is sent while the window is full, until chunks are timed out.
+        assert!(outgoing.poll(999).unwrap().is_empty());
+        assert_eq!(outgoing.poll(1000).unwrap(), vec![0, 1]);
+
+        outgoing.on_ack(&ChunkAck::new(Uuid::nil(), 3, &[1, 2]));
+        assert_eq!(outgoing.poll(1000).unwrap(), vec![2]);
+        assert!(outgoing.poll(2000).is_err());
+    }
+}
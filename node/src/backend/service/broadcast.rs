@@ -0,0 +1,243 @@
+#![warn(missing_docs)]
+//! Broadcast of backend messages to local consumers, such as websocket and jsonrpc clients.
+//!
+//! Backend broadcasts every message it handles through a `tokio::sync::broadcast` channel of
+//! fixed capacity, which never blocks the sender. Once a receiver is `capacity` messages
+//! behind, each new message overwrites the oldest one it has not seen, and the receiver gets
+//! `RecvError::Lagged` with the number it missed on its next receive. When no receiver is
+//! alive, a message is dropped. Both losses are silent to the sender, so [Broadcaster] counts
+//! them, and optionally holds processing while receivers are far behind, see
+//! [BroadcastBackpressure].
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde::Serialize;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::broadcast::Sender;
+
+use crate::backend::types::BackendMessage;
+use crate::prelude::rings_core::metrics;
+use crate::prelude::rings_core::metrics::MetricsImpl;
+use crate::prelude::rings_core::utils::get_epoch_ms;
+
+/// Default capacity of the broadcast channel of backend messages.
+pub const BROADCAST_CAPACITY: usize = 1024;
+
+/// Interval of checking the lag of receivers while holding a message, in milliseconds.
+const BACKPRESSURE_POLL_MS: u64 = 10;
+
+/// Config of [Broadcaster].
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BroadcastConfig {
+    /// capacity of the broadcast channel, which the channel given to backend must be
+    /// created with
+    #[serde(default = "default_capacity")]
+    pub capacity: usize,
+    /// hold processing while receivers are far behind, disabled if not provided
+    #[serde(default)]
+    pub backpressure: Option<BroadcastBackpressure>,
+}
+
+fn default_capacity() -> usize {
+    BROADCAST_CAPACITY
+}
+
+impl Default for BroadcastConfig {
+    fn default() -> Self {
+        Self {
+            capacity: BROADCAST_CAPACITY,
+            backpressure: None,
+        }
+    }
+}
+
+/// Backpressure of broadcast. Before broadcasting a message, backend waits until the
+/// slowest receiver is at most `max_lag` messages behind, for at most `max_wait_ms`.
+/// Handling of messages from peers is held meanwhile.
+///
+/// A receiver which is never read, such as a subscription nobody polls, stays at full lag,
+/// so each message is held for `max_wait_ms`. Keep it short.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BroadcastBackpressure {
+    /// lag of the slowest receiver in messages, above which a message is held
+    pub max_lag: usize,
+    /// max time of holding a message in milliseconds, after which it's broadcast anyway
+    pub max_wait_ms: u64,
+}
+
+/// Counters of [Broadcaster].
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BroadcastStats {
+    /// Messages queued for the slowest receiver.
+    pub lag: usize,
+    /// Messages overwritten before the slowest receiver has seen them.
+    pub overwritten: u64,
+    /// Messages dropped since no receiver is alive.
+    pub unreceived: u64,
+    /// Time held by backpressure in total, in milliseconds.
+    pub held_ms: u64,
+}
+
+/// Sending side of the broadcast of backend messages, see the
+/// [module level documentation](self).
+pub struct Broadcaster {
+    sender: Sender<BackendMessage>,
+    config: BroadcastConfig,
+    metrics: MetricsImpl,
+    overwritten: AtomicU64,
+    unreceived: AtomicU64,
+    held_ms: AtomicU64,
+}
+
+impl Broadcaster {
+    /// Wrap a broadcast sender created with `config.capacity`.
+    pub fn new(
+        sender: Sender<BackendMessage>,
+        mut config: BroadcastConfig,
+        metrics: MetricsImpl,
+    ) -> Self {
+        // The channel rounds its capacity up to a power of two.
+        config.capacity = config.capacity.next_power_of_two();
+        Self {
+            sender,
+            config,
+            metrics,
+            overwritten: AtomicU64::new(0),
+            unreceived: AtomicU64::new(0),
+            held_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Subscribe messages broadcast from now on.
+    pub fn subscribe(&self) -> Receiver<BackendMessage> {
+        self.sender.subscribe()
+    }
+
+    /// Number of messages queued for the slowest receiver.
+    pub fn lag(&self) -> usize {
+        self.sender.len()
+    }
+
+    /// Get counters of broadcast.
+    pub fn stats(&self) -> BroadcastStats {
+        BroadcastStats {
+            lag: self.lag(),
+            overwritten: self.overwritten.load(Ordering::Relaxed),
+            unreceived: self.unreceived.load(Ordering::Relaxed),
+            held_ms: self.held_ms.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Broadcast a message, after backpressure if configured.
+    pub async fn send(&self, msg: BackendMessage) {
+        if let Some(backpressure) = self.config.backpressure {
+            self.hold(backpressure).await;
+        }
+        if self.sender.receiver_count() > 0 && self.lag() >= self.config.capacity {
+            self.overwritten.fetch_add(1, Ordering::Relaxed);
+            self.metrics
+                .increment_counter(metrics::BACKEND_BROADCAST_OVERWRITTEN, 1);
+        }
+        if self.sender.send(msg).is_err() {
+            self.unreceived.fetch_add(1, Ordering::Relaxed);
+            self.metrics
+                .increment_counter(metrics::BACKEND_BROADCAST_UNRECEIVED, 1);
+            tracing::debug!("broadcast backend_message without receiver");
+        }
+        self.metrics
+            .set_gauge(metrics::BACKEND_BROADCAST_LAG, self.lag() as f64);
+    }
+
+    async fn hold(&self, backpressure: BroadcastBackpressure) {
+        if self.lag() <= backpressure.max_lag {
+            return;
+        }
+        let start = get_epoch_ms();
+        let deadline = start + backpressure.max_wait_ms as u128;
+        while self.lag() > backpressure.max_lag && get_epoch_ms() < deadline {
+            tokio::time::sleep(Duration::from_millis(BACKPRESSURE_POLL_MS)).await;
+        }
+        let held = get_epoch_ms().saturating_sub(start) as u64;
+        self.held_ms.fetch_add(held, Ordering::Relaxed);
+        if self.lag() > backpressure.max_lag {
+            tracing::warn!(
+                "broadcast receivers are {} messages behind after holding {}ms",
+                self.lag(),
+                held
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::types::MessageType;
+    use crate::prelude::rings_core::metrics::noop_recorder;
+
+    fn message() -> BackendMessage {
+        BackendMessage::from((MessageType::SimpleText.into(), "hello".as_bytes()))
+    }
+
+    #[tokio::test]
+    async fn test_broadcaster_counts_losses() {
+        let config = BroadcastConfig {
+            capacity: 2,
+            backpressure: None,
+        };
+        let (sender, receiver) = tokio::sync::broadcast::channel(config.capacity);
+        drop(receiver);
+        let broadcaster = Broadcaster::new(sender, config, noop_recorder());
+
+        broadcaster.send(message()).await;
+        assert_eq!(broadcaster.stats().unreceived, 1);
+
+        let mut receiver = broadcaster.subscribe();
+        for _ in 0..3 {
+            broadcaster.send(message()).await;
+        }
+        assert_eq!(broadcaster.stats(), BroadcastStats {
+            lag: 2,
+            overwritten: 1,
+            unreceived: 1,
+            held_ms: 0,
+        });
+        assert!(matches!(
+            receiver.recv().await,
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(1))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_broadcaster_backpressure() {
+        let config = BroadcastConfig {
+            capacity: 4,
+            backpressure: Some(BroadcastBackpressure {
+                max_lag: 1,
+                max_wait_ms: 50,
+            }),
+        };
+        let (sender, mut receiver) = tokio::sync::broadcast::channel(config.capacity);
+        let broadcaster = Broadcaster::new(sender, config, noop_recorder());
+
+        broadcaster.send(message()).await;
+        broadcaster.send(message()).await;
+        assert_eq!(broadcaster.stats().held_ms, 0);
+
+        // The receiver is 2 messages behind, the next one is held until timeout.
+        broadcaster.send(message()).await;
+        assert!(broadcaster.stats().held_ms >= 50);
+        assert_eq!(broadcaster.lag(), 3);
+
+        // Consumed in time, the next one is not held.
+        for _ in 0..3 {
+            receiver.recv().await.unwrap();
+        }
+        let held = broadcaster.stats().held_ms;
+        broadcaster.send(message()).await;
+        assert_eq!(broadcaster.stats().held_ms, held);
+        assert_eq!(broadcaster.stats().overwritten, 0);
+    }
+}
@@ -0,0 +1,119 @@
+#![warn(missing_docs)]
+//! Per-service bulkhead, which bounds how many requests or tunnels of each hidden service
+//! are processed simultaneously, so that a popular service cannot overload its upstream,
+//! nor starve other services of the node.
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::error::Error;
+use crate::error::Result;
+use crate::prelude::rings_core::prelude::dashmap::DashMap;
+
+/// Concurrency of services, cloning it shares the counts.
+/// Work of every service is counted, and rejected only if the service has a limit.
+#[derive(Debug, Clone, Default)]
+pub struct ServiceConcurrency {
+    limits: Arc<HashMap<String, usize>>,
+    current: Arc<DashMap<String, usize>>,
+}
+
+/// Permit of a request or tunnel of service, released on drop.
+#[derive(Debug)]
+pub struct ServicePermit {
+    current: Arc<DashMap<String, usize>>,
+    service: String,
+}
+
+impl Drop for ServicePermit {
+    fn drop(&mut self) {
+        self.current.remove_if_mut(&self.service, |_, count| {
+            *count -= 1;
+            *count == 0
+        });
+    }
+}
+
+impl ServiceConcurrency {
+    /// Create with `max_concurrency` of services, a service without limit is unbounded.
+    /// Service names are case insensitive.
+    pub fn new(limits: impl IntoIterator<Item = (String, Option<usize>)>) -> Self {
+        let limits = limits
+            .into_iter()
+            .filter_map(|(name, limit)| Some((name.to_ascii_lowercase(), limit?)))
+            .collect();
+        Self {
+            limits: Arc::new(limits),
+            current: Default::default(),
+        }
+    }
+
+    /// Max concurrency of service, None if it's unbounded.
+    pub fn limit(&self, service: &str) -> Option<usize> {
+        self.limits.get(&service.to_ascii_lowercase()).copied()
+    }
+
+    /// Number of requests or tunnels of service being processed.
+    pub fn current(&self, service: &str) -> usize {
+        self.current
+            .get(&service.to_ascii_lowercase())
+            .map(|c| *c)
+            .unwrap_or(0)
+    }
+
+    /// Current concurrency of services having work in process.
+    pub fn snapshot(&self) -> Vec<(String, usize)> {
+        self.current
+            .iter()
+            .map(|e| (e.key().clone(), *e.value()))
+            .collect()
+    }
+
+    /// Take a permit for a request or tunnel of service, fails with [Error::ServiceBusy]
+    /// if its limit is reached.
+    pub fn acquire(&self, service: &str) -> Result<ServicePermit> {
+        let service = service.to_ascii_lowercase();
+        let limit = self.limits.get(&service).copied();
+        let mut count = self.current.entry(service.clone()).or_insert(0);
+        if limit.is_some_and(|limit| *count >= limit) {
+            return Err(Error::ServiceBusy(service));
+        }
+        *count += 1;
+        Ok(ServicePermit {
+            current: self.current.clone(),
+            service,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_service_concurrency() {
+        let concurrency =
+            ServiceConcurrency::new([("Busy".to_string(), Some(2)), ("free".to_string(), None)]);
+        assert_eq!(concurrency.limit("busy"), Some(2));
+        assert_eq!(concurrency.limit("free"), None);
+
+        let a = concurrency.acquire("busy").unwrap();
+        let b = concurrency.acquire("BUSY").unwrap();
+        assert_eq!(concurrency.current("Busy"), 2);
+        assert!(matches!(
+            concurrency.acquire("busy"),
+            Err(Error::ServiceBusy(_))
+        ));
+
+        // Limits are per service.
+        let frees = (0..10)
+            .map(|_| concurrency.acquire("free").unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(concurrency.current("free"), 10);
+
+        drop(a);
+        let _c = concurrency.clone().acquire("busy").unwrap();
+        drop(b);
+        drop(frees);
+        assert_eq!(concurrency.snapshot(), vec![("busy".to_string(), 1)]);
+    }
+}
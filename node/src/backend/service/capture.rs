@@ -0,0 +1,522 @@
+#![warn(missing_docs)]
+//! Opt-in capture of the raw byte stream of tunnels, like tcpdump for a tunnel.
+//!
+//! Each tunnel is captured into its own file named by `tid` under [CaptureConfig::dir].
+//! A capture file is a sequence of records:
+//! `[timestamp_ms: u64 LE][direction: u8][length: u32 LE][bytes]`.
+//! Once a file exceeds [CaptureConfig::max_bytes] it's rotated, and at most
+//! [CaptureConfig::max_files] files are kept per tunnel.
+//! Use [render_capture] to read a capture file in a human-readable form.
+//!
+//! Files of at most [CaptureConfig::max_tunnels] tunnels are kept, with at most
+//! [CaptureConfig::max_total_bytes] bytes in total. Files of the oldest tunnel are removed
+//! to make room, and its capture is stopped if it's still open.
+//!
+//! Files of all tunnels are written by a single thread of [TunnelCaptures], so that a slow
+//! disk never stalls the async runtime. Records are queued to it, and dropped if the queue
+//! is full.
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use serde::Deserialize;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio::sync::oneshot;
+
+use crate::backend::service::proxy::TunnelId;
+use crate::error::Error;
+use crate::error::Result;
+use crate::prelude::rings_core::utils::get_epoch_ms;
+
+/// Length of header of a capture record.
+const RECORD_HEADER_LEN: usize = 8 + 1 + 4;
+
+/// Default max size of a capture file in bytes.
+pub const DEFAULT_CAPTURE_MAX_BYTES: u64 = 16 * 1024 * 1024;
+/// Default max number of capture files kept per tunnel.
+pub const DEFAULT_CAPTURE_MAX_FILES: usize = 4;
+/// Default max number of tunnels whose capture files are kept.
+pub const DEFAULT_CAPTURE_MAX_TUNNELS: usize = 64;
+/// Default max bytes of capture files kept of all tunnels.
+pub const DEFAULT_CAPTURE_MAX_TOTAL_BYTES: u64 = 256 * 1024 * 1024;
+/// Max number of commands queued to the writer of all tunnels.
+const CAPTURE_QUEUE_LEN: usize = 4096;
+
+fn default_max_bytes() -> u64 {
+    DEFAULT_CAPTURE_MAX_BYTES
+}
+
+fn default_max_files() -> usize {
+    DEFAULT_CAPTURE_MAX_FILES
+}
+
+fn default_max_tunnels() -> usize {
+    DEFAULT_CAPTURE_MAX_TUNNELS
+}
+
+fn default_max_total_bytes() -> u64 {
+    DEFAULT_CAPTURE_MAX_TOTAL_BYTES
+}
+
+/// Where and how much to capture.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CaptureConfig {
+    /// directory of capture files
+    pub dir: PathBuf,
+    /// max size of a capture file in bytes
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: u64,
+    /// max number of capture files kept per tunnel, including the one being written
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+    /// max number of tunnels whose capture files are kept
+    #[serde(default = "default_max_tunnels")]
+    pub max_tunnels: usize,
+    /// max bytes of capture files kept of all tunnels
+    #[serde(default = "default_max_total_bytes")]
+    pub max_total_bytes: u64,
+}
+
+/// Direction of captured bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CaptureDirection {
+    /// read from local stream, sent to peer
+    Outbound = 0,
+    /// received from peer, written to local stream
+    Inbound = 1,
+}
+
+impl TryFrom<u8> for CaptureDirection {
+    type Error = Error;
+
+    fn try_from(v: u8) -> Result<Self> {
+        match v {
+            0 => Ok(Self::Outbound),
+            1 => Ok(Self::Inbound),
+            _ => Err(Error::DecodeError),
+        }
+    }
+}
+
+/// Command sent to the writer of all tunnels.
+enum CaptureCommand {
+    Open(TunnelId),
+    Record {
+        tid: TunnelId,
+        ts: u64,
+        direction: CaptureDirection,
+        data: Vec<u8>,
+    },
+    Close(TunnelId),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Files of a tunnel, the latest one is being written.
+struct CaptureWriter {
+    path: PathBuf,
+    file: File,
+    written: u64,
+}
+
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut path = path.to_path_buf().into_os_string();
+    path.push(format!(".{}", n));
+    path.into()
+}
+
+/// Remove a file, returns its size, or 0 if it doesn't exist.
+fn remove_file(path: &Path) -> u64 {
+    let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    match fs::remove_file(path) {
+        Ok(()) => size,
+        Err(_) => 0,
+    }
+}
+
+impl CaptureWriter {
+    fn create(config: &CaptureConfig, tid: TunnelId) -> std::io::Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+        let path = config.dir.join(format!("{}.cap", tid));
+        let file = File::create(&path)?;
+        Ok(Self {
+            path,
+            file,
+            written: 0,
+        })
+    }
+
+    /// Rotate files, returns bytes of the file removed.
+    fn rotate(&mut self, max_files: usize) -> std::io::Result<u64> {
+        let keep = max_files.max(1);
+        let removed = if keep == 1 {
+            self.written
+        } else {
+            let removed = remove_file(&rotated_path(&self.path, keep - 1));
+            for n in (1..keep - 1).rev() {
+                let from = rotated_path(&self.path, n);
+                if from.exists() {
+                    fs::rename(from, rotated_path(&self.path, n + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+            removed
+        };
+        self.file = File::create(&self.path)?;
+        self.written = 0;
+        Ok(removed)
+    }
+
+    /// Write a record, returns bytes of the file removed by rotation.
+    fn write(
+        &mut self,
+        config: &CaptureConfig,
+        ts: u64,
+        direction: CaptureDirection,
+        data: &[u8],
+    ) -> std::io::Result<u64> {
+        let len = (RECORD_HEADER_LEN + data.len()) as u64;
+        let mut removed = 0;
+        if self.written > 0 && self.written + len > config.max_bytes {
+            removed = self.rotate(config.max_files)?;
+        }
+        let mut record = Vec::with_capacity(len as usize);
+        record.extend_from_slice(&ts.to_le_bytes());
+        record.push(direction as u8);
+        record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        record.extend_from_slice(data);
+        self.file.write_all(&record)?;
+        self.written += len;
+        Ok(removed)
+    }
+}
+
+/// State of the writer thread, owning capture files of all tunnels.
+struct CaptureStore {
+    config: CaptureConfig,
+    writers: HashMap<TunnelId, CaptureWriter>,
+    /// Tunnels whose files are kept, the oldest first.
+    kept: VecDeque<TunnelId>,
+    /// Bytes of files kept.
+    bytes: u64,
+}
+
+impl CaptureStore {
+    fn new(config: CaptureConfig) -> Self {
+        Self {
+            config,
+            writers: HashMap::new(),
+            kept: VecDeque::new(),
+            bytes: 0,
+        }
+    }
+
+    /// Handle commands until all senders are dropped.
+    fn run(mut self, mut receiver: mpsc::Receiver<CaptureCommand>) {
+        while let Some(cmd) = receiver.blocking_recv() {
+            match cmd {
+                CaptureCommand::Open(tid) => self.open(tid),
+                CaptureCommand::Record {
+                    tid,
+                    ts,
+                    direction,
+                    data,
+                } => self.record(tid, ts, direction, &data),
+                CaptureCommand::Close(tid) => {
+                    self.writers.remove(&tid);
+                }
+                CaptureCommand::Flush(done) => {
+                    let _ = done.send(());
+                }
+            }
+        }
+    }
+
+    fn open(&mut self, tid: TunnelId) {
+        while self.kept.len() >= self.config.max_tunnels.max(1) {
+            self.remove_oldest();
+        }
+        match CaptureWriter::create(&self.config, tid) {
+            Ok(writer) => {
+                self.writers.insert(tid, writer);
+                self.kept.push_back(tid);
+            }
+            Err(e) => tracing::error!("Create capture of tunnel {} failed: {}", tid, e),
+        }
+    }
+
+    /// Write a record of tunnel. Files of other tunnels are removed, the oldest first, if the
+    /// record exceeds [CaptureConfig::max_total_bytes], and the record is dropped if that's
+    /// not enough. Capturing a tunnel is stopped on its first write failure.
+    fn record(&mut self, tid: TunnelId, ts: u64, direction: CaptureDirection, data: &[u8]) {
+        if !self.writers.contains_key(&tid) {
+            return;
+        }
+        let len = (RECORD_HEADER_LEN + data.len()) as u64;
+        while self.bytes + len > self.config.max_total_bytes
+            && self.kept.front().is_some_and(|oldest| *oldest != tid)
+        {
+            self.remove_oldest();
+        }
+        if self.bytes + len > self.config.max_total_bytes {
+            tracing::warn!(
+                "Tunnel captures are full, drop a record of {} bytes",
+                data.len()
+            );
+            return;
+        }
+        let Some(writer) = self.writers.get_mut(&tid) else {
+            return;
+        };
+        match writer.write(&self.config, ts, direction, data) {
+            Ok(removed) => self.bytes = (self.bytes + len).saturating_sub(removed),
+            Err(e) => {
+                tracing::error!("Tunnel capture {:?} stopped: {}", writer.path, e);
+                self.writers.remove(&tid);
+            }
+        }
+    }
+
+    /// Stop capturing the oldest tunnel kept and remove its files.
+    fn remove_oldest(&mut self) {
+        let Some(tid) = self.kept.pop_front() else {
+            return;
+        };
+        self.writers.remove(&tid);
+        let path = self.config.dir.join(format!("{}.cap", tid));
+        let mut removed = remove_file(&path);
+        for n in 1..self.config.max_files.max(1) {
+            removed += remove_file(&rotated_path(&path, n));
+        }
+        self.bytes = self.bytes.saturating_sub(removed);
+    }
+}
+
+/// Captures of tunnels sharing a single writer thread and the caps of [CaptureConfig].
+/// Cloning it shares the writer, which is stopped once all clones and captures are dropped.
+#[derive(Clone)]
+pub struct TunnelCaptures(mpsc::Sender<CaptureCommand>);
+
+impl TunnelCaptures {
+    /// Start the writer thread of captures.
+    pub fn new(config: CaptureConfig) -> Result<Self> {
+        let (sender, receiver) = mpsc::channel(CAPTURE_QUEUE_LEN);
+        std::thread::Builder::new()
+            .name("tunnel-capture".to_string())
+            .spawn(move || CaptureStore::new(config).run(receiver))
+            .map_err(|e| Error::OpenFileError(e.to_string()))?;
+        Ok(Self(sender))
+    }
+
+    /// Create capture file of tunnel `tid`, which is written until all clones of the capture
+    /// are dropped, or the files of tunnel are removed to make room for others.
+    pub fn capture(&self, tid: TunnelId) -> TunnelCapture {
+        if self.0.try_send(CaptureCommand::Open(tid)).is_err() {
+            tracing::warn!(
+                "Tunnel captures fall behind, tunnel {} is not captured",
+                tid
+            );
+        }
+        TunnelCapture {
+            tid,
+            sender: self.0.clone(),
+            _closer: Arc::new(CaptureCloser {
+                tid,
+                sender: self.0.clone(),
+            }),
+        }
+    }
+
+    /// Wait until commands queued before are handled.
+    pub async fn flush(&self) {
+        let (done, wait) = oneshot::channel();
+        if self.0.send(CaptureCommand::Flush(done)).await.is_ok() {
+            let _ = wait.await;
+        }
+    }
+}
+
+/// Closes the file of tunnel once the last clone of its capture is dropped.
+struct CaptureCloser {
+    tid: TunnelId,
+    sender: mpsc::Sender<CaptureCommand>,
+}
+
+impl Drop for CaptureCloser {
+    fn drop(&mut self) {
+        let _ = self.sender.try_send(CaptureCommand::Close(self.tid));
+    }
+}
+
+/// TunnelCapture records bytes of a tunnel, see [TunnelCaptures]. Capturing is stopped on
+/// the first write failure, so that a full disk never breaks the tunnel. Cloned captures
+/// share the same file.
+#[derive(Clone)]
+pub struct TunnelCapture {
+    tid: TunnelId,
+    sender: mpsc::Sender<CaptureCommand>,
+    _closer: Arc<CaptureCloser>,
+}
+
+impl TunnelCapture {
+    /// Record bytes flowing in `direction`. The record is dropped if the writer falls behind.
+    pub fn record(&self, direction: CaptureDirection, data: &[u8]) {
+        let cmd = CaptureCommand::Record {
+            tid: self.tid,
+            ts: get_epoch_ms() as u64,
+            direction,
+            data: data.to_vec(),
+        };
+        if let Err(mpsc::error::TrySendError::Full(_)) = self.sender.try_send(cmd) {
+            tracing::warn!(
+                "Tunnel capture falls behind, drop a record of {} bytes",
+                data.len()
+            );
+        }
+    }
+
+    /// Wait until records queued before are written.
+    pub async fn flush(&self) {
+        let (done, wait) = oneshot::channel();
+        if self.sender.send(CaptureCommand::Flush(done)).await.is_ok() {
+            let _ = wait.await;
+        }
+    }
+}
+
+/// Render a capture file as lines of `timestamp direction length`, each followed by
+/// a hex dump of the bytes.
+pub fn render_capture(path: impl AsRef<Path>) -> Result<String> {
+    let mut buf = vec![];
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut buf))
+        .map_err(|e| Error::OpenFileError(e.to_string()))?;
+
+    let mut out = String::new();
+    let mut rest = buf.as_slice();
+    while !rest.is_empty() {
+        if rest.len() < RECORD_HEADER_LEN {
+            return Err(Error::DecodeError);
+        }
+        let ts = u64::from_le_bytes(rest[..8].try_into().unwrap());
+        let direction = CaptureDirection::try_from(rest[8])?;
+        let len = u32::from_le_bytes(rest[9..13].try_into().unwrap()) as usize;
+        let data = rest
+            .get(RECORD_HEADER_LEN..RECORD_HEADER_LEN + len)
+            .ok_or(Error::DecodeError)?;
+        rest = &rest[RECORD_HEADER_LEN + len..];
+
+        let arrow = match direction {
+            CaptureDirection::Outbound => "local -> peer",
+            CaptureDirection::Inbound => "peer -> local",
+        };
+        let _ = writeln!(out, "{} {} {} bytes", ts, arrow, len);
+        for (i, line) in data.chunks(16).enumerate() {
+            let hex = line
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<Vec<_>>();
+            let ascii: String = line
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            let _ = writeln!(out, "  {:08x}  {:<47}  {}", i * 16, hex.join(" "), ascii);
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::rings_core::prelude::uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_capture_rotate_and_render() {
+        let dir = std::env::temp_dir().join(format!("rings-capture-{}", Uuid::new_v4()));
+        let config = CaptureConfig {
+            dir: dir.clone(),
+            max_bytes: 64,
+            max_files: 2,
+            max_tunnels: DEFAULT_CAPTURE_MAX_TUNNELS,
+            max_total_bytes: DEFAULT_CAPTURE_MAX_TOTAL_BYTES,
+        };
+        let tid = Uuid::new_v4();
+        let capture = TunnelCaptures::new(config).unwrap().capture(tid);
+
+        capture.record(CaptureDirection::Outbound, b"GET / HTTP/1.1\r\n");
+        capture.record(CaptureDirection::Inbound, b"HTTP/1.1 200 OK\r\n");
+        capture.flush().await;
+        let path = dir.join(format!("{}.cap", tid));
+        let rendered = render_capture(&path).unwrap();
+        assert!(rendered.contains("local -> peer 16 bytes"));
+        assert!(rendered.contains("HTTP/1.1 200 OK.."));
+
+        // Each record exceeds the size of a file with the previous one, so files are rotated,
+        // and the oldest records are dropped.
+        capture.record(CaptureDirection::Inbound, &[0u8; 40]);
+        capture.record(CaptureDirection::Outbound, &[1u8; 30]);
+        capture.flush().await;
+        assert!(render_capture(&path)
+            .unwrap()
+            .contains("local -> peer 30 bytes"));
+        let rotated = render_capture(dir.join(format!("{}.cap.1", tid))).unwrap();
+        assert!(rotated.contains("peer -> local 40 bytes"));
+        assert!(!rotated.contains("GET"));
+        assert!(!dir.join(format!("{}.cap.2", tid)).exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_capture_caps_of_all_tunnels() {
+        let dir = std::env::temp_dir().join(format!("rings-capture-{}", Uuid::new_v4()));
+        let record_len = (RECORD_HEADER_LEN + 20) as u64;
+        let captures = TunnelCaptures::new(CaptureConfig {
+            dir: dir.clone(),
+            max_bytes: DEFAULT_CAPTURE_MAX_BYTES,
+            max_files: 2,
+            max_tunnels: 2,
+            max_total_bytes: record_len * 3,
+        })
+        .unwrap();
+        let path = |tid: Uuid| dir.join(format!("{}.cap", tid));
+
+        // Files of the oldest tunnel are removed once more tunnels are captured.
+        let tids = [Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+        let c: Vec<_> = tids.iter().map(|tid| captures.capture(*tid)).collect();
+        captures.flush().await;
+        assert!(!path(tids[0]).exists());
+        assert!(path(tids[1]).exists() && path(tids[2]).exists());
+        c[0].record(CaptureDirection::Outbound, &[0u8; 20]);
+
+        // Files of other tunnels are removed when total bytes are exceeded, and a record
+        // exceeding the cap alone is dropped.
+        c[1].record(CaptureDirection::Outbound, &[1u8; 20]);
+        c[2].record(CaptureDirection::Outbound, &[2u8; 20]);
+        c[2].record(CaptureDirection::Outbound, &[2u8; 20]);
+        c[2].record(CaptureDirection::Outbound, &[2u8; 20]);
+        captures.flush().await;
+        assert!(!path(tids[1]).exists());
+        c[2].record(CaptureDirection::Outbound, &[2u8; 20]);
+        captures.flush().await;
+        let rendered = render_capture(path(tids[2])).unwrap();
+        assert_eq!(rendered.matches("local -> peer 20 bytes").count(), 3);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,109 @@
+#![warn(missing_docs)]
+//! Echo endpoint, which sends the data of request back to requester.
+//! It is used to measure round trips and to verify the message path end to end.
+use bincode::Options;
+use bytes::Bytes;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::backend::types::encode_custom_messages;
+use crate::backend::types::BackendMessage;
+use crate::backend::types::MessageEndpoint;
+use crate::backend::types::MessageType;
+use crate::consts::BACKEND_MTU;
+use crate::error::Error;
+use crate::error::Result;
+use crate::prelude::rings_core::prelude::uuid::Uuid;
+use crate::prelude::*;
+
+/// Messages of echo, carried by [BackendMessage] of [MessageType::Echo].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum EchoMessage {
+    /// Ask remote to send data back.
+    Request {
+        /// id of request
+        id: Uuid,
+        /// data to be echoed
+        data: Bytes,
+    },
+    /// Data sent back by remote.
+    Reply {
+        /// id of request
+        id: Uuid,
+        /// data of request
+        data: Bytes,
+    },
+}
+
+impl TryFrom<&[u8]> for EchoMessage {
+    type Error = Error;
+
+    /// Decode untrusted bytes, reading is limited to the length of input.
+    fn try_from(value: &[u8]) -> Result<Self> {
+        bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .allow_trailing_bytes()
+            .with_limit(value.len() as u64)
+            .deserialize(value)
+            .map_err(|_| Error::DecodeError)
+    }
+}
+
+impl TryFrom<&BackendMessage> for EchoMessage {
+    type Error = Error;
+
+    /// Parse a received [BackendMessage], which should be of [MessageType::Echo].
+    fn try_from(msg: &BackendMessage) -> Result<Self> {
+        if !matches!(MessageType::from(msg.message_type), MessageType::Echo) {
+            return Err(Error::InvalidMessage);
+        }
+        EchoMessage::try_from(msg.data.as_slice())
+    }
+}
+
+/// EchoEndpoint replies [EchoMessage::Request] with its data.
+#[derive(Clone, Debug, Default)]
+pub struct EchoEndpoint;
+
+#[async_trait::async_trait]
+impl MessageEndpoint for EchoEndpoint {
+    async fn handle_message(
+        &self,
+        ctx: &MessagePayload,
+        msg: &BackendMessage,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        let EchoMessage::Request { id, data } = EchoMessage::try_from(msg)? else {
+            return Ok(vec![]);
+        };
+        let reply =
+            BackendMessage::try_from((MessageType::Echo, &EchoMessage::Reply { id, data }))?;
+        // Large replies are chunked, the same as requests.
+        encode_custom_messages::<BACKEND_MTU>(reply)?
+            .into_iter()
+            .map(|data| {
+                Ok(MessageHandlerEvent::SendReportMessage(
+                    ctx.clone(),
+                    Message::custom(&data).map_err(|_| Error::InvalidMessage)?,
+                ))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_echo_message() {
+        let msg = EchoMessage::Request {
+            id: Uuid::new_v4(),
+            data: Bytes::from_static(b"ping"),
+        };
+        let backend_msg = BackendMessage::try_from((MessageType::Echo, &msg)).unwrap();
+        assert_eq!(EchoMessage::try_from(&backend_msg).unwrap(), msg);
+
+        let text = BackendMessage::from((MessageType::SimpleText.into(), "text".as_bytes()));
+        assert!(EchoMessage::try_from(&text).is_err());
+    }
+}
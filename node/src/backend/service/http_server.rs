@@ -2,24 +2,58 @@
 
 //! http server handler
 
+use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Instant;
 
 use bytes::Bytes;
 use serde::Deserialize;
 use serde::Serialize;
 
+use crate::backend::http_compression;
+use crate::backend::http_compression::HTTP_COMPRESSION_HEADER;
+use crate::backend::service::access_log::AccessLog;
+use crate::backend::service::access_log::AccessLogEntry;
+use crate::backend::service::access_log::RedactionRules;
+use crate::backend::service::bulkhead::ServiceConcurrency;
+use crate::backend::service::response_cache::CacheLookup;
+use crate::backend::service::response_cache::ResponseCache;
+use crate::backend::service::response_cache::ResponseCacheConfig;
+use crate::backend::service::response_cache::ResponseCacheStats;
 use crate::backend::types::BackendMessage;
 use crate::backend::types::HttpResponse;
+use crate::backend::types::HttpResponsePart;
+use crate::backend::types::ResponseEncoding;
 use crate::backend::MessageEndpoint;
 use crate::backend::MessageType;
 use crate::consts::BACKEND_MTU;
 use crate::error::Error;
 use crate::error::Result;
 use crate::prelude::rings_core::chunk::ChunkList;
+use crate::prelude::rings_core::message::MessageVerificationExt;
+use crate::prelude::rings_core::metrics;
+use crate::prelude::rings_core::metrics::noop_recorder;
+use crate::prelude::rings_core::metrics::MetricsImpl;
+use crate::prelude::rings_core::utils::get_epoch_ms;
 use crate::prelude::rings_rpc::types::HttpRequest;
 use crate::prelude::*;
 
+/// Header of request asking for a progressive response, see [HttpResponsePart].
+/// It's not forwarded to upstream.
+pub const HTTP_PROGRESS_HEADER: &str = "x-rings-progress";
+
+/// Max length of a piece of body in a progressive response.
+pub const HTTP_BODY_PIECE_LEN: usize = 32 * 1024;
+
+/// Check if request asks for a progressive response, by [HTTP_PROGRESS_HEADER].
+pub fn wants_progress(request: &HttpRequest) -> bool {
+    request
+        .headers
+        .keys()
+        .any(|k| k.eq_ignore_ascii_case(HTTP_PROGRESS_HEADER))
+}
+
 /// HTTP Server Config, specific determine port.
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct HttpServiceConfig {
@@ -31,13 +65,38 @@ pub struct HttpServiceConfig {
 
     /// mode of hidden service
     pub prefix: String,
+
+    /// max number of requests processed simultaneously, excess is rejected as busy
+    #[serde(default)]
+    pub max_concurrency: Option<usize>,
+
+    /// how requests are summarized in access log, bodies are redacted by default
+    #[serde(default)]
+    pub redaction: RedactionRules,
+
+    /// cache of responses, see [response_cache](crate::backend::service::response_cache),
+    /// not cached if not provided
+    #[serde(default)]
+    pub cache: Option<ResponseCacheConfig>,
 }
 
 impl From<Vec<HttpServiceConfig>> for HttpServer {
     fn from(configs: Vec<HttpServiceConfig>) -> Self {
         Self {
             client: Arc::new(reqwest::Client::new()),
+            concurrency: ServiceConcurrency::new(
+                configs.iter().map(|x| (x.name.clone(), x.max_concurrency)),
+            ),
+            caches: configs
+                .iter()
+                .filter_map(|x| {
+                    let cache = ResponseCache::new(x.cache.clone()?);
+                    Some((x.name.to_lowercase(), cache))
+                })
+                .collect(),
             services: configs,
+            swarm: None,
+            access_log: None,
         }
     }
 }
@@ -50,17 +109,219 @@ pub struct HttpServer {
 
     /// hidden services
     pub services: Vec<HttpServiceConfig>,
+
+    /// requests being processed by services
+    pub concurrency: ServiceConcurrency,
+
+    /// caches of responses by lowercase name of service
+    caches: HashMap<String, ResponseCache>,
+
+    /// swarm sending parts of progressive responses
+    swarm: Option<Arc<Swarm>>,
+
+    /// access log of requests
+    access_log: Option<AccessLog>,
 }
 
 impl HttpServer {
-    /// execute http request
+    /// Set swarm sending parts of progressive responses, see [HttpResponsePart].
+    /// Responses are sent whole if it's not set.
+    pub fn with_swarm(mut self, swarm: Arc<Swarm>) -> Self {
+        self.swarm = Some(swarm);
+        self
+    }
+
+    /// Record requests to access log, see [access_log](crate::backend::service::access_log).
+    /// Not recorded if it's not set.
+    pub fn with_access_log(mut self, access_log: Option<AccessLog>) -> Self {
+        self.access_log = access_log;
+        self
+    }
+
+    /// find hidden service by name
+    pub fn service(&self, name: &str) -> Option<&HttpServiceConfig> {
+        self.services
+            .iter()
+            .find(|x| x.name.eq_ignore_ascii_case(name))
+    }
+
+    /// execute http request on the service named in request
     pub async fn execute(&self, request: &HttpRequest) -> Result<HttpResponse> {
         let service = self
-            .services
-            .iter()
-            .find(|x| x.name.eq_ignore_ascii_case(request.name.as_str()))
+            .service(request.name.as_str())
             .ok_or(Error::InvalidService)?;
+        self.execute_on(service, request).await
+    }
+
+    /// Drop cached responses of `path` of service, or every response of service if path
+    /// is None. Fails with [Error::InvalidService] if service has no cache.
+    pub fn invalidate_cache(&self, service: &str, path: Option<&str>) -> Result<()> {
+        let cache = self
+            .caches
+            .get(&service.to_lowercase())
+            .ok_or(Error::InvalidService)?;
+        cache.invalidate(path);
+        Ok(())
+    }
+
+    /// Get counters of the response cache of service, None if it has no cache.
+    pub fn cache_stats(&self, service: &str) -> Option<ResponseCacheStats> {
+        self.caches.get(&service.to_lowercase()).map(|c| c.stats())
+    }
+
+    fn metrics(&self) -> MetricsImpl {
+        self.swarm
+            .as_ref()
+            .map(|s| s.metrics())
+            .unwrap_or_else(noop_recorder)
+    }
+
+    /// execute http request on given service, fails with [Error::ServiceBusy]
+    /// if max concurrency of service is reached. Responses cached are served without
+    /// a permit, see [response_cache](crate::backend::service::response_cache).
+    pub async fn execute_on(
+        &self,
+        service: &HttpServiceConfig,
+        request: &HttpRequest,
+    ) -> Result<HttpResponse> {
+        let cache = self.caches.get(&service.name.to_lowercase());
+        let lookup = cache
+            .map(|c| c.lookup(request, get_epoch_ms()))
+            .unwrap_or(CacheLookup::Bypass);
+        let metrics = self.metrics();
+        match lookup {
+            CacheLookup::Hit(response) => {
+                metrics.increment_counter(metrics::HTTP_CACHE_HIT, 1);
+                return Ok(response);
+            }
+            CacheLookup::Miss => metrics.increment_counter(metrics::HTTP_CACHE_MISS, 1),
+            _ => {}
+        }
+
+        let _permit = self.concurrency.acquire(&service.name)?;
+        let resp = match lookup {
+            CacheLookup::Stale(ref etag, _) => {
+                let mut request = request.clone();
+                request
+                    .headers
+                    .insert("If-None-Match".to_string(), etag.clone());
+                self.send_upstream(service, &request).await?
+            }
+            _ => self.send_upstream(service, request).await?,
+        };
+
+        let status = resp.status().as_u16();
+        let headers = response_headers(&resp);
+        if let (CacheLookup::Stale(_, stale), Some(cache)) = (&lookup, cache) {
+            // The requester didn't ask for a 304, so it gets the cached response, even if the
+            // cache dropped it meanwhile.
+            if status == 304 {
+                metrics.increment_counter(metrics::HTTP_CACHE_HIT, 1);
+                return Ok(cache
+                    .revalidate(request, &headers, get_epoch_ms())
+                    .unwrap_or_else(|| stale.clone()));
+            }
+            metrics.increment_counter(metrics::HTTP_CACHE_MISS, 1);
+        }
+        let body = resp
+            .bytes()
+            .await
+            .map_err(|e| Error::HttpRequestError(e.to_string()))?;
+
+        let response = HttpResponse {
+            status,
+            headers,
+            body: Some(body),
+        };
+        if let Some(cache) = cache {
+            cache.store(request, &response, get_epoch_ms());
+        }
+        Ok(response)
+    }
+
+    /// Execute http request on given service, and report the response progressively
+    /// to requester: `Head` as soon as upstream responds, then `Body` pieces as they
+    /// arrive, then `End`, see [HttpResponsePart].
+    /// Returns the status and the length of body sent.
+    pub async fn execute_progressive(
+        &self,
+        swarm: &Swarm,
+        ctx: &MessagePayload,
+        service: &HttpServiceConfig,
+        request: &HttpRequest,
+    ) -> Result<(u16, usize)> {
+        self.execute_parts(service, request, |part| send_part(swarm, ctx, part))
+            .await
+    }
+
+    /// Execute http request on given service, and pass parts of the response to `send`
+    /// in the order of [HttpServer::execute_progressive].
+    async fn execute_parts<F, Fut>(
+        &self,
+        service: &HttpServiceConfig,
+        request: &HttpRequest,
+        send: F,
+    ) -> Result<(u16, usize)>
+    where
+        F: Fn(HttpResponsePart) -> Fut,
+        Fut: std::future::Future<Output = Result<()>>,
+    {
+        let _permit = self.concurrency.acquire(&service.name)?;
+        let mut resp = self.send_upstream(service, request).await?;
 
+        let status = resp.status().as_u16();
+        let head = HttpResponsePart::Head {
+            status,
+            headers: response_headers(&resp),
+        };
+        send(head).await?;
+
+        let mut seq = 0;
+        let mut size = 0;
+        loop {
+            let chunk = match resp.chunk().await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                Err(e) => {
+                    let reason = e.to_string();
+                    send(HttpResponsePart::Abort { reason }).await?;
+                    return Err(Error::HttpRequestError(e.to_string()));
+                }
+            };
+            size += chunk.len();
+            for piece in chunk.chunks(HTTP_BODY_PIECE_LEN) {
+                let data = chunk.slice_ref(piece);
+                send(HttpResponsePart::Body { seq, data }).await?;
+                seq += 1;
+            }
+        }
+        send(HttpResponsePart::End { pieces: seq }).await?;
+        Ok((status, size))
+    }
+
+    /// Record a request to access log with its response or error, if access log is set.
+    fn record_access(
+        &self,
+        entry: Option<AccessLogEntry>,
+        response: std::result::Result<(u16, usize), &Error>,
+        started: Instant,
+    ) {
+        let (Some(access_log), Some(entry)) = (self.access_log.as_ref(), entry) else {
+            return;
+        };
+        let duration_ms = started.elapsed().as_millis() as u64;
+        let entry = match response {
+            Ok((status, size)) => entry.respond(status, Some(size), duration_ms),
+            Err(e) => entry.respond(e, None, duration_ms),
+        };
+        access_log.record(&entry);
+    }
+
+    async fn send_upstream(
+        &self,
+        service: &HttpServiceConfig,
+        request: &HttpRequest,
+    ) -> Result<reqwest::Response> {
         let url = format!(
             "{}/{}",
             service.prefix,
@@ -72,10 +333,13 @@ impl HttpServer {
         let request_method =
             http::Method::from_str(request.method.as_str()).map_err(|_| Error::InvalidMethod)?;
 
-        let headers = (&request.headers).try_into().map_err(|e| {
-            tracing::info!("invalid_headers: {}", e);
-            Error::InvalidHeaders
-        })?;
+        let mut headers: reqwest::header::HeaderMap =
+            (&request.headers).try_into().map_err(|e| {
+                tracing::info!("invalid_headers: {}", e);
+                Error::InvalidHeaders
+            })?;
+        headers.remove(HTTP_PROGRESS_HEADER);
+        headers.remove(HTTP_COMPRESSION_HEADER);
 
         let request_builder = self
             .client
@@ -90,30 +354,28 @@ impl HttpServer {
             request_builder
         };
 
-        let resp = request_builder
+        request_builder
             .send()
             .await
-            .map_err(|e| Error::HttpRequestError(e.to_string()))?;
-
-        let status = resp.status().as_u16();
-
-        let headers = resp
-            .headers()
-            .iter()
-            .map(|(key, value)| (key.to_string(), value.to_str().unwrap_or("").to_owned()))
-            .collect();
+            .map_err(|e| Error::HttpRequestError(e.to_string()))
+    }
+}
 
-        let body = resp
-            .bytes()
-            .await
-            .map_err(|e| Error::HttpRequestError(e.to_string()))?;
+fn response_headers(resp: &reqwest::Response) -> HashMap<String, String> {
+    resp.headers()
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_str().unwrap_or("").to_owned()))
+        .collect()
+}
 
-        Ok(HttpResponse {
-            status,
-            headers,
-            body: Some(body),
-        })
-    }
+/// Report a part of progressive response to requester.
+async fn send_part(swarm: &Swarm, ctx: &MessagePayload, part: HttpResponsePart) -> Result<()> {
+    let msg: Vec<u8> = BackendMessage::try_from((MessageType::HttpResponsePart, &part))?.into();
+    let ev = super::utils::send_report_message(ctx, &msg)?;
+    swarm
+        .handle_message_handler_events(&vec![ev])
+        .await
+        .map_err(Error::SendMessage)
 }
 
 #[async_trait::async_trait]
@@ -125,20 +387,59 @@ impl MessageEndpoint for HttpServer {
     ) -> Result<Vec<MessageHandlerEvent>> {
         let req: HttpRequest = bincode::deserialize(&msg.data).map_err(|_| Error::DecodeError)?;
 
-        let resp = self.execute(&req).await?;
+        let service = self
+            .service(msg.service().unwrap_or(req.name.as_str()))
+            .ok_or(Error::InvalidService)?;
+        let started = Instant::now();
+        let entry = self.access_log.as_ref().map(|_| {
+            let peer = ctx.transaction.signer();
+            AccessLogEntry::http(
+                get_epoch_ms() as u64,
+                peer,
+                &service.name,
+                &req,
+                &service.redaction,
+            )
+        });
+        if let Some(swarm) = self.swarm.as_ref().filter(|_| wants_progress(&req)) {
+            let result = self.execute_progressive(swarm, ctx, service, &req).await;
+            self.record_access(entry, result.as_ref().copied(), started);
+            result?;
+            return Ok(vec![]);
+        }
+        let result = self.execute_on(service, &req).await;
+        let response = result
+            .as_ref()
+            .map(|r| (r.status, r.body.as_ref().map(|b| b.len()).unwrap_or(0)));
+        self.record_access(entry, response, started);
+        let resp = result?;
         tracing::debug!("Sending HTTP response: {:?}", resp);
-        tracing::debug!("resp_bytes start gzip");
-        let json_bytes = bincode::serialize(&resp)
-            .map_err(|_| Error::EncodeError)?
-            .into();
-        let resp_bytes =
-            message::encode_data_gzip(&json_bytes, 9).map_err(|_| Error::EncodeError)?;
-
-        let resp_bytes: Bytes = BackendMessage::from((
-            MessageType::HttpResponse.into(),
-            resp_bytes.to_vec().as_slice(),
-        ))
-        .into();
+        let resp_bytes: Bytes = if http_compression::wants_compression(&req) {
+            let encoded = http_compression::encode_response(&resp)?;
+            if encoded.encoding == ResponseEncoding::Gzip {
+                let input = bincode::serialized_size(&resp).unwrap_or(0);
+                let metrics = self.metrics();
+                metrics.increment_counter(metrics::HTTP_COMPRESSION_INPUT_BYTES, input);
+                metrics.increment_counter(
+                    metrics::HTTP_COMPRESSION_OUTPUT_BYTES,
+                    encoded.data.len() as u64,
+                );
+            }
+            BackendMessage::try_from((MessageType::EncodedHttpResponse, &encoded))?.into()
+        } else {
+            tracing::debug!("resp_bytes start gzip");
+            let json_bytes = bincode::serialize(&resp)
+                .map_err(|_| Error::EncodeError)?
+                .into();
+            let resp_bytes =
+                message::encode_data_gzip(&json_bytes, 9).map_err(|_| Error::EncodeError)?;
+
+            BackendMessage::from((
+                MessageType::HttpResponse.into(),
+                resp_bytes.to_vec().as_slice(),
+            ))
+            .into()
+        };
         tracing::debug!("resp_bytes gzip_data len: {}", resp_bytes.len());
 
         let chunks = ChunkList::<BACKEND_MTU>::from(&resp_bytes);
@@ -156,3 +457,100 @@ impl MessageEndpoint for HttpServer {
         Ok(events)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::backend::types::HttpResponseAssembler;
+    use crate::prelude::rings_core::consts::MAX_DECOMPRESSED_SIZE;
+
+    /// Serve one request with a chunked body, written piece by piece like a slow upstream.
+    async fn slow_upstream(body: Vec<u8>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = stream.read(&mut buf).await.unwrap();
+            let head = "HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\n\
+                        transfer-encoding: chunked\r\n\r\n";
+            stream.write_all(head.as_bytes()).await.unwrap();
+            for piece in body.chunks(40000) {
+                let size = format!("{:x}\r\n", piece.len());
+                stream.write_all(size.as_bytes()).await.unwrap();
+                stream.write_all(piece).await.unwrap();
+                stream.write_all(b"\r\n").await.unwrap();
+                stream.flush().await.unwrap();
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+            stream.write_all(b"0\r\n\r\n").await.unwrap();
+        });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn test_progressive_response() {
+        let body = (0..100_000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let service = HttpServiceConfig {
+            name: "api".to_string(),
+            register_service: None,
+            prefix: slow_upstream(body.clone()).await,
+            max_concurrency: None,
+            redaction: Default::default(),
+            cache: None,
+        };
+        let server = HttpServer::from(vec![service.clone()]);
+        let request = HttpRequest {
+            name: "api".to_string(),
+            method: "GET".to_string(),
+            path: "/slow".to_string(),
+            timeout: Default::default(),
+            headers: HashMap::from([(HTTP_PROGRESS_HEADER.to_string(), "1".to_string())]),
+            body: None,
+        };
+
+        let parts = Mutex::new(vec![]);
+        let (status, size) = server
+            .execute_parts(&service, &request, |part| {
+                parts.lock().unwrap().push(part);
+                async { Ok(()) }
+            })
+            .await
+            .unwrap();
+        assert_eq!((status, size), (200, body.len()));
+
+        let parts = parts.into_inner().unwrap();
+        assert!(matches!(parts[0], HttpResponsePart::Head {
+            status: 200,
+            ..
+        }));
+        let pieces = parts
+            .iter()
+            .filter(|p| matches!(p, HttpResponsePart::Body { .. }))
+            .count();
+        assert!(pieces >= body.len() / HTTP_BODY_PIECE_LEN);
+        assert_eq!(
+            parts.last(),
+            Some(&HttpResponsePart::End {
+                pieces: pieces as u64
+            })
+        );
+
+        // The requester reassembles the response from its parts.
+        let mut assembler = HttpResponseAssembler::new(MAX_DECOMPRESSED_SIZE);
+        let mut response = None;
+        for part in parts {
+            response = assembler.push(part).unwrap();
+        }
+        let response = response.unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.headers["content-type"], "text/plain");
+        assert_eq!(response.body.unwrap(), Bytes::from(body));
+    }
+}
@@ -0,0 +1,353 @@
+#![warn(missing_docs)]
+//! Idempotent requests, which are executed at most once even if they are retried.
+//!
+//! A request wrapped in [IdempotentRequest] carries a key chosen by the requester. The
+//! receiver remembers the replies to the key for [IDEMPOTENCY_TTL], and a retried request
+//! with the same key is answered with the remembered replies instead of being executed again.
+//! A request failed with an error is not remembered, so that it can be retried.
+//!
+//! Keys are scoped by the signer of request, and bound to a digest of the wrapped request,
+//! so a key reused for a different request is rejected instead of replaying the replies.
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::time::Duration;
+
+use bincode::Options;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::backend::service::Backend;
+use crate::backend::types::BackendMessage;
+use crate::backend::types::MessageType;
+use crate::error::Error;
+use crate::error::Result;
+use crate::prelude::rings_core::ecc::keccak256;
+use crate::prelude::rings_core::utils::get_epoch_ms;
+use crate::prelude::*;
+
+/// Max length of an idempotency key.
+pub const IDEMPOTENCY_KEY_MAX_LEN: usize = 64;
+
+/// How long replies to a key are remembered.
+pub const IDEMPOTENCY_TTL: Duration = Duration::from_secs(600);
+
+/// Max number of keys remembered, the oldest key is forgotten first.
+pub const IDEMPOTENCY_CACHE_CAPACITY: usize = 4096;
+
+/// A request carrying an idempotency key, in a [BackendMessage] of [MessageType::Idempotent].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct IdempotentRequest {
+    key: String,
+    message: BackendMessage,
+}
+
+impl IdempotentRequest {
+    /// Wrap a request, fails with [Error::InvalidIdempotencyKey] if key is empty
+    /// or longer than [IDEMPOTENCY_KEY_MAX_LEN].
+    pub fn new(key: &str, message: BackendMessage) -> Result<Self> {
+        if key.is_empty() || key.len() > IDEMPOTENCY_KEY_MAX_LEN {
+            return Err(Error::InvalidIdempotencyKey(key.to_string()));
+        }
+        Ok(Self {
+            key: key.to_string(),
+            message,
+        })
+    }
+
+    /// Idempotency key of request.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// The request wrapped.
+    pub fn message(&self) -> &BackendMessage {
+        &self.message
+    }
+
+    /// Digest of the request wrapped, which a key is bound to.
+    pub fn digest(&self) -> Result<[u8; 32]> {
+        let encoded = bincode::serialize(&self.message).map_err(|_| Error::EncodeError)?;
+        Ok(keccak256(&encoded))
+    }
+}
+
+impl TryFrom<&IdempotentRequest> for BackendMessage {
+    type Error = Error;
+
+    fn try_from(req: &IdempotentRequest) -> Result<Self> {
+        BackendMessage::try_from((MessageType::Idempotent, req))
+    }
+}
+
+impl TryFrom<&BackendMessage> for IdempotentRequest {
+    type Error = Error;
+
+    /// Parse a received [BackendMessage], which should be of [MessageType::Idempotent].
+    fn try_from(msg: &BackendMessage) -> Result<Self> {
+        if !matches!(MessageType::from(msg.message_type), MessageType::Idempotent) {
+            return Err(Error::InvalidMessage);
+        }
+        let req: Self = bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .allow_trailing_bytes()
+            .with_limit(msg.data.len() as u64)
+            .deserialize(&msg.data)
+            .map_err(|_| Error::DecodeError)?;
+        Self::new(&req.key, req.message)
+    }
+}
+
+/// State of a key when a request with it is received, see [IdempotencyCache::begin].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdempotencyState {
+    /// The key is new, the request should be executed.
+    New,
+    /// A request with the key is being executed.
+    InProgress,
+    /// A request with the key was executed with these replies.
+    Done(Vec<Message>),
+    /// The key is remembered for a different request.
+    Conflict,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    created_at: u128,
+    digest: [u8; 32],
+    replies: Option<Vec<Message>>,
+}
+
+#[derive(Debug, Default)]
+struct CacheInner {
+    entries: HashMap<(Did, String), CacheEntry>,
+    order: VecDeque<(u128, (Did, String))>,
+}
+
+/// IdempotencyCache remembers replies to idempotency keys of each requester,
+/// bounded by [IDEMPOTENCY_CACHE_CAPACITY] and limited by [IDEMPOTENCY_TTL].
+#[derive(Debug, Default)]
+pub struct IdempotencyCache(Mutex<CacheInner>);
+
+impl IdempotencyCache {
+    fn lock(&self) -> MutexGuard<'_, CacheInner> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Look up key of requester for a request of digest, a new key is marked in progress.
+    pub fn begin(&self, requester: Did, key: &str, digest: [u8; 32]) -> IdempotencyState {
+        self.begin_at(requester, key, digest, get_epoch_ms())
+    }
+
+    fn begin_at(&self, requester: Did, key: &str, digest: [u8; 32], now: u128) -> IdempotencyState {
+        let mut inner = self.lock();
+        inner.evict(now);
+
+        let id = (requester, key.to_string());
+        if let Some(entry) = inner.entries.get(&id) {
+            if entry.digest != digest {
+                return IdempotencyState::Conflict;
+            }
+            return match &entry.replies {
+                Some(replies) => IdempotencyState::Done(replies.clone()),
+                None => IdempotencyState::InProgress,
+            };
+        }
+        inner.entries.insert(id.clone(), CacheEntry {
+            created_at: now,
+            digest,
+            replies: None,
+        });
+        inner.order.push_back((now, id));
+        IdempotencyState::New
+    }
+
+    /// Remember replies of request executed, or forget the key if request failed.
+    pub fn complete(&self, requester: Did, key: &str, replies: Option<Vec<Message>>) {
+        let mut inner = self.lock();
+        let id = (requester, key.to_string());
+        match replies {
+            Some(replies) => {
+                if let Some(entry) = inner.entries.get_mut(&id) {
+                    entry.replies = Some(replies);
+                }
+            }
+            None => {
+                inner.entries.remove(&id);
+            }
+        }
+    }
+
+    /// Number of keys remembered.
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    /// Check if no key is remembered.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl CacheInner {
+    /// Forget expired keys, and the oldest keys beyond capacity.
+    fn evict(&mut self, now: u128) {
+        let ttl = IDEMPOTENCY_TTL.as_millis();
+        while let Some((created_at, id)) = self.order.front() {
+            let expired = now.saturating_sub(*created_at) >= ttl;
+            if !expired && self.entries.len() < IDEMPOTENCY_CACHE_CAPACITY {
+                break;
+            }
+            // A key forgotten and seen again is queued again, skip the stale position.
+            if self.entries.get(id).map(|e| e.created_at) == Some(*created_at) {
+                self.entries.remove(id);
+            }
+            self.order.pop_front();
+        }
+    }
+}
+
+impl Backend {
+    /// Handle an [IdempotentRequest], the wrapped request is dispatched only if its key
+    /// is new. A retry of a request still in progress is dropped, and the requester
+    /// should retry it again later. A key reused for a different request fails with
+    /// [Error::IdempotencyKeyReused].
+    pub(crate) async fn dispatch_idempotent(
+        &self,
+        payload: &MessagePayload,
+        msg: &BackendMessage,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        let req = IdempotentRequest::try_from(msg)?;
+        let requester = payload.transaction.signer();
+        match self.idempotency.begin(requester, req.key(), req.digest()?) {
+            IdempotencyState::New => {}
+            IdempotencyState::Conflict => {
+                return Err(Error::IdempotencyKeyReused(req.key().to_string()));
+            }
+            IdempotencyState::InProgress => {
+                tracing::debug!("request {} of {} is in progress", req.key(), requester);
+                return Ok(vec![]);
+            }
+            IdempotencyState::Done(replies) => {
+                tracing::debug!("request {} of {} is replayed", req.key(), requester);
+                return Ok(replies
+                    .into_iter()
+                    .map(|reply| MessageHandlerEvent::SendReportMessage(payload.clone(), reply))
+                    .collect());
+            }
+        }
+
+        let requested = Self::requested_service(req.message());
+        let result = self
+            .dispatch_message(payload, req.message(), requested.as_deref())
+            .await;
+        // Only replies are remembered, other effects of handling are never replayed.
+        let replies = result.as_ref().ok().map(|evs| {
+            evs.iter()
+                .filter_map(|ev| match ev {
+                    MessageHandlerEvent::SendReportMessage(_, reply) => Some(reply.clone()),
+                    _ => None,
+                })
+                .collect()
+        });
+        self.idempotency.complete(requester, req.key(), replies);
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DIGEST: [u8; 32] = [1; 32];
+
+    #[test]
+    fn test_idempotent_request() {
+        let inner = BackendMessage::from((MessageType::SimpleText.into(), "hello".as_bytes()));
+        let req = IdempotentRequest::new("order-1", inner.clone()).unwrap();
+        let msg = BackendMessage::try_from(&req).unwrap();
+        let parsed = IdempotentRequest::try_from(&msg).unwrap();
+        assert_eq!(parsed.key(), "order-1");
+        assert_eq!(parsed.message(), &inner);
+
+        assert!(IdempotentRequest::new("", inner.clone()).is_err());
+        let long = "k".repeat(IDEMPOTENCY_KEY_MAX_LEN + 1);
+        assert!(matches!(
+            IdempotentRequest::new(&long, inner),
+            Err(Error::InvalidIdempotencyKey(_))
+        ));
+    }
+
+    #[test]
+    fn test_idempotency_cache() {
+        let cache = IdempotencyCache::default();
+        let peer: Did = SecretKey::random().address().into();
+        let other: Did = SecretKey::random().address().into();
+        let reply = Message::custom(b"done").unwrap();
+
+        assert_eq!(cache.begin_at(peer, "a", DIGEST, 0), IdempotencyState::New);
+        assert_eq!(
+            cache.begin_at(peer, "a", DIGEST, 1),
+            IdempotencyState::InProgress
+        );
+        // Keys are scoped by requester.
+        assert_eq!(cache.begin_at(other, "a", DIGEST, 1), IdempotencyState::New);
+
+        cache.complete(peer, "a", Some(vec![reply.clone()]));
+        assert_eq!(
+            cache.begin_at(peer, "a", DIGEST, 2),
+            IdempotencyState::Done(vec![reply])
+        );
+
+        // A failed request can be retried.
+        cache.complete(other, "a", None);
+        assert_eq!(cache.begin_at(other, "a", DIGEST, 3), IdempotencyState::New);
+
+        // A key reused for a different request is rejected.
+        assert_eq!(
+            cache.begin_at(peer, "a", [2; 32], 2),
+            IdempotencyState::Conflict
+        );
+        let other_req = IdempotentRequest::new(
+            "order-1",
+            BackendMessage::from((MessageType::SimpleText.into(), "bye".as_bytes())),
+        )
+        .unwrap();
+        let req = IdempotentRequest::new(
+            "order-1",
+            BackendMessage::from((MessageType::SimpleText.into(), "hello".as_bytes())),
+        )
+        .unwrap();
+        assert_ne!(req.digest().unwrap(), other_req.digest().unwrap());
+
+        // Keys expire after TTL.
+        let ttl = IDEMPOTENCY_TTL.as_millis();
+        assert_eq!(
+            cache.begin_at(peer, "a", DIGEST, ttl),
+            IdempotencyState::New
+        );
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_idempotency_cache_bounded() {
+        let cache = IdempotencyCache::default();
+        let peer: Did = SecretKey::random().address().into();
+        for i in 0..IDEMPOTENCY_CACHE_CAPACITY + 10 {
+            cache.begin_at(peer, &i.to_string(), DIGEST, i as u128);
+        }
+        assert_eq!(cache.len(), IDEMPOTENCY_CACHE_CAPACITY);
+        // The oldest keys are forgotten first.
+        assert_eq!(
+            cache.begin_at(
+                peer,
+                &(IDEMPOTENCY_CACHE_CAPACITY + 9).to_string(),
+                DIGEST,
+                0
+            ),
+            IdempotencyState::InProgress
+        );
+        assert_eq!(cache.begin_at(peer, "0", DIGEST, 0), IdempotencyState::New);
+    }
+}
@@ -1,16 +1,28 @@
-#![allow(clippy::ptr_offset_with_cast)]
 //! An Backend HTTP service handle custom message from `MessageHandler` as CallbackFn.
+pub mod access_log;
+pub mod arq;
+pub mod broadcast;
+pub mod bulkhead;
+pub mod capture;
+pub mod echo;
 pub mod http_server;
+pub mod idempotency;
 pub mod proxy;
+pub mod request;
+pub mod response_cache;
+pub mod self_test;
 pub mod tcp_server;
 pub mod text;
+pub mod transfer;
+pub mod tunnel_pool;
+pub mod typed;
 pub mod utils;
 
 use std::sync::Arc;
 
-use arrayref::array_refs;
 use async_trait::async_trait;
 use bytes::Bytes;
+use rings_transport::core::transport::WebrtcConnectionState;
 use serde::Deserialize;
 use serde::Serialize;
 use tokio::sync::broadcast::Sender;
@@ -18,32 +30,98 @@ use tokio::sync::Mutex;
 
 use crate::backend::extension::Extension;
 use crate::backend::extension::ExtensionConfig;
+use crate::backend::extension::ExtensionSender;
+use crate::backend::extension::ExtensionStats;
+use crate::backend::service::access_log::AccessLog;
+use crate::backend::service::access_log::AccessLogConfig;
+use crate::backend::service::arq::ArqConfig;
+use crate::backend::service::arq::ChunkArq;
+use crate::backend::service::broadcast::BroadcastConfig;
+use crate::backend::service::broadcast::BroadcastStats;
+use crate::backend::service::broadcast::Broadcaster;
+use crate::backend::service::capture::CaptureConfig;
+use crate::backend::service::capture::TunnelCaptures;
+use crate::backend::service::echo::EchoEndpoint;
 use crate::backend::service::http_server::HttpServer;
 use crate::backend::service::http_server::HttpServiceConfig;
+use crate::backend::service::idempotency::IdempotencyCache;
+use crate::backend::service::proxy::TunnelMessage;
+use crate::backend::service::proxy::TunnelQueueConfig;
+use crate::backend::service::request::InFlightLimit;
+use crate::backend::service::request::PendingRequests;
+use crate::backend::service::response_cache::ResponseCacheStats;
 use crate::backend::service::tcp_server::TcpServer;
 use crate::backend::service::tcp_server::TcpServiceConfig;
 use crate::backend::service::text::TextEndpoint;
+use crate::backend::service::transfer::TransferConfig;
+use crate::backend::service::transfer::TransferEndpoint;
+use crate::backend::service::tunnel_pool::TunnelPoolConfig;
+use crate::backend::service::typed::TypedEndpoint;
+use crate::backend::types::is_transformed_custom_message;
+use crate::backend::types::split_custom_message;
 use crate::backend::types::BackendMessage;
+use crate::backend::types::ErrorResponse;
 use crate::backend::types::MessageEndpoint;
 use crate::backend::types::MessageType;
+use crate::backend::types::CUSTOM_MESSAGE_FLAG_ACKED_CHUNK;
+use crate::backend::types::CUSTOM_MESSAGE_FLAG_ACK_REQUEST;
 use crate::consts::BACKEND_MTU;
+use crate::consts::MAX_IN_FLIGHT_PER_PEER;
+use crate::drain::Drain;
 use crate::error::Error;
 use crate::error::Result;
 use crate::prelude::rings_core::chunk::Chunk;
-use crate::prelude::rings_core::chunk::ChunkList;
 use crate::prelude::rings_core::chunk::ChunkManager;
+use crate::prelude::rings_core::chunk::ChunkPool;
+use crate::prelude::rings_core::chunk::DEFAULT_PEER_CHUNK_BUDGET;
+use crate::prelude::rings_core::consts::MAX_DECOMPRESSED_SIZE;
+use crate::prelude::rings_core::dht::Chord;
+use crate::prelude::rings_core::dht::Did;
+use crate::prelude::rings_core::dht::PeerRingAction;
 use crate::prelude::rings_core::swarm::callback::SwarmCallback;
+use crate::prelude::rings_core::swarm::callback::SwarmEvent;
+use crate::prelude::rings_core::swarm::AuthAction;
+use crate::prelude::rings_core::swarm::Decision;
+use crate::prelude::rings_core::swarm::DropReason;
+use crate::prelude::rings_core::swarm::PeerSignal;
+use crate::prelude::rings_core::traffic::TrafficCounters;
+use crate::prelude::rings_core::traffic::TrafficMeter;
+use crate::prelude::rings_core::utils::get_epoch_ms;
 use crate::prelude::*;
 
+/// Length of custom message carried by event, used for traffic accounting.
+fn custom_message_len(ev: &MessageHandlerEvent) -> usize {
+    match ev {
+        MessageHandlerEvent::SendMessage(Message::CustomMessage(CustomMessage(m)), _)
+        | MessageHandlerEvent::SendDirectMessage(Message::CustomMessage(CustomMessage(m)), _)
+        | MessageHandlerEvent::SendReportMessage(_, Message::CustomMessage(CustomMessage(m))) => {
+            m.len()
+        }
+        _ => 0,
+    }
+}
+
 /// A Backend struct contains http_server.
 pub struct Backend {
     pub swarm: Arc<Swarm>,
     http_server: Arc<HttpServer>,
     pub tcp_server: Arc<TcpServer>,
     text_endpoint: TextEndpoint,
+    echo_endpoint: EchoEndpoint,
+    typed_endpoint: TypedEndpoint,
+    pub transfer_endpoint: Arc<TransferEndpoint>,
     extension_endpoint: Extension,
-    sender: Sender<BackendMessage>,
-    chunk_list: Arc<Mutex<ChunkList<BACKEND_MTU>>>,
+    broadcaster: Broadcaster,
+    chunk_pool: Arc<Mutex<ChunkPool<BACKEND_MTU, MessagePayload>>>,
+    arq: Arc<ChunkArq>,
+    traffic: TrafficMeter<String>,
+    pending: PendingRequests,
+    idempotency: IdempotencyCache,
+    outgoing_in_flight: InFlightLimit,
+    incoming_in_flight: InFlightLimit,
+    drain: Drain,
+    unknown_destination: UnknownDestinationPolicy,
+    max_message_size: usize,
 }
 
 /// BackendConfig
@@ -55,6 +133,92 @@ pub struct BackendConfig {
     pub tcp_services: Vec<TcpServiceConfig>,
     /// extension
     pub extensions: ExtensionConfig,
+    /// max number of in-flight requests per peer, in each direction.
+    /// Defaults to [MAX_IN_FLIGHT_PER_PEER].
+    #[serde(default)]
+    pub max_in_flight_per_peer: Option<usize>,
+    /// capture of tunnels, disabled if not provided
+    #[serde(default)]
+    pub tunnel_capture: Option<CaptureConfig>,
+    /// access log of http and tcp services, disabled if not provided
+    #[serde(default)]
+    pub access_log: Option<AccessLogConfig>,
+    /// behaviour on messages addressed to other nodes
+    #[serde(default)]
+    pub unknown_destination: UnknownDestinationPolicy,
+    /// workers and read buffers shared by tunnels, each tunnel has its own if not provided
+    #[serde(default)]
+    pub tunnel_pool: Option<TunnelPoolConfig>,
+    /// queue of packages from peer in each tunnel, see [TunnelOverflow](proxy::TunnelOverflow)
+    #[serde(default)]
+    pub tunnel_queue: TunnelQueueConfig,
+    /// max size of a message reassembled from chunks or decompressed, in bytes.
+    /// Defaults to [MAX_DECOMPRESSED_SIZE].
+    #[serde(default)]
+    pub max_message_size: Option<usize>,
+    /// max bytes of incomplete chunked messages buffered for each peer, the oldest message
+    /// of peer is dropped when it's exceeded. Defaults to [DEFAULT_PEER_CHUNK_BUDGET].
+    #[serde(default)]
+    pub peer_chunk_budget: Option<usize>,
+    /// broadcast of handled messages to local consumers, see [Broadcaster]
+    #[serde(default)]
+    pub broadcast: BroadcastConfig,
+    /// acknowledged chunks sent by [ChunkArq::send], such as segments of transfers
+    #[serde(default)]
+    pub chunk_arq: ArqConfig,
+    /// resumable transfers, disabled by default, see [TransferConfig]
+    #[serde(default)]
+    pub transfer: TransferConfig,
+}
+
+/// Behaviour on a message which reaches this node while addressed to another node,
+/// such as one misrouted by a stale finger table.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UnknownDestinationPolicy {
+    /// Drop it silently, which suits an endpoint node.
+    #[default]
+    Drop,
+    /// Forward it toward the destination by DHT routing, which suits a relay node.
+    Relay,
+    /// Drop it with a log.
+    Log,
+}
+
+impl UnknownDestinationPolicy {
+    /// Events handling a payload whose destination is not the node of swarm.
+    /// Payloads still on their way are forwarded by swarm, so only the ones relayed
+    /// to this node are routed again, by the successor of their destination.
+    pub fn events(&self, swarm: &Swarm, payload: &MessagePayload) -> Vec<MessageHandlerEvent> {
+        let destination = payload.transaction.destination;
+        match self {
+            Self::Drop => vec![],
+            Self::Log => {
+                tracing::info!(
+                    "Drop message {} addressed to {}",
+                    payload.transaction.tx_id,
+                    destination
+                );
+                vec![]
+            }
+            Self::Relay if payload.relay.destination != swarm.did() => vec![],
+            Self::Relay => {
+                let next = match swarm.dht().find_successor(destination) {
+                    Ok(PeerRingAction::Some(did)) | Ok(PeerRingAction::RemoteAction(did, _)) => did,
+                    _ => swarm.did(),
+                };
+                if next == swarm.did() {
+                    tracing::warn!(
+                        "No route to {}, drop message {}",
+                        destination,
+                        payload.transaction.tx_id
+                    );
+                    return vec![];
+                }
+                vec![MessageHandlerEvent::ResetDestination(payload.clone(), next)]
+            }
+        }
+    }
 }
 
 /// HiddenServerMode
@@ -75,27 +239,366 @@ pub enum HiddenServerMode {
 impl Backend {
     /// new backend
     /// - `ipfs_gateway`
+    /// - `drain`: draining state shared with processor, see [Processor::drain](crate::processor::Processor::drain)
     pub async fn new(
         config: BackendConfig,
         sender: Sender<BackendMessage>,
         swarm: Arc<Swarm>,
+        drain: Drain,
     ) -> Result<Self> {
-        Ok(Self {
+        let max_in_flight = config
+            .max_in_flight_per_peer
+            .unwrap_or(MAX_IN_FLIGHT_PER_PEER);
+        let access_log = config
+            .access_log
+            .as_ref()
+            .map(AccessLog::open)
+            .transpose()?;
+        let tunnel_capture = config.tunnel_capture.map(TunnelCaptures::new).transpose()?;
+        let arq = Arc::new(ChunkArq::new(config.chunk_arq));
+        let backend = Self {
             swarm: swarm.clone(),
-            http_server: Arc::new(HttpServer::from(config.http_services)),
-            tcp_server: Arc::new(TcpServer::new(config.tcp_services, swarm.clone())),
+            http_server: Arc::new(
+                HttpServer::from(config.http_services)
+                    .with_swarm(swarm.clone())
+                    .with_access_log(access_log.clone()),
+            ),
+            tcp_server: Arc::new(
+                TcpServer::new(config.tcp_services, swarm.clone())
+                    .with_capture(tunnel_capture)
+                    .with_access_log(access_log)
+                    .with_pool(config.tunnel_pool)
+                    .with_queue(config.tunnel_queue)
+                    .with_drain(drain.clone()),
+            ),
             text_endpoint: TextEndpoint,
-            sender,
-            extension_endpoint: Extension::new(&config.extensions).await?,
-            chunk_list: Default::default(),
-        })
+            echo_endpoint: EchoEndpoint,
+            typed_endpoint: TypedEndpoint,
+            transfer_endpoint: Arc::new(
+                TransferEndpoint::new(swarm.clone())
+                    .with_config(config.transfer)
+                    .with_arq(arq.clone()),
+            ),
+            broadcaster: Broadcaster::new(sender, config.broadcast, swarm.metrics()),
+            extension_endpoint: Extension::new(&config.extensions, swarm.clone()).await?,
+            chunk_pool: Arc::new(Mutex::new(ChunkPool::new(
+                config
+                    .peer_chunk_budget
+                    .unwrap_or(DEFAULT_PEER_CHUNK_BUDGET),
+            ))),
+            arq,
+            traffic: TrafficMeter::default(),
+            pending: PendingRequests::default(),
+            idempotency: IdempotencyCache::default(),
+            outgoing_in_flight: InFlightLimit::new(max_in_flight),
+            incoming_in_flight: InFlightLimit::new(max_in_flight),
+            drain,
+            unknown_destination: config.unknown_destination,
+            max_message_size: config.max_message_size.unwrap_or(MAX_DECOMPRESSED_SIZE),
+        };
+        backend.start_endpoints().await?;
+        Ok(backend)
+    }
+
+    fn endpoints(&self) -> [&(dyn MessageEndpoint + Send + Sync); 7] {
+        [
+            &self.text_endpoint,
+            &self.echo_endpoint,
+            &self.typed_endpoint,
+            self.transfer_endpoint.as_ref(),
+            self.http_server.as_ref(),
+            self.tcp_server.as_ref(),
+            &self.extension_endpoint,
+        ]
     }
 
-    async fn handle_chunk_data(&self, data: &[u8]) -> Result<Option<Bytes>> {
+    async fn start_endpoints(&self) -> Result<()> {
+        for endpoint in self.endpoints() {
+            endpoint.on_start().await?;
+        }
+        Ok(())
+    }
+
+    /// Shutdown backend, all endpoints will be stopped.
+    /// Errors of endpoints are logged, so that every endpoint has a chance to stop.
+    pub async fn shutdown(&self) {
+        for endpoint in self.endpoints() {
+            if let Err(e) = endpoint.on_stop().await {
+                tracing::error!("stop backend endpoint failed: {}", e);
+            }
+        }
+    }
+
+    /// Reassemble chunks of a message. A message claiming more than `max_message_size`
+    /// bytes is rejected by its first chunk, and its chunks received are discarded.
+    /// Incomplete messages of a sender over its budget are dropped, see [ChunkPool],
+    /// and the payload carrying the first chunk of each is delivered to the deadletter.
+    async fn handle_chunk_data(
+        &self,
+        payload: &MessagePayload,
+        data: &[u8],
+    ) -> Result<Option<Bytes>> {
         let chunk_item = Chunk::from_bincode(data).map_err(|_| Error::DecodeError)?;
-        let mut chunk_list = self.chunk_list.lock().await;
-        let data = chunk_list.handle(chunk_item);
-        Ok(data)
+        self.handle_chunk(payload, chunk_item).await
+    }
+
+    /// Same as [Backend::handle_chunk_data], with the chunk decoded.
+    async fn handle_chunk(
+        &self,
+        payload: &MessagePayload,
+        chunk_item: Chunk,
+    ) -> Result<Option<Bytes>> {
+        let sender = payload.transaction.signer();
+        let mut chunk_pool = self.chunk_pool.lock().await;
+        if chunk_item.declared_len() > self.max_message_size {
+            chunk_pool.remove(chunk_item.meta.id);
+            return Err(Error::MessageTooLarge(self.max_message_size));
+        }
+        let (data, evicted) = chunk_pool.handle_from_with(sender, chunk_item, || payload.clone());
+        for e in evicted {
+            tracing::warn!(
+                "Drop chunked message {} of {} bytes from {}, over budget of {} bytes",
+                e.id,
+                e.bytes,
+                e.did,
+                chunk_pool.peer_budget()
+            );
+            self.swarm
+                .record_drop(DropReason::OverBudget, Some(&e.origin));
+        }
+        match data {
+            Some(data) if data.len() > self.max_message_size => {
+                Err(Error::MessageTooLarge(self.max_message_size))
+            }
+            _ => Ok(data),
+        }
+    }
+
+    /// Route a message tagged with service id to the server hosting that service.
+    /// A http request is only routed to a http service, and a tunnel message to a tcp one,
+    /// so a tag naming a service of the other kind is refused.
+    async fn handle_service_message(
+        &self,
+        ctx: &MessagePayload,
+        msg: &BackendMessage,
+        service: &str,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        let is_http = self.http_server.service(service).is_some();
+        let is_tcp = self.tcp_server.has_service(service);
+        let mismatch = |kind: &str| Error::ServiceTagMismatch(service.to_string(), kind.into());
+        match MessageType::from(msg.message_type) {
+            MessageType::HttpRequest if is_http => self.http_server.handle_message(ctx, msg).await,
+            MessageType::HttpRequest if is_tcp => Err(mismatch("http")),
+            MessageType::TunnelMessage if is_tcp => self.tcp_server.handle_message(ctx, msg).await,
+            MessageType::TunnelMessage if is_http => Err(mismatch("tcp")),
+            _ => Err(Error::InvalidService),
+        }
+    }
+
+    /// Name of service requested by message, which is either tagged,
+    /// or the name of http service in request. Only the name, the first field of
+    /// [HttpRequest](crate::prelude::rings_rpc::types::HttpRequest), is decoded.
+    /// A tunnel dial requests the service it connects to whatever its tag is, see
+    /// [TunnelMessage::dialed_service].
+    fn requested_service(msg: &BackendMessage) -> Option<String> {
+        if let MessageType::TunnelMessage = MessageType::from(msg.message_type) {
+            if let Some(service) = TunnelMessage::dialed_service(&msg.data) {
+                return Some(service);
+            }
+        }
+        if let Some(service) = msg.service() {
+            return Some(service.to_string());
+        }
+        match MessageType::from(msg.message_type) {
+            MessageType::HttpRequest => bincode::deserialize::<String>(&msg.data).ok(),
+            _ => None,
+        }
+    }
+
+    /// Check if a http or tcp service is hosted by this node.
+    fn hosts_service(&self, service: &str) -> bool {
+        self.http_server.service(service).is_some() || self.tcp_server.has_service(service)
+    }
+
+    /// Check if sender of message may use the requested service, see
+    /// [Swarm::authorize]. Messages of an established tunnel are checked at dialing.
+    async fn authorize_service(
+        &self,
+        payload: &MessagePayload,
+        requested: Option<&str>,
+    ) -> Result<()> {
+        let Some(service) = requested.map(str::to_string) else {
+            return Ok(());
+        };
+        let peer = payload.transaction.signer();
+        match self
+            .swarm
+            .authorize(peer, &AuthAction::Service(service.clone()))
+            .await
+        {
+            Decision::Allow => Ok(()),
+            Decision::Deny(reason) => {
+                tracing::warn!("{peer} is denied to use service {service}: {reason}");
+                Err(Error::NoPermission)
+            }
+        }
+    }
+
+    /// Dispatch a received message to the endpoint handling its type. `requested` is the
+    /// service requested by message, see [Backend::requested_service].
+    async fn dispatch_message(
+        &self,
+        payload: &MessagePayload,
+        msg: &BackendMessage,
+        requested: Option<&str>,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        // New requests are rejected while draining, new tunnels are checked by tcp server.
+        let _work = match MessageType::from(msg.message_type) {
+            MessageType::HttpRequest | MessageType::Echo => self.drain.admit()?,
+            _ => self.drain.track(),
+        };
+        self.authorize_service(payload, requested).await?;
+        match (msg.message_type.into(), msg.service()) {
+            (MessageType::HttpRequest | MessageType::TunnelMessage, Some(service)) => {
+                self.handle_service_message(payload, msg, service).await
+            }
+            (MessageType::SimpleText, _) => self.text_endpoint.handle_message(payload, msg).await,
+            (MessageType::HttpRequest, _) => self.http_server.handle_message(payload, msg).await,
+            (MessageType::TunnelMessage, _) => self.tcp_server.handle_message(payload, msg).await,
+            (MessageType::Extension, _) => {
+                self.extension_endpoint.handle_message(payload, msg).await
+            }
+            (MessageType::Echo, _) => self.echo_endpoint.handle_message(payload, msg).await,
+            (MessageType::Typed, _) => self.typed_endpoint.handle_message(payload, msg).await,
+            (MessageType::Transfer, _) => self.transfer_endpoint.handle_message(payload, msg).await,
+            (MessageType::ChunkAck, _) => {
+                self.handle_chunk_ack(payload, msg);
+                Ok(vec![])
+            }
+            (MessageType::Error, _) => {
+                match ErrorResponse::try_from(msg) {
+                    Ok(e) => tracing::warn!(
+                        "remote failed on handling message {}: {:?}, {}",
+                        payload.transaction.tx_id,
+                        e.code,
+                        e.message
+                    ),
+                    Err(e) => tracing::error!("decode error response failed: {}", e),
+                }
+                Ok(vec![])
+            }
+            _ => {
+                tracing::debug!(
+                    "custom_message handle unsupported, tag: {:?}",
+                    msg.message_type
+                );
+                Ok(vec![])
+            }
+        }
+    }
+
+    /// Get a handle for extensions to send messages proactively.
+    pub fn extension_sender(&self) -> ExtensionSender {
+        self.extension_endpoint.sender()
+    }
+
+    /// Get counters of executions of each extension.
+    pub fn extension_stats(&self) -> Vec<ExtensionStats> {
+        self.extension_endpoint.stats()
+    }
+
+    /// Name of service a message is accounted to. Messages requesting a service hosted by
+    /// this node are accounted to that service, others to the endpoint handling them, so
+    /// that peers can't grow the counters by naming arbitrary services.
+    fn traffic_key(&self, msg: &BackendMessage, requested: Option<&str>) -> String {
+        if let Some(service) = requested.filter(|s| self.hosts_service(s)) {
+            return service.to_string();
+        }
+        match MessageType::from(msg.message_type) {
+            MessageType::HttpRequest => "http".to_string(),
+            MessageType::HttpResponsePart => "http".to_string(),
+            MessageType::SimpleText => "text".to_string(),
+            MessageType::TunnelMessage => "tcp".to_string(),
+            MessageType::Extension => "extension".to_string(),
+            MessageType::Multipart => "multipart".to_string(),
+            MessageType::Transfer => "transfer".to_string(),
+            MessageType::Echo => "echo".to_string(),
+            MessageType::Typed => "typed".to_string(),
+            MessageType::Idempotent => "idempotent".to_string(),
+            MessageType::ChunkAck => "chunk".to_string(),
+            _ => "unknown".to_string(),
+        }
+    }
+
+    /// Report the error of handling message back to requester, see [ErrorResponse].
+    fn error_response(payload: &MessagePayload, e: &Error) -> Vec<MessageHandlerEvent> {
+        let resp = ErrorResponse::from(e);
+        let ev = BackendMessage::try_from((MessageType::Error, &resp))
+            .and_then(|msg| utils::send_report_message(payload, &Vec::<u8>::from(msg)));
+        match ev {
+            Ok(ev) => vec![ev],
+            Err(e) => {
+                tracing::error!("create error response failed: {}", e);
+                vec![]
+            }
+        }
+    }
+
+    /// Get cumulative bytes received and sent by service.
+    pub fn service_traffic(&self, name: &str) -> TrafficCounters {
+        self.traffic.get(&name.to_string())
+    }
+
+    /// Get cumulative bytes received and sent by all services.
+    pub fn services_traffic(&self) -> Vec<(String, TrafficCounters)> {
+        self.traffic.snapshot()
+    }
+
+    /// Get numbers of requests and tunnels being processed by services having work in
+    /// process, so that operators can tune `max_concurrency` of services.
+    pub fn services_concurrency(&self) -> Vec<(String, usize)> {
+        let mut concurrency = self.http_server.concurrency.snapshot();
+        concurrency.extend(self.tcp_server.concurrency.snapshot());
+        concurrency
+    }
+
+    /// Get counters of the response cache of http service, None if it has no cache.
+    pub fn service_cache_stats(&self, name: &str) -> Option<ResponseCacheStats> {
+        self.http_server.cache_stats(name)
+    }
+
+    /// Drop cached responses of `path` of http service, or all of them if path is None,
+    /// so that operators can purge content updated at upstream before expiry.
+    pub fn invalidate_service_cache(&self, name: &str, path: Option<&str>) -> Result<()> {
+        self.http_server.invalidate_cache(name, path)
+    }
+
+    /// Reset traffic counters of service, and return counters before reset.
+    pub fn reset_service_traffic(&self, name: &str) -> TrafficCounters {
+        self.traffic.reset(&name.to_string())
+    }
+
+    /// Get numbers of requests in flight with peer, as `(outgoing, incoming)`.
+    pub fn in_flight(&self, peer: Did) -> (usize, usize) {
+        (
+            self.outgoing_in_flight.in_flight(peer),
+            self.incoming_in_flight.in_flight(peer),
+        )
+    }
+
+    /// Get max number of in-flight requests per peer, in each direction.
+    pub fn in_flight_limit(&self) -> usize {
+        self.outgoing_in_flight.limit()
+    }
+
+    /// Get counters of the broadcast of handled messages, see [Broadcaster].
+    pub fn broadcast_stats(&self) -> BroadcastStats {
+        self.broadcaster.stats()
+    }
+
+    /// Get bytes of incomplete chunked messages buffered for peer, see [ChunkPool].
+    pub async fn chunk_memory(&self, peer: Did) -> usize {
+        self.chunk_pool.lock().await.peer_memory(peer)
     }
 
     /// Get service names from server config for storage register.
@@ -126,7 +629,12 @@ impl SwarmCallback for Backend {
         payload: &MessagePayload,
     ) -> std::result::Result<(), Box<dyn std::error::Error>> {
         if payload.transaction.destination != self.swarm.did() {
-            return Ok(());
+            let evs = self.unknown_destination.events(&self.swarm, payload);
+            return self
+                .swarm
+                .handle_message_handler_events(&evs)
+                .await
+                .map_err(|e| e.into());
         }
 
         let data: Message = payload.transaction.data()?;
@@ -135,21 +643,43 @@ impl SwarmCallback for Backend {
             return Ok(());
         };
 
-        let (left, msg) = array_refs![&msg, 4; ..;];
-        let (&[flag], _) = array_refs![left, 1, 3];
+        let transformed = is_transformed_custom_message(&msg);
+        let (flag, msg) = match split_custom_message(&msg) {
+            Ok(v) => v,
+            Err(e) => {
+                tracing::error!("decode custom_message header failed: {}", e);
+                return Ok(());
+            }
+        };
 
-        let msg = if flag == 1 {
-            let data = self.handle_chunk_data(msg).await?;
+        let msg = if flag == 0 && !transformed {
+            BackendMessage::try_from(msg)
+        } else {
+            let data = match flag {
+                0 => Some(Bytes::copy_from_slice(msg)),
+                1 => self.handle_chunk_data(payload, msg).await?,
+                CUSTOM_MESSAGE_FLAG_ACKED_CHUNK | CUSTOM_MESSAGE_FLAG_ACK_REQUEST => {
+                    self.handle_acked_chunk(payload, msg, flag == CUSTOM_MESSAGE_FLAG_ACK_REQUEST)
+                        .await?
+                }
+                _ => {
+                    tracing::warn!("invalid custom_message flag: {}", flag);
+                    return Ok(());
+                }
+            };
+            // Frames of the payload pipeline are reversed once joined.
+            let data = match data {
+                Some(data) if transformed => self
+                    .swarm
+                    .restore_payload(payload.transaction.signer(), data.to_vec())?
+                    .map(Bytes::from),
+                data => data,
+            };
             if let Some(data) = data {
                 BackendMessage::try_from(data.to_vec().as_ref())
             } else {
                 return Ok(());
             }
-        } else if flag == 0 {
-            BackendMessage::try_from(msg)
-        } else {
-            tracing::warn!("invalid custom_message flag: {}", flag);
-            return Ok(());
         };
 
         if let Err(e) = msg {
@@ -158,22 +688,54 @@ impl SwarmCallback for Backend {
         }
         let msg = msg.unwrap();
         tracing::debug!("receive custom_message: {:?}", msg);
+        if let Some(latencies) = payload.relay.trace_latencies(get_epoch_ms()) {
+            tracing::info!(
+                "custom_message {} traced, hop latencies in ms: {:?}",
+                payload.transaction.tx_id,
+                latencies
+            );
+        }
 
-        let result = match msg.message_type.into() {
-            MessageType::SimpleText => self.text_endpoint.handle_message(payload, &msg).await,
-            MessageType::HttpRequest => self.http_server.handle_message(payload, &msg).await,
-            MessageType::TunnelMessage => self.tcp_server.handle_message(payload, &msg).await,
-            MessageType::Extension => self.extension_endpoint.handle_message(payload, &msg).await,
-            _ => {
-                tracing::debug!(
-                    "custom_message handle unsupported, tag: {:?}",
-                    msg.message_type
-                );
-                Ok(vec![])
+        self.pending.dispatch(
+            payload.transaction.tx_id,
+            payload.transaction.signer(),
+            &msg,
+        );
+
+        let requested = Self::requested_service(&msg);
+        let traffic_key = self.traffic_key(&msg, requested.as_deref());
+        self.traffic.record_received(&traffic_key, msg.data.len());
+
+        // Error responses and chunk acks are never limited, so that two busy nodes never
+        // bounce them. Requests are counted against the signer, which can't be forged like
+        // the origin of relay path. A request is in flight until its response is sent.
+        let signer = payload.transaction.signer();
+        let permit = match MessageType::from(msg.message_type) {
+            MessageType::Error | MessageType::ChunkAck => Ok(None),
+            _ => self.incoming_in_flight.acquire(signer).map(Some),
+        };
+        let (_permit, result) = match permit {
+            Ok(permit) => {
+                let result = match MessageType::from(msg.message_type) {
+                    MessageType::Idempotent => self.dispatch_idempotent(payload, &msg).await,
+                    _ => {
+                        self.dispatch_message(payload, &msg, requested.as_deref())
+                            .await
+                    }
+                };
+                (permit, result)
+            }
+            Err(e) => {
+                self.swarm
+                    .record_peer_signal(signer, PeerSignal::RateLimited);
+                (None, Err(e))
             }
         };
-        if let Err(e) = self.sender.send(msg) {
-            tracing::error!("broadcast backend_message failed, {}", e);
+        self.broadcaster.send(msg).await;
+
+        if let Ok(evs) = &result {
+            let sent: usize = evs.iter().map(custom_message_len).sum();
+            self.traffic.record_sent(&traffic_key, sent);
         }
 
         match result {
@@ -184,8 +746,196 @@ impl SwarmCallback for Backend {
                 .map_err(|e| e.into()),
             Err(e) => {
                 tracing::error!("handle custom_message failed: {}", e);
-                Ok(())
+                let ev = Self::error_response(payload, &e);
+                self.swarm
+                    .handle_message_handler_events(&ev)
+                    .await
+                    .map_err(|e| e.into())
             }
         }
     }
+
+    async fn on_event(
+        &self,
+        event: &SwarmEvent,
+    ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        // Resume transfers interrupted by the dropped transport.
+        if let SwarmEvent::ConnectionStateChange {
+            peer,
+            state: WebrtcConnectionState::Connected,
+        } = event
+        {
+            let transfer_endpoint = self.transfer_endpoint.clone();
+            let peer = *peer;
+            tokio::spawn(async move { transfer_endpoint.resume_peer(peer).await });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::rings_core::swarm::Authorizer;
+    use crate::prelude::SecretKey;
+    use crate::prelude::SessionSk;
+    use crate::tests::native::prepare_processor;
+    use crate::tests::native::prepare_processor_with;
+
+    /// Allows every service except ssh.
+    struct DenySsh;
+
+    #[async_trait]
+    impl Authorizer for DenySsh {
+        async fn authorize(&self, _did: Did, action: &AuthAction) -> Decision {
+            match action {
+                AuthAction::Service(name) if name == "ssh" => Decision::Deny("ssh".to_string()),
+                _ => Decision::Allow,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_authorize_tcp_dial() {
+        let (processor, path) =
+            prepare_processor_with(None, |b| b.authorizer(Box::new(DenySsh))).await;
+        let swarm = processor.swarm.clone();
+        let config = BackendConfig {
+            tcp_services: ["web", "ssh"]
+                .into_iter()
+                .map(|name| TcpServiceConfig {
+                    name: name.to_string(),
+                    register_service: None,
+                    addr: "127.0.0.1:1".parse().unwrap(),
+                    capture: false,
+                    max_concurrency: None,
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let (sender, _) = tokio::sync::broadcast::channel(1);
+        let backend = Backend::new(config, sender, swarm.clone(), Drain::default())
+            .await
+            .unwrap();
+
+        let sender = SessionSk::new_with_seckey(&SecretKey::random()).unwrap();
+        let payload = MessagePayload::new_send(
+            Message::custom("dial".as_bytes()).unwrap(),
+            &sender,
+            swarm.did(),
+            swarm.did(),
+        )
+        .unwrap();
+        let dial = TunnelMessage::TcpDial {
+            tid: uuid::Uuid::new_v4(),
+            service: "ssh".to_string(),
+        };
+        let data = bincode::serialize(&dial).unwrap();
+        let untagged = BackendMessage::from((MessageType::TunnelMessage.into(), data.as_slice()));
+        let mismatched = untagged.clone().with_service("web");
+
+        // The dialed service is authorized, whether the dial is tagged or not.
+        for msg in [untagged, mismatched.clone()] {
+            let requested = Backend::requested_service(&msg);
+            assert_eq!(requested.as_deref(), Some("ssh"));
+            let result = backend
+                .dispatch_message(&payload, &msg, requested.as_deref())
+                .await;
+            assert!(matches!(result, Err(Error::NoPermission)));
+        }
+
+        // A dial tagged with another service is refused even if both are allowed.
+        let result = backend
+            .tcp_server
+            .handle_message(&payload, &mismatched)
+            .await;
+        assert!(matches!(
+            result,
+            Err(Error::ServiceTagMismatch(tag, service)) if tag == "web" && service == "ssh"
+        ));
+
+        tokio::fs::remove_dir_all(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_service_tag_of_other_kind() {
+        let (processor, path) = prepare_processor(None).await;
+        let swarm = processor.swarm.clone();
+        let config = BackendConfig {
+            tcp_services: vec![TcpServiceConfig {
+                name: "ssh".to_string(),
+                register_service: None,
+                addr: "127.0.0.1:1".parse().unwrap(),
+                capture: false,
+                max_concurrency: None,
+            }],
+            ..Default::default()
+        };
+        let (sender, _) = tokio::sync::broadcast::channel(1);
+        let backend = Backend::new(config, sender, swarm.clone(), Drain::default())
+            .await
+            .unwrap();
+
+        let sender = SessionSk::new_with_seckey(&SecretKey::random()).unwrap();
+        let payload = MessagePayload::new_send(
+            Message::custom("request".as_bytes()).unwrap(),
+            &sender,
+            swarm.did(),
+            swarm.did(),
+        )
+        .unwrap();
+        let data = bincode::serialize(&"ssh".to_string()).unwrap();
+        let msg = BackendMessage::from((MessageType::HttpRequest.into(), data.as_slice()))
+            .with_service("ssh");
+
+        // A http request tagged with a tcp service never reaches the tcp server.
+        let result = backend.dispatch_message(&payload, &msg, Some("ssh")).await;
+        assert!(matches!(
+            result,
+            Err(Error::ServiceTagMismatch(tag, kind)) if tag == "ssh" && kind == "http"
+        ));
+
+        tokio::fs::remove_dir_all(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_relay_unknown_destination() {
+        let (processor, path) = prepare_processor(None).await;
+        let swarm = processor.swarm.clone();
+        let key = SecretKey::random();
+        let destination = key.address().into();
+        swarm.dht().join(destination).unwrap();
+
+        // A message for the peer, which is misrouted to this node.
+        let sender = SessionSk::new_with_seckey(&SecretKey::random()).unwrap();
+        let mut payload = MessagePayload::new_send(
+            Message::custom("hello".as_bytes()).unwrap(),
+            &sender,
+            swarm.did(),
+            destination,
+        )
+        .unwrap();
+        payload.relay.destination = swarm.did();
+
+        assert!(UnknownDestinationPolicy::Drop
+            .events(&swarm, &payload)
+            .is_empty());
+        assert!(UnknownDestinationPolicy::Log
+            .events(&swarm, &payload)
+            .is_empty());
+        let evs = UnknownDestinationPolicy::Relay.events(&swarm, &payload);
+        assert!(matches!(
+            evs.as_slice(),
+            [MessageHandlerEvent::ResetDestination(p, next)]
+                if p.transaction.tx_id == payload.transaction.tx_id && *next == destination
+        ));
+
+        // A message still on its way is forwarded by swarm.
+        payload.relay.destination = destination;
+        assert!(UnknownDestinationPolicy::Relay
+            .events(&swarm, &payload)
+            .is_empty());
+
+        tokio::fs::remove_dir_all(path).await.unwrap();
+    }
 }
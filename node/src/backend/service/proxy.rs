@@ -1,19 +1,37 @@
+use std::collections::BTreeMap;
 use std::net::SocketAddr;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
 
+use bincode::Options;
 use bytes::Bytes;
 use serde::Deserialize;
 use serde::Serialize;
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
+use tokio::net::tcp::ReadHalf;
 use tokio::net::TcpStream;
 use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
 use tokio::time::timeout;
 use tokio_util::sync::CancellationToken;
 
+use crate::backend::service::access_log::TunnelLogGuard;
+use crate::backend::service::bulkhead::ServicePermit;
+use crate::backend::service::capture::CaptureDirection;
+use crate::backend::service::capture::TunnelCapture;
+use crate::backend::service::tunnel_pool::PooledTask;
+use crate::backend::service::tunnel_pool::TunnelPool;
+use crate::backend::service::tunnel_pool::TUNNEL_QUEUE_SIZE;
+use crate::backend::service::tunnel_pool::TUNNEL_READ_BUFFER_SIZE;
 use crate::backend::types::BackendMessage;
 use crate::backend::types::MessageType;
+use crate::consts::TUNNEL_CLOSE_TIMEOUT;
+use crate::drain::DrainGuard;
+use crate::error::Error;
 use crate::error::TunnelDefeat;
 use crate::prelude::rings_core::dht::Did;
 use crate::prelude::rings_core::prelude::uuid::Uuid;
@@ -23,28 +41,283 @@ use crate::prelude::Swarm;
 
 pub type TunnelId = Uuid;
 
+/// Max number of packages received ahead of a missing one. A tunnel is closed with
+/// [TunnelDefeat::PackageLost] when the gap isn't filled within this window.
+pub const TUNNEL_REORDER_WINDOW: u64 = 64;
+
+/// Bytes trailing every tunnel message of this version, which tell peer that extended tunnel
+/// messages, such as `TcpShutdownWrite` and `TcpCloseAck`, are understood. Older versions
+/// ignore trailing bytes when decoding, so extended messages are only sent to a peer once
+/// this marker is seen from it, and peers of older versions get the legacy messages.
+pub const TUNNEL_EXTENDED_MARKER: [u8; 2] = [0xE7, 0x01];
+
+/// Behaviour when the queue of packages from peer in a tunnel is full, since its local stream
+/// is written slower than peer sends.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TunnelOverflow {
+    /// Wait for room, which keeps TCP semantics. Handling of messages from peers is held
+    /// meanwhile, so one slow tunnel stalls the others, until peer slows down.
+    #[default]
+    Block,
+    /// Close the tunnel with [TunnelDefeat::Congested], so that other tunnels keep going.
+    Close,
+    /// Queue up to the given number of packages, then close as [TunnelOverflow::Close].
+    /// The queue takes memory only as it grows.
+    Grow(usize),
+}
+
+/// Config of the queue of packages from peer in each tunnel.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TunnelQueueConfig {
+    /// max number of packages queued, defaults to `queue_size` of [TunnelPool] if tunnels
+    /// are pooled, or [TUNNEL_QUEUE_SIZE]
+    #[serde(default)]
+    pub capacity: Option<usize>,
+    /// behaviour when the queue is full
+    #[serde(default)]
+    pub overflow: TunnelOverflow,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub enum TunnelMessage {
-    TcpDial { tid: TunnelId, service: String },
-    TcpClose { tid: TunnelId, reason: TunnelDefeat },
-    TcpPackage { tid: TunnelId, body: Bytes },
+    TcpDial {
+        tid: TunnelId,
+        service: String,
+    },
+    TcpClose {
+        tid: TunnelId,
+        reason: TunnelDefeat,
+    },
+    /// Bytes of local stream, sent to peers of older versions.
+    TcpPackage {
+        tid: TunnelId,
+        body: Bytes,
+    },
+    /// Acknowledge a `TcpClose` after the remaining packages are written to local stream.
+    /// Extended, see [TUNNEL_EXTENDED_MARKER].
+    TcpCloseAck {
+        tid: TunnelId,
+    },
+    /// Local stream of sender reached EOF after `seq` packages, the receiver shuts down
+    /// writing of its local stream once they are written, while the opposite direction
+    /// keeps open. Extended, see [TUNNEL_EXTENDED_MARKER].
+    TcpShutdownWrite {
+        tid: TunnelId,
+        seq: u64,
+    },
+    /// Bytes of local stream, `seq` counts packages of the tunnel from 0, so that receiver
+    /// restores the order of bytes even if packages are delivered out of order.
+    /// Extended, see [TUNNEL_EXTENDED_MARKER].
+    TcpSequencedPackage {
+        tid: TunnelId,
+        seq: u64,
+        body: Bytes,
+    },
+    /// Acknowledge a `TcpDial` once the service is connected, so that the dialer knows the
+    /// tunnel is open. Extended, see [TUNNEL_EXTENDED_MARKER].
+    TcpDialAck {
+        tid: TunnelId,
+    },
+}
+
+/// Data from peer to be written to local stream.
+#[derive(Debug)]
+enum RemoteData {
+    Package(u64, Bytes),
+    ShutdownWrite(u64),
+}
+
+/// ReorderBuffer restores the order of packages by their sequence numbers.
+/// Duplicated packages are dropped, and packages ahead of a missing one are held
+/// until it arrives, up to [TUNNEL_REORDER_WINDOW].
+#[derive(Debug, Default)]
+pub struct ReorderBuffer {
+    next: u64,
+    pending: BTreeMap<u64, Bytes>,
+}
+
+impl ReorderBuffer {
+    /// Sequence number of the next package to be written.
+    pub fn next_seq(&self) -> u64 {
+        self.next
+    }
+
+    /// Take a package, returns packages ready to be written in order.
+    /// Fails with [TunnelDefeat::PackageLost] if the package is too far ahead.
+    pub fn push(&mut self, seq: u64, body: Bytes) -> Result<Vec<Bytes>, TunnelDefeat> {
+        if seq < self.next {
+            tracing::debug!("Drop duplicated package {seq}, expected {}", self.next);
+            return Ok(vec![]);
+        }
+        if seq - self.next >= TUNNEL_REORDER_WINDOW {
+            return Err(TunnelDefeat::PackageLost);
+        }
+        self.pending.insert(seq, body);
+
+        let mut ready = vec![];
+        while let Some(body) = self.pending.remove(&self.next) {
+            ready.push(body);
+            self.next += 1;
+        }
+        Ok(ready)
+    }
+}
+
+/// Why the remote stream stops being written to local stream.
+enum RemoteExit {
+    /// Remote stream is closed by [Tunnel::close].
+    Closed,
+    /// Peer half-closed the tunnel by `TcpShutdownWrite`.
+    ShutdownWrite,
+    Defeat(TunnelDefeat),
+}
+
+impl TunnelMessage {
+    /// Decode untrusted bytes, and check if the sender understands extended messages,
+    /// see [TUNNEL_EXTENDED_MARKER].
+    pub fn decode(value: &[u8]) -> Result<(Self, bool), Error> {
+        let options = bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .allow_trailing_bytes();
+        let msg: Self = options
+            .with_limit(value.len() as u64)
+            .deserialize(value)
+            .map_err(|_| Error::DecodeError)?;
+        let len = options
+            .serialized_size(&msg)
+            .map_err(|_| Error::DecodeError)? as usize;
+        let extended = value
+            .get(len..)
+            .is_some_and(|rest| rest.starts_with(&TUNNEL_EXTENDED_MARKER));
+        Ok((msg, extended))
+    }
+
+    /// Name of service dialed by `value`, if it's an encoded [TunnelMessage::TcpDial].
+    /// Only the variant tag, the first 4 bytes, is read from other messages, which may
+    /// carry large packages.
+    pub fn dialed_service(value: &[u8]) -> Option<String> {
+        if value.get(..4)? != [0u8; 4] {
+            return None;
+        }
+        match Self::decode(value).ok()?.0 {
+            Self::TcpDial { service, .. } => Some(service),
+            _ => None,
+        }
+    }
+
+    /// Id of the tunnel which the message belongs to.
+    pub fn tid(&self) -> TunnelId {
+        match self {
+            Self::TcpDial { tid, .. }
+            | Self::TcpClose { tid, .. }
+            | Self::TcpPackage { tid, .. }
+            | Self::TcpCloseAck { tid }
+            | Self::TcpShutdownWrite { tid, .. }
+            | Self::TcpSequencedPackage { tid, .. }
+            | Self::TcpDialAck { tid } => *tid,
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for TunnelMessage {
+    type Error = Error;
+
+    /// Decode untrusted bytes. Reading is limited to the length of input,
+    /// so that a forged length prefix cannot cause an unbounded allocation.
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        Self::decode(value).map(|(msg, _)| msg)
+    }
 }
 
 pub struct Tunnel {
     tid: TunnelId,
-    remote_stream_tx: Option<mpsc::Sender<Bytes>>,
+    remote_stream_tx: Option<mpsc::Sender<RemoteData>>,
     listener_cancel_token: Option<CancellationToken>,
-    listener: Option<tokio::task::JoinHandle<()>>,
+    listener: Option<ListenerHandle>,
+    capture: Option<TunnelCapture>,
+    drain_guard: Option<DrainGuard>,
+    service_permit: Option<ServicePermit>,
+    access_log: Option<TunnelLogGuard>,
+    pool: Option<TunnelPool>,
+    queue: TunnelQueueConfig,
+    congested: CancellationToken,
+    peer_extended: Arc<AtomicBool>,
+    legacy_seq: AtomicU64,
 }
 
 pub struct TunnelListener {
     tid: TunnelId,
     local_stream: TcpStream,
-    remote_stream_tx: mpsc::Sender<Bytes>,
-    remote_stream_rx: mpsc::Receiver<Bytes>,
+    remote_stream_rx: mpsc::Receiver<RemoteData>,
     swarm: Arc<Swarm>,
     peer_did: Did,
     cancel_token: CancellationToken,
+    capture: Option<TunnelCapture>,
+    pool: Option<TunnelPool>,
+    congested: CancellationToken,
+    peer_extended: Arc<AtomicBool>,
+}
+
+/// Handle of the listener of a tunnel, which is driven by a task of its own, or by a worker
+/// of [TunnelPool].
+enum ListenerHandle {
+    Task(tokio::task::JoinHandle<()>),
+    Pooled(PooledTask),
+}
+
+impl ListenerHandle {
+    fn is_finished(&self) -> bool {
+        match self {
+            Self::Task(handle) => handle.is_finished(),
+            Self::Pooled(task) => task.is_finished(),
+        }
+    }
+
+    /// Wait until the listener exits.
+    async fn finished(&mut self) {
+        match self {
+            Self::Task(handle) => {
+                let _ = handle.await;
+            }
+            Self::Pooled(task) => task.finished().await,
+        }
+    }
+
+    fn abort(&self) {
+        match self {
+            Self::Task(handle) => handle.abort(),
+            Self::Pooled(task) => task.abort(),
+        }
+    }
+}
+
+/// Buffer of reading local stream, owned by tunnel or borrowed from [TunnelPool].
+enum ReadBuffer {
+    Owned(Vec<u8>),
+    Pooled(TunnelPool),
+}
+
+impl ReadBuffer {
+    /// Read local stream, returns empty bytes on EOF.
+    async fn read(&mut self, stream: &mut ReadHalf<'_>) -> std::io::Result<Bytes> {
+        match self {
+            Self::Owned(buf) => {
+                let n = stream.read(buf).await?;
+                Ok(Bytes::copy_from_slice(&buf[..n]))
+            }
+            // Borrow a buffer only when there is something to read.
+            Self::Pooled(pool) => loop {
+                stream.readable().await?;
+                let mut buf = pool.acquire().await;
+                match stream.try_read(&mut buf) {
+                    Ok(n) => return Ok(Bytes::copy_from_slice(&buf[..n])),
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+                    Err(e) => return Err(e),
+                }
+            },
+        }
+    }
 }
 
 impl Drop for Tunnel {
@@ -71,14 +344,117 @@ impl Tunnel {
             remote_stream_tx: None,
             listener: None,
             listener_cancel_token: None,
+            capture: None,
+            drain_guard: None,
+            service_permit: None,
+            access_log: None,
+            pool: None,
+            queue: TunnelQueueConfig::default(),
+            congested: CancellationToken::new(),
+            peer_extended: Arc::new(AtomicBool::new(false)),
+            legacy_seq: AtomicU64::new(0),
         }
     }
 
-    pub async fn send(&self, bytes: Bytes) {
-        if let Some(ref tx) = self.remote_stream_tx {
-            let _ = tx.send(bytes).await;
-        } else {
+    /// Mark peer as understanding extended messages, once [TUNNEL_EXTENDED_MARKER] is seen
+    /// from it.
+    pub fn set_peer_extended(&self) {
+        self.peer_extended.store(true, Ordering::Relaxed);
+    }
+
+    /// Check if peer understands extended messages, see [TUNNEL_EXTENDED_MARKER].
+    pub fn is_peer_extended(&self) -> bool {
+        self.peer_extended.load(Ordering::Relaxed)
+    }
+
+    /// Set the queue of packages from peer, see [TunnelOverflow]. Should be set before
+    /// listening.
+    pub fn with_queue(mut self, queue: TunnelQueueConfig) -> Self {
+        self.queue = queue;
+        self
+    }
+
+    /// Drive the tunnel by workers of [TunnelPool], sharing read buffers with other tunnels.
+    /// Should be set before listening.
+    pub fn with_pool(mut self, pool: Option<TunnelPool>) -> Self {
+        self.pool = pool;
+        self
+    }
+
+    /// Hold the guard of draining until the listener exits, should be set before listening.
+    pub fn with_drain_guard(mut self, guard: DrainGuard) -> Self {
+        self.drain_guard = Some(guard);
+        self
+    }
+
+    /// Hold the permit of service concurrency until the listener exits,
+    /// should be set before listening.
+    pub fn with_service_permit(mut self, permit: ServicePermit) -> Self {
+        self.service_permit = Some(permit);
+        self
+    }
+
+    /// Record the tunnel to access log as closed once the listener exits, should be set
+    /// before listening.
+    pub fn with_access_log(mut self, guard: Option<TunnelLogGuard>) -> Self {
+        self.access_log = guard;
+        self
+    }
+
+    /// Record bytes flowing through the tunnel, should be set before listening.
+    pub fn with_capture(mut self, capture: TunnelCapture) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    /// Write package `seq` of peer to local stream, after the packages before it.
+    pub async fn send(&self, seq: u64, bytes: Bytes) {
+        self.send_remote_data(RemoteData::Package(seq, bytes)).await
+    }
+
+    /// Send a `TcpPackage` from peer of an older version, which is numbered in order of
+    /// arrival. Peer sends packages of both kinds from one sequence, and only switches to
+    /// sequenced packages, so the numbers are consistent.
+    pub async fn send_legacy(&self, bytes: Bytes) {
+        let seq = self.legacy_seq.fetch_add(1, Ordering::Relaxed);
+        self.send(seq, bytes).await
+    }
+
+    /// Shut down writing of local stream after the `seq` packages sent by peer,
+    /// on `TcpShutdownWrite` from peer.
+    pub async fn shutdown_write(&self, seq: u64) {
+        self.send_remote_data(RemoteData::ShutdownWrite(seq)).await
+    }
+
+    async fn send_remote_data(&self, data: RemoteData) {
+        let Some(ref tx) = self.remote_stream_tx else {
             tracing::error!("Tunnel {} remote stream tx is none", self.tid);
+            return;
+        };
+        if self.queue.overflow == TunnelOverflow::Block {
+            let _ = tx.send(data).await;
+            return;
+        }
+        if self.congested.is_cancelled() {
+            return;
+        }
+        if let Err(TrySendError::Full(_)) = tx.try_send(data) {
+            tracing::warn!("Tunnel {} is congested, closing", self.tid);
+            self.congested.cancel();
+        }
+    }
+
+    /// Number of packages queued by the tunnel, the queue grows up to it.
+    fn queue_size(&self) -> usize {
+        let capacity = self
+            .queue
+            .capacity
+            .or_else(|| self.pool.as_ref().map(|p| p.queue_size()))
+            .unwrap_or(TUNNEL_QUEUE_SIZE)
+            .max(1);
+        match self.queue.overflow {
+            TunnelOverflow::Grow(limit) => limit.max(capacity),
+            _ => capacity,
         }
     }
 
@@ -87,29 +463,83 @@ impl Tunnel {
             return;
         }
 
-        let mut listener = TunnelListener::new(self.tid, local_stream, swarm, peer_did).await;
+        let (mut listener, remote_stream_tx) = TunnelListener::new(
+            self.tid,
+            local_stream,
+            swarm,
+            peer_did,
+            self.pool.clone(),
+            self.queue_size(),
+        )
+        .await;
+        listener.capture = self.capture.clone();
+        listener.congested = self.congested.clone();
+        listener.peer_extended = self.peer_extended.clone();
         let listener_cancel_token = listener.cancel_token();
-        let remote_stream_tx = listener.remote_stream_tx.clone();
-        let listener_handler = tokio::spawn(Box::pin(async move { listener.listen().await }));
+        let drain_guard = self.drain_guard.take();
+        let service_permit = self.service_permit.take();
+        let access_log = self.access_log.take();
+        let listen = async move {
+            listener.listen().await;
+            drop(access_log);
+            drop(service_permit);
+            drop(drain_guard);
+        };
+        let listener_handler = match self.pool {
+            Some(ref pool) => ListenerHandle::Pooled(pool.spawn(listen)),
+            None => ListenerHandle::Task(tokio::spawn(Box::pin(listen))),
+        };
 
         self.remote_stream_tx = Some(remote_stream_tx);
         self.listener = Some(listener_handler);
         self.listener_cancel_token = Some(listener_cancel_token);
     }
+
+    /// Check if the listener is exited.
+    pub fn is_finished(&self) -> bool {
+        self.listener
+            .as_ref()
+            .map(|l| l.is_finished())
+            .unwrap_or(false)
+    }
+
+    /// Close the tunnel gracefully, on `TcpClose` or `TcpCloseAck` from peer.
+    /// The remote stream is closed, so that listener exits after writing remaining
+    /// packages to local stream. Waiting is bounded by [TUNNEL_CLOSE_TIMEOUT].
+    pub async fn close(mut self) {
+        self.remote_stream_tx.take();
+        if let Some(listener) = self.listener.as_mut() {
+            let wait = Duration::from_secs(TUNNEL_CLOSE_TIMEOUT);
+            if timeout(wait, listener.finished()).await.is_err() {
+                tracing::warn!("Tunnel {} close timeout", self.tid);
+            }
+        }
+    }
 }
 
 impl TunnelListener {
-    async fn new(tid: TunnelId, local_stream: TcpStream, swarm: Arc<Swarm>, peer_did: Did) -> Self {
-        let (remote_stream_tx, remote_stream_rx) = mpsc::channel(1024);
-        Self {
+    async fn new(
+        tid: TunnelId,
+        local_stream: TcpStream,
+        swarm: Arc<Swarm>,
+        peer_did: Did,
+        pool: Option<TunnelPool>,
+        queue_size: usize,
+    ) -> (Self, mpsc::Sender<RemoteData>) {
+        let (remote_stream_tx, remote_stream_rx) = mpsc::channel(queue_size);
+        let listener = Self {
             tid,
             local_stream,
-            remote_stream_tx,
             remote_stream_rx,
             swarm,
             peer_did,
             cancel_token: CancellationToken::new(),
-        }
+            capture: None,
+            pool,
+            congested: CancellationToken::new(),
+            peer_extended: Arc::new(AtomicBool::new(false)),
+        };
+        (listener, remote_stream_tx)
     }
 
     fn cancel_token(&self) -> CancellationToken {
@@ -117,73 +547,183 @@ impl TunnelListener {
     }
 
     async fn listen(&mut self) {
+        let tid = self.tid;
+        let peer_did = self.peer_did;
+        let swarm = self.swarm.clone();
+        let cancel_token = self.cancel_token.clone();
+        let congested = self.congested.clone();
+        let peer_extended = self.peer_extended.clone();
         let (mut local_read, mut local_write) = self.local_stream.split();
+        let remote_stream_rx = &mut self.remote_stream_rx;
+        let capture = self.capture.as_ref();
+        let mut read_buf = match self.pool.clone() {
+            Some(pool) => ReadBuffer::Pooled(pool),
+            None => ReadBuffer::Owned(vec![0u8; TUNNEL_READ_BUFFER_SIZE]),
+        };
 
+        // Returns `None` when local stream reaches EOF and peer is told to half-close.
+        // Peers of older versions can't half-close, the tunnel is closed on EOF instead.
         let listen_local = async {
+            let mut seq = 0;
             loop {
-                if self.cancel_token.is_cancelled() {
-                    break TunnelDefeat::ConnectionClosed;
+                if cancel_token.is_cancelled() {
+                    break Some(TunnelDefeat::ConnectionClosed);
                 }
 
-                let mut buf = [0u8; 30000];
-                match local_read.read(&mut buf).await {
+                match read_buf.read(&mut local_read).await {
                     Err(e) => {
-                        break e.kind().into();
+                        break Some(e.kind().into());
                     }
-                    Ok(n) if n == 0 => {
-                        break TunnelDefeat::ConnectionClosed;
+                    Ok(body) if body.is_empty() && !peer_extended.load(Ordering::Relaxed) => {
+                        break Some(TunnelDefeat::ConnectionClosed);
                     }
-                    Ok(n) => {
-                        let body = Bytes::copy_from_slice(&buf[..n]);
-                        let message = TunnelMessage::TcpPackage {
-                            tid: self.tid,
-                            body,
-                        };
+                    Ok(body) if body.is_empty() => {
+                        let message = TunnelMessage::TcpShutdownWrite { tid, seq };
                         let custom_msg = wrap_custom_message(&message);
-                        if let Err(e) = self.swarm.send_message(custom_msg, self.peer_did).await {
-                            tracing::error!("Send TcpPackage message failed: {e:?}");
-                            break TunnelDefeat::WebrtcDatachannelSendFailed;
+                        if let Err(e) = swarm.send_message(custom_msg, peer_did).await {
+                            tracing::error!("Send TcpShutdownWrite message failed: {e:?}");
+                            break Some(TunnelDefeat::WebrtcDatachannelSendFailed);
+                        }
+                        break None;
+                    }
+                    Ok(body) => {
+                        if let Some(c) = capture {
+                            c.record(CaptureDirection::Outbound, &body);
+                        }
+                        let extended = peer_extended.load(Ordering::Relaxed);
+                        let sent = send_tcp_package(&swarm, peer_did, tid, seq, body, extended);
+                        match sent.await {
+                            Ok(next_seq) => seq = next_seq,
+                            Err(defeat) => break Some(defeat),
                         }
                     }
                 }
             }
         };
 
+        // Writes remaining packages to local stream before it exits by [Tunnel::close]
+        // or `TcpShutdownWrite`.
         let listen_remote = async {
+            let mut reorder = ReorderBuffer::default();
+            // Number of packages to be written before shutting down, once peer reaches EOF.
+            let mut shutdown_at = None;
             loop {
-                if self.cancel_token.is_cancelled() {
-                    break TunnelDefeat::ConnectionClosed;
+                if cancel_token.is_cancelled() {
+                    break RemoteExit::Defeat(TunnelDefeat::ConnectionClosed);
                 }
 
-                if let Some(body) = self.remote_stream_rx.recv().await {
+                let bodies = match remote_stream_rx.recv().await {
+                    Some(RemoteData::Package(seq, body)) => match reorder.push(seq, body) {
+                        Ok(bodies) => bodies,
+                        Err(defeat) => {
+                            tracing::error!("Tunnel {tid} lost package {}", reorder.next_seq());
+                            break RemoteExit::Defeat(defeat);
+                        }
+                    },
+                    Some(RemoteData::ShutdownWrite(seq)) => {
+                        shutdown_at = Some(seq);
+                        vec![]
+                    }
+                    None => {
+                        if let Err(e) = local_write.shutdown().await {
+                            tracing::warn!("Shutdown local stream failed: {e:?}");
+                        }
+                        break RemoteExit::Closed;
+                    }
+                };
+                let mut write_error = None;
+                for body in bodies {
+                    if let Some(c) = capture {
+                        c.record(CaptureDirection::Inbound, &body);
+                    }
                     if let Err(e) = local_write.write_all(&body).await {
-                        tracing::error!("Write to local stream failed: {e:?}");
-                        break e.kind().into();
+                        write_error = Some(e);
+                        break;
+                    }
+                }
+                if let Some(e) = write_error {
+                    tracing::error!("Write to local stream failed: {e:?}");
+                    break RemoteExit::Defeat(e.kind().into());
+                }
+                if shutdown_at.is_some_and(|seq| reorder.next_seq() >= seq) {
+                    if let Err(e) = local_write.shutdown().await {
+                        tracing::warn!("Shutdown local stream failed: {e:?}");
                     }
+                    break RemoteExit::ShutdownWrite;
                 }
             }
         };
 
-        tokio::select! {
-            defeat = listen_local => {
-                tracing::info!("Local stream closed: {defeat:?}");
-                let message = TunnelMessage::TcpClose {
-                    tid: self.tid,
-                    reason: defeat,
-                };
-                let custom_msg = wrap_custom_message(&message);
-                if let Err(e) =  self.swarm.send_message(custom_msg, self.peer_did).await {
-                    tracing::error!("Send TcpClose message failed: {e:?}");
+        tokio::pin!(listen_local);
+        tokio::pin!(listen_remote);
+
+        // The tunnel is half-closed when one of the directions reaches EOF,
+        // and it's fully closed when both are done.
+        let mut local_eof = false;
+        let mut remote_eof = false;
+        let done = TunnelMessage::TcpClose {
+            tid,
+            reason: TunnelDefeat::ConnectionClosed,
+        };
+        let message = loop {
+            tokio::select! {
+                defeat = &mut listen_local, if !local_eof => {
+                    let Some(defeat) = defeat else {
+                        tracing::info!("Tunnel {tid} local stream half-closed");
+                        local_eof = true;
+                        if remote_eof {
+                            break Some(done);
+                        }
+                        continue;
+                    };
+                    tracing::info!("Local stream closed: {defeat:?}");
+                    let message = TunnelMessage::TcpClose { tid, reason: defeat };
+                    let custom_msg = wrap_custom_message(&message);
+                    if let Err(e) = swarm.send_message(custom_msg, peer_did).await {
+                        tracing::error!("Send TcpClose message failed: {e:?}");
+                    }
+                    // Packages sent by peer before it receives `TcpClose` are still in flight,
+                    // keep writing them until peer acknowledges.
+                    if !remote_eof {
+                        let wait = Duration::from_secs(TUNNEL_CLOSE_TIMEOUT);
+                        if timeout(wait, &mut listen_remote).await.is_err() {
+                            tracing::warn!("Tunnel {tid} TcpClose is not acknowledged");
+                        }
+                    }
+                    break None;
+                },
+                // Packages queued are dropped, peer is told the tunnel is congested.
+                _ = congested.cancelled() => {
+                    tracing::info!("Tunnel {tid} closed by congestion");
+                    break Some(TunnelMessage::TcpClose { tid, reason: TunnelDefeat::Congested });
                 }
-            },
-            defeat = listen_remote => {
-                tracing::info!("Remote stream closed: {defeat:?}");
-                let message = TunnelMessage::TcpClose {
-                    tid: self.tid,
-                    reason: defeat,
-                };
-                let custom_msg = wrap_custom_message(&message);
-                let _ = self.swarm.send_message(custom_msg, self.peer_did).await;
+                exit = &mut listen_remote, if !remote_eof => {
+                    match exit {
+                        RemoteExit::Closed => {
+                            tracing::info!("Remote stream closed by peer");
+                            let extended = peer_extended.load(Ordering::Relaxed);
+                            break extended.then_some(TunnelMessage::TcpCloseAck { tid });
+                        }
+                        RemoteExit::ShutdownWrite => {
+                            tracing::info!("Tunnel {tid} remote stream half-closed");
+                            remote_eof = true;
+                            if local_eof {
+                                break Some(done);
+                            }
+                        }
+                        RemoteExit::Defeat(defeat) => {
+                            tracing::info!("Remote stream closed: {defeat:?}");
+                            break Some(TunnelMessage::TcpClose { tid, reason: defeat });
+                        }
+                    }
+                }
+            }
+        };
+
+        if let Some(message) = message {
+            let custom_msg = wrap_custom_message(&message);
+            if let Err(e) = swarm.send_message(custom_msg, peer_did).await {
+                tracing::error!("Send {message:?} failed: {e:?}");
             }
         }
     }
@@ -200,6 +740,63 @@ pub async fn tcp_connect_with_timeout(
     }
 }
 
+/// Send a package read from local stream to peer in pieces of the MTU learned for peer,
+/// returns the next sequence number.
+/// If sending fails, the rest of package is resent in pieces of the MTU of the next hop towards
+/// peer, which shrinks on each failure, see [Swarm::next_hop_mtu], so that a tunnel survives
+/// frames too big for the link. Each piece takes a sequence number. Fails once the MTU can't
+/// shrink further.
+/// Pieces are sent as `TcpSequencedPackage` if peer is `extended`, or `TcpPackage` otherwise.
+async fn send_tcp_package(
+    swarm: &Swarm,
+    peer_did: Did,
+    tid: TunnelId,
+    mut seq: u64,
+    mut body: Bytes,
+    extended: bool,
+) -> Result<u64, TunnelDefeat> {
+    let mut size = swarm.peer_mtu(peer_did);
+    while !body.is_empty() {
+        let next_hop = match swarm.next_hop_mtu(peer_did) {
+            Ok((next_hop, _)) => next_hop,
+            Err(e) => {
+                tracing::error!("Send TcpPackage message failed: {e:?}");
+                return Err(TunnelDefeat::WebrtcDatachannelSendFailed);
+            }
+        };
+        let piece = body.slice(..size.min(body.len()));
+        let len = piece.len();
+        let message = if extended {
+            TunnelMessage::TcpSequencedPackage {
+                tid,
+                seq,
+                body: piece,
+            }
+        } else {
+            TunnelMessage::TcpPackage { tid, body: piece }
+        };
+        let result = swarm
+            .send_message_by_hop(wrap_custom_message(&message), peer_did, next_hop)
+            .await;
+        swarm.record_chunk_sent(next_hop, len, result.is_ok());
+        match result {
+            Ok(_) => {
+                body = body.slice(len..);
+                seq += 1;
+            }
+            Err(e) => {
+                size = swarm.peer_mtu(next_hop);
+                if size >= len {
+                    tracing::error!("Send TcpPackage message failed: {e:?}");
+                    return Err(TunnelDefeat::WebrtcDatachannelSendFailed);
+                }
+                tracing::warn!("Send TcpPackage message of {len} bytes failed, retry by {size}");
+            }
+        }
+    }
+    Ok(seq)
+}
+
 async fn tcp_connect(addr: SocketAddr) -> Result<TcpStream, TunnelDefeat> {
     match TcpStream::connect(addr).await {
         Ok(o) => Ok(o),
@@ -207,11 +804,17 @@ async fn tcp_connect(addr: SocketAddr) -> Result<TcpStream, TunnelDefeat> {
     }
 }
 
+/// Wrap a tunnel message to be sent to peer, followed by [TUNNEL_EXTENDED_MARKER].
 pub fn wrap_custom_message(message: &TunnelMessage) -> Message {
-    let message_bytes = bincode::serialize(message).unwrap();
+    let mut message_bytes = bincode::serialize(message).unwrap();
+    message_bytes.extend_from_slice(&TUNNEL_EXTENDED_MARKER);
 
     let backend_msg =
         BackendMessage::from((MessageType::TunnelMessage.into(), message_bytes.as_slice()));
+    let backend_msg = match message {
+        TunnelMessage::TcpDial { service, .. } => backend_msg.with_service(service),
+        _ => backend_msg,
+    };
 
     let backend_msg_bytes: Vec<u8> = backend_msg.into();
 
@@ -222,3 +825,363 @@ pub fn wrap_custom_message(message: &TunnelMessage) -> Message {
 
     Message::custom(&new_bytes).unwrap()
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use futures::future::join_all;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::backend::service::tunnel_pool::TunnelPoolConfig;
+    use crate::prelude::SecretKey;
+    use crate::tests::native::prepare_processor;
+
+    #[tokio::test]
+    async fn test_tunnel_half_close() {
+        let (processor, path) = prepare_processor(None).await;
+        let peer_did = SecretKey::random().address().into();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (local_stream, _) = listener.accept().await.unwrap();
+
+        let mut tunnel = Tunnel::new(Uuid::new_v4());
+        tunnel
+            .listen(local_stream, processor.swarm.clone(), peer_did)
+            .await;
+
+        // Peer finished writing, local stream gets EOF after the remaining packages.
+        tunnel.send(0, Bytes::from_static(b"request")).await;
+        tunnel.shutdown_write(1).await;
+        let mut received = vec![];
+        timeout(Duration::from_secs(5), client.read_to_end(&mut received))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(received, b"request");
+
+        // The opposite direction keeps open.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!tunnel.is_finished());
+
+        drop(tunnel);
+        tokio::fs::remove_dir_all(path).await.unwrap();
+    }
+
+    #[test]
+    fn test_tunnel_message_extended_marker() {
+        let tid = Uuid::new_v4();
+        let message = TunnelMessage::TcpClose {
+            tid,
+            reason: TunnelDefeat::ConnectionClosed,
+        };
+        let legacy = bincode::serialize(&message).unwrap();
+        let (decoded, extended) = TunnelMessage::decode(&legacy).unwrap();
+        assert_eq!(decoded.tid(), tid);
+        assert!(!extended);
+
+        let mut bytes = legacy.clone();
+        bytes.extend_from_slice(&TUNNEL_EXTENDED_MARKER);
+        let (decoded, extended) = TunnelMessage::decode(&bytes).unwrap();
+        assert_eq!(decoded.tid(), tid);
+        assert!(extended);
+
+        // Peers of older versions decode it as well.
+        let decoded: TunnelMessage = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded.tid(), tid);
+    }
+
+    #[test]
+    fn test_reorder_buffer() {
+        let stream: Vec<u8> = (0..=255).collect();
+        let packages: Vec<(u64, Bytes)> = stream
+            .chunks(16)
+            .enumerate()
+            .map(|(i, c)| (i as u64, Bytes::copy_from_slice(c)))
+            .collect();
+
+        // Reordered and duplicated, as on an unordered channel.
+        let mut reordered = packages.clone();
+        reordered.swap(0, 3);
+        reordered.swap(5, 9);
+        reordered.reverse();
+        reordered.insert(4, packages[2].clone());
+
+        let mut reorder = ReorderBuffer::default();
+        let mut received = vec![];
+        for (seq, body) in reordered {
+            for body in reorder.push(seq, body).unwrap() {
+                received.extend_from_slice(&body);
+            }
+        }
+        assert_eq!(received, stream);
+        assert_eq!(reorder.next_seq(), packages.len() as u64);
+
+        // A gap never filled is detected.
+        let next = reorder.next_seq();
+        assert!(matches!(
+            reorder.push(next + TUNNEL_REORDER_WINDOW, Bytes::new()),
+            Err(TunnelDefeat::PackageLost)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_tunnel_reorder_packages() {
+        let (processor, path) = prepare_processor(None).await;
+        let peer_did = SecretKey::random().address().into();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (local_stream, _) = listener.accept().await.unwrap();
+
+        let mut tunnel = Tunnel::new(Uuid::new_v4());
+        tunnel
+            .listen(local_stream, processor.swarm.clone(), peer_did)
+            .await;
+
+        // Shutdown overtakes the packages, which are delivered in reverse.
+        tunnel.shutdown_write(3).await;
+        for (seq, body) in [(2, "c"), (1, "b"), (0, "a")] {
+            tunnel.send(seq, Bytes::from(body)).await;
+        }
+        let mut received = vec![];
+        timeout(Duration::from_secs(5), client.read_to_end(&mut received))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(received, b"abc");
+
+        drop(tunnel);
+        tokio::fs::remove_dir_all(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tunnel_legacy_packages() {
+        let (processor, path) = prepare_processor(None).await;
+        let peer_did = SecretKey::random().address().into();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (local_stream, _) = listener.accept().await.unwrap();
+
+        let mut tunnel = Tunnel::new(Uuid::new_v4());
+        tunnel
+            .listen(local_stream, processor.swarm.clone(), peer_did)
+            .await;
+
+        // Peer of an older version sends unnumbered packages, then learns the capability.
+        tunnel.send_legacy(Bytes::from("a")).await;
+        tunnel.send_legacy(Bytes::from("b")).await;
+        tunnel.send(3, Bytes::from("d")).await;
+        tunnel.send(2, Bytes::from("c")).await;
+        let mut received = [0; 4];
+        timeout(Duration::from_secs(5), client.read_exact(&mut received))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&received, b"abcd");
+
+        drop(tunnel);
+        tokio::fs::remove_dir_all(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tunnel_close_on_overflow() {
+        let (processor, path) = prepare_processor(None).await;
+        let peer_did = SecretKey::random().address().into();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (local_stream, _) = listener.accept().await.unwrap();
+
+        let mut tunnel = Tunnel::new(Uuid::new_v4()).with_queue(TunnelQueueConfig {
+            capacity: Some(1),
+            overflow: TunnelOverflow::Close,
+        });
+        tunnel
+            .listen(local_stream, processor.swarm.clone(), peer_did)
+            .await;
+
+        // Sending never waits, so the listener has no chance to take the first package
+        // before the queue overflows.
+        for seq in 0..3 {
+            tunnel.send(seq, Bytes::from_static(b"package")).await;
+        }
+        let mut received = vec![];
+        timeout(Duration::from_secs(5), client.read_to_end(&mut received))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(received.len() <= b"package".len());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(tunnel.is_finished());
+
+        drop(tunnel);
+        tokio::fs::remove_dir_all(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pooled_tunnel() {
+        let (processor, path) = prepare_processor(None).await;
+        let peer_did = SecretKey::random().address().into();
+        let pool = TunnelPool::new(&TunnelPoolConfig {
+            buffers: 1,
+            queue_size: 8,
+            workers: 2,
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut tunnels = vec![];
+        let mut clients = vec![];
+        for _ in 0..4 {
+            let client = TcpStream::connect(listener.local_addr().unwrap())
+                .await
+                .unwrap();
+            let (local_stream, _) = listener.accept().await.unwrap();
+            let mut tunnel = Tunnel::new(Uuid::new_v4()).with_pool(Some(pool.clone()));
+            tunnel
+                .listen(local_stream, processor.swarm.clone(), peer_did)
+                .await;
+            tunnels.push(tunnel);
+            clients.push(client);
+        }
+        // Tunnels are driven by workers of the pool, instead of tasks of their own.
+        assert_eq!(pool.loads(), vec![2, 2]);
+
+        for (tunnel, client) in tunnels.iter().zip(clients.iter_mut()) {
+            tunnel.send(0, Bytes::from_static(b"request")).await;
+            tunnel.shutdown_write(1).await;
+            let mut received = vec![];
+            timeout(Duration::from_secs(5), client.read_to_end(&mut received))
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(received, b"request");
+        }
+
+        // A tunnel exits its worker once local stream is closed.
+        drop(clients.pop());
+        let tunnel = tunnels.pop().unwrap();
+        tunnel.close().await;
+        assert_eq!(pool.loads().iter().sum::<usize>(), 3);
+
+        drop(tunnels);
+        tokio::fs::remove_dir_all(path).await.unwrap();
+    }
+
+    /// Resident memory in KiB and CPU time of the process, read from procfs.
+    /// CPU time is counted in ticks of 10ms, which is the clock tick of Linux.
+    fn process_usage() -> Option<(u64, Duration)> {
+        let status = std::fs::read_to_string("/proc/self/status").ok()?;
+        let rss = status
+            .lines()
+            .find_map(|l| l.strip_prefix("VmRSS:"))?
+            .trim()
+            .trim_end_matches("kB")
+            .trim()
+            .parse()
+            .ok()?;
+        let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+        let (_, stat) = stat.rsplit_once(')')?;
+        let fields = stat.split_whitespace().collect::<Vec<_>>();
+        let utime: u64 = fields.get(11)?.parse().ok()?;
+        let stime: u64 = fields.get(12)?.parse().ok()?;
+        Some((rss, Duration::from_millis((utime + stime) * 10)))
+    }
+
+    /// Open 500 tunnels, which are idle at first, then write packages of peer to their local
+    /// streams. Memory taken by the idle tunnels, and CPU time taken by the traffic, are
+    /// printed. The modes are measured in separate processes, since memory freed by one is
+    /// kept by the allocator for the other.
+    async fn bench_concurrent_tunnels(pool: Option<TunnelPool>) {
+        const TUNNELS: usize = 500;
+        const PACKAGES: usize = 100;
+        const PACKAGE_SIZE: usize = 1024;
+        let (processor, path) = prepare_processor(None).await;
+        let peer_did = SecretKey::random().address().into();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mode = match pool {
+            Some(_) => "pool of workers",
+            None => "task per tunnel",
+        };
+
+        let (rss_before, _) = process_usage().expect("procfs is available");
+        let mut tunnels = vec![];
+        let mut clients = vec![];
+        for _ in 0..TUNNELS {
+            let client = TcpStream::connect(addr).await.unwrap();
+            let (local_stream, _) = listener.accept().await.unwrap();
+            let mut tunnel = Tunnel::new(Uuid::new_v4()).with_pool(pool.clone());
+            tunnel
+                .listen(local_stream, processor.swarm.clone(), peer_did)
+                .await;
+            tunnels.push(tunnel);
+            clients.push(client);
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let (rss_idle, cpu_idle) = process_usage().unwrap();
+
+        let started = Instant::now();
+        let readers = clients.into_iter().map(|mut client| {
+            tokio::spawn(async move {
+                let mut received = vec![0u8; PACKAGES * PACKAGE_SIZE];
+                client.read_exact(&mut received).await.unwrap();
+                client
+            })
+        });
+        let readers = tokio::spawn(join_all(readers));
+        let body = Bytes::from(vec![7u8; PACKAGE_SIZE]);
+        for seq in 0..PACKAGES as u64 {
+            for tunnel in tunnels.iter() {
+                tunnel.send(seq, body.clone()).await;
+            }
+        }
+        let clients = readers.await.unwrap();
+        let elapsed = started.elapsed();
+        let (rss_busy, cpu_busy) = process_usage().unwrap();
+
+        println!(
+            "{TUNNELS} tunnels by {mode}: idle tunnels take {} KiB, {} KiB at peak; \
+             {} KiB written to each in {:?} taking {:?} of CPU",
+            rss_idle.saturating_sub(rss_before),
+            rss_busy.saturating_sub(rss_before),
+            PACKAGES * PACKAGE_SIZE / 1024,
+            elapsed,
+            cpu_busy.saturating_sub(cpu_idle)
+        );
+
+        drop(clients);
+        drop(tunnels);
+        tokio::fs::remove_dir_all(path).await.unwrap();
+    }
+
+    /// Run by `cargo test -p rings-node --release bench_tunnels_by_task -- --ignored --nocapture`.
+    #[ignore]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn bench_tunnels_by_task() {
+        bench_concurrent_tunnels(None).await
+    }
+
+    /// Run by `cargo test -p rings-node --release bench_tunnels_by_pool -- --ignored --nocapture`.
+    #[ignore]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn bench_tunnels_by_pool() {
+        let pool = TunnelPool::new(&TunnelPoolConfig {
+            buffers: 64,
+            queue_size: 64,
+            workers: 4,
+        });
+        bench_concurrent_tunnels(Some(pool)).await
+    }
+}
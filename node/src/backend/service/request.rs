@@ -0,0 +1,368 @@
+#![warn(missing_docs)]
+//! Request/response over backend messages, for protocols whose request can be answered by
+//! replies of different shapes, such as a result, an [ErrorResponse](crate::backend::types::ErrorResponse)
+//! or a redirect.
+//!
+//! A reply is correlated with its request by tx_id, since a remote endpoint reports back
+//! with the tx_id of the payload it handles. Only replies signed by the destination of
+//! the request are accepted, so that other peers can't answer for it.
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::backend::service::http_server::HTTP_PROGRESS_HEADER;
+use crate::backend::service::Backend;
+use crate::backend::types::encode_custom_messages_for;
+use crate::backend::types::BackendMessage;
+use crate::backend::types::ErrorResponse;
+use crate::backend::types::HttpResponse;
+use crate::backend::types::HttpResponseAssembler;
+use crate::backend::types::HttpResponsePart;
+use crate::backend::types::MessageType;
+use crate::consts::BACKEND_MTU;
+use crate::error::Error;
+use crate::error::Result;
+use crate::prelude::rings_core::consts::MAX_DECOMPRESSED_SIZE;
+use crate::prelude::rings_core::prelude::dashmap::DashMap;
+use crate::prelude::rings_core::prelude::uuid::Uuid;
+use crate::prelude::rings_core::utils::get_epoch_ms;
+use crate::prelude::rings_rpc::types::HttpRequest;
+use crate::prelude::*;
+
+/// A message waiting for replies.
+#[derive(Debug)]
+struct PendingEntry {
+    sender: mpsc::UnboundedSender<BackendMessage>,
+    destination: Did,
+    message_type: u16,
+    sent_at: u128,
+    replies: usize,
+}
+
+/// Snapshot of a message waiting for replies, see [Backend::pending_outbound].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingInfo {
+    /// tx_id of the message sent
+    pub tx_id: Uuid,
+    /// did of destination
+    pub destination: Did,
+    /// type of the backend message sent, see [MessageType](crate::backend::types::MessageType)
+    pub message_type: u16,
+    /// milliseconds since the message is sent
+    pub age_ms: u128,
+    /// replies received but not accepted by the request yet.
+    /// Requests are never resent, so this is the way to tell a silent peer from
+    /// a peer replying with something unexpected.
+    pub replies: usize,
+}
+
+/// Requests waiting for replies, keyed by tx_id of the messages sent.
+#[derive(Debug, Default)]
+pub struct PendingRequests(DashMap<Uuid, PendingEntry>);
+
+impl PendingRequests {
+    /// Deliver a message signed by `signer` to the request waiting on `tx_id`, returns false
+    /// if no one waits, or the request is not sent to `signer`.
+    pub fn dispatch(&self, tx_id: Uuid, signer: Did, msg: &BackendMessage) -> bool {
+        match self.0.get_mut(&tx_id) {
+            Some(entry) if entry.destination != signer => {
+                tracing::warn!("reply of {tx_id} from {signer} is not from the requested peer");
+                false
+            }
+            Some(mut entry) => {
+                entry.replies += 1;
+                entry.sender.send(msg.clone()).is_ok()
+            }
+            None => false,
+        }
+    }
+
+    /// Snapshot of messages waiting for replies, the oldest first.
+    pub fn snapshot(&self) -> Vec<PendingInfo> {
+        let now = get_epoch_ms();
+        let mut infos: Vec<PendingInfo> = self
+            .0
+            .iter()
+            .map(|e| PendingInfo {
+                tx_id: *e.key(),
+                destination: e.destination,
+                message_type: e.message_type,
+                age_ms: now.saturating_sub(e.sent_at),
+                replies: e.replies,
+            })
+            .collect();
+        infos.sort_by(|a, b| b.age_ms.cmp(&a.age_ms));
+        infos
+    }
+
+    /// Number of messages waiting for replies.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Check if no message is waiting for reply.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// InFlightLimit caps the number of requests in flight with each peer.
+#[derive(Debug)]
+pub struct InFlightLimit {
+    limit: usize,
+    counts: DashMap<Did, usize>,
+}
+
+/// Permit of an in-flight request, released on drop.
+pub struct InFlightPermit<'a> {
+    counts: &'a DashMap<Did, usize>,
+    peer: Did,
+}
+
+impl InFlightLimit {
+    /// Create a limit allowing `limit` requests in flight per peer.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            counts: DashMap::new(),
+        }
+    }
+
+    /// Max number of in-flight requests per peer.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Number of requests in flight with peer.
+    pub fn in_flight(&self, peer: Did) -> usize {
+        self.counts.get(&peer).map(|c| *c).unwrap_or(0)
+    }
+
+    /// Take a permit for a request with peer, fails with [Error::TooManyInFlight]
+    /// if the limit is reached.
+    pub fn acquire(&self, peer: Did) -> Result<InFlightPermit<'_>> {
+        let mut count = self.counts.entry(peer).or_insert(0);
+        if *count >= self.limit {
+            return Err(Error::TooManyInFlight(peer.to_string()));
+        }
+        *count += 1;
+        Ok(InFlightPermit {
+            counts: &self.counts,
+            peer,
+        })
+    }
+}
+
+impl Drop for InFlightPermit<'_> {
+    fn drop(&mut self) {
+        self.counts.remove_if_mut(&self.peer, |_, count| {
+            *count -= 1;
+            *count == 0
+        });
+    }
+}
+
+/// Remove pending entries of a request when it's matched, timed out or cancelled.
+struct PendingGuard<'a> {
+    pending: &'a PendingRequests,
+    tx_ids: Vec<Uuid>,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        for tx_id in &self.tx_ids {
+            self.pending.0.remove(tx_id);
+        }
+    }
+}
+
+impl Backend {
+    /// List messages sent by requests and not replied yet, such as to show
+    /// "3 messages sending" in UI, or to see whether requests to a peer are backing up.
+    pub fn pending_outbound(&self) -> Vec<PendingInfo> {
+        self.pending.snapshot()
+    }
+
+    /// Send a request to peer, and wait for the first reply accepted by `predicate`.
+    /// Replies rejected by `predicate` are skipped, and [Error::ResponseTimeout] is returned
+    /// if no reply is accepted within `timeout`.
+    /// Fails with [Error::TooManyInFlight] if too many requests to peer are waiting.
+    pub async fn request_until<T, F>(
+        &self,
+        peer: Did,
+        req: BackendMessage,
+        timeout: Duration,
+        predicate: F,
+    ) -> Result<T>
+    where
+        F: Fn(&BackendMessage) -> Option<T>,
+    {
+        let _permit = self.outgoing_in_flight.acquire(peer)?;
+        let _work = self.drain.track();
+        let (_guard, mut receiver) = self.send_request(peer, req).await?;
+
+        let wait = async {
+            while let Some(msg) = receiver.recv().await {
+                if let Some(v) = predicate(&msg) {
+                    return Ok(v);
+                }
+            }
+            Err(Error::InternalError)
+        };
+        tokio::time::timeout(timeout, wait)
+            .await
+            .unwrap_or(Err(Error::ResponseTimeout))
+    }
+
+    /// Send a http request asking for a progressive response to peer, see
+    /// [HttpResponsePart], and reassemble the response from its parts.
+    /// Unlike [Backend::request_until], `idle_timeout` bounds the wait for each part rather
+    /// than the whole response, so that a slow upstream which keeps sending is not timed out.
+    pub async fn request_http_progressive(
+        &self,
+        peer: Did,
+        mut req: HttpRequest,
+        idle_timeout: Duration,
+    ) -> Result<HttpResponse> {
+        req.headers
+            .insert(HTTP_PROGRESS_HEADER.to_string(), "1".to_string());
+        let msg =
+            BackendMessage::try_from((MessageType::HttpRequest, &req))?.with_service(&req.name);
+
+        let _permit = self.outgoing_in_flight.acquire(peer)?;
+        let _work = self.drain.track();
+        let (_guard, mut receiver) = self.send_request(peer, msg).await?;
+
+        let mut assembler = HttpResponseAssembler::new(MAX_DECOMPRESSED_SIZE);
+        loop {
+            let msg = tokio::time::timeout(idle_timeout, receiver.recv())
+                .await
+                .map_err(|_| Error::ResponseTimeout)?
+                .ok_or(Error::InternalError)?;
+            match MessageType::from(msg.message_type) {
+                MessageType::HttpResponsePart => {
+                    let part = HttpResponsePart::try_from(&msg)?;
+                    if let Some(resp) = assembler.push(part)? {
+                        return Ok(resp);
+                    }
+                }
+                MessageType::Error => {
+                    let e = ErrorResponse::try_from(&msg)?;
+                    return Err(Error::RemoteRpcError(e.message));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Send a request to peer, and register its payloads to receive replies, which are
+    /// received until the guard returned is dropped.
+    async fn send_request(
+        &self,
+        peer: Did,
+        req: BackendMessage,
+    ) -> Result<(PendingGuard<'_>, mpsc::UnboundedReceiver<BackendMessage>)> {
+        let message_type = req.message_type;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let mut guard = PendingGuard {
+            pending: &self.pending,
+            tx_ids: vec![],
+        };
+
+        let next_hop = self
+            .swarm
+            .infer_next_hop(None, peer)
+            .map_err(Error::SendMessage)?;
+        // A chunked request is replied to the payload of its last chunk,
+        // so that every chunk is registered before it's sent.
+        for frame in encode_custom_messages_for(&self.swarm, peer, req, BACKEND_MTU)? {
+            let msg = Message::custom(&frame).map_err(Error::SendMessage)?;
+            let payload = MessagePayload::new_send(msg, self.swarm.session_sk(), next_hop, peer)
+                .map_err(Error::SendMessage)?;
+            let tx_id = payload.transaction.tx_id;
+            self.pending.0.insert(tx_id, PendingEntry {
+                sender: sender.clone(),
+                destination: peer,
+                message_type,
+                sent_at: get_epoch_ms(),
+                replies: 0,
+            });
+            guard.tx_ids.push(tx_id);
+            self.swarm
+                .send_payload(payload)
+                .await
+                .map_err(Error::SendMessage)?;
+        }
+        Ok((guard, receiver))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pending_requests() {
+        let pending = PendingRequests::default();
+        let msg = BackendMessage::from((MessageType::SimpleText.into(), "reply".as_bytes()));
+        let (tx_a, tx_b) = (Uuid::new_v4(), Uuid::new_v4());
+        let peer: Did = SecretKey::random().address().into();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let entry = |sent_at| PendingEntry {
+            sender: sender.clone(),
+            destination: peer,
+            message_type: MessageType::Echo.into(),
+            sent_at,
+            replies: 0,
+        };
+        {
+            let _guard = PendingGuard {
+                pending: &pending,
+                tx_ids: vec![tx_a, tx_b],
+            };
+            pending.0.insert(tx_a, entry(get_epoch_ms() - 1000));
+            pending.0.insert(tx_b, entry(get_epoch_ms()));
+            assert_eq!(pending.len(), 2);
+
+            let other: Did = SecretKey::random().address().into();
+            assert!(!pending.dispatch(tx_b, other, &msg));
+            assert!(pending.dispatch(tx_b, peer, &msg));
+            assert!(!pending.dispatch(Uuid::new_v4(), peer, &msg));
+            assert_eq!(receiver.try_recv().unwrap().data, msg.data);
+
+            let infos = pending.snapshot();
+            assert_eq!(infos.len(), 2);
+            assert_eq!((infos[0].tx_id, infos[0].replies), (tx_a, 0));
+            assert_eq!((infos[1].tx_id, infos[1].replies), (tx_b, 1));
+            assert!(infos[0].age_ms >= 1000);
+            assert_eq!(infos[1].destination, peer);
+        }
+        // Entries are removed once the request is done.
+        assert!(pending.is_empty());
+        assert!(!pending.dispatch(tx_a, peer, &msg));
+    }
+
+    #[test]
+    fn test_in_flight_limit() {
+        let limit = InFlightLimit::new(2);
+        let peer: Did = SecretKey::random().address().into();
+        let other: Did = SecretKey::random().address().into();
+
+        let p1 = limit.acquire(peer).unwrap();
+        let _p2 = limit.acquire(peer).unwrap();
+        assert_eq!(limit.in_flight(peer), 2);
+        assert!(matches!(
+            limit.acquire(peer),
+            Err(Error::TooManyInFlight(_))
+        ));
+        // Limit is counted per peer.
+        assert!(limit.acquire(other).is_ok());
+        assert_eq!(limit.in_flight(other), 0);
+
+        drop(p1);
+        assert_eq!(limit.in_flight(peer), 1);
+        assert!(limit.acquire(peer).is_ok());
+    }
+}
@@ -0,0 +1,604 @@
+#![warn(missing_docs)]
+//! Read-through cache of responses of an http service, see [ResponseCacheConfig].
+//!
+//! Responses to `GET` and `HEAD` requests of status 200 are cached by method, path and the
+//! values of request headers in [ResponseCacheConfig::vary_headers] and in `Vary` of the
+//! response. A repeated request is answered from the cache while the response is fresh, without
+//! a round trip to upstream. A response of `Vary: *` is never cached.
+//!
+//! Freshness follows `Cache-Control` of upstream, unless
+//! [ResponseCacheConfig::respect_origin_headers] is off:
+//! - `no-store` and `private` responses are never cached.
+//! - `s-maxage` or `max-age` decides how long a response is fresh.
+//! - `no-cache` responses, and the ones without either age, are cached if they have an `ETag`,
+//!   but revalidated on every request.
+//!
+//! Responses to requests with `Authorization` or `Cookie` are cached only if they are
+//! `public` or have `s-maxage`, since they are served to every requester.
+//!
+//! A stale response with an `ETag` is revalidated by `If-None-Match`, and served again if
+//! upstream answers 304. A request asking for `no-cache` or `no-store` bypasses the cache.
+//!
+//! The cache is bounded by [ResponseCacheConfig::max_bytes], and the oldest responses are
+//! evicted first. Progressive responses, see [HttpResponsePart], are never cached.
+//!
+//! [HttpResponsePart]: crate::backend::types::HttpResponsePart
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::backend::types::HttpResponse;
+use crate::prelude::rings_rpc::types::HttpRequest;
+
+/// Default max bytes of responses cached by a service.
+pub const DEFAULT_RESPONSE_CACHE_BYTES: usize = 16 * 1024 * 1024;
+
+/// Default time in seconds a response is fresh, if origin headers are not respected.
+pub const DEFAULT_RESPONSE_CACHE_TTL_SECS: u64 = 60;
+
+/// Config of the response cache of an http service.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct ResponseCacheConfig {
+    /// Max bytes of responses cached, the oldest responses are evicted beyond it.
+    pub max_bytes: usize,
+    /// Time in seconds a response is fresh, if
+    /// [ResponseCacheConfig::respect_origin_headers] is off.
+    pub default_ttl_secs: u64,
+    /// Follow `Cache-Control` of upstream. Otherwise every response of status 200 is fresh
+    /// for [ResponseCacheConfig::default_ttl_secs].
+    pub respect_origin_headers: bool,
+    /// Headers of request which vary responses, such as `accept`. Requests differing in them
+    /// are cached separately.
+    pub vary_headers: Vec<String>,
+}
+
+impl Default for ResponseCacheConfig {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_RESPONSE_CACHE_BYTES,
+            default_ttl_secs: DEFAULT_RESPONSE_CACHE_TTL_SECS,
+            respect_origin_headers: true,
+            vary_headers: vec!["accept".to_string(), "accept-encoding".to_string()],
+        }
+    }
+}
+
+/// Counters of a [ResponseCache].
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResponseCacheStats {
+    /// Requests answered from cache, including revalidated ones.
+    pub hits: u64,
+    /// Cacheable requests sent to upstream.
+    pub misses: u64,
+    /// Number of responses cached.
+    pub entries: usize,
+    /// Bytes of responses cached.
+    pub bytes: usize,
+}
+
+/// Result of looking up a request, see [ResponseCache::lookup].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheLookup {
+    /// The request is not cacheable, send it to upstream as is.
+    Bypass,
+    /// No response is cached.
+    Miss,
+    /// A stale response is cached, revalidate it by this `ETag`.
+    Stale(String, HttpResponse),
+    /// A fresh response is cached.
+    Hit(HttpResponse),
+}
+
+/// Key of a cached response.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    method: String,
+    path: String,
+    vary: Vec<(String, Option<String>)>,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    response: HttpResponse,
+    etag: Option<String>,
+    expires_at: u128,
+    /// Position of the entry in [CacheInner::order], bumped when it's stored again.
+    generation: u64,
+    size: usize,
+}
+
+#[derive(Debug, Default)]
+struct CacheInner {
+    entries: HashMap<CacheKey, CacheEntry>,
+    /// Names of headers in `Vary` of the last response stored by method and path, with the
+    /// number of responses stored of them.
+    varies: HashMap<(String, String), (Vec<String>, usize)>,
+    /// Keys in the order they are stored, with the generation of entry when queued. A position
+    /// whose generation doesn't match the entry is stale, and skipped or compacted.
+    order: VecDeque<(u64, CacheKey)>,
+    generation: u64,
+    bytes: usize,
+}
+
+/// Cache of responses of a service, see [response_cache](self).
+#[derive(Debug, Default)]
+pub struct ResponseCache {
+    config: ResponseCacheConfig,
+    inner: Mutex<CacheInner>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+fn header<'a>(headers: &'a HashMap<String, String>, name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+/// Directives of `Cache-Control`, lowercase, with their values.
+fn cache_control(headers: &HashMap<String, String>) -> Vec<(String, Option<String>)> {
+    header(headers, "cache-control")
+        .unwrap_or_default()
+        .split(',')
+        .filter(|d| !d.trim().is_empty())
+        .map(|d| match d.split_once('=') {
+            Some((k, v)) => (
+                k.trim().to_lowercase(),
+                Some(v.trim().trim_matches('"').to_string()),
+            ),
+            None => (d.trim().to_lowercase(), None),
+        })
+        .collect()
+}
+
+fn response_size(response: &HttpResponse) -> usize {
+    let headers: usize = response
+        .headers
+        .iter()
+        .map(|(k, v)| k.len() + v.len())
+        .sum();
+    headers + response.body.as_ref().map(|b| b.len()).unwrap_or(0)
+}
+
+impl ResponseCache {
+    fn lock(&self) -> MutexGuard<'_, CacheInner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Create a cache of config.
+    pub fn new(config: ResponseCacheConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Key of request, varied by the names of headers in `vary` besides
+    /// [ResponseCacheConfig::vary_headers]. None if it's not cacheable.
+    pub fn key(&self, request: &HttpRequest, vary: &[String]) -> Option<CacheKey> {
+        let method = request.method.to_uppercase();
+        if method != "GET" && method != "HEAD" {
+            return None;
+        }
+        let bypass = cache_control(&request.headers)
+            .iter()
+            .any(|(d, _)| d == "no-cache" || d == "no-store");
+        if bypass {
+            return None;
+        }
+        let mut names: Vec<String> = self
+            .config
+            .vary_headers
+            .iter()
+            .chain(vary)
+            .map(|h| h.to_lowercase())
+            .collect();
+        names.sort();
+        names.dedup();
+        Some(CacheKey {
+            method,
+            path: request.path.clone(),
+            vary: names
+                .into_iter()
+                .map(|h| {
+                    let value = header(&request.headers, &h).map(|v| v.to_string());
+                    (h, value)
+                })
+                .collect(),
+        })
+    }
+
+    /// Look up a request, a cacheable request not answered is counted as a miss.
+    pub fn lookup(&self, request: &HttpRequest, now: u128) -> CacheLookup {
+        let mut inner = self.lock();
+        let Some(key) = self.key(request, inner.vary_of(request)) else {
+            return CacheLookup::Bypass;
+        };
+        inner.evict(now, self.config.max_bytes);
+        let lookup = match inner.entries.get(&key) {
+            Some(entry) if entry.expires_at > now => CacheLookup::Hit(entry.response.clone()),
+            Some(CacheEntry {
+                etag: Some(etag),
+                response,
+                ..
+            }) => CacheLookup::Stale(etag.clone(), response.clone()),
+            _ => CacheLookup::Miss,
+        };
+        match lookup {
+            CacheLookup::Hit(_) => self.hits.fetch_add(1, Ordering::SeqCst),
+            _ => self.misses.fetch_add(1, Ordering::SeqCst),
+        };
+        lookup
+    }
+
+    /// Time in milliseconds a response is fresh, None if it must not be stored.
+    fn ttl_ms(&self, headers: &HashMap<String, String>) -> Option<u128> {
+        if !self.config.respect_origin_headers {
+            return Some(self.config.default_ttl_secs as u128 * 1000);
+        }
+        let directives = cache_control(headers);
+        let has = |name: &str| directives.iter().any(|(d, _)| d == name);
+        if has("no-store") || has("private") {
+            return None;
+        }
+        if has("no-cache") {
+            return Some(0);
+        }
+        let max_age = |name: &str| {
+            directives
+                .iter()
+                .find(|(d, _)| d == name)
+                .and_then(|(_, v)| v.as_ref()?.parse::<u128>().ok())
+        };
+        Some(
+            max_age("s-maxage")
+                .or_else(|| max_age("max-age"))
+                .map(|secs| secs * 1000)
+                .unwrap_or(0),
+        )
+    }
+
+    /// Check if a response to a request with credentials can be served to other requesters.
+    fn is_shared(request: &HttpRequest, response: &HttpResponse) -> bool {
+        let credentialed = ["authorization", "cookie"]
+            .iter()
+            .any(|h| header(&request.headers, h).is_some());
+        !credentialed
+            || cache_control(&response.headers)
+                .iter()
+                .any(|(d, _)| d == "public" || d == "s-maxage")
+    }
+
+    /// Store a response from upstream, if it's cacheable.
+    pub fn store(&self, request: &HttpRequest, response: &HttpResponse, now: u128) {
+        if response.status != 200 || !Self::is_shared(request, response) {
+            return;
+        }
+        let vary: Vec<String> = header(&response.headers, "vary")
+            .unwrap_or_default()
+            .split(',')
+            .map(|h| h.trim().to_lowercase())
+            .filter(|h| !h.is_empty())
+            .collect();
+        if vary.iter().any(|h| h == "*") {
+            return;
+        }
+        let Some(key) = self.key(request, &vary) else {
+            return;
+        };
+        let Some(ttl) = self.ttl_ms(&response.headers) else {
+            return;
+        };
+        let etag = header(&response.headers, "etag").map(|e| e.to_string());
+        // A response never fresh is only worth storing to be revalidated.
+        if ttl == 0 && etag.is_none() {
+            return;
+        }
+        let size = response_size(response);
+        if size > self.config.max_bytes {
+            return;
+        }
+
+        let mut inner = self.lock();
+        inner.remove(&key);
+        let varies = inner
+            .varies
+            .entry((key.method.clone(), key.path.clone()))
+            .or_default();
+        *varies = (vary, varies.1 + 1);
+        inner.bytes += size;
+        inner.generation += 1;
+        let generation = inner.generation;
+        inner.order.push_back((generation, key.clone()));
+        inner.entries.insert(key, CacheEntry {
+            response: response.clone(),
+            etag,
+            expires_at: now + ttl,
+            generation,
+            size,
+        });
+        inner.evict(now, self.config.max_bytes);
+        inner.compact();
+    }
+
+    /// Refresh a stale response revalidated by upstream with `headers` of 304, and return it.
+    pub fn revalidate(
+        &self,
+        request: &HttpRequest,
+        headers: &HashMap<String, String>,
+        now: u128,
+    ) -> Option<HttpResponse> {
+        let ttl = self.ttl_ms(headers);
+        let mut inner = self.lock();
+        let key = self.key(request, inner.vary_of(request))?;
+        let Some(ttl) = ttl else {
+            inner.remove(&key);
+            return None;
+        };
+        let entry = inner.entries.get_mut(&key)?;
+        entry.expires_at = now + ttl;
+        // The revalidation was counted as a miss on lookup.
+        let _ = self
+            .misses
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |m| m.checked_sub(1));
+        self.hits.fetch_add(1, Ordering::SeqCst);
+        Some(entry.response.clone())
+    }
+
+    /// Drop cached responses of `path`, or every response if path is None.
+    pub fn invalidate(&self, path: Option<&str>) {
+        let mut inner = self.lock();
+        let keys: Vec<CacheKey> = inner
+            .entries
+            .keys()
+            .filter(|k| path.map(|p| k.path == p).unwrap_or(true))
+            .cloned()
+            .collect();
+        for key in keys {
+            inner.remove(&key);
+        }
+        inner.compact();
+    }
+
+    /// Get counters of cache.
+    pub fn stats(&self) -> ResponseCacheStats {
+        let inner = self.lock();
+        ResponseCacheStats {
+            hits: self.hits.load(Ordering::SeqCst),
+            misses: self.misses.load(Ordering::SeqCst),
+            entries: inner.entries.len(),
+            bytes: inner.bytes,
+        }
+    }
+}
+
+impl CacheInner {
+    /// Names of headers in `Vary` of the last response stored for request.
+    fn vary_of(&self, request: &HttpRequest) -> &[String] {
+        self.varies
+            .get(&(request.method.to_uppercase(), request.path.clone()))
+            .map(|(names, _)| names.as_slice())
+            .unwrap_or_default()
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.bytes -= entry.size;
+            let id = (key.method.clone(), key.path.clone());
+            if let Some((_, count)) = self.varies.get_mut(&id) {
+                *count -= 1;
+                if *count == 0 {
+                    self.varies.remove(&id);
+                }
+            }
+        }
+    }
+
+    /// Drop stale responses which can't be revalidated, and the oldest responses beyond
+    /// `max_bytes`.
+    fn evict(&mut self, now: u128, max_bytes: usize) {
+        while let Some((generation, key)) = self.order.front() {
+            let entry = self
+                .entries
+                .get(key)
+                .filter(|e| e.generation == *generation);
+            // A response removed or stored again leaves a stale position, skip it.
+            let Some(entry) = entry else {
+                self.order.pop_front();
+                continue;
+            };
+            let dead = entry.expires_at <= now && entry.etag.is_none();
+            if !dead && self.bytes <= max_bytes {
+                break;
+            }
+            let key = key.clone();
+            self.remove(&key);
+            self.order.pop_front();
+        }
+    }
+
+    /// Drop stale positions of [CacheInner::order] once they outnumber the entries, so the
+    /// queue stays bounded when the front entry is alive.
+    fn compact(&mut self) {
+        if self.order.len() <= 2 * self.entries.len() + 1 {
+            return;
+        }
+        let entries = &self.entries;
+        self.order.retain(|(generation, key)| {
+            entries
+                .get(key)
+                .map(|e| e.generation == *generation)
+                .unwrap_or(false)
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn request(path: &str, headers: &[(&str, &str)]) -> HttpRequest {
+        HttpRequest {
+            name: "api".to_string(),
+            method: "GET".to_string(),
+            path: path.to_string(),
+            timeout: Default::default(),
+            headers: headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            body: None,
+        }
+    }
+
+    fn response(cache_control: &str, etag: Option<&str>) -> HttpResponse {
+        let mut headers = HashMap::from([("Cache-Control".to_string(), cache_control.into())]);
+        if let Some(etag) = etag {
+            headers.insert("ETag".to_string(), etag.to_string());
+        }
+        HttpResponse {
+            status: 200,
+            headers,
+            body: Some(vec![0u8; 100].into()),
+        }
+    }
+
+    #[test]
+    fn test_response_cache() {
+        let cache = ResponseCache::new(ResponseCacheConfig::default());
+        let req = request("/users", &[("Accept", "application/json")]);
+        assert_eq!(cache.lookup(&req, 0), CacheLookup::Miss);
+
+        let resp = response("public, max-age=10", Some("\"v1\""));
+        cache.store(&req, &resp, 0);
+        assert_eq!(cache.lookup(&req, 9_999), CacheLookup::Hit(resp.clone()));
+        // Requests differing in vary headers are cached separately.
+        let html = request("/users", &[("accept", "text/html")]);
+        assert_eq!(cache.lookup(&html, 0), CacheLookup::Miss);
+
+        // A stale response is revalidated by its etag.
+        assert_eq!(
+            cache.lookup(&req, 10_000),
+            CacheLookup::Stale("\"v1\"".to_string(), resp.clone())
+        );
+        let refreshed = cache.revalidate(&req, &HashMap::new(), 10_000);
+        assert_eq!(refreshed, Some(resp));
+        assert!(matches!(cache.lookup(&req, 10_001), CacheLookup::Hit(_)));
+
+        for cache_control in ["no-store", "private, max-age=60"] {
+            let req = request("/private", &[]);
+            cache.store(&req, &response(cache_control, None), 0);
+            assert_eq!(cache.lookup(&req, 1), CacheLookup::Miss);
+        }
+        let bypass = request("/users", &[("Cache-Control", "no-cache")]);
+        assert_eq!(cache.lookup(&bypass, 1), CacheLookup::Bypass);
+
+        cache.invalidate(Some("/users"));
+        assert_eq!(cache.lookup(&req, 1), CacheLookup::Miss);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.entries, stats.bytes), (3, 0, 0));
+    }
+
+    #[test]
+    fn test_response_cache_shared() {
+        let cache = ResponseCache::new(ResponseCacheConfig::default());
+
+        // Responses to requests with credentials are cached only if they are shared.
+        let auth = request("/me", &[("Authorization", "Bearer alice")]);
+        cache.store(&auth, &response("max-age=60", None), 0);
+        assert_eq!(cache.lookup(&request("/me", &[]), 1), CacheLookup::Miss);
+        let cookie = request("/me", &[("Cookie", "session=alice")]);
+        cache.store(&cookie, &response("public, max-age=60", None), 0);
+        assert!(matches!(cache.lookup(&cookie, 1), CacheLookup::Hit(_)));
+
+        // Requests differing in headers of `Vary` of upstream are cached separately.
+        let mut resp = response("max-age=60", None);
+        resp.headers
+            .insert("Vary".to_string(), "X-Tenant".to_string());
+        let tenant = |t: &str| request("/tenant", &[("x-tenant", t)]);
+        cache.store(&tenant("a"), &resp, 0);
+        assert_eq!(
+            cache.lookup(&tenant("a"), 1),
+            CacheLookup::Hit(resp.clone())
+        );
+        assert_eq!(cache.lookup(&tenant("b"), 1), CacheLookup::Miss);
+        resp.headers.insert("Vary".to_string(), "*".to_string());
+        cache.store(&request("/any", &[]), &resp, 0);
+        assert_eq!(cache.lookup(&request("/any", &[]), 1), CacheLookup::Miss);
+
+        // A response without freshness is only stored to be revalidated.
+        let mut resp = response("public", None);
+        cache.store(&request("/plain", &[]), &resp, 0);
+        assert_eq!(cache.lookup(&request("/plain", &[]), 1), CacheLookup::Miss);
+        resp.headers
+            .insert("ETag".to_string(), "\"v1\"".to_string());
+        cache.store(&request("/plain", &[]), &resp, 0);
+        assert_eq!(
+            cache.lookup(&request("/plain", &[]), 1),
+            CacheLookup::Stale("\"v1\"".to_string(), resp)
+        );
+    }
+
+    #[test]
+    fn test_response_cache_bounded() {
+        let size = response_size(&response("max-age=60", None));
+        let cache = ResponseCache::new(ResponseCacheConfig {
+            max_bytes: size * 2,
+            ..Default::default()
+        });
+        for i in 0..3 {
+            let req = request(&format!("/{}", i), &[]);
+            cache.store(&req, &response("max-age=60", None), i);
+        }
+        assert_eq!(cache.stats().bytes, size * 2);
+        // The oldest response is evicted first.
+        assert_eq!(cache.lookup(&request("/0", &[]), 3), CacheLookup::Miss);
+        assert!(matches!(
+            cache.lookup(&request("/2", &[]), 3),
+            CacheLookup::Hit(_)
+        ));
+
+        // Responses without freshness are dropped once expired.
+        let cache = ResponseCache::new(ResponseCacheConfig {
+            respect_origin_headers: false,
+            default_ttl_secs: 1,
+            ..Default::default()
+        });
+        let req = request("/", &[]);
+        cache.store(&req, &response("no-store", None), 0);
+        assert!(matches!(cache.lookup(&req, 999), CacheLookup::Hit(_)));
+        assert_eq!(cache.lookup(&req, 1000), CacheLookup::Miss);
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn test_response_cache_order_bounded() {
+        let cache = ResponseCache::new(ResponseCacheConfig::default());
+        let first = request("/first", &[]);
+        cache.store(&first, &response("max-age=60", None), 0);
+
+        // Storing a response again behind a fresh one doesn't grow the queue.
+        let req = request("/again", &[]);
+        for _ in 0..1000 {
+            cache.store(&req, &response("max-age=60", None), 0);
+        }
+        assert!(cache.inner.lock().unwrap().order.len() <= 5);
+        assert!(matches!(cache.lookup(&req, 1), CacheLookup::Hit(_)));
+
+        for i in 0..1000 {
+            let req = request(&format!("/{}", i), &[]);
+            cache.store(&req, &response("max-age=60", None), 0);
+            cache.invalidate(Some(&format!("/{}", i)));
+        }
+        assert!(cache.inner.lock().unwrap().order.len() <= 5);
+        assert!(matches!(cache.lookup(&first, 1), CacheLookup::Hit(_)));
+    }
+}
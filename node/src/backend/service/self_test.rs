@@ -0,0 +1,322 @@
+#![warn(missing_docs)]
+//! One-shot self-test exercising the full message path to a peer, so that operators can
+//! confirm end-to-end connectivity after bringing up a node and before taking traffic.
+use std::future::Future;
+use std::time::Duration;
+use std::time::Instant;
+
+use bytes::Bytes;
+use serde::Deserialize;
+use serde::Serialize;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::backend::service::echo::EchoMessage;
+use crate::backend::service::proxy::wrap_custom_message;
+use crate::backend::service::proxy::Tunnel;
+use crate::backend::service::proxy::TunnelMessage;
+use crate::backend::service::Backend;
+use crate::backend::types::BackendMessage;
+use crate::backend::types::ErrorResponse;
+use crate::backend::types::MessageType;
+use crate::consts::BACKEND_MTU;
+use crate::consts::SELF_TEST_TIMEOUT;
+use crate::error::Error;
+use crate::error::Result;
+use crate::prelude::rings_core::prelude::uuid::Uuid;
+use crate::prelude::rings_core::swarm::PeerSignal;
+use crate::prelude::*;
+
+/// Length of the small message sent by self-test.
+const SMALL_MESSAGE_LEN: usize = 1024;
+
+/// Result of a check of self-test.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SelfTestCheck {
+    /// name of check, such as `ping` or `tunnel`
+    pub name: String,
+    /// whether the check passed
+    pub passed: bool,
+    /// time taken by the check in milliseconds
+    pub elapsed_ms: u128,
+    /// reason of failure
+    pub error: Option<String>,
+}
+
+/// Report of [Backend::self_test].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SelfTestReport {
+    /// the peer tested with
+    pub peer: Did,
+    /// checks in the order they ran
+    pub checks: Vec<SelfTestCheck>,
+}
+
+impl SelfTestReport {
+    /// Check if all checks passed.
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|c| c.passed)
+    }
+}
+
+async fn run_check<F>(name: &str, check: F) -> SelfTestCheck
+where F: Future<Output = Result<()>> {
+    let start = Instant::now();
+    let result = tokio::time::timeout(Duration::from_secs(SELF_TEST_TIMEOUT), check)
+        .await
+        .unwrap_or(Err(Error::ResponseTimeout));
+    SelfTestCheck {
+        name: name.to_string(),
+        passed: result.is_ok(),
+        elapsed_ms: start.elapsed().as_millis(),
+        error: result.err().map(|e| e.to_string()),
+    }
+}
+
+/// Receive backend messages until `f` returns a result.
+async fn recv_until<T>(
+    receiver: &mut broadcast::Receiver<BackendMessage>,
+    f: impl Fn(&BackendMessage) -> Option<Result<T>>,
+) -> Result<T> {
+    loop {
+        match receiver.recv().await {
+            Ok(msg) => {
+                if let Some(result) = f(&msg) {
+                    return result;
+                }
+            }
+            Err(RecvError::Lagged(n)) => tracing::warn!("self-test lagged {} messages", n),
+            Err(RecvError::Closed) => return Err(Error::InternalError),
+        }
+    }
+}
+
+impl Backend {
+    async fn echo(&self, peer: Did, data: Bytes) -> Result<()> {
+        let id = Uuid::new_v4();
+        let req = BackendMessage::try_from((MessageType::Echo, &EchoMessage::Request {
+            id,
+            data: data.clone(),
+        }))?;
+        let timeout = Duration::from_secs(SELF_TEST_TIMEOUT);
+        self.request_until(peer, req, timeout, |msg| match EchoMessage::try_from(msg) {
+            Ok(EchoMessage::Reply {
+                id: rid,
+                data: echoed,
+            }) if rid == id => Some(
+                (echoed == data)
+                    .then_some(())
+                    .ok_or(Error::ResponseMismatch),
+            ),
+            _ => ErrorResponse::try_from(msg)
+                .ok()
+                .map(|e| Err(Error::RemoteRpcError(e.message))),
+        })
+        .await?
+    }
+
+    /// Dial service through a tunnel. The dial passes once the remote acknowledges that the
+    /// service is connected, by `TcpDialAck`. Peers of older versions never acknowledge, so
+    /// the dial fails on timeout with them.
+    async fn dial_tunnel(&self, peer: Did, service: &str) -> Result<()> {
+        let mut receiver = self.broadcaster.subscribe();
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .map_err(|e| Error::TunnelError(e.kind().into()))?;
+        let addr = listener
+            .local_addr()
+            .map_err(|e| Error::TunnelError(e.kind().into()))?;
+        let (local, accepted) = tokio::join!(TcpStream::connect(addr), listener.accept());
+        let local = local.map_err(|e| Error::TunnelError(e.kind().into()))?;
+        let (stream, _) = accepted.map_err(|e| Error::TunnelError(e.kind().into()))?;
+
+        let tid = Uuid::new_v4();
+        let mut tunnel = Tunnel::new(tid);
+        tunnel.listen(stream, self.swarm.clone(), peer).await;
+        self.tcp_server.tunnels.insert(tid, tunnel);
+
+        let dial = TunnelMessage::TcpDial {
+            tid,
+            service: service.to_string(),
+        };
+        let result = match self
+            .swarm
+            .send_message(wrap_custom_message(&dial), peer)
+            .await
+        {
+            Err(e) => Err(Error::SendMessage(e)),
+            Ok(_) => {
+                recv_until(&mut receiver, |msg| {
+                    match MessageType::from(msg.message_type) {
+                        MessageType::TunnelMessage => {
+                            match TunnelMessage::try_from(msg.data.as_slice()) {
+                                Ok(TunnelMessage::TcpDialAck { tid: t }) if t == tid => {
+                                    Some(Ok(()))
+                                }
+                                Ok(TunnelMessage::TcpClose { tid: t, reason }) if t == tid => {
+                                    Some(Err(Error::TunnelError(reason)))
+                                }
+                                _ => None,
+                            }
+                        }
+                        // The remote reports a dial failure, such as an unknown service.
+                        MessageType::Error => ErrorResponse::try_from(msg)
+                            .ok()
+                            .map(|e| Err(Error::RemoteRpcError(e.message))),
+                        _ => None,
+                    }
+                })
+                .await
+            }
+        };
+
+        // Close the local side, so that the tunnel is closed on both sides.
+        drop(local);
+        if let Some((_, tunnel)) = self.tcp_server.tunnels.remove(&tid) {
+            tokio::spawn(tunnel.close());
+        }
+        result
+    }
+
+    /// Run a self-test with peer, which sends a ping, a small message and a chunked large
+    /// message to be echoed, and dials `service` through a tunnel if it's provided.
+    /// Each check is bounded by [SELF_TEST_TIMEOUT], and all checks run even if some fail.
+    pub async fn self_test(&self, peer: Did, service: Option<&str>) -> SelfTestReport {
+        let ping = run_check("ping", self.echo(peer, Bytes::new())).await;
+        if ping.passed {
+            let rtt = PeerSignal::Rtt(ping.elapsed_ms as u64);
+            self.swarm.record_peer_signal(peer, rtt);
+        }
+        let mut checks = vec![
+            ping,
+            run_check(
+                "small_message",
+                self.echo(peer, Bytes::from(vec![7u8; SMALL_MESSAGE_LEN])),
+            )
+            .await,
+            run_check(
+                "chunked_message",
+                self.echo(peer, Bytes::from(vec![9u8; BACKEND_MTU * 2])),
+            )
+            .await,
+        ];
+        if let Some(service) = service {
+            checks.push(run_check("tunnel", self.dial_tunnel(peer, service)).await);
+        }
+        SelfTestReport { peer, checks }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::backend::service::tcp_server::TcpServiceConfig;
+    use crate::backend::service::BackendConfig;
+    use crate::drain::Drain;
+    use crate::processor::Processor;
+    use crate::tests::native::prepare_processor;
+
+    /// Set a backend serving `tcp_services` to processor.
+    async fn set_backend(processor: &Processor, tcp_services: Vec<TcpServiceConfig>) {
+        let config = BackendConfig {
+            tcp_services,
+            ..Default::default()
+        };
+        let (sender, _) = broadcast::channel(64);
+        let backend = Backend::new(config, sender, processor.swarm.clone(), Drain::default())
+            .await
+            .unwrap();
+        processor.set_backend(Arc::new(backend)).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run_check() {
+        let check = run_check("ok", async { Ok(()) }).await;
+        assert!(check.passed);
+        assert_eq!(check.error, None);
+
+        let check = run_check("failed", async { Err(Error::ResponseMismatch) }).await;
+        assert!(!check.passed);
+        assert!(check.error.is_some());
+
+        let report = SelfTestReport {
+            peer: SecretKey::random().address().into(),
+            checks: vec![check],
+        };
+        assert!(!report.passed());
+    }
+
+    #[tokio::test]
+    async fn test_self_test_between_two_nodes() {
+        // A tcp service echoing what it reads.
+        let echo = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let echo_addr = echo.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = echo.accept().await {
+                tokio::spawn(async move {
+                    let (mut r, mut w) = stream.split();
+                    let _ = tokio::io::copy(&mut r, &mut w).await;
+                });
+            }
+        });
+
+        let (p1, path1) = prepare_processor(None).await;
+        let (p2, path2) = prepare_processor(None).await;
+
+        // Self-test needs a backend.
+        let result = p1.self_test(p2.did(), None).await;
+        assert!(matches!(result, Err(Error::BackendNotSet)));
+
+        set_backend(&p1, vec![]).await;
+        set_backend(&p2, vec![TcpServiceConfig {
+            name: "echo".to_string(),
+            register_service: None,
+            addr: echo_addr,
+            capture: false,
+            max_concurrency: None,
+        }])
+        .await;
+
+        let swarm1 = p1.swarm.clone();
+        let swarm2 = p2.swarm.clone();
+        tokio::spawn(async move { swarm1.listen().await });
+        tokio::spawn(async move { swarm2.listen().await });
+
+        let (conn, offer) = p1.swarm.create_offer(p2.did()).await.unwrap();
+        let (_, answer) = p2.swarm.answer_offer(offer).await.unwrap();
+        p1.swarm.accept_answer(answer).await.unwrap();
+        conn.webrtc_wait_for_data_channel_open().await.unwrap();
+
+        let report = p1.self_test(p2.did(), Some("echo")).await.unwrap();
+        assert_eq!(report.peer, p2.did());
+        let names = report
+            .checks
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect::<Vec<_>>();
+        let expected = ["ping", "small_message", "chunked_message", "tunnel"];
+        assert_eq!(names, expected);
+        assert!(report.passed(), "{:?}", report);
+
+        // A missing service fails the tunnel check only.
+        let report = p1.self_test(p2.did(), Some("missing")).await.unwrap();
+        let (tunnel, others) = report.checks.split_last().unwrap();
+        assert!(others.iter().all(|c| c.passed), "{:?}", report);
+        assert_eq!(tunnel.name, "tunnel");
+        assert!(!tunnel.passed);
+        assert!(tunnel.error.is_some());
+
+        // An unreachable peer fails every check.
+        let unreachable = SecretKey::random().address().into();
+        let report = p1.self_test(unreachable, None).await.unwrap();
+        assert_eq!(report.checks.len(), 3);
+        assert!(report.checks.iter().all(|c| !c.passed), "{:?}", report);
+
+        tokio::fs::remove_dir_all(path1).await.unwrap();
+        tokio::fs::remove_dir_all(path2).await.unwrap();
+    }
+}
@@ -2,22 +2,36 @@
 
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Instant;
 
 use serde::Deserialize;
 use serde::Serialize;
 
+use crate::backend::service::access_log::AccessLog;
+use crate::backend::service::access_log::AccessLogEntry;
+use crate::backend::service::access_log::TunnelLogGuard;
+use crate::backend::service::bulkhead::ServiceConcurrency;
+use crate::backend::service::capture::TunnelCapture;
+use crate::backend::service::capture::TunnelCaptures;
 use crate::backend::service::proxy::tcp_connect_with_timeout;
 use crate::backend::service::proxy::wrap_custom_message;
 use crate::backend::service::proxy::Tunnel;
 use crate::backend::service::proxy::TunnelId;
 use crate::backend::service::proxy::TunnelMessage;
+use crate::backend::service::proxy::TunnelQueueConfig;
+use crate::backend::service::tunnel_pool::TunnelPool;
+use crate::backend::service::tunnel_pool::TunnelPoolConfig;
 use crate::backend::types::BackendMessage;
 use crate::backend::MessageEndpoint;
 use crate::consts::TCP_SERVER_TIMEOUT;
+use crate::drain::Drain;
 use crate::error::Error;
 use crate::error::Result;
+use crate::error::TunnelDefeat;
+use crate::prelude::rings_core::dht::Did;
 use crate::prelude::rings_core::message::MessageVerificationExt;
 use crate::prelude::rings_core::prelude::dashmap::DashMap;
+use crate::prelude::rings_core::utils::get_epoch_ms;
 use crate::prelude::*;
 
 /// HTTP Server Config, specific determine port.
@@ -31,6 +45,14 @@ pub struct TcpServiceConfig {
 
     /// address of hidden service
     pub addr: SocketAddr,
+
+    /// capture tunnels to this service, requires capture of backend to be configured
+    #[serde(default)]
+    pub capture: bool,
+
+    /// max number of tunnels open simultaneously, excess is refused as busy
+    #[serde(default)]
+    pub max_concurrency: Option<usize>,
 }
 
 /// TcpServer provides reverse proxy for hidden tcp services on RingsNetwork.
@@ -41,18 +63,117 @@ pub struct TcpServer {
     /// tunnels to services
     pub tunnels: DashMap<TunnelId, Tunnel>,
 
+    /// tunnels open to services
+    pub concurrency: ServiceConcurrency,
+
     swarm: Arc<Swarm>,
+
+    capture: Option<TunnelCaptures>,
+
+    drain: Drain,
+
+    pool: Option<TunnelPool>,
+
+    queue: TunnelQueueConfig,
+
+    access_log: Option<AccessLog>,
 }
 
 impl TcpServer {
     /// Create a new instance of TcpServer
     pub fn new(services: Vec<TcpServiceConfig>, swarm: Arc<Swarm>) -> Self {
+        let concurrency =
+            ServiceConcurrency::new(services.iter().map(|x| (x.name.clone(), x.max_concurrency)));
         Self {
             services,
             tunnels: DashMap::new(),
+            concurrency,
             swarm,
+            capture: None,
+            drain: Drain::default(),
+            pool: None,
+            queue: TunnelQueueConfig::default(),
+            access_log: None,
         }
     }
+
+    /// Record tunnels to access log once they are refused or closed, see
+    /// [access_log](crate::backend::service::access_log). Not recorded if it's not set.
+    pub fn with_access_log(mut self, access_log: Option<AccessLog>) -> Self {
+        self.access_log = access_log;
+        self
+    }
+
+    /// Set the queue of packages from peer in each tunnel, see
+    /// [TunnelOverflow](crate::backend::service::proxy::TunnelOverflow).
+    pub fn with_queue(mut self, queue: TunnelQueueConfig) -> Self {
+        self.queue = queue;
+        self
+    }
+
+    /// Get the queue config of tunnels, which tunnels dialed by this node should share.
+    pub fn queue(&self) -> TunnelQueueConfig {
+        self.queue
+    }
+
+    /// Drive tunnels by a pool of workers sharing read buffers, see [TunnelPool]. Each tunnel
+    /// is driven by a task of its own, and owns its buffers, if not configured.
+    pub fn with_pool(mut self, config: Option<TunnelPoolConfig>) -> Self {
+        self.pool = config.as_ref().map(TunnelPool::new);
+        self
+    }
+
+    /// Set draining state, new tunnels are rejected while draining.
+    pub fn with_drain(mut self, drain: Drain) -> Self {
+        self.drain = drain;
+        self
+    }
+
+    /// Set captures of tunnels, see [TunnelCaptures].
+    pub fn with_capture(mut self, capture: Option<TunnelCaptures>) -> Self {
+        self.capture = capture;
+        self
+    }
+
+    /// Create a capture of tunnel, returns `None` if capture is not configured.
+    pub fn new_capture(&self, tid: TunnelId) -> Option<TunnelCapture> {
+        Some(self.capture.as_ref()?.capture(tid))
+    }
+
+    /// Record a tunnel refused to access log.
+    fn record_refused(&self, peer: Did, service: &str, tid: TunnelId, started: Instant, e: &Error) {
+        let Some(access_log) = self.access_log.as_ref() else {
+            return;
+        };
+        let entry = AccessLogEntry::tunnel(get_epoch_ms() as u64, peer, service, tid);
+        let duration_ms = started.elapsed().as_millis() as u64;
+        access_log.record(&entry.respond(e, None, duration_ms));
+    }
+
+    /// Open a tunnel in access log, which is recorded as closed once its listener exits,
+    /// see [AccessLog::open_tunnel].
+    fn open_tunnel_log(
+        &self,
+        peer: Did,
+        service: &str,
+        tid: TunnelId,
+        started: Instant,
+    ) -> Option<TunnelLogGuard> {
+        let entry = AccessLogEntry::tunnel(get_epoch_ms() as u64, peer, service, tid);
+        Some(self.access_log.as_ref()?.open_tunnel(entry, started))
+    }
+
+    /// List id of tunnels open to services.
+    pub fn list_tunnels(&self) -> Vec<TunnelId> {
+        self.tunnels.iter().map(|x| *x.key()).collect()
+    }
+
+    /// Check if a hidden service with given name is hosted.
+    pub fn has_service(&self, name: &str) -> bool {
+        self.services
+            .iter()
+            .any(|x| x.name.eq_ignore_ascii_case(name))
+    }
 }
 
 #[async_trait::async_trait]
@@ -63,16 +184,44 @@ impl MessageEndpoint for TcpServer {
         msg: &BackendMessage,
     ) -> Result<Vec<MessageHandlerEvent>> {
         let peer_did = ctx.transaction.signer();
-        let tunnel_msg: TunnelMessage =
-            bincode::deserialize(&msg.data).map_err(|_| Error::DecodeError)?;
+        let (tunnel_msg, extended) = TunnelMessage::decode(msg.data.as_slice())?;
+        // Tunnels dialed by this node learn capability of peer from its replies.
+        if extended {
+            if let Some(tunnel) = self.tunnels.get(&tunnel_msg.tid()) {
+                tunnel.set_peer_extended();
+            }
+        }
 
         match tunnel_msg {
             TunnelMessage::TcpDial { tid, service } => {
+                // The dialed service is authorized, so a tag naming another is refused.
+                if let Some(tag) = msg.service().filter(|t| !t.eq_ignore_ascii_case(&service)) {
+                    return Err(Error::ServiceTagMismatch(tag.to_string(), service));
+                }
                 let service = self
                     .services
                     .iter()
                     .find(|x| x.name.eq_ignore_ascii_case(&service))
                     .ok_or(Error::InvalidService)?;
+                let started = Instant::now();
+
+                let admitted = self.drain.admit().and_then(|work| {
+                    let permit = self.concurrency.acquire(&service.name)?;
+                    Ok((work, permit))
+                });
+                let (work, permit) = match admitted {
+                    Ok(admitted) => admitted,
+                    Err(e) => {
+                        let reason = TunnelDefeat::ConnectionRefused;
+                        let msg = TunnelMessage::TcpClose { tid, reason };
+                        self.swarm
+                            .send_report_message(ctx, wrap_custom_message(&msg))
+                            .await
+                            .map_err(Error::SendMessage)?;
+                        self.record_refused(peer_did, &service.name, tid, started, &e);
+                        return Err(e);
+                    }
+                };
 
                 match tcp_connect_with_timeout(service.addr, TCP_SERVER_TIMEOUT).await {
                     Err(e) => {
@@ -83,28 +232,92 @@ impl MessageEndpoint for TcpServer {
                             .await
                             .map_err(Error::SendMessage)?;
 
-                        Err(Error::TunnelError(e))?;
+                        let e = Error::TunnelError(e);
+                        self.record_refused(peer_did, &service.name, tid, started, &e);
+                        Err(e)?;
                     }
 
                     Ok(local_stream) => {
-                        let mut tunnel = Tunnel::new(tid);
+                        // Release tunnels whose close is never acknowledged.
+                        self.tunnels.retain(|_, t| !t.is_finished());
+                        // The tunnel is counted as work in flight until its listener exits.
+                        let mut tunnel = Tunnel::new(tid)
+                            .with_pool(self.pool.clone())
+                            .with_queue(self.queue)
+                            .with_drain_guard(work)
+                            .with_service_permit(permit)
+                            .with_access_log(self.open_tunnel_log(
+                                peer_did,
+                                &service.name,
+                                tid,
+                                started,
+                            ));
+                        if extended {
+                            tunnel.set_peer_extended();
+                        }
+                        if service.capture {
+                            if let Some(capture) = self.new_capture(tid) {
+                                tunnel = tunnel.with_capture(capture);
+                            }
+                        }
                         tunnel
                             .listen(local_stream, self.swarm.clone(), peer_did)
                             .await;
                         self.tunnels.insert(tid, tunnel);
+                        if extended {
+                            let msg = TunnelMessage::TcpDialAck { tid };
+                            self.swarm
+                                .send_report_message(ctx, wrap_custom_message(&msg))
+                                .await
+                                .map_err(Error::SendMessage)?;
+                        }
                     }
                 }
             }
-            TunnelMessage::TcpClose { tid, .. } => {
-                self.tunnels.remove(&tid);
+            TunnelMessage::TcpClose { tid, .. } => match self.tunnels.remove(&tid) {
+                // The listener acknowledges after remaining packages are written.
+                Some((_, tunnel)) => {
+                    tokio::spawn(tunnel.close());
+                }
+                // Peers of older versions don't wait for the acknowledgement.
+                None if !extended => {}
+                None => {
+                    let msg = TunnelMessage::TcpCloseAck { tid };
+                    self.swarm
+                        .send_message(wrap_custom_message(&msg), peer_did)
+                        .await
+                        .map_err(Error::SendMessage)?;
+                }
+            },
+            TunnelMessage::TcpCloseAck { tid } => {
+                if let Some((_, tunnel)) = self.tunnels.remove(&tid) {
+                    tokio::spawn(tunnel.close());
+                }
+            }
+            TunnelMessage::TcpShutdownWrite { tid, seq } => {
+                self.tunnels
+                    .get(&tid)
+                    .ok_or(Error::TunnelNotFound)?
+                    .shutdown_write(seq)
+                    .await;
             }
             TunnelMessage::TcpPackage { tid, body } => {
                 self.tunnels
                     .get(&tid)
                     .ok_or(Error::TunnelNotFound)?
-                    .send(body)
+                    .send_legacy(body)
+                    .await;
+            }
+            TunnelMessage::TcpSequencedPackage { tid, seq, body } => {
+                self.tunnels
+                    .get(&tid)
+                    .ok_or(Error::TunnelNotFound)?
+                    .send(seq, body)
                     .await;
             }
+            // The tunnel is open once its listener is spawned, the ack only tells the dialer
+            // that the service is connected.
+            TunnelMessage::TcpDialAck { .. } => {}
         }
 
         Ok(vec![])
@@ -0,0 +1,685 @@
+#![warn(missing_docs)]
+//! Resumable transfers of large data between peers.
+//!
+//! The sender splits data into segments carrying their offset and the total length.
+//! The receiver only appends a segment at the offset it has received so far and acknowledges
+//! that offset, so a transfer interrupted by a dropped transport is continued from the last
+//! acknowledged offset instead of restarted. To resume, the sender queries the offset by
+//! [TransferMessage::Resume], which is done for all transfers to a peer once it is connected again.
+//!
+//! Segments sent by [TransferEndpoint::send] are chunked with acks, see [arq](super::arq), so
+//! that a chunk lost on a lossy link is resent alone instead of the whole segment. Segments
+//! resumed are sent unacknowledged by a task of their own, at most [TransferConfig::window]
+//! segments ahead of the offset acknowledged by receiver.
+//!
+//! Transfers are disabled unless [TransferConfig::enabled] is set. Data received is kept
+//! within [TransferConfig::max_bytes], and is dropped [TransferConfig::ttl_ms] after the last
+//! segment of an incoming transfer, or after a transfer completed.
+use std::sync::Arc;
+use std::time::Duration;
+
+use bincode::Options;
+use bytes::Bytes;
+use serde::Deserialize;
+use serde::Serialize;
+use tokio::sync::Notify;
+
+use crate::backend::service::arq::ChunkArq;
+use crate::backend::types::encode_custom_messages_for;
+use crate::backend::types::BackendMessage;
+use crate::backend::types::MessageEndpoint;
+use crate::backend::types::MessageType;
+use crate::consts::BACKEND_MTU;
+use crate::consts::TRANSFER_ACK_TIMEOUT;
+use crate::consts::TRANSFER_MAX_LEN;
+use crate::consts::TRANSFER_MAX_PER_PEER;
+use crate::consts::TRANSFER_SEGMENT_SIZE;
+use crate::error::Error;
+use crate::error::Result;
+use crate::prelude::rings_core::prelude::dashmap::DashMap;
+use crate::prelude::rings_core::prelude::uuid::Uuid;
+use crate::prelude::rings_core::utils::get_epoch_ms;
+use crate::prelude::*;
+
+/// Id of a transfer, generated by sender.
+pub type TransferId = Uuid;
+
+/// Messages of a transfer, carried by [BackendMessage] of [MessageType::Transfer].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TransferMessage {
+    /// A segment of data starting at `offset`, `total` is the length of whole data.
+    Data {
+        /// id of transfer
+        id: TransferId,
+        /// offset of segment
+        offset: u64,
+        /// length of whole data
+        total: u64,
+        /// data of segment
+        data: Bytes,
+    },
+    /// Query the received offset of a transfer, sent by sender on resumption.
+    Resume {
+        /// id of transfer
+        id: TransferId,
+    },
+    /// The received offset of a transfer, replied to [TransferMessage::Data] and [TransferMessage::Resume].
+    Ack {
+        /// id of transfer
+        id: TransferId,
+        /// length of data received continuously from the start
+        offset: u64,
+    },
+    /// Cancel a transfer, the other side discards its state.
+    Cancel {
+        /// id of transfer
+        id: TransferId,
+    },
+}
+
+impl TryFrom<&[u8]> for TransferMessage {
+    type Error = Error;
+
+    /// Decode untrusted bytes, reading is limited to the length of input.
+    fn try_from(value: &[u8]) -> Result<Self> {
+        bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .allow_trailing_bytes()
+            .with_limit(value.len() as u64)
+            .deserialize(value)
+            .map_err(|_| Error::DecodeError)
+    }
+}
+
+/// Config of resumable transfers, see [transfer](self).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct TransferConfig {
+    /// accept transfers, transfers sent and received are rejected if not enabled
+    pub enabled: bool,
+    /// milliseconds an incoming transfer is kept since its last segment, and a completed
+    /// one since it completed
+    pub ttl_ms: u64,
+    /// max bytes of incoming and completed transfers, incoming ones are counted by their
+    /// whole length. The earliest completed transfers are dropped for a new one.
+    pub max_bytes: u64,
+    /// max number of segments of a resumed transfer sent and not acknowledged yet
+    pub window: usize,
+}
+
+impl Default for TransferConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ttl_ms: 600_000,
+            max_bytes: 2 * TRANSFER_MAX_LEN,
+            window: 16,
+        }
+    }
+}
+
+/// Progress of a transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferProgress {
+    /// Bytes acknowledged by receiver, or received from sender.
+    pub offset: u64,
+    /// Length of whole data.
+    pub total: u64,
+}
+
+struct OutgoingTransfer {
+    destination: Did,
+    data: Bytes,
+    acked: u64,
+    /// Waiting for the acknowledgement of [TransferMessage::Resume].
+    resuming: bool,
+    /// Notified on each ack, which the task sending resumed segments waits for.
+    ack_notify: Arc<Notify>,
+}
+
+struct IncomingTransfer {
+    sender: Did,
+    total: u64,
+    data: Vec<u8>,
+    /// Time of the last segment.
+    updated_at: u128,
+}
+
+struct CompletedTransfer {
+    sender: Did,
+    data: Bytes,
+    completed_at: u128,
+}
+
+impl IncomingTransfer {
+    /// Append segment if it starts at the received offset, and return the received offset.
+    /// Duplicated or out of order segments are ignored, the sender will resume from the offset.
+    /// Segments ending past the declared length are rejected.
+    fn write(&mut self, offset: u64, segment: &[u8]) -> Result<u64> {
+        let end = offset
+            .checked_add(segment.len() as u64)
+            .ok_or(Error::InvalidData)?;
+        if end > self.total {
+            return Err(Error::InvalidData);
+        }
+        if offset == self.data.len() as u64 {
+            self.data.extend_from_slice(segment);
+        }
+        Ok(self.data.len() as u64)
+    }
+
+    fn is_complete(&self) -> bool {
+        self.data.len() as u64 == self.total
+    }
+}
+
+/// TransferEndpoint sends and receives resumable transfers, see [TransferMessage].
+pub struct TransferEndpoint {
+    swarm: Arc<Swarm>,
+    outgoing: Arc<DashMap<TransferId, OutgoingTransfer>>,
+    incoming: DashMap<TransferId, IncomingTransfer>,
+    completed: DashMap<TransferId, CompletedTransfer>,
+    arq: Option<Arc<ChunkArq>>,
+    config: TransferConfig,
+}
+
+impl TransferEndpoint {
+    /// Create a new instance of TransferEndpoint, which is disabled until configured by
+    /// [TransferEndpoint::with_config].
+    pub fn new(swarm: Arc<Swarm>) -> Self {
+        Self {
+            swarm,
+            outgoing: Arc::new(DashMap::new()),
+            incoming: DashMap::new(),
+            completed: DashMap::new(),
+            arq: None,
+            config: TransferConfig::default(),
+        }
+    }
+
+    /// Set the config of transfers, see [TransferConfig].
+    pub fn with_config(mut self, config: TransferConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Check if transfers are enabled, see [TransferConfig::enabled].
+    fn check_enabled(&self) -> Result<()> {
+        if self.config.enabled {
+            Ok(())
+        } else {
+            Err(Error::InvalidService)
+        }
+    }
+
+    /// Send segments with acks of [ChunkArq], which should be the one receiving acks of swarm.
+    pub fn with_arq(mut self, arq: Arc<ChunkArq>) -> Self {
+        self.arq = Some(arq);
+        self
+    }
+
+    async fn send_transfer_message(&self, destination: Did, msg: &TransferMessage) -> Result<()> {
+        Self::send_transfer_message_by(&self.swarm, destination, msg).await
+    }
+
+    async fn send_transfer_message_by(
+        swarm: &Swarm,
+        destination: Did,
+        msg: &TransferMessage,
+    ) -> Result<()> {
+        let msg = BackendMessage::try_from((MessageType::Transfer, msg))?;
+        for data in encode_custom_messages_for(swarm, destination, msg, BACKEND_MTU)? {
+            let msg = Message::custom(&data).map_err(Error::SendMessage)?;
+            swarm
+                .send_message(msg, destination)
+                .await
+                .map_err(Error::SendMessage)?;
+        }
+        Ok(())
+    }
+
+    /// Send a segment with acks if possible, see [arq](super::arq). Acks are of chunks of a
+    /// message as is, so a segment transformed by a payload pipeline of swarm is sent
+    /// unacknowledged.
+    async fn send_segment(&self, destination: Did, msg: &TransferMessage) -> Result<()> {
+        match &self.arq {
+            Some(arq) if self.swarm.payload_pipeline().is_empty() => {
+                let msg = BackendMessage::try_from((MessageType::Transfer, msg))?;
+                arq.send(&self.swarm, destination, msg).await
+            }
+            _ => self.send_transfer_message(destination, msg).await,
+        }
+    }
+
+    /// Send segments of transfer from offset with acks.
+    /// If the transport drops meanwhile, the transfer is kept to be resumed.
+    async fn send_from(&self, id: TransferId, offset: u64) -> Result<()> {
+        let (destination, data) = {
+            let transfer = self.outgoing.get(&id).ok_or(Error::TransferNotFound)?;
+            (transfer.destination, transfer.data.clone())
+        };
+        let total = data.len() as u64;
+        let mut offset = offset.min(total) as usize;
+        while offset < data.len() {
+            // Stop if the transfer is cancelled meanwhile.
+            if !self.outgoing.contains_key(&id) {
+                return Ok(());
+            }
+            let end = (offset + TRANSFER_SEGMENT_SIZE).min(data.len());
+            let msg = TransferMessage::Data {
+                id,
+                offset: offset as u64,
+                total,
+                data: data.slice(offset..end),
+            };
+            self.send_segment(destination, &msg).await?;
+            offset = end;
+        }
+        Ok(())
+    }
+
+    /// Send segments of a resumed transfer from offset on a task of its own, so that the
+    /// handler of acks never waits for sending. See [TransferEndpoint::send_windowed].
+    fn spawn_resumed(&self, id: TransferId, offset: u64) {
+        let swarm = self.swarm.clone();
+        let outgoing = self.outgoing.clone();
+        let window = (self.config.window.max(1) * TRANSFER_SEGMENT_SIZE) as u64;
+        tokio::spawn(async move {
+            if let Err(e) = Self::send_windowed(&swarm, &outgoing, id, offset, window).await {
+                tracing::warn!("resumed transfer {} interrupted: {}", id, e);
+            }
+        });
+    }
+
+    /// Send segments unacknowledged from offset, at most `window` bytes ahead of the offset
+    /// acknowledged by receiver. It stops once the transfer is cancelled or completed, or no
+    /// ack arrives in [TRANSFER_ACK_TIMEOUT], and the transfer is left to be resumed again.
+    async fn send_windowed(
+        swarm: &Swarm,
+        outgoing: &DashMap<TransferId, OutgoingTransfer>,
+        id: TransferId,
+        offset: u64,
+        window: u64,
+    ) -> Result<()> {
+        let (destination, data) = {
+            let transfer = outgoing.get(&id).ok_or(Error::TransferNotFound)?;
+            (transfer.destination, transfer.data.clone())
+        };
+        let total = data.len() as u64;
+        let mut offset = offset.min(total);
+        while offset < total {
+            let ack_notify = {
+                let Some(transfer) = outgoing.get(&id) else {
+                    return Ok(());
+                };
+                (offset >= transfer.acked.saturating_add(window))
+                    .then(|| transfer.ack_notify.clone())
+            };
+            if let Some(ack_notify) = ack_notify {
+                tokio::time::timeout(
+                    Duration::from_secs(TRANSFER_ACK_TIMEOUT),
+                    ack_notify.notified(),
+                )
+                .await
+                .map_err(|_| Error::ResponseTimeout)?;
+                continue;
+            }
+            let end = (offset + TRANSFER_SEGMENT_SIZE as u64).min(total);
+            let msg = TransferMessage::Data {
+                id,
+                offset,
+                total,
+                data: data.slice(offset as usize..end as usize),
+            };
+            Self::send_transfer_message_by(swarm, destination, &msg).await?;
+            offset = end;
+        }
+        Ok(())
+    }
+
+    /// Start a transfer of data to destination, and return its id.
+    /// A transfer interrupted by the transport is kept, and resumed once the peer is connected again.
+    /// Segments are acknowledged, so it should never be awaited by a handler of messages.
+    pub async fn send(&self, destination: Did, data: Bytes) -> Result<TransferId> {
+        self.check_enabled()?;
+        if data.len() as u64 > TRANSFER_MAX_LEN {
+            return Err(Error::InvalidData);
+        }
+        let id = TransferId::new_v4();
+        self.outgoing.insert(id, OutgoingTransfer {
+            destination,
+            data,
+            acked: 0,
+            resuming: false,
+            ack_notify: Arc::new(Notify::new()),
+        });
+        if let Err(e) = self.send_from(id, 0).await {
+            tracing::warn!("transfer {} interrupted, it will be resumed: {}", id, e);
+        }
+        Ok(id)
+    }
+
+    /// Query the received offset of transfer, the rest is sent once receiver acknowledges.
+    pub async fn resume(&self, id: TransferId) -> Result<()> {
+        let destination = {
+            let mut transfer = self.outgoing.get_mut(&id).ok_or(Error::TransferNotFound)?;
+            transfer.resuming = true;
+            transfer.destination
+        };
+        self.send_transfer_message(destination, &TransferMessage::Resume { id })
+            .await
+    }
+
+    /// Resume all transfers to peer, called when peer is connected again.
+    pub async fn resume_peer(&self, peer: Did) {
+        let ids: Vec<TransferId> = self
+            .outgoing
+            .iter()
+            .filter(|t| t.destination == peer)
+            .map(|t| *t.key())
+            .collect();
+        for id in ids {
+            if let Err(e) = self.resume(id).await {
+                tracing::warn!("resume transfer {} failed: {}", id, e);
+            }
+        }
+    }
+
+    /// Cancel an outgoing transfer, receiver discards data received.
+    pub async fn cancel(&self, id: TransferId) -> Result<()> {
+        let (_, transfer) = self.outgoing.remove(&id).ok_or(Error::TransferNotFound)?;
+        self.send_transfer_message(transfer.destination, &TransferMessage::Cancel { id })
+            .await
+    }
+
+    /// Get progress of an outgoing or incoming transfer.
+    pub fn progress(&self, id: TransferId) -> Option<TransferProgress> {
+        if let Some(t) = self.outgoing.get(&id) {
+            return Some(TransferProgress {
+                offset: t.acked,
+                total: t.data.len() as u64,
+            });
+        }
+        self.incoming.get(&id).map(|t| TransferProgress {
+            offset: t.data.len() as u64,
+            total: t.total,
+        })
+    }
+
+    /// Take data of a completed incoming transfer along with its sender.
+    pub fn take_completed(&self, id: TransferId) -> Option<(Did, Bytes)> {
+        self.completed
+            .remove(&id)
+            .map(|(_, t)| (t.sender, t.data))
+    }
+
+    /// Number of incoming transfers from peer in progress.
+    fn incoming_of(&self, peer: Did) -> usize {
+        self.incoming.iter().filter(|t| t.sender == peer).count()
+    }
+
+    /// Drop incoming transfers without segments, and completed transfers not taken,
+    /// for [TransferConfig::ttl_ms].
+    fn sweep(&self, now: u128) {
+        let ttl = self.config.ttl_ms as u128;
+        self.incoming
+            .retain(|_, t| now.saturating_sub(t.updated_at) < ttl);
+        self.completed
+            .retain(|_, t| now.saturating_sub(t.completed_at) < ttl);
+    }
+
+    /// Bytes of incoming transfers by their whole length, and of completed transfers.
+    fn reserved_bytes(&self) -> u64 {
+        let incoming: u64 = self.incoming.iter().map(|t| t.total).sum();
+        let completed: u64 = self.completed.iter().map(|t| t.data.len() as u64).sum();
+        incoming + completed
+    }
+
+    /// Make room for a new incoming transfer of `total` bytes within
+    /// [TransferConfig::max_bytes], by dropping the earliest completed transfers.
+    fn reserve(&self, total: u64) -> Result<()> {
+        let max_bytes = self.config.max_bytes;
+        while self.reserved_bytes().saturating_add(total) > max_bytes {
+            let earliest = self
+                .completed
+                .iter()
+                .min_by_key(|t| t.completed_at)
+                .map(|t| *t.key());
+            let Some(id) = earliest else {
+                return Err(Error::TransferOverBudget(max_bytes));
+            };
+            tracing::warn!("drop completed transfer {} not taken, over budget", id);
+            self.completed.remove(&id);
+        }
+        Ok(())
+    }
+
+    /// Write a segment of incoming transfer. A peer can't start more than
+    /// [TRANSFER_MAX_PER_PEER] transfers at the same time, and a transfer is rejected with
+    /// [Error::TransferOverBudget] if it doesn't fit into [TransferConfig::max_bytes].
+    fn handle_data(
+        &self,
+        peer: Did,
+        id: TransferId,
+        offset: u64,
+        total: u64,
+        data: &[u8],
+    ) -> Result<u64> {
+        if total > TRANSFER_MAX_LEN {
+            return Err(Error::InvalidData);
+        }
+        let now = get_epoch_ms();
+        if !self.incoming.contains_key(&id) {
+            if self.incoming_of(peer) >= TRANSFER_MAX_PER_PEER {
+                return Err(Error::TooManyInFlight(peer.to_string()));
+            }
+            self.sweep(now);
+            self.reserve(total)?;
+        }
+        let mut transfer = self.incoming.entry(id).or_insert_with(|| IncomingTransfer {
+            sender: peer,
+            total,
+            data: vec![],
+            updated_at: now,
+        });
+        if transfer.sender != peer || transfer.total != total {
+            return Err(Error::NoPermission);
+        }
+        let received = transfer.write(offset, data)?;
+        transfer.updated_at = now;
+        if transfer.is_complete() {
+            drop(transfer);
+            if let Some((_, t)) = self.incoming.remove(&id) {
+                tracing::info!("transfer {} from {} completed", id, peer);
+                self.completed.insert(id, CompletedTransfer {
+                    sender: peer,
+                    data: t.data.into(),
+                    completed_at: now,
+                });
+            }
+        }
+        Ok(received)
+    }
+
+    async fn handle_ack(&self, peer: Did, id: TransferId, offset: u64) -> Result<()> {
+        let resume_from = {
+            let Some(mut transfer) = self.outgoing.get_mut(&id) else {
+                return Ok(());
+            };
+            if transfer.destination != peer {
+                return Err(Error::NoPermission);
+            }
+            if offset > transfer.data.len() as u64 {
+                return Err(Error::InvalidData);
+            }
+            transfer.acked = transfer.acked.max(offset);
+            transfer.ack_notify.notify_one();
+            if transfer.acked >= transfer.data.len() as u64 {
+                drop(transfer);
+                self.outgoing.remove(&id);
+                return Ok(());
+            }
+            if !transfer.resuming {
+                return Ok(());
+            }
+            transfer.resuming = false;
+            // The receiver may have lost its state, resume from its offset anyway.
+            transfer.acked = offset;
+            offset
+        };
+        // Acks of peer are handled after this handler returns, so they can't be waited here.
+        self.spawn_resumed(id, resume_from);
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl MessageEndpoint for TransferEndpoint {
+    async fn handle_message(
+        &self,
+        ctx: &MessagePayload,
+        msg: &BackendMessage,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        self.check_enabled()?;
+        let peer = ctx.transaction.signer();
+        match TransferMessage::try_from(msg.data.as_slice())? {
+            TransferMessage::Data {
+                id,
+                offset,
+                total,
+                data,
+            } => {
+                let offset = self.handle_data(peer, id, offset, total, &data)?;
+                self.send_transfer_message(peer, &TransferMessage::Ack { id, offset })
+                    .await?;
+            }
+            TransferMessage::Resume { id } => {
+                let offset = match self.incoming.get(&id) {
+                    Some(t) if t.sender == peer => t.data.len() as u64,
+                    Some(_) => return Err(Error::NoPermission),
+                    None => match self.completed.get(&id) {
+                        Some(t) if t.sender == peer => t.data.len() as u64,
+                        _ => 0,
+                    },
+                };
+                self.send_transfer_message(peer, &TransferMessage::Ack { id, offset })
+                    .await?;
+            }
+            TransferMessage::Ack { id, offset } => self.handle_ack(peer, id, offset).await?,
+            TransferMessage::Cancel { id } => {
+                self.incoming.remove_if(&id, |_, t| t.sender == peer);
+            }
+        }
+        Ok(vec![])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_incoming_transfer_offset() {
+        let peer: Did = SecretKey::random().address().into();
+        let mut transfer = IncomingTransfer {
+            sender: peer,
+            total: 6,
+            data: vec![],
+            updated_at: 0,
+        };
+        assert_eq!(transfer.write(0, b"ab").unwrap(), 2);
+        // Duplicated and out of order segments are ignored.
+        assert_eq!(transfer.write(0, b"ab").unwrap(), 2);
+        assert_eq!(transfer.write(4, b"ef").unwrap(), 2);
+        assert!(!transfer.is_complete());
+        assert_eq!(transfer.write(2, b"cd").unwrap(), 4);
+        assert_eq!(transfer.write(4, b"ef").unwrap(), 6);
+        assert!(transfer.is_complete());
+        assert_eq!(transfer.data, b"abcdef");
+
+        // Segments beyond total are rejected.
+        assert!(transfer.write(6, b"g").is_err());
+        assert!(transfer.write(u64::MAX, b"g").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_incoming_transfers_per_peer() {
+        let (processor, path) = crate::tests::native::prepare_processor(None).await;
+        let endpoint = TransferEndpoint::new(processor.swarm.clone()).with_config(TransferConfig {
+            enabled: true,
+            ..Default::default()
+        });
+        let peer: Did = SecretKey::random().address().into();
+        for _ in 0..TRANSFER_MAX_PER_PEER {
+            let id = TransferId::new_v4();
+            assert_eq!(endpoint.handle_data(peer, id, 0, 4, b"ab").unwrap(), 2);
+        }
+        let id = TransferId::new_v4();
+        assert!(endpoint.handle_data(peer, id, 0, 4, b"ab").is_err());
+
+        let other: Did = SecretKey::random().address().into();
+        assert!(endpoint.handle_data(other, id, 0, 4, b"ab").is_ok());
+        tokio::fs::remove_dir_all(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_transfer_budget_and_expiry() {
+        let (processor, path) = crate::tests::native::prepare_processor(None).await;
+        let endpoint = TransferEndpoint::new(processor.swarm.clone()).with_config(TransferConfig {
+            enabled: true,
+            ttl_ms: 60_000,
+            max_bytes: 8,
+            window: 1,
+        });
+        let peer: Did = SecretKey::random().address().into();
+        let other: Did = SecretKey::random().address().into();
+
+        let completed = TransferId::new_v4();
+        assert_eq!(endpoint.handle_data(peer, completed, 0, 4, b"abcd").unwrap(), 4);
+        let incoming = TransferId::new_v4();
+        assert_eq!(endpoint.handle_data(peer, incoming, 0, 4, b"ab").unwrap(), 2);
+
+        // The earliest completed transfer is dropped for a new one, but incoming ones are kept.
+        let id = TransferId::new_v4();
+        assert_eq!(endpoint.handle_data(other, id, 0, 4, b"ab").unwrap(), 2);
+        assert!(endpoint.take_completed(completed).is_none());
+        assert!(matches!(
+            endpoint.handle_data(other, TransferId::new_v4(), 0, 4, b"ab"),
+            Err(Error::TransferOverBudget(8))
+        ));
+
+        // Incoming transfers without segments for ttl are dropped.
+        endpoint.sweep(get_epoch_ms() + 60_000);
+        assert!(endpoint.progress(incoming).is_none());
+        assert!(endpoint.progress(id).is_none());
+        tokio::fs::remove_dir_all(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_transfer_disabled() {
+        let (processor, path) = crate::tests::native::prepare_processor(None).await;
+        let endpoint = TransferEndpoint::new(processor.swarm.clone());
+        let peer: Did = SecretKey::random().address().into();
+        assert!(matches!(
+            endpoint.send(peer, Bytes::from_static(b"data")).await,
+            Err(Error::InvalidService)
+        ));
+        tokio::fs::remove_dir_all(path).await.unwrap();
+    }
+
+    #[test]
+    fn test_transfer_message_roundtrip() {
+        let msg = TransferMessage::Data {
+            id: TransferId::new_v4(),
+            offset: 3,
+            total: 10,
+            data: Bytes::from_static(b"data"),
+        };
+        let backend_msg = BackendMessage::try_from((MessageType::Transfer, &msg)).unwrap();
+        assert_eq!(
+            TransferMessage::try_from(backend_msg.data.as_slice()).unwrap(),
+            msg
+        );
+        assert!(TransferMessage::try_from([1u8, 2, 3].as_slice()).is_err());
+    }
+}
@@ -0,0 +1,354 @@
+#![warn(missing_docs)]
+//! Tunnels multiplexed over a pool of workers, for gateway nodes proxying many connections.
+//!
+//! By default, each tunnel is driven by a task of its own, which owns a read buffer of
+//! [TUNNEL_READ_BUFFER_SIZE] bytes and a queue of [TUNNEL_QUEUE_SIZE] packages for its whole
+//! life, even when it's idle. With a [TunnelPool], tunnels are driven by a fixed number of
+//! workers instead, and share read buffers:
+//!
+//! - A tunnel is assigned to the least loaded worker when it's opened, see [TunnelPool::spawn].
+//!   A worker polls each of its tunnels which is woken at most once per round, so a busy tunnel
+//!   cannot starve the others of the same worker.
+//! - A tunnel waits until its local stream is readable, then borrows a buffer from the pool
+//!   only for the time of copying what is read, see [TunnelPool::acquire]. Buffers are lent
+//!   in the order tunnels ask for them, and memory of read buffers is bounded by
+//!   [TunnelPoolConfig::buffers] whatever the number of tunnels.
+//! - The queue of packages from peer of a tunnel is shorter, see [TunnelPoolConfig::queue_size].
+use std::future::Future;
+use std::ops::Deref;
+use std::ops::DerefMut;
+use std::pin::Pin;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::sync::OnceLock;
+
+use futures::future::AbortHandle;
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+use serde::Deserialize;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+
+/// Size of the read buffer of a tunnel.
+pub const TUNNEL_READ_BUFFER_SIZE: usize = 30000;
+
+/// Max number of packages from peer queued in a tunnel, waiting to be written to local stream.
+pub const TUNNEL_QUEUE_SIZE: usize = 1024;
+
+/// Config of [TunnelPool].
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct TunnelPoolConfig {
+    /// number of read buffers shared by all tunnels
+    pub buffers: usize,
+    /// max number of packages queued in each tunnel
+    #[serde(default = "default_queue_size")]
+    pub queue_size: usize,
+    /// number of workers driving all tunnels
+    #[serde(default = "default_workers")]
+    pub workers: usize,
+}
+
+fn default_queue_size() -> usize {
+    64
+}
+
+fn default_workers() -> usize {
+    4
+}
+
+type TunnelFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A worker driving tunnels, see [TunnelPool::spawn].
+#[derive(Debug)]
+struct Worker {
+    tx: mpsc::UnboundedSender<TunnelFuture>,
+    /// Number of tunnels driven by the worker.
+    load: Arc<AtomicUsize>,
+}
+
+impl Worker {
+    fn spawn() -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel::<TunnelFuture>();
+        tokio::spawn(async move {
+            let mut tunnels = FuturesUnordered::new();
+            loop {
+                tokio::select! {
+                    Some(tunnel) = rx.recv() => tunnels.push(tunnel),
+                    Some(()) = tunnels.next(), if !tunnels.is_empty() => {}
+                    else => break,
+                }
+            }
+        });
+        Self {
+            tx,
+            load: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+/// Decreases load of worker once the tunnel exits, or is dropped by an exiting worker.
+struct LoadGuard(Arc<AtomicUsize>);
+
+impl Drop for LoadGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Handle of a tunnel driven by a worker of [TunnelPool].
+#[derive(Debug)]
+pub struct PooledTask {
+    done: CancellationToken,
+    abort: AbortHandle,
+}
+
+impl PooledTask {
+    /// Check if the tunnel exited.
+    pub fn is_finished(&self) -> bool {
+        self.done.is_cancelled()
+    }
+
+    /// Wait until the tunnel exits.
+    pub async fn finished(&self) {
+        self.done.cancelled().await
+    }
+
+    /// Stop driving the tunnel, it's dropped by its worker.
+    pub fn abort(&self) {
+        self.abort.abort()
+    }
+}
+
+/// Workers and read buffers shared by tunnels, cloning it shares them.
+#[derive(Debug, Clone)]
+pub struct TunnelPool {
+    permits: Arc<Semaphore>,
+    free: Arc<Mutex<Vec<Vec<u8>>>>,
+    queue_size: usize,
+    worker_count: usize,
+    /// Workers are started on first use, so that a pool can be created out of a runtime.
+    workers: Arc<OnceLock<Vec<Worker>>>,
+}
+
+/// A buffer borrowed from [TunnelPool], returned on drop.
+#[derive(Debug)]
+pub struct PooledBuffer {
+    buf: Vec<u8>,
+    free: Arc<Mutex<Vec<Vec<u8>>>>,
+    _permit: OwnedSemaphorePermit,
+}
+
+/// Lock the free buffers. A panic never leaves them inconsistent, so a poisoned lock is
+/// recovered, instead of failing every tunnel after it.
+fn lock_free(free: &Mutex<Vec<Vec<u8>>>) -> MutexGuard<'_, Vec<Vec<u8>>> {
+    free.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl TunnelPool {
+    /// Create a pool from config, buffers are allocated on first use.
+    pub fn new(config: &TunnelPoolConfig) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(config.buffers.max(1))),
+            free: Arc::new(Mutex::new(vec![])),
+            queue_size: config.queue_size.max(1),
+            worker_count: config.workers.max(1),
+            workers: Arc::new(OnceLock::new()),
+        }
+    }
+
+    /// Max number of packages queued in each tunnel.
+    pub fn queue_size(&self) -> usize {
+        self.queue_size
+    }
+
+    /// Drive a tunnel by the least loaded worker. Workers exit once the pool is dropped
+    /// and their tunnels exit.
+    pub fn spawn(&self, tunnel: impl Future<Output = ()> + Send + 'static) -> PooledTask {
+        let workers = self
+            .workers
+            .get_or_init(|| (0..self.worker_count).map(|_| Worker::spawn()).collect());
+        let worker = workers
+            .iter()
+            .min_by_key(|w| w.load.load(Ordering::Relaxed))
+            .expect("pool has at least one worker");
+        worker.load.fetch_add(1, Ordering::Relaxed);
+        let load = LoadGuard(worker.load.clone());
+
+        let (tunnel, abort) = futures::future::abortable(tunnel);
+        let done = CancellationToken::new();
+        let finished = done.clone().drop_guard();
+        let task = Box::pin(async move {
+            let _ = tunnel.await;
+            drop(load);
+            drop(finished);
+        });
+        // The worker is only gone with the runtime, the tunnel is dropped as finished then.
+        let _ = worker.tx.send(task);
+        PooledTask { done, abort }
+    }
+
+    /// Number of tunnels driven by each worker, empty if no tunnel is spawned yet.
+    pub fn loads(&self) -> Vec<usize> {
+        self.workers
+            .get()
+            .map(|workers| {
+                workers
+                    .iter()
+                    .map(|w| w.load.load(Ordering::Relaxed))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Borrow a buffer, waiting for one to be returned if all are lent.
+    pub async fn acquire(&self) -> PooledBuffer {
+        let permit = self
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore of tunnel pool is never closed");
+        let buf = lock_free(&self.free)
+            .pop()
+            .unwrap_or_else(|| vec![0u8; TUNNEL_READ_BUFFER_SIZE]);
+        PooledBuffer {
+            buf,
+            free: self.free.clone(),
+            _permit: permit,
+        }
+    }
+
+    /// Number of buffers allocated and not lent.
+    pub fn idle(&self) -> usize {
+        lock_free(&self.free).len()
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        let buf = std::mem::take(&mut self.buf);
+        lock_free(&self.free).push(buf);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn pool(buffers: usize, workers: usize) -> TunnelPool {
+        TunnelPool::new(&TunnelPoolConfig {
+            buffers,
+            queue_size: 8,
+            workers,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_tunnel_pool() {
+        let pool = pool(2, 1);
+        assert_eq!(pool.queue_size(), 8);
+
+        let a = pool.acquire().await;
+        let b = pool.acquire().await;
+        assert_eq!(a.len(), TUNNEL_READ_BUFFER_SIZE);
+        assert_eq!(pool.idle(), 0);
+
+        // All buffers are lent, the next tunnel waits.
+        let waiting = tokio::time::timeout(Duration::from_millis(50), pool.acquire()).await;
+        assert!(waiting.is_err());
+
+        // Returned buffers are reused.
+        drop(a);
+        assert_eq!(pool.idle(), 1);
+        let _c = pool.acquire().await;
+        assert_eq!(pool.idle(), 0);
+        drop(b);
+        assert_eq!(pool.idle(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_tunnel_pool_workers() {
+        let pool = pool(2, 4);
+        assert!(pool.loads().is_empty());
+
+        // Tunnels are spread over workers.
+        let stop = CancellationToken::new();
+        let tasks = (0..8)
+            .map(|_| {
+                let stop = stop.clone();
+                pool.spawn(async move { stop.cancelled().await })
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(pool.loads(), vec![2, 2, 2, 2]);
+        assert!(tasks.iter().all(|t| !t.is_finished()));
+
+        // An aborted tunnel is dropped by its worker.
+        tasks[0].abort();
+        tasks[0].finished().await;
+        assert_eq!(pool.loads().iter().sum::<usize>(), 7);
+
+        stop.cancel();
+        for task in tasks.iter() {
+            task.finished().await;
+        }
+        assert_eq!(pool.loads(), vec![0, 0, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_tunnel_pool_fairness() {
+        // Tunnels always ready to make progress, sharing one worker.
+        let pool = pool(2, 1);
+        let stop = CancellationToken::new();
+        let steps = (0..50)
+            .map(|_| Arc::new(AtomicUsize::new(0)))
+            .collect::<Vec<_>>();
+        let tasks = steps
+            .iter()
+            .map(|steps| {
+                let (steps, stop) = (steps.clone(), stop.clone());
+                pool.spawn(async move {
+                    while !stop.is_cancelled() {
+                        steps.fetch_add(1, Ordering::Relaxed);
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        stop.cancel();
+        for task in tasks.iter() {
+            task.finished().await;
+        }
+
+        // Every tunnel takes a step in each round of the worker.
+        let steps = steps
+            .iter()
+            .map(|s| s.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        let (min, max) = (steps.iter().min().unwrap(), steps.iter().max().unwrap());
+        assert!(*min > 0);
+        assert!(max - min <= 2, "{:?}", steps);
+    }
+}
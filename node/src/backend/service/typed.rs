@@ -0,0 +1,219 @@
+#![warn(missing_docs)]
+//! Typed endpoint, which handles binary data labeled with a MIME type, such as `image/png`
+//! or `application/json; charset=utf-8`, so that receivers can branch on the content type
+//! instead of guessing how to interpret the bytes.
+use bincode::Options;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::backend::types::BackendMessage;
+use crate::backend::types::MessageEndpoint;
+use crate::backend::types::MessageType;
+use crate::error::Error;
+use crate::error::Result;
+use crate::prelude::*;
+
+/// Max length of a content type, including parameters.
+pub const CONTENT_TYPE_MAX_LEN: usize = 255;
+
+/// Max length of type, subtype and parameter names, see RFC 6838.
+const RESTRICTED_NAME_MAX_LEN: usize = 127;
+
+fn is_restricted_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    let Some(first) = chars.next() else {
+        return false;
+    };
+    s.len() <= RESTRICTED_NAME_MAX_LEN
+        && first.is_ascii_alphanumeric()
+        && chars.all(|c| c.is_ascii_alphanumeric() || "!#$&-^_.+".contains(c))
+}
+
+fn is_parameter_value(s: &str) -> bool {
+    match s.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        // A quoted value may contain spaces, but no quotes or control characters.
+        Some(v) => v
+            .chars()
+            .all(|c| c == ' ' || (c.is_ascii_graphic() && c != '"')),
+        None => {
+            !s.is_empty()
+                && s.chars()
+                    .all(|c| c.is_ascii_graphic() && !"\"\\".contains(c))
+        }
+    }
+}
+
+/// Check if content type is a valid MIME type, which is `type/subtype`
+/// optionally followed by `; name=value` parameters.
+pub fn validate_content_type(content_type: &str) -> Result<()> {
+    let invalid = || Error::InvalidContentType(content_type.to_string());
+    if content_type.len() > CONTENT_TYPE_MAX_LEN {
+        return Err(invalid());
+    }
+
+    let mut parts = content_type.split(';');
+    let essence = parts.next().unwrap_or_default().trim_matches(' ');
+    let (ty, subtype) = essence.split_once('/').ok_or_else(invalid)?;
+    if !is_restricted_name(ty) || !is_restricted_name(subtype) {
+        return Err(invalid());
+    }
+    for param in parts {
+        let (name, value) = param
+            .trim_matches(' ')
+            .split_once('=')
+            .ok_or_else(invalid)?;
+        if !is_restricted_name(name) || !is_parameter_value(value) {
+            return Err(invalid());
+        }
+    }
+    Ok(())
+}
+
+/// TypedPayload carries bytes along with their MIME type, in a [BackendMessage]
+/// of [MessageType::Typed]. The content type is validated on both sending and receiving.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TypedPayload {
+    content_type: String,
+    data: Vec<u8>,
+}
+
+impl TypedPayload {
+    /// Create a payload, fails with [Error::InvalidContentType] if content type is not
+    /// a valid MIME type, see [validate_content_type].
+    pub fn new(content_type: &str, data: &[u8]) -> Result<Self> {
+        validate_content_type(content_type)?;
+        Ok(Self {
+            content_type: content_type.to_string(),
+            data: data.to_vec(),
+        })
+    }
+
+    /// MIME type of data.
+    pub fn content_type(&self) -> &str {
+        &self.content_type
+    }
+
+    /// Type and subtype of content type in lowercase, without parameters,
+    /// such as `text/plain` of `Text/Plain; charset=utf-8`.
+    pub fn essence(&self) -> String {
+        self.content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase()
+    }
+
+    /// Data of payload.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl TryFrom<&TypedPayload> for BackendMessage {
+    type Error = Error;
+
+    fn try_from(payload: &TypedPayload) -> Result<Self> {
+        BackendMessage::try_from((MessageType::Typed, payload))
+    }
+}
+
+impl TryFrom<&BackendMessage> for TypedPayload {
+    type Error = Error;
+
+    /// Parse a received [BackendMessage], which should be of [MessageType::Typed].
+    /// Reading is limited to the length of data, and the content type is validated.
+    fn try_from(msg: &BackendMessage) -> Result<Self> {
+        if !matches!(MessageType::from(msg.message_type), MessageType::Typed) {
+            return Err(Error::InvalidMessage);
+        }
+        let payload: Self = bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .allow_trailing_bytes()
+            .with_limit(msg.data.len() as u64)
+            .deserialize(&msg.data)
+            .map_err(|_| Error::DecodeError)?;
+        validate_content_type(&payload.content_type)?;
+        Ok(payload)
+    }
+}
+
+/// TypedEndpoint validates received [TypedPayload], which is then delivered to
+/// subscribers of backend messages.
+#[derive(Clone, Debug, Default)]
+pub struct TypedEndpoint;
+
+#[async_trait::async_trait]
+impl MessageEndpoint for TypedEndpoint {
+    async fn handle_message(
+        &self,
+        ctx: &MessagePayload,
+        msg: &BackendMessage,
+    ) -> Result<Vec<MessageHandlerEvent>> {
+        let payload = TypedPayload::try_from(msg)?;
+        tracing::info!(
+            "Typed, From: {}, Content-Type: {}, Length: {}",
+            ctx.relay.origin_sender(),
+            payload.content_type(),
+            payload.data().len()
+        );
+        Ok(vec![])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate_content_type() {
+        for valid in [
+            "image/png",
+            "application/json; charset=utf-8",
+            "application/vnd.api+json",
+            "text/plain;format=\"fixed width\"",
+        ] {
+            assert!(validate_content_type(valid).is_ok(), "{}", valid);
+        }
+        for invalid in [
+            "",
+            "png",
+            "image/",
+            "/png",
+            "image/png/x",
+            "text/plain; charset",
+            "text/plain; charset=",
+            "text /plain",
+            "text/plain\n",
+        ] {
+            assert!(
+                matches!(
+                    validate_content_type(invalid),
+                    Err(Error::InvalidContentType(_))
+                ),
+                "{:?}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_typed_payload() {
+        let payload = TypedPayload::new("Image/PNG; q=1", &[0x89, b'P', b'N', b'G']).unwrap();
+        assert_eq!(payload.essence(), "image/png");
+
+        let msg = BackendMessage::try_from(&payload).unwrap();
+        assert_eq!(TypedPayload::try_from(&msg).unwrap(), payload);
+
+        // Content type is validated on receiving, too.
+        let forged = TypedPayload {
+            content_type: "not a type".to_string(),
+            data: vec![],
+        };
+        let msg = BackendMessage::try_from((MessageType::Typed, &forged)).unwrap();
+        assert!(TypedPayload::try_from(&msg).is_err());
+
+        let text = BackendMessage::from((MessageType::SimpleText.into(), "text".as_bytes()));
+        assert!(TypedPayload::try_from(&text).is_err());
+    }
+}
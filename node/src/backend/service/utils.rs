@@ -4,6 +4,20 @@ use crate::error::Error;
 use crate::error::Result;
 use crate::prelude::*;
 
+/// send report message, the data should be a serialized [BackendMessage](crate::backend::types::BackendMessage)
+/// which is not chunked.
+pub fn send_report_message(ctx: &MessagePayload, data: &[u8]) -> Result<MessageHandlerEvent> {
+    let mut new_bytes: Vec<u8> = Vec::with_capacity(data.len() + 4);
+    new_bytes.push(0);
+    new_bytes.extend_from_slice(&[0u8; 3]);
+    new_bytes.extend_from_slice(data);
+
+    Ok(MessageHandlerEvent::SendReportMessage(
+        ctx.clone(),
+        Message::custom(&new_bytes).map_err(|_| Error::InvalidMessage)?,
+    ))
+}
+
 /// send chunk report message
 pub async fn send_chunk_report_message(
     ctx: &MessagePayload,
@@ -6,8 +6,12 @@ use bytes::Bytes;
 use serde::Deserialize;
 use serde::Serialize;
 
+use crate::backend::http_compression::HTTP_COMPRESSION_LEVEL;
+use crate::consts::BACKEND_MTU;
 use crate::error::Error;
 use crate::error::Result;
+use crate::prelude::rings_core::chunk::ChunkList;
+use crate::prelude::rings_core::dht::Did;
 use crate::prelude::*;
 
 /// Enum MessageType of BackendMessage.
@@ -27,6 +31,24 @@ pub enum MessageType {
     Extension,
     /// tunnel Message
     TunnelMessage,
+    /// multipart message, see [MultipartMessage]
+    Multipart,
+    /// error response, see [ErrorResponse]
+    Error,
+    /// resumable transfer, see [TransferMessage](crate::backend::service::transfer::TransferMessage)
+    Transfer,
+    /// echo, see [EchoMessage](crate::backend::service::echo::EchoMessage)
+    Echo,
+    /// binary data with MIME type, see [TypedPayload](crate::backend::service::typed::TypedPayload)
+    Typed,
+    /// request carrying an idempotency key, see [IdempotentRequest](crate::backend::service::idempotency::IdempotentRequest)
+    Idempotent,
+    /// part of a progressive http response, see [HttpResponsePart]
+    HttpResponsePart,
+    /// chunks received of a message, see [ChunkAck](crate::backend::service::arq::ChunkAck)
+    ChunkAck,
+    /// http response compressed by its content, see [EncodedHttpResponse]
+    EncodedHttpResponse,
 }
 
 impl From<&[u8; 2]> for MessageType {
@@ -44,6 +66,15 @@ impl From<u16> for MessageType {
             4 => MessageType::HttpResponse,
             5 => MessageType::Extension,
             6 => MessageType::TunnelMessage,
+            7 => MessageType::Multipart,
+            8 => MessageType::Error,
+            9 => MessageType::Transfer,
+            10 => MessageType::Echo,
+            11 => MessageType::Typed,
+            12 => MessageType::Idempotent,
+            13 => MessageType::HttpResponsePart,
+            14 => MessageType::ChunkAck,
+            15 => MessageType::EncodedHttpResponse,
             _ => MessageType::Unknown,
         }
     }
@@ -59,10 +90,25 @@ impl From<MessageType> for u16 {
             MessageType::HttpResponse => 4,
             MessageType::Extension => 5,
             MessageType::TunnelMessage => 6,
+            MessageType::Multipart => 7,
+            MessageType::Error => 8,
+            MessageType::Transfer => 9,
+            MessageType::Echo => 10,
+            MessageType::Typed => 11,
+            MessageType::Idempotent => 12,
+            MessageType::HttpResponsePart => 13,
+            MessageType::ChunkAck => 14,
+            MessageType::EncodedHttpResponse => 15,
         }
     }
 }
 
+/// Length of the header of a serialized [BackendMessage], which is `message_type` and `extra`.
+pub const BACKEND_MESSAGE_HEADER_LEN: usize = 32;
+
+/// Max length of the service id carried in the `extra` bytes of a [BackendMessage].
+pub const SERVICE_ID_MAX_LEN: usize = 29;
+
 /// BackendMessage struct for CustomMessage.
 /// A backend message body's length at least is 32bytes;
 /// - `message_type`: `[u8;2]`
@@ -92,6 +138,34 @@ impl BackendMessage {
     }
 }
 
+impl BackendMessage {
+    /// Tag the message with the id of the service it is sent to, so that the receiver
+    /// can route it without parsing the data. The id is stored in `extra` as a length
+    /// byte followed by its utf-8 bytes. An id longer than [SERVICE_ID_MAX_LEN] is
+    /// left untagged, and the receiver will fall back to the service named in the data.
+    /// Note that `extra` of [MessageType::Extension] is used by extensions, don't tag it.
+    pub fn with_service(mut self, service: &str) -> Self {
+        let id = service.as_bytes();
+        if id.is_empty() || id.len() > SERVICE_ID_MAX_LEN {
+            tracing::debug!("service id {:?} is not taggable", service);
+            return self;
+        }
+        self.extra = [0u8; 30];
+        self.extra[0] = id.len() as u8;
+        self.extra[1..=id.len()].copy_from_slice(id);
+        self
+    }
+
+    /// Get the service id tagged by [BackendMessage::with_service].
+    pub fn service(&self) -> Option<&str> {
+        let len = self.extra[0] as usize;
+        if len == 0 || len > SERVICE_ID_MAX_LEN {
+            return None;
+        }
+        std::str::from_utf8(&self.extra[1..=len]).ok()
+    }
+}
+
 impl From<(u16, &[u8])> for BackendMessage {
     fn from((message_type, data): (u16, &[u8])) -> Self {
         Self::new(message_type, [0u8; 30], data)
@@ -112,18 +186,23 @@ where T: Serialize
 impl TryFrom<&[u8]> for BackendMessage {
     type Error = Error;
 
-    #[allow(clippy::ptr_offset_with_cast)]
+    /// Decode untrusted bytes, input shorter than [BACKEND_MESSAGE_HEADER_LEN] is rejected.
     fn try_from(value: &[u8]) -> std::result::Result<Self, Self::Error> {
-        if value.len() < 32 {
+        if value.len() < BACKEND_MESSAGE_HEADER_LEN {
             return Err(Error::InvalidMessage);
         }
-        let (left, right) = arrayref::array_refs![value, 32; ..;];
-        let (message_type, _) = arrayref::array_refs![left, 2; ..;];
+        let (header, data) = value.split_at(BACKEND_MESSAGE_HEADER_LEN);
+        let (message_type, extra) = header.split_at(2);
+
+        let mut message_type_bytes = [0u8; 2];
+        message_type_bytes.copy_from_slice(message_type);
+        let mut extra_bytes = [0u8; 30];
+        extra_bytes.copy_from_slice(extra);
 
         Ok(Self::new(
-            u16::from_le_bytes(*message_type),
-            [0u8; 30],
-            right,
+            u16::from_le_bytes(message_type_bytes),
+            extra_bytes,
+            data,
         ))
     }
 }
@@ -154,6 +233,260 @@ impl From<BackendMessage> for Vec<u8> {
     }
 }
 
+/// Length of the header of a backend custom message, a flag byte followed by 3 reserved bytes.
+pub const CUSTOM_MESSAGE_HEADER_LEN: usize = 4;
+
+/// Flag of a chunk whose receiver reports chunks received by
+/// [ChunkAck](crate::backend::service::arq::ChunkAck), see [arq](crate::backend::service::arq).
+pub const CUSTOM_MESSAGE_FLAG_ACKED_CHUNK: u8 = 2;
+
+/// Same as [CUSTOM_MESSAGE_FLAG_ACKED_CHUNK], and receiver is asked to report right away.
+pub const CUSTOM_MESSAGE_FLAG_ACK_REQUEST: u8 = 3;
+
+/// Marker in the second byte of header, of a message whose data is a frame made by the payload
+/// pipeline of sender, see [transform](rings_core::swarm::transform). The frame is framed by the
+/// flag like a [BackendMessage], and reversed by the pipeline of receiver once joined.
+pub const CUSTOM_MESSAGE_TRANSFORMED: u8 = 1;
+
+/// Check if a backend custom message carries a frame of the payload pipeline,
+/// see [CUSTOM_MESSAGE_TRANSFORMED].
+pub fn is_transformed_custom_message(msg: &[u8]) -> bool {
+    msg.len() >= CUSTOM_MESSAGE_HEADER_LEN && msg[1] == CUSTOM_MESSAGE_TRANSFORMED
+}
+
+/// Split a backend custom message into its flag and body.
+/// The flag is `0` for a plain [BackendMessage], `1` for a chunk of it, and
+/// [CUSTOM_MESSAGE_FLAG_ACKED_CHUNK] or [CUSTOM_MESSAGE_FLAG_ACK_REQUEST] for an acknowledged
+/// chunk of it.
+pub fn split_custom_message(msg: &[u8]) -> Result<(u8, &[u8])> {
+    if msg.len() < CUSTOM_MESSAGE_HEADER_LEN {
+        return Err(Error::InvalidMessage);
+    }
+    let (header, body) = msg.split_at(CUSTOM_MESSAGE_HEADER_LEN);
+    Ok((header[0], body))
+}
+
+/// Encode a [BackendMessage] into the bodies of custom messages, with the header of
+/// [CUSTOM_MESSAGE_HEADER_LEN] prepended.
+/// A message longer than `MTU` is split into chunks, which are joined by receiver.
+pub fn encode_custom_messages<const MTU: usize>(msg: BackendMessage) -> Result<Vec<Vec<u8>>> {
+    encode_custom_messages_with_mtu(msg, MTU)
+}
+
+/// Same as [encode_custom_messages], but split by an MTU known at runtime,
+/// such as the MTU negotiated with receiver.
+pub fn encode_custom_messages_with_mtu(msg: BackendMessage, mtu: usize) -> Result<Vec<Vec<u8>>> {
+    encode_custom_data(msg.into(), mtu, 0)
+}
+
+/// Encode a [BackendMessage] sent to `destination` by the payload pipeline of swarm, see
+/// [Swarm::payload_pipeline]. Each frame made by the pipeline is split by `mtu` the same as
+/// [encode_custom_messages_with_mtu], and marked by [CUSTOM_MESSAGE_TRANSFORMED].
+pub fn encode_custom_messages_for(
+    swarm: &Swarm,
+    destination: Did,
+    msg: BackendMessage,
+    mtu: usize,
+) -> Result<Vec<Vec<u8>>> {
+    if swarm.payload_pipeline().is_empty() {
+        return encode_custom_messages_with_mtu(msg, mtu);
+    }
+    let mut messages = vec![];
+    for frame in swarm.transform_payload(destination, msg.into())? {
+        messages.extend(encode_custom_data(
+            frame.into(),
+            mtu,
+            CUSTOM_MESSAGE_TRANSFORMED,
+        )?);
+    }
+    Ok(messages)
+}
+
+/// Encode data with header, split into chunks if longer than `mtu`.
+/// `marker` is put in the second byte of header, see [CUSTOM_MESSAGE_TRANSFORMED].
+fn encode_custom_data(bytes: Bytes, mtu: usize, marker: u8) -> Result<Vec<Vec<u8>>> {
+    let header = |flag: u8| [flag, marker, 0, 0];
+    if bytes.len() <= mtu {
+        let mut data = Vec::with_capacity(bytes.len() + CUSTOM_MESSAGE_HEADER_LEN);
+        data.extend_from_slice(&header(0));
+        data.extend_from_slice(&bytes);
+        return Ok(vec![data]);
+    }
+    ChunkList::<BACKEND_MTU>::split(&bytes, mtu)
+        .into_iter()
+        .map(|c| {
+            let chunk = c.to_bincode().map_err(|_| Error::EncodeError)?;
+            let mut data = Vec::with_capacity(chunk.len() + CUSTOM_MESSAGE_HEADER_LEN);
+            data.extend_from_slice(&header(1));
+            data.extend_from_slice(&chunk);
+            Ok(data)
+        })
+        .collect()
+}
+
+/// A named part of [MultipartMessage].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MessagePart {
+    /// name of part, such as `header` or `body`
+    pub name: String,
+    /// data of part
+    pub data: Vec<u8>,
+}
+
+/// MultipartMessage carries ordered named parts in a [BackendMessage] of
+/// [MessageType::Multipart], such as a json header along with a binary body.
+/// Names are not required to be unique, [MultipartMessage::part] returns the first match.
+/// Use [encode_custom_messages] to send it, large messages will be chunked.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MultipartMessage {
+    parts: Vec<MessagePart>,
+}
+
+impl MultipartMessage {
+    /// Append a part.
+    pub fn with_part(mut self, name: &str, data: &[u8]) -> Self {
+        self.push_part(name, data);
+        self
+    }
+
+    /// Append a part.
+    pub fn push_part(&mut self, name: &str, data: &[u8]) {
+        self.parts.push(MessagePart {
+            name: name.to_string(),
+            data: data.to_vec(),
+        })
+    }
+
+    /// Get data of the first part named `name`.
+    pub fn part(&self, name: &str) -> Option<&[u8]> {
+        self.parts
+            .iter()
+            .find(|p| p.name == name)
+            .map(|p| p.data.as_slice())
+    }
+
+    /// All parts in order.
+    pub fn parts(&self) -> &[MessagePart] {
+        &self.parts
+    }
+}
+
+impl TryFrom<&MultipartMessage> for BackendMessage {
+    type Error = Error;
+
+    fn try_from(msg: &MultipartMessage) -> std::result::Result<Self, Self::Error> {
+        BackendMessage::try_from((MessageType::Multipart, msg))
+    }
+}
+
+impl TryFrom<&BackendMessage> for MultipartMessage {
+    type Error = Error;
+
+    /// Parse a received [BackendMessage], which should be of [MessageType::Multipart].
+    fn try_from(msg: &BackendMessage) -> std::result::Result<Self, Self::Error> {
+        if !matches!(MessageType::from(msg.message_type), MessageType::Multipart) {
+            return Err(Error::InvalidMessage);
+        }
+        bincode::deserialize(&msg.data).map_err(|_| Error::DecodeError)
+    }
+}
+
+/// Category of [ErrorResponse], so that clients can branch on it programmatically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[repr(u16)]
+pub enum ErrorCode {
+    /// the request is malformed
+    InvalidRequest = 400,
+    /// the requester is not permitted
+    Unauthorized = 401,
+    /// the service or tunnel is not found
+    NotFound = 404,
+    /// the message type is not supported
+    Unsupported = 405,
+    /// the upstream of service failed or timed out
+    UpstreamFailure = 502,
+    /// the service is not running
+    Unavailable = 503,
+    /// other failures of the remote node
+    Internal = 500,
+}
+
+impl ErrorCode {
+    /// Stable description of code, which is sent to peers instead of the error itself,
+    /// so that details of the remote node, such as upstream addresses, never leak.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::InvalidRequest => "invalid request",
+            Self::Unauthorized => "unauthorized",
+            Self::NotFound => "not found",
+            Self::Unsupported => "unsupported",
+            Self::UpstreamFailure => "upstream failure",
+            Self::Unavailable => "unavailable",
+            Self::Internal => "internal error",
+        }
+    }
+}
+
+impl From<&Error> for ErrorCode {
+    fn from(e: &Error) -> Self {
+        match e {
+            Error::InvalidMessage
+            | Error::InvalidData
+            | Error::InvalidHeaders
+            | Error::InvalidAddress
+            | Error::DecodeError
+            | Error::HttpRequestError(_)
+            | Error::InvalidContentType(_) => Self::InvalidRequest,
+            Error::NoPermission | Error::InvalidAuthData | Error::VerifyError(_) => {
+                Self::Unauthorized
+            }
+            Error::InvalidService | Error::TunnelNotFound | Error::TransferNotFound => {
+                Self::NotFound
+            }
+            Error::InvalidMethod => Self::Unsupported,
+            Error::RemoteRpcError(_) | Error::TunnelError(_) => Self::UpstreamFailure,
+            Error::ExtensionStopped
+            | Error::TooManyInFlight(_)
+            | Error::Draining
+            | Error::ServiceBusy(_) => Self::Unavailable,
+            Error::ResponseTimeout => Self::UpstreamFailure,
+            _ => Self::Internal,
+        }
+    }
+}
+
+/// ErrorResponse is sent back to requester as a [BackendMessage] of [MessageType::Error]
+/// when an endpoint failed on handling its message. It is a report message carrying
+/// the `tx_id` of the request, so clients can fail the request instead of waiting timeout.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ErrorResponse {
+    /// category of error
+    pub code: ErrorCode,
+    /// description of error, see [ErrorCode::description]
+    pub message: String,
+}
+
+impl From<&Error> for ErrorResponse {
+    fn from(e: &Error) -> Self {
+        let code = ErrorCode::from(e);
+        Self {
+            code,
+            message: code.description().to_string(),
+        }
+    }
+}
+
+impl TryFrom<&BackendMessage> for ErrorResponse {
+    type Error = Error;
+
+    fn try_from(msg: &BackendMessage) -> std::result::Result<Self, Self::Error> {
+        if !matches!(MessageType::from(msg.message_type), MessageType::Error) {
+            return Err(Error::InvalidMessage);
+        }
+        bincode::deserialize(&msg.data).map_err(|_| Error::DecodeError)
+    }
+}
+
 /// Message Endpoint trait
 #[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
 #[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
@@ -164,12 +497,23 @@ pub trait MessageEndpoint {
         ctx: &MessagePayload,
         data: &BackendMessage,
     ) -> Result<Vec<MessageHandlerEvent>>;
+
+    /// Called once when backend starts, endpoints can setup resources here,
+    /// such as connection pools and background tasks.
+    async fn on_start(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once when backend shuts down, endpoints should release resources here.
+    async fn on_stop(&self) -> Result<()> {
+        Ok(())
+    }
 }
 
 /// HttpResponse
 /// - `status`: Status machine with numbers, like 200, 300, 400, 500.
 /// - `body`: Message chunk split bytes and send back to remote client.
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct HttpResponse {
     /// status
     pub status: u16,
@@ -178,3 +522,370 @@ pub struct HttpResponse {
     /// body: optional
     pub body: Option<Bytes>,
 }
+
+/// Part of a progressive http response, requested by header
+/// [HTTP_PROGRESS_HEADER](crate::backend::service::http_server::HTTP_PROGRESS_HEADER).
+/// A response is sent as `Head`, then `Body` parts, then `End`, so that requester knows
+/// the request is progressing before the whole body arrives, and can extend its timeout.
+/// `Abort` replaces `End` if reading the body from upstream fails.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub enum HttpResponsePart {
+    /// Status and headers, sent as soon as upstream responds.
+    Head {
+        /// status
+        status: u16,
+        /// headers
+        headers: HashMap<String, String>,
+    },
+    /// A piece of body, `seq` counts pieces from 0.
+    Body {
+        /// sequence number of piece
+        seq: u64,
+        /// data of piece
+        data: Bytes,
+    },
+    /// Body is complete after `pieces` pieces.
+    End {
+        /// number of pieces of body
+        pieces: u64,
+    },
+    /// Reading body from upstream failed.
+    Abort {
+        /// why the body is incomplete
+        reason: String,
+    },
+}
+
+impl TryFrom<&BackendMessage> for HttpResponsePart {
+    type Error = Error;
+
+    fn try_from(msg: &BackendMessage) -> Result<Self> {
+        if !matches!(
+            MessageType::from(msg.message_type),
+            MessageType::HttpResponsePart
+        ) {
+            return Err(Error::InvalidMessage);
+        }
+        bincode::deserialize(&msg.data).map_err(|_| Error::DecodeError)
+    }
+}
+
+/// Reassemble [HttpResponsePart]s of a progressive response on requester side.
+/// Parts are expected in the order they are sent, a part out of order fails with
+/// [Error::InvalidMessage], and a body longer than `max_len` with [Error::MessageTooLarge].
+#[derive(Debug)]
+pub struct HttpResponseAssembler {
+    max_len: usize,
+    head: Option<(u16, HashMap<String, String>)>,
+    body: Vec<u8>,
+    pieces: u64,
+}
+
+impl HttpResponseAssembler {
+    /// Create an assembler accepting a body of up to `max_len` bytes.
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            max_len,
+            head: None,
+            body: vec![],
+            pieces: 0,
+        }
+    }
+
+    /// Status of response, known once `Head` is received.
+    pub fn status(&self) -> Option<u16> {
+        self.head.as_ref().map(|(status, _)| *status)
+    }
+
+    /// Take a part, the whole response is returned once `End` is received.
+    /// Fails with [Error::HttpRequestError] on `Abort`.
+    pub fn push(&mut self, part: HttpResponsePart) -> Result<Option<HttpResponse>> {
+        match (part, self.head.is_some()) {
+            (HttpResponsePart::Head { status, headers }, false) => {
+                self.head = Some((status, headers));
+                Ok(None)
+            }
+            (HttpResponsePart::Body { seq, data }, true) if seq == self.pieces => {
+                if self.body.len().saturating_add(data.len()) > self.max_len {
+                    return Err(Error::MessageTooLarge(self.max_len));
+                }
+                self.body.extend_from_slice(&data);
+                self.pieces += 1;
+                Ok(None)
+            }
+            (HttpResponsePart::End { pieces }, true) if pieces == self.pieces => {
+                let (status, headers) = self.head.take().ok_or(Error::InvalidMessage)?;
+                Ok(Some(HttpResponse {
+                    status,
+                    headers,
+                    body: Some(std::mem::take(&mut self.body).into()),
+                }))
+            }
+            (HttpResponsePart::Abort { reason }, _) => Err(Error::HttpRequestError(reason)),
+            _ => Err(Error::InvalidMessage),
+        }
+    }
+}
+
+/// Encoding of the data of an [EncodedHttpResponse].
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+pub enum ResponseEncoding {
+    /// not compressed
+    Identity,
+    /// compressed by gzip
+    Gzip,
+}
+
+/// Http response sent to requesters asking for it by header
+/// [HTTP_COMPRESSION_HEADER](crate::backend::http_compression::HTTP_COMPRESSION_HEADER).
+/// Unlike a [MessageType::HttpResponse], which is always gzipped, it's compressed only if the
+/// body is compressible, see [http_compression](crate::backend::http_compression).
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct EncodedHttpResponse {
+    /// encoding of data
+    pub encoding: ResponseEncoding,
+    /// [HttpResponse] serialized by bincode, then encoded
+    pub data: Bytes,
+}
+
+impl EncodedHttpResponse {
+    /// Serialize response and encode it by `encoding`.
+    pub fn encode(response: &HttpResponse, encoding: ResponseEncoding) -> Result<Self> {
+        let data: Bytes = bincode::serialize(response)
+            .map_err(|_| Error::EncodeError)?
+            .into();
+        let data = match encoding {
+            ResponseEncoding::Identity => data,
+            ResponseEncoding::Gzip => message::encode_data_gzip(&data, HTTP_COMPRESSION_LEVEL)
+                .map_err(|_| Error::EncodeError)?,
+        };
+        Ok(Self { encoding, data })
+    }
+
+    /// Decode and deserialize the response, decompressing at most `max_size` bytes.
+    pub fn decode(&self, max_size: usize) -> Result<HttpResponse> {
+        let data = match self.encoding {
+            ResponseEncoding::Identity => self.data.clone(),
+            ResponseEncoding::Gzip => message::decode_gzip_data_with_limit(&self.data, max_size)
+                .map_err(|_| Error::DecodeError)?,
+        };
+        bincode::deserialize(&data).map_err(|_| Error::DecodeError)
+    }
+}
+
+impl TryFrom<&BackendMessage> for EncodedHttpResponse {
+    type Error = Error;
+
+    fn try_from(msg: &BackendMessage) -> Result<Self> {
+        if !matches!(
+            MessageType::from(msg.message_type),
+            MessageType::EncodedHttpResponse
+        ) {
+            return Err(Error::InvalidMessage);
+        }
+        bincode::deserialize(&msg.data).map_err(|_| Error::DecodeError)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::rings_core::chunk::Chunk;
+    use crate::prelude::rings_core::chunk::ChunkManager;
+
+    #[test]
+    fn test_decode_short_input() {
+        for len in 0..BACKEND_MESSAGE_HEADER_LEN {
+            assert!(BackendMessage::try_from(vec![1u8; len]).is_err());
+        }
+        for len in 0..CUSTOM_MESSAGE_HEADER_LEN {
+            assert!(split_custom_message(&vec![1u8; len]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_http_response_part() {
+        let parts = [
+            HttpResponsePart::Head {
+                status: 200,
+                headers: HashMap::from([("content-type".to_string(), "text/plain".to_string())]),
+            },
+            HttpResponsePart::Body {
+                seq: 0,
+                data: Bytes::from_static(b"hello"),
+            },
+            HttpResponsePart::End { pieces: 1 },
+        ];
+        for part in parts {
+            let msg = BackendMessage::try_from((MessageType::HttpResponsePart, &part)).unwrap();
+            assert_eq!(HttpResponsePart::try_from(&msg).unwrap(), part);
+        }
+
+        let text = BackendMessage::from((MessageType::SimpleText.into(), "text".as_bytes()));
+        assert!(HttpResponsePart::try_from(&text).is_err());
+    }
+
+    #[test]
+    fn test_http_response_assembler() {
+        let head = HttpResponsePart::Head {
+            status: 200,
+            headers: HashMap::new(),
+        };
+        let body = |seq, data: &'static [u8]| HttpResponsePart::Body {
+            seq,
+            data: Bytes::from_static(data),
+        };
+
+        let mut assembler = HttpResponseAssembler::new(16);
+        assert_eq!(assembler.push(head.clone()).unwrap(), None);
+        assert_eq!(assembler.status(), Some(200));
+        assert_eq!(assembler.push(body(0, b"hello ")).unwrap(), None);
+        assert_eq!(assembler.push(body(1, b"world")).unwrap(), None);
+        let resp = assembler
+            .push(HttpResponsePart::End { pieces: 2 })
+            .unwrap()
+            .unwrap();
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.body.unwrap(), Bytes::from_static(b"hello world"));
+
+        // Parts out of order are rejected.
+        let mut assembler = HttpResponseAssembler::new(16);
+        assert!(assembler.push(body(0, b"early")).is_err());
+        assembler.push(head.clone()).unwrap();
+        assert!(assembler.push(body(1, b"gap")).is_err());
+        assert!(assembler.push(HttpResponsePart::End { pieces: 1 }).is_err());
+
+        // Body is bounded.
+        let mut assembler = HttpResponseAssembler::new(4);
+        assembler.push(head).unwrap();
+        assert!(matches!(
+            assembler.push(body(0, b"too long")),
+            Err(Error::MessageTooLarge(4))
+        ));
+
+        let mut assembler = HttpResponseAssembler::new(4);
+        assert!(matches!(
+            assembler.push(HttpResponsePart::Abort {
+                reason: "reset".to_string()
+            }),
+            Err(Error::HttpRequestError(_))
+        ));
+    }
+
+    #[test]
+    fn test_service_tag() {
+        let msg = BackendMessage::from((MessageType::HttpRequest.into(), "body".as_bytes()));
+        assert_eq!(msg.service(), None);
+
+        let msg = msg.with_service("ipfs");
+        assert_eq!(msg.service(), Some("ipfs"));
+        let bytes: Vec<u8> = msg.into();
+        assert_eq!(
+            BackendMessage::try_from(bytes).unwrap().service(),
+            Some("ipfs")
+        );
+
+        let long_name = "a".repeat(SERVICE_ID_MAX_LEN + 1);
+        let msg = BackendMessage::from((MessageType::HttpRequest.into(), "body".as_bytes()));
+        assert_eq!(msg.with_service(&long_name).service(), None);
+    }
+
+    #[test]
+    fn test_multipart_message() {
+        let body = vec![9u8; 100];
+        let multipart = MultipartMessage::default()
+            .with_part("header", br#"{"kind":"blob"}"#)
+            .with_part("body", &body);
+        let msg = BackendMessage::try_from(&multipart).unwrap();
+
+        let frames = encode_custom_messages::<64>(msg.clone()).unwrap();
+        assert!(frames.len() > 1);
+        let mut chunks = ChunkList::<64>::default();
+        let mut joined = None;
+        for frame in frames {
+            let (flag, body) = split_custom_message(&frame).unwrap();
+            assert_eq!(flag, 1);
+            joined = chunks.handle(Chunk::from_bincode(body).unwrap());
+        }
+        let received = BackendMessage::try_from(joined.unwrap().to_vec()).unwrap();
+        assert_eq!(received, msg);
+
+        let parsed = MultipartMessage::try_from(&received).unwrap();
+        assert_eq!(parsed, multipart);
+        assert_eq!(parsed.part("body"), Some(body.as_slice()));
+        assert_eq!(parsed.part("missing"), None);
+
+        let frames = encode_custom_messages::<1024>(msg).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(split_custom_message(&frames[0]).unwrap().0, 0);
+
+        let text = BackendMessage::from((MessageType::SimpleText.into(), "text".as_bytes()));
+        assert!(MultipartMessage::try_from(&text).is_err());
+    }
+
+    #[test]
+    fn test_error_response() {
+        let resp = ErrorResponse::from(&Error::InvalidService);
+        assert_eq!(resp.code, ErrorCode::NotFound);
+        assert_eq!(resp.message, "not found");
+
+        let upstream = Error::RemoteRpcError("connect to 10.0.0.1:8080 failed".to_string());
+        assert_eq!(ErrorResponse::from(&upstream).message, "upstream failure");
+
+        let msg = BackendMessage::try_from((MessageType::Error, &resp)).unwrap();
+        let bytes: Vec<u8> = msg.into();
+        let msg = BackendMessage::try_from(bytes).unwrap();
+        assert_eq!(ErrorResponse::try_from(&msg).unwrap(), resp);
+
+        let text = BackendMessage::from((MessageType::SimpleText.into(), "text".as_bytes()));
+        assert!(ErrorResponse::try_from(&text).is_err());
+    }
+
+    #[test]
+    fn test_backend_message_roundtrip() {
+        let msg = BackendMessage::new(2, [7u8; 30], b"hello");
+        let bytes: Vec<u8> = msg.clone().into();
+        assert_eq!(BackendMessage::try_from(bytes).unwrap(), msg);
+    }
+
+    #[cfg(feature = "node")]
+    #[tokio::test]
+    async fn test_encode_transformed_custom_messages() {
+        use crate::prelude::rings_core::swarm::PayloadTransformConfig;
+        use crate::tests::native::prepare_processor_with_transforms;
+
+        let transforms = vec![
+            PayloadTransformConfig::Gzip { level: 6 },
+            PayloadTransformConfig::Sign,
+        ];
+        let (processor, path) = prepare_processor_with_transforms(None, transforms).await;
+        let swarm = processor.swarm.clone();
+        let msg =
+            BackendMessage::from((MessageType::SimpleText.into(), "text".repeat(64).as_bytes()));
+
+        // Transformed data is still split by mtu, without a chunk stage in pipeline.
+        let frames = encode_custom_messages_for(&swarm, swarm.did(), msg.clone(), 64).unwrap();
+        assert!(frames.len() > 1);
+        let mut chunks = ChunkList::<BACKEND_MTU>::default();
+        let mut joined = None;
+        for frame in frames {
+            assert!(is_transformed_custom_message(&frame));
+            let (flag, body) = split_custom_message(&frame).unwrap();
+            assert_eq!(flag, 1);
+            let chunk = Chunk::from_bincode(body).unwrap();
+            assert!(chunk.data.len() <= 64);
+            joined = chunks.handle(chunk);
+        }
+        let data = swarm
+            .restore_payload(swarm.did(), joined.unwrap().to_vec())
+            .unwrap()
+            .unwrap();
+        assert_eq!(BackendMessage::try_from(data).unwrap(), msg);
+
+        // Plain messages are never marked.
+        let frames = encode_custom_messages_with_mtu(msg, 64).unwrap();
+        assert!(frames.iter().all(|f| !is_transformed_custom_message(f)));
+
+        tokio::fs::remove_dir_all(path).await.unwrap();
+    }
+}
@@ -1,23 +1,25 @@
 #![allow(non_snake_case, non_upper_case_globals, clippy::ptr_offset_with_cast)]
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::sync::Mutex;
 
 use anyhow::anyhow;
-use arrayref::array_refs;
 use bytes::Bytes;
 use js_sys;
 use js_sys::Uint8Array;
 use rings_core::async_trait;
+use rings_core::consts::MAX_DECOMPRESSED_SIZE;
 use rings_core::dht::Did;
 use rings_core::ecc::PublicKey;
 use rings_core::message::CustomMessage;
 use rings_core::message::MessageCallback;
 use rings_core::message::MessageHandlerEvent;
 use rings_core::message::MessagePayload;
+use rings_core::message::MessageVerificationExt;
+use rings_core::prelude::uuid::Uuid;
 use rings_core::prelude::vnode;
-use rings_core::prelude::vnode::VirtualNode;
 use rings_core::session::SessionSkBuilder;
 use rings_core::storage::PersistenceStorage;
 use rings_core::swarm::impls::ConnectionHandshake;
@@ -33,18 +35,26 @@ use wasm_bindgen_futures;
 use wasm_bindgen_futures::future_to_promise;
 use wasm_bindgen_futures::JsFuture;
 
+use crate::backend::http_compression::HTTP_COMPRESSION_HEADER;
+use crate::backend::types::is_transformed_custom_message;
+use crate::backend::types::split_custom_message;
 use crate::backend::types::BackendMessage;
+use crate::backend::types::EncodedHttpResponse;
+use crate::backend::types::ErrorResponse;
 use crate::backend::types::HttpResponse;
+use crate::backend::types::HttpResponseAssembler;
+use crate::backend::types::HttpResponsePart;
 use crate::backend::types::MessageType;
 use crate::consts::BACKEND_MTU;
+use crate::consts::MAX_IN_FLIGHT_PER_PEER;
 use crate::error;
 use crate::jsonrpc::build_handler;
 use crate::jsonrpc::handler::browser::MethodHandler;
 use crate::jsonrpc::HandlerType;
 use crate::measure::PeriodicMeasure;
 use crate::prelude::chunk::Chunk;
-use crate::prelude::chunk::ChunkList;
 use crate::prelude::chunk::ChunkManager;
+use crate::prelude::chunk::ChunkPool;
 use crate::prelude::http;
 use crate::prelude::jsonrpc_core::types::id::Id;
 use crate::prelude::jsonrpc_core::MethodCall;
@@ -532,7 +542,7 @@ impl Client {
         future_to_promise(async move {
             let method = http::Method::from_str(method.as_str()).map_err(JsError::from)?;
 
-            let headers: Vec<(String, String)> = if headers.is_null() {
+            let mut headers: Vec<(String, String)> = if headers.is_null() {
                 Vec::new()
             } else if headers.is_object() {
                 let mut header_vec: Vec<(String, String)> = Vec::new();
@@ -556,6 +566,13 @@ impl Client {
             } else {
                 Vec::new()
             };
+            // This client decodes responses compressed by content, see `http_compression`.
+            if !headers
+                .iter()
+                .any(|(k, _)| k.eq_ignore_ascii_case(HTTP_COMPRESSION_HEADER))
+            {
+                headers.push((HTTP_COMPRESSION_HEADER.to_string(), "gzip".to_string()));
+            }
 
             let b = body.map(|item| item.to_vec());
 
@@ -620,7 +637,7 @@ impl Client {
         let p = self.processor.clone();
 
         future_to_promise(async move {
-            let rid = VirtualNode::gen_did(&name).map_err(JsError::from)?;
+            let rid = p.gen_vid(&name).map_err(JsError::from)?;
 
             tracing::debug!("browser lookup_service storage_fetch: {}", rid);
             p.storage_fetch(rid).await.map_err(JsError::from)?;
@@ -649,7 +666,8 @@ pub struct MessageCallbackInstance {
     custom_message: Arc<js_sys::Function>,
     http_response_message: Arc<js_sys::Function>,
     builtin_message: Arc<js_sys::Function>,
-    chunk_list: Arc<Mutex<ChunkList<BACKEND_MTU>>>,
+    chunk_pool: Arc<Mutex<ChunkPool<BACKEND_MTU>>>,
+    http_parts: Arc<Mutex<HashMap<(Did, Uuid), HttpResponseAssembler>>>,
 }
 
 #[wasm_export]
@@ -664,7 +682,8 @@ impl MessageCallbackInstance {
             custom_message: Arc::new(custom_message.clone()),
             http_response_message: Arc::new(http_response_message.clone()),
             builtin_message: Arc::new(builtin_message.clone()),
-            chunk_list: Default::default(),
+            chunk_pool: Default::default(),
+            http_parts: Default::default(),
         })
     }
 }
@@ -684,6 +703,27 @@ impl MessageCallbackInstance {
             MessageType::HttpResponse => {
                 self.handle_http_response(relay, m.data.as_slice()).await?;
             }
+            MessageType::EncodedHttpResponse => {
+                let http_response = EncodedHttpResponse::try_from(&m)
+                    .and_then(|r| r.decode(MAX_DECOMPRESSED_SIZE))
+                    .map_err(|e| anyhow::anyhow!("{}", e))?;
+                self.emit_http_response(relay, &http_response).await?;
+            }
+            MessageType::HttpResponsePart => {
+                let part = HttpResponsePart::try_from(&m).map_err(|e| anyhow::anyhow!("{}", e))?;
+                if let Some(http_response) = self.handle_http_response_part(relay, part)? {
+                    self.emit_http_response(relay, &http_response).await?;
+                }
+            }
+            MessageType::Error => {
+                let e = ErrorResponse::try_from(&m).map_err(|e| anyhow::anyhow!("{}", e))?;
+                log::warn!(
+                    "remote failed on handling message {}: {:?}, {}",
+                    relay.transaction.tx_id,
+                    e.code,
+                    e.message
+                );
+            }
             _ => {
                 return Ok(());
             }
@@ -725,15 +765,51 @@ impl MessageCallbackInstance {
             relay.transaction.tx_id,
             msg_content.len(),
         );
-        let this = JsValue::null();
-        let msg_content = message::decode_gzip_data(&Bytes::from(data.to_vec())).unwrap();
+        let msg_content = message::decode_gzip_data_with_limit(
+            &Bytes::from(data.to_vec()),
+            MAX_DECOMPRESSED_SIZE,
+        )?;
         log::info!(
             "message of {:?} received, after gunzip: {:?}",
             relay.transaction.tx_id,
             msg_content.len(),
         );
         let http_response: HttpResponse = bincode::deserialize(&msg_content)?;
-        let msg_content = js_value::serialize(&http_response)
+        self.emit_http_response(relay, &http_response).await
+    }
+
+    /// Reassemble a progressive response, which is emitted once its last part is received.
+    /// Parts are matched by the signer and the tx_id of the request they reply to.
+    fn handle_http_response_part(
+        &self,
+        relay: &MessagePayload,
+        part: HttpResponsePart,
+    ) -> anyhow::Result<Option<HttpResponse>> {
+        let key = (relay.transaction.signer(), relay.transaction.tx_id);
+        let mut parts = self
+            .http_parts
+            .lock()
+            .map_err(|_| anyhow!("lock http parts failed"))?;
+        if !parts.contains_key(&key) && parts.len() >= MAX_IN_FLIGHT_PER_PEER {
+            return Err(anyhow!("too many progressive responses in flight"));
+        }
+        let result = parts
+            .entry(key)
+            .or_insert_with(|| HttpResponseAssembler::new(MAX_DECOMPRESSED_SIZE))
+            .push(part);
+        if !matches!(result, Ok(None)) {
+            parts.remove(&key);
+        }
+        result.map_err(|e| anyhow!("{}", e))
+    }
+
+    async fn emit_http_response(
+        &self,
+        relay: &MessagePayload,
+        http_response: &HttpResponse,
+    ) -> anyhow::Result<()> {
+        let this = JsValue::null();
+        let msg_content = js_value::serialize(http_response)
             .map_err(|_| anyhow!("Failed on serialize message"))?;
         if let Ok(r) = self.http_response_message.call2(
             &this,
@@ -749,29 +825,45 @@ impl MessageCallbackInstance {
         Ok(())
     }
 
-    fn handle_chunk_data(&self, data: &[u8]) -> anyhow::Result<Option<Bytes>> {
-        let c_lock = self.chunk_list.try_lock();
+    fn handle_chunk_data(&self, sender: Did, data: &[u8]) -> anyhow::Result<Option<Bytes>> {
+        let c_lock = self.chunk_pool.try_lock();
         if c_lock.is_err() {
             return Err(anyhow!("lock chunklist failed"));
         }
-        let mut chunk_list = c_lock.unwrap();
+        let mut chunk_pool = c_lock.unwrap();
 
         let chunk_item =
             Chunk::from_bincode(data).map_err(|_| anyhow!("BincodeDeserialize failed"))?;
 
         log::debug!(
-            "before handle chunk, chunk list len: {}",
-            chunk_list.as_vec().len()
+            "before handle chunk, buffered from {}: {} bytes",
+            sender,
+            chunk_pool.peer_memory(sender)
         );
         log::debug!(
             "chunk id: {}, total size: {}",
             chunk_item.meta.id,
             chunk_item.chunk[1]
         );
-        let data = chunk_list.handle(chunk_item);
+        if chunk_item.declared_len() > MAX_DECOMPRESSED_SIZE {
+            chunk_pool.remove(chunk_item.meta.id);
+            return Err(anyhow!(
+                "chunked message exceeds {MAX_DECOMPRESSED_SIZE} bytes"
+            ));
+        }
+        let (data, evicted) = chunk_pool.handle_from(sender, chunk_item);
+        for e in evicted {
+            log::warn!(
+                "drop chunked message {} of {} bytes from {}, over budget",
+                e.id,
+                e.bytes,
+                e.did
+            );
+        }
         log::debug!(
-            "after handle chunk, chunk list len: {}",
-            chunk_list.as_vec().len()
+            "after handle chunk, buffered from {}: {} bytes",
+            sender,
+            chunk_pool.peer_memory(sender)
         );
 
         Ok(data)
@@ -785,15 +877,21 @@ impl MessageCallback for MessageCallbackInstance {
         relay: &MessagePayload,
         msg: &CustomMessage,
     ) -> Vec<MessageHandlerEvent> {
-        if msg.0.len() < 2 {
+        // Browser has no payload pipeline to reverse frames of it.
+        if is_transformed_custom_message(&msg.0) {
+            log::error!("transformed custom message is not supported");
             return vec![];
         }
-
-        let (left, right) = array_refs![&msg.0, 4; ..;];
-        let (&[tag], _) = array_refs![left, 1, 3];
+        let (tag, right) = match split_custom_message(&msg.0) {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("decode custom message header failed: {}", e);
+                return vec![];
+            }
+        };
 
         let data = if tag == 1 {
-            let data = self.handle_chunk_data(right);
+            let data = self.handle_chunk_data(relay.transaction.signer(), right);
             if let Err(e) = data {
                 log::error!("handle chunk data failed: {}", e);
                 return vec![];
@@ -11,3 +11,18 @@ pub const MSG_SEND_FAILED_LIMIT: i16 = 10;
 pub const MSG_RECV_FAILED_LIMIT: i16 = 10;
 /// Timeout for proxied TCP connections
 pub const TCP_SERVER_TIMEOUT: u64 = 30;
+/// Timeout in seconds of waiting the acknowledgement of closing a tunnel
+pub const TUNNEL_CLOSE_TIMEOUT: u64 = 10;
+/// Length of a segment of resumable transfer
+pub const TRANSFER_SEGMENT_SIZE: usize = 32 * 1024;
+/// Max length of data sent by a resumable transfer
+pub const TRANSFER_MAX_LEN: u64 = 256 * 1024 * 1024;
+/// Max number of incoming transfers from a peer in progress at the same time
+pub const TRANSFER_MAX_PER_PEER: usize = 4;
+/// Timeout in seconds of waiting an ack of resumed transfer, after which it's resumed again
+/// once the peer is connected again
+pub const TRANSFER_ACK_TIMEOUT: u64 = 30;
+/// Timeout in seconds of each check of self-test
+pub const SELF_TEST_TIMEOUT: u64 = 10;
+/// Default max number of in-flight requests per peer, in each direction
+pub const MAX_IN_FLIGHT_PER_PEER: usize = 64;
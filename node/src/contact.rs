@@ -0,0 +1,141 @@
+//! This module provide the `ContactBook`, which maps human-friendly names to Dids.
+//! Contacts are local-only and persisted in storage, they are never published to the network.
+#![warn(missing_docs)]
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::error::Error;
+use crate::error::Result;
+use crate::prelude::rings_core::dht::Did;
+use crate::prelude::rings_core::prelude::dashmap::DashMap;
+use crate::prelude::rings_core::storage::PersistenceStorageRemove;
+use crate::prelude::PersistenceStorage;
+use crate::prelude::PersistenceStorageReadAndWrite;
+
+/// Max length of a contact name.
+pub const CONTACT_NAME_MAX_LEN: usize = 64;
+
+/// `ContactBook` resolves contact names to Dids.
+/// A name is unique in the book, and can never be mistaken for a hex Did string.
+#[derive(Debug)]
+pub struct ContactBook {
+    storage: Arc<PersistenceStorage>,
+    cache: DashMap<String, Did>,
+}
+
+impl ContactBook {
+    /// Create a new `ContactBook` with the given storage.
+    pub fn new(storage: PersistenceStorage) -> Self {
+        Self {
+            storage: Arc::new(storage),
+            cache: DashMap::new(),
+        }
+    }
+
+    fn gen_storage_key(name: &str) -> String {
+        format!("ContactBook/contacts/{}", name)
+    }
+
+    /// Check if name is valid for a contact. A name should be non-empty, without whitespaces,
+    /// no longer than [CONTACT_NAME_MAX_LEN], and should not look like a hex Did.
+    pub fn validate_name(name: &str) -> Result<()> {
+        let invalid = |reason: &str| Err(Error::InvalidContactName(format!("{name:?} {reason}")));
+        if name.is_empty() || name.len() > CONTACT_NAME_MAX_LEN {
+            return invalid("has invalid length");
+        }
+        if name.chars().any(char::is_whitespace) {
+            return invalid("contains whitespace");
+        }
+        if name.to_lowercase().starts_with("0x") || Did::from_str(name).is_ok() {
+            return invalid("is ambiguous with did");
+        }
+        Ok(())
+    }
+
+    /// Get did of contact.
+    pub async fn resolve(&self, name: &str) -> Result<Option<Did>> {
+        if let Some(did) = self.cache.get(name) {
+            return Ok(Some(*did));
+        }
+        let did: Option<Did> = self
+            .storage
+            .get(&Self::gen_storage_key(name))
+            .await
+            .map_err(Error::Storage)?;
+        if let Some(did) = did {
+            self.cache.insert(name.to_string(), did);
+        }
+        Ok(did)
+    }
+
+    /// Add a contact. Adding an existing name with another did fails,
+    /// remove the contact first to rename it.
+    pub async fn add(&self, name: &str, did: Did) -> Result<()> {
+        Self::validate_name(name)?;
+        match self.resolve(name).await? {
+            Some(existed) if existed == did => return Ok(()),
+            Some(_) => return Err(Error::ContactExists(name.to_string())),
+            None => {}
+        }
+        self.storage
+            .put(&Self::gen_storage_key(name), &did)
+            .await
+            .map_err(Error::Storage)?;
+        self.cache.insert(name.to_string(), did);
+        Ok(())
+    }
+
+    /// Remove a contact.
+    pub async fn remove(&self, name: &str) -> Result<()> {
+        self.cache.remove(name);
+        self.storage
+            .remove(&Self::gen_storage_key(name))
+            .await
+            .map_err(Error::Storage)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "node")]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_contact_book() {
+        let path = PersistenceStorage::random_path("./tmp");
+        let storage = PersistenceStorage::new_with_path(path.as_str())
+            .await
+            .unwrap();
+
+        let alice = Did::from_str("0x11E807fcc88dD319270493fB2e822e388Fe36ab0").unwrap();
+        let bob = Did::from_str("0x999999cf1046e68e36E1aA2E0E07105eDDD1f08E").unwrap();
+
+        let book = ContactBook::new(storage);
+        assert_eq!(book.resolve("alice").await.unwrap(), None);
+
+        book.add("alice", alice).await.unwrap();
+        book.add("alice", alice).await.unwrap();
+        assert!(matches!(
+            book.add("alice", bob).await,
+            Err(Error::ContactExists(_))
+        ));
+        assert_eq!(book.resolve("alice").await.unwrap(), Some(alice));
+
+        book.remove("alice").await.unwrap();
+        assert_eq!(book.resolve("alice").await.unwrap(), None);
+        book.add("alice", bob).await.unwrap();
+        assert_eq!(book.resolve("alice").await.unwrap(), Some(bob));
+
+        for name in [
+            "",
+            "a b",
+            "0xalice",
+            "11E807fcc88dD319270493fB2e822e388Fe36ab0",
+        ] {
+            assert!(matches!(
+                book.add(name, alice).await,
+                Err(Error::InvalidContactName(_))
+            ));
+        }
+    }
+}
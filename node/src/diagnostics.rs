@@ -0,0 +1,124 @@
+//! This module provides [DiagnosticsBundle], a snapshot of the state of a node which users
+//! attach to bug reports of connectivity, see [Processor::diagnostics_bundle].
+//!
+//! A bundle never contains keys. Data stored on DHT is only counted unless
+//! [DiagnosticsDetail::Full] is asked explicitly, since it may carry private payloads.
+#![warn(missing_docs)]
+use rings_transport::timing::ConnectionTiming;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::prelude::rings_core::inspect::DHTInspect;
+use crate::prelude::rings_core::inspect::StorageInspect;
+use crate::prelude::rings_core::swarm::DropReason;
+use crate::prelude::rings_core::traffic::TrafficCounters;
+#[cfg(doc)]
+use crate::processor::Processor;
+
+/// Detail of a [DiagnosticsBundle].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiagnosticsDetail {
+    /// Omit private data, which is the default.
+    #[default]
+    Redacted,
+    /// Include data stored on DHT, which may carry private payloads.
+    Full,
+}
+
+/// Snapshot of the state of a node, serializable to JSON.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiagnosticsBundle {
+    /// Time of collecting, in milliseconds since epoch.
+    pub created_at_ms: u128,
+    /// Version of program.
+    pub version: String,
+    /// Summary of node config.
+    pub config: ConfigSummary,
+    /// Routing table of DHT.
+    pub dht: DHTInspect,
+    /// Connected peers.
+    pub peers: Vec<PeerDiagnostics>,
+    /// Counts of dropped messages by reason since the last summary of drop log.
+    pub drops: Vec<(DropReason, u64)>,
+    /// Id of tunnels open to hidden tcp services, see [DiagnosticsBundle::with_tunnels].
+    pub tunnels: Vec<String>,
+    /// Data stored on DHT.
+    pub storage: StorageDiagnostics,
+}
+
+/// Summary of node config, in a [DiagnosticsBundle].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigSummary {
+    /// Did of node.
+    pub did: String,
+    /// Application id scoping topics of virtual nodes.
+    pub app_id: Option<String>,
+    /// Whether node participates in DHT.
+    pub dht_enabled: bool,
+    /// Whether initial convergence of DHT is completed.
+    pub converged: bool,
+    /// Interval of stabilization, in seconds.
+    pub stabilize_timeout: usize,
+}
+
+/// State and stats of a connected peer, in a [DiagnosticsBundle].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerDiagnostics {
+    /// Did of peer.
+    pub did: String,
+    /// State of webrtc connection.
+    pub state: String,
+    /// Behaviour score of peer.
+    pub score: f64,
+    /// Negotiated MTU of peer.
+    pub mtu: usize,
+    /// Bytes sent to and received from peer.
+    pub traffic: TrafficCounters,
+    /// Whether peer is pinned as a permanent connection.
+    pub pinned: bool,
+    /// Whether the transport of peer is trusted.
+    pub trusted: bool,
+    /// Time taken by each phase of handshake with peer.
+    #[serde(default)]
+    pub timing: ConnectionTiming,
+}
+
+/// Data stored on DHT, in a [DiagnosticsBundle].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageDiagnostics {
+    /// Number of virtual nodes in persistence storage.
+    pub persistence_items: usize,
+    /// Number of virtual nodes in cache.
+    pub cache_items: usize,
+    /// Virtual nodes in persistence storage, only with [DiagnosticsDetail::Full].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub persistence_storage: Option<StorageInspect>,
+    /// Virtual nodes in cache, only with [DiagnosticsDetail::Full].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_storage: Option<StorageInspect>,
+}
+
+impl StorageDiagnostics {
+    pub(crate) fn new(
+        persistence: StorageInspect,
+        cache: StorageInspect,
+        detail: DiagnosticsDetail,
+    ) -> Self {
+        let full = detail == DiagnosticsDetail::Full;
+        Self {
+            persistence_items: persistence.items.len(),
+            cache_items: cache.items.len(),
+            persistence_storage: full.then_some(persistence),
+            cache_storage: full.then_some(cache),
+        }
+    }
+}
+
+impl DiagnosticsBundle {
+    /// Attach tunnels, which are owned by backend rather than [Processor].
+    pub fn with_tunnels<T: ToString>(mut self, tunnels: impl IntoIterator<Item = T>) -> Self {
+        self.tunnels = tunnels.into_iter().map(|t| t.to_string()).collect();
+        self
+    }
+}
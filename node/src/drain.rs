@@ -0,0 +1,111 @@
+//! This module provide [Drain], which lets a node stop accepting new work while
+//! finishing the work in flight, such as before a restart behind a load balancer.
+#![warn(missing_docs)]
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::error::Result;
+use crate::prelude::rings_core::utils::get_epoch_ms;
+use crate::prelude::rings_core::utils::sleep_ms;
+
+/// Interval in milliseconds of checking whether the work in flight is done.
+const DRAIN_POLL_INTERVAL_MS: u64 = 50;
+
+#[derive(Debug, Default)]
+struct DrainState {
+    draining: AtomicBool,
+    in_flight: AtomicUsize,
+}
+
+/// Drain is a shared handle of draining state, cloning it shares the state.
+/// New work is admitted by [Drain::admit], which fails once draining is started,
+/// and work is counted as in flight until its [DrainGuard] is dropped.
+#[derive(Debug, Clone, Default)]
+pub struct Drain(Arc<DrainState>);
+
+/// Guard of a piece of work in flight, released on drop.
+#[derive(Debug)]
+pub struct DrainGuard(Arc<DrainState>);
+
+impl Drop for DrainGuard {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Drain {
+    /// Check if draining is started.
+    pub fn is_draining(&self) -> bool {
+        self.0.draining.load(Ordering::SeqCst)
+    }
+
+    /// Start draining, new work is rejected from now on.
+    pub fn start(&self) {
+        self.0.draining.store(true, Ordering::SeqCst);
+    }
+
+    /// Number of pieces of work in flight.
+    pub fn in_flight(&self) -> usize {
+        self.0.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Count a piece of work which is part of the work in flight, such as a package of
+    /// an existing tunnel, so that it's never rejected.
+    pub fn track(&self) -> DrainGuard {
+        self.0.in_flight.fetch_add(1, Ordering::SeqCst);
+        DrainGuard(self.0.clone())
+    }
+
+    /// Admit a new piece of work, fails with [Error::Draining] if draining is started.
+    pub fn admit(&self) -> Result<DrainGuard> {
+        let guard = self.track();
+        // Checked after counting, so that work admitted concurrently with `start`
+        // is always seen by `wait_idle`.
+        if self.is_draining() {
+            return Err(Error::Draining);
+        }
+        Ok(guard)
+    }
+
+    /// Wait until no work is in flight, returns false if it's not done within `deadline`.
+    pub async fn wait_idle(&self, deadline: Duration) -> bool {
+        let until = get_epoch_ms() + deadline.as_millis();
+        loop {
+            if self.in_flight() == 0 {
+                return true;
+            }
+            if get_epoch_ms() >= until {
+                return false;
+            }
+            sleep_ms(DRAIN_POLL_INTERVAL_MS).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_drain() {
+        let drain = Drain::default();
+        let guard = drain.admit().unwrap();
+        assert_eq!(drain.in_flight(), 1);
+
+        drain.clone().start();
+        assert!(drain.is_draining());
+        assert!(matches!(drain.admit(), Err(Error::Draining)));
+        // Work of existing tunnels or requests is still accepted.
+        let tracked = drain.track();
+        assert_eq!(drain.in_flight(), 2);
+
+        drop(tracked);
+        assert!(!drain.wait_idle(Duration::from_millis(100)).await);
+        drop(guard);
+        assert!(drain.wait_idle(Duration::from_millis(100)).await);
+    }
+}
@@ -53,6 +53,14 @@ pub enum Error {
     WasmGlobalMemoryLockError = 405,
     #[error("WASM failed to load file.")]
     WasmFailedToLoadFile = 406,
+    #[error("Extension is not running.")]
+    ExtensionStopped = 407,
+    #[error("Extension {0} is running too many executions.")]
+    ExtensionBusy(String) = 408,
+    #[error("Extension {0} timed out after {1} ms.")]
+    ExtensionTimeout(String, u64) = 409,
+    #[error("Extension ran out of fuel of {0} operators.")]
+    ExtensionOutOfFuel(u64) = 410,
     #[error("Invalid did.")]
     InvalidDid = 500,
     #[error("Invalid method.")]
@@ -89,6 +97,24 @@ pub enum Error {
     Storage(rings_core::error::Error) = 807,
     #[error("Swarm Error: {0}")]
     Swarm(rings_core::error::Error) = 808,
+    #[error("Invalid contact name: {0}")]
+    InvalidContactName(String) = 809,
+    #[error("Contact {0} already exists with another did")]
+    ContactExists(String) = 810,
+    #[error("Contact {0} not found")]
+    ContactNotFound(String) = 811,
+    #[error("Invalid content type: {0}")]
+    InvalidContentType(String) = 812,
+    #[error("Peer book is not configured")]
+    PeerBookNotConfigured = 813,
+    #[error("Contact book is not configured")]
+    ContactBookNotConfigured = 814,
+    #[error("Secret key of account is not configured")]
+    SecretKeyNotConfigured = 815,
+    #[error("Service tag {0} mismatches service {1} requested by message")]
+    ServiceTagMismatch(String, String) = 816,
+    #[error("Backend is not set")]
+    BackendNotSet = 817,
     #[error("Create File Error: {0}")]
     CreateFileError(String) = 900,
     #[error("Open File Error: {0}")]
@@ -105,6 +131,28 @@ pub enum Error {
     TunnelNotFound = 1003,
     #[error("Tunnel error: {0:?}")]
     TunnelError(TunnelDefeat) = 1004,
+    #[error("transfer not found")]
+    TransferNotFound = 1005,
+    #[error("wait response timeout")]
+    ResponseTimeout = 1006,
+    #[error("response mismatched with request")]
+    ResponseMismatch = 1007,
+    #[error("too many in-flight requests with peer {0}")]
+    TooManyInFlight(String) = 1008,
+    #[error("node is draining, new work is not accepted")]
+    Draining = 1009,
+    #[error("service {0} is busy, max concurrency reached")]
+    ServiceBusy(String) = 1010,
+    #[error("invalid idempotency key: {0}")]
+    InvalidIdempotencyKey(String) = 1011,
+    #[error("message exceeds the limit of {0} bytes")]
+    MessageTooLarge(usize) = 1012,
+    #[error("chunks of message {0} are not acknowledged after retransmits")]
+    ChunksUnacknowledged(String) = 1013,
+    #[error("idempotency key {0} is reused for a different request")]
+    IdempotencyKeyReused(String) = 1014,
+    #[error("transfers exceed the budget of {0} bytes")]
+    TransferOverBudget(u64) = 1015,
     #[error("core error: {0}")]
     CoreError(#[from] rings_core::error::Error) = 1102,
     #[error("external singer error: {0}")]
@@ -122,6 +170,8 @@ pub enum TunnelDefeat {
     ConnectionReset = 5,
     NotConnected = 6,
     ConnectionClosed = 7,
+    PackageLost = 8,
+    Congested = 9,
     Unknown = u8::MAX,
 }
 
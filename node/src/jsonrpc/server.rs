@@ -2,7 +2,7 @@
 //! A jsonrpc-server of rings-node
 /// [JSON-RPC]: https://www.jsonrpc.org/specification
 use std::collections::HashSet;
-use std::str::FromStr;
+use std::net::IpAddr;
 use std::sync::Arc;
 
 #[cfg(feature = "browser")]
@@ -17,6 +17,8 @@ use tokio::sync::broadcast::Receiver;
 #[cfg(feature = "node")]
 use tokio::sync::Mutex;
 
+#[cfg(feature = "node")]
+use crate::backend::http_compression;
 use crate::backend::types::BackendMessage;
 use crate::backend::MessageType;
 use crate::error::Error as ServerError;
@@ -29,7 +31,6 @@ use crate::prelude::rings_core::message::Decoder;
 use crate::prelude::rings_core::message::Encoded;
 use crate::prelude::rings_core::message::Encoder;
 use crate::prelude::rings_core::message::MessagePayload;
-use crate::prelude::rings_core::prelude::vnode::VirtualNode;
 use crate::prelude::rings_rpc;
 use crate::prelude::rings_rpc::response::Peer;
 use crate::prelude::rings_rpc::types::HttpRequest;
@@ -48,6 +49,8 @@ pub struct RpcMeta {
     /// if is_auth set to true, rpc server of *native node* will check signature from
     /// HEAD['X-SIGNATURE']
     is_auth: bool,
+    /// IP of the client, offers from which are limited by the IP limiter of swarm.
+    source: Option<IpAddr>,
 }
 
 impl RpcMeta {
@@ -57,6 +60,25 @@ impl RpcMeta {
         }
         Ok(())
     }
+
+    /// Resolve a did or a contact name. On failure the `invalid` error, which was returned
+    /// for an invalid did before contact names were accepted, is kept for clients,
+    /// with the reason in its data.
+    async fn resolve_did(&self, destination: &str, invalid: Error) -> Result<Did> {
+        self.processor
+            .resolve_did(destination)
+            .await
+            .map_err(|e| Error {
+                data: Some(Value::String(e.to_string())),
+                ..invalid
+            })
+    }
+
+    /// Set IP of the client, which is known by the http server.
+    pub fn with_source(mut self, source: IpAddr) -> Self {
+        self.source = Some(source);
+        self
+    }
 }
 
 impl From<(Arc<Processor>, Arc<Mutex<Receiver<BackendMessage>>>, bool)> for RpcMeta {
@@ -71,6 +93,7 @@ impl From<(Arc<Processor>, Arc<Mutex<Receiver<BackendMessage>>>, bool)> for RpcM
             processor,
             receiver: Some(receiver),
             is_auth,
+            source: None,
         }
     }
 }
@@ -81,6 +104,7 @@ impl From<(Arc<Processor>, bool)> for RpcMeta {
             processor,
             receiver: None,
             is_auth,
+            source: None,
         }
     }
 }
@@ -91,6 +115,7 @@ impl From<Arc<Processor>> for RpcMeta {
             processor,
             receiver: None,
             is_auth: true,
+            source: None,
         }
     }
 }
@@ -160,7 +185,9 @@ pub(crate) async fn connect_with_did(params: Params, meta: RpcMeta) -> Result<Va
     let address_str = p
         .first()
         .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
-    let did = Did::from_str(address_str).map_err(|_| Error::new(ErrorCode::InvalidParams))?;
+    let did = meta
+        .resolve_did(address_str, Error::new(ErrorCode::InvalidParams))
+        .await?;
 
     meta.processor
         .connect_with_did(did, true)
@@ -178,7 +205,9 @@ pub(crate) async fn create_offer(params: Params, meta: RpcMeta) -> Result<Value>
     let address_str = p
         .first()
         .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
-    let did = Did::from_str(address_str).map_err(|_| Error::new(ErrorCode::InvalidParams))?;
+    let did = meta
+        .resolve_did(address_str, Error::new(ErrorCode::InvalidParams))
+        .await?;
 
     let (_, offer_payload) = meta
         .processor
@@ -206,6 +235,15 @@ pub(crate) async fn answer_offer(params: Params, meta: RpcMeta) -> Result<Value>
     let offer_payload =
         MessagePayload::from_encoded(&encoded).map_err(|_| ServerError::DecodeError)?;
 
+    if let Some(source) = meta.source {
+        meta.processor
+            .swarm
+            .check_ip_limit(offer_payload.relay.origin_sender(), &[source])
+            .await
+            .map_err(ServerError::AnswerOffer)
+            .map_err(Error::from)?;
+    }
+
     let (_, answer_payload) = meta
         .processor
         .swarm
@@ -267,7 +305,9 @@ pub(crate) async fn close_connection(params: Params, meta: RpcMeta) -> Result<Va
     let did = params
         .first()
         .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
-    let did = Did::from_str(did).map_err(|_| Error::from(ServerError::InvalidDid))?;
+    let did = meta
+        .resolve_did(did, Error::from(ServerError::InvalidDid))
+        .await?;
     meta.processor.disconnect(did).await?;
     Ok(serde_json::json!({}))
 }
@@ -383,8 +423,12 @@ pub(crate) async fn send_http_request_message(params: Params, meta: RpcMeta) ->
         .to_owned();
     let http_request: HttpRequest =
         serde_json::from_value(p2).map_err(|_| Error::new(ErrorCode::InvalidParams))?;
+    // Only the browser client decodes responses compressed by content.
+    #[cfg(feature = "node")]
+    let http_request = http_compression::strip_compression_header(http_request);
 
-    let msg: BackendMessage = (MessageType::HttpRequest, &http_request).try_into()?;
+    let msg: BackendMessage = BackendMessage::try_from((MessageType::HttpRequest, &http_request))?
+        .with_service(&http_request.name);
     let msg: Vec<u8> = msg.into();
     // TODO chunk message flag
     let tx_id = meta.processor.send_message(destination, &msg).await?;
@@ -433,7 +477,10 @@ pub(crate) async fn fetch_messages_of_topic(params: Params, meta: RpcMeta) -> Re
         .as_i64()
         .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
 
-    let vid = VirtualNode::gen_did(topic).map_err(|_| Error::new(ErrorCode::InvalidParams))?;
+    let vid = meta
+        .processor
+        .gen_vid(topic)
+        .map_err(|_| Error::new(ErrorCode::InvalidParams))?;
 
     meta.processor.storage_fetch(vid).await?;
     let result = meta.processor.storage_check_cache(vid).await;
@@ -473,7 +520,10 @@ pub(crate) async fn lookup_service(params: Params, meta: RpcMeta) -> Result<Valu
         .as_str()
         .ok_or_else(|| Error::new(ErrorCode::InvalidParams))?;
 
-    let rid = VirtualNode::gen_did(name).map_err(|_| Error::new(ErrorCode::InvalidParams))?;
+    let rid = meta
+        .processor
+        .gen_vid(name)
+        .map_err(|_| Error::new(ErrorCode::InvalidParams))?;
 
     meta.processor.storage_fetch(rid).await?;
     let result = meta.processor.storage_check_cache(rid).await;
@@ -524,4 +574,20 @@ mod tests {
             .await
             .unwrap();
     }
+
+    #[tokio::test]
+    async fn test_unresolved_did_keeps_error_code() {
+        let meta = new_rnd_meta().await;
+        let e = create_offer(Params::Array(vec!["alice".into()]), meta.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(e.code, ErrorCode::InvalidParams);
+        assert!(e.data.is_some());
+
+        let e = close_connection(Params::Array(vec!["alice".into()]), meta)
+            .await
+            .unwrap_err();
+        assert_eq!(e.code, Error::from(ServerError::InvalidDid).code);
+        assert!(e.data.is_some());
+    }
 }
@@ -4,14 +4,21 @@ pub mod backend;
 #[cfg(feature = "browser")]
 pub mod browser;
 pub mod consts;
+pub mod contact;
+pub mod diagnostics;
+pub mod drain;
 pub mod error;
 pub mod jsonrpc;
+pub mod liveness;
 pub mod logging;
 pub mod measure;
 #[cfg(feature = "node")]
 pub mod native;
+pub mod peer_book;
 pub mod prelude;
 pub mod processor;
+pub mod reachability;
+pub mod ring_layout;
 pub mod seed;
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,199 @@
+//! This module provide [Liveness], which tells supervisors, such as a liveness probe of
+//! Kubernetes, whether a node is alive and processing.
+//! It combines the heartbeats of the listen loop and the stabilization loop, see
+//! [Heartbeat](crate::prelude::rings_core::swarm::Heartbeat), with the state of transports.
+#![warn(missing_docs)]
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::prelude::rings_core::swarm::Pulse;
+
+/// A listen loop handling an event for longer than this, in milliseconds, is considered stuck.
+pub const LISTEN_STUCK_MS: u64 = 30_000;
+
+/// A stabilization pass running for longer than this, in milliseconds, is considered stuck.
+pub const STABILIZATION_STUCK_MS: u64 = 120_000;
+
+/// Stabilization is considered stalled if no pass started or finished within this many
+/// intervals.
+pub const STABILIZATION_STALL_INTERVALS: u64 = 3;
+
+/// Transports are considered unresponsive if this many events wait for the listen loop.
+pub const EVENT_QUEUE_BACKLOG: usize = 1024;
+
+/// Overall status of [Liveness], ordered from the best to the worst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LivenessStatus {
+    /// Everything is alive.
+    Healthy,
+    /// Alive, but something is off, such as stalled stabilization. Restarting won't help
+    /// necessarily.
+    Degraded,
+    /// Not processing, the node should be restarted.
+    Unhealthy,
+}
+
+/// Liveness of a node, with reasons of the status if it's not healthy.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Liveness {
+    /// Overall status, the worst of all signals.
+    pub status: LivenessStatus,
+    /// Why the node is not healthy, empty if it is.
+    pub reasons: Vec<String>,
+}
+
+impl Liveness {
+    /// Check if the node is alive, which is not [LivenessStatus::Unhealthy].
+    pub fn is_live(&self) -> bool {
+        self.status != LivenessStatus::Unhealthy
+    }
+}
+
+/// Signals combined into [Liveness].
+#[derive(Debug, Clone, Copy)]
+pub struct LivenessSignals {
+    /// Heartbeat of the listen loop of swarm.
+    pub listen: Pulse,
+    /// Heartbeat of the stabilization loop.
+    pub stabilization: Pulse,
+    /// Whether stabilization is stopped deliberately, such as for shutdown.
+    pub stabilization_stopped: bool,
+    /// Interval of stabilization in milliseconds.
+    pub stabilization_interval_ms: u64,
+    /// Number of transport events waiting for the listen loop.
+    pub event_queue_depth: usize,
+    /// Number of connections, except the ones still handshaking.
+    pub connections: usize,
+    /// Number of connections which are connected.
+    pub connected: usize,
+}
+
+impl LivenessSignals {
+    /// Combine signals into [Liveness].
+    pub fn evaluate(&self) -> Liveness {
+        let mut status = LivenessStatus::Healthy;
+        let mut reasons = vec![];
+        let mut report = |s: LivenessStatus, reason: String| {
+            status = status.max(s);
+            reasons.push(reason);
+        };
+
+        if !self.listen.running {
+            report(
+                LivenessStatus::Unhealthy,
+                "listen loop is not running".to_string(),
+            );
+        } else if let Some(ms) = self.listen.busy_ms.filter(|ms| *ms > LISTEN_STUCK_MS) {
+            report(
+                LivenessStatus::Unhealthy,
+                format!("listen loop is stuck on an event for {} ms", ms),
+            );
+        }
+
+        let stall_ms = self.stabilization_interval_ms * STABILIZATION_STALL_INTERVALS;
+        if self.stabilization_stopped {
+            report(
+                LivenessStatus::Degraded,
+                "stabilization is stopped".to_string(),
+            );
+        } else if !self.stabilization.running {
+            report(
+                LivenessStatus::Degraded,
+                "stabilization loop is not running".to_string(),
+            );
+        } else if let Some(ms) = self.stabilization.busy_ms {
+            if ms > STABILIZATION_STUCK_MS {
+                report(
+                    LivenessStatus::Degraded,
+                    format!("stabilization is stuck on a pass for {} ms", ms),
+                );
+            }
+        } else if let Some(ms) = self
+            .stabilization
+            .since_last_beat_ms
+            .filter(|ms| *ms > stall_ms)
+        {
+            report(
+                LivenessStatus::Degraded,
+                format!("no stabilization pass for {} ms", ms),
+            );
+        }
+
+        if self.event_queue_depth >= EVENT_QUEUE_BACKLOG {
+            report(
+                LivenessStatus::Degraded,
+                format!(
+                    "{} transport events are waiting for the listen loop",
+                    self.event_queue_depth
+                ),
+            );
+        }
+        if self.connections > 0 && self.connected == 0 {
+            report(
+                LivenessStatus::Degraded,
+                format!("none of {} connections is connected", self.connections),
+            );
+        }
+
+        Liveness { status, reasons }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pulse(running: bool, since_last_beat_ms: u64, busy_ms: Option<u64>) -> Pulse {
+        Pulse {
+            running,
+            since_last_beat_ms: Some(since_last_beat_ms),
+            busy_ms,
+        }
+    }
+
+    fn healthy() -> LivenessSignals {
+        LivenessSignals {
+            listen: pulse(true, 10, None),
+            stabilization: pulse(true, 1000, None),
+            stabilization_stopped: false,
+            stabilization_interval_ms: 3000,
+            event_queue_depth: 0,
+            connections: 2,
+            connected: 1,
+        }
+    }
+
+    #[test]
+    fn test_liveness() {
+        let liveness = healthy().evaluate();
+        assert_eq!(liveness.status, LivenessStatus::Healthy);
+        assert!(liveness.reasons.is_empty());
+
+        // An idle listen loop is alive however long it waits.
+        let mut signals = healthy();
+        signals.listen = pulse(true, 3_600_000, None);
+        assert_eq!(signals.evaluate().status, LivenessStatus::Healthy);
+
+        let mut signals = healthy();
+        signals.stabilization = pulse(true, 10_000, None);
+        signals.connected = 0;
+        let liveness = signals.evaluate();
+        assert_eq!(liveness.status, LivenessStatus::Degraded);
+        assert_eq!(liveness.reasons, vec![
+            "no stabilization pass for 10000 ms",
+            "none of 2 connections is connected",
+        ]);
+        assert!(liveness.is_live());
+
+        // The worst signal decides.
+        signals.listen = pulse(true, LISTEN_STUCK_MS + 1, Some(LISTEN_STUCK_MS + 1));
+        let liveness = signals.evaluate();
+        assert_eq!(liveness.status, LivenessStatus::Unhealthy);
+        assert_eq!(liveness.reasons.len(), 3);
+
+        let mut signals = healthy();
+        signals.listen.running = false;
+        assert!(!signals.evaluate().is_live());
+    }
+}
@@ -3,16 +3,34 @@ use std::fs;
 use std::io;
 use std::path::PathBuf;
 
+use rings_transport::ice_gathering::IceGathering;
 use serde::Deserialize;
 use serde::Serialize;
 
 use crate::backend::extension::ExtensionConfig;
+use crate::backend::service::access_log::AccessLogConfig;
+use crate::backend::service::arq::ArqConfig;
+use crate::backend::service::broadcast::BroadcastConfig;
+use crate::backend::service::capture::CaptureConfig;
 use crate::backend::service::http_server::HttpServiceConfig;
+use crate::backend::service::proxy::TunnelQueueConfig;
 use crate::backend::service::tcp_server::TcpServiceConfig;
+use crate::backend::service::transfer::TransferConfig;
+use crate::backend::service::tunnel_pool::TunnelPoolConfig;
 use crate::backend::service::BackendConfig;
+use crate::backend::service::UnknownDestinationPolicy;
 use crate::error::Error;
 use crate::error::Result;
+use crate::prelude::rings_core::dht::Did;
 use crate::prelude::rings_core::ecc::SecretKey;
+use crate::prelude::rings_core::message::handlers::store_limit::StoreLimitConfig;
+use crate::prelude::rings_core::message::MessageFilter;
+use crate::prelude::rings_core::swarm::ExpiryPolicy;
+use crate::prelude::rings_core::swarm::IpLimitConfig;
+use crate::prelude::rings_core::swarm::KeyRotation;
+use crate::prelude::rings_core::swarm::MtuBounds;
+use crate::prelude::rings_core::swarm::PayloadTransformConfig;
+use crate::prelude::rings_core::swarm::DEFAULT_STABILIZE_INTERVAL;
 use crate::prelude::SessionSk;
 use crate::processor::ProcessorConfig;
 use crate::processor::ProcessorConfigSerialized;
@@ -26,12 +44,16 @@ lazy_static::lazy_static! {
     path: get_storage_location(".rings", "measure"),
     capacity: DEFAULT_STORAGE_CAPACITY,
   };
+  static ref DEFAULT_PEER_BOOK_STORAGE_CONFIG: StorageConfig = StorageConfig {
+    path: get_storage_location(".rings", "peers"),
+    capacity: DEFAULT_STORAGE_CAPACITY,
+  };
 }
 
 pub const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1:50000";
 pub const DEFAULT_ENDPOINT_URL: &str = "http://127.0.0.1:50000";
 pub const DEFAULT_ICE_SERVERS: &str = "stun://stun.l.google.com:19302";
-pub const DEFAULT_STABILIZE_TIMEOUT: usize = 3;
+pub const DEFAULT_STABILIZE_TIMEOUT: usize = DEFAULT_STABILIZE_INTERVAL;
 pub const DEFAULT_STORAGE_CAPACITY: usize = 200000000;
 
 pub fn get_storage_location<P>(prefix: P, path: P) -> String
@@ -65,10 +87,120 @@ pub struct Config {
     pub tcp_services: Vec<TcpServiceConfig>,
     pub data_storage: StorageConfig,
     pub measure_storage: StorageConfig,
+    /// Storage of peers connected, which are reconnected at startup, see
+    /// [PeerBook](crate::peer_book::PeerBook). Peers are not remembered if not provided.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_book: Option<StorageConfig>,
     /// When there is no configuration in the YAML file,
-    /// its deserialization is equivalent to `ExtensionConfig(vec![])` in Rust.
+    /// its deserialization is equivalent to `ExtensionConfig::default()` in Rust.
+    /// Executions of extensions are unbounded unless `max_concurrency`, `timeout_ms` or `fuel`
+    /// is set, see [extension](crate::backend::extension).
     #[serde(default)]
     pub extension: ExtensionConfig,
+    /// Capture of tunnels, disabled if not provided.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tunnel_capture: Option<CaptureConfig>,
+    /// Access log of http and tcp services, such as `path: /var/log/rings/access.log`.
+    /// Disabled if not provided.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_log: Option<AccessLogConfig>,
+    /// Behaviour on messages addressed to other nodes, `drop` by default.
+    #[serde(default)]
+    pub unknown_destination: UnknownDestinationPolicy,
+    /// Workers and read buffers shared by tunnels, suits gateway nodes proxying many
+    /// connections.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tunnel_pool: Option<TunnelPoolConfig>,
+    /// Queue of packages from peer in each tunnel, such as `capacity: 256` and
+    /// `overflow: close`. Each tunnel queues up to 1024 packages and waits when it's full
+    /// by default.
+    #[serde(default)]
+    pub tunnel_queue: TunnelQueueConfig,
+    /// Max size of a message reassembled from chunks or decompressed, in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_message_size: Option<usize>,
+    /// Max bytes of incomplete chunked messages buffered for each peer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_chunk_budget: Option<usize>,
+    /// Max number of requests in flight with each peer, in each direction. A request is in
+    /// flight until its response is sent. Up to 64 requests by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_in_flight_per_peer: Option<usize>,
+    /// Capacity and backpressure of broadcasting handled messages to local consumers.
+    #[serde(default)]
+    pub broadcast: BroadcastConfig,
+    /// Acknowledged chunks, such as `window: 32` for a lossy link. Up to 64 chunks are sent
+    /// before acknowledged, and a chunk is resent after 3 seconds without ack by default.
+    /// A message of more than 1024 acknowledged chunks is rejected by default.
+    #[serde(default)]
+    pub chunk_arq: ArqConfig,
+    /// Resumable transfers, such as `enabled: true`. Disabled by default. Data received is
+    /// kept up to 512 MiB, and for 10 minutes after the last segment or completion.
+    #[serde(default)]
+    pub transfer: TransferConfig,
+    /// Types of message accepted, such as `deny: [CustomMessage]` for a storage node.
+    /// Every type is accepted by default.
+    #[serde(default)]
+    pub message_filter: MessageFilter,
+    /// Grace windows of messages expired in transit by types, such as
+    /// `grace_ms: {QueryForTopoInfoSend: 3000}`. A grace window tolerates clock skew of peers,
+    /// but widens the window of replaying, so keep writes strict. Every type is strict by default.
+    #[serde(default)]
+    pub expiry_policy: ExpiryPolicy,
+    /// Rotation of the key of encryption to each peer, such as `max_messages: 1000` and
+    /// `max_age_ms: 600000`, which are the defaults. The ephemeral key offered to peers is
+    /// rotated every `max_age_ms` as well.
+    #[serde(default)]
+    pub key_rotation: KeyRotation,
+    /// Limits of new transports from a single IP, such as `max_pending: 8`,
+    /// `max_per_window: 32` and `window_ms: 60000`. Not limited by IP if not provided.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip_limit: Option<IpLimitConfig>,
+    /// Gathering of ICE candidates, such as `mode: full` for signaling that exchanges
+    /// descriptions only. Candidates are trickled, and gathering times out after 3 seconds
+    /// by default.
+    #[serde(default)]
+    pub ice_gathering: IceGathering,
+    /// Range in bytes which chunk size of each peer adapts in, such as `min: 16384` and
+    /// `max: 262144`. Chunks shrink on failures of sending and grow after sustained success.
+    #[serde(default)]
+    pub mtu_bounds: MtuBounds,
+    /// Capabilities announced to the network periodically, such as names of services.
+    /// Not announced if not provided.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub announce: Option<Vec<String>>,
+    /// Seeds of a closed network, messages are only handled if they are from seeds or nodes
+    /// in the view of DHT. Messages from any node are handled if not provided.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub closed_network: Option<Vec<Did>>,
+    /// Rate limit of storing virtual nodes by origin, such as `burst: 32` and `per_sec: 2`,
+    /// scaled by scores of origins. Stores are not limited if not provided.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store_limit: Option<StoreLimitConfig>,
+    /// Path of a zstd dictionary shared with peers, such as one trained on control messages,
+    /// which small payloads are compressed against. Not compressed if not provided.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression_dictionary: Option<String>,
+    /// Transforms of data sent to peers by services in order, such as `kind: gzip`,
+    /// `kind: encrypt`, `kind: sign` and `kind: chunk`. Peers should be configured with the
+    /// same ones. Sent as is if empty. Transformed data is split by the MTU of peer anyway,
+    /// so `kind: chunk` is only needed for smaller chunks.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub payload_transforms: Vec<PayloadTransformConfig>,
+    /// Embedded admin API, disabled if not provided.
+    /// It is only served when built with feature `admin`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub admin: Option<AdminConfig>,
+}
+
+/// Config of embedded admin API, every request should carry the token
+/// in header `Authorization: Bearer <token>`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AdminConfig {
+    /// Address the admin API binds to, such as `127.0.0.1:50001`.
+    pub bind: String,
+    /// Token to authorize requests.
+    pub token: String,
 }
 
 impl TryFrom<&Config> for ProcessorConfigSerialized {
@@ -117,6 +249,17 @@ impl From<&Config> for BackendConfig {
             http_services: config.http_services.clone(),
             tcp_services: config.tcp_services.clone(),
             extensions: config.extension.clone(),
+            max_in_flight_per_peer: config.max_in_flight_per_peer,
+            max_message_size: config.max_message_size,
+            peer_chunk_budget: config.peer_chunk_budget,
+            broadcast: config.broadcast,
+            chunk_arq: config.chunk_arq,
+            transfer: config.transfer,
+            tunnel_capture: config.tunnel_capture.clone(),
+            access_log: config.access_log.clone(),
+            unknown_destination: config.unknown_destination,
+            tunnel_pool: config.tunnel_pool.clone(),
+            tunnel_queue: config.tunnel_queue,
         }
     }
 }
@@ -141,7 +284,31 @@ impl Config {
             tcp_services: vec![],
             data_storage: DEFAULT_DATA_STORAGE_CONFIG.clone(),
             measure_storage: DEFAULT_MEASURE_STORAGE_CONFIG.clone(),
+            peer_book: Some(DEFAULT_PEER_BOOK_STORAGE_CONFIG.clone()),
             extension: ExtensionConfig::default(),
+            tunnel_capture: None,
+            access_log: None,
+            unknown_destination: UnknownDestinationPolicy::default(),
+            tunnel_pool: None,
+            tunnel_queue: TunnelQueueConfig::default(),
+            max_message_size: None,
+            peer_chunk_budget: None,
+            max_in_flight_per_peer: None,
+            broadcast: BroadcastConfig::default(),
+            chunk_arq: ArqConfig::default(),
+            transfer: TransferConfig::default(),
+            message_filter: MessageFilter::default(),
+            expiry_policy: ExpiryPolicy::default(),
+            key_rotation: KeyRotation::default(),
+            ip_limit: None,
+            ice_gathering: IceGathering::default(),
+            mtu_bounds: MtuBounds::default(),
+            announce: None,
+            closed_network: None,
+            store_limit: None,
+            compression_dictionary: None,
+            payload_transforms: vec![],
+            admin: None,
         }
     }
 
@@ -0,0 +1,260 @@
+//! Embedded admin API, which exposes introspection and control of a running node
+//! as JSON endpoints. It is served only when built with feature `admin` and configured
+//! with [AdminConfig], and every request must carry the configured token.
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::Path;
+use axum::extract::Query;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::routing::post;
+use axum::Json;
+use axum::Router;
+use serde::Deserialize;
+use serde::Serialize;
+
+use super::http_error::HttpError;
+use crate::backend::service::self_test::SelfTestReport;
+use crate::backend::service::Backend;
+use crate::diagnostics::DiagnosticsBundle;
+use crate::diagnostics::DiagnosticsDetail;
+use crate::liveness::Liveness;
+use crate::native::config::AdminConfig;
+use crate::prelude::http::header;
+use crate::prelude::http::HeaderMap;
+use crate::prelude::rings_core::dht::Did;
+use crate::prelude::rings_core::inspect::SwarmInspect;
+use crate::prelude::rings_rpc::response::Peer;
+use crate::processor::Processor;
+
+/// Default deadline of `POST /drain`, in seconds.
+pub const DEFAULT_DRAIN_TIMEOUT: u64 = 30;
+
+/// Admin state
+#[derive(Clone)]
+pub struct AdminState {
+    token: String,
+    processor: Arc<Processor>,
+    backend: Arc<Backend>,
+}
+
+/// Query of `POST /drain`.
+#[derive(Debug, Deserialize)]
+pub struct DrainQuery {
+    /// Seconds to wait for in-flight work, defaults to [DEFAULT_DRAIN_TIMEOUT].
+    timeout: Option<u64>,
+}
+
+/// Query of `GET /diagnostics`.
+#[derive(Debug, Deserialize)]
+pub struct DiagnosticsQuery {
+    /// Detail of bundle, defaults to [DiagnosticsDetail::Redacted].
+    #[serde(default)]
+    detail: DiagnosticsDetail,
+}
+
+/// Query of `POST /self_test/:did`.
+#[derive(Debug, Deserialize)]
+pub struct SelfTestQuery {
+    /// Hidden service of peer to dial through a tunnel, not dialed if it's not provided.
+    service: Option<String>,
+}
+
+/// Result of `POST /drain`.
+#[derive(Debug, Serialize)]
+pub struct DrainResponse {
+    /// Whether in-flight work finished before the deadline.
+    idle: bool,
+}
+
+/// Run the admin API, with routes:
+/// - `GET /peers`: connected peers.
+/// - `GET /tunnels`: id of tunnels open to hidden tcp services.
+/// - `GET /dht`: state of swarm, DHT and storages.
+/// - `GET /diagnostics?detail=<redacted|full>`: bundle of node state for bug reports.
+/// - `POST /stabilize`: run a round of stabilization now.
+/// - `POST /disconnect/:did`: disconnect a peer.
+/// - `POST /drain?timeout=<secs>`: stop accepting new work and wait in-flight work.
+/// - `POST /self_test/:did?service=<name>`: [SelfTestReport] of the message path to a peer,
+///   which dials `service` through a tunnel if it's provided.
+/// - `GET /liveness`: [Liveness] of node, responded with 503 if it's unhealthy. It requires
+///   no token, so that it's easy to probe.
+pub async fn run_admin_api(
+    config: AdminConfig,
+    processor: Arc<Processor>,
+    backend: Arc<Backend>,
+) -> anyhow::Result<()> {
+    bind_admin_api(config, processor, backend)?.await
+}
+
+/// Bind the admin API on [AdminConfig::bind], and return the future serving it, see
+/// [run_admin_api]. It fails right away if the address can't be bound, so that a node
+/// configured with admin API doesn't start without it.
+pub fn bind_admin_api(
+    config: AdminConfig,
+    processor: Arc<Processor>,
+    backend: Arc<Backend>,
+) -> anyhow::Result<impl Future<Output = anyhow::Result<()>>> {
+    let binding_addr = config.bind.parse()?;
+    let state = Arc::new(AdminState {
+        token: config.token,
+        processor,
+        backend,
+    });
+
+    let app = Router::new()
+        .route("/peers", get(peers_handler))
+        .route("/tunnels", get(tunnels_handler))
+        .route("/dht", get(dht_handler))
+        .route("/diagnostics", get(diagnostics_handler))
+        .route("/stabilize", post(stabilize_handler))
+        .route("/disconnect/:did", post(disconnect_handler))
+        .route("/drain", post(drain_handler))
+        .route("/self_test/:did", post(self_test_handler))
+        .route("/liveness", get(liveness_handler))
+        .with_state(state);
+
+    let server = axum::Server::try_bind(&binding_addr)?.serve(app.into_make_service());
+    println!("Admin endpoint: http://{}", config.bind);
+    Ok(async move {
+        server.await?;
+        Ok(())
+    })
+}
+
+/// Compare bytes in constant time, so that the token can't be guessed by timing.
+fn token_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn authorize(state: &AdminState, headers: &HeaderMap) -> Result<(), HttpError> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .ok_or(HttpError::Unauthorized)?;
+    // An empty token in config never authorizes.
+    if state.token.is_empty() || !token_eq(token.as_bytes(), state.token.as_bytes()) {
+        return Err(HttpError::Unauthorized);
+    }
+    Ok(())
+}
+
+async fn peers_handler(
+    State(state): State<Arc<AdminState>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<Peer>>, HttpError> {
+    authorize(&state, &headers)?;
+    let peers = state
+        .processor
+        .list_peers()
+        .await
+        .map_err(|_| HttpError::Internal)?;
+    Ok(Json(peers.iter().map(|x| x.into_response_peer()).collect()))
+}
+
+async fn tunnels_handler(
+    State(state): State<Arc<AdminState>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<String>>, HttpError> {
+    authorize(&state, &headers)?;
+    let tunnels = state.backend.tcp_server.list_tunnels();
+    Ok(Json(tunnels.iter().map(|x| x.to_string()).collect()))
+}
+
+async fn dht_handler(
+    State(state): State<Arc<AdminState>>,
+    headers: HeaderMap,
+) -> Result<Json<SwarmInspect>, HttpError> {
+    authorize(&state, &headers)?;
+    Ok(Json(state.processor.swarm.inspect().await))
+}
+
+async fn diagnostics_handler(
+    State(state): State<Arc<AdminState>>,
+    headers: HeaderMap,
+    Query(query): Query<DiagnosticsQuery>,
+) -> Result<Json<DiagnosticsBundle>, HttpError> {
+    authorize(&state, &headers)?;
+    let bundle = state
+        .processor
+        .diagnostics_bundle_with(query.detail)
+        .await
+        .with_tunnels(state.backend.tcp_server.list_tunnels());
+    Ok(Json(bundle))
+}
+
+async fn stabilize_handler(
+    State(state): State<Arc<AdminState>>,
+    headers: HeaderMap,
+) -> Result<Json<()>, HttpError> {
+    authorize(&state, &headers)?;
+    state.processor.stabilize_now().await.map_err(|e| {
+        tracing::error!("Stabilize failed: {}", e);
+        HttpError::Internal
+    })?;
+    Ok(Json(()))
+}
+
+async fn disconnect_handler(
+    State(state): State<Arc<AdminState>>,
+    headers: HeaderMap,
+    Path(did): Path<String>,
+) -> Result<Json<()>, HttpError> {
+    authorize(&state, &headers)?;
+    let did: Did = did.parse().map_err(|_| HttpError::BadRequest)?;
+    state.processor.disconnect(did).await.map_err(|e| {
+        tracing::error!("Disconnect {} failed: {}", did, e);
+        HttpError::Internal
+    })?;
+    Ok(Json(()))
+}
+
+async fn drain_handler(
+    State(state): State<Arc<AdminState>>,
+    headers: HeaderMap,
+    Query(query): Query<DrainQuery>,
+) -> Result<Json<DrainResponse>, HttpError> {
+    authorize(&state, &headers)?;
+    let timeout = Duration::from_secs(query.timeout.unwrap_or(DEFAULT_DRAIN_TIMEOUT));
+    let idle = state.processor.drain(timeout).await;
+    Ok(Json(DrainResponse { idle }))
+}
+
+async fn self_test_handler(
+    State(state): State<Arc<AdminState>>,
+    headers: HeaderMap,
+    Path(did): Path<String>,
+    Query(query): Query<SelfTestQuery>,
+) -> Result<Json<SelfTestReport>, HttpError> {
+    authorize(&state, &headers)?;
+    let did: Did = did.parse().map_err(|_| HttpError::BadRequest)?;
+    let report = state.backend.self_test(did, query.service.as_deref()).await;
+    Ok(Json(report))
+}
+
+async fn liveness_handler(State(state): State<Arc<AdminState>>) -> (StatusCode, Json<Liveness>) {
+    let liveness = state.processor.liveness();
+    let code = if liveness.is_live() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (code, Json(liveness))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_token_eq() {
+        assert!(token_eq(b"secret", b"secret"));
+        assert!(!token_eq(b"secret", b"secreT"));
+        assert!(!token_eq(b"secret", b"secret1"));
+        assert!(!token_eq(b"", b"secret"));
+    }
+}
@@ -1,5 +1,7 @@
 //! rings-node service run with `Swarm` and chord stabilization.
 #![warn(missing_docs)]
+#[cfg(feature = "admin")]
+pub mod admin;
 mod http_error;
 mod ws;
 
@@ -97,6 +99,7 @@ pub async fn run_http_api(
 
 async fn jsonrpc_io_handler(
     State(state): State<Arc<JsonrpcState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     headermap: HeaderMap,
     body: String,
 ) -> Result<JsonResponse, HttpError> {
@@ -122,12 +125,11 @@ async fn jsonrpc_io_handler(
     } else {
         false
     };
+    let meta = RpcMeta::from((state.processor.clone(), state.receiver.clone(), is_auth))
+        .with_source(addr.ip());
     let r = state
         .io_handler
-        .handle_request(
-            &body,
-            (state.processor.clone(), state.receiver.clone(), is_auth).into(),
-        )
+        .handle_request(&body, meta)
         .await
         .ok_or(HttpError::BadRequest)?;
     Ok(JsonResponse(r))
@@ -0,0 +1,252 @@
+//! This module provide the `PeerBook`, which persists peers to reconnect after a restart or
+//! a network change, along with their [ReconnectPriority].
+//! Peers are reconnected in tiers of priority, so that connectivity to infrastructure, such
+//! as bootstrap and relay nodes, is restored before nice-to-have peers. Pinned peers, see
+//! [Swarm::pin_peer](crate::prelude::rings_core::swarm::Swarm::pin_peer), are implicitly of the
+//! highest priority.
+//!
+//! [Processor](crate::processor::Processor) remembers peers it connects: ones connected by url
+//! of their jsonrpc servers, such as bootstrap and relay nodes, are of
+//! [ReconnectPriority::High], and are reconnected by url again, since nothing else can reach
+//! them before any connection. Others are of [ReconnectPriority::Normal]. Peers connecting to
+//! this node, or connected by DHT, are not remembered, they are found again through the ring.
+#![warn(missing_docs)]
+use std::sync::Arc;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::error::Error;
+use crate::error::Result;
+use crate::prelude::rings_core::dht::Did;
+use crate::prelude::rings_core::storage::PersistenceStorageRemove;
+use crate::prelude::PersistenceStorage;
+use crate::prelude::PersistenceStorageReadAndWrite;
+
+/// Priority of reconnecting a peer, ordered from the lowest to the highest.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ReconnectPriority {
+    /// Ephemeral peers, reconnected last.
+    Low,
+    /// Ordinary peers.
+    #[default]
+    Normal,
+    /// Infrastructure, such as bootstrap and relay nodes, reconnected first.
+    High,
+}
+
+/// A peer persisted in [PeerBook].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerEntry {
+    /// Did of peer.
+    pub did: Did,
+    /// Priority of reconnecting peer.
+    #[serde(default)]
+    pub priority: ReconnectPriority,
+    /// Url of the jsonrpc server of peer, which it's reconnected by if provided.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+/// `PeerBook` persists peers with their priorities of reconnecting.
+/// It should be given a storage of its own, since every entry of storage is listed.
+#[derive(Debug)]
+pub struct PeerBook {
+    storage: Arc<PersistenceStorage>,
+}
+
+impl PeerBook {
+    /// Create a new `PeerBook` with the given storage.
+    pub fn new(storage: PersistenceStorage) -> Self {
+        Self {
+            storage: Arc::new(storage),
+        }
+    }
+
+    fn gen_storage_key(did: Did) -> String {
+        format!("PeerBook/peers/{}", did)
+    }
+
+    /// Add a peer, or update its priority.
+    pub async fn add(&self, did: Did, priority: ReconnectPriority) -> Result<()> {
+        let url = self.get(did).await?.and_then(|e| e.url);
+        self.put(&PeerEntry { did, priority, url }).await
+    }
+
+    /// Remember a peer connected, which never lowers its priority, and keeps its url if `url`
+    /// is not provided.
+    pub async fn remember(
+        &self,
+        did: Did,
+        priority: ReconnectPriority,
+        url: Option<&str>,
+    ) -> Result<()> {
+        let entry = match self.get(did).await? {
+            Some(e) => PeerEntry {
+                did,
+                priority: e.priority.max(priority),
+                url: url.map(|u| u.to_string()).or(e.url),
+            },
+            None => PeerEntry {
+                did,
+                priority,
+                url: url.map(|u| u.to_string()),
+            },
+        };
+        self.put(&entry).await
+    }
+
+    /// Get a peer.
+    pub async fn get(&self, did: Did) -> Result<Option<PeerEntry>> {
+        self.storage
+            .get(&Self::gen_storage_key(did))
+            .await
+            .map_err(Error::Storage)
+    }
+
+    async fn put(&self, entry: &PeerEntry) -> Result<()> {
+        self.storage
+            .put(&Self::gen_storage_key(entry.did), entry)
+            .await
+            .map_err(Error::Storage)
+    }
+
+    /// Remove a peer.
+    pub async fn remove(&self, did: Did) -> Result<()> {
+        self.storage
+            .remove(&Self::gen_storage_key(did))
+            .await
+            .map_err(Error::Storage)
+    }
+
+    /// List peers.
+    pub async fn list(&self) -> Result<Vec<PeerEntry>> {
+        let entries: Vec<(String, PeerEntry)> =
+            self.storage.get_all().await.map_err(Error::Storage)?;
+        Ok(entries.into_iter().map(|(_, entry)| entry).collect())
+    }
+
+    /// Group peers into tiers of reconnecting, from the highest priority to the lowest.
+    /// Pinned peers come first, and each peer appears once, in its highest tier.
+    pub fn reconnect_tiers(entries: &[PeerEntry], pinned: &[Did]) -> Vec<Vec<Did>> {
+        let mut seen = std::collections::HashSet::new();
+        let mut tiers = vec![pinned.iter().copied().filter(|d| seen.insert(*d)).collect()];
+        for priority in [
+            ReconnectPriority::High,
+            ReconnectPriority::Normal,
+            ReconnectPriority::Low,
+        ] {
+            let tier = entries
+                .iter()
+                .filter(|e| e.priority == priority && seen.insert(e.did))
+                .map(|e| e.did)
+                .collect();
+            tiers.push(tier);
+        }
+        tiers.retain(|tier: &Vec<Did>| !tier.is_empty());
+        tiers
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "node")]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_peer_book() {
+        let path = PersistenceStorage::random_path("./tmp");
+        let storage = PersistenceStorage::new_with_path(path.as_str())
+            .await
+            .unwrap();
+        let relay = Did::from_str("0x11E807fcc88dD319270493fB2e822e388Fe36ab0").unwrap();
+        let ephemeral = Did::from_str("0x999999cf1046e68e36E1aA2E0E07105eDDD1f08E").unwrap();
+
+        let book = PeerBook::new(storage);
+        book.add(relay, ReconnectPriority::Normal).await.unwrap();
+        book.add(relay, ReconnectPriority::High).await.unwrap();
+        book.add(ephemeral, ReconnectPriority::Low).await.unwrap();
+
+        let mut entries = book.list().await.unwrap();
+        entries.sort_by_key(|e| e.priority);
+        assert_eq!(entries, vec![
+            PeerEntry {
+                did: ephemeral,
+                priority: ReconnectPriority::Low,
+                url: None,
+            },
+            PeerEntry {
+                did: relay,
+                priority: ReconnectPriority::High,
+                url: None,
+            },
+        ]);
+
+        book.remove(ephemeral).await.unwrap();
+        assert_eq!(book.list().await.unwrap().len(), 1);
+
+        // Remembering never lowers priority, and keeps url.
+        book.remember(relay, ReconnectPriority::Normal, Some("http://relay"))
+            .await
+            .unwrap();
+        book.remember(relay, ReconnectPriority::Low, None)
+            .await
+            .unwrap();
+        assert_eq!(book.get(relay).await.unwrap().unwrap(), PeerEntry {
+            did: relay,
+            priority: ReconnectPriority::High,
+            url: Some("http://relay".to_string()),
+        });
+        book.remember(ephemeral, ReconnectPriority::Low, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            book.get(ephemeral).await.unwrap().unwrap().priority,
+            ReconnectPriority::Low
+        );
+    }
+
+    #[test]
+    fn test_reconnect_tiers() {
+        let (pinned, relay, normal, ephemeral): (Did, Did, Did, Did) =
+            (1u32.into(), 2u32.into(), 3u32.into(), 4u32.into());
+        let entries = [
+            PeerEntry {
+                did: ephemeral,
+                priority: ReconnectPriority::Low,
+                url: None,
+            },
+            PeerEntry {
+                did: normal,
+                priority: ReconnectPriority::Normal,
+                url: None,
+            },
+            PeerEntry {
+                did: relay,
+                priority: ReconnectPriority::High,
+                url: None,
+            },
+            // A pinned peer is reconnected with pinned ones, whatever its priority.
+            PeerEntry {
+                did: pinned,
+                priority: ReconnectPriority::Low,
+                url: None,
+            },
+        ];
+        assert_eq!(PeerBook::reconnect_tiers(&entries, &[pinned]), vec![
+            vec![pinned],
+            vec![relay],
+            vec![normal],
+            vec![ephemeral],
+        ]);
+        assert_eq!(PeerBook::reconnect_tiers(&entries[..2], &[]), vec![
+            vec![normal],
+            vec![ephemeral]
+        ]);
+    }
+}
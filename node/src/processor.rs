@@ -2,41 +2,100 @@
 
 //! Processor of rings-node jsonrpc-server.
 
+use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::Arc;
+#[cfg(feature = "node")]
+use std::sync::RwLock;
 
 use futures::future::Join;
 use futures::Future;
+use futures::Stream;
+use futures::StreamExt;
 #[cfg(feature = "node")]
 use jsonrpc_core::Metadata;
 use rings_core::message::MessagePayload;
 use rings_core::swarm::impls::ConnectionHandshake;
+use rings_transport::backpressure::BufferWatermark;
 use rings_transport::core::transport::ConnectionInterface;
+use rings_transport::core::transport::WebrtcConnectionState;
+use rings_transport::ice_gathering::IceGathering;
 use serde::Deserialize;
 use serde::Serialize;
 
+#[cfg(feature = "node")]
+use crate::backend::http_compression;
+#[cfg(feature = "node")]
+use crate::backend::service::self_test::SelfTestReport;
+use crate::backend::service::typed::TypedPayload;
+#[cfg(feature = "node")]
+use crate::backend::service::Backend;
+use crate::backend::types::encode_custom_messages_for;
 use crate::backend::types::BackendMessage;
 use crate::backend::types::MessageType;
+use crate::backend::types::MultipartMessage;
 use crate::consts::DATA_REDUNDANT;
+use crate::contact::ContactBook;
+use crate::diagnostics::ConfigSummary;
+use crate::diagnostics::DiagnosticsBundle;
+use crate::diagnostics::DiagnosticsDetail;
+use crate::diagnostics::PeerDiagnostics;
+use crate::diagnostics::StorageDiagnostics;
+use crate::drain::Drain;
 use crate::error::Error;
 use crate::error::Result;
+use crate::liveness::Liveness;
+use crate::liveness::LivenessSignals;
 use crate::measure::PeriodicMeasure;
+use crate::peer_book::PeerBook;
+use crate::peer_book::ReconnectPriority;
 use crate::prelude::http;
 use crate::prelude::jsonrpc_client::SimpleClient;
 use crate::prelude::jsonrpc_core;
+use crate::prelude::rings_core::chunk::ChunkPacer;
+use crate::prelude::rings_core::chunk::ChunkPacing;
+use crate::prelude::rings_core::consts::MAX_DECOMPRESSED_SIZE;
 use crate::prelude::rings_core::dht::Did;
 use crate::prelude::rings_core::dht::Stabilization;
 use crate::prelude::rings_core::dht::TStabilize;
+use crate::prelude::rings_core::identity::EncryptedIdentity;
+use crate::prelude::rings_core::inspect::DHTInspect;
+use crate::prelude::rings_core::inspect::StorageInspect;
+use crate::prelude::rings_core::message::handlers::announce::KnownNode;
+use crate::prelude::rings_core::message::handlers::routing::RoutingSnapshot;
+use crate::prelude::rings_core::message::handlers::routing::ROUTING_QUERY_TIMEOUT_MS;
+use crate::prelude::rings_core::message::handlers::store_limit::StoreLimitConfig;
+use crate::prelude::rings_core::message::CompressionDictionary;
 use crate::prelude::rings_core::message::Decoder;
 use crate::prelude::rings_core::message::Encoded;
 use crate::prelude::rings_core::message::Encoder;
 use crate::prelude::rings_core::message::Message;
+use crate::prelude::rings_core::message::MessageFilter;
 use crate::prelude::rings_core::message::PayloadSender;
+use crate::prelude::rings_core::metrics::MetricsImpl;
+use crate::prelude::rings_core::metrics::CHUNK_PACING_DELAY_MS;
+use crate::prelude::rings_core::metrics::CHUNK_SENT;
 use crate::prelude::rings_core::prelude::uuid;
 use crate::prelude::rings_core::storage::PersistenceStorage;
+use crate::prelude::rings_core::swarm::AuthorizerImpl;
+use crate::prelude::rings_core::swarm::ConnectionGateImpl;
+use crate::prelude::rings_core::swarm::DropLogConfig;
+use crate::prelude::rings_core::swarm::ExpiryPolicy;
+use crate::prelude::rings_core::swarm::IpLimitConfig;
+use crate::prelude::rings_core::swarm::KeyRotation;
 use crate::prelude::rings_core::swarm::MeasureImpl;
+use crate::prelude::rings_core::swarm::MtuBounds;
+use crate::prelude::rings_core::swarm::NetworkProfile;
+use crate::prelude::rings_core::swarm::PayloadPipeline;
+use crate::prelude::rings_core::swarm::PayloadTransformConfig;
+use crate::prelude::rings_core::swarm::RelaySelectorImpl;
+use crate::prelude::rings_core::swarm::RetryBudget;
+use crate::prelude::rings_core::swarm::StreamReader;
+use crate::prelude::rings_core::swarm::StreamWriter;
 use crate::prelude::rings_core::swarm::Swarm;
 use crate::prelude::rings_core::swarm::SwarmBuilder;
+use crate::prelude::rings_core::types::channel::OverflowPolicy;
+use crate::prelude::rings_core::utils::get_epoch_ms;
 use crate::prelude::rings_rpc::method;
 use crate::prelude::rings_rpc::response;
 use crate::prelude::rings_rpc::types::HttpRequest;
@@ -48,7 +107,13 @@ use crate::prelude::ChordStorageInterface;
 use crate::prelude::ChordStorageInterfaceCacheChecker;
 use crate::prelude::Connection;
 use crate::prelude::CustomMessage;
+use crate::prelude::SecretKey;
 use crate::prelude::SessionSk;
+use crate::reachability::Reachability;
+use crate::reachability::ReachabilityCache;
+use crate::ring_layout::RingSegment;
+use crate::ring_layout::RING_LAYOUT_CONCURRENCY;
+use crate::ring_layout::RING_LAYOUT_MAX_NODES;
 
 /// ProcessorConfig is usually serialized as json or yaml.
 /// There is a `from_config` method in [ProcessorBuilder] used to initialize the Builder with a serialized ProcessorConfig.
@@ -205,10 +270,40 @@ pub struct ProcessorBuilder {
     ice_servers: String,
     external_address: Option<String>,
     session_sk: SessionSk,
+    secret_key: Option<SecretKey>,
     storage: Option<PersistenceStorage>,
     measure: Option<MeasureImpl>,
+    metrics: Option<MetricsImpl>,
+    tracing: Option<tracing::Dispatch>,
     message_callback: Option<CallbackFn>,
+    event_queue: Option<(usize, OverflowPolicy)>,
+    connection_gate: Option<ConnectionGateImpl>,
+    ip_limit: Option<IpLimitConfig>,
+    authorizer: Option<AuthorizerImpl>,
+    lookup_retry: Option<RetryBudget>,
+    drop_log: Option<DropLogConfig>,
+    buffer_watermark: Option<BufferWatermark>,
+    ice_gathering: Option<IceGathering>,
+    mtu_bounds: MtuBounds,
+    app_id: Option<String>,
+    contact_book: Option<PersistenceStorage>,
+    peer_book: Option<PersistenceStorage>,
+    network_profile: Option<NetworkProfile>,
+    send_timeout_ms: Option<u64>,
+    relay_selector: Option<RelaySelectorImpl>,
+    compact_payload: bool,
+    no_dht: bool,
+    closed_network: Option<Vec<Did>>,
+    store_limit: Option<StoreLimitConfig>,
+    message_filter: MessageFilter,
+    expiry_policy: ExpiryPolicy,
+    key_rotation: KeyRotation,
+    announcement: Option<Vec<String>>,
+    compression_dictionary: Option<CompressionDictionary>,
+    payload_transforms: Vec<PayloadTransformConfig>,
+    max_message_size: Option<usize>,
     stabilize_timeout: usize,
+    stabilize_interval: Option<usize>,
 }
 
 /// Processor for rings-node jsonrpc server
@@ -218,6 +313,21 @@ pub struct Processor {
     pub swarm: Arc<Swarm>,
     /// a stabilization instance,
     pub stabilization: Arc<Stabilization>,
+    /// secret key of the account of node, to be exported.
+    secret_key: Option<SecretKey>,
+    /// application id scoping topics of virtual nodes.
+    app_id: Option<String>,
+    /// local contact book, mapping names to dids.
+    contacts: Option<Arc<ContactBook>>,
+    /// peers persisted to reconnect, with their priorities.
+    peers: Option<Arc<PeerBook>>,
+    /// recently computed reachability of dids.
+    reachability: Arc<ReachabilityCache>,
+    /// draining state shared with backend.
+    drain: Drain,
+    /// backend serving messages of peers, see [Processor::set_backend].
+    #[cfg(feature = "node")]
+    backend: Arc<RwLock<Option<Arc<Backend>>>>,
 }
 
 impl ProcessorBuilder {
@@ -234,13 +344,61 @@ impl ProcessorBuilder {
             ice_servers: config.ice_servers.clone(),
             external_address: config.external_address.clone(),
             session_sk: config.session_sk.clone(),
+            secret_key: None,
             storage: None,
             measure: None,
+            metrics: None,
+            tracing: None,
             message_callback: None,
+            event_queue: None,
+            connection_gate: None,
+            ip_limit: None,
+            authorizer: None,
+            lookup_retry: None,
+            drop_log: None,
+            buffer_watermark: None,
+            ice_gathering: None,
+            mtu_bounds: MtuBounds::default(),
+            app_id: None,
+            contact_book: None,
+            peer_book: None,
+            network_profile: None,
+            send_timeout_ms: None,
+            relay_selector: None,
+            compact_payload: false,
+            no_dht: false,
+            closed_network: None,
+            store_limit: None,
+            message_filter: MessageFilter::default(),
+            expiry_policy: ExpiryPolicy::default(),
+            key_rotation: KeyRotation::default(),
+            announcement: None,
+            compression_dictionary: None,
+            payload_transforms: vec![],
+            max_message_size: None,
             stabilize_timeout: config.stabilize_timeout,
+            stabilize_interval: None,
         })
     }
 
+    /// Take the identity exported by [Processor::export_identity] from another node, such as
+    /// when restoring a replaced machine, so that the processor resumes the ring position and
+    /// virtual nodes of that node.
+    pub fn identity(self, identity: &[u8], password: &str) -> Result<Self> {
+        let key = EncryptedIdentity::from_bytes(identity)
+            .and_then(|identity| identity.decrypt(password))
+            .map_err(Error::CoreError)?;
+        self.secret_key(key)
+    }
+
+    /// Take the secret key of the account of node, which signs a new session in place of the
+    /// configured one, and is exported by [Processor::export_identity].
+    pub fn secret_key(mut self, key: SecretKey) -> Result<Self> {
+        self.session_sk = SessionSk::new_with_seckey(&key).map_err(Error::CoreError)?;
+        self.secret_key = Some(key);
+        Ok(self)
+    }
+
     /// Set the storage for the processor.
     pub fn storage(mut self, storage: PersistenceStorage) -> Self {
         self.storage = Some(storage);
@@ -253,19 +411,228 @@ impl ProcessorBuilder {
         self
     }
 
+    /// Set the metrics recorder for the processor.
+    /// The host decides where metrics go. They are dropped if no recorder is set.
+    pub fn with_metrics(mut self, recorder: MetricsImpl) -> Self {
+        self.metrics = Some(recorder);
+        self
+    }
+
+    /// Set the tracing subscriber for the processor.
+    /// It's installed as global default when building, unless another one was installed before.
+    pub fn with_tracing(mut self, dispatch: tracing::Dispatch) -> Self {
+        self.tracing = Some(dispatch);
+        self
+    }
+
     /// Set the message callback for the processor.
     pub fn message_callback(mut self, callback: CallbackFn) -> Self {
         self.message_callback = Some(callback);
         self
     }
 
+    /// Bound the queue of incoming events of the processor.
+    /// See [SwarmBuilder::event_queue] for details.
+    pub fn event_queue(mut self, capacity: usize, policy: OverflowPolicy) -> Self {
+        self.event_queue = Some((capacity, policy));
+        self
+    }
+
+    /// Limit transports by source IP, see [SwarmBuilder::ip_limit].
+    pub fn ip_limit(mut self, config: IpLimitConfig) -> Self {
+        self.ip_limit = Some(config);
+        self
+    }
+
+    /// Set the connection gate for the processor, peers rejected by the gate cannot connect.
+    pub fn connection_gate(mut self, gate: ConnectionGateImpl) -> Self {
+        self.connection_gate = Some(gate);
+        self
+    }
+
+    /// Set the authorizer for the processor, which decides whether peers may connect
+    /// and use services, see [SwarmBuilder::authorizer].
+    pub fn authorizer(mut self, authorizer: AuthorizerImpl) -> Self {
+        self.authorizer = Some(authorizer);
+        self
+    }
+
+    /// Set the retry budget of DHT lookups for the processor.
+    pub fn lookup_retry(mut self, budget: RetryBudget) -> Self {
+        self.lookup_retry = Some(budget);
+        self
+    }
+
+    /// Set the sampled logging of dropped messages for the processor.
+    pub fn drop_log(mut self, config: DropLogConfig) -> Self {
+        self.drop_log = Some(config);
+        self
+    }
+
+    /// Set the watermarks of buffered amount of transport data channels for the processor.
+    pub fn buffer_watermark(mut self, watermark: BufferWatermark) -> Self {
+        self.buffer_watermark = Some(watermark);
+        self
+    }
+
+    /// Set the mode and timeout of ICE gathering for the processor, see
+    /// [SwarmBuilder::ice_gathering_mode].
+    pub fn ice_gathering(mut self, gathering: IceGathering) -> Self {
+        self.ice_gathering = Some(gathering);
+        self
+    }
+
+    /// Set the range which chunk size of each peer adapts in, see [SwarmBuilder::mtu_bounds].
+    pub fn mtu_bounds(mut self, bounds: MtuBounds) -> Self {
+        self.mtu_bounds = bounds;
+        self
+    }
+
+    /// Set the application id for the processor.
+    /// Topics of virtual nodes, including service names, are scoped by the application id,
+    /// so that applications sharing a ring never read or overwrite each other's data.
+    pub fn app_id(mut self, id: impl Into<String>) -> Self {
+        self.app_id = Some(id.into());
+        self
+    }
+
+    /// Set the storage of contact book for the processor.
+    /// With a contact book, destinations can be given by contact names instead of dids.
+    pub fn contact_book(mut self, storage: PersistenceStorage) -> Self {
+        self.contact_book = Some(storage);
+        self
+    }
+
+    /// Set the storage of peer book for the processor, which should not be shared with others.
+    /// With a peer book, peers are persisted to be reconnected by priority, see
+    /// [Processor::reconnect_peers].
+    pub fn peer_book(mut self, storage: PersistenceStorage) -> Self {
+        self.peer_book = Some(storage);
+        self
+    }
+
+    /// Set defaults of timing and buffering settings by a [NetworkProfile], see
+    /// [SwarmBuilder::network_profile]. The profile only fills settings which are not given
+    /// by other methods of the builder. The stabilization interval of the profile replaces
+    /// the one of [ProcessorConfig], unless it's given by
+    /// [ProcessorBuilder::stabilize_interval].
+    pub fn network_profile(mut self, profile: NetworkProfile) -> Self {
+        self.network_profile = Some(profile);
+        self
+    }
+
+    /// Set the interval of stabilization in seconds, which takes precedence over the one of
+    /// [NetworkProfile] and [ProcessorConfig].
+    pub fn stabilize_interval(mut self, secs: usize) -> Self {
+        self.stabilize_interval = Some(secs);
+        self
+    }
+
+    /// Interval of stabilization, given explicitly, by the profile, or by [ProcessorConfig]
+    /// in order.
+    fn stabilize_interval_or_profile(&self) -> usize {
+        self.stabilize_interval
+            .or_else(|| self.network_profile.map(|p| p.stabilize_interval()))
+            .unwrap_or(self.stabilize_timeout)
+    }
+
+    /// Set the max time in milliseconds for a sending to hand off a message to transport,
+    /// see [SwarmBuilder::send_timeout].
+    pub fn send_timeout(mut self, ms: u64) -> Self {
+        self.send_timeout_ms = Some(ms);
+        self
+    }
+
+    /// Set the relay selector for the processor, see [SwarmBuilder::relay_selector].
+    pub fn relay_selector(mut self, selector: RelaySelectorImpl) -> Self {
+        self.relay_selector = Some(selector);
+        self
+    }
+
+    /// Send payloads in compact encoding, see [SwarmBuilder::compact_payload].
+    pub fn compact_payload(mut self) -> Self {
+        self.compact_payload = true;
+        self
+    }
+
+    /// Only handle messages from members of a closed network with seeds,
+    /// see [SwarmBuilder::closed_network].
+    pub fn closed_network(mut self, seeds: Vec<Did>) -> Self {
+        self.closed_network = Some(seeds);
+        self
+    }
+
+    /// Rate-limit storing virtual nodes by origin, see [SwarmBuilder::store_limit].
+    pub fn store_limit(mut self, config: StoreLimitConfig) -> Self {
+        self.store_limit = Some(config);
+        self
+    }
+
+    /// Run the processor without DHT participation, see [SwarmBuilder::no_dht].
+    pub fn no_dht(mut self) -> Self {
+        self.no_dht = true;
+        self
+    }
+
+    /// Accept only the types of message passing filter, see [SwarmBuilder::message_filter].
+    pub fn message_filter(mut self, filter: MessageFilter) -> Self {
+        self.message_filter = filter;
+        self
+    }
+
+    /// Tolerate messages expired in transit by types, see [SwarmBuilder::expiry_policy].
+    pub fn expiry_policy(mut self, policy: ExpiryPolicy) -> Self {
+        self.expiry_policy = policy;
+        self
+    }
+
+    /// Rotate the key of encryption to each peer by policy, see [SwarmBuilder::key_rotation].
+    pub fn key_rotation(mut self, rotation: KeyRotation) -> Self {
+        self.key_rotation = rotation;
+        self
+    }
+
+    /// Announce the processor with capabilities periodically, see [SwarmBuilder::announce].
+    pub fn announce(mut self, capabilities: Vec<String>) -> Self {
+        self.announcement = Some(capabilities);
+        self
+    }
+
+    /// Compress small payloads against a shared dictionary, see
+    /// [SwarmBuilder::compression_dictionary].
+    pub fn compression_dictionary(mut self, dictionary: CompressionDictionary) -> Self {
+        self.compression_dictionary = Some(dictionary);
+        self
+    }
+
+    /// Transform data of services sent to peers by built-in transforms in order, see
+    /// [SwarmBuilder::payload_pipeline]. Peers should be configured with the same ones.
+    pub fn payload_transforms(mut self, transforms: Vec<PayloadTransformConfig>) -> Self {
+        self.payload_transforms = transforms;
+        self
+    }
+
+    /// Max size of data decompressed by payload transforms, in bytes, which defaults to
+    /// [MAX_DECOMPRESSED_SIZE].
+    pub fn max_message_size(mut self, max_size: usize) -> Self {
+        self.max_message_size = Some(max_size);
+        self
+    }
+
     /// Build the [Processor].
     pub fn build(self) -> Result<Processor> {
+        let stabilize_interval = self.stabilize_interval_or_profile();
         self.session_sk
             .session()
             .verify_self()
             .map_err(|e| Error::VerifyError(e.to_string()))?;
 
+        if let Some(dispatch) = self.tracing {
+            if let Err(e) = tracing::dispatcher::set_global_default(dispatch) {
+                tracing::warn!("Failed to install tracing subscriber: {}", e);
+            }
+        }
+
         let storage = self
             .storage
             .expect("Please set storage by `storage()` method");
@@ -280,16 +647,111 @@ impl ProcessorBuilder {
             swarm_builder = swarm_builder.measure(measure);
         }
 
+        if let Some(metrics) = self.metrics {
+            swarm_builder = swarm_builder.metrics(metrics);
+        }
+
+        if let Some((capacity, policy)) = self.event_queue {
+            swarm_builder = swarm_builder.event_queue(capacity, policy);
+        }
+
+        if let Some(gate) = self.connection_gate {
+            swarm_builder = swarm_builder.connection_gate(gate);
+        }
+
+        if let Some(config) = self.ip_limit {
+            swarm_builder = swarm_builder.ip_limit(config);
+        }
+
+        if let Some(authorizer) = self.authorizer {
+            swarm_builder = swarm_builder.authorizer(authorizer);
+        }
+
+        if let Some(budget) = self.lookup_retry {
+            swarm_builder = swarm_builder.lookup_retry(budget);
+        }
+
+        if let Some(config) = self.drop_log {
+            swarm_builder = swarm_builder.drop_log(config);
+        }
+
+        if let Some(watermark) = self.buffer_watermark {
+            swarm_builder = swarm_builder.buffer_watermark(watermark);
+        }
+
+        if let Some(gathering) = self.ice_gathering {
+            swarm_builder = swarm_builder
+                .ice_gathering_mode(gathering.mode)
+                .ice_gathering_timeout(gathering.timeout_ms);
+        }
+
+        swarm_builder = swarm_builder.mtu_bounds(self.mtu_bounds);
+
+        if let Some(profile) = self.network_profile {
+            swarm_builder = swarm_builder.network_profile(profile);
+        }
+
+        if let Some(ms) = self.send_timeout_ms {
+            swarm_builder = swarm_builder.send_timeout(ms);
+        }
+
+        if let Some(selector) = self.relay_selector {
+            swarm_builder = swarm_builder.relay_selector(selector);
+        }
+
+        if self.compact_payload {
+            swarm_builder = swarm_builder.compact_payload();
+        }
+
+        if self.no_dht {
+            swarm_builder = swarm_builder.no_dht();
+        }
+
+        if let Some(seeds) = self.closed_network {
+            swarm_builder = swarm_builder.closed_network(seeds);
+        }
+
+        if let Some(config) = self.store_limit {
+            swarm_builder = swarm_builder.store_limit(config);
+        }
+
+        swarm_builder = swarm_builder
+            .message_filter(self.message_filter)
+            .expiry_policy(self.expiry_policy)
+            .key_rotation(self.key_rotation);
+
+        if let Some(capabilities) = self.announcement {
+            swarm_builder = swarm_builder.announce(capabilities);
+        }
+
+        if let Some(dictionary) = self.compression_dictionary {
+            swarm_builder = swarm_builder.compression_dictionary(dictionary);
+        }
+
+        let pipeline = PayloadPipeline::from_config_with_max_size(
+            &self.payload_transforms,
+            self.max_message_size.unwrap_or(MAX_DECOMPRESSED_SIZE),
+        )?;
+        swarm_builder = swarm_builder.payload_pipeline(pipeline);
+
         if let Some(callback) = self.message_callback {
             swarm_builder = swarm_builder.message_callback(callback);
         }
 
         let swarm = Arc::new(swarm_builder.build());
-        let stabilization = Arc::new(Stabilization::new(swarm.clone(), self.stabilize_timeout));
+        let stabilization = Arc::new(Stabilization::new(swarm.clone(), stabilize_interval));
 
         Ok(Processor {
             swarm,
             stabilization,
+            secret_key: self.secret_key,
+            app_id: self.app_id,
+            contacts: self.contact_book.map(|s| Arc::new(ContactBook::new(s))),
+            peers: self.peer_book.map(|s| Arc::new(PeerBook::new(s))),
+            reachability: Arc::new(ReachabilityCache::default()),
+            drain: Drain::default(),
+            #[cfg(feature = "node")]
+            backend: Arc::new(RwLock::new(None)),
         })
     }
 }
@@ -297,6 +759,32 @@ impl ProcessorBuilder {
 #[cfg(feature = "node")]
 impl Metadata for Processor {}
 
+#[cfg(feature = "node")]
+impl Processor {
+    /// Serve messages of peers by backend, which is set as the callback of swarm. The backend
+    /// is kept for diagnostics, such as [Processor::self_test].
+    pub fn set_backend(&self, backend: Arc<Backend>) -> Result<()> {
+        self.swarm
+            .set_callback(backend.clone())
+            .map_err(Error::CoreError)?;
+        *self.backend.write().unwrap_or_else(|e| e.into_inner()) = Some(backend);
+        Ok(())
+    }
+
+    /// Run a self-test of the message path with peer, which sends a ping, a small message and
+    /// a chunked large message to be echoed, and dials `service` through a tunnel if it's
+    /// provided. See [Backend::self_test].
+    pub async fn self_test(&self, peer_did: Did, service: Option<&str>) -> Result<SelfTestReport> {
+        let backend = self
+            .backend
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+            .ok_or(Error::BackendNotSet)?;
+        Ok(backend.self_test(peer_did, service).await)
+    }
+}
+
 impl Processor {
     /// Listen processor message
     pub fn listen(&self) -> Join<impl Future, impl Future> {
@@ -311,11 +799,77 @@ impl Processor {
 }
 
 impl Processor {
+    /// Run one stabilization pass immediately, and return when it completes.
+    /// It waits for the running pass instead if there is one.
+    pub async fn stabilize_now(&self) -> Result<()> {
+        self.stabilization.trigger().await.map_err(Error::CoreError)
+    }
+
+    /// Stop stabilization for shutdown, the pass in progress is finished before returning.
+    /// See [Stabilization::stop].
+    pub async fn stop_stabilization(&self) {
+        self.stabilization.stop().await
+    }
+
+    /// Check if the node is alive and processing, by heartbeats of the listen loop and the
+    /// stabilization loop, and the state of transports. See [liveness](crate::liveness).
+    pub fn liveness(&self) -> Liveness {
+        // Connections still handshaking are neither responsive nor not.
+        let states: Vec<_> = self
+            .swarm
+            .get_connections()
+            .iter()
+            .map(|(_, conn)| conn.webrtc_connection_state())
+            .filter(|s| {
+                !matches!(
+                    s,
+                    WebrtcConnectionState::New | WebrtcConnectionState::Connecting
+                )
+            })
+            .collect();
+        let connected = states
+            .iter()
+            .filter(|s| **s == WebrtcConnectionState::Connected)
+            .count();
+        LivenessSignals {
+            listen: self.swarm.listen_pulse(),
+            stabilization: self.stabilization.pulse(),
+            stabilization_stopped: self.stabilization.is_stopped(),
+            stabilization_interval_ms: self.stabilization.get_timeout() as u64 * 1000,
+            event_queue_depth: self.swarm.event_queue_depth(),
+            connections: states.len(),
+            connected,
+        }
+        .evaluate()
+    }
+
+    /// Get the draining state, which should be shared with backend to reject new work.
+    pub fn drain_state(&self) -> Drain {
+        self.drain.clone()
+    }
+
+    /// Stop accepting new work, and wait for the work in flight to complete until `deadline`.
+    /// Returns false if work is still in flight when deadline passes.
+    /// Services are no longer registered while draining. Registrations on DHT can't be removed,
+    /// so they expire as their records are replaced by other nodes.
+    pub async fn drain(&self, deadline: std::time::Duration) -> bool {
+        self.drain.start();
+        self.drain.wait_idle(deadline).await
+    }
+
     /// Get current did
     pub fn did(&self) -> Did {
         self.swarm.did()
     }
 
+    /// Export the identity of node encrypted by password, to be backed up and imported by
+    /// [ProcessorBuilder::identity]. Fails unless the secret key of the account was given by
+    /// [ProcessorBuilder::secret_key], since a session alone expires.
+    pub fn export_identity(&self, password: &str) -> Result<EncryptedIdentity> {
+        let key = self.secret_key.ok_or(Error::SecretKeyNotConfigured)?;
+        EncryptedIdentity::encrypt(&key, password).map_err(Error::CoreError)
+    }
+
     /// Connect peer with remote rings-node jsonrpc server.
     /// * peer_url: the remote rings-node jsonrpc server url.
     pub async fn connect_peer_via_http(&self, peer_url: &str) -> Result<Peer> {
@@ -365,6 +919,8 @@ impl Processor {
             .accept_answer(answer_payload)
             .await
             .map_err(Error::AcceptAnswer)?;
+        self.remember_peer(did, ReconnectPriority::High, Some(peer_url))
+            .await;
 
         Ok(Peer::from((did, conn)))
     }
@@ -382,9 +938,68 @@ impl Processor {
                 .await
                 .map_err(|e| Error::ConnectError(rings_core::error::Error::Transport(e)))?;
         }
+        self.remember_peer(did, ReconnectPriority::Normal, None)
+            .await;
         Ok(Peer::from((did, conn)))
     }
 
+    /// Connect a peer by url of its jsonrpc server if provided, or by did otherwise.
+    async fn connect_peer(&self, did: Did, url: Option<&str>, wait_for_open: bool) -> Result<Peer> {
+        let Some(url) = url else {
+            return self.connect_with_did(did, wait_for_open).await;
+        };
+        let peer = self.connect_peer_via_http(url).await?;
+        if wait_for_open {
+            if let Some(conn) = self.swarm.get_connection(did) {
+                conn.webrtc_wait_for_data_channel_open()
+                    .await
+                    .map_err(|e| Error::ConnectError(rings_core::error::Error::Transport(e)))?;
+            }
+        }
+        Ok(peer)
+    }
+
+    /// Connect many peers concurrently, such as the peers of a bootstrap list, with at most
+    /// [CONNECT_MANY_CONCURRENCY] handshakes in flight. Duplicated dids are connected once.
+    /// The returned stream yields a [ConnectProgress] as each attempt completes, in order of
+    /// completion, and an unreachable peer never fails the attempts of others.
+    pub fn connect_many(
+        &self,
+        dids: impl IntoIterator<Item = Did>,
+        wait_for_open: bool,
+    ) -> impl Stream<Item = ConnectProgress> + '_ {
+        let mut dids = dids.into_iter().collect::<Vec<_>>();
+        let mut seen = std::collections::HashSet::new();
+        dids.retain(|did| seen.insert(*did));
+        self.connect_peers(
+            dids.into_iter().map(|did| (did, None)).collect(),
+            wait_for_open,
+        )
+    }
+
+    /// Connect peers concurrently like [Processor::connect_many], each by its url if provided,
+    /// see [Processor::connect_peer].
+    fn connect_peers(
+        &self,
+        peers: Vec<(Did, Option<String>)>,
+        wait_for_open: bool,
+    ) -> impl Stream<Item = ConnectProgress> + '_ {
+        let total = peers.len();
+        futures::stream::iter(peers)
+            .map(move |(did, url)| async move {
+                let result = self.connect_peer(did, url.as_deref(), wait_for_open).await;
+                (did, result)
+            })
+            .buffer_unordered(CONNECT_MANY_CONCURRENCY)
+            .enumerate()
+            .map(move |(i, (did, result))| ConnectProgress {
+                did,
+                result,
+                completed: i + 1,
+                total,
+            })
+    }
+
     /// List all peers.
     pub async fn list_peers(&self) -> Result<Vec<Peer>> {
         let conns = self.swarm.get_connections();
@@ -405,8 +1020,41 @@ impl Processor {
         Ok(Peer::from(&(did, conn)))
     }
 
+    /// Check whether did can be reached right now, without sending anything to it.
+    /// An open connection is checked first, then a connected peer which recently relayed
+    /// messages of did to this node, see [Swarm::observed_relay].
+    /// Results are cached for [REACHABILITY_CACHE_TTL_MS](crate::reachability::REACHABILITY_CACHE_TTL_MS).
+    pub fn reachability(&self, did: Did) -> Reachability {
+        if let Some(r) = self.reachability.get(did) {
+            return r;
+        }
+
+        let direct = self
+            .swarm
+            .get_connection(did)
+            .map(|c| c.webrtc_connection_state() == WebrtcConnectionState::Connected)
+            .unwrap_or(false);
+        let r = if direct {
+            Reachability::Direct
+        } else {
+            let via = self.swarm.observed_relay(did).filter(|via| {
+                self.swarm
+                    .get_connection(*via)
+                    .map(|c| c.webrtc_connection_state() == WebrtcConnectionState::Connected)
+                    .unwrap_or(false)
+            });
+            match via {
+                Some(via) => Reachability::Relay(via),
+                None => Reachability::Unreachable,
+            }
+        };
+        self.reachability.insert(did, r);
+        r
+    }
+
     /// Disconnect a peer with web3 did.
     pub async fn disconnect(&self, did: Did) -> Result<()> {
+        self.reachability.invalidate(did);
         self.swarm
             .disconnect(did)
             .await
@@ -432,15 +1080,8 @@ impl Processor {
             destination,
             msg,
         );
-        let destination = Did::from_str(destination).map_err(|_| Error::InvalidDid)?;
-
-        let mut new_msg = Vec::with_capacity(msg.len() + 4);
-        // chunked mark
-        new_msg.push(0);
-        new_msg.extend_from_slice(&[0u8; 3]);
-        new_msg.extend_from_slice(msg);
-
-        let msg = Message::custom(&new_msg).map_err(Error::SendMessage)?;
+        let destination = self.resolve_did(destination).await?;
+        let msg = Self::unchunked_custom_message(msg)?;
 
         let uuid = self
             .swarm
@@ -450,6 +1091,29 @@ impl Processor {
         Ok(uuid)
     }
 
+    /// Send custom message to a did with hops traced, see [MessageRelay::trace](rings_core::message::MessageRelay::trace).
+    /// Receivers get the trace from the relay of payload, with the time each hop sent it.
+    pub async fn send_traced_message(&self, destination: &str, msg: &[u8]) -> Result<uuid::Uuid> {
+        tracing::info!("send_traced_message, destination: {}", destination);
+        let destination = self.resolve_did(destination).await?;
+        let msg = Self::unchunked_custom_message(msg)?;
+
+        self.swarm
+            .send_traced_message(msg, destination)
+            .await
+            .map_err(Error::SendMessage)
+    }
+
+    fn unchunked_custom_message(msg: &[u8]) -> Result<Message> {
+        let mut new_msg = Vec::with_capacity(msg.len() + 4);
+        // chunked mark
+        new_msg.push(0);
+        new_msg.extend_from_slice(&[0u8; 3]);
+        new_msg.extend_from_slice(msg);
+
+        Message::custom(&new_msg).map_err(Error::SendMessage)
+    }
+
     /// send http request message to node
     /// - destination: did of destination
     /// - url: ipfs url
@@ -476,10 +1140,12 @@ impl Processor {
             url.to_string(),
             timeout,
         );
-        let msg: BackendMessage = BackendMessage::try_from((
-            MessageType::HttpRequest,
-            &HttpRequest::new(name, method, url, timeout, headers, body),
-        ))?;
+        let request = HttpRequest::new(name, method, url, timeout, headers, body);
+        // Only the browser client decodes responses compressed by content.
+        #[cfg(feature = "node")]
+        let request = http_compression::strip_compression_header(request);
+        let msg: BackendMessage = BackendMessage::try_from((MessageType::HttpRequest, &request))?
+            .with_service(&request.name);
         let msg: Vec<u8> = msg.into();
 
         self.send_message(destination, &msg).await
@@ -528,6 +1194,188 @@ impl Processor {
         self.send_message(destination, &msg[..]).await
     }
 
+    /// send multipart message, it will be chunked if larger than the MTU of destination
+    /// - destination: did of destination
+    /// - msg: multipart message
+    pub async fn send_multipart_message(
+        &self,
+        destination: &str,
+        msg: &MultipartMessage,
+    ) -> Result<Vec<uuid::Uuid>> {
+        tracing::info!(
+            "send_multipart_message, destination: {}, parts: {}",
+            destination,
+            msg.parts().len(),
+        );
+        let msg = BackendMessage::try_from(msg)?;
+        self.send_backend_message(destination, msg, ChunkPacing::default())
+            .await
+    }
+
+    /// send binary data labeled with a MIME type,
+    /// it will be chunked if larger than the MTU of destination
+    /// - destination: did of destination
+    /// - payload: typed payload, see [TypedPayload::new]
+    pub async fn send_typed_message(
+        &self,
+        destination: &str,
+        payload: &TypedPayload,
+    ) -> Result<Vec<uuid::Uuid>> {
+        tracing::info!(
+            "send_typed_message, destination: {}, content_type: {}",
+            destination,
+            payload.content_type(),
+        );
+        let msg = BackendMessage::try_from(payload)?;
+        self.send_backend_message(destination, msg, ChunkPacing::default())
+            .await
+    }
+
+    /// send backend message, it will be chunked if larger than the MTU negotiated with the
+    /// next hop towards destination, see [Swarm::next_hop_mtu], and the chunks are sent
+    /// by that hop with `pacing`.
+    /// - destination: did of destination
+    /// - msg: backend message
+    /// - pacing: pacing of chunks, [ChunkPacing::Unpaced] by default
+    pub async fn send_backend_message(
+        &self,
+        destination: &str,
+        msg: BackendMessage,
+        pacing: ChunkPacing,
+    ) -> Result<Vec<uuid::Uuid>> {
+        tracing::info!(
+            "send_backend_message, destination: {}, pacing: {:?}",
+            destination,
+            pacing,
+        );
+        let destination = self.resolve_did(destination).await?;
+        let metrics = self.swarm.metrics();
+        let mut pacer = ChunkPacer::new(pacing);
+
+        let (next_hop, mtu) = self
+            .swarm
+            .next_hop_mtu(destination)
+            .map_err(Error::SendMessage)?;
+        let mut uuids = vec![];
+        for data in encode_custom_messages_for(&self.swarm, destination, msg, mtu)? {
+            let delay = pacer.pace(data.len()).await;
+            metrics.record_histogram(CHUNK_PACING_DELAY_MS, delay as f64);
+
+            let msg = Message::custom(&data).map_err(Error::SendMessage)?;
+            let result = self
+                .swarm
+                .send_message_by_hop(msg, destination, next_hop)
+                .await;
+            self.swarm
+                .record_chunk_sent(next_hop, data.len(), result.is_ok());
+            let uuid = result.map_err(Error::SendMessage)?;
+            metrics.increment_counter(CHUNK_SENT, 1);
+            uuids.push(uuid);
+        }
+        Ok(uuids)
+    }
+
+    fn peer_book(&self) -> Result<&PeerBook> {
+        self.peers.as_deref().ok_or(Error::PeerBookNotConfigured)
+    }
+
+    /// Remember a peer connected if a peer book is configured, see [PeerBook::remember].
+    /// Failing to persist it never fails connecting.
+    async fn remember_peer(&self, did: Did, priority: ReconnectPriority, url: Option<&str>) {
+        let Some(book) = self.peers.as_deref() else {
+            return;
+        };
+        if let Err(e) = book.remember(did, priority, url).await {
+            tracing::warn!("Failed on remembering peer {did}: {e}");
+        }
+    }
+
+    /// Persist a peer to be reconnected with priority, or update its priority.
+    pub async fn save_peer(&self, did: Did, priority: ReconnectPriority) -> Result<()> {
+        self.peer_book()?.add(did, priority).await
+    }
+
+    /// Stop reconnecting a persisted peer.
+    pub async fn forget_peer(&self, did: Did) -> Result<()> {
+        self.peer_book()?.remove(did).await
+    }
+
+    /// Reconnect persisted peers and pinned peers, such as after a restart or a network change.
+    /// Peers are reconnected in tiers, pinned ones first, then by [ReconnectPriority] from the
+    /// highest, so that each tier has the whole budget of [CONNECT_MANY_CONCURRENCY] handshakes
+    /// before the next one starts. Peers with urls are reconnected by them, so that they are
+    /// reached before any connection. Returns the result of every attempt.
+    pub async fn reconnect_peers(&self, wait_for_open: bool) -> Result<Vec<ConnectProgress>> {
+        let entries = self.peer_book()?.list().await?;
+        let urls: HashMap<Did, String> = entries
+            .iter()
+            .filter_map(|e| Some((e.did, e.url.clone()?)))
+            .collect();
+        let mut results = vec![];
+        for tier in PeerBook::reconnect_tiers(&entries, &self.swarm.pinned_peers()) {
+            let tier = tier
+                .into_iter()
+                .filter(|did| self.swarm.get_connection(*did).is_none())
+                .map(|did| (did, urls.get(&did).cloned()))
+                .collect();
+            results.extend(
+                self.connect_peers(tier, wait_for_open)
+                    .collect::<Vec<_>>()
+                    .await,
+            );
+        }
+        Ok(results)
+    }
+
+    fn contact_book(&self) -> Result<&ContactBook> {
+        self.contacts
+            .as_deref()
+            .ok_or(Error::ContactBookNotConfigured)
+    }
+
+    /// Add a contact to the contact book.
+    pub async fn add_contact(&self, name: &str, did: Did) -> Result<()> {
+        self.contact_book()?.add(name, did).await
+    }
+
+    /// Remove a contact from the contact book.
+    pub async fn remove_contact(&self, name: &str) -> Result<()> {
+        self.contact_book()?.remove(name).await
+    }
+
+    /// Get did of a contact.
+    pub async fn resolve_contact(&self, name: &str) -> Result<Did> {
+        self.contact_book()?
+            .resolve(name)
+            .await?
+            .ok_or_else(|| Error::ContactNotFound(name.to_string()))
+    }
+
+    /// Parse destination as a did, or resolve it as a contact name if it's not a did.
+    /// Contact names never look like dids, so a did is always taken literally.
+    pub async fn resolve_did(&self, destination: &str) -> Result<Did> {
+        if let Ok(did) = Did::from_str(destination) {
+            return Ok(did);
+        }
+        if self.contacts.is_none() || ContactBook::validate_name(destination).is_err() {
+            return Err(Error::InvalidDid);
+        }
+        self.resolve_contact(destination).await
+    }
+
+    /// Get topic scoped by the application id of processor.
+    pub fn scoped_topic(&self, topic: &str) -> String {
+        match &self.app_id {
+            Some(app_id) => vnode::VirtualNode::namespaced_topic(app_id, topic),
+            None => topic.to_string(),
+        }
+    }
+
+    /// Generate did of virtual node from topic, scoped by the application id of processor.
+    pub fn gen_vid(&self, topic: &str) -> Result<Did> {
+        vnode::VirtualNode::gen_did(&self.scoped_topic(topic)).map_err(Error::VNodeError)
+    }
+
     /// check local cache of dht
     pub async fn storage_check_cache(&self, did: Did) -> Option<vnode::VirtualNode> {
         self.swarm.storage_check_cache(did).await
@@ -540,6 +1388,43 @@ impl Processor {
             .map_err(Error::VNodeError)
     }
 
+    /// Open a stream to a connected peer for a large transfer. After a signed setup message,
+    /// data is sent in bare frames on the transport of peer, without the envelope of messages.
+    pub async fn open_stream(&self, did: Did) -> Result<StreamWriter> {
+        self.swarm
+            .open_stream(did)
+            .await
+            .map_err(Error::SendMessage)
+    }
+
+    /// Wait for the next stream opened by a connected peer, see [Processor::open_stream].
+    pub async fn accept_stream(&self) -> Option<StreamReader> {
+        self.swarm.accept_stream().await
+    }
+
+    /// Watch changes of virtual node on DHT. The watch is renewed in stabilization,
+    /// and ends when the returned stream is dropped.
+    pub async fn watch_vnode(&self, did: Did) -> Result<impl Stream<Item = vnode::VNodeChanged>> {
+        let changes = self.swarm.dht().vnode_subscriptions.subscribe(did);
+        self.swarm
+            .storage_watch(did)
+            .await
+            .map_err(Error::VNodeError)?;
+        Ok(changes)
+    }
+
+    /// search all replicas of virtual node on DHT
+    pub async fn storage_search_replicas(&self, did: Did) -> Result<()> {
+        <Swarm as ChordStorageInterface<DATA_REDUNDANT>>::storage_search_replicas(&self.swarm, did)
+            .await
+            .map_err(Error::VNodeError)
+    }
+
+    /// check replicas of virtual node collected by `storage_search_replicas`
+    pub async fn storage_check_replicas(&self, did: Did) -> Vec<vnode::VNodeReplica> {
+        self.swarm.storage_check_replicas(did).await
+    }
+
     /// store virtual node on DHT
     pub async fn storage_store(&self, vnode: vnode::VirtualNode) -> Result<()> {
         <Swarm as ChordStorageInterface<DATA_REDUNDANT>>::storage_store(&self.swarm, vnode)
@@ -551,15 +1436,18 @@ impl Processor {
     pub async fn storage_append_data(&self, topic: &str, data: Encoded) -> Result<()> {
         <Swarm as ChordStorageInterface<DATA_REDUNDANT>>::storage_append_data(
             &self.swarm,
-            topic,
+            &self.scoped_topic(topic),
             data,
         )
         .await
         .map_err(Error::VNodeError)
     }
 
-    /// register service
+    /// register service, fails with [Error::Draining] while draining.
     pub async fn register_service(&self, name: &str) -> Result<()> {
+        if self.drain.is_draining() {
+            return Err(Error::Draining);
+        }
         let encoded_did = self
             .did()
             .to_string()
@@ -567,13 +1455,125 @@ impl Processor {
             .map_err(Error::ServiceRegisterError)?;
         <Swarm as ChordStorageInterface<DATA_REDUNDANT>>::storage_touch_data(
             &self.swarm,
-            name,
+            &self.scoped_topic(name),
             encoded_did,
         )
         .await
         .map_err(Error::ServiceRegisterError)
     }
 
+    /// Nodes learned from announcements gossiped through the network, the freshest first.
+    /// They may not be connected, see [SwarmBuilder::announce].
+    pub fn known_nodes(&self) -> Vec<KnownNode> {
+        self.swarm.known_nodes()
+    }
+
+    /// Snapshot of the view of DHT of this node, to be compared with
+    /// [Processor::remote_routing] of other nodes.
+    pub fn local_routing(&self) -> RoutingSnapshot {
+        self.swarm.routing_snapshot()
+    }
+
+    /// Ask a node for its view of DHT, which is disclosed only if this node is authorized
+    /// by the authorizer of that node, see [RoutingSnapshot::disagreements].
+    pub async fn remote_routing(&self, did: Did) -> Result<RoutingSnapshot> {
+        self.swarm
+            .query_routing(did, ROUTING_QUERY_TIMEOUT_MS)
+            .await
+            .map_err(Error::CoreError)
+    }
+
+    /// Gather the layout of the ring of DHT, for rendering a ring diagram. Starting from this
+    /// node, the nodes known from each view are queried by [Processor::remote_routing], at most
+    /// [RING_LAYOUT_MAX_NODES] of them. Nodes not answering, such as unreachable ones or ones
+    /// denying the query, are gaps of the layout, see [RingSegment].
+    pub async fn ring_layout(&self) -> Vec<RingSegment> {
+        let local = self
+            .swarm
+            .routing_snapshot()
+            .count_vnodes(&self.swarm.dht())
+            .await;
+        let mut seen = std::collections::HashSet::from([self.did()]);
+        let mut frontier = RingSegment::known_nodes(&local);
+        let mut snapshots = vec![local];
+        let mut unreachable = vec![];
+
+        while !frontier.is_empty() && seen.len() < RING_LAYOUT_MAX_NODES {
+            frontier.retain(|did| !seen.contains(did));
+            frontier.truncate(RING_LAYOUT_MAX_NODES - seen.len());
+            seen.extend(frontier.iter().copied());
+            let results = futures::stream::iter(std::mem::take(&mut frontier))
+                .map(|did| async move { (did, self.remote_routing(did).await) })
+                .buffer_unordered(RING_LAYOUT_CONCURRENCY)
+                .collect::<Vec<_>>()
+                .await;
+            for (did, result) in results {
+                match result {
+                    Ok(snapshot) => {
+                        frontier.extend(RingSegment::known_nodes(&snapshot));
+                        snapshots.push(snapshot);
+                    }
+                    Err(e) => {
+                        tracing::debug!("Leave a gap of ring layout at {did}: {e:?}");
+                        unreachable.push(did);
+                    }
+                }
+            }
+            frontier.sort();
+            frontier.dedup();
+        }
+        RingSegment::layout(&snapshots, &unreachable)
+    }
+
+    /// Collect a [DiagnosticsBundle] for bug reports, with private data redacted.
+    /// Tunnels are owned by backend, see [DiagnosticsBundle::with_tunnels].
+    pub async fn diagnostics_bundle(&self) -> DiagnosticsBundle {
+        self.diagnostics_bundle_with(DiagnosticsDetail::Redacted)
+            .await
+    }
+
+    /// Collect a [DiagnosticsBundle] for bug reports with given detail.
+    pub async fn diagnostics_bundle_with(&self, detail: DiagnosticsDetail) -> DiagnosticsBundle {
+        let swarm = &self.swarm;
+        let dht = swarm.dht();
+        let peers = swarm
+            .get_connections()
+            .into_iter()
+            .map(|(did, conn)| PeerDiagnostics {
+                did: did.to_string(),
+                state: format!("{:?}", conn.webrtc_connection_state()),
+                score: swarm.peer_score(did),
+                mtu: swarm.peer_mtu(did),
+                traffic: swarm.peer_traffic(did),
+                pinned: swarm.is_pinned_peer(did),
+                trusted: swarm.is_trusted_transport(did),
+                timing: conn.connection_timing(),
+            })
+            .collect();
+        let storage = StorageDiagnostics::new(
+            StorageInspect::inspect_persistence_storage(&dht.storage).await,
+            StorageInspect::inspect_mem_storage(&dht.cache),
+            detail,
+        );
+
+        DiagnosticsBundle {
+            created_at_ms: get_epoch_ms(),
+            version: crate::util::build_version(),
+            config: ConfigSummary {
+                did: self.did().to_string(),
+                app_id: self.app_id.clone(),
+                dht_enabled: swarm.dht_enabled(),
+                converged: swarm.is_converged(),
+                stabilize_timeout: self.stabilization.get_timeout(),
+            },
+            dht: DHTInspect::inspect(&dht),
+            peers,
+            drops: swarm.drop_counts(),
+            tunnels: vec![],
+            storage,
+        }
+    }
+
     /// get node info
     pub async fn get_node_info(&self) -> Result<response::NodeInfo> {
         Ok(response::NodeInfo {
@@ -621,6 +1621,21 @@ impl Peer {
     }
 }
 
+/// Max number of handshakes in flight of [Processor::connect_many].
+pub const CONNECT_MANY_CONCURRENCY: usize = 8;
+
+/// Progress of [Processor::connect_many], with the result of an attempt just completed.
+pub struct ConnectProgress {
+    /// did of the peer.
+    pub did: Did,
+    /// result of connecting the peer.
+    pub result: Result<Peer>,
+    /// number of attempts completed, including this one.
+    pub completed: usize,
+    /// number of peers to connect.
+    pub total: usize,
+}
+
 /// unpack custom message to text
 pub fn unpack_text_message(msg: &CustomMessage) -> Result<String> {
     let (left, right) = msg.0.split_at(4);
@@ -640,6 +1655,7 @@ mod test {
     use super::*;
     use crate::prelude::*;
     use crate::tests::native::prepare_processor;
+    use crate::tests::native::prepare_processor_with;
 
     #[tokio::test]
     async fn test_processor_create_offer() {
@@ -652,6 +1668,191 @@ mod test {
         tokio::fs::remove_dir_all(path).await.unwrap();
     }
 
+    #[tokio::test]
+    async fn test_stabilize_interval_precedence() {
+        // Without a profile, the interval is the one of config.
+        let (processor, path) = prepare_processor(None).await;
+        assert_eq!(processor.stabilization.get_timeout(), 200);
+        tokio::fs::remove_dir_all(path).await.unwrap();
+
+        // A profile replaces the interval of config.
+        for profile in [
+            NetworkProfile::Balanced,
+            NetworkProfile::LowLatency,
+            NetworkProfile::Mobile,
+            NetworkProfile::HighThroughput,
+            NetworkProfile::Lossy,
+        ] {
+            let (processor, path) =
+                prepare_processor_with(None, |b| b.network_profile(profile)).await;
+            assert_eq!(
+                processor.stabilization.get_timeout(),
+                profile.stabilize_interval()
+            );
+            tokio::fs::remove_dir_all(path).await.unwrap();
+        }
+
+        // An explicit interval wins, whether it's given before or after the profile.
+        let (processor, path) = prepare_processor_with(None, |b| {
+            b.stabilize_interval(7)
+                .network_profile(NetworkProfile::Mobile)
+        })
+        .await;
+        assert_eq!(processor.stabilization.get_timeout(), 7);
+        tokio::fs::remove_dir_all(path).await.unwrap();
+
+        let (processor, path) = prepare_processor_with(None, |b| {
+            b.network_profile(NetworkProfile::Mobile)
+                .stabilize_interval(7)
+        })
+        .await;
+        assert_eq!(processor.stabilization.get_timeout(), 7);
+        tokio::fs::remove_dir_all(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_processor_reachability() {
+        let peer_did = SecretKey::random().address().into();
+        let (processor, path) = prepare_processor(None).await;
+        assert_eq!(processor.reachability(peer_did), Reachability::Unreachable);
+
+        // A connection being negotiated doesn't make peer reachable.
+        processor.reachability.invalidate(peer_did);
+        processor.swarm.create_offer(peer_did).await.unwrap();
+        assert_eq!(processor.reachability(peer_did), Reachability::Unreachable);
+        tokio::fs::remove_dir_all(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_processor_diagnostics_bundle() {
+        let (processor, path) = prepare_processor(None).await;
+        let vnode = vnode::VirtualNode::try_from("private".to_string()).unwrap();
+        processor
+            .swarm
+            .dht()
+            .storage
+            .put(&vnode.did, &vnode)
+            .await
+            .unwrap();
+
+        let bundle = processor.diagnostics_bundle().await.with_tunnels(["t1"]);
+        assert_eq!(bundle.config.did, processor.did().to_string());
+        assert_eq!(bundle.tunnels, vec!["t1".to_string()]);
+        assert_eq!(bundle.storage.persistence_items, 1);
+        // Stored data and keys are redacted by default.
+        let json = serde_json::to_string(&bundle).unwrap();
+        assert!(!json.contains("persistence_storage"));
+        let sk = processor.swarm.session_sk().dump().unwrap();
+        assert!(!json.contains(&sk));
+
+        let full = processor
+            .diagnostics_bundle_with(DiagnosticsDetail::Full)
+            .await;
+        assert_eq!(full.storage.persistence_storage.unwrap().items.len(), 1);
+        tokio::fs::remove_dir_all(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_processor_connect_many() {
+        let (processor, path) = prepare_processor(None).await;
+        let dids: Vec<Did> = (0..3)
+            .map(|_| SecretKey::random().address().into())
+            .collect();
+
+        let progress = processor
+            .connect_many([dids[0], dids[1], dids[0], dids[2]], false)
+            .collect::<Vec<_>>()
+            .await;
+
+        // Each peer is reported once, and progress counts up to the number of peers.
+        assert_eq!(progress.len(), 3);
+        let mut reported = progress.iter().map(|p| p.did).collect::<Vec<_>>();
+        reported.sort();
+        let mut expected = dids.clone();
+        expected.sort();
+        assert_eq!(reported, expected);
+        for (i, p) in progress.iter().enumerate() {
+            assert_eq!(p.completed, i + 1);
+            assert_eq!(p.total, 3);
+            // Without any connected peer, offers cannot be relayed to the peers.
+            assert!(p.result.is_err());
+        }
+        tokio::fs::remove_dir_all(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_processor_identity() {
+        let (p1, path1) = prepare_processor(None).await;
+        assert!(matches!(
+            p1.export_identity("secret"),
+            Err(Error::SecretKeyNotConfigured)
+        ));
+
+        let key = SecretKey::random();
+        let (p2, path2) = prepare_processor_with(None, |b| b.secret_key(key).unwrap()).await;
+        assert_eq!(p2.did(), key.address().into());
+        let identity = p2.export_identity("secret").unwrap().to_bytes().unwrap();
+
+        let (p3, path3) =
+            prepare_processor_with(None, |b| b.identity(&identity, "secret").unwrap()).await;
+        assert_eq!(p3.did(), p2.did());
+        assert!(p3.export_identity("secret").is_ok());
+
+        for path in [path1, path2, path3] {
+            tokio::fs::remove_dir_all(path).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_processor_peer_book() {
+        let book_path = PersistenceStorage::random_path("./tmp");
+        let book = PersistenceStorage::new_with_path(book_path.as_str())
+            .await
+            .unwrap();
+        let (p1, path1) = prepare_processor_with(None, |b| b.peer_book(book)).await;
+        let (p2, path2) = prepare_processor(None).await;
+        let relay: Did = SecretKey::random().address().into();
+        p1.save_peer(relay, ReconnectPriority::High).await.unwrap();
+
+        // A peer failed to connect is not remembered.
+        let unreachable: Did = SecretKey::random().address().into();
+        assert!(p1.connect_with_did(unreachable, false).await.is_err());
+        assert!(p1
+            .peers
+            .as_ref()
+            .unwrap()
+            .get(unreachable)
+            .await
+            .unwrap()
+            .is_none());
+
+        let (conn, offer) = p1.swarm.create_offer(p2.did()).await.unwrap();
+        let (_, answer) = p2.swarm.answer_offer(offer).await.unwrap();
+        p1.swarm.accept_answer(answer).await.unwrap();
+        conn.webrtc_wait_for_data_channel_open().await.unwrap();
+
+        // A peer connected is remembered, without lowering priority of others.
+        p1.connect_with_did(p2.did(), true).await.unwrap();
+        let mut entries = p1.peers.as_ref().unwrap().list().await.unwrap();
+        entries.sort_by_key(|e| e.priority);
+        let entries: Vec<_> = entries.iter().map(|e| (e.did, e.priority)).collect();
+        assert_eq!(entries, vec![
+            (p2.did(), ReconnectPriority::Normal),
+            (relay, ReconnectPriority::High),
+        ]);
+
+        // Peers remembered are reconnected by priority, connected ones are skipped.
+        let progress = p1.reconnect_peers(false).await.unwrap();
+        assert!(progress.iter().all(|p| p.did != p2.did()));
+        p1.disconnect(p2.did()).await.unwrap();
+        let progress = p1.reconnect_peers(false).await.unwrap();
+        assert_eq!(progress.last().unwrap().did, p2.did());
+
+        tokio::fs::remove_dir_all(path1).await.unwrap();
+        tokio::fs::remove_dir_all(path2).await.unwrap();
+        tokio::fs::remove_dir_all(book_path).await.unwrap();
+    }
+
     struct MsgCallbackStruct {
         msgs: Arc<Mutex<Vec<String>>>,
     }
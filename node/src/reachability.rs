@@ -0,0 +1,139 @@
+//! This module provide [Reachability], which tells whether a Did can be reached right now,
+//! and a short-lived cache of it.
+//! Reachability is derived from local state only, no application data is sent.
+#![warn(missing_docs)]
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::prelude::rings_core::dht::Did;
+use crate::prelude::rings_core::prelude::dashmap::DashMap;
+use crate::prelude::rings_core::utils::get_epoch_ms;
+
+/// How long in milliseconds a reachability result is cached.
+pub const REACHABILITY_CACHE_TTL_MS: u128 = 1000;
+/// Max number of dids whose reachability is cached.
+pub const REACHABILITY_CACHE_MAX_ENTRIES: usize = 1024;
+
+/// Whether and how a Did can be reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Reachability {
+    /// There is an open connection to the Did.
+    Direct,
+    /// Messages of the Did were recently relayed to this node by the connected peer.
+    /// It only means the peer forwarded its traffic, it's not a guarantee of delivery.
+    Relay(Did),
+    /// Neither a connection nor a route to the Did exists.
+    Unreachable,
+}
+
+/// ReachabilityCache keeps results of reachability for a short time,
+/// so that frequent queries, such as from UI, won't recompute or probe every time.
+#[derive(Debug)]
+pub struct ReachabilityCache {
+    ttl_ms: u128,
+    entries: DashMap<Did, (Reachability, u128)>,
+}
+
+impl Default for ReachabilityCache {
+    fn default() -> Self {
+        Self::new(REACHABILITY_CACHE_TTL_MS)
+    }
+}
+
+impl ReachabilityCache {
+    /// Create a cache keeping results for `ttl_ms` milliseconds.
+    pub fn new(ttl_ms: u128) -> Self {
+        Self {
+            ttl_ms,
+            entries: DashMap::new(),
+        }
+    }
+
+    /// Get cached reachability of did if it's not expired.
+    pub fn get(&self, did: Did) -> Option<Reachability> {
+        let now = get_epoch_ms();
+        let entry = self.entries.get(&did)?;
+        let (reachability, at) = *entry;
+        drop(entry);
+        if now.saturating_sub(at) < self.ttl_ms {
+            return Some(reachability);
+        }
+        self.entries.remove_if(&did, |_, (_, t)| *t == at);
+        None
+    }
+
+    /// Cache reachability of did. Once [REACHABILITY_CACHE_MAX_ENTRIES] dids are cached,
+    /// expired results are dropped, then the oldest one if none is expired.
+    pub fn insert(&self, did: Did, reachability: Reachability) {
+        let now = get_epoch_ms();
+        if self.entries.len() >= REACHABILITY_CACHE_MAX_ENTRIES && !self.entries.contains_key(&did)
+        {
+            self.entries
+                .retain(|_, (_, at)| now.saturating_sub(*at) < self.ttl_ms);
+            if self.entries.len() >= REACHABILITY_CACHE_MAX_ENTRIES {
+                let oldest = self.entries.iter().min_by_key(|e| e.1).map(|e| *e.key());
+                if let Some(oldest) = oldest {
+                    self.entries.remove(&oldest);
+                }
+            }
+        }
+        self.entries.insert(did, (reachability, now));
+    }
+
+    /// Number of dids cached, including the expired ones not dropped yet.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if no did is cached.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Forget cached reachability of did, such as when its connection changed.
+    pub fn invalidate(&self, did: Did) {
+        self.entries.remove(&did);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::SecretKey;
+
+    #[test]
+    fn test_reachability_cache() {
+        let did: Did = SecretKey::random().address().into();
+        let via: Did = SecretKey::random().address().into();
+
+        let cache = ReachabilityCache::default();
+        assert_eq!(cache.get(did), None);
+        cache.insert(did, Reachability::Relay(via));
+        assert_eq!(cache.get(did), Some(Reachability::Relay(via)));
+        cache.invalidate(did);
+        assert_eq!(cache.get(did), None);
+
+        let cache = ReachabilityCache::new(0);
+        cache.insert(did, Reachability::Direct);
+        assert_eq!(cache.get(did), None);
+    }
+
+    #[test]
+    fn test_reachability_cache_is_bounded() {
+        let cache = ReachabilityCache::default();
+        let first: Did = SecretKey::random().address().into();
+        cache.insert(first, Reachability::Direct);
+        for _ in 1..REACHABILITY_CACHE_MAX_ENTRIES {
+            cache.insert(
+                SecretKey::random().address().into(),
+                Reachability::Unreachable,
+            );
+        }
+        assert_eq!(cache.len(), REACHABILITY_CACHE_MAX_ENTRIES);
+
+        let did: Did = SecretKey::random().address().into();
+        cache.insert(did, Reachability::Direct);
+        assert_eq!(cache.len(), REACHABILITY_CACHE_MAX_ENTRIES);
+        assert_eq!(cache.get(did), Some(Reachability::Direct));
+    }
+}
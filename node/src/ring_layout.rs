@@ -0,0 +1,141 @@
+//! This module provide [RingSegment], the layout of the ring of DHT, for rendering a ring
+//! diagram, such as to spot nodes holding an unbalanced share of keys.
+//! The layout is gathered from the views of DHT of reachable nodes, see
+//! [Processor::ring_layout](crate::processor::Processor::ring_layout). Nodes known from the
+//! views of others but not answering are kept in the layout as gaps, rather than failing it.
+#![warn(missing_docs)]
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::prelude::rings_core::dht::Did;
+use crate::prelude::rings_core::message::handlers::routing::RoutingSnapshot;
+
+/// Max number of nodes gathered into a layout.
+pub const RING_LAYOUT_MAX_NODES: usize = 256;
+
+/// Max number of nodes queried concurrently when gathering a layout.
+pub const RING_LAYOUT_CONCURRENCY: usize = 8;
+
+/// A node on the ring, with the arc of key space it's responsible for.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RingSegment {
+    /// Did of node.
+    pub did: Did,
+    /// Position of node on the ring, as a fraction of the key space from 0.
+    pub position: f64,
+    /// Start of the arc of node, exclusive, which is the previous node on the ring.
+    /// The arc ends at the node, inclusive.
+    pub arc_start: Did,
+    /// Fraction of the key space covered by the arc.
+    pub arc_fraction: f64,
+    /// Number of virtual nodes held by node, None if unknown.
+    pub vnodes: Option<u64>,
+    /// Predecessor in the view of node, which differs from `arc_start` if views disagree,
+    /// None if unknown.
+    pub predecessor: Option<Did>,
+    /// Whether the node answered. A node not answering is a gap of the layout, whose arc is
+    /// inferred from its neighbours.
+    pub reachable: bool,
+}
+
+fn fraction(did: Did) -> f64 {
+    let mut high = [0u8; 8];
+    high.copy_from_slice(&did.as_bytes()[..8]);
+    u64::from_be_bytes(high) as f64 / 2f64.powi(64)
+}
+
+impl RingSegment {
+    /// Nodes known from a view of DHT, except the node itself.
+    pub fn known_nodes(snapshot: &RoutingSnapshot) -> Vec<Did> {
+        let mut dids: Vec<Did> = snapshot
+            .successors
+            .iter()
+            .chain(snapshot.predecessor.iter())
+            .chain(snapshot.finger.iter().flatten())
+            .copied()
+            .filter(|did| *did != snapshot.did)
+            .collect();
+        dids.sort();
+        dids.dedup();
+        dids
+    }
+
+    /// Lay out nodes on the ring by their views of DHT, with nodes known but unreachable as
+    /// gaps. Segments are ordered by position.
+    pub fn layout(snapshots: &[RoutingSnapshot], unreachable: &[Did]) -> Vec<RingSegment> {
+        let mut dids: Vec<Did> = snapshots
+            .iter()
+            .map(|s| s.did)
+            .chain(unreachable.iter().copied())
+            .collect();
+        dids.sort();
+        dids.dedup();
+
+        dids.iter()
+            .enumerate()
+            .map(|(i, did)| {
+                let arc_start = dids[(i + dids.len() - 1) % dids.len()];
+                let snapshot = snapshots.iter().find(|s| s.did == *did);
+                RingSegment {
+                    did: *did,
+                    position: fraction(*did),
+                    arc_start,
+                    // A single node is responsible for the whole ring.
+                    arc_fraction: if dids.len() == 1 {
+                        1.0
+                    } else {
+                        fraction(*did - arc_start)
+                    },
+                    vnodes: snapshot.and_then(|s| s.vnodes),
+                    predecessor: snapshot.and_then(|s| s.predecessor),
+                    reachable: snapshot.is_some(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn snapshot(did: Did, successor: Did, predecessor: Did, vnodes: u64) -> RoutingSnapshot {
+        RoutingSnapshot {
+            did,
+            successors: vec![successor],
+            predecessor: Some(predecessor),
+            finger: vec![None, Some(successor)],
+            created_at_ms: 0,
+            vnodes: Some(vnodes),
+        }
+    }
+
+    #[test]
+    fn test_ring_layout() {
+        let a = Did::from(0u32);
+        let b = Did::from_str("0x4000000000000000000000000000000000000000").unwrap();
+        let c = Did::from_str("0x8000000000000000000000000000000000000000").unwrap();
+        let snapshots = [snapshot(b, c, a, 3), snapshot(a, b, c, 1)];
+        assert_eq!(RingSegment::known_nodes(&snapshots[1]), vec![b, c]);
+
+        // c is known from the views of a and b, but doesn't answer.
+        let layout = RingSegment::layout(&snapshots, &[c]);
+        assert_eq!(layout.iter().map(|s| s.did).collect::<Vec<_>>(), vec![
+            a, b, c
+        ]);
+        assert_eq!(layout[0].arc_start, c);
+        assert_eq!(layout[0].arc_fraction, 0.5);
+        assert_eq!(layout[0].vnodes, Some(1));
+        assert_eq!(layout[1].position, 0.25);
+        assert_eq!(layout[1].arc_fraction, 0.25);
+        assert_eq!(layout[1].predecessor, Some(a));
+        assert!(!layout[2].reachable);
+        assert_eq!(layout[2].arc_start, b);
+        assert_eq!(layout[2].vnodes, None);
+
+        let layout = RingSegment::layout(&snapshots[..1], &[]);
+        assert_eq!(layout[0].arc_fraction, 1.0);
+    }
+}
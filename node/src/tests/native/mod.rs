@@ -1,5 +1,6 @@
 use crate::prelude::rings_core::ecc::SecretKey;
 use crate::prelude::rings_core::storage::PersistenceStorage;
+use crate::prelude::rings_core::swarm::PayloadTransformConfig;
 use crate::prelude::CallbackFn;
 use crate::prelude::SessionSk;
 use crate::processor::Processor;
@@ -7,6 +8,20 @@ use crate::processor::ProcessorBuilder;
 use crate::processor::ProcessorConfig;
 
 pub async fn prepare_processor(message_callback: Option<CallbackFn>) -> (Processor, String) {
+    prepare_processor_with_transforms(message_callback, vec![]).await
+}
+
+pub async fn prepare_processor_with_transforms(
+    message_callback: Option<CallbackFn>,
+    transforms: Vec<PayloadTransformConfig>,
+) -> (Processor, String) {
+    prepare_processor_with(message_callback, |b| b.payload_transforms(transforms)).await
+}
+
+pub async fn prepare_processor_with(
+    message_callback: Option<CallbackFn>,
+    build: impl FnOnce(ProcessorBuilder) -> ProcessorBuilder,
+) -> (Processor, String) {
     let key = SecretKey::random();
     let sm = SessionSk::new_with_seckey(&key).unwrap();
 
@@ -25,6 +40,7 @@ pub async fn prepare_processor(message_callback: Option<CallbackFn>) -> (Process
     let mut procssor_builder = ProcessorBuilder::from_serialized(&config)
         .unwrap()
         .storage(storage);
+    procssor_builder = build(procssor_builder);
 
     if let Some(callback) = message_callback {
         procssor_builder = procssor_builder.message_callback(callback);
@@ -0,0 +1,195 @@
+//! This module contains [BufferWatermark] and [BufferedAmountLow], which apply backpressure
+//! on the send side of a data channel according to its `bufferedAmount`.
+//!
+//! A sender pushing faster than the channel drains makes `bufferedAmount` grow, until memory
+//! is exhausted or sending fails. Instead, a sender waits for the `bufferedamountlow` event
+//! once sending would take `bufferedAmount` over the high watermark.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+use crate::error::Error;
+use crate::error::Result;
+use crate::notifier::Notifier;
+
+/// Default high watermark of buffered amount in bytes.
+pub const DEFAULT_BUFFER_HIGH_WATERMARK: usize = 4 * 1024 * 1024;
+/// Default low watermark of buffered amount in bytes.
+pub const DEFAULT_BUFFER_LOW_WATERMARK: usize = 1024 * 1024;
+
+/// Watermarks of buffered amount of a data channel.
+/// Sending waits once the buffered amount would exceed `high`,
+/// and resumes when the buffered amount drops to `low`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferWatermark {
+    /// High watermark in bytes.
+    pub high: usize,
+    /// Low watermark in bytes, which is the `bufferedAmountLowThreshold` of data channel.
+    pub low: usize,
+}
+
+impl BufferWatermark {
+    /// Create watermarks, `low` is capped by `high`.
+    pub fn new(high: usize, low: usize) -> Self {
+        Self {
+            high,
+            low: low.min(high),
+        }
+    }
+
+    /// Check if `len` bytes can be sent when `buffered` bytes are waiting in data channel.
+    /// A message larger than the gap between watermarks is sent once the buffer drops to `low`,
+    /// so that it is never blocked forever.
+    pub fn allows(&self, buffered: usize, len: usize) -> bool {
+        buffered <= self.low || buffered.saturating_add(len) <= self.high
+    }
+}
+
+impl Default for BufferWatermark {
+    fn default() -> Self {
+        Self::new(DEFAULT_BUFFER_HIGH_WATERMARK, DEFAULT_BUFFER_LOW_WATERMARK)
+    }
+}
+
+#[derive(Default)]
+struct BufferedAmountLowState {
+    closed: bool,
+    waiting: Option<Notifier>,
+}
+
+/// BufferedAmountLow wakes senders waiting on the `bufferedamountlow` event of a data channel.
+/// Unlike [Notifier], it can be notified repeatedly.
+#[derive(Clone, Default)]
+pub struct BufferedAmountLow(Arc<Mutex<BufferedAmountLowState>>);
+
+impl BufferedAmountLow {
+    fn lock(&self) -> MutexGuard<'_, BufferedAmountLowState> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Wake all waiting senders, called on `bufferedamountlow` event.
+    pub fn notify(&self) {
+        let waiting = self.lock().waiting.take();
+        if let Some(n) = waiting {
+            n.set_result(true)
+        }
+    }
+
+    /// Fail all waiting and further senders, called when data channel is closed.
+    pub fn close(&self) {
+        let waiting = {
+            let mut state = self.lock();
+            state.closed = true;
+            state.waiting.take()
+        };
+        if let Some(n) = waiting {
+            n.set_result(false)
+        }
+    }
+
+    fn listen(&self) -> Result<Notifier> {
+        let mut state = self.lock();
+        if state.closed {
+            return Err(Error::DataChannelClosed);
+        }
+        Ok(state.waiting.get_or_insert_with(Notifier::default).clone())
+    }
+
+    /// Wait until `len` bytes can be sent under the watermark.
+    /// The `buffered_amount` is queried after listening, so that an event in between is not missed.
+    pub async fn wait<F, Fut>(
+        &self,
+        watermark: BufferWatermark,
+        len: usize,
+        buffered_amount: F,
+    ) -> Result<()>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = usize>,
+    {
+        loop {
+            let notifier = self.listen()?;
+            if watermark.allows(buffered_amount().await, len) {
+                return Ok(());
+            }
+            notifier.await.map_err(|_| Error::DataChannelClosed)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+
+    #[test]
+    fn test_watermark_allows() {
+        let wm = BufferWatermark::new(100, 200);
+        assert_eq!(wm.low, 100);
+
+        let wm = BufferWatermark::new(100, 20);
+        assert!(wm.allows(0, 500));
+        assert!(wm.allows(20, 500));
+        assert!(wm.allows(50, 50));
+        assert!(!wm.allows(50, 51));
+    }
+
+    #[tokio::test]
+    async fn test_fast_sender_backpressure() {
+        let wm = BufferWatermark::new(1000, 200);
+        let buffered = Arc::new(AtomicUsize::new(0));
+        let low = BufferedAmountLow::default();
+
+        // Drain the channel slowly, and fire the event when crossing the low watermark.
+        let drainer = {
+            let buffered = buffered.clone();
+            let low = low.clone();
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+                    let prev = buffered
+                        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |v| {
+                            Some(v.saturating_sub(50))
+                        })
+                        .unwrap();
+                    let next = prev.saturating_sub(50);
+                    if prev > wm.low && next <= wm.low {
+                        low.notify();
+                    }
+                }
+            })
+        };
+
+        let mut max_buffered = 0;
+        for _ in 0..200 {
+            low.wait(wm, 100, || async { buffered.load(Ordering::SeqCst) })
+                .await
+                .unwrap();
+            let now = buffered.fetch_add(100, Ordering::SeqCst) + 100;
+            max_buffered = max_buffered.max(now);
+        }
+        assert!(max_buffered <= wm.high);
+
+        // Waiting senders fail once the channel is closed.
+        buffered.store(usize::MAX / 2, Ordering::SeqCst);
+        drainer.abort();
+        let waiting = {
+            let low = low.clone();
+            let buffered = buffered.clone();
+            tokio::spawn(async move {
+                low.wait(wm, 100, || async { buffered.load(Ordering::SeqCst) })
+                    .await
+            })
+        };
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        low.close();
+        assert!(matches!(
+            waiting.await.unwrap(),
+            Err(Error::DataChannelClosed)
+        ));
+    }
+}
@@ -5,6 +5,7 @@ use bytes::Bytes;
 use crate::core::callback::BoxedTransportCallback;
 use crate::core::transport::TransportMessage;
 use crate::core::transport::WebrtcConnectionState;
+use crate::framing::FrameNegotiation;
 use crate::notifier::Notifier;
 
 /// [InnerTransportCallback] wraps the [BoxedTransportCallback] with inner handling for a specific connection.
@@ -13,6 +14,7 @@ pub struct InnerTransportCallback {
     pub cid: String,
     callback: BoxedTransportCallback,
     data_channel_open_notifier: Notifier,
+    frames: FrameNegotiation,
 }
 
 impl InnerTransportCallback {
@@ -26,9 +28,15 @@ impl InnerTransportCallback {
             cid: cid.to_string(),
             callback,
             data_channel_open_notifier,
+            frames: FrameNegotiation::default(),
         }
     }
 
+    /// Get the negotiation of framing with peer, which connection should encode messages by.
+    pub fn frames(&self) -> FrameNegotiation {
+        self.frames.clone()
+    }
+
     /// Notify the data channel is open.
     pub fn on_data_channel_open(&self) {
         self.data_channel_open_notifier.set_result(true)
@@ -41,7 +49,18 @@ impl InnerTransportCallback {
 
     /// This method is invoked on a binary message arrival over the data channel of webrtc.
     pub async fn on_message(&self, msg: &Bytes) {
-        match bincode::deserialize(msg) {
+        let payload = match self.frames.decode(msg) {
+            Ok(Some(p)) => p,
+            Ok(None) => {
+                tracing::debug!("Peer of {} reads framed messages", self.cid);
+                return;
+            }
+            Err(e) => {
+                tracing::error!("Invalid DataChannelMessage frame from {}: {e}", self.cid);
+                return;
+            }
+        };
+        match bincode::deserialize(payload) {
             Ok(m) => self.handle_message(&m).await,
             Err(e) => {
                 tracing::error!("Deserialize DataChannelMessage failed: {e:?}");
@@ -60,6 +79,20 @@ impl InnerTransportCallback {
         }
     }
 
+    /// This method is invoked when a local ICE candidate is gathered.
+    pub async fn on_ice_candidate(&self, candidate: &str) {
+        if let Err(e) = self.callback.on_ice_candidate(&self.cid, candidate).await {
+            tracing::error!("Callback on_ice_candidate failed: {e:?}");
+        }
+    }
+
+    /// This method is invoked once the data channel is open, after handshake.
+    pub async fn on_handshake_complete(&self) {
+        if let Err(e) = self.callback.on_handshake_complete(&self.cid).await {
+            tracing::error!("Callback on_handshake_complete failed: {e:?}");
+        }
+    }
+
     async fn handle_message(&self, msg: &TransportMessage) {
         match msg {
             TransportMessage::Custom(bytes) => {
@@ -1,5 +1,6 @@
 //! This module contains the [ConnectionRef] struct.
 
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::sync::Weak;
 
@@ -12,6 +13,7 @@ use crate::core::transport::TransportMessage;
 use crate::core::transport::WebrtcConnectionState;
 use crate::error::Error;
 use crate::error::Result;
+use crate::timing::ConnectionTiming;
 
 /// The[ConnectionRef] is a weak reference to a connection and implements the `ConnectionInterface` trait.
 /// When the connection is dropped, it returns an error called [Error::ConnectionReleased].
@@ -86,6 +88,14 @@ where
         self.upgrade()?.webrtc_accept_answer(answer).await
     }
 
+    async fn webrtc_gathered_description(&self) -> Result<Option<Self::Sdp>> {
+        self.upgrade()?.webrtc_gathered_description().await
+    }
+
+    async fn webrtc_add_ice_candidate(&self, candidate: &str) -> Result<()> {
+        self.upgrade()?.webrtc_add_ice_candidate(candidate).await
+    }
+
     async fn webrtc_wait_for_data_channel_open(&self) -> Result<()> {
         self.upgrade()?.webrtc_wait_for_data_channel_open().await
     }
@@ -93,6 +103,16 @@ where
     async fn close(&self) -> Result<()> {
         self.upgrade()?.close().await
     }
+
+    fn connection_timing(&self) -> ConnectionTiming {
+        self.upgrade()
+            .map(|c| c.connection_timing())
+            .unwrap_or_default()
+    }
+
+    async fn webrtc_remote_address(&self) -> Option<SocketAddr> {
+        self.upgrade().ok()?.webrtc_remote_address().await
+    }
 }
 
 #[cfg(not(feature = "web-sys-webrtc"))]
@@ -134,6 +154,14 @@ where
         self.upgrade()?.webrtc_accept_answer(answer).await
     }
 
+    async fn webrtc_gathered_description(&self) -> Result<Option<Self::Sdp>> {
+        self.upgrade()?.webrtc_gathered_description().await
+    }
+
+    async fn webrtc_add_ice_candidate(&self, candidate: &str) -> Result<()> {
+        self.upgrade()?.webrtc_add_ice_candidate(candidate).await
+    }
+
     async fn webrtc_wait_for_data_channel_open(&self) -> Result<()> {
         self.upgrade()?.webrtc_wait_for_data_channel_open().await
     }
@@ -141,4 +169,14 @@ where
     async fn close(&self) -> Result<()> {
         self.upgrade()?.close().await
     }
+
+    fn connection_timing(&self) -> ConnectionTiming {
+        self.upgrade()
+            .map(|c| c.connection_timing())
+            .unwrap_or_default()
+    }
+
+    async fn webrtc_remote_address(&self) -> Option<SocketAddr> {
+        self.upgrade().ok()?.webrtc_remote_address().await
+    }
 }
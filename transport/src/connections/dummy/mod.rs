@@ -3,13 +3,13 @@ use std::sync::Mutex;
 use std::time::Duration;
 
 use async_trait::async_trait;
-use bytes::Bytes;
 use dashmap::DashMap;
 use lazy_static::lazy_static;
 use rand::distributions::Distribution;
 use serde::Deserialize;
 use serde::Serialize;
 
+use crate::backpressure::BufferWatermark;
 use crate::callback::InnerTransportCallback;
 use crate::connection_ref::ConnectionRef;
 use crate::core::callback::BoxedTransportCallback;
@@ -19,6 +19,7 @@ use crate::core::transport::TransportMessage;
 use crate::core::transport::WebrtcConnectionState;
 use crate::error::Error;
 use crate::error::Result;
+use crate::ice_gathering::IceGathering;
 use crate::ice_server::IceServer;
 use crate::notifier::Notifier;
 use crate::pool::Pool;
@@ -106,6 +107,16 @@ impl DummyTransport {
 
         Self { pool: Pool::new() }
     }
+
+    /// Dummy connections have no buffered amount, the watermark is ignored.
+    pub fn with_buffer_watermark(self, _watermark: BufferWatermark) -> Self {
+        self
+    }
+
+    /// Dummy connections have no candidates, the gathering is ignored.
+    pub fn with_ice_gathering(self, _gathering: IceGathering) -> Self {
+        self
+    }
 }
 
 #[async_trait]
@@ -118,8 +129,14 @@ impl ConnectionInterface for DummyConnection {
             random_delay().await;
         }
         self.webrtc_wait_for_data_channel_open().await?;
-        let data = bincode::serialize(&msg).map(Bytes::from)?;
-        self.remote_callback().on_message(&data).await;
+        let messages = self
+            .callback()
+            .frames()
+            .encode(&bincode::serialize(&msg)?)?;
+        let remote_callback = self.remote_callback();
+        for data in messages {
+            remote_callback.on_message(&data).await;
+        }
         Ok(())
     }
 
@@ -1,11 +1,17 @@
 //! Default using [WebrtcConnection] for native environment.
 //! Plus a [WebSysWebrtcConnection] for wasm environment.
 //! Also provide a [DummyConnection] for testing.
+//! And a [WebSocketConnection] bridged by relay, for environments where WebRTC is blocked,
+//! which is used as the fallback of WebRTC for some peers by [FallbackConnection].
 
 #[cfg(feature = "dummy")]
 mod dummy;
+#[cfg(all(feature = "native-webrtc", feature = "native-websocket"))]
+mod native_fallback;
 #[cfg(feature = "native-webrtc")]
 mod native_webrtc;
+#[cfg(feature = "native-websocket")]
+mod native_websocket;
 #[cfg(feature = "web-sys-webrtc")]
 mod web_sys_webrtc;
 
@@ -13,10 +19,22 @@ mod web_sys_webrtc;
 pub use crate::connections::dummy::DummyConnection;
 #[cfg(feature = "dummy")]
 pub use crate::connections::dummy::DummyTransport;
+#[cfg(all(feature = "native-webrtc", feature = "native-websocket"))]
+pub use crate::connections::native_fallback::FallbackConnection;
+#[cfg(all(feature = "native-webrtc", feature = "native-websocket"))]
+pub use crate::connections::native_fallback::FallbackSdp;
+#[cfg(all(feature = "native-webrtc", feature = "native-websocket"))]
+pub use crate::connections::native_fallback::FallbackTransport;
 #[cfg(feature = "native-webrtc")]
 pub use crate::connections::native_webrtc::WebrtcConnection;
 #[cfg(feature = "native-webrtc")]
 pub use crate::connections::native_webrtc::WebrtcTransport;
+#[cfg(feature = "native-websocket")]
+pub use crate::connections::native_websocket::WebSocketConnection;
+#[cfg(feature = "native-websocket")]
+pub use crate::connections::native_websocket::WebSocketSdp;
+#[cfg(feature = "native-websocket")]
+pub use crate::connections::native_websocket::WebSocketTransport;
 #[cfg(feature = "web-sys-webrtc")]
 pub use crate::connections::web_sys_webrtc::WebSysWebrtcConnection;
 #[cfg(feature = "web-sys-webrtc")]
@@ -0,0 +1,394 @@
+//! A transport over WebRTC, which falls back to a WebSocket relay for some peers, for
+//! environments where WebRTC is blocked.
+//!
+//! A connection is created over WebRTC, unless its cid is relayed, which is either set by
+//! [TransportInterface::set_relayed], or marked once a WebRTC connection with the cid failed.
+//! Relayed connections are only created when a relay is given. A connection answering an
+//! offer takes the kind of the offer instead, see [TransportInterface::prepare_answer], and
+//! only offers over the same relay are answered, so that a peer can't make the node join an
+//! arbitrary server.
+//!
+//! The sdp exchanged in handshake is a [FallbackSdp], which is serialized the same as the sdp
+//! of either kind, so peers without the fallback still handshake over WebRTC.
+//!
+//! The remote peer of a relayed connection is not bound to it by handshake, since the relay
+//! could forge its messages, see [TransportInterface::is_relayed].
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use dashmap::DashSet;
+use serde::Deserialize;
+use serde::Serialize;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+
+use crate::backpressure::BufferWatermark;
+use crate::connection_ref::ConnectionRef;
+use crate::connections::WebSocketConnection;
+use crate::connections::WebSocketSdp;
+use crate::connections::WebSocketTransport;
+use crate::connections::WebrtcConnection;
+use crate::connections::WebrtcTransport;
+use crate::core::callback::BoxedTransportCallback;
+use crate::core::callback::TransportCallback;
+use crate::core::transport::ConnectionInterface;
+use crate::core::transport::TransportInterface;
+use crate::core::transport::TransportMessage;
+use crate::core::transport::WebrtcConnectionState;
+use crate::error::Error;
+use crate::error::Result;
+use crate::ice_gathering::IceGathering;
+use crate::pool::Pool;
+use crate::timing::ConnectionTiming;
+
+/// Max number of cids relayed, WebRTC connections failed afterwards are not marked.
+pub const MAX_RELAYED_CIDS: usize = 1024;
+
+type CallbackError = Box<dyn std::error::Error>;
+
+/// Sdp of either kind of connection, serialized as the sdp of the kind.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum FallbackSdp {
+    /// Sdp of a connection over WebSocket relay.
+    WebSocket(WebSocketSdp),
+    /// Sdp of a connection over WebRTC.
+    Webrtc(RTCSessionDescription),
+}
+
+impl FallbackSdp {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::WebSocket(_) => "relay",
+            Self::Webrtc(_) => "webrtc",
+        }
+    }
+}
+
+/// A connection over WebRTC, or over WebSocket relay, see [native_fallback](self).
+pub enum FallbackConnection {
+    /// Connection over WebRTC.
+    Webrtc(WebrtcConnection),
+    /// Connection over WebSocket relay.
+    WebSocket(WebSocketConnection),
+}
+
+impl FallbackConnection {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::WebSocket(_) => "relay",
+            Self::Webrtc(_) => "webrtc",
+        }
+    }
+
+    fn mismatch(&self, sdp: &FallbackSdp) -> Error {
+        Error::SdpKindMismatch(sdp.kind(), self.kind())
+    }
+}
+
+/// [FallbackTransport] manages all the [FallbackConnection] and
+/// provides methods to create, get and close connections.
+pub struct FallbackTransport {
+    webrtc: WebrtcTransport,
+    websocket: Option<WebSocketTransport>,
+    relay: Option<String>,
+    relayed: Arc<DashSet<String>>,
+    pool: Pool<FallbackConnection>,
+}
+
+/// Callback of a WebRTC connection, which marks its cid relayed once it fails.
+struct FallbackCallback {
+    callback: BoxedTransportCallback,
+    relayed: Arc<DashSet<String>>,
+}
+
+#[async_trait]
+impl TransportCallback for FallbackCallback {
+    async fn on_message(&self, cid: &str, msg: &[u8]) -> std::result::Result<(), CallbackError> {
+        self.callback.on_message(cid, msg).await
+    }
+
+    async fn on_peer_connection_state_change(
+        &self,
+        cid: &str,
+        state: WebrtcConnectionState,
+    ) -> std::result::Result<(), CallbackError> {
+        if state == WebrtcConnectionState::Failed && self.relayed.len() < MAX_RELAYED_CIDS {
+            tracing::info!("WebRTC connection {cid} failed, falls back to relay");
+            self.relayed.insert(cid.to_string());
+        }
+        self.callback
+            .on_peer_connection_state_change(cid, state)
+            .await
+    }
+
+    async fn on_ice_candidate(
+        &self,
+        cid: &str,
+        candidate: &str,
+    ) -> std::result::Result<(), CallbackError> {
+        self.callback.on_ice_candidate(cid, candidate).await
+    }
+
+    async fn on_handshake_complete(&self, cid: &str) -> std::result::Result<(), CallbackError> {
+        self.callback.on_handshake_complete(cid).await
+    }
+}
+
+impl FallbackTransport {
+    /// Create a new [FallbackTransport] instance. Connections are only relayed when `relay`
+    /// is given, such as `wss://relay.example.com`.
+    pub fn new(ice_servers: &str, external_address: Option<String>, relay: Option<&str>) -> Self {
+        let relay = relay.map(|r| r.trim_end_matches('/').to_string());
+        Self {
+            webrtc: WebrtcTransport::new(ice_servers, external_address),
+            websocket: relay.as_deref().map(|r| WebSocketTransport::new(r, None)),
+            relay,
+            relayed: Arc::new(DashSet::new()),
+            pool: Pool::new(),
+        }
+    }
+
+    /// Set the watermarks of buffered amount of WebRTC data channels, see [BufferWatermark].
+    pub fn with_buffer_watermark(mut self, watermark: BufferWatermark) -> Self {
+        self.webrtc = self.webrtc.with_buffer_watermark(watermark);
+        self
+    }
+
+    /// Set how WebRTC connections wait for local candidates, see [IceGathering].
+    pub fn with_ice_gathering(mut self, gathering: IceGathering) -> Self {
+        self.webrtc = self.webrtc.with_ice_gathering(gathering);
+        self
+    }
+}
+
+#[async_trait]
+impl ConnectionInterface for FallbackConnection {
+    type Sdp = FallbackSdp;
+    type Error = Error;
+
+    async fn send_message(&self, msg: TransportMessage) -> Result<()> {
+        match self {
+            Self::Webrtc(c) => c.send_message(msg).await,
+            Self::WebSocket(c) => c.send_message(msg).await,
+        }
+    }
+
+    fn webrtc_connection_state(&self) -> WebrtcConnectionState {
+        match self {
+            Self::Webrtc(c) => c.webrtc_connection_state(),
+            Self::WebSocket(c) => c.webrtc_connection_state(),
+        }
+    }
+
+    async fn get_stats(&self) -> Vec<String> {
+        match self {
+            Self::Webrtc(c) => c.get_stats().await,
+            Self::WebSocket(c) => c.get_stats().await,
+        }
+    }
+
+    async fn webrtc_create_offer(&self) -> Result<Self::Sdp> {
+        match self {
+            Self::Webrtc(c) => c.webrtc_create_offer().await.map(FallbackSdp::Webrtc),
+            Self::WebSocket(c) => c.webrtc_create_offer().await.map(FallbackSdp::WebSocket),
+        }
+    }
+
+    async fn webrtc_answer_offer(&self, offer: Self::Sdp) -> Result<Self::Sdp> {
+        match (self, offer) {
+            (Self::Webrtc(c), FallbackSdp::Webrtc(offer)) => {
+                c.webrtc_answer_offer(offer).await.map(FallbackSdp::Webrtc)
+            }
+            (Self::WebSocket(c), FallbackSdp::WebSocket(offer)) => c
+                .webrtc_answer_offer(offer)
+                .await
+                .map(FallbackSdp::WebSocket),
+            (_, offer) => Err(self.mismatch(&offer)),
+        }
+    }
+
+    async fn webrtc_accept_answer(&self, answer: Self::Sdp) -> Result<()> {
+        match (self, answer) {
+            (Self::Webrtc(c), FallbackSdp::Webrtc(answer)) => c.webrtc_accept_answer(answer).await,
+            (Self::WebSocket(c), FallbackSdp::WebSocket(answer)) => {
+                c.webrtc_accept_answer(answer).await
+            }
+            (_, answer) => Err(self.mismatch(&answer)),
+        }
+    }
+
+    async fn webrtc_gathered_description(&self) -> Result<Option<Self::Sdp>> {
+        match self {
+            Self::Webrtc(c) => Ok(c
+                .webrtc_gathered_description()
+                .await?
+                .map(FallbackSdp::Webrtc)),
+            Self::WebSocket(_) => Ok(None),
+        }
+    }
+
+    async fn webrtc_add_ice_candidate(&self, candidate: &str) -> Result<()> {
+        match self {
+            Self::Webrtc(c) => c.webrtc_add_ice_candidate(candidate).await,
+            Self::WebSocket(_) => Ok(()),
+        }
+    }
+
+    async fn webrtc_wait_for_data_channel_open(&self) -> Result<()> {
+        match self {
+            Self::Webrtc(c) => c.webrtc_wait_for_data_channel_open().await,
+            Self::WebSocket(c) => c.webrtc_wait_for_data_channel_open().await,
+        }
+    }
+
+    async fn close(&self) -> Result<()> {
+        match self {
+            Self::Webrtc(c) => c.close().await,
+            Self::WebSocket(c) => c.close().await,
+        }
+    }
+
+    fn connection_timing(&self) -> ConnectionTiming {
+        match self {
+            Self::Webrtc(c) => c.connection_timing(),
+            Self::WebSocket(_) => ConnectionTiming::default(),
+        }
+    }
+
+    async fn webrtc_remote_address(&self) -> Option<SocketAddr> {
+        match self {
+            Self::Webrtc(c) => c.webrtc_remote_address().await,
+            Self::WebSocket(_) => None,
+        }
+    }
+}
+
+#[async_trait]
+impl TransportInterface for FallbackTransport {
+    type Connection = FallbackConnection;
+    type Error = Error;
+
+    async fn new_connection(&self, cid: &str, callback: BoxedTransportCallback) -> Result<()> {
+        if let Ok(existed_conn) = self.pool.connection(cid) {
+            if matches!(
+                existed_conn.webrtc_connection_state(),
+                WebrtcConnectionState::New
+                    | WebrtcConnectionState::Connecting
+                    | WebrtcConnectionState::Connected
+            ) {
+                return Err(Error::ConnectionAlreadyExists(cid.to_string()));
+            }
+        }
+
+        let conn = match self.websocket {
+            Some(ref websocket) if self.relayed.contains(cid) => {
+                FallbackConnection::WebSocket(websocket.create_connection(cid, callback))
+            }
+            Some(_) => {
+                let callback = Box::new(FallbackCallback {
+                    callback,
+                    relayed: self.relayed.clone(),
+                });
+                FallbackConnection::Webrtc(self.webrtc.create_connection(cid, callback).await?)
+            }
+            None => FallbackConnection::Webrtc(self.webrtc.create_connection(cid, callback).await?),
+        };
+
+        self.pool.safely_insert(cid, conn)?;
+        Ok(())
+    }
+
+    async fn close_connection(&self, cid: &str) -> Result<()> {
+        self.pool.safely_remove(cid).await
+    }
+
+    fn connection(&self, cid: &str) -> Result<ConnectionRef<Self::Connection>> {
+        self.pool.connection(cid)
+    }
+
+    fn connections(&self) -> Vec<(String, ConnectionRef<Self::Connection>)> {
+        self.pool.connections()
+    }
+
+    fn connection_ids(&self) -> Vec<String> {
+        self.pool.connection_ids()
+    }
+
+    fn set_external_address(&self, address: Option<String>) {
+        self.webrtc.set_external_address(address)
+    }
+
+    fn set_relayed(&self, cid: &str, relayed: bool) {
+        if relayed {
+            self.relayed.insert(cid.to_string());
+        } else {
+            self.relayed.remove(cid);
+        }
+    }
+
+    fn is_relayed(&self, cid: &str) -> bool {
+        self.websocket.is_some() && self.relayed.contains(cid)
+    }
+
+    fn prepare_answer(&self, cid: &str, offer: &FallbackSdp) {
+        let over_relay = match offer {
+            FallbackSdp::WebSocket(sdp) => {
+                Some(sdp.relay.trim_end_matches('/')) == self.relay.as_deref()
+            }
+            FallbackSdp::Webrtc(_) => false,
+        };
+        self.set_relayed(cid, over_relay);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fallback_sdp_serialized_as_either_kind() {
+        let relayed = WebSocketSdp {
+            relay: "wss://relay.example.com".to_string(),
+            session: "session".to_string(),
+        };
+        let json = serde_json::to_string(&FallbackSdp::WebSocket(relayed.clone())).unwrap();
+        assert_eq!(json, serde_json::to_string(&relayed).unwrap());
+        assert!(matches!(
+            serde_json::from_str(&json).unwrap(),
+            FallbackSdp::WebSocket(sdp) if sdp == relayed
+        ));
+
+        let webrtc = serde_json::json!({"type": "offer", "sdp": "v=0\r\n"});
+        assert!(matches!(
+            serde_json::from_value(webrtc).unwrap(),
+            FallbackSdp::Webrtc(sdp) if sdp.sdp == "v=0\r\n"
+        ));
+    }
+
+    #[test]
+    fn test_answer_only_offers_over_own_relay() {
+        let transport = FallbackTransport::new(
+            "stun://stun.l.google.com:19302",
+            None,
+            Some("wss://relay.example.com/"),
+        );
+        let offer = |relay: &str| {
+            FallbackSdp::WebSocket(WebSocketSdp {
+                relay: relay.to_string(),
+                session: "session".to_string(),
+            })
+        };
+
+        transport.prepare_answer("peer", &offer("wss://relay.example.com"));
+        assert!(transport.is_relayed("peer"));
+        transport.prepare_answer("peer", &offer("wss://evil.example.com"));
+        assert!(!transport.is_relayed("peer"));
+
+        // Without a relay, nothing is relayed.
+        let transport = FallbackTransport::new("stun://stun.l.google.com:19302", None, None);
+        transport.set_relayed("peer", true);
+        assert!(!transport.is_relayed("peer"));
+    }
+}
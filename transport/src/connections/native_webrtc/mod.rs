@@ -1,11 +1,15 @@
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::sync::RwLock;
+use std::time::Duration;
 
 use async_trait::async_trait;
-use bytes::Bytes;
 use webrtc::data_channel::data_channel_message::DataChannelMessage;
 use webrtc::data_channel::data_channel_state::RTCDataChannelState;
 use webrtc::data_channel::RTCDataChannel;
 use webrtc::ice::mdns::MulticastDnsMode;
+use webrtc::ice_transport::ice_candidate::RTCIceCandidate;
+use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
 use webrtc::ice_transport::ice_candidate_type::RTCIceCandidateType;
 use webrtc::ice_transport::ice_credential_type::RTCIceCredentialType;
 use webrtc::ice_transport::ice_server::RTCIceServer;
@@ -13,7 +17,10 @@ use webrtc::peer_connection::configuration::RTCConfiguration;
 use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
 use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
 use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::stats::StatsReportType;
 
+use crate::backpressure::BufferWatermark;
+use crate::backpressure::BufferedAmountLow;
 use crate::callback::InnerTransportCallback;
 use crate::connection_ref::ConnectionRef;
 use crate::core::callback::BoxedTransportCallback;
@@ -23,10 +30,16 @@ use crate::core::transport::TransportMessage;
 use crate::core::transport::WebrtcConnectionState;
 use crate::error::Error;
 use crate::error::Result;
+use crate::framing::FrameNegotiation;
+use crate::ice_gathering::IceGathering;
+use crate::ice_gathering::RemoteCandidates;
 use crate::ice_server::IceCredentialType;
 use crate::ice_server::IceServer;
 use crate::notifier::Notifier;
 use crate::pool::Pool;
+use crate::timing::ConnectionTiming;
+use crate::timing::HandshakeMark;
+use crate::timing::HandshakeTimer;
 
 /// A connection that implemented by webrtc-rs library.
 /// Used for native environment.
@@ -34,13 +47,21 @@ pub struct WebrtcConnection {
     webrtc_conn: RTCPeerConnection,
     webrtc_data_channel: Arc<RTCDataChannel>,
     webrtc_data_channel_open_notifier: Notifier,
+    buffer_watermark: BufferWatermark,
+    buffered_amount_low: BufferedAmountLow,
+    ice_gathering: IceGathering,
+    remote_candidates: RemoteCandidates,
+    handshake_timer: HandshakeTimer,
+    frames: FrameNegotiation,
 }
 
 /// [WebrtcTransport] manages all the [WebrtcConnection] and
 /// provides methods to create, get and close connections.
 pub struct WebrtcTransport {
     ice_servers: Vec<IceServer>,
-    external_address: Option<String>,
+    external_address: RwLock<Option<String>>,
+    buffer_watermark: BufferWatermark,
+    ice_gathering: IceGathering,
     pool: Pool<WebrtcConnection>,
 }
 
@@ -49,137 +70,110 @@ impl WebrtcConnection {
         webrtc_conn: RTCPeerConnection,
         webrtc_data_channel: Arc<RTCDataChannel>,
         webrtc_data_channel_open_notifier: Notifier,
+        buffer_watermark: BufferWatermark,
+        buffered_amount_low: BufferedAmountLow,
+        ice_gathering: IceGathering,
+        handshake_timer: HandshakeTimer,
+        frames: FrameNegotiation,
     ) -> Self {
         Self {
             webrtc_conn,
             webrtc_data_channel,
             webrtc_data_channel_open_notifier,
+            buffer_watermark,
+            buffered_amount_low,
+            ice_gathering,
+            remote_candidates: RemoteCandidates::default(),
+            handshake_timer,
+            frames,
         }
     }
 
     async fn webrtc_gather(&self) -> Result<RTCSessionDescription> {
-        self.webrtc_conn
-            .gathering_complete_promise()
-            .await
-            .recv()
-            .await;
-
-        self.webrtc_conn
-            .local_description()
+        let mut gathered = self.webrtc_conn.gathering_complete_promise().await;
+        let timeout = Duration::from_millis(self.ice_gathering.timeout_ms);
+        if tokio::time::timeout(timeout, gathered.recv())
             .await
-            .ok_or(Error::WebrtcLocalSdpGenerationError)
-    }
-}
-
-impl WebrtcTransport {
-    /// Create a new [WebrtcTransport] instance.
-    pub fn new(ice_servers: &str, external_address: Option<String>) -> Self {
-        let ice_servers = IceServer::vec_from_str(ice_servers).unwrap();
-
-        Self {
-            ice_servers,
-            external_address,
-            pool: Pool::new(),
+            .is_err()
+        {
+            tracing::debug!(
+                "ICE gathering timed out after {} ms",
+                self.ice_gathering.timeout_ms
+            );
         }
-    }
-}
-
-#[async_trait]
-impl ConnectionInterface for WebrtcConnection {
-    type Sdp = RTCSessionDescription;
-    type Error = Error;
+        self.handshake_timer.mark(HandshakeMark::Gathered);
 
-    async fn send_message(&self, msg: TransportMessage) -> Result<()> {
-        self.webrtc_wait_for_data_channel_open().await?;
-        let data = bincode::serialize(&msg).map(Bytes::from)?;
-        self.webrtc_data_channel.send(&data).await?;
-        Ok(())
+        self.webrtc_local_description().await
     }
 
-    async fn get_stats(&self) -> Vec<String> {
+    async fn webrtc_local_description(&self) -> Result<RTCSessionDescription> {
         self.webrtc_conn
-            .get_stats()
+            .local_description()
             .await
-            .reports
-            .into_iter()
-            .map(|x| serde_json::to_string(&x).unwrap_or("failed to dump stats entry".to_string()))
-            .collect()
-    }
-
-    fn webrtc_connection_state(&self) -> WebrtcConnectionState {
-        self.webrtc_conn.connection_state().into()
+            .ok_or(Error::WebrtcLocalSdpGenerationError)
     }
 
-    async fn webrtc_create_offer(&self) -> Result<Self::Sdp> {
-        let setting_offer = self.webrtc_conn.create_offer(None).await?;
-        self.webrtc_conn
-            .set_local_description(setting_offer.clone())
-            .await?;
-
-        self.webrtc_gather().await
+    /// Local description to give out, see [IceGathering].
+    async fn webrtc_description(&self) -> Result<RTCSessionDescription> {
+        let description = if self.ice_gathering.is_trickle() {
+            self.webrtc_local_description().await
+        } else {
+            self.webrtc_gather().await
+        };
+        self.handshake_timer.mark(HandshakeMark::DescriptionSent);
+        description
     }
 
-    async fn webrtc_answer_offer(&self, offer: Self::Sdp) -> Result<Self::Sdp> {
-        tracing::debug!("webrtc_answer_offer, offer: {offer:?}");
-
-        self.webrtc_conn.set_remote_description(offer).await?;
-
-        let answer = self.webrtc_conn.create_answer(None).await?;
-        self.webrtc_conn
-            .set_local_description(answer.clone())
-            .await?;
-
-        self.webrtc_gather().await
+    /// Add remote candidates trickled before the remote description is set.
+    async fn webrtc_add_pending_candidates(&self) -> Result<()> {
+        for candidate in self.remote_candidates.described() {
+            self.webrtc_add_candidate(&candidate).await?;
+        }
+        Ok(())
     }
 
-    async fn webrtc_accept_answer(&self, answer: Self::Sdp) -> Result<()> {
-        tracing::debug!("webrtc_accept_answer, answer: {answer:?}");
-
+    async fn webrtc_add_candidate(&self, candidate: &str) -> Result<()> {
+        let init: RTCIceCandidateInit = serde_json::from_str(candidate)
+            .map_err(|e| Error::InvalidIceCandidate(e.to_string()))?;
         self.webrtc_conn
-            .set_remote_description(answer)
+            .add_ice_candidate(init)
             .await
             .map_err(|e| e.into())
     }
+}
 
-    async fn webrtc_wait_for_data_channel_open(&self) -> Result<()> {
-        if matches!(
-            self.webrtc_connection_state(),
-            WebrtcConnectionState::Failed
-                | WebrtcConnectionState::Closed
-                | WebrtcConnectionState::Disconnected
-        ) {
-            return Err(Error::DataChannelOpen("Connection unavailable".to_string()));
-        }
+impl WebrtcTransport {
+    /// Create a new [WebrtcTransport] instance.
+    pub fn new(ice_servers: &str, external_address: Option<String>) -> Self {
+        let ice_servers = IceServer::vec_from_str(ice_servers).unwrap();
 
-        if self.webrtc_data_channel.ready_state() == RTCDataChannelState::Open {
-            return Ok(());
+        Self {
+            ice_servers,
+            external_address: RwLock::new(external_address),
+            buffer_watermark: BufferWatermark::default(),
+            ice_gathering: IceGathering::default(),
+            pool: Pool::new(),
         }
-
-        self.webrtc_data_channel_open_notifier.clone().await
     }
 
-    async fn close(&self) -> Result<()> {
-        self.webrtc_conn.close().await.map_err(|e| e.into())
+    /// Set the watermarks of buffered amount of data channels, see [BufferWatermark].
+    pub fn with_buffer_watermark(mut self, watermark: BufferWatermark) -> Self {
+        self.buffer_watermark = watermark;
+        self
     }
-}
 
-#[async_trait]
-impl TransportInterface for WebrtcTransport {
-    type Connection = WebrtcConnection;
-    type Error = Error;
-
-    async fn new_connection(&self, cid: &str, callback: BoxedTransportCallback) -> Result<()> {
-        if let Ok(existed_conn) = self.pool.connection(cid) {
-            if matches!(
-                existed_conn.webrtc_connection_state(),
-                WebrtcConnectionState::New
-                    | WebrtcConnectionState::Connecting
-                    | WebrtcConnectionState::Connected
-            ) {
-                return Err(Error::ConnectionAlreadyExists(cid.to_string()));
-            }
-        }
+    /// Set how connections wait for local candidates, see [IceGathering].
+    pub fn with_ice_gathering(mut self, gathering: IceGathering) -> Self {
+        self.ice_gathering = gathering;
+        self
+    }
 
+    /// Create a connection with cid, which is not managed by the transport yet.
+    pub(crate) async fn create_connection(
+        &self,
+        cid: &str,
+        callback: BoxedTransportCallback,
+    ) -> Result<WebrtcConnection> {
         //
         // Setup webrtc connection env
         //
@@ -191,7 +185,12 @@ impl TransportInterface for WebrtcTransport {
         };
 
         let mut setting = webrtc::api::setting_engine::SettingEngine::default();
-        if let Some(ref addr) = self.external_address {
+        let external_address = self
+            .external_address
+            .read()
+            .ok()
+            .and_then(|addr| addr.clone());
+        if let Some(ref addr) = external_address {
             tracing::debug!("setting external ip {:?}", addr);
             setting.set_nat_1to1_ips(vec![addr.to_string()], RTCIceCandidateType::Host);
             setting.set_ice_multicast_dns_mode(MulticastDnsMode::QueryOnly);
@@ -219,16 +218,22 @@ impl TransportInterface for WebrtcTransport {
             webrtc_data_channel_open_notifier.clone(),
         ));
 
+        let handshake_timer = HandshakeTimer::default();
+
         let data_channel_inner_cb = inner_cb.clone();
+        let data_channel_timer = handshake_timer.clone();
         webrtc_conn.on_data_channel(Box::new(move |d: Arc<RTCDataChannel>| {
             let d_label = d.label();
             let d_id = d.id();
             tracing::debug!("New DataChannel {d_label} {d_id}");
 
             let on_open_inner_cb = data_channel_inner_cb.clone();
+            let on_open_timer = data_channel_timer.clone();
             d.on_open(Box::new(move || {
+                on_open_timer.mark(HandshakeMark::DataChannelOpen);
                 on_open_inner_cb.on_data_channel_open();
-                Box::pin(async move {})
+                let inner_cb = on_open_inner_cb.clone();
+                Box::pin(async move { inner_cb.on_handshake_complete().await })
             }));
 
             let on_close_inner_cb = data_channel_inner_cb.clone();
@@ -255,9 +260,38 @@ impl TransportInterface for WebrtcTransport {
             Box::pin(async move {})
         }));
 
+        if self.ice_gathering.is_trickle() {
+            let ice_candidate_inner_cb = inner_cb.clone();
+            let ice_candidate_timer = handshake_timer.clone();
+            webrtc_conn.on_ice_candidate(Box::new(move |c: Option<RTCIceCandidate>| {
+                let inner_cb = ice_candidate_inner_cb.clone();
+                let timer = ice_candidate_timer.clone();
+
+                Box::pin(async move {
+                    // None marks the end of gathering.
+                    let Some(c) = c else {
+                        timer.mark(HandshakeMark::Gathered);
+                        return;
+                    };
+                    let candidate = c
+                        .to_json()
+                        .map_err(|e| e.to_string())
+                        .and_then(|init| serde_json::to_string(&init).map_err(|e| e.to_string()));
+                    match candidate {
+                        Ok(candidate) => inner_cb.on_ice_candidate(&candidate).await,
+                        Err(e) => tracing::error!("Serialize ICE candidate failed: {e}"),
+                    }
+                })
+            }));
+        }
+
         let peer_connection_state_change_inner_cb = inner_cb.clone();
+        let peer_connection_state_change_timer = handshake_timer.clone();
         webrtc_conn.on_peer_connection_state_change(Box::new(move |s: RTCPeerConnectionState| {
             tracing::debug!("Peer Connection State has changed: {s:?}");
+            if s == RTCPeerConnectionState::Connected {
+                peer_connection_state_change_timer.mark(HandshakeMark::Connected);
+            }
 
             let inner_cb = peer_connection_state_change_inner_cb.clone();
 
@@ -271,15 +305,185 @@ impl TransportInterface for WebrtcTransport {
         //
         let webrtc_data_channel = webrtc_conn.create_data_channel("rings", None).await?;
 
+        let buffered_amount_low = BufferedAmountLow::default();
+        webrtc_data_channel
+            .set_buffered_amount_low_threshold(self.buffer_watermark.low)
+            .await;
+
+        let on_buffered_amount_low = buffered_amount_low.clone();
+        webrtc_data_channel
+            .on_buffered_amount_low(Box::new(move || {
+                on_buffered_amount_low.notify();
+                Box::pin(async move {})
+            }))
+            .await;
+
+        let on_close_buffered_amount_low = buffered_amount_low.clone();
+        webrtc_data_channel.on_close(Box::new(move || {
+            on_close_buffered_amount_low.close();
+            Box::pin(async move {})
+        }));
+
         //
         // Construct the Connection
         //
-        let conn = WebrtcConnection::new(
+        Ok(WebrtcConnection::new(
             webrtc_conn,
             webrtc_data_channel,
             webrtc_data_channel_open_notifier,
-        );
+            self.buffer_watermark,
+            buffered_amount_low,
+            self.ice_gathering,
+            handshake_timer,
+            inner_cb.frames(),
+        ))
+    }
+}
+
+#[async_trait]
+impl ConnectionInterface for WebrtcConnection {
+    type Sdp = RTCSessionDescription;
+    type Error = Error;
+
+    async fn send_message(&self, msg: TransportMessage) -> Result<()> {
+        self.webrtc_wait_for_data_channel_open().await?;
+        let messages = self.frames.encode(&bincode::serialize(&msg)?)?;
+        let channel = &self.webrtc_data_channel;
+        for data in messages {
+            self.buffered_amount_low
+                .wait(self.buffer_watermark, data.len(), || {
+                    channel.buffered_amount()
+                })
+                .await?;
+            channel.send(&data).await?;
+        }
+        Ok(())
+    }
+
+    async fn get_stats(&self) -> Vec<String> {
+        self.webrtc_conn
+            .get_stats()
+            .await
+            .reports
+            .into_iter()
+            .map(|x| serde_json::to_string(&x).unwrap_or("failed to dump stats entry".to_string()))
+            .collect()
+    }
+
+    fn webrtc_connection_state(&self) -> WebrtcConnectionState {
+        self.webrtc_conn.connection_state().into()
+    }
+
+    async fn webrtc_create_offer(&self) -> Result<Self::Sdp> {
+        self.handshake_timer.mark(HandshakeMark::Started);
+        let setting_offer = self.webrtc_conn.create_offer(None).await?;
+        self.webrtc_conn
+            .set_local_description(setting_offer.clone())
+            .await?;
+        self.handshake_timer.mark(HandshakeMark::LocalDescribed);
+
+        self.webrtc_description().await
+    }
+
+    async fn webrtc_answer_offer(&self, offer: Self::Sdp) -> Result<Self::Sdp> {
+        tracing::debug!("webrtc_answer_offer, offer: {offer:?}");
+        self.handshake_timer.mark(HandshakeMark::Started);
+
+        self.webrtc_conn.set_remote_description(offer).await?;
+        self.handshake_timer.mark(HandshakeMark::RemoteDescribed);
+        self.webrtc_add_pending_candidates().await?;
+
+        let answer = self.webrtc_conn.create_answer(None).await?;
+        self.webrtc_conn
+            .set_local_description(answer.clone())
+            .await?;
+        self.handshake_timer.mark(HandshakeMark::LocalDescribed);
+
+        self.webrtc_description().await
+    }
+
+    async fn webrtc_accept_answer(&self, answer: Self::Sdp) -> Result<()> {
+        tracing::debug!("webrtc_accept_answer, answer: {answer:?}");
 
+        self.webrtc_conn.set_remote_description(answer).await?;
+        self.handshake_timer.mark(HandshakeMark::RemoteDescribed);
+        self.webrtc_add_pending_candidates().await
+    }
+
+    async fn webrtc_gathered_description(&self) -> Result<Option<Self::Sdp>> {
+        if !self.ice_gathering.is_trickle() {
+            return Ok(None);
+        }
+        self.webrtc_gather().await.map(Some)
+    }
+
+    async fn webrtc_add_ice_candidate(&self, candidate: &str) -> Result<()> {
+        match self.remote_candidates.add(candidate) {
+            Some(candidate) => self.webrtc_add_candidate(&candidate).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn webrtc_wait_for_data_channel_open(&self) -> Result<()> {
+        if matches!(
+            self.webrtc_connection_state(),
+            WebrtcConnectionState::Failed
+                | WebrtcConnectionState::Closed
+                | WebrtcConnectionState::Disconnected
+        ) {
+            return Err(Error::DataChannelOpen("Connection unavailable".to_string()));
+        }
+
+        if self.webrtc_data_channel.ready_state() == RTCDataChannelState::Open {
+            return Ok(());
+        }
+
+        self.webrtc_data_channel_open_notifier.clone().await
+    }
+
+    async fn close(&self) -> Result<()> {
+        self.webrtc_conn.close().await.map_err(|e| e.into())
+    }
+
+    fn connection_timing(&self) -> ConnectionTiming {
+        self.handshake_timer.timing()
+    }
+
+    async fn webrtc_remote_address(&self) -> Option<SocketAddr> {
+        let reports = self.webrtc_conn.get_stats().await.reports;
+        let remote_id = reports.values().find_map(|report| match report {
+            StatsReportType::CandidatePair(pair) if pair.nominated => {
+                Some(pair.remote_candidate_id.clone())
+            }
+            _ => None,
+        })?;
+        match reports.get(&remote_id)? {
+            StatsReportType::RemoteCandidate(candidate) => {
+                Some(SocketAddr::new(candidate.ip.parse().ok()?, candidate.port))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[async_trait]
+impl TransportInterface for WebrtcTransport {
+    type Connection = WebrtcConnection;
+    type Error = Error;
+
+    async fn new_connection(&self, cid: &str, callback: BoxedTransportCallback) -> Result<()> {
+        if let Ok(existed_conn) = self.pool.connection(cid) {
+            if matches!(
+                existed_conn.webrtc_connection_state(),
+                WebrtcConnectionState::New
+                    | WebrtcConnectionState::Connecting
+                    | WebrtcConnectionState::Connected
+            ) {
+                return Err(Error::ConnectionAlreadyExists(cid.to_string()));
+            }
+        }
+
+        let conn = self.create_connection(cid, callback).await?;
         self.pool.safely_insert(cid, conn)?;
         Ok(())
     }
@@ -299,6 +503,12 @@ impl TransportInterface for WebrtcTransport {
     fn connection_ids(&self) -> Vec<String> {
         self.pool.connection_ids()
     }
+
+    fn set_external_address(&self, address: Option<String>) {
+        if let Ok(mut addr) = self.external_address.write() {
+            *addr = address;
+        }
+    }
 }
 
 impl From<IceCredentialType> for RTCIceCredentialType {
@@ -0,0 +1,427 @@
+//! A transport over WebSocket relay, for environments where WebRTC is blocked.
+//!
+//! Both sides of a connection join the same session on a relay server, which bridges
+//! the binary messages between them. The relay is expected to:
+//! - accept WebSocket connections on `{relay}/{session}`,
+//! - pair the two sockets joined the same session, and send a text message
+//!   [RELAY_PAIRED] to both of them once paired,
+//! - forward binary messages between paired sockets as they are,
+//! - close the other socket when one of them is closed.
+//!
+//! The "sdp" exchanged in handshake is a [WebSocketSdp], which tells the answerer
+//! which relay and session to join. Messages are framed and verified by upper layers
+//! the same as the WebRTC transports, the relay is not trusted.
+//!
+//! It's usually not used alone, but as the fallback of WebRTC for some peers, see
+//! [FallbackTransport](crate::connections::FallbackTransport).
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::SplitSink;
+use futures::SinkExt;
+use futures::StreamExt;
+use rand::distributions::Alphanumeric;
+use rand::distributions::DistString;
+use serde::Deserialize;
+use serde::Serialize;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_tungstenite::MaybeTlsStream;
+use tokio_tungstenite::WebSocketStream;
+
+use crate::backpressure::BufferWatermark;
+use crate::callback::InnerTransportCallback;
+use crate::connection_ref::ConnectionRef;
+use crate::core::callback::BoxedTransportCallback;
+use crate::core::transport::ConnectionInterface;
+use crate::core::transport::TransportInterface;
+use crate::core::transport::TransportMessage;
+use crate::core::transport::WebrtcConnectionState;
+use crate::error::Error;
+use crate::error::Result;
+use crate::ice_gathering::IceGathering;
+use crate::notifier::Notifier;
+use crate::pool::Pool;
+
+/// Text message sent by relay when both sides joined the session.
+pub const RELAY_PAIRED: &str = "paired";
+
+type WsSink = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, WsMessage>;
+
+/// The relay and session to join, exchanged as sdp in handshake.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WebSocketSdp {
+    /// Url of relay server, such as `wss://relay.example.com`.
+    pub relay: String,
+    /// Session id on relay.
+    pub session: String,
+}
+
+/// A connection bridged by a WebSocket relay.
+/// Used for native environment where WebRTC is not available.
+pub struct WebSocketConnection {
+    relay: String,
+    session: String,
+    inner_cb: Arc<InnerTransportCallback>,
+    sink: tokio::sync::Mutex<Option<WsSink>>,
+    reader: Mutex<Option<JoinHandle<()>>>,
+    state: Arc<Mutex<WebrtcConnectionState>>,
+    data_channel_open_notifier: Notifier,
+}
+
+/// [WebSocketTransport] manages all the [WebSocketConnection] and
+/// provides methods to create, get and close connections.
+pub struct WebSocketTransport {
+    relay: String,
+    pool: Pool<WebSocketConnection>,
+}
+
+impl WebSocketConnection {
+    fn lock_reader(&self) -> MutexGuard<'_, Option<JoinHandle<()>>> {
+        self.reader.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn new(relay: &str, inner_cb: Arc<InnerTransportCallback>, notifier: Notifier) -> Self {
+        Self {
+            relay: relay.trim_end_matches('/').to_string(),
+            session: Alphanumeric.sample_string(&mut rand::thread_rng(), 32),
+            inner_cb,
+            sink: tokio::sync::Mutex::new(None),
+            reader: Mutex::new(None),
+            state: Arc::new(Mutex::new(WebrtcConnectionState::New)),
+            data_channel_open_notifier: notifier,
+        }
+    }
+
+    /// Join the session on relay, and dispatch messages from relay in background.
+    async fn join(&self, sdp: &WebSocketSdp) -> Result<()> {
+        set_state(
+            &self.state,
+            &self.inner_cb,
+            WebrtcConnectionState::Connecting,
+        )
+        .await;
+
+        let url = format!("{}/{}", sdp.relay.trim_end_matches('/'), sdp.session);
+        let (stream, _) = tokio_tungstenite::connect_async(url.as_str())
+            .await
+            .map_err(|e| Error::WebSocket(e.to_string()))?;
+        let (sink, mut stream) = stream.split();
+        *self.sink.lock().await = Some(sink);
+
+        let inner_cb = self.inner_cb.clone();
+        let state = self.state.clone();
+        let reader = tokio::spawn(async move {
+            while let Some(msg) = stream.next().await {
+                match msg {
+                    Ok(WsMessage::Binary(data)) => inner_cb.on_message(&Bytes::from(data)).await,
+                    Ok(WsMessage::Text(text)) if text == RELAY_PAIRED => {
+                        inner_cb.on_data_channel_open();
+                        set_state(&state, &inner_cb, WebrtcConnectionState::Connected).await;
+                    }
+                    Ok(WsMessage::Close(_)) => break,
+                    Ok(_) => {}
+                    Err(e) => {
+                        tracing::error!("WebSocket of {} failed: {e:?}", inner_cb.cid);
+                        break;
+                    }
+                }
+            }
+            inner_cb.on_data_channel_close();
+            set_state(&state, &inner_cb, WebrtcConnectionState::Disconnected).await;
+            set_state(&state, &inner_cb, WebrtcConnectionState::Closed).await;
+        });
+
+        if let Some(old) = self.lock_reader().replace(reader) {
+            old.abort();
+        }
+        Ok(())
+    }
+}
+
+async fn set_state(
+    state: &Mutex<WebrtcConnectionState>,
+    inner_cb: &InnerTransportCallback,
+    new_state: WebrtcConnectionState,
+) {
+    {
+        let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+        if *state == new_state || *state == WebrtcConnectionState::Closed {
+            return;
+        }
+        *state = new_state;
+    }
+    inner_cb.on_peer_connection_state_change(new_state).await;
+}
+
+impl WebSocketTransport {
+    /// Create a new [WebSocketTransport] instance, all connections are bridged by `relay`.
+    pub fn new(relay: &str, _external_address: Option<String>) -> Self {
+        Self {
+            relay: relay.to_string(),
+            pool: Pool::new(),
+        }
+    }
+
+    /// WebSocket connections are not buffered by data channels, the watermark is ignored.
+    pub fn with_buffer_watermark(self, _watermark: BufferWatermark) -> Self {
+        self
+    }
+
+    /// WebSocket connections have no candidates, the gathering is ignored.
+    pub fn with_ice_gathering(self, _gathering: IceGathering) -> Self {
+        self
+    }
+
+    /// Create a connection with cid, which is not managed by the transport yet.
+    pub(crate) fn create_connection(
+        &self,
+        cid: &str,
+        callback: BoxedTransportCallback,
+    ) -> WebSocketConnection {
+        let notifier = Notifier::default();
+        let inner_cb = Arc::new(InnerTransportCallback::new(cid, callback, notifier.clone()));
+        WebSocketConnection::new(&self.relay, inner_cb, notifier)
+    }
+}
+
+#[async_trait]
+impl ConnectionInterface for WebSocketConnection {
+    type Sdp = WebSocketSdp;
+    type Error = Error;
+
+    async fn send_message(&self, msg: TransportMessage) -> Result<()> {
+        self.webrtc_wait_for_data_channel_open().await?;
+        let messages = self.inner_cb.frames().encode(&bincode::serialize(&msg)?)?;
+        let mut sink = self.sink.lock().await;
+        let sink = sink
+            .as_mut()
+            .ok_or_else(|| Error::DataChannelOpen("WebSocket is not joined".to_string()))?;
+        for data in messages {
+            sink.send(WsMessage::Binary(data.to_vec()))
+                .await
+                .map_err(|e| Error::WebSocket(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    fn webrtc_connection_state(&self) -> WebrtcConnectionState {
+        *self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    async fn get_stats(&self) -> Vec<String> {
+        vec![format!("relay: {}", self.relay)]
+    }
+
+    async fn webrtc_create_offer(&self) -> Result<Self::Sdp> {
+        let sdp = WebSocketSdp {
+            relay: self.relay.clone(),
+            session: self.session.clone(),
+        };
+        self.join(&sdp).await?;
+        Ok(sdp)
+    }
+
+    async fn webrtc_answer_offer(&self, offer: Self::Sdp) -> Result<Self::Sdp> {
+        self.join(&offer).await?;
+        Ok(offer)
+    }
+
+    async fn webrtc_accept_answer(&self, answer: Self::Sdp) -> Result<()> {
+        if answer.session != self.session {
+            return Err(Error::WebSocket(format!(
+                "Answer of session {} mismatched",
+                answer.session
+            )));
+        }
+        Ok(())
+    }
+
+    async fn webrtc_wait_for_data_channel_open(&self) -> Result<()> {
+        if matches!(
+            self.webrtc_connection_state(),
+            WebrtcConnectionState::Failed
+                | WebrtcConnectionState::Closed
+                | WebrtcConnectionState::Disconnected
+        ) {
+            return Err(Error::DataChannelOpen("Connection unavailable".to_string()));
+        }
+
+        self.data_channel_open_notifier.clone().await
+    }
+
+    async fn close(&self) -> Result<()> {
+        if let Some(mut sink) = self.sink.lock().await.take() {
+            if let Err(e) = sink.close().await {
+                tracing::warn!("Close WebSocket failed: {e:?}");
+            }
+        }
+        if let Some(reader) = self.lock_reader().take() {
+            reader.abort();
+        }
+        self.inner_cb.on_data_channel_close();
+        set_state(&self.state, &self.inner_cb, WebrtcConnectionState::Closed).await;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TransportInterface for WebSocketTransport {
+    type Connection = WebSocketConnection;
+    type Error = Error;
+
+    async fn new_connection(&self, cid: &str, callback: BoxedTransportCallback) -> Result<()> {
+        if let Ok(existed_conn) = self.pool.connection(cid) {
+            if matches!(
+                existed_conn.webrtc_connection_state(),
+                WebrtcConnectionState::New
+                    | WebrtcConnectionState::Connecting
+                    | WebrtcConnectionState::Connected
+            ) {
+                return Err(Error::ConnectionAlreadyExists(cid.to_string()));
+            }
+        }
+
+        let conn = self.create_connection(cid, callback);
+        self.pool.safely_insert(cid, conn)?;
+        Ok(())
+    }
+
+    async fn close_connection(&self, cid: &str) -> Result<()> {
+        self.pool.safely_remove(cid).await
+    }
+
+    fn connection(&self, cid: &str) -> Result<ConnectionRef<Self::Connection>> {
+        self.pool.connection(cid)
+    }
+
+    fn connections(&self) -> Vec<(String, ConnectionRef<Self::Connection>)> {
+        self.pool.connections()
+    }
+
+    fn connection_ids(&self) -> Vec<String> {
+        self.pool.connection_ids()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+    use tokio_tungstenite::tungstenite::handshake::server::ErrorResponse;
+    use tokio_tungstenite::tungstenite::handshake::server::Request;
+    use tokio_tungstenite::tungstenite::handshake::server::Response;
+
+    use super::*;
+    use crate::core::callback::TransportCallback;
+
+    struct Recorder(mpsc::UnboundedSender<Vec<u8>>);
+
+    #[async_trait]
+    impl TransportCallback for Recorder {
+        async fn on_message(
+            &self,
+            _cid: &str,
+            msg: &[u8],
+        ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+            self.0.send(msg.to_vec())?;
+            Ok(())
+        }
+    }
+
+    /// A relay pairing sockets joined the same session, as described by module doc.
+    async fn run_relay(listener: TcpListener) {
+        let mut waiting = HashMap::new();
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut session = String::new();
+            let ws =
+                tokio_tungstenite::accept_hdr_async(stream, |req: &Request, resp: Response| {
+                    session = req.uri().path().to_string();
+                    Ok::<_, ErrorResponse>(resp)
+                })
+                .await
+                .unwrap();
+            let Some(mut other) = waiting.remove(&session) else {
+                waiting.insert(session, ws);
+                continue;
+            };
+            let mut ws = ws;
+            other
+                .send(WsMessage::Text(RELAY_PAIRED.into()))
+                .await
+                .unwrap();
+            ws.send(WsMessage::Text(RELAY_PAIRED.into())).await.unwrap();
+
+            let (mut a_sink, mut a_stream) = other.split();
+            let (mut b_sink, mut b_stream) = ws.split();
+            tokio::spawn(async move {
+                while let Some(Ok(msg)) = a_stream.next().await {
+                    if msg.is_binary() && b_sink.send(msg).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            tokio::spawn(async move {
+                while let Some(Ok(msg)) = b_stream.next().await {
+                    if msg.is_binary() && a_sink.send(msg).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn test_websocket_round_trip() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let relay = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(run_relay(listener));
+
+        let (tx1, mut rx1) = mpsc::unbounded_channel();
+        let (tx2, mut rx2) = mpsc::unbounded_channel();
+        let transport1 = WebSocketTransport::new(&relay, None);
+        let transport2 = WebSocketTransport::new(&relay, None);
+        transport1
+            .new_connection("peer2", Box::new(Recorder(tx1)))
+            .await
+            .unwrap();
+        transport2
+            .new_connection("peer1", Box::new(Recorder(tx2)))
+            .await
+            .unwrap();
+        let conn1 = transport1.connection("peer2").unwrap();
+        let conn2 = transport2.connection("peer1").unwrap();
+
+        let offer = conn1.webrtc_create_offer().await.unwrap();
+        let answer = conn2.webrtc_answer_offer(offer).await.unwrap();
+        conn1.webrtc_accept_answer(answer).await.unwrap();
+        conn1.webrtc_wait_for_data_channel_open().await.unwrap();
+        conn2.webrtc_wait_for_data_channel_open().await.unwrap();
+
+        conn1
+            .send_message(TransportMessage::Custom(b"ping".to_vec()))
+            .await
+            .unwrap();
+        assert_eq!(rx2.recv().await.unwrap(), b"ping");
+
+        conn2
+            .send_message(TransportMessage::Custom(b"pong".to_vec()))
+            .await
+            .unwrap();
+        assert_eq!(rx1.recv().await.unwrap(), b"pong");
+
+        conn1.close().await.unwrap();
+        assert_eq!(
+            conn1.webrtc_connection_state(),
+            WebrtcConnectionState::Closed
+        );
+    }
+}
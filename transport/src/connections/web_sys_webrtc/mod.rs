@@ -11,16 +11,20 @@ use web_sys::RtcConfiguration;
 use web_sys::RtcDataChannel;
 use web_sys::RtcDataChannelEvent;
 use web_sys::RtcDataChannelState;
+use web_sys::RtcIceCandidateInit;
 use web_sys::RtcIceCredentialType;
 use web_sys::RtcIceGatheringState;
 use web_sys::RtcIceServer;
 use web_sys::RtcPeerConnection;
+use web_sys::RtcPeerConnectionIceEvent;
 use web_sys::RtcPeerConnectionState;
 use web_sys::RtcSdpType;
 use web_sys::RtcSessionDescription;
 use web_sys::RtcSessionDescriptionInit;
 use web_sys::RtcStatsReport;
 
+use crate::backpressure::BufferWatermark;
+use crate::backpressure::BufferedAmountLow;
 use crate::callback::InnerTransportCallback;
 use crate::connection_ref::ConnectionRef;
 use crate::core::callback::BoxedTransportCallback;
@@ -30,6 +34,9 @@ use crate::core::transport::TransportMessage;
 use crate::core::transport::WebrtcConnectionState;
 use crate::error::Error;
 use crate::error::Result;
+use crate::framing::FrameNegotiation;
+use crate::ice_gathering::IceGathering;
+use crate::ice_gathering::RemoteCandidates;
 use crate::ice_server::IceCredentialType;
 use crate::ice_server::IceServer;
 use crate::notifier::Notifier;
@@ -41,12 +48,19 @@ pub struct WebSysWebrtcConnection {
     webrtc_conn: RtcPeerConnection,
     webrtc_data_channel: RtcDataChannel,
     webrtc_data_channel_open_notifier: Notifier,
+    buffer_watermark: BufferWatermark,
+    buffered_amount_low: BufferedAmountLow,
+    ice_gathering: IceGathering,
+    remote_candidates: RemoteCandidates,
+    frames: FrameNegotiation,
 }
 
 /// [WebSysWebrtcTransport] manages all the [WebSysWebrtcConnection] and
 /// provides methods to create, get and close connections.
 pub struct WebSysWebrtcTransport {
     ice_servers: Vec<IceServer>,
+    buffer_watermark: BufferWatermark,
+    ice_gathering: IceGathering,
     pool: Pool<WebSysWebrtcConnection>,
 }
 
@@ -55,15 +69,27 @@ impl WebSysWebrtcConnection {
         webrtc_conn: RtcPeerConnection,
         webrtc_data_channel: RtcDataChannel,
         webrtc_data_channel_open_notifier: Notifier,
+        buffer_watermark: BufferWatermark,
+        buffered_amount_low: BufferedAmountLow,
+        ice_gathering: IceGathering,
+        frames: FrameNegotiation,
     ) -> Self {
         Self {
             webrtc_conn,
             webrtc_data_channel,
             webrtc_data_channel_open_notifier,
+            buffer_watermark,
+            buffered_amount_low,
+            ice_gathering,
+            remote_candidates: RemoteCandidates::default(),
+            frames,
         }
     }
 
     async fn webrtc_gather(&self) -> Result<String> {
+        if self.webrtc_conn.ice_gathering_state() == RtcIceGatheringState::Complete {
+            return self.webrtc_local_description();
+        }
         let notifier = Notifier::default();
 
         let notifier_clone = notifier.clone();
@@ -80,12 +106,64 @@ impl WebSysWebrtcConnection {
             .set_onicegatheringstatechange(Some(c.as_ref().unchecked_ref()));
         c.forget();
 
+        // The description is given out with candidates gathered so far once timed out.
+        let timeout_ms = self.ice_gathering.timeout_ms;
+        let set_timeout: js_sys::Function =
+            js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str("setTimeout"))
+                .map_err(Error::WebSysWebrtc)?
+                .unchecked_into();
+        let timeout_notifier = notifier.clone();
+        let c = Closure::wrap(Box::new(move || {
+            tracing::debug!("ICE gathering timed out after {} ms", timeout_ms);
+            timeout_notifier.set_result(true)
+        }) as Box<dyn FnMut()>);
+        set_timeout
+            .call2(
+                &JsValue::NULL,
+                c.as_ref().unchecked_ref(),
+                &JsValue::from(timeout_ms as f64),
+            )
+            .map_err(Error::WebSysWebrtc)?;
+        c.forget();
+
         notifier.await?;
+        self.webrtc_local_description()
+    }
+
+    fn webrtc_local_description(&self) -> Result<String> {
         self.webrtc_conn
             .local_description()
             .ok_or(Error::WebrtcLocalSdpGenerationError)
             .map(|x| x.sdp())
     }
+
+    /// Local description to give out, see [IceGathering].
+    async fn webrtc_description(&self) -> Result<String> {
+        if self.ice_gathering.is_trickle() {
+            self.webrtc_local_description()
+        } else {
+            self.webrtc_gather().await
+        }
+    }
+
+    /// Add remote candidates trickled before the remote description is set.
+    async fn webrtc_add_pending_candidates(&self) -> Result<()> {
+        for candidate in self.remote_candidates.described() {
+            self.webrtc_add_candidate(&candidate).await?;
+        }
+        Ok(())
+    }
+
+    async fn webrtc_add_candidate(&self, candidate: &str) -> Result<()> {
+        let init: RtcIceCandidateInit = js_sys::JSON::parse(candidate)
+            .map_err(|_| Error::InvalidIceCandidate(candidate.to_string()))?
+            .unchecked_into();
+        let promise = self
+            .webrtc_conn
+            .add_ice_candidate_with_opt_rtc_ice_candidate_init(Some(&init));
+        JsFuture::from(promise).await.map_err(Error::WebSysWebrtc)?;
+        Ok(())
+    }
 }
 
 impl WebSysWebrtcTransport {
@@ -95,9 +173,23 @@ impl WebSysWebrtcTransport {
 
         Self {
             ice_servers,
+            buffer_watermark: BufferWatermark::default(),
+            ice_gathering: IceGathering::default(),
             pool: Pool::new(),
         }
     }
+
+    /// Set the watermarks of buffered amount of data channels, see [BufferWatermark].
+    pub fn with_buffer_watermark(mut self, watermark: BufferWatermark) -> Self {
+        self.buffer_watermark = watermark;
+        self
+    }
+
+    /// Set how connections wait for local candidates, see [IceGathering].
+    pub fn with_ice_gathering(mut self, gathering: IceGathering) -> Self {
+        self.ice_gathering = gathering;
+        self
+    }
 }
 
 #[async_trait(?Send)]
@@ -107,10 +199,19 @@ impl ConnectionInterface for WebSysWebrtcConnection {
 
     async fn send_message(&self, msg: TransportMessage) -> Result<()> {
         self.webrtc_wait_for_data_channel_open().await?;
-        let data = bincode::serialize(&msg)?;
-        self.webrtc_data_channel
-            .send_with_u8_array(&data)
-            .map_err(Error::WebSysWebrtc)?;
+        let messages = self.frames.encode(&bincode::serialize(&msg)?)?;
+        let channel = &self.webrtc_data_channel;
+        for data in messages {
+            self.buffered_amount_low
+                .wait(self.buffer_watermark, data.len(), || {
+                    let buffered = channel.buffered_amount() as usize;
+                    async move { buffered }
+                })
+                .await?;
+            channel
+                .send_with_u8_array(&data)
+                .map_err(Error::WebSysWebrtc)?;
+        }
         Ok(())
     }
 
@@ -145,7 +246,7 @@ impl ConnectionInterface for WebSysWebrtcConnection {
         let promise = self.webrtc_conn.set_local_description(&set_local_init);
         JsFuture::from(promise).await.map_err(Error::WebSysWebrtc)?;
 
-        self.webrtc_gather().await
+        self.webrtc_description().await
     }
 
     async fn webrtc_answer_offer(&self, offer: Self::Sdp) -> Result<Self::Sdp> {
@@ -156,6 +257,7 @@ impl ConnectionInterface for WebSysWebrtcConnection {
 
         let promise = self.webrtc_conn.set_remote_description(&set_remote_init);
         JsFuture::from(promise).await.map_err(Error::WebSysWebrtc)?;
+        self.webrtc_add_pending_candidates().await?;
 
         let promise = self.webrtc_conn.create_answer();
         let answer_js_value = JsFuture::from(promise).await.map_err(Error::WebSysWebrtc)?;
@@ -168,7 +270,7 @@ impl ConnectionInterface for WebSysWebrtcConnection {
         let promise = self.webrtc_conn.set_local_description(&set_local_init);
         JsFuture::from(promise).await.map_err(Error::WebSysWebrtc)?;
 
-        self.webrtc_gather().await
+        self.webrtc_description().await
     }
 
     async fn webrtc_accept_answer(&self, answer: Self::Sdp) -> Result<()> {
@@ -180,7 +282,21 @@ impl ConnectionInterface for WebSysWebrtcConnection {
         let promise = self.webrtc_conn.set_remote_description(&set_remote_init);
         JsFuture::from(promise).await.map_err(Error::WebSysWebrtc)?;
 
-        Ok(())
+        self.webrtc_add_pending_candidates().await
+    }
+
+    async fn webrtc_gathered_description(&self) -> Result<Option<Self::Sdp>> {
+        if !self.ice_gathering.is_trickle() {
+            return Ok(None);
+        }
+        self.webrtc_gather().await.map(Some)
+    }
+
+    async fn webrtc_add_ice_candidate(&self, candidate: &str) -> Result<()> {
+        match self.remote_candidates.add(candidate) {
+            Some(candidate) => self.webrtc_add_candidate(&candidate).await,
+            None => Ok(()),
+        }
     }
 
     async fn webrtc_wait_for_data_channel_open(&self) -> Result<()> {
@@ -327,6 +443,33 @@ impl TransportInterface for WebSysWebrtcTransport {
             })
         });
 
+        if self.ice_gathering.is_trickle() {
+            let ice_candidate_inner_cb = inner_cb.clone();
+            let on_ice_candidate = Box::new(move |ev: RtcPeerConnectionIceEvent| {
+                // None marks the end of gathering.
+                let Some(c) = ev.candidate() else {
+                    return;
+                };
+                let Some(candidate) = js_sys::JSON::stringify(&c.to_json())
+                    .ok()
+                    .and_then(|s| s.as_string())
+                else {
+                    tracing::error!("Serialize ICE candidate failed");
+                    return;
+                };
+
+                let inner_cb = ice_candidate_inner_cb.clone();
+
+                spawn_local(async move {
+                    inner_cb.on_ice_candidate(&candidate).await;
+                })
+            });
+
+            let c = Closure::wrap(on_ice_candidate as Box<dyn FnMut(RtcPeerConnectionIceEvent)>);
+            webrtc_conn.set_onicecandidate(Some(c.as_ref().unchecked_ref()));
+            c.forget();
+        }
+
         let c = Closure::wrap(on_data_channel as Box<dyn FnMut(RtcDataChannelEvent)>);
         webrtc_conn.set_ondatachannel(Some(c.as_ref().unchecked_ref()));
         c.forget();
@@ -340,6 +483,22 @@ impl TransportInterface for WebSysWebrtcTransport {
         //
         let webrtc_data_channel = webrtc_conn.create_data_channel("rings");
 
+        let buffered_amount_low = BufferedAmountLow::default();
+        webrtc_data_channel.set_buffered_amount_low_threshold(self.buffer_watermark.low as u32);
+
+        let on_buffered_amount_low = buffered_amount_low.clone();
+        let c =
+            Closure::wrap(Box::new(move || on_buffered_amount_low.notify()) as Box<dyn FnMut()>);
+        webrtc_data_channel.set_onbufferedamountlow(Some(c.as_ref().unchecked_ref()));
+        c.forget();
+
+        let on_close_buffered_amount_low = buffered_amount_low.clone();
+        let c = Closure::wrap(
+            Box::new(move || on_close_buffered_amount_low.close()) as Box<dyn FnMut()>
+        );
+        webrtc_data_channel.set_onclose(Some(c.as_ref().unchecked_ref()));
+        c.forget();
+
         //
         // Construct the Connection
         //
@@ -347,6 +506,10 @@ impl TransportInterface for WebSysWebrtcTransport {
             webrtc_conn,
             webrtc_data_channel,
             webrtc_data_channel_open_notifier,
+            self.buffer_watermark,
+            buffered_amount_low,
+            self.ice_gathering,
+            inner_cb.frames(),
         );
 
         self.pool.safely_insert(cid, conn)?;
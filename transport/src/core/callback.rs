@@ -28,6 +28,19 @@ pub trait TransportCallback {
     ) -> Result<(), CallbackError> {
         Ok(())
     }
+
+    /// This method is invoked when a local ICE candidate is gathered in
+    /// [IceGatheringMode::Trickle](crate::ice_gathering::IceGatheringMode::Trickle), which
+    /// should be sent to the remote peer. The candidate is serialized as json.
+    async fn on_ice_candidate(&self, _cid: &str, _candidate: &str) -> Result<(), CallbackError> {
+        Ok(())
+    }
+
+    /// This method is invoked once the data channel is open, when the timing of handshake is
+    /// complete, see [timing](crate::timing).
+    async fn on_handshake_complete(&self, _cid: &str) -> Result<(), CallbackError> {
+        Ok(())
+    }
 }
 
 /// The `new_connection` method of
@@ -4,6 +4,8 @@
 //! There is also a [ConnectionCreation] trait, which is used to specifies the creation of a
 //! [ConnectionInterface] object for [Transport](crate::Transport).
 
+use std::net::SocketAddr;
+
 use async_trait::async_trait;
 use serde::de::DeserializeOwned;
 use serde::Deserialize;
@@ -11,6 +13,7 @@ use serde::Serialize;
 
 use crate::connection_ref::ConnectionRef;
 use crate::core::callback::BoxedTransportCallback;
+use crate::timing::ConnectionTiming;
 
 /// Wrapper for the data that is sent over the data channel.
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -86,12 +89,39 @@ pub trait ConnectionInterface {
     /// Accept a webrtc answer from remote peer.
     async fn webrtc_accept_answer(&self, answer: Self::Sdp) -> Result<(), Self::Error>;
 
+    /// Wait for local candidates to be gathered, or the gathering to time out, and give back
+    /// the local description with them. It's for signaling which can't trickle candidates,
+    /// see [ice_gathering](crate::ice_gathering). None if descriptions given out by the
+    /// connection already carry every candidate, such as a connection never trickling.
+    async fn webrtc_gathered_description(&self) -> Result<Option<Self::Sdp>, Self::Error> {
+        Ok(None)
+    }
+
+    /// Add a candidate trickled by remote peer, serialized as json.
+    /// Candidates arriving before the remote description are added once it's set.
+    async fn webrtc_add_ice_candidate(&self, _candidate: &str) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
     /// Wait for the data channel to be opened after handshake.
     async fn webrtc_wait_for_data_channel_open(&self) -> Result<(), Self::Error>;
 
     /// Close the webrtc connection.
     async fn close(&self) -> Result<(), Self::Error>;
 
+    /// Get time taken by each phase of handshake, see [timing](crate::timing).
+    /// Connections not recording it give every phase as unknown.
+    fn connection_timing(&self) -> ConnectionTiming {
+        ConnectionTiming::default()
+    }
+
+    /// Remote address of the candidate pair selected by ICE, which is the address the
+    /// remote peer is actually reached at, unlike the candidates it claims in signaling.
+    /// None before a pair is selected, or if the connection doesn't know it.
+    async fn webrtc_remote_address(&self) -> Option<SocketAddr> {
+        None
+    }
+
     /// Deprecated, should use `webrtc_connection_state`.
     fn ice_connection_state(&self) -> WebrtcConnectionState {
         self.webrtc_connection_state()
@@ -151,6 +181,24 @@ pub trait TransportInterface {
 
     /// Get all the connection ids in the transport.
     fn connection_ids(&self) -> Vec<String>;
+
+    /// Set the external address announced in candidates of connections created afterwards.
+    /// Transports without such a setting ignore it.
+    fn set_external_address(&self, _address: Option<String>) {}
+
+    /// Create connections with cid over relay instead of WebRTC from now on, or stop it.
+    /// Transports without relay ignore it.
+    fn set_relayed(&self, _cid: &str, _relayed: bool) {}
+
+    /// Check if connections with cid are created over relay. The remote peer of such a
+    /// connection is not bound to it by handshake, since the relay could forge its messages.
+    fn is_relayed(&self, _cid: &str) -> bool {
+        false
+    }
+
+    /// Choose the kind of connection with cid by the offer it answers, before it's created.
+    /// Transports of a single kind ignore it.
+    fn prepare_answer(&self, _cid: &str, _offer: &<Self::Connection as ConnectionInterface>::Sdp) {}
 }
 
 /// Used to store a boxed [TransportInterface] trait object.
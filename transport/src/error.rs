@@ -27,6 +27,9 @@ pub enum Error {
     #[error("WebSysWebRTC error: {}", dump_js_value(.0))]
     WebSysWebrtc(wasm_bindgen::JsValue),
 
+    #[error("WebSocket error: {0}")]
+    WebSocket(String),
+
     #[error("Bincode error: {0}")]
     Bincode(#[from] bincode::Error),
 
@@ -36,9 +39,15 @@ pub enum Error {
     #[error("Failed when waiting for data channel open: {0}")]
     DataChannelOpen(String),
 
+    #[error("Data channel is closed")]
+    DataChannelClosed,
+
     #[error("WebRTC local SDP generation error")]
     WebrtcLocalSdpGenerationError,
 
+    #[error("Invalid ICE candidate: {0}")]
+    InvalidIceCandidate(String),
+
     #[error("Connection {0} already exists")]
     ConnectionAlreadyExists(String),
 
@@ -53,6 +62,27 @@ pub enum Error {
 
     #[error("Notifier timeout, state is not succeeded within {0} ms")]
     NotifierTimeout(u64),
+
+    #[error("Frame header is truncated, got {0} bytes")]
+    FrameHeaderTruncated(usize),
+
+    #[error("Frame is truncated, expected {expected} bytes, got {actual} bytes")]
+    FrameTruncated { expected: usize, actual: usize },
+
+    #[error("Frame has trailing data, expected {expected} bytes, got {actual} bytes")]
+    FrameTrailingData { expected: usize, actual: usize },
+
+    #[error("Frame is too large to encode, got {0} bytes")]
+    FrameTooLarge(usize),
+
+    #[error("Frame has invalid magic byte {0:#x}")]
+    FrameInvalidMagic(u8),
+
+    #[error("Frame version {0} is not supported")]
+    FrameUnsupportedVersion(u8),
+
+    #[error("Description over {0} doesn't match the connection over {1}")]
+    SdpKindMismatch(&'static str, &'static str),
 }
 
 #[cfg(feature = "web-sys-webrtc")]
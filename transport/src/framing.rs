@@ -0,0 +1,243 @@
+//! Length-prefixed framing of data channel messages.
+//!
+//! A frame is a header of [FRAME_MAGIC], [FRAME_VERSION] and the 4 bytes big-endian length of
+//! payload, followed by the payload. When a frame is received, the header is checked against
+//! the actual length of the payload, so that a truncated or concatenated frame is rejected with
+//! a clear error instead of failing opaquely in the deserialization of upper layers.
+//!
+//! Peers of older versions send and read bare payloads, so framing is negotiated by
+//! [FrameNegotiation] for each connection. The first message sent to a peer is preceded by a
+//! hello, which is a frame of empty payload, and payloads are framed only after the peer has
+//! sent a frame too. Until then payloads are sent bare, and bare payloads are always read, so
+//! a connection between peers of different versions keeps working without framing. Older peers
+//! fail to deserialize the hello and drop it.
+
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use bytes::BufMut;
+use bytes::Bytes;
+use bytes::BytesMut;
+
+use crate::error::Error;
+use crate::error::Result;
+
+/// First byte of a frame. A bare payload is the bincode of
+/// [TransportMessage](crate::core::transport::TransportMessage), which starts with its variant
+/// index in little-endian, so it never starts with this byte.
+pub const FRAME_MAGIC: u8 = 0xF7;
+
+/// Version of the frame header.
+pub const FRAME_VERSION: u8 = 1;
+
+/// Length of the frame header in bytes.
+pub const FRAME_HEADER_LEN: usize = 6;
+
+/// Wrap payload into a length-prefixed frame.
+pub fn encode_frame(payload: &[u8]) -> Result<Bytes> {
+    let len = u32::try_from(payload.len()).map_err(|_| Error::FrameTooLarge(payload.len()))?;
+    let mut frame = BytesMut::with_capacity(FRAME_HEADER_LEN + payload.len());
+    frame.put_u8(FRAME_MAGIC);
+    frame.put_u8(FRAME_VERSION);
+    frame.put_u32(len);
+    frame.put_slice(payload);
+    Ok(frame.freeze())
+}
+
+/// Validate a frame and return its payload.
+/// A frame shorter than its header declares is truncated, and a frame longer than
+/// that is considered as multiple frames merged together. Both are rejected.
+pub fn decode_frame(frame: &[u8]) -> Result<&[u8]> {
+    if frame.len() < FRAME_HEADER_LEN {
+        return Err(Error::FrameHeaderTruncated(frame.len()));
+    }
+    let (header, payload) = frame.split_at(FRAME_HEADER_LEN);
+    if header[0] != FRAME_MAGIC {
+        return Err(Error::FrameInvalidMagic(header[0]));
+    }
+    if header[1] != FRAME_VERSION {
+        return Err(Error::FrameUnsupportedVersion(header[1]));
+    }
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&header[2..]);
+    let expected = u32::from_be_bytes(len) as usize;
+
+    if payload.len() < expected {
+        return Err(Error::FrameTruncated {
+            expected,
+            actual: payload.len(),
+        });
+    }
+    if payload.len() > expected {
+        return Err(Error::FrameTrailingData {
+            expected,
+            actual: payload.len(),
+        });
+    }
+    Ok(payload)
+}
+
+/// Negotiation of framing with the peer of a connection, see [framing](self).
+/// Cloned negotiations share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct FrameNegotiation {
+    peer_framed: Arc<AtomicBool>,
+    announced: Arc<AtomicBool>,
+}
+
+impl FrameNegotiation {
+    /// Check if peer has sent a frame, so that it reads frames.
+    pub fn peer_framed(&self) -> bool {
+        self.peer_framed.load(Ordering::SeqCst)
+    }
+
+    /// Get messages to send for payload, in order. The payload is framed if peer reads frames,
+    /// otherwise it's bare, preceded by a hello if it's the first message sent.
+    pub fn encode(&self, payload: &[u8]) -> Result<Vec<Bytes>> {
+        if self.peer_framed() {
+            return Ok(vec![encode_frame(payload)?]);
+        }
+        let mut messages = vec![];
+        if !self.announced.swap(true, Ordering::SeqCst) {
+            messages.push(encode_frame(&[])?);
+        }
+        messages.push(Bytes::copy_from_slice(payload));
+        Ok(messages)
+    }
+
+    /// Get payload of a received message, which is either a frame or a bare payload.
+    /// Returns None for a hello.
+    pub fn decode<'a>(&self, message: &'a [u8]) -> Result<Option<&'a [u8]>> {
+        if message.first() != Some(&FRAME_MAGIC) {
+            return Ok(Some(message));
+        }
+        let payload = decode_frame(message)?;
+        self.peer_framed.store(true, Ordering::SeqCst);
+        Ok((!payload.is_empty()).then_some(payload))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A tiny xorshift generator, so that the fuzzing below is reproducible.
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn bytes(&mut self, max_len: usize) -> Vec<u8> {
+            let len = self.next() as usize % max_len;
+            (0..len).map(|_| self.next() as u8).collect()
+        }
+    }
+
+    #[test]
+    fn test_frame_roundtrip() {
+        for payload in [vec![], vec![0u8], b"hello rings".to_vec(), vec![7u8; 65536]] {
+            let frame = encode_frame(&payload).unwrap();
+            assert_eq!(frame.len(), FRAME_HEADER_LEN + payload.len());
+            assert_eq!(decode_frame(&frame).unwrap(), &payload[..]);
+        }
+    }
+
+    #[test]
+    fn test_truncated_and_merged_frames() {
+        let frame = encode_frame(b"hello rings").unwrap();
+
+        assert!(matches!(
+            decode_frame(&frame[..2]),
+            Err(Error::FrameHeaderTruncated(2))
+        ));
+        assert!(matches!(
+            decode_frame(&frame[..frame.len() - 1]),
+            Err(Error::FrameTruncated {
+                expected: 11,
+                actual: 10
+            })
+        ));
+
+        let merged = [&frame[..], &frame[..]].concat();
+        assert!(matches!(
+            decode_frame(&merged),
+            Err(Error::FrameTrailingData {
+                expected: 11,
+                actual: 28
+            })
+        ));
+    }
+
+    #[test]
+    fn test_frame_negotiation() {
+        let (local, remote) = (FrameNegotiation::default(), FrameNegotiation::default());
+
+        // A peer not known to read frames gets bare payloads, after a hello.
+        let sent = local.encode(b"first").unwrap();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(decode_frame(&sent[0]).unwrap(), b"");
+        assert_eq!(&sent[1][..], b"first");
+        assert_eq!(local.encode(b"second").unwrap(), vec![Bytes::from_static(
+            b"second"
+        )]);
+
+        // Bare payloads of older peers are read, and don't enable framing.
+        assert_eq!(local.decode(b"\0bare").unwrap(), Some(&b"\0bare"[..]));
+        assert!(!local.peer_framed());
+
+        // Once peer announces it, payloads are framed.
+        assert_eq!(remote.decode(&sent[0]).unwrap(), None);
+        assert!(remote.peer_framed());
+        let framed = remote.encode(b"third").unwrap();
+        assert_eq!(framed.len(), 1);
+        assert_eq!(local.decode(&framed[0]).unwrap(), Some(&b"third"[..]));
+        assert!(local.peer_framed());
+        assert_eq!(
+            local.encode(b"fourth").unwrap()[0],
+            encode_frame(b"fourth").unwrap()
+        );
+
+        assert!(matches!(
+            local.decode(&[FRAME_MAGIC, FRAME_VERSION + 1, 0, 0, 0, 0]),
+            Err(Error::FrameUnsupportedVersion(v)) if v == FRAME_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn test_fuzz_malformed_frames() {
+        let mut rng = XorShift(0x5eed_1234_abcd_ef01);
+        for _ in 0..10000 {
+            let payload = rng.bytes(256);
+            let frame = encode_frame(&payload).unwrap();
+
+            // Random garbage never panics, and only passes when it is a valid frame.
+            let garbage = rng.bytes(64);
+            if let Ok(p) = decode_frame(&garbage) {
+                assert_eq!(p.len() + FRAME_HEADER_LEN, garbage.len());
+            }
+
+            // Any cut of a frame is rejected.
+            let cut = rng.next() as usize % frame.len();
+            assert!(decode_frame(&frame[..cut]).is_err());
+
+            // Any extra bytes after a frame are rejected.
+            let mut extended = frame.to_vec();
+            extended.extend(rng.bytes(64));
+            extended.push(0);
+            assert!(decode_frame(&extended).is_err());
+
+            // Flipping the header never makes a frame with wrong length accepted.
+            let mut flipped = frame.to_vec();
+            flipped[rng.next() as usize % FRAME_HEADER_LEN] ^= 1 << (rng.next() % 8);
+            assert!(decode_frame(&flipped).is_err());
+
+            assert_eq!(decode_frame(&frame).unwrap(), &payload[..]);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! This module contains [IceGathering], which decides how a connection waits for its local
+//! ICE candidates before giving out a session description.
+//!
+//! - In [IceGatheringMode::Full], the description is given out once all candidates are
+//!   gathered, or the gathering times out. A single offer and answer carry every candidate,
+//!   which suits signaling exchanging nothing else, such as copying descriptions by hand.
+//!   Slow candidates, such as relay candidates from a distant TURN server, delay the whole
+//!   handshake until the timeout.
+//! - In [IceGatheringMode::Trickle], the description is given out right away, and candidates
+//!   are reported by [TransportCallback::on_ice_candidate] as they are gathered. The signaling
+//!   sends them to the remote peer, which adds them by
+//!   [ConnectionInterface::webrtc_add_ice_candidate]. Connectivity checks start with the first
+//!   candidates, so it generally connects faster, at the cost of more signaling messages.
+//!
+//! [TransportCallback::on_ice_candidate]: crate::core::callback::TransportCallback::on_ice_candidate
+//! [ConnectionInterface::webrtc_add_ice_candidate]: crate::core::transport::ConnectionInterface::webrtc_add_ice_candidate
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Default time waiting for local candidates to be gathered, in milliseconds.
+pub const DEFAULT_ICE_GATHERING_TIMEOUT_MS: u64 = 3000;
+
+/// Max number of remote candidates waiting for the remote description.
+pub const MAX_PENDING_REMOTE_CANDIDATES: usize = 64;
+
+/// Mode of ICE gathering, see [ice_gathering](self).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IceGatheringMode {
+    /// Give out the description once all candidates are gathered.
+    Full,
+    /// Give out the description right away, and candidates as they are gathered.
+    #[default]
+    Trickle,
+}
+
+/// How a connection waits for its local ICE candidates, see [ice_gathering](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IceGathering {
+    /// Mode of gathering.
+    pub mode: IceGatheringMode,
+    /// Max time waiting for all candidates to be gathered, in milliseconds. A description
+    /// waiting longer is given out with the candidates gathered so far.
+    pub timeout_ms: u64,
+}
+
+impl IceGathering {
+    /// Create with mode and timeout.
+    pub fn new(mode: IceGatheringMode, timeout_ms: u64) -> Self {
+        Self { mode, timeout_ms }
+    }
+
+    /// Check if candidates are trickled.
+    pub fn is_trickle(&self) -> bool {
+        self.mode == IceGatheringMode::Trickle
+    }
+}
+
+impl Default for IceGathering {
+    fn default() -> Self {
+        Self::new(
+            IceGatheringMode::default(),
+            DEFAULT_ICE_GATHERING_TIMEOUT_MS,
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+struct RemoteCandidatesState {
+    described: bool,
+    pending: Vec<String>,
+}
+
+/// Candidates of remote peer, which can't be added before its description is set.
+/// Candidates trickled may arrive earlier than the description, since they are signaled
+/// separately, so they are kept until then.
+#[derive(Debug, Clone, Default)]
+pub struct RemoteCandidates(Arc<Mutex<RemoteCandidatesState>>);
+
+impl RemoteCandidates {
+    fn lock(&self) -> MutexGuard<'_, RemoteCandidatesState> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns the candidate if it can be added now, or keeps it until
+    /// [RemoteCandidates::described]. Candidates over [MAX_PENDING_REMOTE_CANDIDATES] are
+    /// dropped.
+    pub fn add(&self, candidate: &str) -> Option<String> {
+        let mut state = self.lock();
+        if state.described {
+            return Some(candidate.to_string());
+        }
+        if state.pending.len() < MAX_PENDING_REMOTE_CANDIDATES {
+            state.pending.push(candidate.to_string());
+        }
+        None
+    }
+
+    /// Mark the remote description as set, returns the candidates kept meanwhile.
+    pub fn described(&self) -> Vec<String> {
+        let mut state = self.lock();
+        state.described = true;
+        std::mem::take(&mut state.pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remote_candidates() {
+        let candidates = RemoteCandidates::default();
+        assert_eq!(candidates.add("a"), None);
+        assert_eq!(candidates.add("b"), None);
+
+        assert_eq!(candidates.described(), vec!["a", "b"]);
+        assert_eq!(candidates.add("c"), Some("c".to_string()));
+        assert!(candidates.described().is_empty());
+    }
+
+    #[test]
+    fn test_ice_gathering_serde() {
+        let gathering: IceGathering = serde_json::from_str(r#"{"mode": "full"}"#).unwrap();
+        assert_eq!(
+            gathering,
+            IceGathering::new(IceGatheringMode::Full, DEFAULT_ICE_GATHERING_TIMEOUT_MS)
+        );
+        assert!(IceGathering::default().is_trickle());
+    }
+}
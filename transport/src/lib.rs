@@ -1,11 +1,15 @@
 #![warn(missing_docs)]
 #![doc = include_str!("../README.md")]
 
+pub mod backpressure;
 pub mod callback;
 pub mod connection_ref;
 pub mod connections;
 pub mod core;
 pub mod error;
+pub mod framing;
+pub mod ice_gathering;
 pub mod ice_server;
 pub mod notifier;
 pub mod pool;
+pub mod timing;
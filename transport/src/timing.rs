@@ -0,0 +1,171 @@
+//! This module contains [ConnectionTiming], the time taken by each phase of the handshake of a
+//! connection, and [HandshakeTimer] which records it.
+//!
+//! The phases tell where a slow handshake spends its time. A slow gathering points to
+//! unreachable STUN or TURN servers, while slow connectivity checks point to NATs between
+//! peers. The answer exchange is the round trip of signaling, which only the offering side
+//! observes.
+//!
+//! The timing is complete once the data channel is open, which is reported by
+//! [TransportCallback::on_handshake_complete].
+//!
+//! [TransportCallback::on_handshake_complete]: crate::core::callback::TransportCallback::on_handshake_complete
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Milestones of a handshake recorded by [HandshakeTimer].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeMark {
+    /// Creating an offer, or answering one, started.
+    Started,
+    /// The local description is set.
+    LocalDescribed,
+    /// Local candidates are gathered, or the gathering timed out.
+    Gathered,
+    /// The local description is given out to signaling.
+    DescriptionSent,
+    /// The remote description is set.
+    RemoteDescribed,
+    /// The peer connection is connected, which means connectivity checks passed.
+    Connected,
+    /// The data channel is open.
+    DataChannelOpen,
+}
+
+/// Time taken by each phase of the handshake of a connection, in milliseconds.
+/// A phase is None if it's not reached, or not observed by this side of the connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionTiming {
+    /// Creating the offer, or the answer, until the local description is set.
+    pub offer_ms: Option<u64>,
+    /// Gathering local candidates after the local description is set.
+    pub gathering_ms: Option<u64>,
+    /// Waiting for the answer after the offer is given out, only observed by the offering side.
+    pub answer_exchange_ms: Option<u64>,
+    /// ICE connectivity checks, from both descriptions being exchanged until connected.
+    pub ice_check_ms: Option<u64>,
+    /// Opening the data channel after connected.
+    pub data_channel_ms: Option<u64>,
+    /// The whole handshake, until the data channel is open.
+    pub total_ms: Option<u64>,
+}
+
+impl ConnectionTiming {
+    /// Check if the handshake is complete, which is the data channel being open.
+    pub fn is_complete(&self) -> bool {
+        self.total_ms.is_some()
+    }
+}
+
+#[derive(Debug, Default)]
+struct HandshakeMarks {
+    started: Option<i64>,
+    local_described: Option<i64>,
+    gathered: Option<i64>,
+    description_sent: Option<i64>,
+    remote_described: Option<i64>,
+    connected: Option<i64>,
+    data_channel_open: Option<i64>,
+}
+
+/// Records milestones of the handshake of a connection, cloning it shares the records.
+/// Only the first time of each milestone is kept.
+#[derive(Debug, Clone, Default)]
+pub struct HandshakeTimer(Arc<Mutex<HandshakeMarks>>);
+
+fn elapsed(from: Option<i64>, to: Option<i64>) -> Option<u64> {
+    let (from, to) = (from?, to?);
+    u64::try_from(to - from).ok()
+}
+
+impl HandshakeTimer {
+    fn lock(&self) -> MutexGuard<'_, HandshakeMarks> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Record a milestone now.
+    pub fn mark(&self, mark: HandshakeMark) {
+        self.mark_at(mark, chrono::Utc::now().timestamp_millis())
+    }
+
+    fn mark_at(&self, mark: HandshakeMark, ms: i64) {
+        let mut marks = self.lock();
+        let slot = match mark {
+            HandshakeMark::Started => &mut marks.started,
+            HandshakeMark::LocalDescribed => &mut marks.local_described,
+            HandshakeMark::Gathered => &mut marks.gathered,
+            HandshakeMark::DescriptionSent => &mut marks.description_sent,
+            HandshakeMark::RemoteDescribed => &mut marks.remote_described,
+            HandshakeMark::Connected => &mut marks.connected,
+            HandshakeMark::DataChannelOpen => &mut marks.data_channel_open,
+        };
+        slot.get_or_insert(ms);
+    }
+
+    /// Time taken by each phase recorded so far.
+    pub fn timing(&self) -> ConnectionTiming {
+        let marks = self.lock();
+        // Connectivity checks start once both descriptions are exchanged.
+        let exchanged = match (marks.description_sent, marks.remote_described) {
+            (Some(sent), Some(remote)) => Some(sent.max(remote)),
+            _ => None,
+        };
+        ConnectionTiming {
+            offer_ms: elapsed(marks.started, marks.local_described),
+            gathering_ms: elapsed(marks.local_described, marks.gathered),
+            // The answering side is described remotely before it starts.
+            answer_exchange_ms: elapsed(marks.description_sent, marks.remote_described),
+            ice_check_ms: elapsed(exchanged, marks.connected),
+            data_channel_ms: elapsed(marks.connected, marks.data_channel_open),
+            total_ms: elapsed(marks.started, marks.data_channel_open),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handshake_timer() {
+        let offerer = HandshakeTimer::default();
+        offerer.mark_at(HandshakeMark::Started, 0);
+        offerer.mark_at(HandshakeMark::LocalDescribed, 5);
+        offerer.mark_at(HandshakeMark::Gathered, 300);
+        offerer.mark_at(HandshakeMark::DescriptionSent, 300);
+        offerer.mark_at(HandshakeMark::RemoteDescribed, 420);
+        offerer.mark_at(HandshakeMark::Connected, 900);
+        assert_eq!(offerer.timing(), ConnectionTiming {
+            offer_ms: Some(5),
+            gathering_ms: Some(295),
+            answer_exchange_ms: Some(120),
+            ice_check_ms: Some(480),
+            data_channel_ms: None,
+            total_ms: None,
+        });
+
+        // Only the first time of a milestone is kept.
+        offerer.mark_at(HandshakeMark::DataChannelOpen, 950);
+        offerer.mark_at(HandshakeMark::DataChannelOpen, 2000);
+        let timing = offerer.clone().timing();
+        assert_eq!(timing.data_channel_ms, Some(50));
+        assert_eq!(timing.total_ms, Some(950));
+        assert!(timing.is_complete());
+
+        let answerer = HandshakeTimer::default();
+        answerer.mark_at(HandshakeMark::Started, 0);
+        answerer.mark_at(HandshakeMark::RemoteDescribed, 2);
+        answerer.mark_at(HandshakeMark::LocalDescribed, 6);
+        answerer.mark_at(HandshakeMark::DescriptionSent, 6);
+        answerer.mark_at(HandshakeMark::Connected, 500);
+        let timing = answerer.timing();
+        assert_eq!(timing.answer_exchange_ms, None);
+        assert_eq!(timing.ice_check_ms, Some(494));
+        assert_eq!(timing.gathering_ms, None);
+    }
+}